@@ -1,5 +1,6 @@
 mod skybox_processor;
 mod texture_compressor;
+mod thumbnail_renderer;
 
 use std::str::FromStr;
 
@@ -7,6 +8,7 @@ use ikari::block_on;
 use ikari::file_manager::GamePathMaker;
 use skybox_processor::SkyboxProcessorArgs;
 use texture_compressor::TextureCompressorArgs;
+use thumbnail_renderer::ThumbnailRendererArgs;
 
 lazy_static::lazy_static! {
     pub static ref PATH_MAKER: GamePathMaker = GamePathMaker::new(None);
@@ -21,6 +23,7 @@ Options:
   --command CMD  Required  The command to run. Possible values include:
                              compress_textures
                              process_skybox
+                             render_thumbnails
   --help         Optional  Display this help message
 ";
 
@@ -57,9 +60,24 @@ Options:
   --help                        Optional  Display this help message
 ";
 
+const THUMBNAIL_RENDERER_HELP: &str = "\
+Render a thumbnail of every gltf file found in a given folder by recursive search, without
+opening a window. Each one is framed by a camera fitted to its bounding sphere and lit by a
+single directional light. The thumbnails are saved as png files named after the gltf files.
+
+Usage: clikari render_thumbnails --search_folder /path/to/folder --out_folder /path/to/folder [OPTIONS]
+
+Options:
+  --search_folder FOLDER   Required  The folder to search in to find gltf files
+  --out_folder FOLDER      Required  Output folder
+  --size VAL               Optional  Width and height of the thumbnails in pixels. Defaults to 256
+  --help                   Optional  Display this help message
+";
+
 enum CommandName {
     CompressTextures,
     ProcessSkybox,
+    RenderThumbnails,
 }
 
 impl FromStr for CommandName {
@@ -69,6 +87,7 @@ impl FromStr for CommandName {
         match input {
             "compress_textures" => Ok(CommandName::CompressTextures),
             "process_skybox" => Ok(CommandName::ProcessSkybox),
+            "render_thumbnails" => Ok(CommandName::RenderThumbnails),
             _ => Err("Command not recognized"),
         }
     }
@@ -80,12 +99,15 @@ enum Command {
     CompressTexturesHelp,
     ProcessSkybox(SkyboxProcessorArgs),
     ProcessSkyboxHelp,
+    RenderThumbnails(ThumbnailRendererArgs),
+    RenderThumbnailsHelp,
 }
 
 enum ArgParseError {
     Root(String),
     CompressTextures(String),
     ProcessSkybox(String),
+    RenderThumbnails(String),
 }
 
 impl Command {
@@ -129,6 +151,20 @@ impl Command {
                     out_folder: args.value_from_str("--out_folder").map_err(error_mapper)?,
                 }));
             }
+            Ok(CommandName::RenderThumbnails) => {
+                if args.contains("--help") {
+                    return Ok(Self::RenderThumbnailsHelp);
+                }
+
+                let error_mapper = |err| ArgParseError::RenderThumbnails(format!("{err}"));
+                return Ok(Self::RenderThumbnails(ThumbnailRendererArgs {
+                    search_folder: args
+                        .value_from_str("--search_folder")
+                        .map_err(error_mapper)?,
+                    out_folder: args.value_from_str("--out_folder").map_err(error_mapper)?,
+                    size: args.opt_value_from_str("--size").map_err(error_mapper)?,
+                }));
+            }
             _ => {}
         };
 
@@ -166,6 +202,9 @@ fn main() {
         Ok(Command::ProcessSkybox(args)) => {
             block_on(skybox_processor::run(args));
         }
+        Ok(Command::RenderThumbnails(args)) => {
+            block_on(thumbnail_renderer::run(args));
+        }
         Ok(Command::Help) => {
             println!("{HELP}");
         }
@@ -175,11 +214,15 @@ fn main() {
         Ok(Command::ProcessSkyboxHelp) => {
             println!("{SKYBOX_PROCESSOR_HELP}");
         }
+        Ok(Command::RenderThumbnailsHelp) => {
+            println!("{THUMBNAIL_RENDERER_HELP}");
+        }
         Err(err) => {
             let (err, helpmsg) = match err {
                 ArgParseError::Root(err) => (err, HELP),
                 ArgParseError::CompressTextures(err) => (err, TEXTURE_COMPRESSOR_HELP),
                 ArgParseError::ProcessSkybox(err) => (err, SKYBOX_PROCESSOR_HELP),
+                ArgParseError::RenderThumbnails(err) => (err, THUMBNAIL_RENDERER_HELP),
             };
             println!("Error: {err}\n\n{helpmsg}");
         }
@@ -3,8 +3,8 @@ use std::path::PathBuf;
 use ikari::{
     file_manager::native_fs,
     renderer::{
-        BaseRenderer, BindedSkybox, Renderer, SkyboxBackgroundPath, SkyboxEnvironmentHDRPath,
-        SkyboxPaths,
+        BaseRenderer, BindedSkybox, Renderer, RendererConfig, SkyboxBackgroundPath,
+        SkyboxEnvironmentHDRPath, SkyboxPaths,
     },
     texture::RawImage,
     texture_compression::TextureCompressionArgs,
@@ -34,7 +34,12 @@ pub async fn run_internal(args: SkyboxProcessorArgs) -> anyhow::Result<()> {
         wgpu::Backends::PRIMARY
     };
 
-    let base_renderer = BaseRenderer::offscreen(backends, Some(DXC_PATH.into())).await?;
+    let base_renderer = BaseRenderer::offscreen(&RendererConfig {
+        backends,
+        dxc_path: Some(DXC_PATH.into()),
+        ..Default::default()
+    })
+    .await?;
     let renderer = Renderer::new(base_renderer, wgpu::TextureFormat::Bgra8Unorm, (1, 1)).await?;
 
     let bindable_skybox = ikari::asset_loader::make_bindable_skybox(&SkyboxPaths {
@@ -0,0 +1,203 @@
+use std::path::{Path, PathBuf};
+
+use glam::f32::{Mat4, Quat, Vec3};
+use walkdir::WalkDir;
+
+use ikari::{
+    asset_loader::SceneAssetLoadParams,
+    camera::DEFAULT_FOV_Y_DEG,
+    collisions::Sphere,
+    engine_state::EngineState,
+    file_manager::{native_fs, FileManager},
+    gltf_loader::build_scene,
+    renderer::{BaseRenderer, DirectionalLight, Renderer, RendererConfig},
+    scene::GameNodeDescBuilder,
+    texture::Texture,
+    transform::TransformBuilder,
+};
+
+use crate::PATH_MAKER;
+
+const DXC_PATH: &str = "dxc/";
+const DEFAULT_THUMBNAIL_SIZE: u32 = 256;
+const FRAMEBUFFER_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+pub struct ThumbnailRendererArgs {
+    pub search_folder: PathBuf,
+    pub out_folder: PathBuf,
+    pub size: Option<u32>,
+}
+
+pub async fn run(args: ThumbnailRendererArgs) {
+    if let Err(err) = run_internal(args).await {
+        log::error!("Error: {err}\n{}", err.backtrace());
+    }
+}
+
+async fn run_internal(args: ThumbnailRendererArgs) -> anyhow::Result<()> {
+    if !args.search_folder.exists() {
+        anyhow::bail!("search folder {:?} does not exist", args.search_folder);
+    }
+
+    let size = args.size.unwrap_or(DEFAULT_THUMBNAIL_SIZE).max(1);
+
+    let base_renderer = BaseRenderer::offscreen(&RendererConfig {
+        dxc_path: Some(DXC_PATH.into()),
+        ..Default::default()
+    })
+    .await?;
+    // a single renderer is much faster than recompiling the pipelines each time,
+    // each asset is unloaded from it once its thumbnail is saved
+    let mut renderer = Renderer::new(base_renderer, FRAMEBUFFER_FORMAT, (size, size)).await?;
+    let render_target = Texture::create_render_target(
+        &renderer.base,
+        (size, size),
+        FRAMEBUFFER_FORMAT,
+        "thumbnail_render_target",
+    );
+
+    native_fs::create_dir_all(&args.out_folder)?;
+
+    let gltf_paths: Vec<_> = WalkDir::new(&args.search_folder)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.into_path())
+        .filter(|path| {
+            path.extension()
+                .map_or(false, |extension| extension == "gltf" || extension == "glb")
+        })
+        .collect();
+
+    for gltf_path in gltf_paths {
+        let out_path = args
+            .out_folder
+            .join(gltf_path.file_stem().unwrap())
+            .with_extension("png");
+        match render_thumbnail(&mut renderer, &render_target, &gltf_path, &out_path).await {
+            Ok(()) => log::info!("{gltf_path:?} -> {out_path:?}"),
+            Err(err) => log::error!("Error rendering a thumbnail of {gltf_path:?}: {err}"),
+        }
+    }
+
+    Ok(())
+}
+
+async fn render_thumbnail(
+    renderer: &mut Renderer,
+    render_target: &Texture,
+    gltf_path: &Path,
+    out_path: &Path,
+) -> anyhow::Result<()> {
+    let mut engine_state = EngineState::new_headless();
+
+    let path = PATH_MAKER.make(gltf_path);
+    let gltf_slice = FileManager::read(&path).await?;
+    let (document, buffers, images) = gltf::import_slice(&gltf_slice)?;
+    let (scene, bindable_scene) = build_scene(
+        (&document, &buffers, &images),
+        SceneAssetLoadParams {
+            path,
+            generate_wireframe_meshes: false,
+            generate_colliders: None,
+        },
+    )
+    .await?;
+    let binded_scene =
+        ikari::asset_loader::bind_scene(&renderer.base, &renderer.constant_data, bindable_scene)?;
+
+    let asset_id = {
+        let mut renderer_data_guard = renderer.data.lock().unwrap();
+        let asset_id =
+            engine_state
+                .scene
+                .merge_scene(&mut renderer_data_guard, scene, binded_scene);
+
+        let bounding_sphere = engine_state
+            .scene
+            .nodes()
+            .filter_map(|node| {
+                engine_state
+                    .scene
+                    .get_node_bounding_sphere(node.id(), &renderer_data_guard)
+            })
+            .reduce(merge_spheres)
+            .ok_or_else(|| anyhow::anyhow!("the scene has no meshes"))?;
+
+        let camera_transform = framing_camera_transform(bounding_sphere);
+        let camera_node_id = engine_state
+            .scene
+            .add_node(
+                GameNodeDescBuilder::new()
+                    .transform(camera_transform)
+                    .build(),
+            )
+            .id();
+        renderer_data_guard.camera_node_id = Some(camera_node_id);
+        asset_id
+    };
+
+    let result = render_and_save(renderer, render_target, &mut engine_state, out_path).await;
+
+    let node_ids: Vec<_> = engine_state.scene.nodes().map(|node| node.id()).collect();
+    for node_id in node_ids {
+        engine_state.scene.remove_node(node_id);
+    }
+    engine_state
+        .scene
+        .unload_asset(&mut renderer.data.lock().unwrap(), asset_id)?;
+
+    result
+}
+
+async fn render_and_save(
+    renderer: &mut Renderer,
+    render_target: &Texture,
+    engine_state: &mut EngineState,
+    out_path: &Path,
+) -> anyhow::Result<()> {
+    engine_state
+        .scene
+        .directional_lights
+        .push(DirectionalLight {
+            direction: Vec3::new(-1.0, -2.0, -1.5).normalize(),
+            color: Vec3::ONE,
+            intensity: 1.0,
+            shadow_mapping_config: Default::default(),
+        });
+
+    renderer.render_offscreen(engine_state, render_target)?;
+    render_target
+        .to_rgba_image(&renderer.base)
+        .await?
+        .save(out_path)?;
+
+    Ok(())
+}
+
+/// Looks at the sphere from the front, slightly above, from far enough that it fits the view
+fn framing_camera_transform(sphere: Sphere) -> ikari::transform::Transform {
+    let half_fov = DEFAULT_FOV_Y_DEG.to_radians() / 2.0;
+    let distance = sphere.radius.max(0.001) / half_fov.sin() * 1.1;
+    let eye = sphere.center + Vec3::new(0.0, 0.5, 1.0).normalize() * distance;
+    let rotation = Quat::from_mat4(&Mat4::look_at_rh(eye, sphere.center, Vec3::Y).inverse());
+    TransformBuilder::new()
+        .position(eye)
+        .rotation(rotation)
+        .build()
+}
+
+fn merge_spheres(a: Sphere, b: Sphere) -> Sphere {
+    let offset = b.center - a.center;
+    let distance = offset.length();
+    if distance + b.radius <= a.radius {
+        return a;
+    }
+    if distance + a.radius <= b.radius {
+        return b;
+    }
+    let radius = (distance + a.radius + b.radius) / 2.0;
+    Sphere {
+        center: a.center + offset * ((radius - a.radius) / distance),
+        radius,
+    }
+}
@@ -4,7 +4,6 @@ use glam::{
     f32::{Vec2, Vec3},
     Quat,
 };
-use ikari::math::{lerp, lerp_vec};
 
 #[derive(Clone, Debug)]
 pub struct BallComponent {
@@ -89,31 +88,4 @@ impl BallComponent {
             }
         });
     }
-
-    pub fn lerp(&self, other: &Self, alpha: f32) -> Self {
-        let transform = ikari::transform::TransformBuilder::new()
-            .position(lerp_vec(
-                self.transform.position(),
-                other.transform.position(),
-                alpha,
-            ))
-            .scale(lerp_vec(
-                self.transform.scale(),
-                other.transform.scale(),
-                alpha,
-            ))
-            .rotation(
-                self.transform
-                    .rotation()
-                    .lerp(other.transform.rotation(), alpha),
-            )
-            .build();
-
-        BallComponent {
-            transform,
-            direction: lerp_vec(self.direction, other.direction, alpha),
-            speed: lerp(self.speed, other.speed, alpha),
-            radius: lerp(self.radius, other.radius, alpha),
-        }
-    }
 }
@@ -1,5 +1,6 @@
 use glam::Vec4;
 
+use ikari::asset_registry::MeshHandle;
 use ikari::physics::PhysicsState;
 use ikari::renderer::RendererConstantData;
 use ikari::scene::{GameNodeId, GameNodeVisual, Material, Scene};
@@ -14,7 +15,7 @@ pub struct Character {
     skin_index: usize,
     collision_box_nodes: Vec<GameNodeId>,
     collision_box_colliders: Vec<ColliderHandle>,
-    collision_debug_mesh_index: usize,
+    collision_debug_mesh: MeshHandle,
     is_displaying_collision_boxes: bool,
 }
 
@@ -31,7 +32,7 @@ impl Character {
             skin_index,
             collision_box_nodes: vec![],
             collision_box_colliders: vec![],
-            collision_debug_mesh_index: renderer_constant_data.cube_mesh_index,
+            collision_debug_mesh: renderer_constant_data.cube_mesh,
             is_displaying_collision_boxes: false,
         };
         res.update(scene, physics_state);
@@ -129,7 +130,7 @@ impl Character {
         ) {
             if let Some(node) = scene.get_node_mut(self.collision_box_nodes[bone_index]) {
                 node.visual = Some(GameNodeVisual::from_mesh_mat(
-                    self.collision_debug_mesh_index,
+                    self.collision_debug_mesh,
                     Material::Transparent {
                         color: Vec4::new(1.0, 0.0, 0.0, 0.3),
                         premultiplied_alpha: false,
@@ -143,7 +144,7 @@ impl Character {
         for node_id in self.collision_box_nodes.iter().cloned() {
             if let Some(node) = scene.get_node_mut(node_id) {
                 node.visual = Some(GameNodeVisual::from_mesh_mat(
-                    self.collision_debug_mesh_index,
+                    self.collision_debug_mesh,
                     Material::Transparent {
                         color: Vec4::new(rand::random(), rand::random(), rand::random(), 0.3),
                         premultiplied_alpha: false,
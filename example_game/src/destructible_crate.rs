@@ -0,0 +1,132 @@
+use glam::f32::{Vec3, Vec4};
+use ikari::destruction::Destructible;
+use ikari::ecs::{Entity, PhysicsBody, SceneNode, Tags, World};
+use ikari::health::{DeathBehavior, Health, HitReaction};
+use ikari::mesh::DynamicPbrParams;
+use ikari::physics::PhysicsState;
+use ikari::scene::{GameNodeDescBuilder, GameNodeVisual, Material, Scene};
+
+use ikari::physics::rapier3d_f64::prelude::*;
+use ikari::transform::TransformBuilder;
+
+use crate::game::COLLISION_GROUP_PLAYER_UNSHOOTABLE;
+
+/// breaks on the first revolver shot
+const HEALTH: f32 = 1.0;
+const COLOR: Vec4 = Vec4::new(0.6, 0.4, 0.2, 1.0);
+
+/// Marker component of the crates that break into eight pieces when shot,
+/// the entity also has a SceneNode, a PhysicsBody, Health, a HitReaction and a Destructible
+#[derive(Copy, Clone, Debug)]
+pub struct DestructibleCrate;
+
+impl DestructibleCrate {
+    /// The mesh is expected to be a cube going from -1 to 1 on each axis
+    pub fn spawn(
+        world: &mut World,
+        scene: &mut Scene,
+        physics_state: &mut PhysicsState,
+        mesh: GameNodeVisual,
+        position: Vec3,
+        half_size: f32,
+    ) -> Entity {
+        let mesh = GameNodeVisual {
+            material: match mesh.material {
+                Material::Pbr {
+                    binded_material, ..
+                } => Material::Pbr {
+                    binded_material,
+                    dynamic_pbr_params: Some(DynamicPbrParams {
+                        base_color_factor: COLOR,
+                        ..Default::default()
+                    }),
+                },
+                material => material,
+            },
+            ..mesh
+        };
+
+        let node_id = scene
+            .add_node(
+                GameNodeDescBuilder::new()
+                    .visual(Some(mesh.clone()))
+                    .transform(
+                        TransformBuilder::new()
+                            .position(position)
+                            .scale(Vec3::splat(half_size))
+                            .build(),
+                    )
+                    .name(Some("destructible_crate".to_string()))
+                    .build(),
+            )
+            .id();
+
+        // pre-fractured into the eight corners of the cube, hidden until it breaks
+        for corner in 0..8 {
+            let sign = |bit: usize| if corner & bit == 0 { -0.5 } else { 0.5 };
+            scene.add_node(
+                GameNodeDescBuilder::new()
+                    .visual(Some(mesh.clone()))
+                    .transform(
+                        TransformBuilder::new()
+                            .position(Vec3::new(sign(1), sign(2), sign(4)))
+                            .scale(Vec3::splat(0.5))
+                            .build(),
+                    )
+                    .name(Some("destructible_crate_piece".to_string()))
+                    .parent_id(Some(node_id))
+                    .visible(false)
+                    .build(),
+            );
+        }
+
+        let rigid_body = RigidBodyBuilder::dynamic()
+            .translation(vector![
+                position.x as f64,
+                position.y as f64,
+                position.z as f64
+            ])
+            .build();
+        let half_size = half_size as f64;
+        let collider = ColliderBuilder::cuboid(half_size, half_size, half_size)
+            .collision_groups(
+                InteractionGroups::all().with_memberships(!COLLISION_GROUP_PLAYER_UNSHOOTABLE),
+            )
+            .friction(1.0)
+            .density(1.0)
+            .build();
+        let rigid_body_handle = physics_state.rigid_body_set.insert(rigid_body);
+        physics_state.collider_set.insert_with_parent(
+            collider,
+            rigid_body_handle,
+            &mut physics_state.rigid_body_set,
+        );
+        physics_state.add_rigid_body_component(node_id, rigid_body_handle);
+
+        let entity = world.spawn();
+        world.insert(entity, DestructibleCrate);
+        world.insert(entity, Destructible::from_children(scene, node_id));
+        world.insert(entity, SceneNode(node_id));
+        world.insert(entity, PhysicsBody(rigid_body_handle));
+        world.insert(entity, Health::new(HEALTH));
+        world.insert(
+            entity,
+            HitReaction {
+                death: DeathBehavior::Fracture,
+                ..Default::default()
+            },
+        );
+        world.insert(entity, Tags::new(&["crate"]));
+        entity
+    }
+
+    /// returns the crate entity that owns this rigid body, if any
+    pub fn find_by_rigid_body(world: &World, rigid_body_handle: RigidBodyHandle) -> Option<Entity> {
+        world
+            .query::<PhysicsBody>()
+            .find(|(entity, PhysicsBody(handle))| {
+                *handle == rigid_body_handle && world.has::<DestructibleCrate>(*entity)
+            })
+            .map(|(entity, _)| entity)
+    }
+}
@@ -1,21 +1,26 @@
 use crate::ball::*;
 use crate::character::*;
+use crate::destructible_crate::*;
 use crate::game_state::*;
 use crate::physics_ball::*;
 use crate::revolver::*;
+use crate::ui_overlay::AssetSpawnRequest;
 use crate::ui_overlay::AudioSoundStats;
+use crate::ui_overlay::EditorAction;
+use crate::ui_overlay::EditorPanelState;
 use crate::ui_overlay::Message;
 use crate::ui_overlay::UiOverlay;
 use crate::ui_overlay::DEFAULT_FONT_BYTES;
 use crate::ui_overlay::DEFAULT_FONT_NAME;
 use crate::ui_overlay::KOOKY_FONT_BYTES;
+use crate::ui_overlay::KOOKY_FONT_NAME;
 
 use std::collections::HashMap;
 use std::sync::Mutex;
 use std::{collections::hash_map::Entry, sync::Arc};
 
 use anyhow::Result;
-use glam::f32::{Vec3, Vec4};
+use glam::f32::{Vec2, Vec3, Vec4};
 use glam::Mat4;
 use glam::Quat;
 use ikari::animation::step_animations;
@@ -26,19 +31,43 @@ use ikari::asset_loader::AudioAssetLoadParams;
 use ikari::asset_loader::SceneAssetLoadParams;
 use ikari::audio::AudioFileFormat;
 use ikari::audio::SoundParams;
+use ikari::audio::SpacialParams;
+use ikari::audio_mixer::{MixerSettings, MUSIC_BUS, SFX_BUS};
+use ikari::camera::{CameraLens, FieldOfView, PhysicalCamera, Projection};
+use ikari::camera_system::{CameraKind, CameraSystem, OrbitCamera, OrbitTarget};
+use ikari::debug_draw::DebugDraw;
+use ikari::decals::Decals;
+use ikari::destruction::update_debris;
+use ikari::ecs::{PhysicsBody, SceneNode, SystemStage};
+use ikari::editor::Editor;
+use ikari::effects::EffectLibrary;
 use ikari::engine_state::EngineState;
 use ikari::file_manager::{FileManager, GamePathMaker};
+use ikari::force_fields::{explode, update_force_fields, Explosion, ForceFalloff, ForceFieldEvent};
+use ikari::game_state_stack::GameStateKind;
+use ikari::game_state_stack::InputContext;
 use ikari::gameloop::GameContext;
+use ikari::gamepad::Gamepads;
+use ikari::health::{DamageEvent, HealthEvent, HealthSystem};
+use ikari::hud::{Hud, HudAnchor, HudText, HudWidget, HudWidgetKind};
+use ikari::input::{ActionEvent, BindingSet, GamepadButton, InputBinding, InputBindings, InputMap};
+use ikari::light_animation::{LightBehavior, LightFlashes};
 use ikari::math::deg_to_rad;
-use ikari::math::lerp_vec;
 use ikari::mesh::BasicMesh;
 use ikari::mesh::DynamicPbrParams;
 use ikari::mesh::PbrTextures;
 use ikari::mesh::Vertex;
+use ikari::minimap::{FogOfWar, Minimap, MinimapMarker, MinimapMarkerShape};
+use ikari::music_player::MusicPlayer;
+use ikari::particles::ParticleSystem;
+use ikari::perf_advisor::{analyze_scene, PerfAdvisorConfig};
 use ikari::physics::rapier3d_f64::prelude::*;
 use ikari::physics::PhysicsState;
+use ikari::picking;
 use ikari::player_controller::ControlledViewDirection;
 use ikari::player_controller::PlayerController;
+use ikari::renderer::BindedAssetId;
+use ikari::renderer::BlobShadowCaster;
 use ikari::renderer::BloomType;
 use ikari::renderer::DirectionalLight;
 use ikari::renderer::DirectionalLightShadowMappingConfig;
@@ -50,20 +79,33 @@ use ikari::renderer::{
     BaseRenderer, Renderer, SkyboxBackgroundPath, SkyboxEnvironmentHDRPath, SurfaceData,
 };
 use ikari::sampler_cache::SamplerDescriptor;
+use ikari::sampler_cache::TextureFiltering;
+use ikari::sampler_cache::TextureQuality;
+use ikari::save_game::SaveId;
+#[cfg(not(target_arch = "wasm32"))]
+use ikari::save_game::{SaveGame, SaveSlots, SavedAmmo, SavedPlayer};
 use ikari::scene::GameNodeDesc;
 use ikari::scene::GameNodeDescBuilder;
 use ikari::scene::GameNodeId;
 use ikari::scene::GameNodeVisual;
 use ikari::scene::Material;
 use ikari::scene::Scene;
+use ikari::scene_file::{SceneFile, SceneFileAsset};
+use ikari::scene_transition::{LoadedScene, SceneTransition};
+#[cfg(feature = "scripting")]
+use ikari::scripting::ScriptHost;
+use ikari::settings::{AudioSettings, GraphicsSettings, Settings};
+use ikari::sound_cue::{SoundCue, SoundCueClip};
 use ikari::texture::Texture;
+use ikari::time_control::{ChannelTimeScale, TimeChannel};
+use ikari::time_of_day::TimeOfDay;
 use ikari::transform::Transform;
 use ikari::transform::TransformBuilder;
 use ikari::ui::IkariUiContainer;
 use ikari::wasm_not_sync::WasmNotArc;
-use winit::event::{ElementState, WindowEvent};
-use winit::keyboard::Key;
-use winit::keyboard::NamedKey;
+use ikari::water::{WaterInteractionSystem, WaterRippleSimulation, WaterSurface};
+use ikari::weapon::{update_projectiles, WeaponEvent, WeaponHit};
+use ikari::world_labels::{WorldLabel, WorldLabels};
 
 // graphics settings
 pub const INITIAL_ENABLE_VSYNC: bool = false;
@@ -77,6 +119,8 @@ pub const INITIAL_BLOOM_RAMP_SIZE: f32 = 0.2;
 pub const INITIAL_NEW_BLOOM_RADIUS: f32 = 0.005;
 pub const INITIAL_NEW_BLOOM_INTENSITY: f32 = 0.04;
 pub const INITIAL_BLOOM_TYPE: BloomType = BloomType::New;
+pub const INITIAL_TEXTURE_FILTERING: Option<TextureFiltering> =
+    Some(TextureFiltering::Anisotropic8x);
 pub const INITIAL_IS_SHOWING_CAMERA_POSE: bool = false;
 pub const INITIAL_IS_SHOWING_CURSOR_MARKER: bool = false;
 pub const INITIAL_ENABLE_SHADOW_DEBUG: bool = false;
@@ -89,15 +133,51 @@ pub const INITIAL_SHADOW_BIAS: f32 = 0.001;
 pub const INITIAL_SKYBOX_WEIGHT: f32 = 1.0;
 pub const INITIAL_SOFT_SHADOW_FACTOR: f32 = 0.00003;
 pub const INITIAL_SOFT_SHADOW_GRID_DIMS: u32 = 4;
+pub const INITIAL_PARALLAX_MAX_STEP_COUNT: u32 = 32;
 
 // game settings
+pub const EDITOR_SCENE_PATH: &str = "src/editor_scene.ron";
+pub const WEAPON_EFFECTS_PATH: &str = "src/effects/weapons.ron";
+pub const WATER_EFFECTS_PATH: &str = "src/effects/water.ron";
+pub const MUSIC_FADE_IN_SECONDS: f32 = 2.0;
+/// the settings are saved in this folder of the platform's config directory
+pub const SETTINGS_APP_NAME: &str = "ikari_example_game";
+pub const QUICK_SAVE_SLOT: &str = "quicksave";
+pub const GUNSHOT_CUE: &str = "gunshot";
+pub const WATER_SPLASH_CUE: &str = "water_splash";
+/// defined in WATER_EFFECTS_PATH
+const WATER_SPLASH_EFFECT: &str = "water_splash";
+const POND_RIPPLE_RESOLUTION: u32 = 256;
+pub const MINIMAP_RESOLUTION: u32 = 160;
+
 pub const ARENA_SIDE_LENGTH: f32 = 500.0;
 pub const ENABLE_GRAVITY: bool = true;
 pub const ENABLE_GRAVITY_ON_PLAYER: bool = false;
 pub const PLAYER_MOVEMENT_SPEED: f32 = 6.0;
+/// how long the view takes to move to the next camera
+pub const CAMERA_BLEND_SECONDS: f32 = 0.75;
+/// cycled through with the cycle_time_scale action
+pub const TIME_SCALES: [f64; 4] = [1.0, 0.5, 0.25, 0.1];
+/// how far away the name of the node in front of the player is shown
+pub const LOOK_AT_DISTANCE: f32 = 5.0;
+/// how far away an asset dropped from the content browser can land
+pub const ASSET_DROP_DISTANCE: f32 = 200.0;
+/// around the ball in front of the player
+pub const LOOKED_AT_BALL_OUTLINE_COLOR: Vec4 = Vec4::new(1.0, 1.0, 1.0, 0.8);
+/// past it the oldest bullet holes are moved to the new hits
+pub const MAX_BULLET_HOLE_DECALS: usize = 200;
+pub const EXPLOSIVE_ROUND_RADIUS: f32 = 2.5;
+pub const EXPLOSIVE_ROUND_IMPULSE: f32 = 0.5;
+/// given to what's at the center of the explosion, less further away like the impulse
+pub const EXPLOSIVE_ROUND_SPLASH_DAMAGE: f32 = 0.5;
+pub const DESTRUCTIBLE_CRATE_HALF_SIZE: f32 = 0.4;
+/// past it the oldest pieces of the broken crates are despawned
+pub const MAX_DEBRIS: usize = 64;
 
 pub const CREATE_POINT_SHADOW_MAP_DEBUG_OBJECTS: bool = false;
 pub const REMOVE_LARGE_OBJECTS_FROM_FOREST: bool = false;
+/// drives the first directional light and the skybox weights instead of the ui
+pub const ENABLE_DAY_NIGHT_CYCLE: bool = false;
 
 // linear colors, not srgb
 pub const _DIRECTIONAL_LIGHT_COLOR_A: Vec3 = Vec3::new(0.84922975, 0.81581426, 0.8832506);
@@ -279,6 +359,14 @@ pub async fn init_game_state(
         "Toggle Wireframe:        F",
         "Toggle Collision Boxes:  C",
         "Draw Bounding Spheres:   J",
+        "Draw Physics Debug:      I",
+        "Print Perf Report:       O",
+        "Toggle Level Editor:     L",
+        "Pause/Resume Time:       K",
+        "Step One Frame:          N",
+        "Cycle Slow Motion:       H",
+        "Quick Save / Load:       F5 / F9",
+        "Reload Level:            F6",
         "Open Options Menu:       Tab",
     ]
     .iter()
@@ -286,6 +374,26 @@ pub async fn init_game_state(
         log::info!("  {line}");
     });
 
+    // slow motion only affects the world, the player keeps moving in real time
+    engine_state.time_control.set_channel_scale(
+        TimeChannel::Player,
+        ChannelTimeScale {
+            scale: 1.0,
+            ignore_global_scale: true,
+        },
+    );
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let settings_path = Settings::default_path(SETTINGS_APP_NAME);
+    #[cfg(not(target_arch = "wasm32"))]
+    let mut settings = settings_path
+        .as_deref()
+        .map(Settings::load_or_default)
+        .unwrap_or_default();
+    #[cfg(target_arch = "wasm32")]
+    let mut settings = Settings::load_from_local_storage_or_default(SETTINGS_APP_NAME);
+    add_settings_to_crash_reports(&settings);
+
     {
         let mut renderer_data_guard = renderer.data.lock().unwrap();
         renderer_data_guard.enable_shadows = INITIAL_ENABLE_SHADOWS;
@@ -294,7 +402,13 @@ pub async fn init_game_state(
         renderer_data_guard.bloom_ramp_size = INITIAL_BLOOM_RAMP_SIZE;
         renderer_data_guard.tone_mapping_exposure = INITIAL_TONE_MAPPING_EXPOSURE;
         renderer_data_guard.render_scale = INITIAL_RENDER_SCALE;
+        renderer_data_guard.soft_shadow_grid_dims = INITIAL_SOFT_SHADOW_GRID_DIMS;
+        renderer_data_guard.parallax_max_step_count = INITIAL_PARALLAX_MAX_STEP_COUNT;
+        settings.graphics.apply(&mut renderer_data_guard);
     }
+    engine_state
+        .frame_limiter
+        .set_max_fps(settings.graphics.max_fps);
 
     let unscaled_framebuffer_size = winit::dpi::PhysicalSize::new(
         surface_data.surface_config.width,
@@ -310,6 +424,13 @@ pub async fn init_game_state(
     let asset_id_map = Arc::new(Mutex::new(HashMap::new()));
     let asset_id_map_clone = asset_id_map.clone();
 
+    // the screen stays covered until the level is on the gpu
+    let mut scene_transition = SceneTransition {
+        fade_out_duration: ikari::time::Duration::ZERO,
+        ..Default::default()
+    };
+    scene_transition.start(&asset_loader, vec![test_level_load_params()])?;
+
     ikari::thread::spawn(move || {
         #[allow(clippy::vec_init_then_push)]
         ikari::block_on(async move {
@@ -331,9 +452,6 @@ pub async fn init_game_state(
             // https://www.cgtrader.com/free-3d-models/character/sci-fi-character/legendary-robot-free-low-poly-3d-model
             gltf_paths.push("src/models/gltf/LegendaryRobot/Legendary_Robot.glb");
 
-            // maze
-            gltf_paths.push("src/models/gltf/TestLevel/test_level.glb");
-
             // other
             // gltf_paths.push(get_misc_gltf_path());
 
@@ -343,6 +461,7 @@ pub async fn init_game_state(
                     asset_loader.load_gltf_scene(SceneAssetLoadParams {
                         path: GAME_PATH_MAKER.make(path),
                         generate_wireframe_meshes: true,
+                        generate_colliders: None,
                     }),
                 );
             }
@@ -357,6 +476,8 @@ pub async fn init_game_state(
                     fixed_volume: false,
                     spacial_params: None,
                     stream: !cfg!(target_arch = "wasm32"),
+                    looping: true,
+                    bus: MUSIC_BUS.to_string(),
                 },
             });
 
@@ -365,9 +486,24 @@ pub async fn init_game_state(
                 format: AudioFileFormat::Wav,
                 sound_params: SoundParams {
                     initial_volume: 0.4,
-                    fixed_volume: true,
-                    spacial_params: None,
+                    fixed_volume: false,
+                    spacial_params: Some(SpacialParams::new(Vec3::ZERO)),
+                    stream: false,
+                    looping: false,
+                    bus: SFX_BUS.to_string(),
+                },
+            });
+
+            audio_load_params.push(AudioAssetLoadParams {
+                path: GAME_PATH_MAKER.make("src/sounds/splash.wav"),
+                format: AudioFileFormat::Wav,
+                sound_params: SoundParams {
+                    initial_volume: 0.5,
+                    fixed_volume: false,
+                    spacial_params: Some(SpacialParams::new(Vec3::ZERO)),
                     stream: false,
+                    looping: false,
+                    bus: SFX_BUS.to_string(),
                 },
             });
 
@@ -446,6 +582,31 @@ pub async fn init_game_state(
     ];
     // let point_lights: Vec<(ikari::transform::Transform, Vec3, f32)> = vec![];
 
+    // the player's bindings live in the settings file in their config directory, the
+    // defaults are built into the game so nothing is ever written next to the sources
+    let input_bindings = settings
+        .input
+        .clone()
+        .unwrap_or_else(default_input_bindings);
+
+    // same for the mixer: the buses are built in and the player's levels come from the
+    // settings file
+    {
+        let mut audio_manager_guard = engine_state.audio_manager.lock().unwrap();
+        audio_manager_guard.apply_mixer_settings(&MixerSettings::default());
+        settings.audio.apply(&mut audio_manager_guard);
+    }
+
+    settings.input = Some(input_bindings.clone());
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(settings_path) = settings_path.as_deref() {
+        if !settings_path.exists() {
+            if let Err(err) = settings.save(settings_path) {
+                log::error!("Error saving the default settings: {err:?}");
+            }
+        }
+    }
+
     let physics_state = &mut engine_state.physics_state;
     let scene = &mut engine_state.scene;
 
@@ -474,13 +635,83 @@ pub async fn init_game_state(
 
     renderer.data.lock().unwrap().camera_node_id = Some(player_node_id);
 
+    let mut camera_system = CameraSystem::default();
+    camera_system.add_camera(CameraKind::FirstPerson);
+    let orbit_camera_id = camera_system.add_camera(CameraKind::Orbit(OrbitCamera {
+        target_offset: Vec3::new(0.0, 0.5, 0.0),
+        ..OrbitCamera::new(OrbitTarget::Player, 4.0)
+    }));
+    camera_system.set_camera_lens(
+        orbit_camera_id,
+        Some(CameraLens {
+            field_of_view: FieldOfView::Vertical(deg_to_rad(60.0)),
+            ..Default::default()
+        }),
+    );
+    let cinematic_camera_node_id = scene
+        .add_node(
+            GameNodeDescBuilder::new()
+                .transform(
+                    TransformBuilder::new()
+                        .position(Vec3::new(12.0, 8.0, 12.0))
+                        .rotation(
+                            ControlledViewDirection {
+                                horizontal: deg_to_rad(45.0),
+                                vertical: deg_to_rad(-25.0),
+                            }
+                            .to_quat(),
+                        )
+                        .build(),
+                )
+                .name(Some("cinematic_camera".into()))
+                .build(),
+        )
+        .id();
+    let cinematic_camera_id = camera_system.add_camera(CameraKind::Fixed(cinematic_camera_node_id));
+    camera_system.set_camera_lens(
+        cinematic_camera_id,
+        Some(CameraLens {
+            physical: Some(PhysicalCamera {
+                focal_length: 85.0,
+                aperture: 1.8,
+                shutter_speed: 1.0 / 8000.0,
+                focus_distance: 17.0,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+    );
+    let top_down_camera_node_id = scene
+        .add_node(
+            GameNodeDescBuilder::new()
+                .transform(
+                    TransformBuilder::new()
+                        .position(Vec3::new(0.0, 50.0, 0.0))
+                        .rotation(Quat::from_rotation_x(deg_to_rad(-90.0)))
+                        .build(),
+                )
+                .name(Some("top_down_camera".into()))
+                .build(),
+        )
+        .id();
+    let top_down_camera_id = camera_system.add_camera(CameraKind::Fixed(top_down_camera_node_id));
+    camera_system.set_camera_lens(
+        top_down_camera_id,
+        Some(CameraLens {
+            projection: Projection::Orthographic { height: 40.0 },
+            near_plane_distance: 0.1,
+            far_plane_distance: 200.0,
+            ..Default::default()
+        }),
+    );
+
     let mut point_light_node_ids: Vec<GameNodeId> = Vec::new();
     for (transform, color, intensity) in point_lights {
         let node_id = scene
             .add_node(
                 GameNodeDescBuilder::new()
                     .visual(Some(GameNodeVisual::from_mesh_mat(
-                        renderer.constant_data.sphere_mesh_index,
+                        renderer.constant_data.sphere_mesh,
                         Material::Unlit {
                             color: color * intensity * 10.0,
                         },
@@ -490,12 +721,29 @@ pub async fn init_game_state(
             )
             .id();
         point_light_node_ids.push(node_id);
-        scene.point_lights.push(PointLight {
+        scene.add_point_light(PointLight {
             node_id,
             color,
             intensity,
+            casts_shadows: true,
         });
     }
+    if let Some(node_id) = point_light_node_ids.first() {
+        engine_state.light_animations.add(
+            scene,
+            *node_id,
+            vec![
+                LightBehavior::ColorCycle {
+                    colors: vec![POINT_LIGHT_COLOR, POINT_LIGHT_COLOR_B],
+                    seconds_per_color: 1.5,
+                },
+                LightBehavior::Flicker {
+                    speed: 4.0,
+                    amount: 0.2,
+                },
+            ],
+        );
+    }
 
     // let simple_normal_map_path = "src/textures/simple_normal_map.jpg";
     // let simple_normal_map_bytes = FileLoader::read(simple_normal_map_path).await?;
@@ -599,6 +847,54 @@ pub async fn init_game_state(
         },
     )?;
 
+    // the dark squares of the checkerboard are sunk into the floor, the height is in the alpha
+    // channel for the parallax occlusion mapping
+    let floor_normal_map_img = {
+        let checkerboard_height = |x: i64, y: i64| {
+            let scale = 10;
+            if (x.div_euclid(scale) + y.div_euclid(scale)) % 2 == 0 {
+                0.0
+            } else {
+                1.0
+            }
+        };
+        let mut img = image::RgbaImage::new(1024, 1024);
+        for x in 0..img.width() {
+            for y in 0..img.height() {
+                let (x, y) = (x as i64, y as i64);
+                let normal = Vec3::new(
+                    checkerboard_height(x - 1, y) - checkerboard_height(x + 1, y),
+                    checkerboard_height(x, y - 1) - checkerboard_height(x, y + 1),
+                    1.0,
+                )
+                .normalize();
+                let [r, g, b] = (normal * 0.5 + 0.5).to_array().map(|c| (c * 255.0) as u8);
+                img.put_pixel(
+                    x as u32,
+                    y as u32,
+                    [r, g, b, (checkerboard_height(x, y) * 255.0) as u8].into(),
+                );
+            }
+        }
+        img
+    };
+    let floor_normal_map = Texture::from_decoded_image(
+        &renderer.base,
+        &floor_normal_map_img.into(),
+        Some("floor_normal_map"),
+        wgpu::TextureFormat::Rgba8Unorm.into(),
+        true,
+        &SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        },
+    )?;
+
     // add balls to scene
 
     // source: https://www.solarsystemscope.com/textures/
@@ -647,7 +943,7 @@ pub async fn init_game_state(
         .add_node(
             GameNodeDescBuilder::new()
                 .visual(Some(GameNodeVisual::make_pbr(
-                    renderer.constant_data.sphere_mesh_index,
+                    renderer.constant_data.sphere_mesh,
                     test_object_pbr_material_index,
                 )))
                 .transform(
@@ -662,6 +958,18 @@ pub async fn init_game_state(
         .id();
     // scene.remove_node(test_object_node_id);
 
+    #[cfg(feature = "scripting")]
+    let script_host = {
+        let mut script_host = ScriptHost::new()?;
+        if let Err(err) = script_host.attach(
+            test_object_node_id,
+            &GAME_PATH_MAKER.make("src/scripts/test_object.lua"),
+        ) {
+            log::error!("Error loading the test object's script: {err:?}");
+        }
+        script_host
+    };
+
     // add floor to scene
 
     let ball_count = 0;
@@ -683,28 +991,61 @@ pub async fn init_game_state(
         let node = scene.add_node(
             GameNodeDescBuilder::new()
                 .visual(Some(GameNodeVisual::make_pbr(
-                    renderer.constant_data.sphere_mesh_index,
+                    renderer.constant_data.sphere_mesh,
                     ball_pbr_material_index,
                 )))
                 .transform(ball.transform)
+                .interpolate_transform(true)
                 .build(),
         );
         ball_node_ids.push(node.id());
     }
 
     let physics_ball_count = 500;
-    let physics_balls: Vec<_> = (0..physics_ball_count)
-        .map(|_| {
-            PhysicsBall::new_random(
-                scene,
-                physics_state,
-                GameNodeVisual::make_pbr(
-                    renderer.constant_data.sphere_mesh_index,
-                    ball_pbr_material_index,
-                ),
-            )
-        })
-        .collect();
+    for _ in 0..physics_ball_count {
+        PhysicsBall::spawn_random(
+            &mut engine_state.world,
+            scene,
+            physics_state,
+            GameNodeVisual::make_pbr(renderer.constant_data.sphere_mesh, ball_pbr_material_index),
+        );
+    }
+    renderer.data.lock().unwrap().blob_shadow_casters.extend(
+        engine_state
+            .world
+            .query::<SceneNode>()
+            .filter(|(entity, _)| engine_state.world.has::<PhysicsBall>(*entity))
+            .map(|(_, SceneNode(node_id))| BlobShadowCaster::new(*node_id)),
+    );
+    engine_state.systems.add_system(
+        SystemStage::FixedUpdate,
+        "Despawn fallen physics balls",
+        despawn_fallen_physics_balls,
+    );
+    engine_state.systems.add_system(
+        SystemStage::FixedUpdate,
+        "Force fields",
+        update_force_fields,
+    );
+
+    for index in 0..5 {
+        let entity = DestructibleCrate::spawn(
+            &mut engine_state.world,
+            scene,
+            physics_state,
+            GameNodeVisual::make_pbr(renderer.constant_data.cube_mesh, ball_pbr_material_index),
+            Vec3::new(-6.0 + 3.0 * index as f32, DESTRUCTIBLE_CRATE_HALF_SIZE, 8.0),
+            DESTRUCTIBLE_CRATE_HALF_SIZE,
+        );
+        engine_state
+            .world
+            .insert(entity, SaveId(format!("crate_{index}")));
+    }
+    engine_state.systems.add_system(
+        SystemStage::FixedUpdate,
+        "Debris",
+        |engine_state: &mut EngineState| update_debris(engine_state, MAX_DEBRIS),
+    );
 
     if CREATE_POINT_SHADOW_MAP_DEBUG_OBJECTS {
         let cube_radius = 4.0;
@@ -735,7 +1076,7 @@ pub async fn init_game_state(
             .add_node(
                 GameNodeDescBuilder::new()
                     .visual(Some(GameNodeVisual::make_pbr(
-                        renderer.constant_data.sphere_mesh_index,
+                        renderer.constant_data.sphere_mesh,
                         ball_pbr_mesh_index,
                     )))
                     .transform(
@@ -758,10 +1099,8 @@ pub async fn init_game_state(
             },
             Default::default(),
         )?;
-        let game_node_visual = GameNodeVisual::make_pbr(
-            renderer.constant_data.plane_mesh_index,
-            wall_pbr_material_index,
-        );
+        let game_node_visual =
+            GameNodeVisual::make_pbr(renderer.constant_data.plane_mesh, wall_pbr_material_index);
 
         let ceiling_transform = TransformBuilder::new()
             .position(cube_center + Vec3::new(0.0, cube_radius, 0.0))
@@ -773,7 +1112,7 @@ pub async fn init_game_state(
             .build();
         let ceiling_game_node_mesh = GameNodeVisual {
             material: Material::Pbr {
-                binded_material_index: wall_pbr_material_index,
+                binded_material: wall_pbr_material_index,
                 dynamic_pbr_params: Some(DynamicPbrParams {
                     base_color_factor: Vec4::new(1.0, 0.5, 0.5, 1.0),
                     ..Default::default()
@@ -790,7 +1129,7 @@ pub async fn init_game_state(
 
         let wall_1_node_mesh = GameNodeVisual {
             material: Material::Pbr {
-                binded_material_index: wall_pbr_material_index,
+                binded_material: wall_pbr_material_index,
                 dynamic_pbr_params: Some(DynamicPbrParams {
                     base_color_factor: Vec4::new(0.5, 1.0, 0.5, 1.0),
                     ..Default::default()
@@ -815,7 +1154,7 @@ pub async fn init_game_state(
 
         let wall_2_node_mesh = GameNodeVisual {
             material: Material::Pbr {
-                binded_material_index: wall_pbr_material_index,
+                binded_material: wall_pbr_material_index,
                 dynamic_pbr_params: Some(DynamicPbrParams {
                     base_color_factor: Vec4::new(0.5, 0.5, 1.0, 1.0),
                     ..Default::default()
@@ -840,7 +1179,7 @@ pub async fn init_game_state(
 
         let wall_3_node_mesh = GameNodeVisual {
             material: Material::Pbr {
-                binded_material_index: wall_pbr_material_index,
+                binded_material: wall_pbr_material_index,
                 dynamic_pbr_params: Some(DynamicPbrParams {
                     base_color_factor: Vec4::new(1.0, 0.5, 1.0, 1.0),
                     ..Default::default()
@@ -865,7 +1204,7 @@ pub async fn init_game_state(
 
         let wall_4_node_mesh = GameNodeVisual {
             material: Material::Pbr {
-                binded_material_index: wall_pbr_material_index,
+                binded_material: wall_pbr_material_index,
                 dynamic_pbr_params: Some(DynamicPbrParams {
                     base_color_factor: Vec4::new(1.0, 1.0, 0.5, 1.0),
                     ..Default::default()
@@ -919,9 +1258,13 @@ pub async fn init_game_state(
         &mut renderer.data.lock().unwrap(),
         &PbrTextures {
             base_color: Some(&checkerboard_texture),
+            normal: Some(&floor_normal_map),
+            ..Default::default()
+        },
+        DynamicPbrParams {
+            parallax_height_scale: 0.002,
             ..Default::default()
         },
-        Default::default(),
     )?;
     let floor_transform = TransformBuilder::new()
         .position(Vec3::new(0.0, -0.01, 0.0))
@@ -930,12 +1273,50 @@ pub async fn init_game_state(
     let _floor_node = scene.add_node(
         GameNodeDescBuilder::new()
             .visual(Some(GameNodeVisual::make_pbr(
-                renderer.constant_data.plane_mesh_index,
+                renderer.constant_data.plane_mesh,
                 floor_pbr_mesh_index,
             )))
             .transform(floor_transform)
             .build(),
     );
+    // a pond in a corner of the arena, the balls that fall in splash and leave ripples
+    let pond_surface = WaterSurface {
+        height: 0.3,
+        min_xz: Vec2::new(-45.0, -45.0),
+        max_xz: Vec2::new(-15.0, -15.0),
+    };
+    let mut pond_ripples = WaterRippleSimulation::new(&renderer.base, POND_RIPPLE_RESOLUTION);
+    pond_ripples.bind_surface_material(renderer)?;
+    let pond_center = (pond_surface.min_xz + pond_surface.max_xz) / 2.0;
+    let pond_node_id = scene
+        .add_node(
+            GameNodeDescBuilder::new()
+                .visual(
+                    pond_ripples
+                        .surface_material(&pond_surface)
+                        .map(|material| {
+                            GameNodeVisual::from_mesh_mat(
+                                renderer.constant_data.plane_mesh,
+                                material,
+                            )
+                        }),
+                )
+                .transform(
+                    TransformBuilder::new()
+                        .position(Vec3::new(pond_center.x, pond_surface.height, pond_center.y))
+                        .scale(Vec3::new(
+                            pond_surface.size().x / 2.0,
+                            1.0,
+                            pond_surface.size().y / 2.0,
+                        ))
+                        .build(),
+                )
+                .name(Some("pond".to_string()))
+                .build(),
+        )
+        .id();
+    let pond = WaterInteractionSystem::new(pond_surface);
+
     // let ceiling_transform = TransformBuilder::new()
     //     .position(Vec3::new(0.0, 10.0, 0.0))
     //     .scale(Vec3::new(ARENA_SIDE_LENGTH, 1.0, ARENA_SIDE_LENGTH))
@@ -987,7 +1368,7 @@ pub async fn init_game_state(
         let bouncing_ball_node = scene.add_node(
             GameNodeDescBuilder::new()
                 .visual(Some(GameNodeVisual::make_pbr(
-                    renderer.constant_data.sphere_mesh_index,
+                    renderer.constant_data.sphere_mesh,
                     bouncing_ball_pbr_mesh_index,
                 )))
                 .transform(
@@ -1087,6 +1468,7 @@ pub async fn init_game_state(
                 color: [1.0, 1.0, 1.0, 1.0],
                 bone_indices: [0, 1, 2, 3],
                 bone_weights: [1.0, 0.0, 0.0, 0.0],
+                lightmap_tex_coords: [0.0, 0.0],
             })
             .collect(),
         indices: vec![0, 2, 1, 0, 3, 2],
@@ -1120,7 +1502,7 @@ pub async fn init_game_state(
                     .visual(Some(GameNodeVisual::from_mesh_mat(
                         crosshair_mesh_index,
                         Material::Pbr {
-                            binded_material_index: crosshair_material_index,
+                            binded_material: crosshair_material_index,
                             dynamic_pbr_params: Some(DynamicPbrParams {
                                 emissive_factor: crosshair_color,
                                 base_color_factor: Vec4::new(0.0, 0.0, 0.0, 1.0),
@@ -1134,14 +1516,38 @@ pub async fn init_game_state(
             .id(),
     );
 
+    let mut decals = Decals::new(renderer);
+    decals.set_max_count(IMPACT_EFFECT, MAX_BULLET_HOLE_DECALS);
+
+    let mut effect_library = EffectLibrary::new();
+    for path in [WEAPON_EFFECTS_PATH, WATER_EFFECTS_PATH] {
+        effect_library.load_file(&asset_loader_clone, GAME_PATH_MAKER.make(path));
+    }
+    let mut particle_system = ParticleSystem::new(renderer);
+    particle_system.set_particle_limit_scale(settings.graphics.particle_limit_scale);
+
     let ui_overlay = {
         let surface_format = surface_data.surface_config.format;
+        let mut ui_overlay = UiOverlay::new(window);
+        // the options menu is applied to the renderer every frame so it starts from the settings
+        {
+            let renderer_data_guard = renderer.data.lock().unwrap();
+            ui_overlay.enable_vsync = settings.graphics.vsync;
+            ui_overlay.enable_soft_shadows = renderer_data_guard.enable_soft_shadows;
+            ui_overlay.soft_shadow_grid_dims = renderer_data_guard.soft_shadow_grid_dims;
+            ui_overlay.quality_preset = settings.graphics.preset;
+            ui_overlay.bloom_type = renderer_data_guard.bloom_type;
+            ui_overlay.texture_filtering = Some(settings.graphics.texture_filtering);
+            ui_overlay.texture_resolution_bias = settings.graphics.texture_resolution_bias;
+            ui_overlay.enable_taa = renderer_data_guard.taa.is_enabled();
+            ui_overlay.parallax_max_step_count = renderer_data_guard.parallax_max_step_count;
+        }
         IkariUiContainer::new(
             window,
             &renderer.base.device,
             &renderer.base.queue,
             surface_format,
-            UiOverlay::new(window),
+            ui_overlay,
             Some(DEFAULT_FONT_NAME),
             vec![
                 DEFAULT_FONT_BYTES,
@@ -1152,18 +1558,49 @@ pub async fn init_game_state(
         )
     };
 
+    let mut hud = Hud::default();
+    let revolver_hud_label = hud.add(HudWidget {
+        kind: HudWidgetKind::Text(HudText {
+            text: "Revolver".to_string(),
+            size: 28.0,
+            color: iced::Color::WHITE,
+            font: Some(iced::Font::with_name(KOOKY_FONT_NAME)),
+        }),
+        margin: (24.0, 4.0),
+        is_visible: false,
+        anchor: HudAnchor::BottomRight,
+    });
+    let revolver_cooldown_hud_bar = hud.add(HudWidget {
+        margin: (24.0, 24.0),
+        is_visible: false,
+        ..HudWidget::progress_bar(1.0, (160.0, 10.0), HudAnchor::BottomRight)
+    });
+    let minimap = Minimap {
+        markers: vec![(
+            "ball".to_string(),
+            MinimapMarker::new(MinimapMarkerShape::Circle, [255, 140, 40, 255], 2.5),
+        )],
+        fog_of_war: Some(FogOfWar::new(2.0, 20.0)),
+        ..Minimap::new(MINIMAP_RESOLUTION, 40.0)
+    };
+    let minimap_hud_image = hud.add(HudWidget::image(
+        iced::widget::image::Handle::from_pixels(1, 1, vec![0; 4]),
+        HudAnchor::TopRight,
+    ));
+
     Ok(GameState {
-        state_update_time_accumulator: 0.0,
         is_playing_animations: true,
 
         bgm_sound_index: None,
+        music_player: MusicPlayer {
+            volume: 0.3,
+            ..Default::default()
+        },
         gunshot_sound_index: None,
         // gunshot_sound_data,
         point_light_node_ids,
 
-        next_balls: balls.clone(),
-        prev_balls: balls.clone(),
-        actual_balls: balls,
+        balls,
         ball_node_ids,
         ball_pbr_mesh_index: ball_pbr_material_index,
 
@@ -1176,17 +1613,51 @@ pub async fn init_game_state(
         bouncing_ball_node_id,
         bouncing_ball_body_handle,
 
-        physics_balls,
-
         // player_node_id,
         player_controller,
+        camera_system,
+        input_map: InputMap::new(input_bindings, InputContext::Gameplay),
+        gamepads: Gamepads::new(),
         character: None,
 
         asset_loader: asset_loader_clone,
         asset_binder,
+        scene_transition,
+        test_level: None,
         asset_id_map: asset_id_map_clone,
+        pending_spawned_scenes: vec![],
+
+        editor: Editor::default(),
+        editor_assets: HashMap::new(),
+
+        #[cfg(feature = "scripting")]
+        script_host,
 
         ui_overlay,
+        hud,
+        world_labels: WorldLabels::default(),
+        health_system: HealthSystem::default(),
+        time_of_day: TimeOfDay::new(Default::default(), 10.0),
+        settings,
+        #[cfg(not(target_arch = "wasm32"))]
+        settings_path,
+        was_showing_options_menu: false,
+        was_editor_enabled: false,
+        revolver_hud_label,
+        revolver_cooldown_hud_bar,
+        minimap,
+        minimap_hud_image,
+        looked_at_ball_node_id: None,
+        decals,
+        light_flashes: LightFlashes::default(),
+        effect_library,
+        particle_system,
+        pond,
+        pond_ripples,
+        pond_node_id,
+        debug_draw: DebugDraw::new(),
+        physics_debug_draw: Default::default(),
+        is_showing_physics_debug: false,
     })
 }
 
@@ -1202,98 +1673,181 @@ pub fn process_window_input(
     }: GameContext<GameState>,
     event: &winit::event::WindowEvent,
 ) {
-    #[allow(clippy::single_match)]
-    match event {
-        WindowEvent::KeyboardInput { event, .. } => {
-            let key = event.logical_key.as_ref();
-            if event.state == ElementState::Pressed && key == Key::Named(NamedKey::Tab) {
+    for ActionEvent { action, is_pressed } in game_state.input_map.process_window_event(event) {
+        if is_pressed && action == "toggle_popup_menu" {
+            game_state
+                .ui_overlay
+                .queue_message(Message::TogglePopupMenu);
+        }
+
+        if is_pressed {
+            continue;
+        }
+
+        let mut render_data_guard = renderer.data.lock().unwrap();
+        match action.as_str() {
+            "decrease_render_scale" => {
+                drop(render_data_guard);
+                increment_render_scale(
+                    renderer,
+                    surface_data,
+                    false,
+                    window,
+                    &mut game_state.ui_overlay,
+                );
+            }
+            "increase_render_scale" => {
+                drop(render_data_guard);
+                increment_render_scale(
+                    renderer,
+                    surface_data,
+                    true,
+                    window,
+                    &mut game_state.ui_overlay,
+                );
+            }
+            "decrease_exposure" => {
+                increment_exposure(&mut render_data_guard, false);
+            }
+            "increase_exposure" => {
+                increment_exposure(&mut render_data_guard, true);
+            }
+            "decrease_bloom_threshold" => {
+                increment_bloom_threshold(&mut render_data_guard, false);
+            }
+            "increase_bloom_threshold" => {
+                increment_bloom_threshold(&mut render_data_guard, true);
+            }
+            "toggle_animations" => {
+                game_state.is_playing_animations = !game_state.is_playing_animations;
+            }
+            "toggle_shadows" => {
+                render_data_guard.enable_shadows = !render_data_guard.enable_shadows;
+            }
+            "cycle_bloom_type" => {
                 game_state
                     .ui_overlay
-                    .queue_message(Message::TogglePopupMenu);
+                    .queue_message(Message::BloomTypeChanged(
+                        match render_data_guard.bloom_type {
+                            BloomType::Disabled => BloomType::Old,
+                            BloomType::Old => BloomType::New,
+                            BloomType::New => BloomType::Disabled,
+                        },
+                    ));
             }
-
-            if event.state == ElementState::Released {
-                let mut render_data_guard = renderer.data.lock().unwrap();
-                match key {
-                    Key::Character(character) => match character.to_lowercase().as_str() {
-                        "z" => {
-                            drop(render_data_guard);
-                            increment_render_scale(
-                                renderer,
-                                surface_data,
-                                false,
-                                window,
-                                &mut game_state.ui_overlay,
-                            );
-                        }
-                        "x" => {
-                            drop(render_data_guard);
-                            increment_render_scale(
-                                renderer,
-                                surface_data,
-                                true,
-                                window,
-                                &mut game_state.ui_overlay,
-                            );
-                        }
-                        "r" => {
-                            increment_exposure(&mut render_data_guard, false);
-                        }
-                        "t" => {
-                            increment_exposure(&mut render_data_guard, true);
-                        }
-                        "y" => {
-                            increment_bloom_threshold(&mut render_data_guard, false);
-                        }
-                        "u" => {
-                            increment_bloom_threshold(&mut render_data_guard, true);
-                        }
-                        "p" => {
-                            game_state.is_playing_animations = !game_state.is_playing_animations;
-                        }
-                        "m" => {
-                            render_data_guard.enable_shadows = !render_data_guard.enable_shadows;
-                        }
-                        "b" => {
-                            game_state
-                                .ui_overlay
-                                .queue_message(Message::BloomTypeChanged(match render_data_guard
-                                    .bloom_type
-                                {
-                                    BloomType::Disabled => BloomType::Old,
-                                    BloomType::Old => BloomType::New,
-                                    BloomType::New => BloomType::Disabled,
-                                }));
-                        }
-                        "f" => {
-                            render_data_guard.enable_wireframe_mode =
-                                !render_data_guard.enable_wireframe_mode;
-                        }
-                        "j" => {
-                            render_data_guard.draw_node_bounding_spheres =
-                                !render_data_guard.draw_node_bounding_spheres;
-                        }
-                        "c" => {
-                            if let Some(character) = game_state.character.as_mut() {
-                                character.toggle_collision_box_display(&mut engine_state.scene);
-                            }
-                        }
-                        _ => {}
-                    },
-                    Key::Named(NamedKey::Escape) => {
-                        elwt.exit();
-                    }
-                    _ => {}
+            "toggle_wireframe" => {
+                render_data_guard.enable_wireframe_mode = !render_data_guard.enable_wireframe_mode;
+            }
+            "toggle_bounding_spheres" => {
+                render_data_guard.draw_node_bounding_spheres =
+                    !render_data_guard.draw_node_bounding_spheres;
+            }
+            "print_perf_report" => {
+                let render_height = (surface_data.surface_config.height as f32
+                    * render_data_guard.render_scale) as u32;
+                let report = analyze_scene(
+                    &engine_state.scene,
+                    &render_data_guard,
+                    render_height,
+                    Some(engine_state.time().last_frame_time()),
+                    &PerfAdvisorConfig::default(),
+                );
+                log::info!("{report}");
+            }
+            "cycle_camera" => {
+                game_state
+                    .camera_system
+                    .cycle_active_camera(CAMERA_BLEND_SECONDS);
+            }
+            "toggle_level_editor" => {
+                let is_editor_enabled = game_state.ui_overlay.get_state().is_editor_enabled;
+                game_state
+                    .ui_overlay
+                    .queue_message(Message::ToggleEditor(!is_editor_enabled));
+            }
+            "toggle_physics_debug" => {
+                game_state.is_showing_physics_debug = !game_state.is_showing_physics_debug;
+            }
+            "toggle_collision_boxes" => {
+                if let Some(character) = game_state.character.as_mut() {
+                    character.toggle_collision_box_display(&mut engine_state.scene);
+                }
+            }
+            "toggle_time_paused" => {
+                engine_state.game_state_stack.toggle_pause();
+            }
+            "step_frame" => {
+                if engine_state.game_state_stack.should_run_simulation() {
+                    engine_state.game_state_stack.push(GameStateKind::Paused);
+                }
+                engine_state.time_control.step_frame();
+            }
+            "cycle_time_scale" => {
+                let time_scale = engine_state.time_control.time_scale();
+                let next_time_scale = TIME_SCALES
+                    .iter()
+                    .copied()
+                    .find(|scale| *scale < time_scale)
+                    .unwrap_or(TIME_SCALES[0]);
+                engine_state.time_control.set_time_scale(next_time_scale);
+                log::info!("Time scale: {next_time_scale}");
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            "quick_save" => match quick_save(engine_state, game_state) {
+                Ok(()) => log::info!("Game saved"),
+                Err(err) => log::error!("Error saving the game: {err:?}"),
+            },
+            #[cfg(not(target_arch = "wasm32"))]
+            "quick_load" => match quick_load(engine_state, game_state) {
+                Ok(()) => log::info!("Game loaded"),
+                Err(err) => log::error!("Error loading the game: {err:?}"),
+            },
+            "reload_level" => {
+                if let Err(err) = game_state
+                    .scene_transition
+                    .start(&game_state.asset_loader, vec![test_level_load_params()])
+                {
+                    log::warn!("{err}");
                 }
             }
+            "exit" => {
+                elwt.exit();
+            }
+            _ => {}
         }
-        _ => {}
-    };
+    }
 
     game_state
         .player_controller
         .process_window_event(event, window);
 
+    #[cfg(feature = "scripting")]
+    if engine_state.game_state_stack.input_context() == InputContext::Gameplay {
+        game_state.script_host.process_window_event(event);
+    }
+
+    if let winit::event::WindowEvent::MouseInput {
+        state: winit::event::ElementState::Released,
+        button: winit::event::MouseButton::Left,
+        ..
+    } = event
+    {
+        if game_state.ui_overlay.get_state().is_dragging_asset() {
+            game_state
+                .ui_overlay
+                .queue_message(Message::ContentBrowserAssetDropped);
+        }
+    }
+
+    let cursor_captured_by_ui = game_state.ui_overlay.is_cursor_over_widget();
+    game_state.editor.process_window_event(
+        event,
+        window,
+        cursor_captured_by_ui,
+        engine_state,
+        renderer,
+    );
+
     game_state.ui_overlay.handle_window_event(window, event);
 }
 
@@ -1355,6 +1909,44 @@ pub fn increment_render_scale(
     ui_overlay.resize(unscaled_framebuffer_size, window.scale_factor());
 }
 
+/// Saves the settings as they currently are, e.g. after the player closed the options menu
+fn save_settings(game_state: &mut GameState, engine_state: &EngineState, renderer: &Renderer) {
+    let settings = &mut game_state.settings;
+    let ui_state = game_state.ui_overlay.get_state();
+    settings.graphics = GraphicsSettings::from_renderer_data(
+        &renderer.data.lock().unwrap(),
+        TextureQuality {
+            filtering: ui_state.texture_filtering,
+            resolution_bias: ui_state.texture_resolution_bias,
+        },
+        ui_state.enable_vsync,
+        engine_state.frame_limiter.max_fps(),
+        settings.graphics.particle_limit_scale,
+    );
+    settings.audio = AudioSettings::from_audio_manager(&engine_state.audio_manager.lock().unwrap());
+    add_settings_to_crash_reports(settings);
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(settings_path) = game_state.settings_path.as_deref() {
+        if let Err(err) = settings.save(settings_path) {
+            log::error!("Error saving the settings: {err:?}");
+        }
+    }
+    #[cfg(target_arch = "wasm32")]
+    if let Err(err) = settings.save_to_local_storage(SETTINGS_APP_NAME) {
+        log::error!("Error saving the settings: {err:?}");
+    }
+}
+
+fn add_settings_to_crash_reports(settings: &Settings) {
+    match settings.to_toml_string() {
+        Ok(settings_toml) => {
+            ikari::crash_handler::set_report_section("settings.toml", settings_toml);
+        }
+        Err(err) => log::warn!("Failed to serialize the settings for the crash reports: {err:?}"),
+    }
+}
+
 pub fn increment_exposure(renderer_data: &mut RendererData, increase: bool) {
     let delta = 0.05;
     let change = if increase { delta } else { -delta };
@@ -1371,6 +1963,31 @@ pub fn increment_bloom_threshold(renderer_data: &mut RendererData, increase: boo
 }
 
 #[profiling::function]
+pub fn fixed_update_game_state(
+    GameContext {
+        game_state,
+        engine_state,
+        ..
+    }: GameContext<GameState>,
+) {
+    let timestep_seconds = engine_state.simulation_timestep.timestep_seconds;
+
+    let physics_state = &mut engine_state.physics_state;
+    physics_state.integration_parameters.dt = timestep_seconds;
+    physics_state.step();
+
+    #[cfg(feature = "scripting")]
+    game_state.script_host.fixed_update(engine_state);
+
+    // the scene draws them in between the last two fixed updates
+    for (ball, node_id) in game_state.balls.iter_mut().zip(&game_state.ball_node_ids) {
+        ball.update(timestep_seconds);
+        if let Some(node) = engine_state.scene.get_node_mut(*node_id) {
+            node.transform = ball.transform;
+        }
+    }
+}
+
 pub fn update_game_state(
     GameContext {
         game_state,
@@ -1405,6 +2022,33 @@ pub fn update_game_state(
         }
     }
 
+    if let Some(loaded_scenes) = game_state.scene_transition.update(
+        engine_state.time().last_frame_time(),
+        &game_state.asset_loader,
+        &game_state.asset_binder,
+    ) {
+        let mut renderer_data_guard = renderer_data.lock().unwrap();
+        if let Some((asset_id, node_ids)) = game_state.test_level.take() {
+            if let Err(err) =
+                remove_test_level(engine_state, &mut renderer_data_guard, asset_id, &node_ids)
+            {
+                log::error!("Error unloading the test level: {err:?}");
+            }
+        }
+        for loaded_scene in loaded_scenes {
+            game_state.test_level = Some(add_test_level(
+                engine_state,
+                &mut renderer_data_guard,
+                loaded_scene,
+            ));
+        }
+    }
+    game_state
+        .ui_overlay
+        .queue_message(Message::LoadingScreenChanged(
+            game_state.scene_transition.loading_screen(),
+        ));
+
     {
         let loaded_scenes = game_state.asset_binder.loaded_scenes();
         let mut loaded_assets_guard = loaded_scenes.lock().unwrap();
@@ -1519,46 +2163,41 @@ pub fn update_game_state(
             }
         }
 
-        if let Some(asset_id) =
-            asset_id_map_guard.get(&"src/models/gltf/TestLevel/test_level.glb".to_string())
-        {
-            if let Entry::Occupied(entry) = loaded_assets_guard.entry(*asset_id) {
+        game_state
+            .pending_spawned_scenes
+            .retain(|(asset_id, spawn_position, asset)| {
+                let Entry::Occupied(entry) = loaded_assets_guard.entry(*asset_id) else {
+                    return true;
+                };
                 let (_, (other_scene, other_render_buffers)) = entry.remove_entry();
+
+                // the asset goes under its own node so the level editor can move it and
+                // save it as a reference to the file
+                let asset_node_id = engine_state
+                    .scene
+                    .add_node(
+                        GameNodeDescBuilder::new()
+                            .name(Some(asset.path.clone()))
+                            .transform(TransformBuilder::new().position(*spawn_position).build())
+                            .build(),
+                    )
+                    .id();
                 let skip_nodes = engine_state.scene.node_count();
                 engine_state.scene.merge_scene(
                     &mut renderer_data_guard,
                     other_scene,
                     other_render_buffers,
                 );
-
-                let test_level_node_ids: Vec<_> = engine_state
-                    .scene
-                    .nodes()
-                    .skip(skip_nodes)
-                    .map(|node| node.id())
-                    .collect();
-                for node_id in test_level_node_ids {
-                    if let Some(_mesh) = engine_state
-                        .scene
-                        .get_node_mut(node_id)
-                        .unwrap()
-                        .visual
-                        .as_mut()
-                    {
-                        // mesh.wireframe = true;
+                for node in engine_state.scene.nodes_mut().skip(skip_nodes) {
+                    if node.parent_id.is_none() {
+                        node.parent_id = Some(asset_node_id);
                     }
-                    let transform =
-                        &mut engine_state.scene.get_node_mut(node_id).unwrap().transform;
-                    transform.set_position(transform.position() + Vec3::new(0.0, 25.0, 0.0));
-                    add_static_box(
-                        &mut engine_state.physics_state,
-                        &engine_state.scene,
-                        &renderer_data_guard,
-                        node_id,
-                    );
                 }
-            }
-        }
+                game_state
+                    .editor_assets
+                    .insert(asset_node_id, asset.clone());
+                false
+            });
 
         if let Some(asset_id) = asset_id_map_guard.get(&get_misc_gltf_path().to_string()) {
             if let Entry::Occupied(entry) = loaded_assets_guard.entry(*asset_id) {
@@ -1584,16 +2223,11 @@ pub fn update_game_state(
             if let Entry::Occupied(entry) = loaded_audio_guard.entry(*asset_id) {
                 let (_, bgm_sound_index) = entry.remove_entry();
                 game_state.bgm_sound_index = Some(bgm_sound_index);
-
-                let audio_manager_clone = engine_state.audio_manager.clone();
-                let bgm_sound_index_clone = bgm_sound_index;
-                ikari::thread::spawn(move || {
-                    // #[cfg(not(target_arch = "wasm32"))]
-                    // ikari::thread::sleep(ikari::time::Duration::from_secs_f32(5.0));
-
-                    let mut audio_manager_guard = audio_manager_clone.lock().unwrap();
-                    audio_manager_guard.play_sound(bgm_sound_index_clone);
-                });
+                game_state.music_player.play(
+                    &mut engine_state.audio_manager.lock().unwrap(),
+                    bgm_sound_index,
+                    MUSIC_FADE_IN_SECONDS,
+                );
             }
         }
         if let Some(asset_id) = asset_id_map_guard.get(&"src/sounds/gunshot.wav".to_string()) {
@@ -1601,6 +2235,33 @@ pub fn update_game_state(
                 let (_, gunshot_sound_index) = entry.remove_entry();
                 game_state.gunshot_sound_index = Some(gunshot_sound_index);
                 // audio_manager_guard.set_sound_volume(gunshot_sound_index, 0.001);
+                engine_state.audio_manager.lock().unwrap().sound_cues.add(
+                    GUNSHOT_CUE,
+                    SoundCue {
+                        volume_range: (0.85, 1.0),
+                        pitch_range: (0.94, 1.06),
+                        ..SoundCue::new(vec![SoundCueClip {
+                            sound_index: gunshot_sound_index,
+                            weight: 1.0,
+                        }])
+                    },
+                );
+            }
+        }
+        if let Some(asset_id) = asset_id_map_guard.get(&"src/sounds/splash.wav".to_string()) {
+            if let Entry::Occupied(entry) = loaded_audio_guard.entry(*asset_id) {
+                let (_, splash_sound_index) = entry.remove_entry();
+                engine_state.audio_manager.lock().unwrap().sound_cues.add(
+                    WATER_SPLASH_CUE,
+                    SoundCue {
+                        volume_range: (0.6, 1.0),
+                        pitch_range: (0.85, 1.15),
+                        ..SoundCue::new(vec![SoundCueClip {
+                            sound_index: splash_sound_index,
+                            weight: 1.0,
+                        }])
+                    },
+                );
             }
         }
     }
@@ -1637,8 +2298,6 @@ pub fn update_game_state(
     let time_tracker = engine_state.time();
     let global_time_seconds = time_tracker.global_time().as_secs_f32();
 
-    // results in ~60 state changes per second
-    let min_update_timestep_seconds = 1.0 / 60.0;
     // if frametime takes longer than this, we give up on trying to catch up completely
     // prevents the game from getting stuck in a spiral of death
     let max_delay_catchup_seconds = 0.25;
@@ -1646,51 +2305,77 @@ pub fn update_game_state(
     if frame_time_seconds > max_delay_catchup_seconds {
         frame_time_seconds = max_delay_catchup_seconds;
     }
-    game_state.state_update_time_accumulator += frame_time_seconds;
-
-    engine_state.physics_state.step();
-
+    // the world follows engine_state.time_control while the camera, audio and UI stay in real time
+    let time_control = &engine_state.time_control;
+    let world_time_seconds = time_control
+        .world_delta_seconds()
+        .min(max_delay_catchup_seconds);
+    let player_time_seconds = time_control
+        .delta_seconds(TimeChannel::Player)
+        .min(max_delay_catchup_seconds);
+    let animation_time_seconds = time_control
+        .delta_seconds(TimeChannel::Animation)
+        .min(max_delay_catchup_seconds);
+    let script_time_seconds = time_control
+        .delta_seconds(TimeChannel::Scripts)
+        .min(max_delay_catchup_seconds);
+    for ActionEvent { action, is_pressed } in game_state.gamepads.update(&mut game_state.input_map)
+    {
+        if is_pressed && action == "toggle_popup_menu" {
+            game_state
+                .ui_overlay
+                .queue_message(Message::TogglePopupMenu);
+        }
+        if !is_pressed && action == "cycle_camera" {
+            game_state
+                .camera_system
+                .cycle_active_camera(CAMERA_BLEND_SECONDS);
+        }
+    }
+    game_state
+        .player_controller
+        .process_input(&game_state.input_map, player_time_seconds as f32);
     game_state
         .player_controller
         .update(&mut engine_state.physics_state);
 
-    let new_player_transform = game_state
-        .player_controller
-        .transform(&engine_state.physics_state);
-    if let Some(camera_node_id) = renderer_data.lock().unwrap().camera_node_id {
-        if let Some(player_transform) = engine_state.scene.get_node_mut(camera_node_id) {
-            player_transform.transform = new_player_transform;
-        }
+    let camera_node_id = renderer_data.lock().unwrap().camera_node_id;
+    if let Some(camera_node_id) = camera_node_id {
+        game_state.camera_system.update(
+            &mut engine_state.scene,
+            &engine_state.physics_state,
+            Some(&game_state.player_controller),
+            camera_node_id,
+            frame_time_seconds as f32,
+        );
+    }
+    if let Some(camera_node_id) = camera_node_id {
+        let listener_transform = engine_state
+            .scene
+            .get_global_transform_for_node(camera_node_id);
+        engine_state
+            .audio_manager
+            .lock()
+            .unwrap()
+            .update_spatial_audio(
+                listener_transform,
+                &engine_state.scene,
+                frame_time_seconds as f32,
+            );
+    }
+    game_state.music_player.update(
+        &mut engine_state.audio_manager.lock().unwrap(),
+        engine_state.game_state_stack.current(),
+        frame_time_seconds as f32,
+    );
+    let surface_aspect_ratio =
+        surface_data.surface_config.width as f32 / surface_data.surface_config.height.max(1) as f32;
+    {
+        let mut renderer_data_guard = renderer_data.lock().unwrap();
+        renderer_data_guard.camera_lens =
+            game_state.camera_system.active_lens(surface_aspect_ratio);
+        renderer_data_guard.camera_render_layers = game_state.camera_system.active_render_layers();
     }
-
-    // update ball positions
-    while game_state.state_update_time_accumulator >= min_update_timestep_seconds {
-        if game_state.state_update_time_accumulator < min_update_timestep_seconds * 2.0 {
-            game_state.prev_balls = game_state.next_balls.clone();
-        }
-        game_state.prev_balls = game_state.next_balls.clone();
-        game_state
-            .next_balls
-            .iter_mut()
-            .for_each(|ball| ball.update(min_update_timestep_seconds));
-        game_state.state_update_time_accumulator -= min_update_timestep_seconds;
-    }
-    let alpha = game_state.state_update_time_accumulator / min_update_timestep_seconds;
-    game_state.actual_balls = game_state
-        .prev_balls
-        .iter()
-        .zip(game_state.next_balls.iter())
-        .map(|(prev_ball, next_ball)| prev_ball.lerp(next_ball, alpha as f32))
-        .collect();
-    game_state
-        .ball_node_ids
-        .iter()
-        .zip(game_state.actual_balls.iter())
-        .for_each(|(node_id, ball)| {
-            if let Some(node) = engine_state.scene.get_node_mut(*node_id) {
-                node.transform = ball.transform;
-            }
-        });
 
     if let Some(point_light_0) = engine_state.scene.point_lights.get_mut(0) {
         let t = {
@@ -1710,8 +2395,6 @@ pub fn update_game_state(
             t
         };
 
-        point_light_0.color = lerp_vec(POINT_LIGHT_COLOR, POINT_LIGHT_COLOR_B, (2.0 * t).sin());
-
         let node_id = point_light_0.node_id;
         if let Some(node) = engine_state.scene.get_node_mut(node_id) {
             let center = if CREATE_POINT_SHADOW_MAP_DEBUG_OBJECTS {
@@ -1744,8 +2427,12 @@ pub fn update_game_state(
     let point_lights_with_ids: Vec<_> = game_state
         .point_light_node_ids
         .iter()
-        .cloned()
-        .zip(engine_state.scene.point_lights.iter().cloned())
+        .flat_map(|node_id| {
+            engine_state
+                .scene
+                .get_point_light(*node_id)
+                .map(|point_light| (*node_id, point_light.clone()))
+        })
         .collect();
     point_lights_with_ids
         .iter()
@@ -1785,9 +2472,18 @@ pub fn update_game_state(
         // engine_state.scene.directional_lights[0] = directional_light_0;
     }
 
+    if ENABLE_DAY_NIGHT_CYCLE {
+        for event in game_state.time_of_day.update(world_time_seconds as f32) {
+            log::info!("{event:?} at {:.1}h", game_state.time_of_day.hours());
+        }
+        game_state
+            .time_of_day
+            .apply(&mut engine_state.scene.directional_lights, 0, renderer);
+    }
+
     // rotate the test object
     let rotational_displacement =
-        Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), frame_time_seconds as f32 / 5.0);
+        Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), world_time_seconds as f32 / 5.0);
     if let Some(node) = engine_state
         .scene
         .get_node_mut(game_state.test_object_node_id)
@@ -1796,14 +2492,22 @@ pub fn update_game_state(
             .set_rotation(rotational_displacement * node.transform.rotation());
     }
 
+    #[cfg(feature = "scripting")]
+    {
+        game_state.script_host.reload_changed_scripts();
+        game_state
+            .script_host
+            .update(engine_state, script_time_seconds);
+    }
+
     // remove physics balls over time
-    game_state.ball_spawner_acc += frame_time_seconds;
+    game_state.ball_spawner_acc += world_time_seconds;
     let rate = 0.1; // lower value spawns balls more quickly
-    let prev_ball_count = game_state.physics_balls.len();
+    let prev_ball_count = engine_state.world.query::<PhysicsBall>().count();
     while game_state.ball_spawner_acc > rate {
         // let new_ball = BallComponent::rand();
         // let new_ball_transform = new_ball.transform;
-        // game_state.next_balls.push(new_ball);
+        // game_state.balls.push(new_ball);
         // engine_state.scene.nodes.push(
         //     GameNodeBuilder::new()
         //         .mesh(Some(GameNodeMesh::Pbr {
@@ -1826,7 +2530,7 @@ pub fn update_game_state(
         // ));
         game_state.ball_spawner_acc -= rate;
     }
-    let new_ball_count = game_state.physics_balls.len();
+    let new_ball_count = engine_state.world.query::<PhysicsBall>().count();
     if prev_ball_count != new_ball_count {
         // logger_log(&format!("Ball count: {:?}", new_ball_count));
     }
@@ -1839,12 +2543,7 @@ pub fn update_game_state(
     {
         node.transform.apply_isometry(*ball_body.position());
     }
-
-    physics_state.integration_parameters.dt = frame_time_seconds;
-    game_state
-        .physics_balls
-        .iter()
-        .for_each(|physics_ball| physics_ball.update(&mut engine_state.scene, physics_state));
+    physics_state.sync_rigid_body_components(&mut engine_state.scene);
 
     if let Some(crosshair_node) = game_state
         .crosshair_node_id
@@ -1865,115 +2564,185 @@ pub fn update_game_state(
                 .build();
     }
 
-    if let Some(revolver) = game_state.revolver.as_mut() {
+    let weapon_events = if let Some(revolver) = game_state.revolver.as_mut() {
         revolver.update(
             game_state.player_controller.view_direction,
             &mut engine_state.scene,
         );
 
-        if game_state.player_controller.mouse_button_pressed
-            && revolver.fire(&mut engine_state.scene)
+        let mut weapon_events = revolver
+            .weapon
+            .update(&mut engine_state.scene, world_time_seconds as f32);
+        // no shooting from the menus or while the simulation is paused
+        let is_in_gameplay = engine_state.game_state_stack.should_run_simulation()
+            && engine_state.game_state_stack.input_context() == InputContext::Gameplay;
+        if is_in_gameplay
+            && game_state.input_map.is_action_pressed("reload")
+            && revolver.weapon.reload(&mut engine_state.scene)
         {
-            /* if let Some(bgm_sound_index) = game_state.bgm_sound_index {
-                if time_tracker.global_time_seconds() > 30.0 {
-                    game_state
-                        .audio_manager
-                        .lock()
-                        .unwrap()
-                        .play_sound(bgm_sound_index);
-                    game_state.bgm_sound_index = None;
-                }
-            } */
-
-            if let Some(gunshot_sound_index) = game_state.gunshot_sound_index {
-                {
-                    let mut audio_manager_guard = engine_state.audio_manager.lock().unwrap();
-                    audio_manager_guard.play_sound(gunshot_sound_index);
-                    audio_manager_guard.reload_sound(
-                        gunshot_sound_index,
-                        SoundParams {
-                            initial_volume: 0.4,
-                            fixed_volume: true,
-                            spacial_params: None,
-                            stream: false,
-                        },
-                    )
-                }
-            }
+            weapon_events.push(WeaponEvent::ReloadStarted);
+        }
 
+        let is_fire_pressed = game_state.player_controller.mouse_button_pressed
+            || game_state.input_map.is_action_pressed("fire");
+        if is_in_gameplay && is_fire_pressed {
             let player_position = game_state
                 .player_controller
                 .position(&engine_state.physics_state);
-            let direction_vec = game_state.player_controller.view_direction.to_vector();
-            let ray = Ray::new(
-                point![
-                    player_position.x as f64,
-                    player_position.y as f64,
-                    player_position.z as f64
-                ],
-                vector![
-                    direction_vec.x as f64,
-                    direction_vec.y as f64,
-                    direction_vec.z as f64
-                ],
+            let direction = game_state.player_controller.view_direction.to_vector();
+            weapon_events.extend(
+                revolver
+                    .weapon
+                    .fire(engine_state, player_position, direction),
             );
-            let max_distance = ARENA_SIDE_LENGTH as f64 * 10.0;
-            let solid = true;
-            if let Some((collider_handle, collision_point_distance)) =
-                engine_state.physics_state.query_pipeline.cast_ray(
-                    &engine_state.physics_state.rigid_body_set,
-                    &engine_state.physics_state.collider_set,
-                    &ray,
-                    max_distance,
-                    solid,
-                    QueryFilter::from(
-                        InteractionGroups::all().with_filter(!COLLISION_GROUP_PLAYER_UNSHOOTABLE),
-                    ),
-                )
-            {
-                // The first collider hit has the handle `handle` and it hit after
-                // the ray travelled a distance equal to `ray.dir * toi`.
-                let _hit_point = ray.point_at(collision_point_distance); // Same as: `ray.origin + ray.dir * toi`
-
-                if let Some(rigid_body_handle) = engine_state
-                    .physics_state
-                    .collider_set
-                    .get(collider_handle)
-                    .unwrap()
-                    .parent()
-                {
-                    if let Some((ball_index, ball)) = game_state
-                        .physics_balls
-                        .iter()
-                        .enumerate()
-                        .find(|(_, ball)| ball.rigid_body_handle() == rigid_body_handle)
-                    {
-                        // ball.toggle_wireframe(&mut engine_state.scene);
-                        ball.destroy(&mut engine_state.scene, &mut engine_state.physics_state);
-                        game_state.physics_balls.remove(ball_index);
-                    }
-                }
-                if let Some(character) = game_state.character.as_mut() {
-                    character.handle_hit(&mut engine_state.scene, collider_handle);
-                }
+        }
+
+        weapon_events
+    } else {
+        vec![]
+    };
+    for event in weapon_events {
+        if let WeaponEvent::Fired {
+            origin,
+            direction,
+            muzzle_effect,
+        } = event
+        {
+            game_state
+                .gamepads
+                .rumble(0.6, ikari::time::Duration::from_millis(150));
+            if let Some(muzzle_effect) = muzzle_effect {
+                spawn_effect(
+                    game_state,
+                    &mut engine_state.scene,
+                    &muzzle_effect,
+                    TransformBuilder::new()
+                        .position(origin)
+                        .rotation(Quat::from_rotation_arc(Vec3::NEG_Z, direction))
+                        .build(),
+                    None,
+                );
             }
         }
     }
 
+    update_projectiles(engine_state, world_time_seconds as f32);
+    for hit in engine_state.events.drain::<WeaponHit>() {
+        handle_weapon_hit(
+            engine_state,
+            &mut game_state.world_labels,
+            game_state.character.as_mut(),
+            &hit,
+        );
+        if let Some(impact_effect) = &hit.impact_effect {
+            spawn_effect(
+                game_state,
+                &mut engine_state.scene,
+                impact_effect,
+                TransformBuilder::new()
+                    .position(hit.position)
+                    .rotation(Quat::from_rotation_arc(
+                        Vec3::NEG_Z,
+                        hit.normal.normalize_or_zero(),
+                    ))
+                    .build(),
+                Some(&hit),
+            );
+        }
+    }
+    for event in engine_state.events.drain::<ForceFieldEvent>() {
+        // the explosions also hurt what they push around
+        if event.field.is_some() {
+            continue;
+        }
+        if let Some(target) = PhysicsBall::find_by_rigid_body(
+            &engine_state.world,
+            event.rigid_body_handle,
+        )
+        .or_else(|| {
+            DestructibleCrate::find_by_rigid_body(&engine_state.world, event.rigid_body_handle)
+        }) {
+            engine_state.events.send(DamageEvent {
+                target,
+                amount: EXPLOSIVE_ROUND_SPLASH_DAMAGE * event.impulse.length()
+                    / EXPLOSIVE_ROUND_IMPULSE,
+                position: None,
+            });
+        }
+    }
+    game_state
+        .light_flashes
+        .update(&mut engine_state.scene, world_time_seconds as f32);
+    game_state
+        .decals
+        .update(&mut engine_state.scene, world_time_seconds as f32);
+    game_state
+        .effect_library
+        .update(&game_state.asset_loader, renderer);
+    update_pond(
+        game_state,
+        engine_state,
+        &base_renderer,
+        frame_time_seconds as f32,
+    );
+    let gravity = engine_state.physics_state.gravity;
+    game_state.particle_system.update(
+        &mut engine_state.scene,
+        frame_time_seconds as f32,
+        Vec3::new(gravity.x as f32, gravity.y as f32, gravity.z as f32),
+        game_state
+            .player_controller
+            .position(&engine_state.physics_state),
+    );
+
+    game_state.health_system.update(
+        engine_state,
+        &renderer_data.lock().unwrap(),
+        world_time_seconds as f32,
+    );
+    for event in engine_state.events.drain::<HealthEvent>() {
+        if let HealthEvent::Died { entity } = event {
+            log::debug!("{entity:?} died");
+        }
+    }
+
     // step animatons
     let scene = &mut engine_state.scene;
-    if game_state.is_playing_animations {
-        step_animations(scene, frame_time_seconds)
+    let animation_events = if game_state.is_playing_animations {
+        step_animations(scene, animation_time_seconds)
+    } else {
+        vec![]
+    };
+
+    for event in animation_events {
+        if let Some(revolver) = game_state.revolver.as_ref() {
+            if event.animation_index == revolver.animation_index()
+                && event.name == SHOT_EVENT
+                && game_state.gunshot_sound_index.is_some()
+            {
+                let revolver_position = scene
+                    .get_global_transform_for_node(revolver.node_id)
+                    .position();
+                engine_state
+                    .audio_manager
+                    .lock()
+                    .unwrap()
+                    .trigger_cue(GUNSHOT_CUE, Some(revolver_position));
+            }
+        }
     }
 
     if let Some(character) = game_state.character.as_mut() {
         character.update(scene, &mut engine_state.physics_state);
     }
 
+    engine_state.scene_observers.update(&engine_state.scene);
+
     {
         profiling::scope!("Sync UI");
 
         let mut renderer_data_guard = renderer.data.lock().unwrap();
+        let mut is_render_scale_changed = false;
 
         let frame_duration = engine_state.time().last_frame_time();
         {
@@ -1982,13 +2751,79 @@ pub fn update_game_state(
             game_state
                 .ui_overlay
                 .queue_message(Message::FrameCompleted(frame_duration));
+            game_state
+                .ui_overlay
+                .queue_message(Message::RenderScaleChanged(
+                    renderer_data_guard.render_scale,
+                ));
+            let ui_state = game_state.ui_overlay.get_state();
+            let dynamic_resolution = &mut engine_state.dynamic_resolution;
+            dynamic_resolution.is_enabled = ui_state.enable_dynamic_resolution;
+            dynamic_resolution.target_fps = ui_state.dynamic_resolution_target_fps;
+            dynamic_resolution.min_render_scale = ui_state.dynamic_resolution_min_scale;
+            dynamic_resolution.max_render_scale = ui_state.dynamic_resolution_max_scale;
             if let Some(gpu_timing_info) = renderer.process_profiler_frame() {
+                let gpu_frame_time = gpu_timing_info
+                    .iter()
+                    .map(|result| {
+                        ikari::time::Duration::from_secs_f64(
+                            (result.time.end - result.time.start).max(0.0),
+                        )
+                    })
+                    .sum();
+                if let Some(render_scale) = engine_state
+                    .dynamic_resolution
+                    .update(gpu_frame_time, renderer_data_guard.render_scale)
+                {
+                    renderer_data_guard.render_scale = render_scale;
+                    is_render_scale_changed = true;
+                }
+                engine_state.frame_profiler.add_gpu_frame(&gpu_timing_info);
                 game_state
                     .ui_overlay
                     .queue_message(Message::GpuFrameCompleted(gpu_timing_info));
             }
         }
 
+        {
+            profiling::scope!("Frame Profiler");
+
+            let ui_state = game_state.ui_overlay.get_state();
+            let is_showing_frame_profiler = ui_state.is_showing_frame_profiler;
+            let requested_profiler_capture = ui_state.requested_profiler_capture;
+            let frame_profiler = &mut engine_state.frame_profiler;
+            frame_profiler.is_paused = ui_state.is_frame_profiler_paused;
+            if is_showing_frame_profiler && !frame_profiler.is_paused {
+                if let Some(frame) = frame_profiler.latest_frame() {
+                    game_state
+                        .ui_overlay
+                        .queue_message(Message::FrameProfiled(frame.clone()));
+                }
+            }
+            if requested_profiler_capture {
+                let result = frame_profiler
+                    .save_capture_in_dir(std::path::Path::new("."))
+                    .map(|path| path.display().to_string())
+                    .map_err(|err| err.to_string());
+                game_state
+                    .ui_overlay
+                    .queue_message(Message::ProfilerCaptureSaved(result));
+            }
+        }
+
+        {
+            let ui_state = game_state.ui_overlay.get_state();
+            let log_generation = ikari::logging::log_generation();
+            if ui_state.is_showing_log_window && ui_state.log_generation != log_generation {
+                game_state
+                    .ui_overlay
+                    .queue_message(Message::LogEntriesChanged((
+                        log_generation,
+                        ikari::logging::recent_log_entries(),
+                    )));
+            }
+        }
+
         {
             profiling::scope!("Audio");
 
@@ -2020,6 +2855,49 @@ pub fn update_game_state(
             }
         }
 
+        let screen_labels = game_state.world_labels.update(
+            &engine_state.scene,
+            &renderer_data_guard,
+            &engine_state.physics_state,
+            (
+                (window.inner_size().width as f64 / window.scale_factor()) as f32,
+                (window.inner_size().height as f64 / window.scale_factor()) as f32,
+            ),
+            frame_time_seconds as f32,
+        );
+        game_state
+            .ui_overlay
+            .queue_message(Message::WorldLabelsChanged(screen_labels));
+
+        {
+            let hud = &mut game_state.hud;
+            let revolver = game_state.revolver.as_ref();
+            hud.set_visible(game_state.revolver_hud_label, revolver.is_some());
+            hud.set_visible(game_state.revolver_cooldown_hud_bar, revolver.is_some());
+            if let Some(revolver) = revolver {
+                hud.set_progress(
+                    game_state.revolver_cooldown_hud_bar,
+                    revolver.weapon.cooldown_progress(),
+                );
+            }
+            if let Some(minimap_image) = game_state.minimap.render(
+                &engine_state.scene,
+                &engine_state.world,
+                &engine_state.physics_state,
+                game_state
+                    .player_controller
+                    .position(&engine_state.physics_state),
+                game_state.player_controller.view_forward_vector(),
+            ) {
+                hud.set_image(game_state.minimap_hud_image, minimap_image);
+            }
+            if hud.take_changed() {
+                game_state
+                    .ui_overlay
+                    .queue_message(Message::HudChanged(hud.clone()));
+            }
+        }
+
         let camera_position = game_state
             .player_controller
             .position(&engine_state.physics_state);
@@ -2031,16 +2909,127 @@ pub fn update_game_state(
                 camera_view_direction,
             )));
 
+        let looked_at_node_id = picking::pick_collider(
+            &engine_state.physics_state,
+            picking::Ray {
+                origin: camera_position,
+                direction: camera_view_direction.to_vector(),
+            },
+            LOOK_AT_DISTANCE,
+            QueryFilter::from(
+                InteractionGroups::all().with_filter(!COLLISION_GROUP_PLAYER_UNSHOOTABLE),
+            ),
+        )
+        .map(|hit| hit.node_id);
+        let looked_at_ball_node_id = looked_at_node_id.filter(|looked_at_node_id| {
+            engine_state
+                .world
+                .query::<SceneNode>()
+                .any(|(entity, SceneNode(node_id))| {
+                    node_id == looked_at_node_id && engine_state.world.has::<PhysicsBall>(entity)
+                })
+        });
+        if looked_at_ball_node_id != game_state.looked_at_ball_node_id {
+            let outlines = &mut renderer_data_guard.outlines;
+            if let Some(node_id) = game_state.looked_at_ball_node_id {
+                outlines.remove(node_id);
+            }
+            if let Some(node_id) = looked_at_ball_node_id {
+                outlines.set(node_id, LOOKED_AT_BALL_OUTLINE_COLOR);
+            }
+            game_state.looked_at_ball_node_id = looked_at_ball_node_id;
+        }
+        let looked_at_node_name = looked_at_node_id
+            .and_then(|node_id| engine_state.scene.get_node(node_id)?.name.clone());
+        game_state
+            .ui_overlay
+            .queue_message(Message::LookedAtNodeChanged(looked_at_node_name));
+
         let ui_state = game_state.ui_overlay.get_state();
 
         if ui_state.was_exit_button_pressed {
             elwt.exit();
         }
 
+        if !ui_state.requested_asset_spawns.is_empty() {
+            let surface_aspect_ratio = surface_data.surface_config.width as f32
+                / surface_data.surface_config.height as f32;
+            for AssetSpawnRequest {
+                asset,
+                drop_position,
+            } in &ui_state.requested_asset_spawns
+            {
+                // dropped assets land on whatever is under the cursor, or float in front
+                // of the camera if there's nothing there
+                let spawn_position = match drop_position.and_then(|drop_position| {
+                    picking::cursor_ray(
+                        &engine_state.scene,
+                        &renderer_data_guard,
+                        drop_position,
+                        surface_aspect_ratio,
+                    )
+                }) {
+                    Some(ray) => picking::pick_collider(
+                        &engine_state.physics_state,
+                        ray,
+                        ASSET_DROP_DISTANCE,
+                        QueryFilter::from(
+                            InteractionGroups::all()
+                                .with_filter(!COLLISION_GROUP_PLAYER_UNSHOOTABLE),
+                        ),
+                    )
+                    .map(|hit| hit.point)
+                    .unwrap_or_else(|| ray.point_at(LOOK_AT_DISTANCE)),
+                    None => camera_position + camera_view_direction.to_vector() * LOOK_AT_DISTANCE,
+                };
+                let asset_id = game_state
+                    .asset_loader
+                    .load_gltf_scene(SceneAssetLoadParams {
+                        path: asset.path.clone(),
+                        generate_wireframe_meshes: true,
+                        generate_colliders: None,
+                    });
+                game_state.pending_spawned_scenes.push((
+                    asset_id,
+                    spawn_position,
+                    SceneFileAsset {
+                        path: asset.path.relative_path.to_string_lossy().to_string(),
+                        generate_wireframe_meshes: true,
+                    },
+                ));
+            }
+            let handled_count = ui_state.requested_asset_spawns.len();
+            game_state
+                .ui_overlay
+                .queue_message(Message::AssetSpawnsHandled(handled_count));
+        }
+
+        let requested_quality_preset = game_state.ui_overlay.get_state().requested_quality_preset;
+        if let Some(preset) = requested_quality_preset {
+            let graphics = &mut game_state.settings.graphics;
+            graphics.set_preset(preset);
+            graphics.apply(&mut renderer_data_guard);
+            game_state
+                .particle_system
+                .set_particle_limit_scale(graphics.particle_limit_scale);
+            is_render_scale_changed = true;
+            game_state
+                .ui_overlay
+                .queue_message(Message::QualityPresetApplied(*graphics));
+            // the options below are copied from the ui state, which must have the preset's
+            // values before that
+            game_state.ui_overlay.update(window);
+        }
+
+        let ui_state = game_state.ui_overlay.get_state();
+
         renderer_data_guard.bloom_type = ui_state.bloom_type;
         renderer_data_guard.new_bloom_radius = ui_state.new_bloom_radius;
         renderer_data_guard.new_bloom_intensity = ui_state.new_bloom_intensity;
         renderer_data_guard.enable_depth_prepass = ui_state.enable_depth_prepass;
+        renderer_data_guard
+            .path_tracer
+            .set_enabled(ui_state.enable_path_tracer);
         renderer_data_guard.enable_directional_shadow_culling =
             ui_state.enable_directional_shadow_culling;
         renderer_data_guard.enable_soft_shadows = ui_state.enable_soft_shadows;
@@ -2049,21 +3038,104 @@ pub fn update_game_state(
         renderer_data_guard.enable_shadow_debug = ui_state.enable_shadow_debug;
         renderer_data_guard.enable_cascade_debug = ui_state.enable_cascade_debug;
         renderer_data_guard.soft_shadow_grid_dims = ui_state.soft_shadow_grid_dims;
+        renderer_data_guard.shadow_filter.mode = ui_state.shadow_filter_mode;
+        renderer_data_guard.shadow_filter.pcf_kernel_radius = ui_state.pcf_kernel_radius;
+        renderer_data_guard.parallax_max_step_count = ui_state.parallax_max_step_count;
+        renderer_data_guard.taa.set_enabled(ui_state.enable_taa);
         renderer_data_guard.draw_culling_frustum = ui_state.draw_culling_frustum;
         renderer_data_guard.draw_point_light_culling_frusta =
             ui_state.draw_point_light_culling_frusta;
         renderer_data_guard.draw_directional_light_culling_frusta =
             ui_state.draw_directional_light_culling_frusta;
-        renderer.set_skybox_weights([1.0 - ui_state.skybox_weight, ui_state.skybox_weight]);
+        if !ENABLE_DAY_NIGHT_CYCLE {
+            renderer.set_skybox_weights([1.0 - ui_state.skybox_weight, ui_state.skybox_weight]);
+        }
         renderer.set_vsync(ui_state.enable_vsync, surface_data);
 
+        if game_state.is_showing_physics_debug {
+            game_state
+                .physics_debug_draw
+                .draw(&engine_state.physics_state, &mut game_state.debug_draw);
+        }
+        game_state.debug_draw.flush(
+            &renderer.base,
+            &mut renderer_data_guard,
+            &mut engine_state.scene,
+        );
+
         drop(renderer_data_guard);
 
+        if is_render_scale_changed {
+            let unscaled_framebuffer_size = winit::dpi::PhysicalSize::new(
+                surface_data.surface_config.width,
+                surface_data.surface_config.height,
+            );
+            // must call this after changing the render scale
+            renderer.resize_surface(surface_data, unscaled_framebuffer_size);
+        }
+
+        renderer.set_texture_quality(TextureQuality {
+            filtering: ui_state.texture_filtering,
+            resolution_bias: ui_state.texture_resolution_bias,
+        });
         renderer.set_culling_frustum_lock(
             engine_state,
             &surface_data.surface_config,
             ui_state.culling_frustum_lock_mode,
         );
+
+        let is_showing_options_menu = ui_state.is_showing_options_menu;
+        if game_state.was_showing_options_menu && !is_showing_options_menu {
+            save_settings(game_state, engine_state, renderer);
+        }
+        game_state.was_showing_options_menu = is_showing_options_menu;
+    }
+
+    {
+        profiling::scope!("Level Editor");
+
+        let ui_state = game_state.ui_overlay.get_state();
+        let editor = &mut game_state.editor;
+        if ui_state.is_editor_enabled != editor.is_enabled() {
+            editor.set_enabled(&mut engine_state.scene, ui_state.is_editor_enabled);
+        }
+        editor.gizmo_mode = ui_state.gizmo_mode;
+
+        let editor_actions = ui_state.requested_editor_actions.clone();
+        for action in &editor_actions {
+            match action {
+                EditorAction::SelectNode(node_id) => editor.select(Some(*node_id)),
+                EditorAction::EditNode(edit) => editor.edit_selected_node(
+                    &mut engine_state.scene,
+                    &renderer.data.lock().unwrap(),
+                    *edit,
+                ),
+                EditorAction::SaveScene => {
+                    save_editor_scene(editor, &game_state.editor_assets, engine_state, renderer)
+                }
+            }
+        }
+        if !editor_actions.is_empty() {
+            game_state
+                .ui_overlay
+                .queue_message(Message::EditorActionsHandled(editor_actions.len()));
+        }
+
+        game_state.editor.update(&mut engine_state.scene, renderer);
+
+        if game_state.editor.is_enabled() {
+            let renderer_data_guard = renderer.data.lock().unwrap();
+            let panel_state = EditorPanelState {
+                hierarchy: game_state.editor.node_hierarchy(&engine_state.scene),
+                selected_node: game_state
+                    .editor
+                    .selected_node_properties(&engine_state.scene, &renderer_data_guard),
+            };
+            drop(renderer_data_guard);
+            game_state
+                .ui_overlay
+                .queue_message(Message::EditorPanelStateChanged(panel_state));
+        }
     }
 
     game_state.ui_overlay.update(window);
@@ -2088,13 +3160,275 @@ pub fn update_game_state(
 
     let is_showing_options_menu = game_state.ui_overlay.get_state().is_showing_options_menu;
     let is_showing_cursor_marker = game_state.ui_overlay.get_state().is_showing_cursor_marker;
+    let is_showing_content_browser = game_state.ui_overlay.get_state().is_showing_content_browser;
+    let is_editor_enabled = game_state.editor.is_enabled();
+
+    let game_state_stack = &mut engine_state.game_state_stack;
+    // the simulation is paused while editing so the physics doesn't fight the gizmo. Only the
+    // editor being toggled is handled here so the pause of the toggle_time_paused action stays
+    if is_editor_enabled != game_state.was_editor_enabled
+        && game_state_stack.current() != GameStateKind::Menu
+    {
+        let is_paused = game_state_stack.current() == GameStateKind::Paused;
+        if is_editor_enabled && !is_paused {
+            game_state_stack.push(GameStateKind::Paused);
+        } else if !is_editor_enabled && is_paused {
+            game_state_stack.pop();
+        }
+        game_state.was_editor_enabled = is_editor_enabled;
+    }
+
+    let is_in_menu = game_state_stack.current() == GameStateKind::Menu;
+    if is_showing_options_menu && !is_in_menu {
+        game_state_stack.push(GameStateKind::Menu);
+    } else if !is_showing_options_menu && is_in_menu {
+        game_state_stack.pop();
+    }
+    if let Some(transition) = game_state_stack.take_transitions().last() {
+        game_state
+            .ui_overlay
+            .queue_message(Message::GameStateChanged(transition.to));
+    }
+
     game_state.player_controller.update_cursor_grab(
-        !is_showing_options_menu && !is_showing_cursor_marker,
+        !game_state_stack.is_cursor_released()
+            && !is_showing_cursor_marker
+            && !is_showing_content_browser
+            && !is_editor_enabled,
         window,
     );
     game_state
         .player_controller
-        .set_is_controlling_game(!is_showing_options_menu);
+        .set_is_controlling_game(game_state_stack.input_context() == InputContext::Gameplay);
+
+    game_state.input_map.set_context(if is_editor_enabled {
+        InputContext::Editor
+    } else {
+        game_state_stack.input_context()
+    });
+    game_state.input_map.end_frame();
+}
+
+/// the bindings used until the player has some in their settings file
+fn default_input_bindings() -> InputBindings {
+    let key = InputBinding::key;
+    let common_bindings = BindingSet::default()
+        .action(
+            "toggle_popup_menu",
+            vec![
+                key("Tab"),
+                InputBinding::GamepadButton(GamepadButton::Start),
+            ],
+        )
+        .action("decrease_render_scale", vec![key("z")])
+        .action("increase_render_scale", vec![key("x")])
+        .action("decrease_exposure", vec![key("r")])
+        .action("increase_exposure", vec![key("t")])
+        .action("decrease_bloom_threshold", vec![key("y")])
+        .action("increase_bloom_threshold", vec![key("u")])
+        .action("toggle_animations", vec![key("p")])
+        .action("toggle_shadows", vec![key("m")])
+        .action("cycle_bloom_type", vec![key("b")])
+        .action("toggle_wireframe", vec![key("f")])
+        .action("toggle_bounding_spheres", vec![key("j")])
+        .action("print_perf_report", vec![key("o")])
+        .action("toggle_level_editor", vec![key("l")])
+        .action("toggle_collision_boxes", vec![key("c")])
+        .action("toggle_physics_debug", vec![key("i")])
+        .action("toggle_time_paused", vec![key("k")])
+        .action("step_frame", vec![key("n")])
+        .action("cycle_time_scale", vec![key("h")])
+        .action("quick_save", vec![key("F5")])
+        .action("quick_load", vec![key("F9")])
+        .action("reload_level", vec![key("F6")])
+        .action("exit", vec![key("Escape")]);
+    InputBindings::default()
+        .context(
+            InputContext::Gameplay,
+            PlayerController::default_bindings()
+                .action(
+                    "fire",
+                    vec![InputBinding::GamepadButton(GamepadButton::RightTrigger)],
+                )
+                .action(
+                    "reload",
+                    vec![key("g"), InputBinding::GamepadButton(GamepadButton::West)],
+                )
+                .action(
+                    "cycle_camera",
+                    vec![key("v"), InputBinding::GamepadButton(GamepadButton::North)],
+                )
+                .merge(common_bindings.clone()),
+        )
+        .context(InputContext::Menu, common_bindings.clone())
+        .context(InputContext::Editor, common_bindings)
+}
+
+/// Splashes and ripples for the physics balls that cross the pond's surface
+fn update_pond(
+    game_state: &mut GameState,
+    engine_state: &mut EngineState,
+    base_renderer: &BaseRenderer,
+    frame_time_seconds: f32,
+) {
+    for (_, PhysicsBody(rigid_body_handle)) in engine_state.world.query::<PhysicsBody>() {
+        if !game_state.pond.is_tracking_rigid_body(*rigid_body_handle) {
+            game_state
+                .pond
+                .track_rigid_body(&engine_state.physics_state, *rigid_body_handle);
+        }
+    }
+
+    let splash_events = game_state
+        .pond
+        .update(&engine_state.physics_state, frame_time_seconds);
+    for splash_event in splash_events {
+        if let Some(effect) = game_state.effect_library.get(WATER_SPLASH_EFFECT) {
+            game_state.particle_system.spawn_effect(
+                effect,
+                TransformBuilder::new()
+                    .position(splash_event.position)
+                    .rotation(Quat::from_rotation_arc(Vec3::NEG_Z, Vec3::Y))
+                    .build(),
+            );
+        }
+        engine_state
+            .audio_manager
+            .lock()
+            .unwrap()
+            .trigger_cue(WATER_SPLASH_CUE, Some(splash_event.position));
+    }
+
+    game_state
+        .pond_ripples
+        .add_drops(game_state.pond.take_ripple_drops());
+    game_state.pond_ripples.update(
+        base_renderer,
+        ikari::time::Duration::from_secs_f32(frame_time_seconds),
+    );
+    let material = game_state
+        .pond_ripples
+        .surface_material(&game_state.pond.surface);
+    if let Some((visual, material)) = engine_state
+        .scene
+        .get_node_mut(game_state.pond_node_id)
+        .and_then(|node| node.visual.as_mut())
+        .zip(material)
+    {
+        visual.material = material;
+    }
+}
+
+/// Spawns the particles and the light flashes of an effect of the EffectLibrary, along with its
+/// decals when it's spawned where a shot hit
+fn spawn_effect(
+    game_state: &mut GameState,
+    scene: &mut Scene,
+    name: &str,
+    transform: Transform,
+    hit: Option<&WeaponHit>,
+) {
+    let Some(effect) = game_state.effect_library.get(name) else {
+        return;
+    };
+    game_state.particle_system.spawn_effect(effect, transform);
+    game_state
+        .light_flashes
+        .spawn_effect(scene, effect, transform);
+    if let Some(hit) = hit {
+        for decal_params in game_state.effect_library.decals(name) {
+            game_state
+                .decals
+                .spawn_at_hit(scene, name, *decal_params, hit);
+        }
+    }
+}
+
+fn handle_weapon_hit(
+    engine_state: &mut EngineState,
+    world_labels: &mut WorldLabels,
+    character: Option<&mut Character>,
+    hit: &WeaponHit,
+) {
+    if let Some(hit_node_id) = hit.node_id {
+        let node_position = engine_state
+            .scene
+            .get_global_transform_for_node(hit_node_id)
+            .position();
+        world_labels.add(WorldLabel {
+            offset: hit.position - node_position,
+            color: iced::Color::from_rgb(1.0, 0.85, 0.2),
+            fade_when_occluded: false,
+            velocity: Vec3::new(0.0, 0.75, 0.0),
+            lifetime_seconds: Some(1.0),
+            ..WorldLabel::new(hit_node_id, "Hit!")
+        });
+    }
+
+    if let Some(rigid_body_handle) = engine_state
+        .physics_state
+        .collider_set
+        .get(hit.collider_handle)
+        .and_then(|collider| collider.parent())
+    {
+        if let Some(target) =
+            PhysicsBall::find_by_rigid_body(&engine_state.world, rigid_body_handle).or_else(|| {
+                DestructibleCrate::find_by_rigid_body(&engine_state.world, rigid_body_handle)
+            })
+        {
+            engine_state.events.send(DamageEvent {
+                target,
+                amount: hit.damage,
+                position: Some(hit.position),
+            });
+        }
+    }
+    if let Some(character) = character {
+        character.handle_hit(&mut engine_state.scene, hit.collider_handle);
+    }
+
+    // the revolver rounds are explosive, they scatter the physics balls around the hit
+    explode(
+        engine_state,
+        &Explosion {
+            center: hit.position,
+            radius: EXPLOSIVE_ROUND_RADIUS,
+            impulse: EXPLOSIVE_ROUND_IMPULSE,
+            falloff: ForceFalloff::Linear,
+        },
+    );
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn save_editor_scene(
+    editor: &mut Editor,
+    editor_assets: &HashMap<GameNodeId, SceneFileAsset>,
+    engine_state: &mut EngineState,
+    renderer: &Renderer,
+) {
+    // the gizmo would be saved along with the level otherwise
+    editor.remove_helper_nodes(&mut engine_state.scene);
+    let scene_file = SceneFile::capture(
+        &engine_state.scene,
+        &engine_state.physics_state,
+        renderer,
+        editor_assets,
+    );
+    let path = GAME_PATH_MAKER.make(EDITOR_SCENE_PATH);
+    match scene_file.save(&path) {
+        Ok(()) => log::info!("Saved the scene to {}", path.relative_path.display()),
+        Err(err) => log::error!("Error saving the scene: {err:?}"),
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn save_editor_scene(
+    _editor: &mut Editor,
+    _editor_assets: &HashMap<GameNodeId, SceneFileAsset>,
+    _engine_state: &mut EngineState,
+    _renderer: &Renderer,
+) {
+    log::error!("Saving scenes isn't supported on the web");
 }
 
 fn add_static_box(
@@ -2112,7 +3446,7 @@ fn add_static_box(
         if let Some(visual) = node.visual.as_ref() {
             let transform: Transform = scene.get_global_transform_for_node(node_id);
             let transform_decomposed = transform.decompose();
-            let bounding_box = renderer_data.binded_meshes[visual.mesh_index].bounding_box;
+            let bounding_box = renderer_data.binded_meshes[visual.mesh.index()].bounding_box;
             let base_scale = (bounding_box.max - bounding_box.min) / 2.0;
             let base_position = (bounding_box.max + bounding_box.min) / 2.0;
             let scale = Vec3::new(
@@ -2157,3 +3491,104 @@ fn add_static_box(
         }
     }
 }
+
+fn test_level_load_params() -> SceneAssetLoadParams {
+    SceneAssetLoadParams {
+        path: GAME_PATH_MAKER.make("src/models/gltf/TestLevel/test_level.glb"),
+        generate_wireframe_meshes: true,
+        generate_colliders: None,
+    }
+}
+
+/// Merges the maze into the scene and gives it static colliders, returns what
+/// remove_test_level needs to take it out again
+fn add_test_level(
+    engine_state: &mut EngineState,
+    renderer_data: &mut RendererData,
+    loaded_scene: LoadedScene,
+) -> (BindedAssetId, Vec<GameNodeId>) {
+    let skip_nodes = engine_state.scene.node_count();
+    let asset_id =
+        engine_state
+            .scene
+            .merge_scene(renderer_data, loaded_scene.scene, loaded_scene.binded_data);
+
+    let test_level_node_ids: Vec<_> = engine_state
+        .scene
+        .nodes()
+        .skip(skip_nodes)
+        .map(|node| node.id())
+        .collect();
+    for node_id in &test_level_node_ids {
+        let transform = &mut engine_state.scene.get_node_mut(*node_id).unwrap().transform;
+        transform.set_position(transform.position() + Vec3::new(0.0, 25.0, 0.0));
+        add_static_box(
+            &mut engine_state.physics_state,
+            &engine_state.scene,
+            renderer_data,
+            *node_id,
+        );
+    }
+    (asset_id, test_level_node_ids)
+}
+
+fn remove_test_level(
+    engine_state: &mut EngineState,
+    renderer_data: &mut RendererData,
+    asset_id: BindedAssetId,
+    node_ids: &[GameNodeId],
+) -> Result<()> {
+    for node_id in node_ids {
+        engine_state.physics_state.remove_static_boxes(*node_id);
+        engine_state.scene.remove_node(*node_id);
+    }
+    engine_state.scene.unload_asset(renderer_data, asset_id)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn quick_save(engine_state: &EngineState, game_state: &GameState) -> Result<()> {
+    let Some(directory) = SaveSlots::default_directory(SETTINGS_APP_NAME) else {
+        anyhow::bail!("There's no data directory to save the game in");
+    };
+
+    let mut save_game = SaveGame::new("Quick save");
+    let mut player = SavedPlayer::from_player_controller(
+        &game_state.player_controller,
+        &engine_state.physics_state,
+    );
+    player.ammo = game_state
+        .revolver
+        .as_ref()
+        .map(|revolver| SavedAmmo::from_weapon(&revolver.weapon));
+    save_game.player = Some(player);
+    save_game.capture_objects(engine_state);
+    save_game.time_of_day_hours = Some(game_state.time_of_day.hours());
+
+    SaveSlots::new(directory).save(QUICK_SAVE_SLOT, &save_game)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn quick_load(engine_state: &mut EngineState, game_state: &mut GameState) -> Result<()> {
+    let Some(directory) = SaveSlots::default_directory(SETTINGS_APP_NAME) else {
+        anyhow::bail!("There's no data directory to load the game from");
+    };
+    let save_game = SaveSlots::new(directory).load(QUICK_SAVE_SLOT)?;
+
+    if let Some(player) = &save_game.player {
+        player.apply_to_player_controller(
+            &mut game_state.player_controller,
+            &mut engine_state.physics_state,
+        );
+        if let (Some(ammo), Some(revolver)) = (player.ammo, game_state.revolver.as_mut()) {
+            ammo.apply(&mut revolver.weapon);
+        }
+    }
+    for id in save_game.restore_objects(engine_state) {
+        // the crates don't come back once broken
+        log::warn!("{id} was saved but is gone, it won't be restored");
+    }
+    if let Some(hours) = save_game.time_of_day_hours {
+        game_state.time_of_day.set_hours(hours);
+    }
+    Ok(())
+}
@@ -1,33 +1,57 @@
 use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
+use glam::Vec3;
 use ikari::asset_loader::{AssetBinder, AssetId, AssetLoader};
+use ikari::asset_registry::MaterialHandle;
+use ikari::camera_system::CameraSystem;
+use ikari::debug_draw::DebugDraw;
+use ikari::decals::Decals;
+use ikari::editor::Editor;
+use ikari::effects::EffectLibrary;
+use ikari::engine_state::EngineState;
+use ikari::gamepad::Gamepads;
+use ikari::health::HealthSystem;
+use ikari::hud::{Hud, HudWidgetId};
+use ikari::input::InputMap;
+use ikari::light_animation::LightFlashes;
+use ikari::minimap::Minimap;
+use ikari::music_player::MusicPlayer;
+use ikari::particles::ParticleSystem;
 use ikari::physics::rapier3d_f64::prelude::*;
+use ikari::physics_debug::PhysicsDebugDraw;
 use ikari::player_controller::PlayerController;
+use ikari::renderer::{BindedAssetId, Renderer};
 use ikari::scene::GameNodeId;
+use ikari::scene_file::SceneFileAsset;
+use ikari::scene_transition::SceneTransition;
+#[cfg(feature = "scripting")]
+use ikari::scripting::ScriptHost;
+use ikari::settings::Settings;
+use ikari::time_of_day::TimeOfDay;
 use ikari::ui::IkariUiContainer;
 use ikari::wasm_not_sync::WasmNotArc;
+use ikari::water::{WaterInteractionSystem, WaterRippleSimulation};
+use ikari::world_labels::WorldLabels;
 
 use crate::ui_overlay::UiOverlay;
-use crate::{
-    ball::BallComponent, character::Character, physics_ball::PhysicsBall, revolver::Revolver,
-};
+use crate::{ball::BallComponent, character::Character, revolver::Revolver};
 
 pub struct GameState {
-    pub state_update_time_accumulator: f64,
     pub is_playing_animations: bool,
 
     pub bgm_sound_index: Option<usize>,
     pub gunshot_sound_index: Option<usize>,
+    pub music_player: MusicPlayer,
     // pub gunshot_sound_data: SoundData,
     pub point_light_node_ids: Vec<GameNodeId>,
 
     // store the previous state and next state and interpolate between them
-    pub next_balls: Vec<BallComponent>,
-    pub prev_balls: Vec<BallComponent>,
-    pub actual_balls: Vec<BallComponent>,
+    pub balls: Vec<BallComponent>,
     pub ball_node_ids: Vec<GameNodeId>,
-    pub ball_pbr_mesh_index: usize,
+    pub ball_pbr_mesh_index: MaterialHandle,
 
     pub ball_spawner_acc: f64,
 
@@ -38,21 +62,67 @@ pub struct GameState {
     pub bouncing_ball_node_id: GameNodeId,
     pub bouncing_ball_body_handle: RigidBodyHandle,
 
-    pub physics_balls: Vec<PhysicsBall>,
-
     pub player_controller: PlayerController,
+    pub camera_system: CameraSystem,
+    pub input_map: InputMap,
+    pub gamepads: Gamepads,
     pub character: Option<Character>,
 
     pub asset_loader: Arc<AssetLoader>,
     pub asset_binder: WasmNotArc<AssetBinder>,
+    /// loads the test level behind a loading screen, at startup and when it's reloaded
+    pub scene_transition: SceneTransition,
+    /// the binded asset and the nodes of the test level, once it's loaded
+    pub test_level: Option<(BindedAssetId, Vec<GameNodeId>)>,
 
     pub ui_overlay: IkariUiContainer<UiOverlay>,
+    pub hud: Hud,
+    pub world_labels: WorldLabels,
+    pub health_system: HealthSystem,
+    pub time_of_day: TimeOfDay,
+    pub settings: Settings,
+    /// None if the platform has no config directory
+    #[cfg(not(target_arch = "wasm32"))]
+    pub settings_path: Option<PathBuf>,
+    /// the settings are saved when the options menu is closed
+    pub was_showing_options_menu: bool,
+    /// the editor pauses the game while it's enabled
+    pub was_editor_enabled: bool,
+    pub revolver_hud_label: HudWidgetId,
+    pub revolver_cooldown_hud_bar: HudWidgetId,
+    pub minimap: Minimap,
+    pub minimap_hud_image: HudWidgetId,
+    /// outlined in RendererData::outlines
+    pub looked_at_ball_node_id: Option<GameNodeId>,
+    pub decals: Decals,
+    pub light_flashes: LightFlashes,
+    pub effect_library: EffectLibrary,
+    pub particle_system: ParticleSystem,
+    pub pond: WaterInteractionSystem,
+    pub pond_ripples: WaterRippleSimulation,
+    pub pond_node_id: GameNodeId,
+    pub debug_draw: DebugDraw,
+    pub physics_debug_draw: PhysicsDebugDraw,
+    pub is_showing_physics_debug: bool,
 
     pub asset_id_map: Arc<Mutex<HashMap<String, AssetId>>>,
+    /// scenes requested from the content browser, merged at the given position once loaded
+    pub pending_spawned_scenes: Vec<(AssetId, Vec3, SceneFileAsset)>,
+
+    pub editor: Editor,
+    /// the nodes spawned from the content browser, saved by the editor as references to their file
+    pub editor_assets: HashMap<GameNodeId, SceneFileAsset>,
+
+    #[cfg(feature = "scripting")]
+    pub script_host: ScriptHost,
 }
 
 impl ikari::gameloop::GameState<UiOverlay> for GameState {
     fn get_ui_container(&mut self) -> &mut IkariUiContainer<UiOverlay> {
         &mut self.ui_overlay
     }
+
+    fn on_device_recreated(&mut self, _renderer: &mut Renderer, _engine_state: &mut EngineState) {
+        self.debug_draw.on_device_recreated();
+    }
 }
@@ -1,5 +1,6 @@
 mod ball;
 mod character;
+mod destructible_crate;
 mod game;
 mod game_state;
 mod physics_ball;
@@ -8,6 +9,7 @@ mod ui_overlay;
 
 use std::sync::Arc;
 
+use crate::game::fixed_update_game_state;
 use crate::game::handle_window_resize;
 use crate::game::init_game_state;
 use crate::game::process_device_input;
@@ -17,6 +19,7 @@ use crate::game::update_game_state;
 use ikari::engine_state::EngineState;
 use ikari::renderer::BaseRenderer;
 use ikari::renderer::Renderer;
+use ikari::renderer::RendererConfig;
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
@@ -60,9 +63,10 @@ async fn start() {
                 .unwrap()
                 .dyn_into::<web_sys::HtmlElement>()
                 .unwrap();
+            // css pixels are logical pixels, winit applies the device pixel ratio
             let _resized_immediately = window.request_inner_size(winit::dpi::LogicalSize::new(
-                (canvas_container.offset_width() as f64 * window.scale_factor()) as u32,
-                (canvas_container.offset_height() as f64 * window.scale_factor()) as u32,
+                canvas_container.offset_width() as f64,
+                canvas_container.offset_height() as f64,
             ));
 
             canvas_container
@@ -79,7 +83,14 @@ async fn start() {
             } else {
                 wgpu::Backends::PRIMARY
             };
-            BaseRenderer::with_window(backends, Some(DXC_PATH.into()), window.clone()).await?
+            let config = RendererConfig {
+                backends,
+                dxc_path: Some(DXC_PATH.into()),
+                retain_cpu_data: true,
+                ibl_cache_folder: Some("ibl_cache".into()),
+                ..Default::default()
+            };
+            BaseRenderer::with_window(&config, window.clone()).await?
         };
 
         log::debug!("base render: {:?}", application_start_time.elapsed());
@@ -111,6 +122,9 @@ async fn start() {
             engine_state,
             renderer,
             surface_data,
+            |game_context| {
+                fixed_update_game_state(game_context);
+            },
             |game_context| {
                 update_game_state(game_context);
             },
@@ -153,16 +167,28 @@ fn main() {
         std::env::set_var("RUST_BACKTRACE", "1");
     }
 
-    if env_var_is_defined("RUST_LOG") {
-        env_logger::init();
+    let logger = if env_var_is_defined("RUST_LOG") {
+        env_logger::Builder::from_default_env().build()
     } else {
         env_logger::builder()
             .filter(Some(env!("CARGO_PKG_NAME")), log::LevelFilter::Info)
             .filter(Some(env!("CARGO_BIN_NAME")), log::LevelFilter::Info)
             .filter(Some("ikari"), log::LevelFilter::Info)
             .filter(Some("wgpu"), log::LevelFilter::Warn)
-            .init();
-    }
+            .build()
+    };
+    let max_log_level = logger.filter();
+    ikari::logging::Logger::init(
+        Box::new(logger),
+        max_log_level,
+        ikari::logging::LoggerConfig {
+            file_path: std::env::var("IKARI_LOG_FILE").ok().map(Into::into),
+            ..Default::default()
+        },
+    )
+    .expect("Failed to initialize logger");
+
+    ikari::crash_handler::install_panic_hook();
 
     #[cfg(feature = "tracy-n-alloc")]
     {
@@ -173,6 +199,10 @@ fn main() {
     }
     #[cfg(feature = "tracy")]
     profiling::tracy_client::Client::start();
+    #[cfg(feature = "frame-profiler")]
+    if let Err(err) = ikari::frame_profiler::install_cpu_scope_collector() {
+        log::warn!("Failed to install the frame profiler's cpu scope collector: {err}");
+    }
 
     ikari::block_on(start());
 }
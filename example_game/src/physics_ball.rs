@@ -1,6 +1,9 @@
 use glam::f32::Vec3;
+use ikari::ecs::{Entity, PhysicsBody, SceneNode, Tags, World};
+use ikari::engine_state::EngineState;
+use ikari::health::{Health, HitReaction};
 use ikari::physics::PhysicsState;
-use ikari::scene::{GameNodeDescBuilder, GameNodeId, GameNodeVisual, Scene};
+use ikari::scene::{GameNodeDescBuilder, GameNodeVisual, Scene};
 
 use ikari::physics::rapier3d_f64::prelude::*;
 use ikari::transform::TransformBuilder;
@@ -8,21 +11,23 @@ use ikari::transform::TransformBuilder;
 use crate::game::{ARENA_SIDE_LENGTH, COLLISION_GROUP_PLAYER_UNSHOOTABLE};
 
 const RESTITUTION: f64 = 0.1;
+/// takes two revolver shots
+const HEALTH: f32 = 2.0;
 
-#[derive(Clone, Debug)]
-pub struct PhysicsBall {
-    node_id: GameNodeId,
-    rigid_body_handle: RigidBodyHandle,
-}
+/// Marker component of the balls that fall from the sky in the arena,
+/// the entity also has a SceneNode, a PhysicsBody, Health and a HitReaction
+#[derive(Copy, Clone, Debug)]
+pub struct PhysicsBall;
 
 impl PhysicsBall {
-    pub fn new(
+    pub fn spawn(
+        world: &mut World,
         scene: &mut Scene,
         physics_state: &mut PhysicsState,
         mesh: GameNodeVisual,
         position: Vec3,
         radius: f32,
-    ) -> Self {
+    ) -> Entity {
         let transform = TransformBuilder::new()
             .position(Vec3::new(position.x, position.y, position.z))
             .scale(Vec3::new(radius, radius, radius))
@@ -58,51 +63,70 @@ impl PhysicsBall {
             rigid_body_handle,
             &mut physics_state.rigid_body_set,
         );
+        physics_state.add_rigid_body_component(node.id(), rigid_body_handle);
 
-        Self {
-            node_id: node.id(),
-            rigid_body_handle,
-        }
+        let entity = world.spawn();
+        world.insert(entity, PhysicsBall);
+        world.insert(entity, SceneNode(node.id()));
+        world.insert(entity, PhysicsBody(rigid_body_handle));
+        world.insert(entity, Health::new(HEALTH));
+        world.insert(entity, HitReaction::default());
+        world.insert(entity, Tags::new(&["ball"]));
+        entity
     }
 
-    pub fn new_random(
+    pub fn spawn_random(
+        world: &mut World,
         scene: &mut Scene,
         physics_state: &mut PhysicsState,
         mesh: GameNodeVisual,
-    ) -> Self {
+    ) -> Entity {
         let radius = 0.2 + (rand::random::<f32>() * 0.4);
         let position = Vec3::new(
             ARENA_SIDE_LENGTH * (rand::random::<f32>() * 2.0 - 1.0) / 4.0,
             radius * 2.0 + rand::random::<f32>() * 15.0 + 5.0,
             ARENA_SIDE_LENGTH * (rand::random::<f32>() * 2.0 - 1.0) / 4.0,
         );
-        Self::new(scene, physics_state, mesh, position, radius)
+        Self::spawn(world, scene, physics_state, mesh, position, radius)
     }
 
-    pub fn update(&self, scene: &mut Scene, physics_state: &mut PhysicsState) {
-        if let Some(node) = scene.get_node_mut(self.node_id) {
-            let rigid_body = &mut physics_state.rigid_body_set[self.rigid_body_handle];
-            node.transform.apply_isometry(*rigid_body.position());
-            if node.transform.decompose().position.y < -1.0 {
-                self.destroy(scene, physics_state);
-            }
-        }
+    /// returns the physics ball entity that owns this rigid body, if any
+    pub fn find_by_rigid_body(world: &World, rigid_body_handle: RigidBodyHandle) -> Option<Entity> {
+        world
+            .query::<PhysicsBody>()
+            .find(|(entity, PhysicsBody(handle))| {
+                *handle == rigid_body_handle && world.has::<PhysicsBall>(*entity)
+            })
+            .map(|(entity, _)| entity)
     }
 
-    pub fn destroy(&self, scene: &mut Scene, physics_state: &mut PhysicsState) {
-        scene.remove_node(self.node_id);
-        physics_state.remove_rigid_body(self.rigid_body_handle);
-    }
-
-    pub fn _toggle_wireframe(&self, scene: &mut Scene) {
-        if let Some(node) = scene.get_node_mut(self.node_id) {
+    pub fn _toggle_wireframe(world: &World, scene: &mut Scene, entity: Entity) {
+        let Some(SceneNode(node_id)) = world.get::<SceneNode>(entity) else {
+            return;
+        };
+        if let Some(node) = scene.get_node_mut(*node_id) {
             if let Some(mesh) = node.visual.as_mut() {
                 mesh.wireframe = !mesh.wireframe;
             }
         }
     }
+}
 
-    pub fn rigid_body_handle(&self) -> RigidBodyHandle {
-        self.rigid_body_handle
+/// System that despawns the balls that fell off the arena.
+/// The node's transform is synced by the rigid body component
+pub fn despawn_fallen_physics_balls(engine_state: &mut EngineState) {
+    for entity in engine_state.world.entities_with::<PhysicsBall>() {
+        let Some(PhysicsBody(rigid_body_handle)) = engine_state.world.get::<PhysicsBody>(entity)
+        else {
+            continue;
+        };
+        let has_fallen = engine_state
+            .physics_state
+            .rigid_body_set
+            .get(*rigid_body_handle)
+            .map_or(true, |rigid_body| rigid_body.translation().y < -1.0);
+        if has_fallen {
+            engine_state.despawn_entity(entity);
+        }
     }
 }
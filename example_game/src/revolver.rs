@@ -1,23 +1,33 @@
 use glam::f32::{Quat, Vec3};
 use ikari::{
     math::{deg_to_rad, lerp},
+    physics::rapier3d_f64::prelude::*,
     player_controller::ControlledViewDirection,
     scene::{GameNodeDescBuilder, GameNodeId, Scene},
-    time::Instant,
     transform::Transform,
+    weapon::{ShotKind, Weapon, WeaponDefinition},
 };
 
+use crate::game::{ARENA_SIDE_LENGTH, COLLISION_GROUP_PLAYER_UNSHOOTABLE};
+
 // (0, 1], higher means it syncs with the camera more quickly
 const CAMERA_FOLLOW_LERP_FACTOR: f32 = 0.8;
 // (0, 1], higher means it sways for a shorter time
 const WEAPON_SWAY_RESET_LERP_FACTOR: f32 = 0.3;
 const MAX_SWAY_DEG: f32 = 3.0;
+const MAGAZINE_SIZE: u32 = 6;
+const RELOAD_SECONDS: f32 = 1.5;
+/// defined in WEAPON_EFFECTS_PATH
+const MUZZLE_FLASH_EFFECT: &str = "revolver_muzzle_flash";
+/// defined in WEAPON_EFFECTS_PATH, its decals are the bullet holes
+pub const IMPACT_EFFECT: &str = "revolver_impact";
+/// animation event sent at the start of the firing animation
+pub const SHOT_EVENT: &str = "shot";
 
 #[derive(Debug)]
 pub struct Revolver {
     animation_index: usize,
-    cooldown: f32,
-    last_fired_instant: Option<Instant>,
+    pub weapon: Weapon,
 
     pub node_id: GameNodeId,
     hand_node_id: GameNodeId,
@@ -51,13 +61,29 @@ impl Revolver {
             model_node.parent_id = Some(node_id);
         }
 
-        // let cooldown = scene.animations[animation_index].length_seconds;
-        let cooldown = scene.animations[animation_index].length_seconds + 0.1;
+        scene.animations[animation_index].add_event(0.0, SHOT_EVENT);
+
+        let weapon = Weapon::new(
+            WeaponDefinition {
+                shot_kind: ShotKind::Hitscan {
+                    max_distance: ARENA_SIDE_LENGTH * 10.0,
+                },
+                fire_interval_seconds: scene.animations[animation_index].length_seconds + 0.1,
+                magazine_size: Some(MAGAZINE_SIZE),
+                reload_seconds: RELOAD_SECONDS,
+                fire_animation: Some(animation_index),
+                collision_groups: InteractionGroups::all()
+                    .with_filter(!COLLISION_GROUP_PLAYER_UNSHOOTABLE),
+                muzzle_effect: Some(MUZZLE_FLASH_EFFECT.to_string()),
+                impact_effect: Some(IMPACT_EFFECT.to_string()),
+                ..Default::default()
+            },
+            None,
+        );
 
         Self {
             animation_index,
-            cooldown,
-            last_fired_instant: None,
+            weapon,
 
             node_id,
             hand_node_id,
@@ -113,17 +139,7 @@ impl Revolver {
         }
     }
 
-    pub fn fire(&mut self, scene: &mut Scene) -> bool {
-        if let Some(last_fired_instant) = self.last_fired_instant {
-            if last_fired_instant.elapsed().as_secs_f32() < self.cooldown {
-                return false;
-            }
-        }
-        self.last_fired_instant = Some(Instant::now());
-        scene.animations[self.animation_index].state.is_playing = true;
-        scene.animations[self.animation_index]
-            .state
-            .current_time_seconds = 0.0;
-        true
+    pub fn animation_index(&self) -> usize {
+        self.animation_index
     }
 }
@@ -2,20 +2,31 @@ use std::collections::hash_map::Entry;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
 
-use glam::Vec3;
+use glam::{EulerRot, Vec2, Vec3, Vec4};
 use iced::alignment::Horizontal;
 use iced::Font;
 
 use iced::widget::{
-    canvas, checkbox, container, radio, scrollable, slider, text, Button, Column, Container, Row,
-    Text,
+    canvas, checkbox, container, mouse_area, radio, scrollable, slider, text, Button, Column,
+    Container, Image, Row, Text,
 };
 use iced::Length;
 use iced::{mouse, Background, Command, Element, Rectangle, Theme};
 use iced_aw::{floating_element, Modal};
 use iced_winit::runtime;
+use ikari::editor::{EditorNodeEntry, GizmoMode, NodeEdit, NodeProperties};
+use ikari::file_manager::AssetEntry;
+use ikari::file_manager::AssetKind;
+use ikari::file_manager::FileManager;
 use ikari::file_manager::GameFilePath;
+use ikari::frame_profiler::cpu_scopes_are_collected;
+use ikari::frame_profiler::ProfiledFrame;
+use ikari::game_state_stack::GameStateKind;
+use ikari::hud::Hud;
+use ikari::logging::{LogCategory, LogEntry};
 use ikari::math::rad_to_deg;
 use ikari::player_controller::ControlledViewDirection;
 use ikari::profile_dump::can_generate_profile_dump;
@@ -23,14 +34,22 @@ use ikari::profile_dump::generate_profile_dump;
 use ikari::profile_dump::PendingPerfDump;
 use ikari::renderer::BloomType;
 use ikari::renderer::CullingFrustumLockMode;
+use ikari::renderer::ShadowFilter;
+use ikari::renderer::ShadowFilterMode;
 use ikari::renderer::MIN_SHADOW_MAP_BIAS;
+use ikari::sampler_cache::TextureFiltering;
+use ikari::scene::{GameNodeId, Material};
+use ikari::scene_transition::LoadingScreen;
+use ikari::settings::{AntiAliasing, GraphicsQualityPreset, GraphicsSettings};
 use ikari::time::Instant;
+use ikari::world_labels::ScreenLabels;
 use plotters::prelude::*;
 use plotters::style::RED;
 use plotters_iced::{Chart, ChartWidget, DrawingBackend};
 
 use ikari::time::Duration;
 
+use crate::game::GAME_PATH_MAKER;
 use crate::game::INITIAL_BLOOM_TYPE;
 use crate::game::INITIAL_ENABLE_CASCADE_DEBUG;
 use crate::game::INITIAL_ENABLE_CULLING_FRUSTUM_DEBUG;
@@ -45,10 +64,13 @@ use crate::game::INITIAL_IS_SHOWING_CAMERA_POSE;
 use crate::game::INITIAL_IS_SHOWING_CURSOR_MARKER;
 use crate::game::INITIAL_NEW_BLOOM_INTENSITY;
 use crate::game::INITIAL_NEW_BLOOM_RADIUS;
+use crate::game::INITIAL_PARALLAX_MAX_STEP_COUNT;
+use crate::game::INITIAL_RENDER_SCALE;
 use crate::game::INITIAL_SHADOW_BIAS;
 use crate::game::INITIAL_SKYBOX_WEIGHT;
 use crate::game::INITIAL_SOFT_SHADOW_FACTOR;
 use crate::game::INITIAL_SOFT_SHADOW_GRID_DIMS;
+use crate::game::INITIAL_TEXTURE_FILTERING;
 
 pub const DEFAULT_FONT_BYTES: &[u8] = include_bytes!("./fonts/Lato-Regular.ttf");
 pub const DEFAULT_FONT_NAME: &str = "Lato";
@@ -57,6 +79,13 @@ pub const KOOKY_FONT_BYTES: &[u8] = include_bytes!("./fonts/Pacifico-Regular.ttf
 pub const KOOKY_FONT_NAME: &str = "Pacifico";
 
 const FRAME_TIME_HISTORY_SIZE: usize = 720;
+
+/// the content browser lists the assets found under this folder
+const CONTENT_BROWSER_ROOT: &str = "src";
+/// where `clikari render_thumbnails` should write the model thumbnails, they're named after
+/// the model files e.g. src/thumbnails/duck.png for src/models/gltf/Duck/duck.glb
+const MODEL_THUMBNAILS_FOLDER: &str = "src/thumbnails";
+const CONTENT_BROWSER_THUMBNAIL_SIZE: u32 = 48;
 const FRAME_TIMES_MOVING_AVERAGE_ALPHA: f64 = 0.01;
 pub(crate) const THEME: iced::Theme = iced::Theme::Dark;
 
@@ -67,25 +96,57 @@ pub struct AudioSoundStats {
     pub buffered_to_pos_seconds: f32,
 }
 
+/// sent by the game every frame while the level editor is enabled
+#[derive(Debug, Clone, Default)]
+pub struct EditorPanelState {
+    pub hierarchy: Vec<EditorNodeEntry>,
+    pub selected_node: Option<NodeProperties>,
+}
+
+#[derive(Debug, Clone)]
+pub enum EditorAction {
+    SelectNode(GameNodeId),
+    EditNode(NodeEdit),
+    SaveScene,
+}
+
 #[derive(Debug, Clone)]
 pub enum Message {
     ViewportDimsChanged((u32, u32)),
     CursorPosChanged(winit::dpi::PhysicalPosition<f64>),
     FrameCompleted(Duration),
     GpuFrameCompleted(Vec<wgpu_profiler::GpuTimerQueryResult>),
+    FrameProfiled(ProfiledFrame),
     CameraPoseChanged((Vec3, ControlledViewDirection)),
+    LookedAtNodeChanged(Option<String>),
+    HudChanged(Hud),
+    LoadingScreenChanged(LoadingScreen),
+    GameStateChanged(GameStateKind),
+    WorldLabelsChanged(ScreenLabels),
     AudioSoundStatsChanged((GameFilePath, AudioSoundStats)),
     #[allow(dead_code)]
     ToggleVSync(bool),
+    QualityPresetChanged(GraphicsQualityPreset),
+    /// sent by the game once it applied the requested preset
+    QualityPresetApplied(GraphicsSettings),
     BloomTypeChanged(BloomType),
+    TextureFilteringChanged(Option<TextureFiltering>),
+    TextureResolutionBiasChanged(u32),
+    ToggleTaa(bool),
     NewBloomRadiusChanged(f32),
     NewBloomIntensityChanged(f32),
     ToggleDepthPrepass(bool),
+    TogglePathTracer(bool),
     ToggleDirectionalShadowCulling(bool),
     ToggleCameraPose(bool),
     ToggleCursorMarker(bool),
     ToggleFpsChart(bool),
     ToggleGpuSpans(bool),
+    ToggleFrameProfiler(bool),
+    ToggleFrameProfilerPaused(bool),
+    SaveProfilerCapture,
+    /// the path of the capture or the error
+    ProfilerCaptureSaved(Result<String, String>),
     ToggleSoftShadows(bool),
     ToggleDrawCullingFrustum(bool),
     ToggleDrawPointLightCullingFrusta(bool),
@@ -93,10 +154,43 @@ pub enum Message {
     ToggleShadowDebug(bool),
     ToggleCascadeDebug(bool),
     ToggleAudioStats(bool),
+    ToggleLogWindow(bool),
+    /// the log generation and all the recent entries, see ikari::logging::log_generation
+    LogEntriesChanged((u64, Vec<LogEntry>)),
+    LogLevelFilterChanged(log::Level),
+    ToggleLogCategory((LogCategory, bool)),
+    ToggleContentBrowser(bool),
+    ContentBrowserDirectoryChanged(PathBuf),
+    /// selects the asset and starts dragging it if it's a model
+    ContentBrowserAssetPressed(usize),
+    /// sent by the game when the mouse is released while an asset is dragged
+    ContentBrowserAssetDropped,
+    /// the mouse was released over the content browser itself
+    ContentBrowserDragCancelled,
+    SpawnSelectedAsset,
+    /// sent by the game once it started loading the first n requested spawns
+    AssetSpawnsHandled(usize),
+    ToggleEditor(bool),
+    EditorPanelStateChanged(EditorPanelState),
+    GizmoModeChanged(GizmoMode),
+    EditorNodeSelected(GameNodeId),
+    EditorNodeEdited(NodeEdit),
+    SaveEditorScene,
+    /// sent by the game once it applied the first n requested editor actions
+    EditorActionsHandled(usize),
     ShadowBiasChanged(f32),
     SkyboxWeightChanged(f32),
     SoftShadowFactorChanged(f32),
     SoftShadowGridDimsChanged(u32),
+    ShadowFilterModeChanged(ShadowFilterMode),
+    PcfKernelRadiusChanged(f32),
+    ParallaxMaxStepCountChanged(u32),
+    ToggleDynamicResolution(bool),
+    DynamicResolutionTargetFpsChanged(f32),
+    DynamicResolutionMinScaleChanged(f32),
+    DynamicResolutionMaxScaleChanged(f32),
+    /// sent by the game every frame, the dynamic resolution may have changed it
+    RenderScaleChanged(f32),
     CullingFrustumLockModeChanged(CullingFrustumLockMode),
     TogglePopupMenu,
     ClosePopupMenu,
@@ -112,18 +206,57 @@ pub struct UiOverlay {
     fps_chart: FpsChart,
     is_showing_fps_chart: bool,
     is_showing_gpu_spans: bool,
+    pub is_showing_frame_profiler: bool,
+    pub is_frame_profiler_paused: bool,
+    pub requested_profiler_capture: bool,
+    profiler_capture_result: Option<Result<String, String>>,
+    profiler_chart: ProfilerChart,
     is_showing_audio_stats: bool,
+    pub is_showing_log_window: bool,
+    pub log_generation: u64,
+    log_entries: Vec<LogEntry>,
+    /// the entries that are less severe than this are hidden
+    log_level_filter: log::Level,
+    hidden_log_categories: HashSet<LogCategory>,
     pub is_showing_options_menu: bool,
     pub was_exit_button_pressed: bool,
     camera_pose: Option<(Vec3, ControlledViewDirection)>, // position, direction
+    looked_at_node_name: Option<String>,
+    hud: Hud,
+    loading_screen: LoadingScreen,
+    /// the top of the GameStateStack, the hud is only shown while playing
+    game_state: GameStateKind,
+    world_labels: ScreenLabels,
 
     audio_sound_stats: BTreeMap<String, AudioSoundStats>,
 
+    pub is_showing_content_browser: bool,
+    /// listed when the content browser is first opened
+    content_browser_assets: Option<Result<Vec<AssetEntry>, String>>,
+    /// relative to the game root, only its files and subfolders are listed
+    content_browser_directory: PathBuf,
+    /// None if the asset has no thumbnail, e.g. a model that clikari didn't render
+    content_browser_thumbnails: HashMap<PathBuf, Option<ContentBrowserThumbnail>>,
+    content_browser_selected_asset: Option<usize>,
+    content_browser_dragged_asset: Option<usize>,
+    pub requested_asset_spawns: Vec<AssetSpawnRequest>,
+
+    pub is_editor_enabled: bool,
+    pub gizmo_mode: GizmoMode,
+    editor_panel_state: EditorPanelState,
+    pub requested_editor_actions: Vec<EditorAction>,
+
     pub enable_vsync: bool,
+    pub quality_preset: GraphicsQualityPreset,
+    pub requested_quality_preset: Option<GraphicsQualityPreset>,
     pub bloom_type: BloomType,
+    pub texture_filtering: Option<TextureFiltering>,
+    pub texture_resolution_bias: u32,
+    pub enable_taa: bool,
     pub new_bloom_radius: f32,
     pub new_bloom_intensity: f32,
     pub enable_depth_prepass: bool,
+    pub enable_path_tracer: bool,
     pub enable_directional_shadow_culling: bool,
     pub enable_soft_shadows: bool,
     pub skybox_weight: f32,
@@ -136,6 +269,14 @@ pub struct UiOverlay {
     pub draw_directional_light_culling_frusta: bool,
     pub culling_frustum_lock_mode: CullingFrustumLockMode,
     pub soft_shadow_grid_dims: u32,
+    pub shadow_filter_mode: ShadowFilterMode,
+    pub pcf_kernel_radius: f32,
+    pub parallax_max_step_count: u32,
+    pub enable_dynamic_resolution: bool,
+    pub dynamic_resolution_target_fps: f32,
+    pub dynamic_resolution_min_scale: f32,
+    pub dynamic_resolution_max_scale: f32,
+    render_scale: f32,
     pub is_showing_camera_pose: bool,
     pub is_showing_cursor_marker: bool,
 
@@ -143,6 +284,22 @@ pub struct UiOverlay {
     perf_dump_completion_time: Option<Instant>,
 }
 
+/// An asset that the content browser wants the game to load into the scene
+#[derive(Debug, Clone)]
+pub struct AssetSpawnRequest {
+    pub asset: AssetEntry,
+    /// where it was dropped, relative to the window like cursor_position.
+    /// None to spawn it in front of the camera
+    pub drop_position: Option<Vec2>,
+}
+
+#[derive(Debug, Clone)]
+struct ContentBrowserThumbnail {
+    handle: iced::widget::image::Handle,
+    /// of the source image
+    dimensions: (u32, u32),
+}
+
 pub struct ContainerStyle;
 
 impl container::StyleSheet for ContainerStyle {
@@ -272,6 +429,118 @@ impl FpsChart {
     }
 }
 
+/// cpu and gpu time of the recent frames of the frame profiler
+#[derive(Debug, Clone, Default)]
+struct ProfilerChart {
+    recent_frames: VecDeque<ProfiledFrame>,
+}
+
+impl Chart<Message> for ProfilerChart {
+    type State = ();
+
+    fn build_chart<DB: DrawingBackend>(&self, _state: &Self::State, mut builder: ChartBuilder<DB>) {
+        let result: Result<(), String> = (|| {
+            let frame_count = self.recent_frames.len() as i32;
+            let cpu_times: Vec<_> = self
+                .recent_frames
+                .iter()
+                .enumerate()
+                .map(|(index, frame)| {
+                    (
+                        index as i32 - frame_count,
+                        frame.duration.as_secs_f32() * 1000.0,
+                    )
+                })
+                .collect();
+            let gpu_times: Vec<_> = self
+                .recent_frames
+                .iter()
+                .enumerate()
+                .filter(|(_, frame)| !frame.gpu_scopes.is_empty())
+                .map(|(index, frame)| {
+                    (
+                        index as i32 - frame_count,
+                        frame.gpu_total().as_secs_f32() * 1000.0,
+                    )
+                })
+                .collect();
+
+            // round up to the nearest multiple of 10ms
+            let max_y = cpu_times
+                .iter()
+                .chain(gpu_times.iter())
+                .map(|(_, millis)| *millis)
+                .fold(20.0f32, f32::max);
+            let max_y = (max_y / 10.0).ceil() * 10.0;
+
+            let mut chart = builder
+                .x_label_area_size(24)
+                .y_label_area_size(52)
+                .build_cartesian_2d(-(frame_count.max(1))..0, 0.0..max_y)
+                .map_err(|err| err.to_string())?;
+
+            let mesh_line_style = ShapeStyle {
+                color: RGBAColor(175, 175, 175, 1.0),
+                filled: false,
+                stroke_width: 1,
+            };
+            let axis_labels_style = (DEFAULT_FONT_NAME, 16, &WHITE);
+
+            chart
+                .configure_mesh()
+                .x_label_formatter(&|x| format!("{}", x.abs()))
+                .y_label_formatter(&|y| format!("{y:.0}ms"))
+                .disable_x_mesh()
+                .light_line_style(mesh_line_style)
+                .bold_line_style(mesh_line_style)
+                .axis_style(WHITE.stroke_width(2))
+                .x_label_style(axis_labels_style)
+                .y_label_style(axis_labels_style)
+                .draw()
+                .map_err(|err| err.to_string())?;
+
+            chart
+                .draw_series(plotters::series::LineSeries::new(
+                    cpu_times,
+                    RED.stroke_width(1),
+                ))
+                .map_err(|err| err.to_string())?
+                .label("CPU")
+                .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 16, y)], RED));
+            chart
+                .draw_series(plotters::series::LineSeries::new(
+                    gpu_times,
+                    CYAN.stroke_width(1),
+                ))
+                .map_err(|err| err.to_string())?
+                .label("GPU")
+                .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 16, y)], CYAN));
+
+            chart
+                .configure_series_labels()
+                .label_font(axis_labels_style)
+                .border_style(WHITE)
+                .draw()
+                .map_err(|err| err.to_string())?;
+
+            Ok(())
+        })();
+
+        if let Err(err) = result {
+            log::error!("Error building profiler chart: {err:?}");
+        }
+    }
+}
+
+impl ProfilerChart {
+    fn view(&self) -> Element<Message, iced::Theme, iced::Renderer> {
+        ChartWidget::new(self)
+            .width(Length::Fixed(400.0))
+            .height(Length::Fixed(200.0))
+            .into()
+    }
+}
+
 impl UiOverlay {
     pub fn new(window: &winit::window::Window) -> Self {
         let cursor_position = winit::dpi::PhysicalPosition::new(-1.0, -1.0);
@@ -290,19 +559,53 @@ impl UiOverlay {
 
             audio_sound_stats: BTreeMap::new(),
 
+            is_showing_content_browser: false,
+            content_browser_assets: None,
+            content_browser_directory: PathBuf::from(CONTENT_BROWSER_ROOT),
+            content_browser_thumbnails: HashMap::new(),
+            content_browser_selected_asset: None,
+            content_browser_dragged_asset: None,
+            requested_asset_spawns: vec![],
+
+            is_editor_enabled: false,
+            gizmo_mode: GizmoMode::default(),
+            editor_panel_state: EditorPanelState::default(),
+            requested_editor_actions: vec![],
+
             camera_pose: None,
+            looked_at_node_name: None,
+            hud: Hud::default(),
+            loading_screen: LoadingScreen::default(),
+            game_state: GameStateKind::Playing,
+            world_labels: ScreenLabels::default(),
             is_showing_camera_pose: INITIAL_IS_SHOWING_CAMERA_POSE,
             is_showing_cursor_marker: INITIAL_IS_SHOWING_CURSOR_MARKER,
             is_showing_fps_chart: false,
             is_showing_gpu_spans: false,
+            is_showing_frame_profiler: false,
+            is_frame_profiler_paused: false,
+            requested_profiler_capture: false,
+            profiler_capture_result: None,
+            profiler_chart: ProfilerChart::default(),
             is_showing_options_menu: false,
             was_exit_button_pressed: false,
             is_showing_audio_stats: false,
+            is_showing_log_window: false,
+            log_generation: 0,
+            log_entries: vec![],
+            log_level_filter: log::Level::Info,
+            hidden_log_categories: HashSet::new(),
             enable_vsync: INITIAL_ENABLE_VSYNC,
+            quality_preset: GraphicsQualityPreset::Custom,
+            requested_quality_preset: None,
             bloom_type: INITIAL_BLOOM_TYPE,
+            texture_filtering: INITIAL_TEXTURE_FILTERING,
+            texture_resolution_bias: 0,
+            enable_taa: false,
             new_bloom_radius: INITIAL_NEW_BLOOM_RADIUS,
             new_bloom_intensity: INITIAL_NEW_BLOOM_INTENSITY,
             enable_depth_prepass: INITIAL_ENABLE_DEPTH_PREPASS,
+            enable_path_tracer: false,
             enable_directional_shadow_culling: INITIAL_ENABLE_DIRECTIONAL_SHADOW_CULLING,
             enable_soft_shadows: INITIAL_ENABLE_SOFT_SHADOWS,
             skybox_weight: INITIAL_SKYBOX_WEIGHT,
@@ -316,12 +619,637 @@ impl UiOverlay {
                 INITIAL_ENABLE_DIRECTIONAL_LIGHT_CULLING_FRUSTUM_DEBUG,
             culling_frustum_lock_mode: CullingFrustumLockMode::None,
             soft_shadow_grid_dims: INITIAL_SOFT_SHADOW_GRID_DIMS,
+            shadow_filter_mode: ShadowFilter::default().mode,
+            pcf_kernel_radius: ShadowFilter::default().pcf_kernel_radius,
+            parallax_max_step_count: INITIAL_PARALLAX_MAX_STEP_COUNT,
+            enable_dynamic_resolution: false,
+            dynamic_resolution_target_fps: 60.0,
+            dynamic_resolution_min_scale: 0.5,
+            dynamic_resolution_max_scale: INITIAL_RENDER_SCALE,
+            render_scale: INITIAL_RENDER_SCALE,
             pending_perf_dump: None,
             perf_dump_completion_time: None,
         }
     }
 }
 
+impl UiOverlay {
+    fn get_content_browser_selected_asset(&self) -> Option<&AssetEntry> {
+        let assets = self.content_browser_assets.as_ref()?.as_ref().ok()?;
+        assets.get(self.content_browser_selected_asset?)
+    }
+
+    /// The subfolders of the current directory and the indices of the assets that are directly
+    /// in it. The folders are found from the asset paths so the ones without assets are skipped
+    fn content_browser_directory_entries(&self) -> (Vec<String>, Vec<usize>) {
+        let mut folders = vec![];
+        let mut asset_indices = vec![];
+        let Some(Ok(assets)) = &self.content_browser_assets else {
+            return (folders, asset_indices);
+        };
+        for (asset_index, asset) in assets.iter().enumerate() {
+            let Ok(path_in_directory) = asset
+                .path
+                .relative_path
+                .strip_prefix(&self.content_browser_directory)
+            else {
+                continue;
+            };
+            let mut components = path_in_directory.components();
+            let first_component = components.next();
+            match (first_component, components.next()) {
+                (Some(folder), Some(_)) => {
+                    let folder = folder.as_os_str().to_string_lossy().to_string();
+                    if !folders.contains(&folder) {
+                        folders.push(folder);
+                    }
+                }
+                (Some(_), None) => asset_indices.push(asset_index),
+                _ => {}
+            }
+        }
+        folders.sort();
+        (folders, asset_indices)
+    }
+
+    /// Decodes the thumbnails of the assets in the current directory that weren't decoded yet.
+    /// Textures are shrunk from the file itself, models use the png rendered by clikari
+    fn load_content_browser_thumbnails(&mut self) {
+        let Some(Ok(assets)) = &self.content_browser_assets else {
+            return;
+        };
+        let (_, asset_indices) = self.content_browser_directory_entries();
+        for asset in asset_indices
+            .into_iter()
+            .map(|asset_index| &assets[asset_index])
+        {
+            let relative_path = &asset.path.relative_path;
+            if self.content_browser_thumbnails.contains_key(relative_path) {
+                continue;
+            }
+            let image_path = match asset.kind {
+                AssetKind::Texture => Some(asset.path.resolve()),
+                AssetKind::Model => relative_path.file_stem().map(|file_stem| {
+                    GAME_PATH_MAKER
+                        .make(
+                            Path::new(MODEL_THUMBNAILS_FOLDER)
+                                .join(file_stem)
+                                .with_extension("png"),
+                        )
+                        .resolve()
+                }),
+                AssetKind::Audio => None,
+            };
+            let thumbnail = image_path
+                .and_then(|image_path| image::open(image_path).ok())
+                .map(|image| {
+                    let dimensions = (image.width(), image.height());
+                    let thumbnail = image
+                        .thumbnail(
+                            CONTENT_BROWSER_THUMBNAIL_SIZE,
+                            CONTENT_BROWSER_THUMBNAIL_SIZE,
+                        )
+                        .to_rgba8();
+                    ContentBrowserThumbnail {
+                        handle: iced::widget::image::Handle::from_pixels(
+                            thumbnail.width(),
+                            thumbnail.height(),
+                            thumbnail.into_raw(),
+                        ),
+                        dimensions,
+                    }
+                });
+            self.content_browser_thumbnails
+                .insert(relative_path.clone(), thumbnail);
+        }
+    }
+
+    fn content_browser_view(&self) -> Element<'_, Message, iced::Theme, iced::Renderer> {
+        let mut content = Column::new().spacing(4).width(Length::Fixed(360.0));
+
+        content = content.push(Text::new(format!(
+            "Content Browser: {}",
+            self.content_browser_directory.display()
+        )));
+
+        match &self.content_browser_assets {
+            Some(Ok(assets)) => {
+                let (folders, asset_indices) = self.content_browser_directory_entries();
+                let mut asset_list = Column::new().spacing(2);
+                if let Some(parent) = self
+                    .content_browser_directory
+                    .parent()
+                    .filter(|_| self.content_browser_directory != Path::new(CONTENT_BROWSER_ROOT))
+                {
+                    asset_list = asset_list.push(
+                        Button::new(Text::new("..").size(14))
+                            .width(Length::Fill)
+                            .on_press(Message::ContentBrowserDirectoryChanged(
+                                parent.to_path_buf(),
+                            )),
+                    );
+                }
+                for folder in folders {
+                    let directory = self.content_browser_directory.join(&folder);
+                    asset_list = asset_list.push(
+                        Button::new(Text::new(format!("{folder}/")).size(14))
+                            .width(Length::Fill)
+                            .on_press(Message::ContentBrowserDirectoryChanged(directory)),
+                    );
+                }
+                for asset_index in asset_indices {
+                    let asset = &assets[asset_index];
+                    let thumbnail: Element<_, _, _> = match self
+                        .content_browser_thumbnails
+                        .get(&asset.path.relative_path)
+                    {
+                        Some(Some(thumbnail)) => Image::new(thumbnail.handle.clone())
+                            .width(Length::Fixed(CONTENT_BROWSER_THUMBNAIL_SIZE as f32))
+                            .height(Length::Fixed(CONTENT_BROWSER_THUMBNAIL_SIZE as f32))
+                            .into(),
+                        _ => Container::new(Text::new(format!("{:?}", asset.kind)).size(12))
+                            .width(Length::Fixed(CONTENT_BROWSER_THUMBNAIL_SIZE as f32))
+                            .height(Length::Fixed(CONTENT_BROWSER_THUMBNAIL_SIZE as f32))
+                            .center_x()
+                            .center_y()
+                            .into(),
+                    };
+                    let file_name = asset
+                        .path
+                        .relative_path
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .to_string();
+                    let mut entry = Container::new(
+                        Row::new()
+                            .spacing(8)
+                            .align_items(iced::Alignment::Center)
+                            .push(thumbnail)
+                            .push(Text::new(file_name).size(14)),
+                    )
+                    .width(Length::Fill)
+                    .padding(2);
+                    if self.content_browser_selected_asset == Some(asset_index) {
+                        entry = entry
+                            .style(iced::theme::Container::Custom(Box::new(ContainerStyle {})));
+                    }
+                    asset_list = asset_list.push(
+                        mouse_area(entry)
+                            .on_press(Message::ContentBrowserAssetPressed(asset_index)),
+                    );
+                }
+                content = content.push(scrollable(asset_list).height(Length::Fixed(
+                    (self.viewport_dims.1 as f32 * 0.4).max(100.0),
+                )));
+            }
+            Some(Err(err)) => {
+                content = content.push(
+                    Text::new(format!("Failed to list assets: {err}"))
+                        .style(iced::Color::from_rgb(0.9, 0.1, 0.2)),
+                );
+            }
+            None => {}
+        }
+
+        if let Some(asset) = self.get_content_browser_selected_asset() {
+            content = content.push(Text::new(format!(
+                "Path: {}",
+                asset.path.relative_path.display()
+            )));
+            content = content.push(Text::new(format!("Type: {:?}", asset.kind)));
+            content = content.push(Text::new(format!(
+                "Size: {:.2} KB",
+                asset.size_bytes as f64 / 1024.0
+            )));
+            if let (AssetKind::Texture, Some(Some(thumbnail))) = (
+                asset.kind,
+                self.content_browser_thumbnails
+                    .get(&asset.path.relative_path),
+            ) {
+                content = content.push(Text::new(format!(
+                    "Dimensions: {}x{}",
+                    thumbnail.dimensions.0, thumbnail.dimensions.1
+                )));
+            }
+            if asset.kind == AssetKind::Model {
+                if self.content_browser_dragged_asset.is_some() {
+                    content = content.push(Text::new("Release over the scene to spawn it"));
+                }
+                content = content.push(
+                    Button::new(Text::new("Spawn In Front Of Camera"))
+                        .width(Length::Shrink)
+                        .on_press(Message::SpawnSelectedAsset),
+                );
+            }
+        }
+
+        content.into()
+    }
+
+    /// Whether a model from the content browser is being dragged into the scene
+    pub fn is_dragging_asset(&self) -> bool {
+        self.content_browser_dragged_asset.is_some()
+    }
+
+    fn log_window_view(&self) -> Element<'_, Message, iced::Theme, iced::Renderer> {
+        // the most recent entries that pass the filters
+        const MAX_SHOWN_LOG_ENTRIES: usize = 200;
+
+        let mut content = Column::new().spacing(4).width(Length::Fixed(520.0));
+
+        content = content.push(Text::new("Log"));
+
+        let mut levels = Row::new().spacing(12);
+        for level in [
+            log::Level::Error,
+            log::Level::Warn,
+            log::Level::Info,
+            log::Level::Debug,
+            log::Level::Trace,
+        ] {
+            levels = levels.push(radio(
+                format!("{level}"),
+                level,
+                Some(self.log_level_filter),
+                Message::LogLevelFilterChanged,
+            ));
+        }
+        content = content.push(levels);
+
+        let mut categories = Row::new().spacing(12);
+        for category in LogCategory::ALL {
+            categories = categories.push(
+                checkbox(
+                    format!("{category}"),
+                    !self.hidden_log_categories.contains(&category),
+                )
+                .on_toggle(move |is_shown| Message::ToggleLogCategory((category, is_shown))),
+            );
+        }
+        content = content.push(categories);
+
+        let mut shown_entries: Vec<_> = self
+            .log_entries
+            .iter()
+            .rev()
+            .filter(|entry| {
+                entry.level <= self.log_level_filter
+                    && !self.hidden_log_categories.contains(&entry.category)
+            })
+            .take(MAX_SHOWN_LOG_ENTRIES)
+            .collect();
+        shown_entries.reverse();
+
+        let mut lines = Column::new().spacing(2);
+        for entry in shown_entries {
+            let color = match entry.level {
+                log::Level::Error => iced::Color::from_rgb(0.9, 0.1, 0.2),
+                log::Level::Warn => iced::Color::from_rgb(1.0, 0.7, 0.1),
+                log::Level::Info => iced::Color::WHITE,
+                log::Level::Debug | log::Level::Trace => iced::Color::from_rgb(0.7, 0.7, 0.7),
+            };
+            let repeat_count = if entry.repeat_count > 1 {
+                format!(" (x{})", entry.repeat_count)
+            } else {
+                String::new()
+            };
+            lines = lines.push(
+                Text::new(format!(
+                    "{} [{}] {}{repeat_count}",
+                    entry.timestamp.format("%H:%M:%S"),
+                    entry.category,
+                    entry.message
+                ))
+                .size(14)
+                .style(color),
+            );
+        }
+        content = content.push(
+            scrollable(lines)
+                .height(Length::Fixed(
+                    (self.viewport_dims.1 as f32 * 0.4).max(100.0),
+                ))
+                .direction(scrollable::Direction::Vertical(
+                    scrollable::Properties::new().alignment(scrollable::Alignment::End),
+                )),
+        );
+
+        content.into()
+    }
+
+    fn frame_profiler_view(&self) -> Element<'_, Message, iced::Theme, iced::Renderer> {
+        // scopes shorter than this are left out of the lists
+        const MIN_LISTED_SCOPE_MILLIS: f64 = 0.05;
+
+        let mut content = Column::new().spacing(4).width(Length::Fixed(420.0));
+
+        content = content.push(Text::new("Frame Profiler"));
+        let mut controls = Row::new().spacing(12).push(
+            checkbox("Paused", self.is_frame_profiler_paused)
+                .on_toggle(Message::ToggleFrameProfilerPaused),
+        );
+        if can_generate_profile_dump() {
+            controls = controls.push(
+                Button::new(Text::new("Save Capture"))
+                    .width(Length::Shrink)
+                    .on_press(Message::SaveProfilerCapture),
+            );
+        }
+        content = content.push(controls);
+        content = content.push(
+            Text::new(format!(
+                "Render scale: {:.2}{}",
+                self.render_scale,
+                if self.enable_dynamic_resolution {
+                    " (dynamic)"
+                } else {
+                    ""
+                }
+            ))
+            .size(14),
+        );
+        match &self.profiler_capture_result {
+            Some(Ok(path)) => {
+                content = content.push(
+                    Text::new(format!("Saved capture to {path}"))
+                        .size(14)
+                        .style(iced::Color::from_rgb(0.7, 1.0, 0.0)),
+                );
+            }
+            Some(Err(err)) => {
+                content = content.push(
+                    Text::new(format!("Failed to save capture: {err}"))
+                        .size(14)
+                        .style(iced::Color::from_rgb(0.9, 0.1, 0.2)),
+                );
+            }
+            None => {}
+        }
+        content = content.push(self.profiler_chart.view());
+
+        let mut scopes = Column::new().spacing(2);
+        let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+
+        scopes = scopes.push(Text::new("CPU"));
+        if !cpu_scopes_are_collected() {
+            scopes = scopes.push(
+                Text::new("Build with the frame-profiler feature to see the CPU scopes").size(14),
+            );
+        } else if let Some(frame) = self.profiler_chart.recent_frames.back() {
+            let mut last_thread = None;
+            for scope in &frame.cpu_scopes {
+                if millis(scope.duration) < MIN_LISTED_SCOPE_MILLIS {
+                    continue;
+                }
+                if last_thread != Some(&scope.thread) {
+                    scopes = scopes.push(Text::new(format!("[{}]", scope.thread)).size(14));
+                    last_thread = Some(&scope.thread);
+                }
+                scopes = scopes.push(
+                    Text::new(format!(
+                        "{}{}: {:.2}ms",
+                        "    ".repeat(scope.depth as usize),
+                        scope.name,
+                        millis(scope.duration)
+                    ))
+                    .size(14),
+                );
+            }
+        }
+
+        scopes = scopes.push(Text::new("GPU"));
+        if let Some(frame) = self
+            .profiler_chart
+            .recent_frames
+            .iter()
+            .rev()
+            .find(|frame| !frame.gpu_scopes.is_empty())
+        {
+            for scope in &frame.gpu_scopes {
+                if millis(scope.duration) < MIN_LISTED_SCOPE_MILLIS {
+                    continue;
+                }
+                scopes = scopes.push(
+                    Text::new(format!(
+                        "{}{}: {:.2}ms",
+                        "    ".repeat(scope.depth as usize),
+                        scope.label,
+                        millis(scope.duration)
+                    ))
+                    .size(14),
+                );
+            }
+        }
+
+        content = content.push(scrollable(scopes).height(Length::Fixed(
+            (self.viewport_dims.1 as f32 * 0.4).max(100.0),
+        )));
+
+        content.into()
+    }
+
+    fn editor_view(&self) -> Element<'_, Message, iced::Theme, iced::Renderer> {
+        let mut content = Column::new().spacing(4).width(Length::Fixed(320.0));
+
+        content = content.push(Text::new("Level Editor"));
+
+        let mut gizmo_modes = Row::new().spacing(12);
+        for mode in GizmoMode::ALL {
+            gizmo_modes = gizmo_modes.push(radio(
+                format!("{mode}"),
+                mode,
+                Some(self.gizmo_mode),
+                Message::GizmoModeChanged,
+            ));
+        }
+        content = content.push(gizmo_modes);
+
+        let selected_node_id = self
+            .editor_panel_state
+            .selected_node
+            .as_ref()
+            .map(|node| node.node_id);
+        let mut hierarchy = Column::new().spacing(2);
+        for entry in &self.editor_panel_state.hierarchy {
+            let style = if Some(entry.node_id) == selected_node_id {
+                iced::theme::Button::Primary
+            } else {
+                iced::theme::Button::Secondary
+            };
+            hierarchy = hierarchy.push(
+                Container::new(
+                    Button::new(Text::new(&entry.name).size(14))
+                        .width(Length::Fill)
+                        .style(style)
+                        .on_press(Message::EditorNodeSelected(entry.node_id)),
+                )
+                .padding([0, 0, 0, 12 * entry.depth.min(16) as u16]),
+            );
+        }
+        content = content.push(scrollable(hierarchy).height(Length::Fixed(
+            (self.viewport_dims.1 as f32 * 0.35).max(100.0),
+        )));
+
+        if let Some(node) = &self.editor_panel_state.selected_node {
+            content = content.push(node_inspector_view(node));
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            content = content.push(
+                Button::new(Text::new("Save Scene"))
+                    .width(Length::Shrink)
+                    .on_press(Message::SaveEditorScene),
+            );
+        }
+
+        content.into()
+    }
+}
+
+fn node_inspector_view(node: &NodeProperties) -> Element<'_, Message, iced::Theme, iced::Renderer> {
+    let mut inspector = Column::new().spacing(4);
+
+    inspector = inspector.push(Text::new(
+        node.name
+            .clone()
+            .unwrap_or_else(|| format!("{:?}", node.node_id)),
+    ));
+
+    let position = node.transform.position;
+    let (yaw, pitch, roll) = node.transform.rotation.to_euler(EulerRot::YXZ);
+    let scale = node.transform.scale;
+    inspector = inspector.push(
+        Text::new(format!(
+            "Position: {:.2}, {:.2}, {:.2}",
+            position.x, position.y, position.z
+        ))
+        .size(14),
+    );
+    inspector = inspector.push(
+        Text::new(format!(
+            "Rotation: {:.1}, {:.1}, {:.1} deg",
+            rad_to_deg(pitch),
+            rad_to_deg(yaw),
+            rad_to_deg(roll)
+        ))
+        .size(14),
+    );
+    inspector = inspector.push(
+        Text::new(format!(
+            "Scale: {:.2}, {:.2}, {:.2}",
+            scale.x, scale.y, scale.z
+        ))
+        .size(14),
+    );
+
+    if let Some(point_light) = &node.point_light {
+        inspector = inspector.push(color_sliders(
+            "Light Color",
+            point_light.color.extend(1.0),
+            3,
+            |color| NodeEdit::PointLightColor(color.truncate()),
+        ));
+        inspector = inspector
+            .push(Text::new(format!("Light Intensity: {:.2}", point_light.intensity)).size(14));
+        inspector = inspector.push(
+            slider(0.0..=50.0, point_light.intensity, |intensity| {
+                Message::EditorNodeEdited(NodeEdit::PointLightIntensity(intensity))
+            })
+            .step(0.1),
+        );
+    }
+
+    match node.material {
+        Some(Material::Pbr {
+            dynamic_pbr_params: Some(params),
+            ..
+        }) => {
+            inspector = inspector.push(color_sliders(
+                "Base Color",
+                params.base_color_factor,
+                4,
+                NodeEdit::MaterialColor,
+            ));
+            inspector = inspector
+                .push(Text::new(format!("Metallic: {:.2}", params.metallic_factor)).size(14));
+            inspector = inspector.push(
+                slider(0.0..=1.0, params.metallic_factor, |metallic| {
+                    Message::EditorNodeEdited(NodeEdit::MaterialMetallic(metallic))
+                })
+                .step(0.01),
+            );
+            inspector = inspector
+                .push(Text::new(format!("Roughness: {:.2}", params.roughness_factor)).size(14));
+            inspector = inspector.push(
+                slider(0.0..=1.0, params.roughness_factor, |roughness| {
+                    Message::EditorNodeEdited(NodeEdit::MaterialRoughness(roughness))
+                })
+                .step(0.01),
+            );
+        }
+        Some(Material::Unlit { color }) => {
+            inspector = inspector.push(color_sliders(
+                "Color",
+                color.extend(1.0),
+                3,
+                NodeEdit::MaterialColor,
+            ));
+        }
+        Some(Material::Transparent { color, .. }) => {
+            inspector = inspector.push(color_sliders("Color", color, 4, NodeEdit::MaterialColor));
+        }
+        _ => {}
+    }
+
+    if let Some(wireframe) = node.wireframe {
+        inspector = inspector.push(
+            checkbox("Wireframe", wireframe)
+                .on_toggle(|wireframe| Message::EditorNodeEdited(NodeEdit::Wireframe(wireframe))),
+        );
+    }
+    inspector = inspector
+        .push(
+            checkbox("Visible", node.visible)
+                .on_toggle(|visible| Message::EditorNodeEdited(NodeEdit::Visible(visible))),
+        )
+        .push(
+            checkbox("Casts shadows", node.casts_shadows).on_toggle(|casts_shadows| {
+                Message::EditorNodeEdited(NodeEdit::CastsShadows(casts_shadows))
+            }),
+        );
+
+    inspector.into()
+}
+
+/// one slider per channel, from 0 to 1
+fn color_sliders<'a>(
+    label: &str,
+    color: Vec4,
+    channel_count: usize,
+    make_edit: impl Fn(Vec4) -> NodeEdit + Copy + 'a,
+) -> Element<'a, Message, iced::Theme, iced::Renderer> {
+    let channels: Vec<_> = color.to_array()[..channel_count]
+        .iter()
+        .map(|channel| format!("{channel:.2}"))
+        .collect();
+    let mut column = Column::new()
+        .spacing(2)
+        .push(Text::new(format!("{label}: {}", channels.join(", "))).size(14));
+    for channel_index in 0..channel_count {
+        column = column.push(
+            slider(0.0..=1.0, color[channel_index], move |value| {
+                let mut color = color;
+                color[channel_index] = value;
+                Message::EditorNodeEdited(make_edit(color))
+            })
+            .step(0.01),
+        );
+    }
+    column.into()
+}
+
 impl<Message> canvas::Program<Message, iced::Theme, iced::Renderer> for UiOverlay {
     type State = ();
 
@@ -470,6 +1398,12 @@ impl runtime::Program for UiOverlay {
                     self.fps_chart.recent_gpu_frame_times.remove(0);
                 }
             }
+            Message::FrameProfiled(frame) => {
+                self.profiler_chart.recent_frames.push_back(frame);
+                if self.profiler_chart.recent_frames.len() > FRAME_TIME_HISTORY_SIZE {
+                    self.profiler_chart.recent_frames.pop_front();
+                }
+            }
             Message::AudioSoundStatsChanged((track_path, stats)) => {
                 self.audio_sound_stats.insert(
                     track_path.relative_path.to_string_lossy().to_string(),
@@ -479,11 +1413,56 @@ impl runtime::Program for UiOverlay {
             Message::CameraPoseChanged(new_state) => {
                 self.camera_pose = Some(new_state);
             }
+            Message::LookedAtNodeChanged(new_state) => {
+                self.looked_at_node_name = new_state;
+            }
+            Message::HudChanged(new_state) => {
+                self.hud = new_state;
+            }
+            Message::LoadingScreenChanged(new_state) => {
+                self.loading_screen = new_state;
+            }
+            Message::GameStateChanged(new_state) => {
+                self.game_state = new_state;
+            }
+            Message::WorldLabelsChanged(new_state) => {
+                self.world_labels = new_state;
+            }
             Message::ToggleVSync(new_state) => {
                 self.enable_vsync = new_state;
             }
+            Message::QualityPresetChanged(new_state) => {
+                self.quality_preset = new_state;
+                self.requested_quality_preset = Some(new_state);
+            }
+            Message::QualityPresetApplied(graphics) => {
+                self.requested_quality_preset = None;
+                self.bloom_type = graphics.bloom_quality.bloom_type();
+                self.texture_filtering = Some(graphics.texture_filtering);
+                self.texture_resolution_bias = graphics.texture_resolution_bias;
+                self.enable_taa = graphics.anti_aliasing == AntiAliasing::Taa;
+                self.parallax_max_step_count = graphics.parallax_max_step_count;
+                let soft_shadow_grid_dims = graphics.shadow_quality.soft_shadow_grid_dims();
+                self.enable_soft_shadows = soft_shadow_grid_dims.is_some();
+                if let Some(soft_shadow_grid_dims) = soft_shadow_grid_dims {
+                    self.soft_shadow_grid_dims = soft_shadow_grid_dims;
+                }
+            }
             Message::BloomTypeChanged(new_state) => {
                 self.bloom_type = new_state;
+                self.quality_preset = GraphicsQualityPreset::Custom;
+            }
+            Message::TextureFilteringChanged(new_state) => {
+                self.texture_filtering = new_state;
+                self.quality_preset = GraphicsQualityPreset::Custom;
+            }
+            Message::TextureResolutionBiasChanged(new_state) => {
+                self.texture_resolution_bias = new_state;
+                self.quality_preset = GraphicsQualityPreset::Custom;
+            }
+            Message::ToggleTaa(new_state) => {
+                self.enable_taa = new_state;
+                self.quality_preset = GraphicsQualityPreset::Custom;
             }
             Message::NewBloomRadiusChanged(new_state) => {
                 self.new_bloom_radius = new_state;
@@ -494,6 +1473,9 @@ impl runtime::Program for UiOverlay {
             Message::ToggleDepthPrepass(new_state) => {
                 self.enable_depth_prepass = new_state;
             }
+            Message::TogglePathTracer(new_state) => {
+                self.enable_path_tracer = new_state;
+            }
             Message::ToggleDirectionalShadowCulling(new_state) => {
                 self.enable_directional_shadow_culling = new_state;
             }
@@ -509,8 +1491,26 @@ impl runtime::Program for UiOverlay {
             Message::ToggleGpuSpans(new_state) => {
                 self.is_showing_gpu_spans = new_state;
             }
+            Message::ToggleFrameProfiler(new_state) => {
+                self.is_showing_frame_profiler = new_state;
+                if !new_state {
+                    self.profiler_chart.recent_frames.clear();
+                }
+            }
+            Message::ToggleFrameProfilerPaused(new_state) => {
+                self.is_frame_profiler_paused = new_state;
+            }
+            Message::SaveProfilerCapture => {
+                self.requested_profiler_capture = true;
+                self.profiler_capture_result = None;
+            }
+            Message::ProfilerCaptureSaved(result) => {
+                self.requested_profiler_capture = false;
+                self.profiler_capture_result = Some(result);
+            }
             Message::ToggleSoftShadows(new_state) => {
                 self.enable_soft_shadows = new_state;
+                self.quality_preset = GraphicsQualityPreset::Custom;
             }
             Message::ToggleDrawCullingFrustum(new_state) => {
                 self.draw_culling_frustum = new_state;
@@ -533,6 +1533,110 @@ impl runtime::Program for UiOverlay {
             Message::ToggleAudioStats(new_state) => {
                 self.is_showing_audio_stats = new_state;
             }
+            Message::ToggleLogWindow(new_state) => {
+                self.is_showing_log_window = new_state;
+            }
+            Message::LogEntriesChanged((generation, entries)) => {
+                self.log_generation = generation;
+                self.log_entries = entries;
+            }
+            Message::LogLevelFilterChanged(new_state) => {
+                self.log_level_filter = new_state;
+            }
+            Message::ToggleLogCategory((category, is_shown)) => {
+                if is_shown {
+                    self.hidden_log_categories.remove(&category);
+                } else {
+                    self.hidden_log_categories.insert(category);
+                }
+            }
+            Message::ToggleContentBrowser(new_state) => {
+                self.is_showing_content_browser = new_state;
+                if new_state && self.content_browser_assets.is_none() {
+                    self.content_browser_assets = Some(
+                        FileManager::list_assets(&GAME_PATH_MAKER.make(CONTENT_BROWSER_ROOT))
+                            .map_err(|err| err.to_string()),
+                    );
+                }
+                if new_state {
+                    self.load_content_browser_thumbnails();
+                } else {
+                    self.content_browser_dragged_asset = None;
+                }
+            }
+            Message::ContentBrowserDirectoryChanged(new_state) => {
+                self.content_browser_directory = new_state;
+                self.content_browser_selected_asset = None;
+                self.load_content_browser_thumbnails();
+            }
+            Message::ContentBrowserAssetPressed(asset_index) => {
+                self.content_browser_selected_asset = Some(asset_index);
+                self.content_browser_dragged_asset = self
+                    .get_content_browser_selected_asset()
+                    .filter(|asset| asset.kind == AssetKind::Model)
+                    .map(|_| asset_index);
+            }
+            Message::ContentBrowserAssetDropped => {
+                let dropped_asset =
+                    self.content_browser_dragged_asset
+                        .take()
+                        .and_then(|asset_index| {
+                            self.content_browser_assets
+                                .as_ref()?
+                                .as_ref()
+                                .ok()?
+                                .get(asset_index)
+                                .cloned()
+                        });
+                if let Some(asset) = dropped_asset {
+                    self.requested_asset_spawns.push(AssetSpawnRequest {
+                        asset,
+                        drop_position: Some(Vec2::new(
+                            self.cursor_position.x as f32,
+                            self.cursor_position.y as f32,
+                        )),
+                    });
+                }
+            }
+            Message::ContentBrowserDragCancelled => {
+                self.content_browser_dragged_asset = None;
+            }
+            Message::SpawnSelectedAsset => {
+                if let Some(asset) = self.get_content_browser_selected_asset() {
+                    self.requested_asset_spawns.push(AssetSpawnRequest {
+                        asset: asset.clone(),
+                        drop_position: None,
+                    });
+                }
+            }
+            Message::AssetSpawnsHandled(count) => {
+                self.requested_asset_spawns
+                    .drain(..count.min(self.requested_asset_spawns.len()));
+            }
+            Message::ToggleEditor(new_state) => {
+                self.is_editor_enabled = new_state;
+            }
+            Message::EditorPanelStateChanged(new_state) => {
+                self.editor_panel_state = new_state;
+            }
+            Message::GizmoModeChanged(new_state) => {
+                self.gizmo_mode = new_state;
+            }
+            Message::EditorNodeSelected(node_id) => {
+                self.requested_editor_actions
+                    .push(EditorAction::SelectNode(node_id));
+            }
+            Message::EditorNodeEdited(edit) => {
+                self.requested_editor_actions
+                    .push(EditorAction::EditNode(edit));
+            }
+            Message::SaveEditorScene => {
+                self.requested_editor_actions.push(EditorAction::SaveScene);
+            }
+            Message::EditorActionsHandled(count) => {
+                self.requested_editor_actions
+                    .drain(..count.min(self.requested_editor_actions.len()));
+            }
             Message::SkyboxWeightChanged(new_state) => {
                 self.skybox_weight = new_state;
             }
@@ -544,6 +1648,36 @@ impl runtime::Program for UiOverlay {
             }
             Message::SoftShadowGridDimsChanged(new_state) => {
                 self.soft_shadow_grid_dims = new_state;
+                self.quality_preset = GraphicsQualityPreset::Custom;
+            }
+            Message::ShadowFilterModeChanged(new_state) => {
+                self.shadow_filter_mode = new_state;
+            }
+            Message::PcfKernelRadiusChanged(new_state) => {
+                self.pcf_kernel_radius = new_state;
+            }
+            Message::ParallaxMaxStepCountChanged(new_state) => {
+                self.parallax_max_step_count = new_state;
+                self.quality_preset = GraphicsQualityPreset::Custom;
+            }
+            Message::ToggleDynamicResolution(new_state) => {
+                self.enable_dynamic_resolution = new_state;
+            }
+            Message::DynamicResolutionTargetFpsChanged(new_state) => {
+                self.dynamic_resolution_target_fps = new_state;
+            }
+            Message::DynamicResolutionMinScaleChanged(new_state) => {
+                self.dynamic_resolution_min_scale = new_state;
+                self.dynamic_resolution_max_scale =
+                    self.dynamic_resolution_max_scale.max(new_state);
+            }
+            Message::DynamicResolutionMaxScaleChanged(new_state) => {
+                self.dynamic_resolution_max_scale = new_state;
+                self.dynamic_resolution_min_scale =
+                    self.dynamic_resolution_min_scale.min(new_state);
+            }
+            Message::RenderScaleChanged(new_state) => {
+                self.render_scale = new_state;
             }
             Message::CullingFrustumLockModeChanged(new_state) => {
                 self.culling_frustum_lock_mode = new_state;
@@ -616,6 +1750,10 @@ impl runtime::Program for UiOverlay {
             }
         }
 
+        if let Some(looked_at_node_name) = &self.looked_at_node_name {
+            rows = rows.push(text(&format!("Looking at: {looked_at_node_name}")));
+        }
+
         if self.is_showing_audio_stats {
             for (file_path, stats) in &self.audio_sound_stats {
                 let format_timestamp = |timestamp| format!("{timestamp:.2}");
@@ -654,19 +1792,80 @@ impl runtime::Program for UiOverlay {
             rows = rows.push(Container::new(self.fps_chart.view()).padding(padding));
         }
 
-        let background_content = Container::new(
-            Row::new()
-                .width(Length::Shrink)
-                .height(Length::Shrink)
-                .padding(8)
-                .push(
-                    Container::new(rows)
+        let mut background_row = Row::new()
+            .width(Length::Shrink)
+            .height(Length::Shrink)
+            .padding(8)
+            .spacing(8)
+            .push(
+                Container::new(rows)
+                    .padding(8)
+                    .style(iced::theme::Container::Custom(container_style)),
+            );
+
+        if self.is_showing_log_window {
+            background_row = background_row.push(
+                Container::new(self.log_window_view())
+                    .padding(8)
+                    .style(iced::theme::Container::Custom(Box::new(ContainerStyle {}))),
+            );
+        }
+
+        if self.is_showing_frame_profiler {
+            background_row = background_row.push(
+                Container::new(self.frame_profiler_view())
+                    .padding(8)
+                    .style(iced::theme::Container::Custom(Box::new(ContainerStyle {}))),
+            );
+        }
+
+        if self.is_showing_content_browser {
+            // the events reach the widgets before the messages queued by the game, so a drag
+            // released over the browser is cancelled before the game reports the drop
+            background_row = background_row.push(
+                mouse_area(
+                    Container::new(self.content_browser_view())
                         .padding(8)
-                        .style(iced::theme::Container::Custom(container_style)),
-                ),
-        )
-        .width(Length::Fill)
-        .height(Length::Fill);
+                        .style(iced::theme::Container::Custom(Box::new(ContainerStyle {}))),
+                )
+                .on_release(Message::ContentBrowserDragCancelled),
+            );
+        }
+
+        if self.is_editor_enabled {
+            background_row = background_row.push(
+                Container::new(self.editor_view())
+                    .padding(8)
+                    .style(iced::theme::Container::Custom(Box::new(ContainerStyle {}))),
+            );
+        }
+
+        let background_content: Element<_, _, _> = match self.game_state {
+            // the world labels go under the hud
+            GameStateKind::Playing => floating_element(
+                floating_element(
+                    Container::new(background_row)
+                        .width(Length::Fill)
+                        .height(Length::Fill),
+                    self.world_labels.view(),
+                )
+                .anchor(floating_element::Anchor::NorthWest),
+                self.hud.view(),
+            )
+            .anchor(floating_element::Anchor::NorthWest)
+            .into(),
+            _ => Container::new(background_row)
+                .width(Length::Fill)
+                .height(Length::Fill)
+                .into(),
+        };
+        let background_content: Element<_, _, _> = if self.loading_screen.is_visible() {
+            floating_element(background_content, self.loading_screen.view())
+                .anchor(floating_element::Anchor::NorthWest)
+                .into()
+        } else {
+            background_content
+        };
 
         let modal_content: Option<Element<_, _, _>> = self.is_showing_options_menu.then(|| {
             let separator_line = Text::new("-------------")
@@ -691,6 +1890,94 @@ impl runtime::Program for UiOverlay {
                     .on_toggle(Message::ToggleDepthPrepass),
             );
 
+            options = options.push(
+                checkbox("Path-traced Reference", self.enable_path_tracer)
+                    .on_toggle(Message::TogglePathTracer),
+            );
+
+            options = options.push(Text::new("Quality Preset"));
+            for preset in GraphicsQualityPreset::ALL {
+                options = options.push(radio(
+                    format!("{preset}"),
+                    preset,
+                    Some(self.quality_preset),
+                    Message::QualityPresetChanged,
+                ));
+            }
+
+            options = options.push(
+                checkbox("Dynamic Resolution", self.enable_dynamic_resolution)
+                    .on_toggle(Message::ToggleDynamicResolution),
+            );
+            if self.enable_dynamic_resolution {
+                options = options.push(Text::new(format!(
+                    "Target FPS: {:.0}",
+                    self.dynamic_resolution_target_fps
+                )));
+                options = options.push(
+                    slider(
+                        30.0..=240.0,
+                        self.dynamic_resolution_target_fps,
+                        Message::DynamicResolutionTargetFpsChanged,
+                    )
+                    .step(1.0),
+                );
+                options = options.push(Text::new(format!(
+                    "Min Render Scale: {:.2}",
+                    self.dynamic_resolution_min_scale
+                )));
+                options = options.push(
+                    slider(
+                        0.1..=2.0,
+                        self.dynamic_resolution_min_scale,
+                        Message::DynamicResolutionMinScaleChanged,
+                    )
+                    .step(0.05),
+                );
+                options = options.push(Text::new(format!(
+                    "Max Render Scale: {:.2}",
+                    self.dynamic_resolution_max_scale
+                )));
+                options = options.push(
+                    slider(
+                        0.1..=2.0,
+                        self.dynamic_resolution_max_scale,
+                        Message::DynamicResolutionMaxScaleChanged,
+                    )
+                    .step(0.05),
+                );
+            }
+
+            options = options.push(Text::new("Texture Filtering"));
+            for filtering in std::iter::once(None).chain(TextureFiltering::ALL.map(Some)) {
+                options = options.push(radio(
+                    filtering.map_or_else(
+                        || String::from("As Authored"),
+                        |filtering| format!("{filtering}"),
+                    ),
+                    filtering,
+                    Some(self.texture_filtering),
+                    Message::TextureFilteringChanged,
+                ));
+            }
+
+            options = options.push(Text::new(format!(
+                "Texture Resolution Bias: {:} (applies to newly loaded textures)",
+                self.texture_resolution_bias
+            )));
+            options = options.push(
+                slider(
+                    0..=3u32,
+                    self.texture_resolution_bias,
+                    Message::TextureResolutionBiasChanged,
+                )
+                .step(1u32),
+            );
+
+            options = options.push(
+                checkbox("Temporal Anti-aliasing", self.enable_taa).on_toggle(Message::ToggleTaa),
+            );
+
             options = options.push(Text::new("Bloom Type"));
             for mode in BloomType::ALL {
                 options = options.push(radio(
@@ -753,6 +2040,18 @@ impl runtime::Program for UiOverlay {
                     .on_toggle(Message::ToggleAudioStats),
             );
 
+            // content browser
+            options = options.push(
+                checkbox("Show Content Browser", self.is_showing_content_browser)
+                    .on_toggle(Message::ToggleContentBrowser),
+            );
+
+            // level editor
+            options = options.push(
+                checkbox("Show Level Editor", self.is_editor_enabled)
+                    .on_toggle(Message::ToggleEditor),
+            );
+
             // fps overlay
             options = options.push(
                 checkbox("Show FPS Chart", self.is_showing_fps_chart)
@@ -771,6 +2070,15 @@ impl runtime::Program for UiOverlay {
                 );
             }
 
+            options = options.push(
+                checkbox("Show Frame Profiler", self.is_showing_frame_profiler)
+                    .on_toggle(Message::ToggleFrameProfiler),
+            );
+            options = options.push(
+                checkbox("Show Log", self.is_showing_log_window)
+                    .on_toggle(Message::ToggleLogWindow),
+            );
+
             // frustum culling debug
             options = options.push(separator_line.clone());
             options = options.push(
@@ -860,6 +2168,39 @@ impl runtime::Program for UiOverlay {
                 )
                 .step(1u32),
             );
+            options = options.push(Text::new("Shadow Filter"));
+            for mode in ShadowFilterMode::ALL {
+                options = options.push(radio(
+                    format!("{mode}"),
+                    mode,
+                    Some(self.shadow_filter_mode),
+                    Message::ShadowFilterModeChanged,
+                ));
+            }
+            options = options.push(Text::new(format!(
+                "PCF Kernel Radius: {:.1}",
+                self.pcf_kernel_radius
+            )));
+            options = options.push(
+                slider(
+                    0.5..=8.0,
+                    self.pcf_kernel_radius,
+                    Message::PcfKernelRadiusChanged,
+                )
+                .step(0.1),
+            );
+            options = options.push(Text::new(format!(
+                "Parallax Steps: {:}",
+                self.parallax_max_step_count
+            )));
+            options = options.push(
+                slider(
+                    0..=64u32,
+                    self.parallax_max_step_count,
+                    Message::ParallaxMaxStepCountChanged,
+                )
+                .step(1u32),
+            );
 
             // profile dump
             if can_generate_profile_dump() {
@@ -0,0 +1,459 @@
+use std::collections::HashMap;
+
+use crate::animation::LoopType;
+use crate::ecs::{Entity, PhysicsBody, SceneNode};
+use crate::engine_state::EngineState;
+use crate::physics::rapier3d_f64::prelude::*;
+use crate::scene::GameNodeId;
+use crate::weapon::Weapon;
+
+use glam::f32::Vec3;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BlackboardValue {
+    Bool(bool),
+    Float(f32),
+    Vec3(Vec3),
+    Entity(Entity),
+    Node(GameNodeId),
+}
+
+/// Per-agent data shared by the nodes of its behavior tree, e.g. the target
+/// picked by a perception action and followed by move_to
+#[derive(Debug, Clone, Default)]
+pub struct Blackboard {
+    values: HashMap<String, BlackboardValue>,
+}
+
+impl Blackboard {
+    pub fn set(&mut self, key: &str, value: BlackboardValue) {
+        self.values.insert(key.to_string(), value);
+    }
+
+    pub fn get(&self, key: &str) -> Option<BlackboardValue> {
+        self.values.get(key).copied()
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<BlackboardValue> {
+        self.values.remove(key)
+    }
+
+    pub fn get_bool(&self, key: &str) -> bool {
+        matches!(self.get(key), Some(BlackboardValue::Bool(true)))
+    }
+
+    pub fn get_f32(&self, key: &str) -> Option<f32> {
+        match self.get(key) {
+            Some(BlackboardValue::Float(value)) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BehaviorStatus {
+    Success,
+    Failure,
+    Running,
+}
+
+/// What the nodes of a tree get access to while it's ticked
+pub struct AgentContext<'a> {
+    pub entity: Entity,
+    pub engine_state: &'a mut EngineState,
+    pub blackboard: &'a mut Blackboard,
+    pub delta_time_seconds: f32,
+}
+
+impl AgentContext<'_> {
+    /// global position of the agent's SceneNode
+    pub fn position(&self) -> Option<Vec3> {
+        let SceneNode(node_id) = self.engine_state.world.get::<SceneNode>(self.entity)?;
+        Some(
+            self.engine_state
+                .scene
+                .get_global_transform_for_node(*node_id)
+                .position(),
+        )
+    }
+
+    /// Vec3 values are used as is, entities and nodes are resolved to their current position
+    pub fn resolve_position(&self, key: &str) -> Option<Vec3> {
+        let node_id = match self.blackboard.get(key)? {
+            BlackboardValue::Vec3(position) => return Some(position),
+            BlackboardValue::Entity(entity) => self.engine_state.world.get::<SceneNode>(entity)?.0,
+            BlackboardValue::Node(node_id) => node_id,
+            _ => return None,
+        };
+        self.engine_state.scene.get_node(node_id)?;
+        Some(
+            self.engine_state
+                .scene
+                .get_global_transform_for_node(node_id)
+                .position(),
+        )
+    }
+
+    fn time_seconds(&self) -> f64 {
+        let timestep = &self.engine_state.simulation_timestep;
+        timestep.tick() as f64 * timestep.timestep_seconds
+    }
+}
+
+pub type Action = Box<dyn FnMut(&mut AgentContext) -> BehaviorStatus>;
+pub type Condition = Box<dyn Fn(&AgentContext) -> bool>;
+
+pub enum BehaviorNode {
+    /// ticks its children in order until one of them fails. A running child is resumed on
+    /// the next tick instead of starting over
+    Sequence {
+        children: Vec<BehaviorNode>,
+        current: usize,
+    },
+    /// ticks its children in order until one of them succeeds, resuming like Sequence
+    Selector {
+        children: Vec<BehaviorNode>,
+        current: usize,
+    },
+    Inverter(Box<BehaviorNode>),
+    /// succeeds once the child is done, whether it failed or not
+    Succeeder(Box<BehaviorNode>),
+    /// fails without ticking the child until seconds have passed since the child was last done
+    Cooldown {
+        seconds: f64,
+        ready_at_seconds: f64,
+        child: Box<BehaviorNode>,
+    },
+    Condition(Condition),
+    Action(Action),
+}
+
+impl BehaviorNode {
+    pub fn sequence(children: Vec<BehaviorNode>) -> Self {
+        Self::Sequence {
+            children,
+            current: 0,
+        }
+    }
+
+    pub fn selector(children: Vec<BehaviorNode>) -> Self {
+        Self::Selector {
+            children,
+            current: 0,
+        }
+    }
+
+    pub fn inverter(child: BehaviorNode) -> Self {
+        Self::Inverter(Box::new(child))
+    }
+
+    pub fn succeeder(child: BehaviorNode) -> Self {
+        Self::Succeeder(Box::new(child))
+    }
+
+    pub fn cooldown(seconds: f64, child: BehaviorNode) -> Self {
+        Self::Cooldown {
+            seconds,
+            ready_at_seconds: f64::MIN,
+            child: Box::new(child),
+        }
+    }
+
+    pub fn condition(condition: impl Fn(&AgentContext) -> bool + 'static) -> Self {
+        Self::Condition(Box::new(condition))
+    }
+
+    pub fn action(action: impl FnMut(&mut AgentContext) -> BehaviorStatus + 'static) -> Self {
+        Self::Action(Box::new(action))
+    }
+
+    pub fn tick(&mut self, context: &mut AgentContext) -> BehaviorStatus {
+        match self {
+            Self::Sequence { children, current } => {
+                tick_composite(children, current, context, BehaviorStatus::Success)
+            }
+            Self::Selector { children, current } => {
+                tick_composite(children, current, context, BehaviorStatus::Failure)
+            }
+            Self::Inverter(child) => match child.tick(context) {
+                BehaviorStatus::Success => BehaviorStatus::Failure,
+                BehaviorStatus::Failure => BehaviorStatus::Success,
+                BehaviorStatus::Running => BehaviorStatus::Running,
+            },
+            Self::Succeeder(child) => match child.tick(context) {
+                BehaviorStatus::Running => BehaviorStatus::Running,
+                _ => BehaviorStatus::Success,
+            },
+            Self::Cooldown {
+                seconds,
+                ready_at_seconds,
+                child,
+            } => {
+                let time_seconds = context.time_seconds();
+                if time_seconds < *ready_at_seconds {
+                    return BehaviorStatus::Failure;
+                }
+                let status = child.tick(context);
+                if status != BehaviorStatus::Running {
+                    *ready_at_seconds = time_seconds + *seconds;
+                }
+                status
+            }
+            Self::Condition(condition) => {
+                if condition(context) {
+                    BehaviorStatus::Success
+                } else {
+                    BehaviorStatus::Failure
+                }
+            }
+            Self::Action(action) => action(context),
+        }
+    }
+}
+
+/// keeps ticking children while they return continue_status
+fn tick_composite(
+    children: &mut [BehaviorNode],
+    current: &mut usize,
+    context: &mut AgentContext,
+    continue_status: BehaviorStatus,
+) -> BehaviorStatus {
+    while let Some(child) = children.get_mut(*current) {
+        let status = child.tick(context);
+        if status == BehaviorStatus::Running {
+            return status;
+        }
+        if status != continue_status {
+            *current = 0;
+            return status;
+        }
+        *current += 1;
+    }
+    *current = 0;
+    continue_status
+}
+
+/// The component that makes an entity an AI agent, ticked by tick_behavior_trees
+pub struct BehaviorTree {
+    root: BehaviorNode,
+    pub blackboard: Blackboard,
+}
+
+impl BehaviorTree {
+    pub fn new(root: BehaviorNode) -> Self {
+        Self {
+            root,
+            blackboard: Blackboard::default(),
+        }
+    }
+
+    pub fn tick(
+        &mut self,
+        entity: Entity,
+        engine_state: &mut EngineState,
+        delta_time_seconds: f32,
+    ) -> BehaviorStatus {
+        self.root.tick(&mut AgentContext {
+            entity,
+            engine_state,
+            blackboard: &mut self.blackboard,
+            delta_time_seconds,
+        })
+    }
+}
+
+/// System that ticks the BehaviorTree of every agent, meant for SystemStage::FixedUpdate.
+/// Also updates the agents' Weapon components and sends their WeaponEvents to
+/// EngineState::events
+#[profiling::function]
+pub fn tick_behavior_trees(engine_state: &mut EngineState) {
+    let delta_time_seconds = engine_state.simulation_timestep.timestep_seconds as f32;
+    for entity in engine_state.world.entities_with::<BehaviorTree>() {
+        if let Some(weapon) = engine_state.world.get_mut::<Weapon>(entity) {
+            for event in weapon.update(&mut engine_state.scene, delta_time_seconds) {
+                engine_state.events.send(event);
+            }
+        }
+
+        // taken out so the nodes can borrow the engine state mutably
+        let Some(mut tree) = engine_state.world.remove::<BehaviorTree>(entity) else {
+            continue;
+        };
+        tree.tick(entity, engine_state, delta_time_seconds);
+        if engine_state.world.is_alive(entity) {
+            engine_state.world.insert(entity, tree);
+        }
+    }
+}
+
+/// Walks straight towards the position stored at target_key, see
+/// AgentContext::resolve_position. Agents with a PhysicsBody are moved by setting their
+/// horizontal velocity, others by moving their node. Succeeds once within arrive_distance
+pub fn move_to(target_key: &str, speed: f32, arrive_distance: f32) -> BehaviorNode {
+    let target_key = target_key.to_string();
+    BehaviorNode::action(move |context| {
+        let (Some(position), Some(target)) =
+            (context.position(), context.resolve_position(&target_key))
+        else {
+            return BehaviorStatus::Failure;
+        };
+        let to_target = Vec3::new(target.x - position.x, 0.0, target.z - position.z);
+        let arrived = to_target.length() <= arrive_distance;
+        let velocity = if arrived {
+            Vec3::ZERO
+        } else {
+            to_target.normalize() * speed
+        };
+
+        let engine_state = &mut *context.engine_state;
+        if let Some(PhysicsBody(rigid_body_handle)) =
+            engine_state.world.get::<PhysicsBody>(context.entity)
+        {
+            let Some(rigid_body) = engine_state
+                .physics_state
+                .rigid_body_set
+                .get_mut(*rigid_body_handle)
+            else {
+                return BehaviorStatus::Failure;
+            };
+            let current_linear_velocity = rigid_body.linvel();
+            rigid_body.set_linvel(
+                vector![
+                    velocity.x as f64,
+                    current_linear_velocity.y,
+                    velocity.z as f64
+                ],
+                true,
+            );
+        } else if let Some(SceneNode(node_id)) = engine_state.world.get::<SceneNode>(context.entity)
+        {
+            if let Some(node) = engine_state.scene.get_node_mut(*node_id) {
+                let new_position =
+                    node.transform.position() + velocity * context.delta_time_seconds;
+                node.transform.set_position(new_position);
+            }
+        }
+
+        if arrived {
+            BehaviorStatus::Success
+        } else {
+            BehaviorStatus::Running
+        }
+    })
+}
+
+/// Restarts an animation of the scene and runs until it stops playing. Looping animations
+/// never stop, so it succeeds right away for those
+pub fn play_animation(animation_index: usize) -> BehaviorNode {
+    let mut started = false;
+    BehaviorNode::action(move |context| {
+        let Some(animation) = context
+            .engine_state
+            .scene
+            .animations
+            .get_mut(animation_index)
+        else {
+            return BehaviorStatus::Failure;
+        };
+        if !started {
+            started = true;
+            animation.state.is_playing = true;
+            animation.state.current_time_seconds = 0.0;
+            if animation.state.loop_type != LoopType::Once {
+                started = false;
+                return BehaviorStatus::Success;
+            }
+            return BehaviorStatus::Running;
+        }
+        if animation.state.is_playing {
+            BehaviorStatus::Running
+        } else {
+            started = false;
+            BehaviorStatus::Success
+        }
+    })
+}
+
+/// Fires the agent's Weapon component at the position stored at target_key and sends the
+/// WeaponEvents to EngineState::events. Reloads when the magazine is empty and fails while the
+/// weapon isn't ready
+pub fn fire_weapon(target_key: &str) -> BehaviorNode {
+    let target_key = target_key.to_string();
+    BehaviorNode::action(move |context| {
+        let (Some(origin), Some(target)) =
+            (context.position(), context.resolve_position(&target_key))
+        else {
+            return BehaviorStatus::Failure;
+        };
+        let engine_state = &mut *context.engine_state;
+        let Some(mut weapon) = engine_state.world.remove::<Weapon>(context.entity) else {
+            return BehaviorStatus::Failure;
+        };
+
+        let status = if weapon.ammo_in_magazine() == Some(0) {
+            weapon.reload(&mut engine_state.scene);
+            BehaviorStatus::Failure
+        } else {
+            let events = weapon.fire(engine_state, origin, target - origin);
+            let fired = !events.is_empty();
+            for event in events {
+                engine_state.events.send(event);
+            }
+            if fired {
+                BehaviorStatus::Success
+            } else {
+                BehaviorStatus::Failure
+            }
+        };
+        engine_state.world.insert(context.entity, weapon);
+        status
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn sequence_resumes_at_running_child() {
+        let mut engine_state = EngineState::new_headless();
+        let entity = engine_state.world.spawn();
+
+        let first_ticks = Rc::new(Cell::new(0));
+        let second_ticks = Rc::new(Cell::new(0));
+        let first_ticks_clone = first_ticks.clone();
+        let second_ticks_clone = second_ticks.clone();
+        let mut tree = BehaviorTree::new(BehaviorNode::sequence(vec![
+            BehaviorNode::action(move |_| {
+                first_ticks_clone.set(first_ticks_clone.get() + 1);
+                BehaviorStatus::Success
+            }),
+            BehaviorNode::action(move |_| {
+                second_ticks_clone.set(second_ticks_clone.get() + 1);
+                if second_ticks_clone.get() < 3 {
+                    BehaviorStatus::Running
+                } else {
+                    BehaviorStatus::Success
+                }
+            }),
+        ]));
+
+        let statuses: Vec<_> = (0..3)
+            .map(|_| tree.tick(entity, &mut engine_state, 0.1))
+            .collect();
+
+        assert_eq!(
+            statuses,
+            vec![
+                BehaviorStatus::Running,
+                BehaviorStatus::Running,
+                BehaviorStatus::Success
+            ]
+        );
+        assert_eq!(first_ticks.get(), 1);
+        assert_eq!(second_ticks.get(), 3);
+    }
+}
@@ -11,9 +11,47 @@ pub struct Animation {
     pub length_seconds: f32,
     pub speed: f32,
     pub channels: Vec<Channel>,
+    /// sorted by time
+    pub events: Vec<AnimationEventMarker>,
     pub state: AnimationState,
 }
 
+/// A named moment of an animation, e.g. the frame where a foot touches the ground.
+/// Loaded from the gltf animation's extras: {"events": [{"time": 0.4, "name": "footstep"}]}
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationEventMarker {
+    pub time_seconds: f32,
+    pub name: String,
+}
+
+/// Sent by step_animations when the playback crosses an AnimationEventMarker
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationEvent {
+    /// index into Scene::animations
+    pub animation_index: usize,
+    pub name: String,
+    pub time_seconds: f32,
+}
+
+impl Animation {
+    pub fn add_event(&mut self, time_seconds: f32, name: &str) {
+        let index = self
+            .events
+            .partition_point(|event| event.time_seconds <= time_seconds);
+        self.events.insert(
+            index,
+            AnimationEventMarker {
+                time_seconds,
+                name: name.to_string(),
+            },
+        );
+    }
+
+    pub fn remove_events(&mut self, name: &str) {
+        self.events.retain(|event| event.name != name);
+    }
+}
+
 #[derive(Debug)]
 pub struct Channel {
     pub node_id: GameNodeId,
@@ -56,7 +94,8 @@ struct KeyframeTime {
     time: f32,
 }
 
-pub fn step_animations(scene: &mut Scene, delta_time_seconds: f64) {
+/// Returns the events that were crossed, in the order of the animations
+pub fn step_animations(scene: &mut Scene, delta_time_seconds: f64) -> Vec<AnimationEvent> {
     pub enum Op {
         Translation(Vec3),
         Scale(Vec3),
@@ -64,12 +103,27 @@ pub fn step_animations(scene: &mut Scene, delta_time_seconds: f64) {
     }
 
     let mut ops: Vec<(GameNodeId, Op)> = Vec::new();
-    for animation in scene.animations.iter_mut() {
+    let mut events: Vec<AnimationEvent> = Vec::new();
+    for (animation_index, animation) in scene.animations.iter_mut().enumerate() {
         let state = &mut animation.state;
         if !state.is_playing {
             continue;
         }
+        let previous_time_seconds = state.current_time_seconds;
         state.current_time_seconds += delta_time_seconds as f32 * animation.speed;
+        for event in crossed_events(
+            &animation.events,
+            animation.length_seconds,
+            state.loop_type,
+            previous_time_seconds,
+            state.current_time_seconds,
+        ) {
+            events.push(AnimationEvent {
+                animation_index,
+                name: event.name.clone(),
+                time_seconds: event.time_seconds,
+            });
+        }
         if state.loop_type == LoopType::Once
             && state.current_time_seconds > animation.length_seconds
         {
@@ -135,6 +189,51 @@ pub fn step_animations(scene: &mut Scene, delta_time_seconds: f64) {
             }
         }
     }
+    events
+}
+
+/// The events that the playback goes through in [from_time_seconds, to_time_seconds), where the
+/// times keep growing past the length of the animation like AnimationState::current_time_seconds
+fn crossed_events(
+    events: &[AnimationEventMarker],
+    length_seconds: f32,
+    loop_type: LoopType,
+    from_time_seconds: f32,
+    to_time_seconds: f32,
+) -> Vec<&AnimationEventMarker> {
+    if events.is_empty() || length_seconds <= 0.0 || to_time_seconds <= from_time_seconds {
+        return vec![];
+    }
+    let first_cycle = (from_time_seconds / length_seconds).floor().max(0.0) as u32;
+    let last_cycle = match loop_type {
+        LoopType::Once => 0,
+        _ => (to_time_seconds / length_seconds).floor() as u32,
+    };
+    let mut crossed = vec![];
+    for cycle in first_cycle..=last_cycle {
+        let cycle_start = cycle as f32 * length_seconds;
+        let is_backwards = loop_type == LoopType::PingPong && cycle % 2 == 1;
+        let mut push_event = |event: &AnimationEventMarker| {
+            let event_time = if is_backwards {
+                cycle_start + length_seconds - event.time_seconds
+            } else {
+                cycle_start + event.time_seconds
+            };
+            // a ping pong turns around on the marker, which was already crossed by the end
+            // of the previous cycle
+            let is_turnaround =
+                loop_type == LoopType::PingPong && cycle > 0 && event_time == cycle_start;
+            if !is_turnaround && event_time >= from_time_seconds && event_time < to_time_seconds {
+                crossed.push(event);
+            }
+        };
+        if is_backwards {
+            events.iter().rev().for_each(&mut push_event);
+        } else {
+            events.iter().for_each(&mut push_event);
+        }
+    }
+    crossed
 }
 
 fn get_vec3_at_moment(
@@ -332,3 +431,39 @@ where
         + v_k_1 * (-2.0 * t_3 + 3.0 * t_2)
         + a_k_1 * t_d * (t_3 - t_2)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crossed_events_follow_the_loop_type() {
+        let events = vec![
+            AnimationEventMarker {
+                time_seconds: 0.0,
+                name: "start".to_string(),
+            },
+            AnimationEventMarker {
+                time_seconds: 0.25,
+                name: "footstep".to_string(),
+            },
+        ];
+        let names = |loop_type: LoopType, from: f32, to: f32| {
+            crossed_events(&events, 1.0, loop_type, from, to)
+                .iter()
+                .map(|event| event.name.as_str())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(names(LoopType::Once, 0.0, 0.1), ["start"]);
+        assert_eq!(names(LoopType::Once, 0.1, 0.2), Vec::<&str>::new());
+        assert_eq!(names(LoopType::Once, 0.1, 1.5), ["footstep"]);
+        assert_eq!(
+            names(LoopType::Wrap, 0.5, 2.5),
+            ["start", "footstep", "start", "footstep"]
+        );
+        // on the way back the footstep is at 1.75
+        assert_eq!(names(LoopType::PingPong, 0.5, 1.5), Vec::<&str>::new());
+        assert_eq!(names(LoopType::PingPong, 1.5, 2.1), ["footstep", "start"]);
+    }
+}
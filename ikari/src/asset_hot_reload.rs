@@ -0,0 +1,121 @@
+use crate::asset_loader::{AssetId, AssetLoader, SceneAssetLoadParams};
+use crate::file_watcher::FileWatcher;
+use crate::renderer::{BindedAssetId, BindedSceneData, RendererData};
+use crate::scene::Scene;
+use crate::time::*;
+
+use std::collections::HashMap;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Reloads the gltf scenes whose files changed on disk and swaps their meshes, materials and
+/// textures in place so that artists see their changes without restarting the game. The nodes
+/// aren't touched, so a new version that adds or removes meshes is skipped with an error,
+/// see RendererData::replace_binded_asset. Does nothing on the web
+#[derive(Debug)]
+pub struct AssetHotReloader {
+    /// the params each asset was loaded with
+    assets: HashMap<BindedAssetId, SceneAssetLoadParams>,
+    /// the root file of each asset and the external buffers and images it references
+    file_watcher: FileWatcher<BindedAssetId>,
+    /// asset loader id of each reload in progress -> the asset it replaces
+    pending_reloads: HashMap<AssetId, BindedAssetId>,
+    pub enabled: bool,
+}
+
+impl Default for AssetHotReloader {
+    fn default() -> Self {
+        Self {
+            assets: HashMap::new(),
+            file_watcher: FileWatcher::new(POLL_INTERVAL),
+            pending_reloads: HashMap::new(),
+            enabled: cfg!(debug_assertions),
+        }
+    }
+}
+
+impl AssetHotReloader {
+    /// params are the ones the scene was loaded with and binded_asset_id comes from
+    /// Scene::merge_scene
+    pub fn watch(&mut self, binded_asset_id: BindedAssetId, params: SceneAssetLoadParams) {
+        self.unwatch(binded_asset_id);
+        #[cfg(not(target_arch = "wasm32"))]
+        self.file_watcher
+            .watch(binded_asset_id, watched_files(&params));
+        self.assets.insert(binded_asset_id, params);
+    }
+
+    /// e.g. before the asset is unloaded
+    pub fn unwatch(&mut self, binded_asset_id: BindedAssetId) {
+        self.assets.remove(&binded_asset_id);
+        self.file_watcher.unwatch(&binded_asset_id);
+        self.pending_reloads
+            .retain(|_, pending_asset_id| *pending_asset_id != binded_asset_id);
+    }
+
+    /// Starts reloading the assets whose files changed and swaps in the ones that finished
+    /// loading. loaded_scenes comes from AssetBinder::loaded_scenes
+    #[profiling::function]
+    pub fn update(
+        &mut self,
+        asset_loader: &AssetLoader,
+        renderer_data: &mut RendererData,
+        loaded_scenes: &mut HashMap<AssetId, (Scene, BindedSceneData)>,
+    ) {
+        self.pending_reloads
+            .retain(|loader_asset_id, binded_asset_id| {
+                let Some((_, render_buffers)) = loaded_scenes.remove(loader_asset_id) else {
+                    return true;
+                };
+                match renderer_data.replace_binded_asset(*binded_asset_id, render_buffers) {
+                    Ok(()) => log::info!("Hot reloaded asset {binded_asset_id:?}"),
+                    Err(err) => log::error!("Error hot reloading asset {binded_asset_id:?}: {err}"),
+                }
+                false
+            });
+
+        if !self.enabled {
+            return;
+        }
+
+        for binded_asset_id in self.file_watcher.poll() {
+            let Some(params) = self.assets.get(&binded_asset_id) else {
+                continue;
+            };
+            log::info!(
+                "{:?} changed on disk, reloading it",
+                params.path.relative_path
+            );
+            let loader_asset_id = asset_loader.load_gltf_scene(params.clone());
+            self.pending_reloads
+                .insert(loader_asset_id, binded_asset_id);
+        }
+    }
+}
+
+/// The external buffers and images of a .gltf file sit next to it, the .glb and .fbx files
+/// usually embed everything
+#[cfg(not(target_arch = "wasm32"))]
+fn watched_files(params: &SceneAssetLoadParams) -> Vec<std::path::PathBuf> {
+    let root_path = params.path.resolve();
+    let mut paths = vec![root_path.clone()];
+    let Ok(gltf) = gltf::Gltf::open(&root_path) else {
+        return paths;
+    };
+    let base_dir = root_path.parent().unwrap_or(std::path::Path::new(""));
+    let buffer_uris = gltf.buffers().filter_map(|buffer| match buffer.source() {
+        gltf::buffer::Source::Uri(uri) => Some(uri),
+        gltf::buffer::Source::Bin => None,
+    });
+    let image_uris = gltf.images().filter_map(|image| match image.source() {
+        gltf::image::Source::Uri { uri, .. } => Some(uri),
+        gltf::image::Source::View { .. } => None,
+    });
+    paths.extend(
+        buffer_uris
+            .chain(image_uris)
+            .filter(|uri| !uri.starts_with("data:"))
+            .map(|uri| base_dir.join(uri)),
+    );
+    paths
+}
@@ -1,8 +1,11 @@
 use crate::audio::*;
 use crate::buffer::*;
+use crate::collider_generation::*;
+use crate::effects::*;
 use crate::file_manager::FileManager;
 use crate::file_manager::GameFilePath;
 use crate::gltf_loader::*;
+use crate::ibl_cache::IblCache;
 use crate::mesh::*;
 use crate::renderer::*;
 use crate::sampler_cache::*;
@@ -23,13 +26,16 @@ use std::collections::hash_map::Entry;
 use std::sync::{Arc, Mutex};
 
 #[derive(Default, Copy, Clone, Debug, Hash, PartialEq, Eq)]
-pub struct AssetId(u64);
+pub struct AssetId(pub(crate) u64);
 
 #[derive(Clone, Debug)]
 pub struct SceneAssetLoadParams {
     pub path: GameFilePath,
     /// generates wireframe counterparts, making all meshes renderable in wireframe mode
     pub generate_wireframe_meshes: bool,
+    /// prepares the collision data needed by collider_generation::generate_node_colliders.
+    /// only ConvexHull and TriangleMesh need extra data, the primitives are fitted to the bounding boxes
+    pub generate_colliders: Option<ColliderGenerationMode>,
 }
 
 #[derive(Clone, Debug)]
@@ -47,10 +53,15 @@ pub struct AssetLoader {
 
     audio_manager: Arc<Mutex<AudioManager>>,
     pending_scenes: Arc<Mutex<Vec<(AssetId, SceneAssetLoadParams)>>>,
+    /// 0..1 for each scene that's on its way to AssetBinder::loaded_scenes
+    scene_load_progress: Arc<Mutex<HashMap<AssetId, f32>>>,
     bindable_scenes: Arc<Mutex<HashMap<AssetId, (Scene, BindableSceneData)>>>,
 
     pending_skyboxes: Arc<Mutex<Vec<(AssetId, SkyboxPaths)>>>,
     bindable_skyboxes: Arc<Mutex<HashMap<AssetId, BindableSkybox>>>,
+
+    pending_effects: Arc<Mutex<Vec<(AssetId, GameFilePath)>>>,
+    pub loaded_effects: Arc<Mutex<HashMap<AssetId, EffectFile>>>,
 }
 
 pub struct AssetBinder {
@@ -75,7 +86,7 @@ trait BindScene: WasmNotSend + WasmNotSync {
         -> WasmNotArc<WasmNotMutex<HashMap<AssetId, (Scene, BindedSceneData)>>>;
 }
 
-type BindGroupCache = HashMap<IndexedPbrTextures, WasmNotArc<wgpu::BindGroup>>;
+pub(crate) type BindGroupCache = HashMap<IndexedPbrTextures, WasmNotArc<wgpu::BindGroup>>;
 
 struct TimeSlicedSceneBinder {
     staged_scenes: WasmNotMutex<HashMap<AssetId, BindedSceneData>>,
@@ -103,6 +114,7 @@ impl AssetLoader {
             next_asset_id: Arc::new(Mutex::new(AssetId(0))),
 
             pending_scenes: Arc::new(Mutex::new(Vec::new())),
+            scene_load_progress: Arc::new(Mutex::new(HashMap::new())),
             bindable_scenes: Arc::new(Mutex::new(HashMap::new())),
             audio_manager,
             pending_audio: Arc::new(Mutex::new(Vec::new())),
@@ -110,6 +122,9 @@ impl AssetLoader {
 
             pending_skyboxes: Arc::new(Mutex::new(Vec::new())),
             bindable_skyboxes: Arc::new(Mutex::new(HashMap::new())),
+
+            pending_effects: Arc::new(Mutex::new(Vec::new())),
+            loaded_effects: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
@@ -122,15 +137,19 @@ impl AssetLoader {
         next_asset_id
     }
 
+    /// loads a gltf scene, or an fbx scene if the path has the .fbx extension
+    /// and the fbx feature is enabled
     pub fn load_gltf_scene(&self, params: SceneAssetLoadParams) -> AssetId {
         let asset_id = self.next_asset_id();
 
         let pending_scenes = self.pending_scenes.clone();
         let mut pending_scenes_guard = pending_scenes.lock().unwrap();
         pending_scenes_guard.push((asset_id, params));
+        set_scene_load_progress(&self.scene_load_progress, asset_id, 0.0);
 
         if pending_scenes_guard.len() == 1 {
             let pending_scenes = self.pending_scenes.clone();
+            let scene_load_progress = self.scene_load_progress.clone();
             let bindable_scenes = self.bindable_scenes.clone();
 
             crate::thread::spawn(move || {
@@ -150,12 +169,23 @@ impl AssetLoader {
                                 profiling::scope!("Read root file");
                                 gltf_slice = FileManager::read(&next_scene_params.path).await?;
                             }
+                            set_scene_load_progress(&scene_load_progress, next_scene_id, 0.2);
+
+                            #[cfg(feature = "fbx")]
+                            if is_fbx_path(&next_scene_params.path) {
+                                return crate::fbx_loader::build_scene(
+                                    &gltf_slice,
+                                    next_scene_params.clone(),
+                                )
+                                .await;
+                            }
 
                             let (document, buffers, images);
                             {
                                 profiling::scope!("Parse root & children");
                                 (document, buffers, images) = gltf::import_slice(&gltf_slice)?;
                             }
+                            set_scene_load_progress(&scene_load_progress, next_scene_id, 0.5);
                             let (other_scene, other_scene_bindable_data) = build_scene(
                                 (&document, &buffers, &images),
                                 next_scene_params.clone(),
@@ -165,13 +195,22 @@ impl AssetLoader {
                             anyhow::Ok((other_scene, other_scene_bindable_data))
                         };
                         match do_load().await {
-                            Ok(result) => {
+                            Ok(mut result) => {
+                                if let Some(mode) = next_scene_params.generate_colliders {
+                                    generate_collision_meshes(&mut result.1, mode);
+                                }
+                                set_scene_load_progress(
+                                    &scene_load_progress,
+                                    next_scene_id,
+                                    SCENE_BIND_PROGRESS,
+                                );
                                 let _replaced_ignored = bindable_scenes
                                     .lock()
                                     .unwrap()
                                     .insert(next_scene_id, result);
                             }
                             Err(err) => {
+                                scene_load_progress.lock().unwrap().remove(&next_scene_id);
                                 log::error!(
                                     "Error loading scene asset {:?}: {}\n{}",
                                     next_scene_params.path,
@@ -188,6 +227,16 @@ impl AssetLoader {
         asset_id
     }
 
+    /// How far along the scene is, 0..1. None once it's in AssetBinder::loaded_scenes or
+    /// if it failed to load
+    pub fn scene_load_progress(&self, asset_id: AssetId) -> Option<f32> {
+        self.scene_load_progress
+            .lock()
+            .unwrap()
+            .get(&asset_id)
+            .copied()
+    }
+
     pub fn load_audio(&self, params: AudioAssetLoadParams) -> AssetId {
         let asset_id = self.next_asset_id();
 
@@ -326,11 +375,22 @@ impl AssetLoader {
                     last_buffer_fill_time = Some(Instant::now());
 
                     if reached_end_of_stream {
-                        log::info!(
-                            "Reached end of stream for file: {:?}",
-                            audio_file_streamer.file_path(),
-                        );
-                        break;
+                        if !audio_manager.lock().unwrap().sound_is_looping(sound_index) {
+                            log::info!(
+                                "Reached end of stream for file: {:?}",
+                                audio_file_streamer.file_path(),
+                            );
+                            break;
+                        }
+                        // the start of the track is written right after its end so that
+                        // there's no gap
+                        if let Err(err) = audio_file_streamer.rewind() {
+                            log::error!(
+                                "Error looping audio stream {:?}: {err:?}",
+                                audio_file_streamer.file_path(),
+                            );
+                            break;
+                        }
                     }
 
                     #[cfg(not(target_arch = "wasm32"))]
@@ -400,6 +460,52 @@ impl AssetLoader {
 
         asset_id
     }
+
+    /// loads a RON file of effect definitions, see [`EffectLibrary`]
+    pub fn load_effect_file(&self, path: GameFilePath) -> AssetId {
+        let asset_id = self.next_asset_id();
+        self.reload_effect_file(asset_id, path);
+        asset_id
+    }
+
+    /// the reloaded file replaces the previous one with the same id once it's
+    /// picked up by [`EffectLibrary::update`]. if it fails validation, the
+    /// previous version is kept
+    pub fn reload_effect_file(&self, asset_id: AssetId, path: GameFilePath) {
+        let mut pending_effects_guard = self.pending_effects.lock().unwrap();
+        pending_effects_guard.push((asset_id, path));
+
+        if pending_effects_guard.len() == 1 {
+            let pending_effects = self.pending_effects.clone();
+            let loaded_effects = self.loaded_effects.clone();
+
+            crate::thread::spawn(move || {
+                profiling::register_thread!("Effect loader");
+                crate::block_on(async move {
+                    while pending_effects.lock().unwrap().len() > 0 {
+                        let (next_effect_id, next_effect_path) =
+                            pending_effects.lock().unwrap().remove(0);
+
+                        match EffectFile::load(next_effect_path.clone()).await {
+                            Ok(result) => {
+                                let _replaced_ignored = loaded_effects
+                                    .lock()
+                                    .unwrap()
+                                    .insert(next_effect_id, result);
+                            }
+                            Err(err) => {
+                                log::error!(
+                                    "Error loading effect asset {:?}: {}",
+                                    next_effect_path.relative_path,
+                                    err,
+                                );
+                            }
+                        }
+                    }
+                });
+            });
+        }
+    }
 }
 
 impl AssetBinder {
@@ -610,51 +716,6 @@ impl ThreadedSceneBinder {
             bind_group_caches: Arc::new(Mutex::new(HashMap::new())),
         }
     }
-
-    fn bind_scene(
-        base_renderer: &BaseRenderer,
-        renderer_constant_data: &RendererConstantData,
-        bind_group_cache: &mut BindGroupCache,
-        bindable_scene: BindableSceneData,
-    ) -> Result<BindedSceneData> {
-        let mut textures: Vec<Texture> = Vec::with_capacity(bindable_scene.textures.len());
-        for bindable_texture in bindable_scene.textures.iter() {
-            textures.push(bind_texture(base_renderer, bindable_texture)?);
-        }
-
-        let mut binded_meshes: Vec<BindedGeometryBuffers> =
-            Vec::with_capacity(bindable_scene.bindable_meshes.len());
-        let mut binded_pbr_materials: Vec<BindedPbrMaterial> =
-            Vec::with_capacity(bindable_scene.bindable_pbr_materials.len());
-        for bindable_mesh in bindable_scene.bindable_meshes.iter() {
-            binded_meshes.push(bind_mesh(base_renderer, bindable_mesh)?);
-        }
-
-        for bindable_pbr_material in bindable_scene.bindable_pbr_materials.iter() {
-            binded_pbr_materials.push(bind_pbr_material(
-                base_renderer,
-                renderer_constant_data,
-                &textures,
-                bind_group_cache,
-                bindable_pbr_material,
-            )?);
-        }
-
-        bind_group_cache.clear();
-
-        let mut binded_wireframe_meshes: Vec<BindedWireframeMesh> =
-            Vec::with_capacity(bindable_scene.bindable_wireframe_meshes.len());
-        for wireframe_mesh in bindable_scene.bindable_wireframe_meshes.iter() {
-            binded_wireframe_meshes.push(bind_wireframe_mesh(base_renderer, wireframe_mesh)?);
-        }
-
-        Ok(BindedSceneData {
-            binded_meshes,
-            binded_wireframe_meshes,
-            binded_pbr_materials,
-            textures,
-        })
-    }
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -667,6 +728,7 @@ impl BindScene for ThreadedSceneBinder {
     ) {
         let loaded_scenes_clone = self.loaded_scenes.clone();
         let bind_group_caches_clone = self.bind_group_caches.clone();
+        let scene_load_progress = asset_loader.scene_load_progress.clone();
 
         let mut bindable_scenes: Vec<_> = vec![];
         {
@@ -685,7 +747,7 @@ impl BindScene for ThreadedSceneBinder {
                 let mut bind_group_caches_guard = bind_group_caches_clone.lock().unwrap();
                 let bind_group_cache = bind_group_caches_guard.entry(scene_id).or_default();
 
-                let binded_scene_result = Self::bind_scene(
+                let binded_scene_result = bind_scene_with_cache(
                     &base_renderer.clone(),
                     &renderer_constant_data.clone(),
                     bind_group_cache,
@@ -707,6 +769,8 @@ impl BindScene for ThreadedSceneBinder {
                         );
                     }
                 }
+                // after the scene is inserted so it always has either a progress or a result
+                scene_load_progress.lock().unwrap().remove(&scene_id);
             }
         });
     }
@@ -748,6 +812,7 @@ impl TimeSlicedSceneBinder {
                         bindable_scene.bindable_pbr_materials.len(),
                     ),
                     textures: Vec::with_capacity(bindable_scene.textures.len()),
+                    cpu_data: None,
                 });
 
             let staged_texture_count = staged_scene.textures.len();
@@ -843,18 +908,50 @@ impl BindScene for TimeSlicedSceneBinder {
                     &bindable_scene,
                 );
                 match binded_scene_result {
-                    Ok(Some(result)) => {
+                    Ok(Some(mut result)) => {
+                        if base_renderer.config.retain_cpu_data {
+                            result.cpu_data = Some(bindable_scene);
+                        }
                         let _replaced_ignored = loaded_scenes_clone
                             .lock()
                             .unwrap()
                             .insert(scene_id, (scene, result));
+                        asset_loader
+                            .scene_load_progress
+                            .lock()
+                            .unwrap()
+                            .remove(&scene_id);
                         break;
                     }
                     Ok(None) => {
+                        if let Some(staged_scene) =
+                            self.staged_scenes.lock().unwrap().get(&scene_id)
+                        {
+                            let staged_count = staged_scene.textures.len()
+                                + staged_scene.binded_meshes.len()
+                                + staged_scene.binded_wireframe_meshes.len()
+                                + staged_scene.binded_pbr_materials.len();
+                            let total_count = bindable_scene.textures.len()
+                                + bindable_scene.bindable_meshes.len()
+                                + bindable_scene.bindable_wireframe_meshes.len()
+                                + bindable_scene.bindable_pbr_materials.len();
+                            set_scene_load_progress(
+                                &asset_loader.scene_load_progress,
+                                scene_id,
+                                SCENE_BIND_PROGRESS
+                                    + (1.0 - SCENE_BIND_PROGRESS) * staged_count as f32
+                                        / total_count.max(1) as f32,
+                            );
+                        }
                         // not done binding this scene yet, add it back to the map to be processed next frame
                         bindable_scenes_guard.insert(scene_id, (scene, bindable_scene));
                     }
                     Err(err) => {
+                        asset_loader
+                            .scene_load_progress
+                            .lock()
+                            .unwrap()
+                            .remove(&scene_id);
                         log::error!(
                             "Error loading asset {:?}: {}\n{}",
                             scene_id,
@@ -880,6 +977,28 @@ impl BindScene for TimeSlicedSceneBinder {
     }
 }
 
+/// The part of a scene's progress that's done once it's ready to be uploaded to the gpu
+const SCENE_BIND_PROGRESS: f32 = 0.8;
+
+fn set_scene_load_progress(
+    scene_load_progress: &Mutex<HashMap<AssetId, f32>>,
+    asset_id: AssetId,
+    progress: f32,
+) {
+    scene_load_progress
+        .lock()
+        .unwrap()
+        .insert(asset_id, progress);
+}
+
+#[cfg(feature = "fbx")]
+fn is_fbx_path(path: &GameFilePath) -> bool {
+    path.relative_path
+        .extension()
+        .map(|extension| extension.eq_ignore_ascii_case("fbx"))
+        .unwrap_or(false)
+}
+
 pub fn bind_skybox(
     base_renderer: &BaseRenderer,
     renderer_constant_data: &RendererConstantData,
@@ -948,32 +1067,46 @@ pub fn bind_skybox(
     let (diffuse_environment_map, specular_environment_map) = match bindable_skybox.environment_hdr
     {
         Some(BindableSkyboxHDREnvironment::Equirectangular(image)) => {
-            let er_hdr_env_texture = Texture::from_decoded_image(
-                base_renderer,
-                &image,
-                None,
-                Some(wgpu::TextureFormat::Rgba16Float),
-                false,
-                &SamplerDescriptor {
-                    address_mode_u: wgpu::AddressMode::ClampToEdge,
-                    address_mode_v: wgpu::AddressMode::ClampToEdge,
-                    address_mode_w: wgpu::AddressMode::ClampToEdge,
-                    mag_filter: wgpu::FilterMode::Linear,
-                    min_filter: wgpu::FilterMode::Linear,
-                    mipmap_filter: wgpu::FilterMode::Nearest,
-                    ..Default::default()
-                },
-            )?;
-
-            let hdr_env_texture = Texture::create_cubemap_from_equirectangular(
-                base_renderer,
-                renderer_constant_data,
-                wgpu::TextureFormat::Rgba16Float,
-                None,
-                &er_hdr_env_texture,
-            )?;
-
-            generate_diffuse_and_specular_maps(&hdr_env_texture)
+            let ibl_cache = IblCache::from_config(&base_renderer.config);
+            let cache_key = IblCache::source_key(&image.raw);
+            match ibl_cache
+                .as_ref()
+                .and_then(|ibl_cache| ibl_cache.load_env_maps(base_renderer, &cache_key))
+            {
+                Some(env_maps) => env_maps,
+                None => {
+                    let er_hdr_env_texture = Texture::from_decoded_image(
+                        base_renderer,
+                        &image,
+                        None,
+                        Some(wgpu::TextureFormat::Rgba16Float),
+                        false,
+                        &SamplerDescriptor {
+                            address_mode_u: wgpu::AddressMode::ClampToEdge,
+                            address_mode_v: wgpu::AddressMode::ClampToEdge,
+                            address_mode_w: wgpu::AddressMode::ClampToEdge,
+                            mag_filter: wgpu::FilterMode::Linear,
+                            min_filter: wgpu::FilterMode::Linear,
+                            mipmap_filter: wgpu::FilterMode::Nearest,
+                            ..Default::default()
+                        },
+                    )?;
+
+                    let hdr_env_texture = Texture::create_cubemap_from_equirectangular(
+                        base_renderer,
+                        renderer_constant_data,
+                        wgpu::TextureFormat::Rgba16Float,
+                        None,
+                        &er_hdr_env_texture,
+                    )?;
+
+                    let env_maps = generate_diffuse_and_specular_maps(&hdr_env_texture);
+                    if let Some(ibl_cache) = &ibl_cache {
+                        ibl_cache.save_env_maps(base_renderer, &cache_key, &env_maps);
+                    }
+                    env_maps
+                }
+            }
         }
         Some(BindableSkyboxHDREnvironment::ProcessedCube { diffuse, specular }) => (
             Texture::create_cubemap(
@@ -1049,7 +1182,71 @@ impl BindSkybox for TimeSlicedSkyboxBinder {
     }
 }
 
-fn bind_texture(
+/// Uploads a scene to the gpu right away on the calling thread, e.g. for tools that render
+/// offscreen and don't run the asset loader
+pub fn bind_scene(
+    base_renderer: &BaseRenderer,
+    renderer_constant_data: &RendererConstantData,
+    bindable_scene: BindableSceneData,
+) -> Result<BindedSceneData> {
+    bind_scene_with_cache(
+        base_renderer,
+        renderer_constant_data,
+        &mut BindGroupCache::default(),
+        bindable_scene,
+    )
+}
+
+fn bind_scene_with_cache(
+    base_renderer: &BaseRenderer,
+    renderer_constant_data: &RendererConstantData,
+    bind_group_cache: &mut BindGroupCache,
+    bindable_scene: BindableSceneData,
+) -> Result<BindedSceneData> {
+    let mut textures: Vec<Texture> = Vec::with_capacity(bindable_scene.textures.len());
+    for bindable_texture in bindable_scene.textures.iter() {
+        textures.push(bind_texture(base_renderer, bindable_texture)?);
+    }
+
+    let mut binded_meshes: Vec<BindedGeometryBuffers> =
+        Vec::with_capacity(bindable_scene.bindable_meshes.len());
+    let mut binded_pbr_materials: Vec<BindedPbrMaterial> =
+        Vec::with_capacity(bindable_scene.bindable_pbr_materials.len());
+    for bindable_mesh in bindable_scene.bindable_meshes.iter() {
+        binded_meshes.push(bind_mesh(base_renderer, bindable_mesh)?);
+    }
+
+    for bindable_pbr_material in bindable_scene.bindable_pbr_materials.iter() {
+        binded_pbr_materials.push(bind_pbr_material(
+            base_renderer,
+            renderer_constant_data,
+            &textures,
+            bind_group_cache,
+            bindable_pbr_material,
+        )?);
+    }
+
+    bind_group_cache.clear();
+
+    let mut binded_wireframe_meshes: Vec<BindedWireframeMesh> =
+        Vec::with_capacity(bindable_scene.bindable_wireframe_meshes.len());
+    for wireframe_mesh in bindable_scene.bindable_wireframe_meshes.iter() {
+        binded_wireframe_meshes.push(bind_wireframe_mesh(base_renderer, wireframe_mesh)?);
+    }
+
+    Ok(BindedSceneData {
+        binded_meshes,
+        binded_wireframe_meshes,
+        binded_pbr_materials,
+        textures,
+        cpu_data: base_renderer
+            .config
+            .retain_cpu_data
+            .then_some(bindable_scene),
+    })
+}
+
+pub(crate) fn bind_texture(
     base_renderer: &BaseRenderer,
     bindable_texture: &BindableTexture,
 ) -> Result<Texture> {
@@ -1059,6 +1256,24 @@ fn bind_texture(
         format,
         sampler_descriptor,
     } = bindable_texture;
+
+    let resolution_bias = base_renderer
+        .sampler_cache
+        .lock()
+        .unwrap()
+        .texture_quality()
+        .resolution_bias;
+    let biased_raw_image = sampler_descriptor
+        .use_texture_quality
+        .then(|| {
+            raw_image.drop_top_mips(
+                format.unwrap_or(wgpu::TextureFormat::Rgba8UnormSrgb),
+                resolution_bias,
+            )
+        })
+        .flatten();
+    let raw_image = biased_raw_image.as_ref().unwrap_or(raw_image);
+
     Texture::from_decoded_image(
         base_renderer,
         raw_image,
@@ -1069,7 +1284,7 @@ fn bind_texture(
     )
 }
 
-fn bind_mesh(
+pub(crate) fn bind_mesh(
     base_renderer: &BaseRenderer,
     mesh: &BindableGeometryBuffers,
 ) -> Result<BindedGeometryBuffers> {
@@ -1093,12 +1308,13 @@ fn bind_mesh(
         vertex_buffer,
         index_buffer: bind_index_buffer(base_renderer, &mesh.indices)?,
         bounding_box: mesh.bounding_box,
+        collision_mesh: mesh.collision_mesh.clone(),
     };
 
     Ok(geometry_buffers)
 }
 
-fn bind_pbr_material(
+pub(crate) fn bind_pbr_material(
     base_renderer: &BaseRenderer,
     renderer_constant_data: &RendererConstantData,
     textures: &[Texture],
@@ -1118,6 +1334,7 @@ fn bind_pbr_material(
                 emissive: get_texture(material.textures.emissive),
                 ambient_occlusion: get_texture(material.textures.ambient_occlusion),
                 metallic_roughness: get_texture(material.textures.metallic_roughness),
+                lightmap: get_texture(material.textures.lightmap),
             };
             let textures_bind_group = WasmNotArc::new(Renderer::make_pbr_textures_bind_group(
                 base_renderer,
@@ -1133,6 +1350,7 @@ fn bind_pbr_material(
     Ok(BindedPbrMaterial {
         textures_bind_group,
         dynamic_pbr_params: material.dynamic_pbr_params,
+        texture_indices: Some(material.textures.clone()),
     })
 }
 
@@ -1146,7 +1364,7 @@ fn bind_wireframe_mesh(
     })
 }
 
-fn bind_index_buffer(
+pub(crate) fn bind_index_buffer(
     base_renderer: &BaseRenderer,
     indices: &BindableIndices,
 ) -> Result<BindedIndexBuffer> {
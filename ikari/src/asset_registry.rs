@@ -0,0 +1,149 @@
+use std::ops::Range;
+
+/// A mesh in RendererData::binded_meshes. Its slot gets a new generation when the asset it
+/// belongs to is unloaded, so a handle that outlives it can be told apart from the mesh that
+/// reuses the slot, see AssetRegistry::is_mesh_alive
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct MeshHandle {
+    index: usize,
+    generation: u32,
+}
+
+/// A material in RendererData::binded_pbr_materials, see MeshHandle
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct MaterialHandle {
+    index: usize,
+    generation: u32,
+}
+
+/// A texture in RendererData::textures, see MeshHandle
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TextureHandle {
+    index: usize,
+    generation: u32,
+}
+
+impl MeshHandle {
+    /// For the scenes that aren't binded yet, whose meshes are indexed in their own
+    /// BindableSceneData. Scene::bind_render_buffers turns them into real handles
+    pub(crate) fn unbinded(index: usize) -> Self {
+        Self {
+            index,
+            generation: 0,
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl MaterialHandle {
+    /// See MeshHandle::unbinded
+    pub(crate) fn unbinded(index: usize) -> Self {
+        Self {
+            index,
+            generation: 0,
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl TextureHandle {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+/// The current generation of every slot of the RendererData lists. The renderer skips the
+/// nodes whose handles aren't alive anymore
+#[derive(Debug, Default)]
+pub struct AssetRegistry {
+    mesh_generations: Vec<u32>,
+    material_generations: Vec<u32>,
+    texture_generations: Vec<u32>,
+}
+
+impl AssetRegistry {
+    /// The handle of whatever is in the slot right now, e.g. right after it was binded
+    pub fn mesh(&mut self, index: usize) -> MeshHandle {
+        MeshHandle {
+            index,
+            generation: current_generation(&mut self.mesh_generations, index),
+        }
+    }
+
+    pub fn material(&mut self, index: usize) -> MaterialHandle {
+        MaterialHandle {
+            index,
+            generation: current_generation(&mut self.material_generations, index),
+        }
+    }
+
+    pub fn texture(&mut self, index: usize) -> TextureHandle {
+        TextureHandle {
+            index,
+            generation: current_generation(&mut self.texture_generations, index),
+        }
+    }
+
+    pub fn is_mesh_alive(&self, mesh: MeshHandle) -> bool {
+        self.mesh_generations.get(mesh.index) == Some(&mesh.generation)
+    }
+
+    pub fn is_material_alive(&self, material: MaterialHandle) -> bool {
+        self.material_generations.get(material.index) == Some(&material.generation)
+    }
+
+    pub fn is_texture_alive(&self, texture: TextureHandle) -> bool {
+        self.texture_generations.get(texture.index) == Some(&texture.generation)
+    }
+
+    /// Invalidates the handles of the freed resources
+    pub(crate) fn free(
+        &mut self,
+        meshes: Range<usize>,
+        materials: Range<usize>,
+        textures: Range<usize>,
+    ) {
+        for (generations, range) in [
+            (&mut self.mesh_generations, meshes),
+            (&mut self.material_generations, materials),
+            (&mut self.texture_generations, textures),
+        ] {
+            for generation in generations.iter_mut().take(range.end).skip(range.start) {
+                *generation += 1;
+            }
+        }
+    }
+}
+
+fn current_generation(generations: &mut Vec<u32>, index: usize) -> u32 {
+    if generations.len() <= index {
+        generations.resize(index + 1, 0);
+    }
+    generations[index]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn freed_handles_are_dead_once_the_slot_is_reused() {
+        let mut registry = AssetRegistry::default();
+        let old_mesh = registry.mesh(3);
+        let other_mesh = registry.mesh(1);
+        assert!(registry.is_mesh_alive(old_mesh));
+
+        registry.free(2..4, 0..0, 0..0);
+        let new_mesh = registry.mesh(3);
+        assert!(!registry.is_mesh_alive(old_mesh));
+        assert!(registry.is_mesh_alive(new_mesh));
+        assert!(registry.is_mesh_alive(other_mesh));
+        assert_ne!(old_mesh, new_mesh);
+    }
+}
@@ -1,12 +1,21 @@
+use crate::audio_mixer::{
+    BusParams, BusSettings, BusSignal, MixerSettings, MASTER_BUS, MUSIC_BUS, SFX_BUS, VOICE_BUS,
+};
 use crate::file_manager::GameFilePath;
+use crate::scene::{GameNodeId, Scene};
+use crate::sound_cue::SoundCues;
 use crate::time::Instant;
+use crate::transform::Transform;
+
+use std::collections::HashMap;
+use std::sync::Arc;
 
 use anyhow::Result;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use glam::f32::Vec3;
+use glam::f32::{Quat, Vec3};
 use oddio::{
-    FixedGain, FramesSignal, Gain, Handle, Mixer, SpatialBuffered, SpatialOptions, SpatialScene,
-    Stop,
+    Cycle, FixedGain, FramesSignal, Gain, Handle, Mixer, Seek, Signal, SpatialBuffered,
+    SpatialOptions, SpatialScene, Stop,
 };
 use symphonia::core::{
     audio::SampleBuffer, codecs::CODEC_TYPE_NULL, io::MediaSource, io::MediaSourceStream,
@@ -14,17 +23,93 @@ use symphonia::core::{
 };
 
 pub struct AudioStreams {
-    _spatial_scene_output_stream: cpal::Stream,
-    _mixer_output_stream: cpal::Stream,
+    _output_stream: Option<cpal::Stream>,
+}
+
+/// A group of sounds that share a volume and effects. Spatial sounds go through the bus's
+/// spatial scene, which is mixed in with the other sounds
+struct AudioBus {
+    mixer_handle: Handle<Mixer<[f32; 2]>>,
+    spatial_scene_handle: Handle<SpatialScene>,
+    params: Arc<BusParams>,
+    settings: BusSettings,
+}
+
+impl AudioBus {
+    /// The returned signal plays the bus with its effects applied
+    fn new(settings: BusSettings) -> (Self, impl Signal<Frame = [f32; 2]> + Send + 'static) {
+        let (mut mixer_handle, mixer) = oddio::split(Mixer::new());
+        let (spatial_scene_handle, spatial_scene) = oddio::split(SpatialScene::new());
+        mixer_handle.control::<Mixer<_>, _>().play(spatial_scene);
+        let params = Arc::new(BusParams::new(&settings));
+        (
+            Self {
+                mixer_handle,
+                spatial_scene_handle,
+                params: params.clone(),
+                settings,
+            },
+            BusSignal::new(mixer, params),
+        )
+    }
 }
 
 pub struct AudioManager {
     master_volume: f32,
     device_sample_rate: u32,
 
-    spatial_scene_handle: Handle<SpatialScene>,
-    mixer_handle: Handle<Mixer<[f32; 2]>>,
+    buses: HashMap<String, AudioBus>,
     sounds: Vec<Option<Sound>>,
+    /// None until the first update_spatial_audio
+    listener: Option<AudioListener>,
+    /// sounds that follow a scene node, by sound index
+    attached_sounds: HashMap<usize, AttachedSound>,
+    pub sound_cues: SoundCues,
+}
+
+/// Where the spatial sounds are heard from, usually the active camera.
+/// oddio's listener is at the origin facing -z, so the sounds are moved into its space
+#[derive(Debug, Copy, Clone)]
+struct AudioListener {
+    position: Vec3,
+    rotation: Quat,
+    velocity: Vec3,
+}
+
+impl Default for AudioListener {
+    fn default() -> Self {
+        Self {
+            position: Vec3::ZERO,
+            rotation: Quat::IDENTITY,
+            velocity: Vec3::ZERO,
+        }
+    }
+}
+
+impl AudioListener {
+    fn to_listener_space(&self, position: Vec3, velocity: Vec3) -> (Vec3, Vec3) {
+        let inverse_rotation = self.rotation.inverse();
+        (
+            inverse_rotation * (position - self.position),
+            // the doppler shift comes from the velocity relative to the listener
+            inverse_rotation * (velocity - self.velocity),
+        )
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct AttachedSound {
+    node_id: GameNodeId,
+    last_position: Option<Vec3>,
+}
+
+/// world space motion of a spatial sound
+#[derive(Debug, Copy, Clone)]
+struct SpatialMotion {
+    position: Vec3,
+    velocity: Vec3,
+    /// jump to the position instead of moving there, e.g. after a teleport
+    discontinuity: bool,
 }
 
 const CHANNEL_COUNT: usize = 2;
@@ -44,11 +129,14 @@ pub struct Sound {
     last_pause_pos_seconds: f32,
     last_resume_time: Option<Instant>,
     buffered_to_pos_seconds: f32,
+    spatial_motion: Option<SpatialMotion>,
+    params: SoundParams,
 }
 
 pub enum SoundSignal {
     Mono { signal: FramesSignal<f32> },
     Stereo { signal: FramesSignal<[f32; 2]> },
+    StereoLooping { signal: Cycle<[f32; 2]> },
 }
 
 pub enum SoundSignalHandle {
@@ -61,6 +149,9 @@ pub enum SoundSignalHandle {
     AmbientFixed {
         signal_handle: Handle<Stop<FixedGain<FramesSignal<[f32; 2]>>>>,
     },
+    AmbientLooping {
+        signal_handle: Handle<Stop<Gain<Cycle<[f32; 2]>>>>,
+    },
     Streamed {
         signal_handle: Handle<Stop<Gain<oddio::Stream<[f32; 2]>>>>,
     },
@@ -70,12 +161,29 @@ pub enum SoundSignalHandle {
 pub enum AudioFileFormat {
     Mp3,
     Wav,
+    Ogg,
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct SpacialParams {
-    initial_position: Vec3,
-    initial_velocity: Vec3,
+    /// world space
+    pub initial_position: Vec3,
+    pub initial_velocity: Vec3,
+    /// the sound doesn't get any louder when the listener is closer than this
+    pub radius: f32,
+    /// the sound can't be heard past this distance
+    pub max_distance: f32,
+}
+
+impl SpacialParams {
+    pub fn new(initial_position: Vec3) -> Self {
+        Self {
+            initial_position,
+            initial_velocity: Vec3::ZERO,
+            radius: 0.1,
+            max_distance: 1000.0,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -84,6 +192,11 @@ pub struct SoundParams {
     pub fixed_volume: bool,
     pub spacial_params: Option<SpacialParams>,
     pub stream: bool,
+    /// restarts seamlessly at the end, not supported for spatial sounds.
+    /// streamed sounds are rewound by the streaming thread before their buffer runs out
+    pub looping: bool,
+    /// the name of the mixer bus that the sound is played into, e.g. audio_mixer::SFX_BUS
+    pub bus: String,
 }
 
 pub struct AudioFileStreamer {
@@ -122,6 +235,7 @@ impl AudioFileStreamer {
             hint.with_extension(match file_format {
                 AudioFileFormat::Mp3 => "mp3",
                 AudioFileFormat::Wav => "wav",
+                AudioFileFormat::Ogg => "ogg",
             });
         }
 
@@ -265,6 +379,19 @@ impl AudioFileStreamer {
         Ok((SoundData(samples), reached_end_of_stream))
     }
 
+    /// Goes back to the start of the track, used to loop streamed sounds
+    pub fn rewind(&mut self) -> Result<()> {
+        self.format_reader.seek(
+            symphonia::core::formats::SeekMode::Accurate,
+            symphonia::core::formats::SeekTo::Time {
+                time: symphonia::core::units::Time::new(0, 0.0),
+                track_id: Some(self.track_id),
+            },
+        )?;
+        self.decoder.reset();
+        Ok(())
+    }
+
     pub fn track_length_seconds(&self) -> Option<f32> {
         self.track_length_seconds
     }
@@ -282,8 +409,7 @@ impl AudioManager {
             .ok_or_else(|| anyhow::anyhow!("No output device found"))?;
         let device_sample_rate = device.default_output_config()?.sample_rate().0;
 
-        let (spatial_scene_handle, spatial_scene) = oddio::split(oddio::SpatialScene::new());
-        let (mixer_handle, mixer) = oddio::split(oddio::Mixer::new());
+        let (master_bus, master_signal) = AudioBus::new(Default::default());
 
         let config = cpal::StreamConfig {
             channels: 2,
@@ -291,47 +417,127 @@ impl AudioManager {
             buffer_size: cpal::BufferSize::Default,
         };
 
-        let spatial_scene_output_stream = device.build_output_stream(
-            &config,
-            move |out_flat: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                let out_stereo = oddio::frame_stereo(out_flat);
-                oddio::run(&spatial_scene, device_sample_rate, out_stereo);
-            },
-            move |err| {
-                log::error!("cpal audio output stream error: {err}");
-            },
-            None,
-        )?;
-        let mixer_output_stream = device.build_output_stream(
+        let output_stream = device.build_output_stream(
             &config,
             move |out_flat: &mut [f32], _: &cpal::OutputCallbackInfo| {
                 let out_stereo = oddio::frame_stereo(out_flat);
-                oddio::run(&mixer, device_sample_rate, out_stereo);
+                oddio::run(&master_signal, device_sample_rate, out_stereo);
             },
             move |err| {
                 log::error!("cpal audio output stream error: {err}");
             },
             None,
         )?;
-        spatial_scene_output_stream.play()?;
-        mixer_output_stream.play()?;
+        output_stream.play()?;
 
         Ok((
-            AudioManager {
-                master_volume: 1.0,
-                device_sample_rate,
-
-                spatial_scene_handle,
-                mixer_handle,
-                sounds: vec![],
-            },
+            Self::with_master_bus(master_bus, device_sample_rate),
             AudioStreams {
-                _spatial_scene_output_stream: spatial_scene_output_stream,
-                _mixer_output_stream: mixer_output_stream,
+                _output_stream: Some(output_stream),
             },
         ))
     }
 
+    /// A manager that works normally but whose sounds aren't played anywhere, for when there's
+    /// no output device, e.g. when rendering headless on a server
+    pub fn new_without_output() -> (AudioManager, AudioStreams) {
+        let (master_bus, _master_signal) = AudioBus::new(Default::default());
+        (
+            Self::with_master_bus(master_bus, 48000),
+            AudioStreams {
+                _output_stream: None,
+            },
+        )
+    }
+
+    fn with_master_bus(master_bus: AudioBus, device_sample_rate: u32) -> Self {
+        let mut audio_manager = AudioManager {
+            master_volume: 1.0,
+            device_sample_rate,
+
+            buses: HashMap::from([(MASTER_BUS.to_string(), master_bus)]),
+            sounds: vec![],
+            listener: None,
+            attached_sounds: HashMap::new(),
+            sound_cues: Default::default(),
+        };
+        for bus in [MUSIC_BUS, SFX_BUS, VOICE_BUS] {
+            audio_manager.add_bus(bus, Default::default());
+        }
+        audio_manager
+    }
+
+    /// Adds a bus that's routed into the master bus, or updates its settings if it already exists
+    pub fn add_bus(&mut self, name: &str, settings: BusSettings) {
+        if self.buses.contains_key(name) {
+            self.set_bus_settings(name, settings);
+            return;
+        }
+        let (bus, signal) = AudioBus::new(settings);
+        if let Some(master_bus) = self.buses.get_mut(MASTER_BUS) {
+            master_bus
+                .mixer_handle
+                .control::<Mixer<_>, _>()
+                .play(signal);
+        }
+        self.buses.insert(name.to_string(), bus);
+    }
+
+    pub fn bus_names(&self) -> impl Iterator<Item = &str> {
+        self.buses.keys().map(String::as_str)
+    }
+
+    pub fn bus_settings(&self, bus: &str) -> Option<&BusSettings> {
+        self.buses.get(bus).map(|bus| &bus.settings)
+    }
+
+    pub fn set_bus_settings(&mut self, bus: &str, settings: BusSettings) {
+        match self.buses.get_mut(bus) {
+            Some(bus) => {
+                bus.params.set(&settings);
+                bus.settings = settings;
+            }
+            None => {
+                log::warn!("There's no audio bus named {bus}");
+            }
+        }
+    }
+
+    pub fn set_bus_volume(&mut self, bus: &str, volume: f32) {
+        if let Some(settings) = self.bus_settings(bus) {
+            self.set_bus_settings(
+                bus,
+                BusSettings {
+                    volume,
+                    ..*settings
+                },
+            );
+        }
+    }
+
+    pub fn set_bus_muted(&mut self, bus: &str, muted: bool) {
+        if let Some(settings) = self.bus_settings(bus) {
+            self.set_bus_settings(bus, BusSettings { muted, ..*settings });
+        }
+    }
+
+    pub fn mixer_settings(&self) -> MixerSettings {
+        MixerSettings {
+            buses: self
+                .buses
+                .iter()
+                .map(|(name, bus)| (name.clone(), bus.settings))
+                .collect(),
+        }
+    }
+
+    /// Buses that are missing from the settings are left as they are
+    pub fn apply_mixer_settings(&mut self, mixer_settings: &MixerSettings) {
+        for (name, settings) in &mixer_settings.buses {
+            self.add_bus(name, *settings);
+        }
+    }
+
     pub fn get_signal(
         sound_data: &SoundData,
         params: SoundParams,
@@ -340,6 +546,7 @@ impl AudioManager {
         let SoundParams {
             spacial_params,
             stream,
+            looping,
             ..
         } = params;
 
@@ -361,13 +568,21 @@ impl AudioManager {
                 SoundSignal::Mono { signal }
             }
             None => {
-                let signal = FramesSignal::from(oddio::Frames::from_iter(
+                let frames = oddio::Frames::from_iter(
                     device_sample_rate,
                     samples.iter().map(|sample| {
                         [sample[0], if channels > 1 { sample[1] } else { sample[0] }]
                     }),
-                ));
-                SoundSignal::Stereo { signal }
+                );
+                if looping {
+                    SoundSignal::StereoLooping {
+                        signal: Cycle::new(frames),
+                    }
+                } else {
+                    SoundSignal::Stereo {
+                        signal: FramesSignal::from(frames),
+                    }
+                }
             }
         })
     }
@@ -398,19 +613,261 @@ impl AudioManager {
     }
 
     pub fn reload_sound(&mut self, sound_index: usize, params: SoundParams) {
+        self.reload_sound_at(sound_index, params, 0.0);
+    }
+
+    fn reload_sound_at(&mut self, sound_index: usize, params: SoundParams, pos_seconds: f32) {
         if let Some(sound) = self.sounds[sound_index].take() {
             let signal = Self::get_signal(&sound.data, params.clone(), self.device_sample_rate);
-            self.sounds[sound_index] = Some(Sound::new(
+            if pos_seconds > 0.0 {
+                match &signal {
+                    Some(SoundSignal::Mono { signal }) => signal.seek(pos_seconds),
+                    Some(SoundSignal::Stereo { signal }) => signal.seek(pos_seconds),
+                    Some(SoundSignal::StereoLooping { signal }) => signal.seek(pos_seconds),
+                    None => {}
+                }
+            }
+            let mut new_sound = Sound::new(
                 self,
                 sound.file_path,
                 sound.data,
                 sound.length_seconds,
                 params,
                 signal,
-            ));
+            );
+            // keep following the node that the sound was attached to
+            if let (Some(old_motion), Some(new_motion)) =
+                (sound.spatial_motion, new_sound.spatial_motion.as_mut())
+            {
+                if self.attached_sounds.contains_key(&sound_index) {
+                    *new_motion = SpatialMotion {
+                        discontinuity: true,
+                        ..old_motion
+                    };
+                }
+            }
+            new_sound.last_pause_pos_seconds = pos_seconds;
+            self.sounds[sound_index] = Some(new_sound);
+        }
+    }
+
+    /// Moves the sound to another mixer bus, it keeps playing from where it was.
+    /// Streamed sounds can only be routed when they're added since their signal can't be rebuilt
+    pub fn route_sound(&mut self, sound_index: usize, bus: &str) {
+        let Some(sound) = self.sounds[sound_index].as_ref() else {
+            return;
+        };
+        if sound.params.bus == bus {
+            return;
+        }
+        if !self.buses.contains_key(bus) {
+            log::warn!("There's no audio bus named {bus}");
+            return;
+        }
+        if sound.params.stream {
+            log::warn!(
+                "Streamed sound {} can't be routed to another bus",
+                sound.file_path.relative_path.display()
+            );
+            return;
+        }
+        let params = SoundParams {
+            bus: bus.to_string(),
+            ..sound.params.clone()
+        };
+        let was_playing = sound.is_playing;
+        let pos_seconds = sound.pos_seconds();
+        self.reload_sound_at(sound_index, params, pos_seconds);
+        if was_playing {
+            self.play_sound(sound_index);
+        }
+    }
+
+    pub fn sound_bus(&self, sound_index: usize) -> Option<&str> {
+        self.sounds[sound_index]
+            .as_ref()
+            .map(|sound| sound.params.bus.as_str())
+    }
+
+    /// The sound follows the node from the next update_spatial_audio on.
+    /// Only has an effect on sounds that were loaded with spacial_params
+    pub fn attach_sound_to_node(&mut self, sound_index: usize, node_id: GameNodeId) {
+        self.attached_sounds.insert(
+            sound_index,
+            AttachedSound {
+                node_id,
+                last_position: None,
+            },
+        );
+    }
+
+    pub fn detach_sound(&mut self, sound_index: usize) {
+        self.attached_sounds.remove(&sound_index);
+    }
+
+    /// Moves a spatial sound that isn't attached to a node, in world space
+    pub fn set_sound_motion(&mut self, sound_index: usize, position: Vec3, velocity: Vec3) {
+        if let Some(motion) = self.sounds[sound_index]
+            .as_mut()
+            .and_then(|sound| sound.spatial_motion.as_mut())
+        {
+            motion.position = position;
+            motion.velocity = velocity;
+        }
+    }
+
+    /// Moves the listener and the attached sounds, should be called once per frame after the
+    /// camera has been moved. The velocities used for the doppler effect are derived from how
+    /// far the nodes moved since the last update
+    #[profiling::function]
+    pub fn update_spatial_audio(
+        &mut self,
+        listener_transform: Transform,
+        scene: &Scene,
+        frame_time_seconds: f32,
+    ) {
+        let velocity_from = |last_position: Option<Vec3>, position: Vec3| match last_position {
+            Some(last_position) if frame_time_seconds > 0.0 => {
+                (position - last_position) / frame_time_seconds
+            }
+            _ => Vec3::ZERO,
+        };
+
+        let listener_position = listener_transform.position();
+        let listener = AudioListener {
+            velocity: velocity_from(
+                self.listener.map(|listener| listener.position),
+                listener_position,
+            ),
+            position: listener_position,
+            rotation: listener_transform.rotation(),
+        };
+        self.listener = Some(listener);
+
+        let sounds = &mut self.sounds;
+        self.attached_sounds.retain(|sound_index, attached_sound| {
+            let Some(Some(sound)) = sounds.get_mut(*sound_index) else {
+                return false;
+            };
+            if scene.get_node(attached_sound.node_id).is_none() {
+                // the sound stays where the node was removed
+                return false;
+            }
+            let position = scene
+                .get_global_transform_for_node(attached_sound.node_id)
+                .position();
+            if let Some(motion) = sound.spatial_motion.as_mut() {
+                motion.velocity = velocity_from(attached_sound.last_position, position);
+                motion.discontinuity |= attached_sound.last_position.is_none();
+                motion.position = position;
+            }
+            attached_sound.last_position = Some(position);
+            true
+        });
+
+        for sound in self.sounds.iter_mut().flatten() {
+            sound.update_motion(&listener);
         }
     }
 
+    /// Plays one of the cue's clips, see SoundCue. position is in world space and only used by
+    /// the clips that were loaded with spacial_params. Returns false if nothing was played
+    pub fn trigger_cue(&mut self, name: &str, position: Option<Vec3>) -> bool {
+        let Some(pick) = self.sound_cues.pick(name) else {
+            return false;
+        };
+        self.play_one_shot(pick.sound_index, pick.volume, pick.pitch, position);
+        true
+    }
+
+    /// Plays a copy of the sound that can overlap with the other copies, e.g. for footsteps.
+    /// The copy can't be paused or moved. volume is multiplied with the sound's volume,
+    /// a pitch of 2 plays it an octave higher and twice as fast
+    pub fn play_one_shot(
+        &mut self,
+        sound_index: usize,
+        volume: f32,
+        pitch: f32,
+        position: Option<Vec3>,
+    ) {
+        let Some(sound) = self.sounds.get(sound_index).and_then(Option::as_ref) else {
+            return;
+        };
+        if sound.params.stream {
+            log::warn!(
+                "Streamed sound {} can't be played as a one shot",
+                sound.file_path.relative_path.display()
+            );
+            return;
+        }
+        let SoundData(samples) = &sound.data;
+        let Some(audio_bus) = self.buses.get_mut(&sound.params.bus) else {
+            return;
+        };
+        if samples.is_empty() || pitch <= 0.0 {
+            return;
+        }
+
+        // the frames are resampled to the device's sample rate as they're played
+        let frames_sample_rate = (self.device_sample_rate as f32 * pitch).round() as u32;
+        let volume_amplitude_ratio = (self.master_volume * sound.volume * volume).powf(2.0);
+        let volume_db = 20.0 * volume_amplitude_ratio.log10();
+
+        match sound.params.spacial_params {
+            Some(spacial_params) => {
+                let position = position.unwrap_or(spacial_params.initial_position);
+                let (position, velocity) = self
+                    .listener
+                    .unwrap_or_default()
+                    .to_listener_space(position, Vec3::ZERO);
+                let signal = FixedGain::new(
+                    FramesSignal::from(oddio::Frames::from_iter(
+                        frames_sample_rate,
+                        samples.iter().map(|sample| sample[0]).collect::<Vec<_>>(),
+                    )),
+                    volume_db,
+                );
+                audio_bus
+                    .spatial_scene_handle
+                    .control::<SpatialScene, _>()
+                    .play_buffered(
+                        signal,
+                        SpatialOptions {
+                            position: [position.x, position.y, position.z].into(),
+                            velocity: [velocity.x, velocity.y, velocity.z].into(),
+                            radius: spacial_params.radius,
+                        },
+                        spacial_params.max_distance,
+                        self.device_sample_rate,
+                        0.1,
+                    );
+            }
+            None => {
+                let signal = FixedGain::new(
+                    FramesSignal::from(oddio::Frames::from_iter(
+                        frames_sample_rate,
+                        samples.iter().copied(),
+                    )),
+                    volume_db,
+                );
+                audio_bus.mixer_handle.control::<Mixer<_>, _>().play(signal);
+            }
+        }
+    }
+
+    pub fn pause_sound(&mut self, sound_index: usize) {
+        if let Some(sound) = self.sounds[sound_index].as_mut() {
+            sound.pause();
+        }
+    }
+
+    pub fn sound_is_looping(&self, sound_index: usize) -> bool {
+        self.sounds[sound_index]
+            .as_ref()
+            .map(|sound| sound.params.looping)
+            .unwrap_or(false)
+    }
+
     pub fn sound_is_playing(&self, sound_index: usize) -> bool {
         self.sounds[sound_index]
             .as_ref()
@@ -442,7 +899,7 @@ impl AudioManager {
             .map(|sound| &sound.file_path)
     }
 
-    pub fn _set_sound_volume(&mut self, sound_index: usize, volume: f32) {
+    pub fn set_sound_volume(&mut self, sound_index: usize, volume: f32) {
         if let Some(sound) = self.sounds[sound_index].as_mut() {
             sound.set_volume(self.master_volume, volume)
         }
@@ -505,43 +962,65 @@ impl Sound {
         params: SoundParams,
         signal: Option<SoundSignal>,
     ) -> Self {
+        let mut params = params;
+        if !audio_manager.buses.contains_key(&params.bus) {
+            log::warn!(
+                "There's no audio bus named {}, playing {} in {SFX_BUS}",
+                params.bus,
+                file_path.relative_path.display()
+            );
+            params.bus = SFX_BUS.to_string();
+        }
         let SoundParams {
             initial_volume,
             fixed_volume,
             spacial_params,
             stream,
+            ref bus,
         } = params;
 
         let last_pause_pos_seconds = 0.0;
         let last_resume_time = None;
         let buffered_to_pos_seconds =
             sound_data.0.len() as f32 / audio_manager.device_sample_rate as f32;
+        let listener = audio_manager.listener.unwrap_or_default();
+        let master_volume = audio_manager.master_volume;
+        let device_sample_rate = audio_manager.device_sample_rate;
+        let audio_bus = audio_manager.buses.get_mut(bus).unwrap();
 
+        let mut spatial_motion = None;
         let signal_handle = match (spacial_params, signal, stream) {
             (
                 Some(SpacialParams {
                     initial_position,
                     initial_velocity,
+                    radius,
+                    max_distance,
                 }),
                 Some(SoundSignal::Mono { signal }),
                 false,
             ) => {
                 let signal = Gain::new(signal);
-
-                let signal_handle = audio_manager
+                spatial_motion = Some(SpatialMotion {
+                    position: initial_position,
+                    velocity: initial_velocity,
+                    discontinuity: false,
+                });
+                let (position, velocity) =
+                    listener.to_listener_space(initial_position, initial_velocity);
+
+                let signal_handle = audio_bus
                     .spatial_scene_handle
                     .control::<SpatialScene, _>()
                     .play_buffered(
                         signal,
                         SpatialOptions {
-                            position: [initial_position.x, initial_position.y, initial_position.z]
-                                .into(),
-                            velocity: [initial_velocity.x, initial_velocity.y, initial_velocity.z]
-                                .into(),
-                            radius: 0.1,
+                            position: [position.x, position.y, position.z].into(),
+                            velocity: [velocity.x, velocity.y, velocity.z].into(),
+                            radius,
                         },
-                        1000.0,
-                        audio_manager.device_sample_rate,
+                        max_distance,
+                        device_sample_rate,
                         0.1,
                     );
 
@@ -549,34 +1028,30 @@ impl Sound {
             }
             (None, Some(SoundSignal::Stereo { signal }), false) => {
                 if fixed_volume {
-                    let volume_amplitude_ratio =
-                        (audio_manager.master_volume * initial_volume).powf(2.0);
+                    let volume_amplitude_ratio = (master_volume * initial_volume).powf(2.0);
                     let volume_db = 20.0 * volume_amplitude_ratio.log10();
                     let signal = FixedGain::new(signal, volume_db);
-                    let signal_handle = audio_manager
-                        .mixer_handle
-                        .control::<Mixer<_>, _>()
-                        .play(signal);
+                    let signal_handle =
+                        audio_bus.mixer_handle.control::<Mixer<_>, _>().play(signal);
                     SoundSignalHandle::AmbientFixed { signal_handle }
                 } else {
                     let signal = Gain::new(signal);
-                    let signal_handle = audio_manager
-                        .mixer_handle
-                        .control::<Mixer<_>, _>()
-                        .play(signal);
+                    let signal_handle =
+                        audio_bus.mixer_handle.control::<Mixer<_>, _>().play(signal);
                     SoundSignalHandle::Ambient { signal_handle }
                 }
             }
+            (None, Some(SoundSignal::StereoLooping { signal }), false) => {
+                let signal = Gain::new(signal);
+                let signal_handle = audio_bus.mixer_handle.control::<Mixer<_>, _>().play(signal);
+                SoundSignalHandle::AmbientLooping { signal_handle }
+            }
             (None, None, true) => {
                 let signal = Gain::new(oddio::Stream::new(
-                    audio_manager.device_sample_rate,
-                    (audio_manager.device_sample_rate as f32 * AUDIO_STREAM_BUFFER_LENGTH_SECONDS)
-                        as usize,
+                    device_sample_rate,
+                    (device_sample_rate as f32 * AUDIO_STREAM_BUFFER_LENGTH_SECONDS) as usize,
                 ));
-                let signal_handle = audio_manager
-                    .mixer_handle
-                    .control::<Mixer<_>, _>()
-                    .play(signal);
+                let signal_handle = audio_bus.mixer_handle.control::<Mixer<_>, _>().play(signal);
                 SoundSignalHandle::Streamed { signal_handle }
             }
             _ => {
@@ -594,9 +1069,11 @@ impl Sound {
             last_pause_pos_seconds,
             last_resume_time,
             buffered_to_pos_seconds,
+            spatial_motion,
+            params,
         };
 
-        sound.set_volume(audio_manager.master_volume, initial_volume);
+        sound.set_volume(master_volume, initial_volume);
         sound.pause();
         sound
     }
@@ -623,6 +1100,9 @@ impl Sound {
             SoundSignalHandle::AmbientFixed { signal_handle } => {
                 signal_handle.control::<Stop<_>, _>().pause();
             }
+            SoundSignalHandle::AmbientLooping { signal_handle } => {
+                signal_handle.control::<Stop<_>, _>().pause();
+            }
             SoundSignalHandle::Streamed { signal_handle } => {
                 signal_handle.control::<Stop<_>, _>().pause();
             }
@@ -642,6 +1122,9 @@ impl Sound {
             SoundSignalHandle::AmbientFixed { signal_handle } => {
                 signal_handle.control::<Stop<_>, _>().resume();
             }
+            SoundSignalHandle::AmbientLooping { signal_handle } => {
+                signal_handle.control::<Stop<_>, _>().resume();
+            }
             SoundSignalHandle::Streamed { signal_handle } => {
                 signal_handle.control::<Stop<_>, _>().resume();
             }
@@ -663,6 +1146,11 @@ impl Sound {
                     .set_amplitude_ratio((master_volume * self.volume).powf(2.0));
             }
             SoundSignalHandle::AmbientFixed { .. } => {}
+            SoundSignalHandle::AmbientLooping { signal_handle } => {
+                signal_handle
+                    .control::<Gain<_>, _>()
+                    .set_amplitude_ratio((master_volume * self.volume).powf(2.0));
+            }
             SoundSignalHandle::Streamed { signal_handle } => {
                 signal_handle
                     .control::<Gain<_>, _>()
@@ -677,12 +1165,27 @@ impl Sound {
                 .last_resume_time
                 .map(|last_resume_time| last_resume_time.elapsed().as_secs_f32())
                 .unwrap_or(0.0);
-        self.length_seconds
-            .map(|length_seconds| length_seconds.min(pos))
-            .unwrap_or(pos)
+        match self.length_seconds {
+            Some(length_seconds) if self.params.looping && length_seconds > 0.0 => {
+                pos % length_seconds
+            }
+            Some(length_seconds) => length_seconds.min(pos),
+            None => pos,
+        }
+    }
+
+    fn update_motion(&mut self, listener: &AudioListener) {
+        let Some(motion) = self.spatial_motion.as_mut() else {
+            return;
+        };
+        let (position, velocity) = listener.to_listener_space(motion.position, motion.velocity);
+        let discontinuity = motion.discontinuity;
+        motion.discontinuity = false;
+        self.set_motion(position, velocity, discontinuity);
     }
 
-    fn _set_motion(&mut self, position: Vec3, velocity: Vec3, discontinuity: bool) {
+    /// position and velocity are relative to the listener
+    fn set_motion(&mut self, position: Vec3, velocity: Vec3, discontinuity: bool) {
         if let SoundSignalHandle::Spacial { signal_handle } = &mut self.signal_handle {
             signal_handle.control::<SpatialBuffered<_>, _>().set_motion(
                 [position.x, position.y, position.z].into(),
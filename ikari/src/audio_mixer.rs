@@ -0,0 +1,371 @@
+use crate::file_manager::{FileManager, GameFilePath};
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use oddio::Signal;
+use serde::{Deserialize, Serialize};
+
+pub const MASTER_BUS: &str = "master";
+pub const MUSIC_BUS: &str = "music";
+pub const SFX_BUS: &str = "sfx";
+pub const VOICE_BUS: &str = "voice";
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompressorSettings {
+    pub threshold_db: f32,
+    /// how many db over the threshold give one db at the output
+    pub ratio: f32,
+    pub attack_seconds: f32,
+    pub release_seconds: f32,
+    pub makeup_gain_db: f32,
+}
+
+impl Default for CompressorSettings {
+    fn default() -> Self {
+        Self {
+            threshold_db: -18.0,
+            ratio: 4.0,
+            attack_seconds: 0.005,
+            release_seconds: 0.2,
+            makeup_gain_db: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BusSettings {
+    /// in 0..1, squared like the volume of the sounds
+    pub volume: f32,
+    pub muted: bool,
+    /// in hz, None to let all frequencies through
+    pub lowpass_cutoff: Option<f32>,
+    /// how much of the bus is sent through the reverb in 0..1, the dry signal is kept
+    pub reverb_send: f32,
+    pub compressor: Option<CompressorSettings>,
+}
+
+impl Default for BusSettings {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            muted: false,
+            lowpass_cutoff: None,
+            reverb_send: 0.0,
+            compressor: None,
+        }
+    }
+}
+
+/// The settings of all the buses, saved as a RON config file.
+/// Every bus other than the master is routed into the master
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MixerSettings {
+    pub buses: BTreeMap<String, BusSettings>,
+}
+
+impl Default for MixerSettings {
+    fn default() -> Self {
+        Self {
+            buses: [MASTER_BUS, MUSIC_BUS, SFX_BUS, VOICE_BUS]
+                .into_iter()
+                .map(|bus| (bus.to_string(), BusSettings::default()))
+                .collect(),
+        }
+    }
+}
+
+impl MixerSettings {
+    pub async fn load(path: &GameFilePath) -> Result<Self> {
+        let text = FileManager::read_to_string(path).await?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        Ok(ron::from_str(text)?)
+    }
+
+    pub fn to_ron_string(&self) -> Result<String> {
+        Ok(ron::ser::to_string_pretty(
+            self,
+            ron::ser::PrettyConfig::default(),
+        )?)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: &GameFilePath) -> Result<()> {
+        std::fs::write(path.resolve(), self.to_ron_string()?)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct AtomicF32(AtomicU32);
+
+impl AtomicF32 {
+    fn load(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    fn store(&self, value: f32) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// The bus settings as read by the audio thread, so that they can be changed without locking it
+#[derive(Debug, Default)]
+pub(crate) struct BusParams {
+    amplitude: AtomicF32,
+    /// 0 when disabled
+    lowpass_cutoff: AtomicF32,
+    reverb_send: AtomicF32,
+    /// 0 when disabled
+    compressor_ratio: AtomicF32,
+    compressor_threshold_db: AtomicF32,
+    compressor_attack_seconds: AtomicF32,
+    compressor_release_seconds: AtomicF32,
+    compressor_makeup_gain_db: AtomicF32,
+}
+
+impl BusParams {
+    pub(crate) fn new(settings: &BusSettings) -> Self {
+        let params = Self::default();
+        params.set(settings);
+        params
+    }
+
+    pub(crate) fn set(&self, settings: &BusSettings) {
+        self.amplitude.store(if settings.muted {
+            0.0
+        } else {
+            settings.volume.powf(2.0)
+        });
+        self.lowpass_cutoff
+            .store(settings.lowpass_cutoff.unwrap_or(0.0).max(0.0));
+        self.reverb_send.store(settings.reverb_send.clamp(0.0, 1.0));
+        let compressor = settings.compressor.unwrap_or(CompressorSettings {
+            ratio: 0.0,
+            ..Default::default()
+        });
+        self.compressor_ratio.store(compressor.ratio);
+        self.compressor_threshold_db.store(compressor.threshold_db);
+        self.compressor_attack_seconds
+            .store(compressor.attack_seconds);
+        self.compressor_release_seconds
+            .store(compressor.release_seconds);
+        self.compressor_makeup_gain_db
+            .store(compressor.makeup_gain_db);
+    }
+}
+
+/// A feedback delay line, the building block of the reverb
+#[derive(Debug, Default)]
+struct DelayLine {
+    buffer: Vec<f32>,
+    index: usize,
+    /// only used by the comb filters
+    damping_state: f32,
+}
+
+impl DelayLine {
+    fn new(length: usize) -> Self {
+        Self {
+            buffer: vec![0.0; length.max(1)],
+            index: 0,
+            damping_state: 0.0,
+        }
+    }
+
+    fn comb(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let output = self.buffer[self.index];
+        self.damping_state = output * (1.0 - damping) + self.damping_state * damping;
+        self.buffer[self.index] = input + self.damping_state * feedback;
+        self.index = (self.index + 1) % self.buffer.len();
+        output
+    }
+
+    fn allpass(&mut self, input: f32, feedback: f32) -> f32 {
+        let delayed = self.buffer[self.index];
+        self.buffer[self.index] = input + delayed * feedback;
+        self.index = (self.index + 1) % self.buffer.len();
+        delayed - input
+    }
+}
+
+// freeverb's tunings, in samples at 44.1khz
+const COMB_LENGTHS: [usize; 4] = [1116, 1188, 1277, 1356];
+const ALLPASS_LENGTHS: [usize; 2] = [556, 441];
+const STEREO_SPREAD: usize = 23;
+const REVERB_FEEDBACK: f32 = 0.84;
+const REVERB_DAMPING: f32 = 0.2;
+const REVERB_WET_GAIN: f32 = 0.3;
+
+#[derive(Debug)]
+struct Reverb {
+    combs: [Vec<DelayLine>; 2],
+    allpasses: [Vec<DelayLine>; 2],
+}
+
+impl Reverb {
+    fn new(sample_rate: f32) -> Self {
+        let scale = sample_rate / 44100.0;
+        let make_lines = |lengths: &[usize], spread: usize| {
+            lengths
+                .iter()
+                .map(|length| DelayLine::new(((length + spread) as f32 * scale) as usize))
+                .collect()
+        };
+        Self {
+            combs: [
+                make_lines(&COMB_LENGTHS, 0),
+                make_lines(&COMB_LENGTHS, STEREO_SPREAD),
+            ],
+            allpasses: [
+                make_lines(&ALLPASS_LENGTHS, 0),
+                make_lines(&ALLPASS_LENGTHS, STEREO_SPREAD),
+            ],
+        }
+    }
+
+    fn process(&mut self, input: f32) -> [f32; 2] {
+        let mut output = [0.0; 2];
+        for (channel, output) in output.iter_mut().enumerate() {
+            let mut wet: f32 = self.combs[channel]
+                .iter_mut()
+                .map(|comb| comb.comb(input, REVERB_FEEDBACK, REVERB_DAMPING))
+                .sum();
+            for allpass in &mut self.allpasses[channel] {
+                wet = allpass.allpass(wet, 0.5);
+            }
+            *output = wet * REVERB_WET_GAIN;
+        }
+        output
+    }
+}
+
+#[derive(Debug, Default)]
+struct BusEffectsState {
+    lowpass_state: [f32; 2],
+    compressor_envelope: f32,
+    /// created on the first sample since the sample rate isn't known before
+    reverb: Option<(f32, Reverb)>,
+}
+
+/// Applies a bus's effects and volume to everything that's played into it
+pub(crate) struct BusSignal<T> {
+    inner: T,
+    params: Arc<BusParams>,
+    state: Mutex<BusEffectsState>,
+}
+
+impl<T> BusSignal<T> {
+    pub(crate) fn new(inner: T, params: Arc<BusParams>) -> Self {
+        Self {
+            inner,
+            params,
+            state: Mutex::new(Default::default()),
+        }
+    }
+}
+
+impl<T: Signal<Frame = [f32; 2]>> Signal for BusSignal<T> {
+    type Frame = [f32; 2];
+
+    fn sample(&self, interval: f32, out: &mut [[f32; 2]]) {
+        self.inner.sample(interval, out);
+
+        let params = &self.params;
+        // the audio thread is the only one that locks it
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let sample_rate = 1.0 / interval;
+
+        let lowpass_cutoff = params.lowpass_cutoff.load();
+        if lowpass_cutoff > 0.0 {
+            let alpha = 1.0 - (-2.0 * std::f32::consts::PI * lowpass_cutoff * interval).exp();
+            for frame in out.iter_mut() {
+                for (sample, filtered) in frame.iter_mut().zip(state.lowpass_state.iter_mut()) {
+                    *filtered += alpha * (*sample - *filtered);
+                    *sample = *filtered;
+                }
+            }
+        }
+
+        let compressor_ratio = params.compressor_ratio.load();
+        if compressor_ratio > 1.0 {
+            let threshold_db = params.compressor_threshold_db.load();
+            let makeup_gain_db = params.compressor_makeup_gain_db.load();
+            let attack = (-interval / params.compressor_attack_seconds.load().max(1e-4)).exp();
+            let release = (-interval / params.compressor_release_seconds.load().max(1e-4)).exp();
+            for frame in out.iter_mut() {
+                let peak = frame[0].abs().max(frame[1].abs());
+                let coefficient = if peak > state.compressor_envelope {
+                    attack
+                } else {
+                    release
+                };
+                state.compressor_envelope = peak + coefficient * (state.compressor_envelope - peak);
+                let level_db = 20.0 * state.compressor_envelope.max(1e-6).log10();
+                let over_db = (level_db - threshold_db).max(0.0);
+                let gain_db = makeup_gain_db - over_db * (1.0 - 1.0 / compressor_ratio);
+                let gain = 10f32.powf(gain_db / 20.0);
+                frame[0] *= gain;
+                frame[1] *= gain;
+            }
+        }
+
+        let reverb_send = params.reverb_send.load();
+        if reverb_send > 0.0 {
+            if state.reverb.as_ref().map(|(rate, _)| *rate) != Some(sample_rate) {
+                state.reverb = Some((sample_rate, Reverb::new(sample_rate)));
+            }
+            let (_, reverb) = state.reverb.as_mut().unwrap();
+            for frame in out.iter_mut() {
+                let [wet_left, wet_right] =
+                    reverb.process((frame[0] + frame[1]) * 0.5 * reverb_send);
+                frame[0] += wet_left;
+                frame[1] += wet_right;
+            }
+        }
+
+        let amplitude = params.amplitude.load();
+        for frame in out.iter_mut() {
+            frame[0] *= amplitude;
+            frame[1] *= amplitude;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mixer_settings_round_trip() {
+        let mut settings = MixerSettings::default();
+        settings.buses.insert(
+            MUSIC_BUS.to_string(),
+            BusSettings {
+                volume: 0.5,
+                lowpass_cutoff: Some(800.0),
+                compressor: Some(Default::default()),
+                ..Default::default()
+            },
+        );
+        let parsed = MixerSettings::parse(&settings.to_ron_string().unwrap()).unwrap();
+        assert_eq!(parsed, settings);
+
+        // missing fields fall back to the defaults
+        let parsed = MixerSettings::parse("(buses: {\"sfx\": (muted: true)})").unwrap();
+        assert_eq!(
+            parsed.buses[SFX_BUS],
+            BusSettings {
+                muted: true,
+                ..Default::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use crate::scene::{GameNodeId, Scene};
+use crate::transform::Transform;
+
+use glam::f32::{Quat, Vec3};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BoneOverrideKind {
+    /// Added on top of the animated rotation, in the local space of the bone. E.g. split an aim
+    /// pitch over a few spine bones
+    Rotation(Quat),
+    /// Turns the bone so its forward axis points at a position in world space, e.g. for heads
+    /// and eyes
+    LookAt {
+        target: Vec3,
+        /// in the local space of the bone
+        forward: Vec3,
+        /// in radians, how far the bone may turn away from the animated pose
+        max_angle: f32,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BoneOverride {
+    pub bone_node_id: GameNodeId,
+    pub kind: BoneOverrideKind,
+    /// 0 keeps the animated pose, 1 applies the full override
+    pub weight: f32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct BoneOverrideId(usize);
+
+#[derive(Debug)]
+struct BoneOverrideSlot {
+    bone_override: BoneOverride,
+    /// the local transform of the bone before the override was applied
+    animated_transform: Transform,
+    /// what the override wrote, so the override isn't stacked on top of itself in the frames
+    /// where no animation touched the bone
+    applied_transform: Option<Transform>,
+}
+
+/// Procedural rotations applied to bones after the animations were sampled. They write the
+/// local transforms of the bone nodes, so they end up in the same bone matrices as the
+/// animations. Called by gameloop after on_update, where the animations are usually stepped.
+///
+/// The overrides are applied in the order they were added, so the ones for parent bones
+/// should be added before the ones for their children
+#[derive(Debug, Default)]
+pub struct BoneOverrides {
+    slots: Vec<Option<BoneOverrideSlot>>,
+}
+
+impl BoneOverrides {
+    pub fn add(&mut self, bone_override: BoneOverride) -> BoneOverrideId {
+        let slot = Some(BoneOverrideSlot {
+            bone_override,
+            animated_transform: Transform::IDENTITY,
+            applied_transform: None,
+        });
+        if let Some(index) = self.slots.iter().position(|slot| slot.is_none()) {
+            self.slots[index] = slot;
+            BoneOverrideId(index)
+        } else {
+            self.slots.push(slot);
+            BoneOverrideId(self.slots.len() - 1)
+        }
+    }
+
+    pub fn get(&self, id: BoneOverrideId) -> Option<&BoneOverride> {
+        self.slots
+            .get(id.0)
+            .and_then(|slot| slot.as_ref())
+            .map(|slot| &slot.bone_override)
+    }
+
+    /// e.g. to move the look at target or fade the weight in and out
+    pub fn get_mut(&mut self, id: BoneOverrideId) -> Option<&mut BoneOverride> {
+        self.slots
+            .get_mut(id.0)
+            .and_then(|slot| slot.as_mut())
+            .map(|slot| &mut slot.bone_override)
+    }
+
+    /// Puts the bone back in its animated pose
+    pub fn remove(&mut self, scene: &mut Scene, id: BoneOverrideId) {
+        let Some(slot) = self.slots.get_mut(id.0).and_then(|slot| slot.take()) else {
+            return;
+        };
+        if let Some(node) = scene.get_node_mut(slot.bone_override.bone_node_id) {
+            if Some(node.transform) == slot.applied_transform {
+                node.transform = slot.animated_transform;
+            }
+        }
+    }
+
+    #[profiling::function]
+    pub fn apply(&mut self, scene: &mut Scene) {
+        for slot in self.slots.iter_mut().flatten() {
+            let bone_node_id = slot.bone_override.bone_node_id;
+            let Some(node) = scene.get_node(bone_node_id) else {
+                continue;
+            };
+            if Some(node.transform) != slot.applied_transform {
+                slot.animated_transform = node.transform;
+            }
+            let parent_global_rotation = node
+                .parent_id
+                .map(|parent_id| scene.get_global_transform_for_node(parent_id).rotation())
+                .unwrap_or(Quat::IDENTITY);
+
+            let animated_rotation = slot.animated_transform.rotation();
+            let override_rotation = match slot.bone_override.kind {
+                BoneOverrideKind::Rotation(rotation) => animated_rotation * rotation,
+                BoneOverrideKind::LookAt {
+                    target,
+                    forward,
+                    max_angle,
+                } => {
+                    let bone_position =
+                        scene.get_global_transform_for_node(bone_node_id).position();
+                    let global_rotation = parent_global_rotation * animated_rotation;
+                    let current_direction = (global_rotation * forward).normalize_or_zero();
+                    let target_direction = (target - bone_position).normalize_or_zero();
+                    if current_direction == Vec3::ZERO || target_direction == Vec3::ZERO {
+                        animated_rotation
+                    } else {
+                        let turn = Quat::from_rotation_arc(current_direction, target_direction);
+                        let (axis, angle) = turn.to_axis_angle();
+                        let turn = Quat::from_axis_angle(axis, angle.min(max_angle));
+                        // the turn is in world space, bring it into the space of the parent
+                        parent_global_rotation.inverse() * turn * global_rotation
+                    }
+                }
+            };
+            let rotation = animated_rotation
+                .slerp(override_rotation, slot.bone_override.weight.clamp(0.0, 1.0));
+
+            let mut transform = slot.animated_transform;
+            transform.set_rotation(rotation);
+            if let Some(node) = scene.get_node_mut(bone_node_id) {
+                node.transform = transform;
+            }
+            slot.applied_transform = Some(transform);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::GameNodeDesc;
+
+    #[test]
+    fn overrides_are_not_stacked_between_frames() {
+        let mut scene = Scene::default();
+        let bone_node_id = scene.add_node(GameNodeDesc::default()).id();
+        let mut bone_overrides = BoneOverrides::default();
+        let id = bone_overrides.add(BoneOverride {
+            bone_node_id,
+            kind: BoneOverrideKind::Rotation(Quat::from_rotation_y(0.5)),
+            weight: 0.5,
+        });
+
+        bone_overrides.apply(&mut scene);
+        bone_overrides.apply(&mut scene);
+
+        let rotation = scene.get_node(bone_node_id).unwrap().transform.rotation();
+        assert!(rotation.angle_between(Quat::from_rotation_y(0.25)) < 1e-4);
+        bone_overrides.remove(&mut scene, id);
+        let rotation = scene.get_node(bone_node_id).unwrap().transform.rotation();
+        assert!(rotation.angle_between(Quat::IDENTITY) < 1e-4);
+    }
+}
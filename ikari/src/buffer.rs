@@ -117,6 +117,26 @@ impl GpuBuffer {
         }
     }
 
+    /// Changes how many elements are in use without touching the contents, e.g. before writing
+    /// the appended elements with write_at. Must fit within the capacity
+    pub fn set_length(&mut self, length: usize) {
+        assert!(
+            length <= self.capacity,
+            "Tried to grow the buffer past its capacity"
+        );
+        self.length = length;
+    }
+
+    /// Overwrites part of the contents, starting at the element with index start. Must fit
+    /// within the current length and be aligned to wgpu::COPY_BUFFER_ALIGNMENT
+    pub fn write_at(&self, queue: &wgpu::Queue, start: usize, data: &[u8]) {
+        assert!(
+            start * self.stride + data.len() <= self.length_bytes(),
+            "Tried to write past the end of the buffer"
+        );
+        queue.write_buffer(&self.src, (start * self.stride) as u64, data);
+    }
+
     pub fn destroy(&self) {
         self.src.destroy();
     }
@@ -0,0 +1,371 @@
+use crate::collisions::{Aabb, Frustum, IntersectionResult, Sphere};
+use crate::picking::Ray;
+
+use glam::f32::Vec3;
+
+/// The leaves' boxes are grown by this much so that the nodes that move a bit every frame
+/// don't need to be reinserted each time
+const FAT_MARGIN: f32 = 0.2;
+
+/// A leaf is also reinserted once its box is this many times bigger than it needs to be,
+/// e.g. after the node was scaled down
+const MAX_FAT_AREA_RATIO: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy)]
+enum BvhNodeKind {
+    Leaf(usize),
+    Branch(usize, usize),
+    Free,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BvhNode {
+    aabb: Aabb,
+    parent: Option<usize>,
+    kind: BvhNodeKind,
+}
+
+/// A dynamic bounding volume hierarchy of boxes identified by a key, e.g. a node index.
+/// The tree is updated incrementally: a moved box is only reinserted when it leaves its
+/// fattened box, see Bvh::update
+#[derive(Debug, Default, Clone)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    free_nodes: Vec<usize>,
+    root: Option<usize>,
+    /// key -> index of its leaf in nodes
+    leaves: Vec<Option<usize>>,
+}
+
+impl Bvh {
+    /// Inserts the box if the key isn't in the tree yet
+    pub fn update(&mut self, key: usize, aabb: Aabb) {
+        if let Some(leaf) = self.leaves.get(key).copied().flatten() {
+            let fat_aabb = self.nodes[leaf].aabb;
+            if contains_aabb(&fat_aabb, &aabb)
+                && surface_area(&fat_aabb) <= surface_area(&fatten(&aabb)) * MAX_FAT_AREA_RATIO
+            {
+                return;
+            }
+            self.remove_leaf(leaf);
+            self.nodes[leaf].aabb = fatten(&aabb);
+            self.insert_leaf(leaf);
+            return;
+        }
+
+        let leaf = self.allocate(BvhNode {
+            aabb: fatten(&aabb),
+            parent: None,
+            kind: BvhNodeKind::Leaf(key),
+        });
+        if self.leaves.len() <= key {
+            self.leaves.resize(key + 1, None);
+        }
+        self.leaves[key] = Some(leaf);
+        self.insert_leaf(leaf);
+    }
+
+    pub fn remove(&mut self, key: usize) {
+        let Some(leaf) = self.leaves.get_mut(key).and_then(Option::take) else {
+            return;
+        };
+        self.remove_leaf(leaf);
+        self.free(leaf);
+    }
+
+    pub fn contains(&self, key: usize) -> bool {
+        matches!(self.leaves.get(key), Some(Some(_)))
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// The keys whose fattened box touches the given box. The results are only candidates,
+    /// the caller is expected to do its own precise test
+    pub fn query_aabb(&self, aabb: Aabb, on_hit: impl FnMut(usize)) {
+        self.visit(
+            |node_aabb| {
+                if intersects_aabb(node_aabb, &aabb) {
+                    IntersectionResult::PartiallyIntersecting
+                } else {
+                    IntersectionResult::NotIntersecting
+                }
+            },
+            on_hit,
+        );
+    }
+
+    pub fn query_sphere(&self, sphere: Sphere, on_hit: impl FnMut(usize)) {
+        self.visit(
+            |node_aabb| {
+                let closest_point = node_aabb.find_closest_surface_point(sphere.center);
+                if closest_point.distance_squared(sphere.center) <= sphere.radius * sphere.radius {
+                    IntersectionResult::PartiallyIntersecting
+                } else {
+                    IntersectionResult::NotIntersecting
+                }
+            },
+            on_hit,
+        );
+    }
+
+    /// The subtrees that are fully inside of the frustum aren't tested any further
+    pub fn query_frustum(&self, frustum: &Frustum, on_hit: impl FnMut(usize)) {
+        self.visit(
+            |node_aabb| frustum.aabb_intersection_test(*node_aabb),
+            on_hit,
+        );
+    }
+
+    pub fn query_ray(&self, ray: Ray, on_hit: impl FnMut(usize)) {
+        self.visit(
+            |node_aabb| {
+                if ray_intersects_aabb(&ray, node_aabb) {
+                    IntersectionResult::PartiallyIntersecting
+                } else {
+                    IntersectionResult::NotIntersecting
+                }
+            },
+            on_hit,
+        );
+    }
+
+    fn visit(
+        &self,
+        mut test: impl FnMut(&Aabb) -> IntersectionResult,
+        mut on_hit: impl FnMut(usize),
+    ) {
+        let Some(root) = self.root else {
+            return;
+        };
+        // (node index, whether an ancestor was found to be fully contained)
+        let mut stack = vec![(root, false)];
+        while let Some((index, is_ancestor_contained)) = stack.pop() {
+            let node = &self.nodes[index];
+            let is_contained = is_ancestor_contained
+                || match test(&node.aabb) {
+                    IntersectionResult::NotIntersecting => continue,
+                    IntersectionResult::PartiallyIntersecting => false,
+                    IntersectionResult::FullyContained => true,
+                };
+            match node.kind {
+                BvhNodeKind::Leaf(key) => on_hit(key),
+                BvhNodeKind::Branch(left, right) => {
+                    stack.push((left, is_contained));
+                    stack.push((right, is_contained));
+                }
+                BvhNodeKind::Free => unreachable!("free nodes aren't part of the tree"),
+            }
+        }
+    }
+
+    fn allocate(&mut self, node: BvhNode) -> usize {
+        match self.free_nodes.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn free(&mut self, index: usize) {
+        self.nodes[index].kind = BvhNodeKind::Free;
+        self.free_nodes.push(index);
+    }
+
+    /// Walks down to the sibling that grows the tree's surface area the least, see
+    /// Box2D's b2DynamicTree::InsertLeaf
+    fn insert_leaf(&mut self, leaf: usize) {
+        let Some(root) = self.root else {
+            self.nodes[leaf].parent = None;
+            self.root = Some(leaf);
+            return;
+        };
+
+        let leaf_aabb = self.nodes[leaf].aabb;
+        let mut sibling = root;
+        while let BvhNodeKind::Branch(left, right) = self.nodes[sibling].kind {
+            let area = surface_area(&self.nodes[sibling].aabb);
+            let combined_area = surface_area(&union(&self.nodes[sibling].aabb, &leaf_aabb));
+            // cost of making a new parent for this node and the leaf
+            let cost = 2.0 * combined_area;
+            // cost of pushing the leaf further down the tree
+            let inheritance_cost = 2.0 * (combined_area - area);
+            let child_cost = |child: usize| {
+                let child_node = &self.nodes[child];
+                let merged_area = surface_area(&union(&child_node.aabb, &leaf_aabb));
+                let growth = match child_node.kind {
+                    BvhNodeKind::Leaf(_) => merged_area,
+                    _ => merged_area - surface_area(&child_node.aabb),
+                };
+                growth + inheritance_cost
+            };
+            let (left_cost, right_cost) = (child_cost(left), child_cost(right));
+            if cost < left_cost && cost < right_cost {
+                break;
+            }
+            sibling = if left_cost < right_cost { left } else { right };
+        }
+
+        let old_parent = self.nodes[sibling].parent;
+        let new_parent = self.allocate(BvhNode {
+            aabb: union(&self.nodes[sibling].aabb, &leaf_aabb),
+            parent: old_parent,
+            kind: BvhNodeKind::Branch(sibling, leaf),
+        });
+        self.nodes[sibling].parent = Some(new_parent);
+        self.nodes[leaf].parent = Some(new_parent);
+        match old_parent {
+            Some(old_parent) => self.replace_child(old_parent, sibling, new_parent),
+            None => self.root = Some(new_parent),
+        }
+        self.refit(old_parent);
+    }
+
+    /// Detaches the leaf from the tree, its parent takes the place of its sibling
+    fn remove_leaf(&mut self, leaf: usize) {
+        let Some(parent) = self.nodes[leaf].parent else {
+            self.root = None;
+            return;
+        };
+        let sibling = match self.nodes[parent].kind {
+            BvhNodeKind::Branch(left, right) if left == leaf => right,
+            BvhNodeKind::Branch(left, _) => left,
+            _ => unreachable!("the parent of a node is always a branch"),
+        };
+        let grandparent = self.nodes[parent].parent;
+        self.nodes[sibling].parent = grandparent;
+        match grandparent {
+            Some(grandparent) => self.replace_child(grandparent, parent, sibling),
+            None => self.root = Some(sibling),
+        }
+        self.free(parent);
+        self.nodes[leaf].parent = None;
+        self.refit(grandparent);
+    }
+
+    fn replace_child(&mut self, parent: usize, old_child: usize, new_child: usize) {
+        if let BvhNodeKind::Branch(left, right) = &mut self.nodes[parent].kind {
+            if *left == old_child {
+                *left = new_child;
+            } else if *right == old_child {
+                *right = new_child;
+            }
+        }
+    }
+
+    fn refit(&mut self, mut index: Option<usize>) {
+        while let Some(current) = index {
+            if let BvhNodeKind::Branch(left, right) = self.nodes[current].kind {
+                self.nodes[current].aabb = union(&self.nodes[left].aabb, &self.nodes[right].aabb);
+            }
+            index = self.nodes[current].parent;
+        }
+    }
+}
+
+fn fatten(aabb: &Aabb) -> Aabb {
+    let margin = Vec3::splat(FAT_MARGIN);
+    Aabb {
+        min: aabb.min - margin,
+        max: aabb.max + margin,
+    }
+}
+
+fn union(a: &Aabb, b: &Aabb) -> Aabb {
+    Aabb {
+        min: a.min.min(b.min),
+        max: a.max.max(b.max),
+    }
+}
+
+fn surface_area(aabb: &Aabb) -> f32 {
+    let size = aabb.size();
+    2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+}
+
+fn contains_aabb(outer: &Aabb, inner: &Aabb) -> bool {
+    outer.min.cmple(inner.min).all() && outer.max.cmpge(inner.max).all()
+}
+
+fn intersects_aabb(a: &Aabb, b: &Aabb) -> bool {
+    a.min.cmple(b.max).all() && a.max.cmpge(b.min).all()
+}
+
+/// Slab test, only counts the hits in front of the ray's origin
+fn ray_intersects_aabb(ray: &Ray, aabb: &Aabb) -> bool {
+    let inverse_direction = ray.direction.recip();
+    let t1 = (aabb.min - ray.origin) * inverse_direction;
+    let t2 = (aabb.max - ray.origin) * inverse_direction;
+    let enter = t1.min(t2).max_element();
+    let exit = t1.max(t2).min_element();
+    exit >= enter.max(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box_at(center: Vec3) -> Aabb {
+        Aabb {
+            min: center - Vec3::splat(0.5),
+            max: center + Vec3::splat(0.5),
+        }
+    }
+
+    fn sorted_hits(query: impl FnOnce(&mut Vec<usize>)) -> Vec<usize> {
+        let mut hits = vec![];
+        query(&mut hits);
+        hits.sort_unstable();
+        hits
+    }
+
+    #[test]
+    fn queries_follow_the_updates() {
+        let mut bvh = Bvh::default();
+        for key in 0..100 {
+            bvh.update(key, unit_box_at(Vec3::new(key as f32 * 3.0, 0.0, 0.0)));
+        }
+
+        let near_origin = Sphere {
+            center: Vec3::ZERO,
+            radius: 4.0,
+        };
+        assert_eq!(
+            sorted_hits(|hits| bvh.query_sphere(near_origin, |key| hits.push(key))),
+            vec![0, 1]
+        );
+
+        // moving a bit stays in the fat box, moving far needs a reinsert
+        bvh.update(1, unit_box_at(Vec3::new(3.1, 0.0, 0.0)));
+        bvh.update(50, unit_box_at(Vec3::new(0.0, 2.0, 0.0)));
+        bvh.remove(0);
+        assert!(!bvh.contains(0));
+        assert_eq!(
+            sorted_hits(|hits| bvh.query_sphere(near_origin, |key| hits.push(key))),
+            vec![1, 50]
+        );
+
+        let ray = Ray {
+            origin: Vec3::new(-10.0, 0.0, 0.0),
+            direction: Vec3::X,
+        };
+        assert_eq!(
+            sorted_hits(|hits| bvh.query_ray(ray, |key| hits.push(key))).len(),
+            98
+        );
+        let ray = Ray {
+            origin: Vec3::new(297.0, 0.0, 0.0),
+            direction: Vec3::X,
+        };
+        assert_eq!(
+            sorted_hits(|hits| bvh.query_ray(ray, |key| hits.push(key))),
+            vec![99]
+        );
+    }
+}
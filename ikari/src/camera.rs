@@ -4,10 +4,15 @@ use crate::player_controller::*;
 use crate::transform::*;
 
 use glam::{
-    f32::{Mat4, Quat, Vec3},
+    f32::{Mat4, Quat, Vec3, Vec4},
     EulerRot,
 };
 
+pub const MAX_CLIP_PLANES: usize = 4;
+pub const DEFAULT_NEAR_PLANE_DISTANCE: f32 = 0.001;
+pub const DEFAULT_FAR_PLANE_DISTANCE: f32 = 100000.0;
+pub const DEFAULT_FOV_Y_DEG: f32 = 45.0;
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Camera {
     pub horizontal_rotation: f32,
@@ -27,6 +32,272 @@ impl Camera {
     }
 }
 
+/// in radians
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FieldOfView {
+    Vertical(f32),
+    /// keeps what's visible on the sides the same for all aspect ratios, e.g. for ultrawide screens
+    Horizontal(f32),
+}
+
+impl FieldOfView {
+    pub fn vertical(self, aspect_ratio: f32) -> f32 {
+        match self {
+            FieldOfView::Vertical(fov_y) => fov_y,
+            FieldOfView::Horizontal(fov_x) => 2.0 * ((fov_x / 2.0).tan() / aspect_ratio).atan(),
+        }
+    }
+
+    pub fn horizontal(self, aspect_ratio: f32) -> f32 {
+        match self {
+            FieldOfView::Vertical(fov_y) => 2.0 * ((fov_y / 2.0).tan() * aspect_ratio).atan(),
+            FieldOfView::Horizontal(fov_x) => fov_x,
+        }
+    }
+}
+
+/// How the aspect ratio of the projection is chosen from the one of the surface
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AspectPolicy {
+    Surface,
+    /// the image is stretched to fill the surface
+    Fixed(f32),
+    /// the surface's aspect ratio clamped to a range, e.g. to stop a very wide window from
+    /// showing more than the game was designed for
+    Clamped {
+        min: f32,
+        max: f32,
+    },
+}
+
+impl AspectPolicy {
+    pub fn apply(self, surface_aspect_ratio: f32) -> f32 {
+        match self {
+            AspectPolicy::Surface => surface_aspect_ratio,
+            AspectPolicy::Fixed(aspect_ratio) => aspect_ratio,
+            AspectPolicy::Clamped { min, max } => surface_aspect_ratio.clamp(min, max),
+        }
+    }
+}
+
+/// A camera modelled after a real one. Its focal length and sensor size give the field of view,
+/// its exposure settings scale the tone mapping exposure and its focus settings give the
+/// circle of confusion used for depth of field
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PhysicalCamera {
+    /// in millimeters, defaults to full frame (36x24)
+    pub sensor_width: f32,
+    pub sensor_height: f32,
+    /// in millimeters
+    pub focal_length: f32,
+    /// f-number, e.g. 2.8
+    pub aperture: f32,
+    /// in seconds
+    pub shutter_speed: f32,
+    pub iso: f32,
+    /// in meters
+    pub focus_distance: f32,
+}
+
+impl Default for PhysicalCamera {
+    fn default() -> Self {
+        Self {
+            sensor_width: 36.0,
+            sensor_height: 24.0,
+            focal_length: 50.0,
+            aperture: 16.0,
+            shutter_speed: 1.0 / 100.0,
+            iso: 100.0,
+            focus_distance: 10.0,
+        }
+    }
+}
+
+impl PhysicalCamera {
+    pub fn field_of_view(&self) -> FieldOfView {
+        FieldOfView::Vertical(2.0 * (self.sensor_height / (2.0 * self.focal_length)).atan())
+    }
+
+    /// exposure value at ISO 100
+    pub fn ev100(&self) -> f32 {
+        (self.aperture * self.aperture / self.shutter_speed * 100.0 / self.iso).log2()
+    }
+
+    /// Multiplier for the tone mapping exposure. The lighting isn't in physical units so the
+    /// exposure is relative to the default camera's, which gives 1
+    pub fn exposure_multiplier(&self) -> f32 {
+        2.0f32.powf(PhysicalCamera::default().ev100() - self.ev100())
+    }
+
+    /// diameter on the sensor, in millimeters, of the blur of a point at this distance in meters
+    pub fn circle_of_confusion(&self, distance: f32) -> f32 {
+        let focal_length_m = self.focal_length / 1000.0;
+        let distance = distance.max(focal_length_m + 0.0001);
+        let focus_distance = self.focus_distance.max(focal_length_m + 0.0001);
+        let aperture_diameter = self.focal_length / self.aperture;
+        (aperture_diameter * focal_length_m * (focus_distance - distance)
+            / (distance * (focus_distance - focal_length_m)))
+            .abs()
+    }
+
+    /// circle_of_confusion converted to pixels of an image of the given height
+    pub fn circle_of_confusion_pixels(&self, distance: f32, image_height: u32) -> f32 {
+        self.circle_of_confusion(distance) / self.sensor_height * image_height as f32
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    Perspective,
+    /// e.g. for top-down, isometric or 2D views. height is the size of the view in world units,
+    /// the width follows the aspect ratio
+    Orthographic {
+        height: f32,
+    },
+}
+
+/// The projection settings of a camera
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CameraLens {
+    pub projection: Projection,
+    /// can be negative for orthographic projections, to see what's behind the camera's position
+    pub near_plane_distance: f32,
+    pub far_plane_distance: f32,
+    /// ignored if there's a physical camera or if the projection is orthographic
+    pub field_of_view: FieldOfView,
+    pub aspect_policy: AspectPolicy,
+    pub physical: Option<PhysicalCamera>,
+}
+
+impl Default for CameraLens {
+    fn default() -> Self {
+        Self {
+            projection: Projection::Perspective,
+            near_plane_distance: DEFAULT_NEAR_PLANE_DISTANCE,
+            far_plane_distance: DEFAULT_FAR_PLANE_DISTANCE,
+            field_of_view: FieldOfView::Vertical(deg_to_rad(DEFAULT_FOV_Y_DEG)),
+            aspect_policy: AspectPolicy::Surface,
+            physical: None,
+        }
+    }
+}
+
+impl CameraLens {
+    pub fn aspect_ratio(&self, surface_aspect_ratio: f32) -> f32 {
+        self.aspect_policy.apply(surface_aspect_ratio)
+    }
+
+    pub fn field_of_view(&self) -> FieldOfView {
+        self.physical
+            .map(|physical| physical.field_of_view())
+            .unwrap_or(self.field_of_view)
+    }
+
+    /// in radians, aspect_ratio is the one returned by CameraLens::aspect_ratio
+    pub fn fov_y(&self, aspect_ratio: f32) -> f32 {
+        self.field_of_view().vertical(aspect_ratio)
+    }
+
+    /// height of the visible area at this distance from the camera, in world units
+    pub fn view_height_at_distance(&self, distance: f32, aspect_ratio: f32) -> f32 {
+        match self.projection {
+            Projection::Perspective => 2.0 * distance * (self.fov_y(aspect_ratio) / 2.0).tan(),
+            Projection::Orthographic { height } => height,
+        }
+    }
+
+    pub fn exposure_multiplier(&self) -> f32 {
+        self.physical
+            .map(|physical| physical.exposure_multiplier())
+            .unwrap_or(1.0)
+    }
+
+    /// Interpolates the planes and the vertical field of view, the rest comes from other
+    pub fn lerp(&self, other: &CameraLens, alpha: f32, surface_aspect_ratio: f32) -> CameraLens {
+        let from_fov_y = self.fov_y(self.aspect_ratio(surface_aspect_ratio));
+        let to_fov_y = other.fov_y(other.aspect_ratio(surface_aspect_ratio));
+        let projection = match (self.projection, other.projection) {
+            (
+                Projection::Orthographic {
+                    height: from_height,
+                },
+                Projection::Orthographic { height: to_height },
+            ) => Projection::Orthographic {
+                height: lerp(from_height, to_height, alpha),
+            },
+            _ => other.projection,
+        };
+        CameraLens {
+            projection,
+            near_plane_distance: lerp(self.near_plane_distance, other.near_plane_distance, alpha),
+            far_plane_distance: lerp(self.far_plane_distance, other.far_plane_distance, alpha),
+            field_of_view: FieldOfView::Vertical(lerp(from_fov_y, to_fov_y, alpha)),
+            physical: other.physical.map(|physical| PhysicalCamera {
+                focal_length: physical.sensor_height
+                    / (2.0 * (lerp(from_fov_y, to_fov_y, alpha) / 2.0).tan()),
+                ..physical
+            }),
+            ..*other
+        }
+    }
+
+    pub fn frustum_descriptor(
+        &self,
+        transform: crate::transform::Transform,
+        surface_aspect_ratio: f32,
+    ) -> CameraFrustumDescriptor {
+        let aspect_ratio = self.aspect_ratio(surface_aspect_ratio);
+        CameraFrustumDescriptor {
+            focal_point: transform.position(),
+            forward_vector: (-transform.z_axis).into(),
+            aspect_ratio,
+            near_plane_distance: self.near_plane_distance,
+            far_plane_distance: self.far_plane_distance,
+            fov_y_rad: self.fov_y(aspect_ratio),
+            orthographic_height: match self.projection {
+                Projection::Perspective => None,
+                Projection::Orthographic { height } => Some(height),
+            },
+        }
+    }
+
+    /// The main camera's view and projection, always with a reverse-z projection
+    pub fn shader_camera_data(
+        &self,
+        transform: Mat4,
+        surface_aspect_ratio: f32,
+    ) -> ShaderCameraData {
+        let aspect_ratio = self.aspect_ratio(surface_aspect_ratio);
+        match self.projection {
+            Projection::Perspective => ShaderCameraData::perspective(
+                transform,
+                aspect_ratio,
+                self.near_plane_distance,
+                self.far_plane_distance,
+                self.fov_y(aspect_ratio),
+                true,
+            ),
+            Projection::Orthographic { height } => {
+                // the orthographic matrix maps view space z from its near to its far plane
+                // into 0..1 and the camera looks down -z, so negating and swapping the planes
+                // puts the near plane at a depth of 1, which is what reverse-z expects
+                ShaderCameraData {
+                    near_plane_distance: self.near_plane_distance,
+                    far_plane_distance: self.far_plane_distance,
+                    ..ShaderCameraData::orthographic(
+                        transform,
+                        height * aspect_ratio,
+                        height,
+                        -self.far_plane_distance,
+                        -self.near_plane_distance,
+                        false,
+                    )
+                }
+            }
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct ShaderCameraData {
     pub proj: Mat4,
@@ -35,6 +306,10 @@ pub struct ShaderCameraData {
     pub position: Vec3,
     pub near_plane_distance: f32,
     pub far_plane_distance: f32,
+    /// world-space planes (normal.xyz, distance) of which the negative side isn't drawn,
+    /// only the first clip_plane_count are used
+    pub clip_planes: [Vec4; MAX_CLIP_PLANES],
+    pub clip_plane_count: u32,
 }
 
 impl ShaderCameraData {
@@ -64,6 +339,8 @@ impl ShaderCameraData {
             position,
             near_plane_distance,
             far_plane_distance,
+            clip_planes: [Vec4::ZERO; MAX_CLIP_PLANES],
+            clip_plane_count: 0,
         }
     }
 
@@ -93,8 +370,33 @@ impl ShaderCameraData {
             position,
             near_plane_distance,
             far_plane_distance,
+            clip_planes: [Vec4::ZERO; MAX_CLIP_PLANES],
+            clip_plane_count: 0,
         }
     }
+
+    /// planes past MAX_CLIP_PLANES are ignored
+    pub fn with_clip_planes(mut self, clip_planes: &[Vec4]) -> Self {
+        if clip_planes.len() > MAX_CLIP_PLANES {
+            log::warn!(
+                "Too many clip planes: {}, only the first {} will be used",
+                clip_planes.len(),
+                MAX_CLIP_PLANES
+            );
+        }
+        let clip_plane_count = clip_planes.len().min(MAX_CLIP_PLANES);
+        self.clip_planes = [Vec4::ZERO; MAX_CLIP_PLANES];
+        self.clip_planes[..clip_plane_count].copy_from_slice(&clip_planes[..clip_plane_count]);
+        self.clip_plane_count = clip_plane_count as u32;
+        self
+    }
+}
+
+/// Plane equation for ShaderCameraData::clip_planes, everything behind the normal is clipped.
+/// e.g. make_clip_plane(water_position, Vec3::Y) only keeps what's above the water
+pub fn make_clip_plane(point_on_plane: Vec3, normal: Vec3) -> Vec4 {
+    let normal = normal.normalize();
+    normal.extend(-normal.dot(point_on_plane))
 }
 
 #[repr(C)]
@@ -103,6 +405,9 @@ pub struct MeshShaderCameraRaw {
     view_proj: [[f32; 4]; 4],
     position: [f32; 3],
     far_plane_distance: f32,
+    clip_plane_count: u32,
+    _padding: [u32; 3],
+    clip_planes: [[f32; 4]; MAX_CLIP_PLANES],
 }
 
 impl From<ShaderCameraData> for MeshShaderCameraRaw {
@@ -112,6 +417,8 @@ impl From<ShaderCameraData> for MeshShaderCameraRaw {
             view,
             position,
             far_plane_distance,
+            clip_planes,
+            clip_plane_count,
             ..
         }: ShaderCameraData,
     ) -> Self {
@@ -119,6 +426,9 @@ impl From<ShaderCameraData> for MeshShaderCameraRaw {
             view_proj: (proj * view).to_cols_array_2d(),
             position: [position.x, position.y, position.z],
             far_plane_distance,
+            clip_plane_count,
+            _padding: [0; 3],
+            clip_planes: clip_planes.map(|plane| plane.to_array()),
         }
     }
 }
@@ -209,7 +519,27 @@ pub fn build_cubemap_face_frusta(
                 near_plane_distance,
                 far_plane_distance,
                 fov_y_rad: deg_to_rad(90.0),
+                orthographic_height: None,
             }
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn physical_cameras_match_their_lens() {
+        let default_camera = PhysicalCamera::default();
+        assert!((default_camera.exposure_multiplier() - 1.0).abs() < 1e-5);
+
+        // a 50mm lens on a full frame sensor has a ~27 degree vertical fov
+        let fov = default_camera.field_of_view();
+        assert!((rad_to_deg(fov.vertical(1.5)) - 26.99).abs() < 0.01);
+        assert!((fov.horizontal(1.5) - 2.0 * (18.0f32 / 50.0).atan()).abs() < 1e-5);
+
+        assert_eq!(default_camera.circle_of_confusion(10.0), 0.0);
+        assert!(default_camera.circle_of_confusion(2.0) > default_camera.circle_of_confusion(5.0));
+    }
+}
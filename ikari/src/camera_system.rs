@@ -0,0 +1,362 @@
+use crate::camera::CameraLens;
+use crate::physics::rapier3d_f64::prelude::{point, vector, QueryFilter, Ray, Real};
+use crate::physics::PhysicsState;
+use crate::player_controller::{ControlledViewDirection, PlayerController};
+use crate::scene::{GameNodeId, RenderLayers, Scene};
+use crate::transform::{Transform, TransformBuilder};
+
+use glam::f32::Vec3;
+
+/// What an orbit camera looks at
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OrbitTarget {
+    Player,
+    Node(GameNodeId),
+}
+
+/// A third person camera at the end of a boom that rotates around its target.
+/// The boom is shortened when something is between the camera and the target
+#[derive(Debug, Clone)]
+pub struct OrbitCamera {
+    pub target: OrbitTarget,
+    /// added to the target's position, e.g. to look at the head instead of the feet
+    pub target_offset: Vec3,
+    pub distance: f32,
+    pub min_distance: f32,
+    /// how far the camera is kept from the surface that shortened the boom
+    pub collision_margin: f32,
+    pub collides: bool,
+    /// follows the player controller's view direction while it's available so that the mouse
+    /// and the gamepad keep working
+    pub view_direction: ControlledViewDirection,
+}
+
+impl OrbitCamera {
+    pub fn new(target: OrbitTarget, distance: f32) -> Self {
+        Self {
+            target,
+            target_offset: Vec3::ZERO,
+            distance,
+            min_distance: 0.25,
+            collision_margin: 0.2,
+            collides: true,
+            view_direction: ControlledViewDirection {
+                horizontal: 0.0,
+                vertical: 0.0,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum CameraKind {
+    /// at the player controller's eyes
+    FirstPerson,
+    Orbit(OrbitCamera),
+    /// copies the global transform of a scene node, e.g. one that's animated for a cutscene
+    Fixed(GameNodeId),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CameraId(usize);
+
+#[derive(Debug, Clone)]
+struct CameraEntry {
+    kind: CameraKind,
+    /// overrides CameraSystem::default_lens
+    lens: Option<CameraLens>,
+    render_layers: RenderLayers,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct CameraBlend {
+    from: Transform,
+    from_lens: CameraLens,
+    duration_seconds: f32,
+    elapsed_seconds: f32,
+}
+
+impl CameraBlend {
+    /// eased in and out so that the switch doesn't start or stop abruptly
+    fn alpha(&self) -> f32 {
+        let alpha = (self.elapsed_seconds / self.duration_seconds).min(1.0);
+        alpha * alpha * (3.0 - 2.0 * alpha)
+    }
+}
+
+/// Holds the cameras of the game and moves the renderer's camera node to the active one,
+/// blending smoothly when the active camera changes
+#[derive(Debug, Default)]
+pub struct CameraSystem {
+    cameras: Vec<Option<CameraEntry>>,
+    active_camera: Option<CameraId>,
+    blend: Option<CameraBlend>,
+    /// the transform given to the camera node on the last update
+    last_transform: Option<Transform>,
+    /// the lens of the cameras that don't have their own
+    pub default_lens: CameraLens,
+}
+
+impl CameraSystem {
+    pub fn add_camera(&mut self, camera: CameraKind) -> CameraId {
+        let camera_id = CameraId(self.cameras.len());
+        self.cameras.push(Some(CameraEntry {
+            kind: camera,
+            lens: None,
+            render_layers: RenderLayers::DEFAULT,
+        }));
+        if self.active_camera.is_none() {
+            self.active_camera = Some(camera_id);
+        }
+        camera_id
+    }
+
+    pub fn remove_camera(&mut self, camera_id: CameraId) {
+        if let Some(camera) = self.cameras.get_mut(camera_id.0) {
+            *camera = None;
+        }
+        if self.active_camera == Some(camera_id) {
+            self.active_camera = None;
+            self.blend = None;
+        }
+    }
+
+    fn get_entry(&self, camera_id: CameraId) -> Option<&CameraEntry> {
+        self.cameras.get(camera_id.0).and_then(Option::as_ref)
+    }
+
+    pub fn get_camera(&self, camera_id: CameraId) -> Option<&CameraKind> {
+        self.get_entry(camera_id).map(|entry| &entry.kind)
+    }
+
+    pub fn get_camera_mut(&mut self, camera_id: CameraId) -> Option<&mut CameraKind> {
+        self.cameras
+            .get_mut(camera_id.0)
+            .and_then(Option::as_mut)
+            .map(|entry| &mut entry.kind)
+    }
+
+    /// None to use the default lens
+    pub fn set_camera_lens(&mut self, camera_id: CameraId, lens: Option<CameraLens>) {
+        if let Some(entry) = self.cameras.get_mut(camera_id.0).and_then(Option::as_mut) {
+            entry.lens = lens;
+        }
+    }
+
+    /// e.g. a minimap camera that sees the minimap layer but not the viewmodel one
+    pub fn set_camera_render_layers(&mut self, camera_id: CameraId, render_layers: RenderLayers) {
+        if let Some(entry) = self.cameras.get_mut(camera_id.0).and_then(Option::as_mut) {
+            entry.render_layers = render_layers;
+        }
+    }
+
+    /// The layers seen by the active camera, switched right away when the active camera changes.
+    /// Meant to be copied into RendererData::camera_render_layers every frame
+    pub fn active_render_layers(&self) -> RenderLayers {
+        self.active_camera
+            .and_then(|camera_id| self.get_entry(camera_id))
+            .map_or(RenderLayers::DEFAULT, |entry| entry.render_layers)
+    }
+
+    fn camera_lens(&self, camera_id: Option<CameraId>) -> CameraLens {
+        camera_id
+            .and_then(|camera_id| self.get_entry(camera_id))
+            .and_then(|entry| entry.lens)
+            .unwrap_or(self.default_lens)
+    }
+
+    /// The lens of the active camera, interpolated during blends. Meant to be copied into
+    /// RendererData::camera_lens every frame
+    pub fn active_lens(&self, surface_aspect_ratio: f32) -> CameraLens {
+        let lens = self.camera_lens(self.active_camera);
+        match self.blend {
+            Some(blend) => blend
+                .from_lens
+                .lerp(&lens, blend.alpha(), surface_aspect_ratio),
+            None => lens,
+        }
+    }
+
+    pub fn camera_ids(&self) -> impl Iterator<Item = CameraId> + '_ {
+        self.cameras
+            .iter()
+            .enumerate()
+            .filter(|(_, camera)| camera.is_some())
+            .map(|(index, _)| CameraId(index))
+    }
+
+    pub fn active_camera(&self) -> Option<CameraId> {
+        self.active_camera
+    }
+
+    pub fn is_blending(&self) -> bool {
+        self.blend.is_some()
+    }
+
+    /// Switches to the camera, blending from the current view over blend_duration_seconds.
+    /// Switching in the middle of a blend starts the new one from wherever the view is
+    pub fn set_active_camera(&mut self, camera_id: CameraId, blend_duration_seconds: f32) {
+        if self.get_camera(camera_id).is_none() || self.active_camera == Some(camera_id) {
+            return;
+        }
+        let from_lens = self.camera_lens(self.active_camera);
+        self.active_camera = Some(camera_id);
+        self.blend = self
+            .last_transform
+            .filter(|_| blend_duration_seconds > 0.0)
+            .map(|from| CameraBlend {
+                from,
+                from_lens,
+                duration_seconds: blend_duration_seconds,
+                elapsed_seconds: 0.0,
+            });
+    }
+
+    /// Activates the camera that was added after the active one, wrapping around
+    pub fn cycle_active_camera(&mut self, blend_duration_seconds: f32) {
+        let camera_ids: Vec<_> = self.camera_ids().collect();
+        let next_index = self
+            .active_camera
+            .and_then(|active_camera| camera_ids.iter().position(|id| *id == active_camera))
+            .map_or(0, |index| (index + 1) % camera_ids.len());
+        if let Some(camera_id) = camera_ids.get(next_index) {
+            self.set_active_camera(*camera_id, blend_duration_seconds);
+        }
+    }
+
+    /// Moves the camera node to the active camera, should be called once per frame after the
+    /// player controller and the physics have been updated
+    pub fn update(
+        &mut self,
+        scene: &mut Scene,
+        physics_state: &PhysicsState,
+        player_controller: Option<&PlayerController>,
+        camera_node_id: GameNodeId,
+        frame_time_seconds: f32,
+    ) {
+        let Some(active_camera) = self.active_camera else {
+            return;
+        };
+        let Some(camera) = self.get_camera_mut(active_camera) else {
+            return;
+        };
+        let Some(target_transform) =
+            compute_camera_transform(camera, scene, physics_state, player_controller)
+        else {
+            return;
+        };
+
+        let transform = match self.blend.as_mut() {
+            Some(blend) => {
+                blend.elapsed_seconds += frame_time_seconds;
+                let transform = blend_transforms(blend.from, target_transform, blend.alpha());
+                if blend.elapsed_seconds >= blend.duration_seconds {
+                    self.blend = None;
+                }
+                transform
+            }
+            None => target_transform,
+        };
+
+        if let Some(camera_node) = scene.get_node_mut(camera_node_id) {
+            camera_node.transform = transform;
+        }
+        self.last_transform = Some(transform);
+    }
+}
+
+fn compute_camera_transform(
+    camera: &mut CameraKind,
+    scene: &Scene,
+    physics_state: &PhysicsState,
+    player_controller: Option<&PlayerController>,
+) -> Option<Transform> {
+    match camera {
+        CameraKind::FirstPerson => {
+            player_controller.map(|player_controller| player_controller.transform(physics_state))
+        }
+        CameraKind::Orbit(orbit_camera) => {
+            if let Some(player_controller) = player_controller {
+                orbit_camera.view_direction = player_controller.view_direction;
+            }
+            let target_position = match orbit_camera.target {
+                OrbitTarget::Player => player_controller?.position(physics_state),
+                OrbitTarget::Node(node_id) => {
+                    scene.get_node(node_id)?;
+                    scene.get_global_transform_for_node(node_id).position()
+                }
+            } + orbit_camera.target_offset;
+
+            let backward = -orbit_camera.view_direction.to_vector();
+            let mut distance = orbit_camera.distance;
+            if orbit_camera.collides {
+                let mut filter = QueryFilter::default();
+                if let (OrbitTarget::Player, Some(player_controller)) =
+                    (orbit_camera.target, player_controller)
+                {
+                    filter = filter.exclude_rigid_body(player_controller.rigid_body_handle);
+                }
+                let ray = Ray::new(
+                    point![
+                        target_position.x as Real,
+                        target_position.y as Real,
+                        target_position.z as Real
+                    ],
+                    vector![backward.x as Real, backward.y as Real, backward.z as Real],
+                );
+                if let Some((_, hit_distance)) = physics_state.query_pipeline.cast_ray(
+                    &physics_state.rigid_body_set,
+                    &physics_state.collider_set,
+                    &ray,
+                    (distance + orbit_camera.collision_margin) as Real,
+                    true,
+                    filter,
+                ) {
+                    distance = (hit_distance as f32 - orbit_camera.collision_margin)
+                        .max(orbit_camera.min_distance);
+                }
+            }
+
+            Some(
+                TransformBuilder::new()
+                    .position(target_position + backward * distance)
+                    .rotation(orbit_camera.view_direction.to_quat())
+                    .build(),
+            )
+        }
+        CameraKind::Fixed(node_id) => {
+            scene.get_node(*node_id)?;
+            Some(scene.get_global_transform_for_node(*node_id))
+        }
+    }
+}
+
+fn blend_transforms(from: Transform, to: Transform, alpha: f32) -> Transform {
+    TransformBuilder::new()
+        .position(from.position().lerp(to.position(), alpha))
+        .rotation(from.rotation().slerp(to.rotation(), alpha))
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycling_skips_removed_cameras() {
+        let mut camera_system = CameraSystem::default();
+        let first_person = camera_system.add_camera(CameraKind::FirstPerson);
+        let orbit = camera_system.add_camera(CameraKind::Orbit(OrbitCamera::new(
+            OrbitTarget::Player,
+            3.0,
+        )));
+        let third = camera_system.add_camera(CameraKind::FirstPerson);
+        assert_eq!(camera_system.active_camera(), Some(first_person));
+
+        camera_system.remove_camera(orbit);
+        camera_system.cycle_active_camera(0.0);
+        assert_eq!(camera_system.active_camera(), Some(third));
+        camera_system.cycle_active_camera(0.0);
+        assert_eq!(camera_system.active_camera(), Some(first_person));
+    }
+}
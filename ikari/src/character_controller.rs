@@ -0,0 +1,438 @@
+use crate::math::*;
+use crate::physics::*;
+use crate::scene::*;
+
+use rapier3d_f64::control::{CharacterAutostep, CharacterLength, KinematicCharacterController};
+use rapier3d_f64::prelude::*;
+
+use glam::f32::{Quat, Vec3};
+
+#[derive(Copy, Clone, Debug)]
+pub struct CharacterControllerConfig {
+    pub capsule_radius: f32,
+    /// total height of the capsule, including the caps
+    pub standing_height: f32,
+    pub crouching_height: f32,
+    /// height of the eyes above the feet, as a proportion of the current capsule height
+    pub eye_height_ratio: f32,
+    pub walk_speed: f32,
+    pub crouch_speed: f32,
+    /// initial upward velocity of a jump
+    pub jump_speed: f32,
+    /// downward acceleration while airborne, as a positive number
+    pub gravity: f32,
+    pub max_fall_speed: f32,
+    /// slopes steeper than this can't be walked up
+    pub max_slope_climb_angle_deg: f32,
+    /// the character slides down slopes steeper than this
+    pub min_slope_slide_angle_deg: f32,
+    /// stairs up to this height are stepped up automatically
+    pub max_step_height: f32,
+    pub min_step_width: f32,
+    /// keeps the character glued to the ground when walking down slopes and stairs
+    pub snap_to_ground_distance: f32,
+    /// time after walking off a ledge during which a jump is still allowed
+    pub coyote_time_seconds: f32,
+    /// jumps pressed slightly before landing are performed on landing
+    pub jump_buffer_seconds: f32,
+}
+
+impl Default for CharacterControllerConfig {
+    fn default() -> Self {
+        Self {
+            capsule_radius: 0.3,
+            standing_height: 1.8,
+            crouching_height: 1.1,
+            eye_height_ratio: 0.92,
+            walk_speed: 4.5,
+            crouch_speed: 2.0,
+            jump_speed: 5.0,
+            gravity: 9.8,
+            max_fall_speed: 50.0,
+            max_slope_climb_angle_deg: 45.0,
+            min_slope_slide_angle_deg: 50.0,
+            max_step_height: 0.35,
+            min_step_width: 0.2,
+            snap_to_ground_distance: 0.3,
+            coyote_time_seconds: 0.12,
+            jump_buffer_seconds: 0.12,
+        }
+    }
+}
+
+/// What the character wants to do this frame, typically filled from player input or AI
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CharacterControllerInput {
+    /// desired movement direction in world space. only the horizontal component is used,
+    /// and it's normalized if longer than 1 so analog sticks can walk slowly
+    pub move_direction: Vec3,
+    /// whether the jump button is held. Only the press is buffered, holding it down doesn't
+    /// jump again on landing
+    pub jump: bool,
+    pub crouch: bool,
+}
+
+/// Kinematic capsule character that collides with the physics world without being
+/// pushed around by it. The rigid body's position is the center of the capsule.
+#[derive(Clone, Debug)]
+pub struct CharacterController {
+    pub config: CharacterControllerConfig,
+    pub rigid_body_handle: RigidBodyHandle,
+    pub collider_handle: ColliderHandle,
+    /// node that gets its position synced to the character's feet in `update_node`
+    pub node_id: Option<GameNodeId>,
+
+    kinematic_controller: KinematicCharacterController,
+    vertical_velocity: f32,
+    is_grounded: bool,
+    is_crouching: bool,
+    seconds_since_grounded: f32,
+    seconds_since_jump_pressed: Option<f32>,
+    was_jump_held: bool,
+    has_jumped_since_grounded: bool,
+    facing: Quat,
+}
+
+impl CharacterController {
+    pub fn new(
+        physics_state: &mut PhysicsState,
+        config: CharacterControllerConfig,
+        feet_position: Vec3,
+        node_id: Option<GameNodeId>,
+    ) -> Self {
+        let center = feet_position + Vec3::new(0.0, config.standing_height / 2.0, 0.0);
+
+        let rigid_body = RigidBodyBuilder::kinematic_position_based()
+            .translation(vector![center.x as f64, center.y as f64, center.z as f64])
+            .build();
+        let rigid_body_handle = physics_state.rigid_body_set.insert(rigid_body);
+        let collider =
+            ColliderBuilder::new(Self::capsule_shape(&config, config.standing_height)).build();
+        let collider_handle = physics_state.collider_set.insert_with_parent(
+            collider,
+            rigid_body_handle,
+            &mut physics_state.rigid_body_set,
+        );
+
+        let kinematic_controller = KinematicCharacterController {
+            up: Vector::y_axis(),
+            offset: CharacterLength::Absolute(0.02),
+            slide: true,
+            autostep: Some(CharacterAutostep {
+                max_height: CharacterLength::Absolute(config.max_step_height as f64),
+                min_width: CharacterLength::Absolute(config.min_step_width as f64),
+                include_dynamic_bodies: false,
+            }),
+            max_slope_climb_angle: deg_to_rad(config.max_slope_climb_angle_deg) as f64,
+            min_slope_slide_angle: deg_to_rad(config.min_slope_slide_angle_deg) as f64,
+            snap_to_ground: Some(CharacterLength::Absolute(
+                config.snap_to_ground_distance as f64,
+            )),
+        };
+
+        Self {
+            config,
+            rigid_body_handle,
+            collider_handle,
+            node_id,
+
+            kinematic_controller,
+            vertical_velocity: 0.0,
+            is_grounded: false,
+            is_crouching: false,
+            seconds_since_grounded: f32::MAX,
+            seconds_since_jump_pressed: None,
+            was_jump_held: false,
+            has_jumped_since_grounded: false,
+            facing: Quat::IDENTITY,
+        }
+    }
+
+    fn capsule_shape(config: &CharacterControllerConfig, height: f32) -> SharedShape {
+        let half_segment_length = (height / 2.0 - config.capsule_radius).max(0.0);
+        SharedShape::capsule_y(half_segment_length as f64, config.capsule_radius as f64)
+    }
+
+    fn current_height(&self) -> f32 {
+        if self.is_crouching {
+            self.config.crouching_height
+        } else {
+            self.config.standing_height
+        }
+    }
+
+    #[profiling::function]
+    pub fn update(
+        &mut self,
+        physics_state: &mut PhysicsState,
+        input: CharacterControllerInput,
+        delta_time_seconds: f32,
+    ) {
+        if delta_time_seconds <= 0.0 {
+            return;
+        }
+
+        self.update_crouch(physics_state, input.crouch);
+
+        let horizontal_movement = {
+            let direction = Vec3::new(input.move_direction.x, 0.0, input.move_direction.z);
+            let direction = if direction.length_squared() > 1.0 {
+                direction.normalize()
+            } else {
+                direction
+            };
+            let speed = if self.is_crouching {
+                self.config.crouch_speed
+            } else {
+                self.config.walk_speed
+            };
+            if direction.length_squared() > 0.0001 {
+                self.facing =
+                    Quat::from_rotation_arc(Vec3::new(0.0, 0.0, 1.0), direction.normalize());
+            }
+            direction * speed
+        };
+
+        let is_jump_press = input.jump && !self.was_jump_held;
+        self.was_jump_held = input.jump;
+        if is_jump_press {
+            self.seconds_since_jump_pressed = Some(0.0);
+        } else if let Some(seconds) = self.seconds_since_jump_pressed.as_mut() {
+            *seconds += delta_time_seconds;
+        }
+
+        let wants_to_jump = self
+            .seconds_since_jump_pressed
+            .map(|seconds| seconds <= self.config.jump_buffer_seconds)
+            .unwrap_or(false);
+        let can_jump = !self.has_jumped_since_grounded
+            && !self.is_crouching
+            && self.seconds_since_grounded <= self.config.coyote_time_seconds;
+
+        if wants_to_jump && can_jump {
+            self.vertical_velocity = self.config.jump_speed;
+            self.has_jumped_since_grounded = true;
+            self.seconds_since_jump_pressed = None;
+        } else if self.is_grounded {
+            // keep a small downward velocity so the ground snapping and detection keep working
+            self.vertical_velocity = -0.1;
+        } else {
+            self.vertical_velocity = (self.vertical_velocity
+                - self.config.gravity * delta_time_seconds)
+                .max(-self.config.max_fall_speed);
+        }
+
+        let desired_translation = Vec3::new(
+            horizontal_movement.x,
+            self.vertical_velocity,
+            horizontal_movement.z,
+        ) * delta_time_seconds;
+
+        let Some(rigid_body) = physics_state.rigid_body_set.get(self.rigid_body_handle) else {
+            return;
+        };
+        let Some(collider) = physics_state.collider_set.get(self.collider_handle) else {
+            return;
+        };
+        let character_position = *rigid_body.position();
+
+        // don't snap to the ground on the way up from a jump
+        let mut kinematic_controller = self.kinematic_controller;
+        if self.vertical_velocity > 0.0 {
+            kinematic_controller.snap_to_ground = None;
+        }
+
+        let mut hit_ceiling = false;
+        let movement = kinematic_controller.move_shape(
+            delta_time_seconds as f64,
+            &physics_state.rigid_body_set,
+            &physics_state.collider_set,
+            &physics_state.query_pipeline,
+            collider.shape(),
+            &character_position,
+            vector![
+                desired_translation.x as f64,
+                desired_translation.y as f64,
+                desired_translation.z as f64
+            ],
+            QueryFilter::default()
+                .exclude_rigid_body(self.rigid_body_handle)
+                .exclude_sensors(),
+            |collision| {
+                if collision.toi.normal1.y < -0.7 {
+                    hit_ceiling = true;
+                }
+            },
+        );
+
+        if hit_ceiling && self.vertical_velocity > 0.0 {
+            self.vertical_velocity = 0.0;
+        }
+
+        let was_grounded = self.is_grounded;
+        self.is_grounded = movement.grounded && self.vertical_velocity <= 0.0;
+        if self.is_grounded {
+            self.seconds_since_grounded = 0.0;
+            self.has_jumped_since_grounded = false;
+        } else {
+            self.seconds_since_grounded += delta_time_seconds;
+            if was_grounded {
+                // walked off a ledge, start falling from rest
+                self.vertical_velocity = self.vertical_velocity.min(0.0);
+            }
+        }
+
+        let new_translation = character_position.translation.vector + movement.translation;
+        if let Some(rigid_body) = physics_state.rigid_body_set.get_mut(self.rigid_body_handle) {
+            rigid_body.set_next_kinematic_translation(new_translation);
+        }
+    }
+
+    /// switches between the standing and crouching capsule, keeping the feet in place.
+    /// standing back up only happens if there's enough room above the character
+    fn update_crouch(&mut self, physics_state: &mut PhysicsState, wants_to_crouch: bool) {
+        if wants_to_crouch == self.is_crouching {
+            return;
+        }
+
+        let Some(rigid_body) = physics_state.rigid_body_set.get(self.rigid_body_handle) else {
+            return;
+        };
+        let current_center = rigid_body.position().translation.vector;
+
+        let new_height = if wants_to_crouch {
+            self.config.crouching_height
+        } else {
+            self.config.standing_height
+        };
+        let height_change = (new_height - self.current_height()) as f64;
+        let new_center = current_center + vector![0.0, height_change / 2.0, 0.0];
+        let new_shape = Self::capsule_shape(&self.config, new_height);
+
+        if !wants_to_crouch {
+            let is_obstructed = physics_state
+                .query_pipeline
+                .intersection_with_shape(
+                    &physics_state.rigid_body_set,
+                    &physics_state.collider_set,
+                    &Isometry::translation(new_center.x, new_center.y, new_center.z),
+                    &*new_shape,
+                    QueryFilter::default()
+                        .exclude_rigid_body(self.rigid_body_handle)
+                        .exclude_sensors(),
+                )
+                .is_some();
+            if is_obstructed {
+                return;
+            }
+        }
+
+        if let Some(collider) = physics_state.collider_set.get_mut(self.collider_handle) {
+            collider.set_shape(new_shape);
+        }
+        if let Some(rigid_body) = physics_state.rigid_body_set.get_mut(self.rigid_body_handle) {
+            rigid_body.set_translation(new_center, true);
+        }
+        self.is_crouching = wants_to_crouch;
+    }
+
+    pub fn is_grounded(&self) -> bool {
+        self.is_grounded
+    }
+
+    pub fn is_crouching(&self) -> bool {
+        self.is_crouching
+    }
+
+    /// rotation around the y axis that faces the last movement direction
+    pub fn facing(&self) -> Quat {
+        self.facing
+    }
+
+    pub fn center_position(&self, physics_state: &PhysicsState) -> Vec3 {
+        let translation = physics_state
+            .rigid_body_set
+            .get(self.rigid_body_handle)
+            .map(|rigid_body| *rigid_body.translation())
+            .unwrap_or_else(Vector::zeros);
+        Vec3::new(
+            translation.x as f32,
+            translation.y as f32,
+            translation.z as f32,
+        )
+    }
+
+    pub fn feet_position(&self, physics_state: &PhysicsState) -> Vec3 {
+        self.center_position(physics_state) - Vec3::new(0.0, self.current_height() / 2.0, 0.0)
+    }
+
+    /// where a first-person camera should go
+    pub fn eye_position(&self, physics_state: &PhysicsState) -> Vec3 {
+        self.feet_position(physics_state)
+            + Vec3::new(
+                0.0,
+                self.current_height() * self.config.eye_height_ratio,
+                0.0,
+            )
+    }
+
+    /// moves the character's node to its feet and turns it to face the movement direction,
+    /// which is what a third-person character model needs
+    pub fn update_node(&self, scene: &mut Scene, physics_state: &PhysicsState) {
+        let Some(node_id) = self.node_id else {
+            return;
+        };
+        let feet_position = self.feet_position(physics_state);
+        if let Some(node) = scene.get_node_mut(node_id) {
+            node.transform.set_position(feet_position);
+            node.transform.set_rotation(self.facing);
+        }
+    }
+
+    pub fn teleport(&mut self, physics_state: &mut PhysicsState, feet_position: Vec3) {
+        let center = feet_position + Vec3::new(0.0, self.current_height() / 2.0, 0.0);
+        if let Some(rigid_body) = physics_state.rigid_body_set.get_mut(self.rigid_body_handle) {
+            rigid_body.set_translation(
+                vector![center.x as f64, center.y as f64, center.z as f64],
+                true,
+            );
+        }
+        self.vertical_velocity = 0.0;
+    }
+
+    pub fn remove(self, physics_state: &mut PhysicsState) {
+        physics_state.remove_rigid_body(self.rigid_body_handle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holding_jump_only_buffers_the_press() {
+        let mut physics_state = PhysicsState::new();
+        let mut character =
+            CharacterController::new(&mut physics_state, Default::default(), Vec3::ZERO, None);
+        let input = CharacterControllerInput {
+            jump: true,
+            ..Default::default()
+        };
+
+        character.update(&mut physics_state, input, 0.1);
+        assert_eq!(character.seconds_since_jump_pressed, Some(0.0));
+
+        // still held after the buffer window, landing now must not jump
+        for _ in 0..3 {
+            character.update(&mut physics_state, input, 0.1);
+        }
+        assert!(character
+            .seconds_since_jump_pressed
+            .map_or(true, |seconds| seconds
+                > character.config.jump_buffer_seconds));
+
+        character.update(&mut physics_state, Default::default(), 0.1);
+        character.update(&mut physics_state, input, 0.1);
+        assert_eq!(character.seconds_since_jump_pressed, Some(0.0));
+    }
+}
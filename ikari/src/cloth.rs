@@ -0,0 +1,471 @@
+use crate::dynamic_mesh::DynamicMesh;
+use crate::mesh::Vertex;
+use crate::renderer::{BaseRenderer, RendererData};
+use crate::scene::{GameNodeId, Scene};
+use crate::transform::Transform;
+use crate::wind::Wind;
+
+use glam::f32::{Vec2, Vec3};
+
+#[derive(Debug, Copy, Clone)]
+pub struct ClothParams {
+    /// number of particles along the width, at least 2
+    pub columns: usize,
+    /// number of particles along the height, at least 2
+    pub rows: usize,
+    pub size: Vec2,
+    pub gravity: Vec3,
+    /// fraction of the velocity lost per step
+    pub damping: f32,
+    /// how much of the stretching is corrected per iteration, in [0, 1]
+    pub stiffness: f32,
+    /// like stiffness but for folding, lower makes the cloth softer
+    pub bend_stiffness: f32,
+    /// how hard the wind pushes on the cloth
+    pub drag: f32,
+    /// constraint solver iterations per step, more makes the cloth stretch less
+    pub iterations: u32,
+    /// distance kept between the cloth and the colliders
+    pub thickness: f32,
+}
+
+impl Default for ClothParams {
+    fn default() -> Self {
+        Self {
+            columns: 12,
+            rows: 16,
+            size: Vec2::new(1.0, 1.5),
+            gravity: Vec3::new(0.0, -9.8, 0.0),
+            damping: 0.01,
+            stiffness: 1.0,
+            bend_stiffness: 0.3,
+            drag: 0.5,
+            iterations: 8,
+            thickness: 0.02,
+        }
+    }
+}
+
+/// Usually attached to bones so a cape doesn't go through the body. The shapes are in the local
+/// space of the node and the radius is in world units
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ClothCollider {
+    Sphere {
+        node_id: GameNodeId,
+        center: Vec3,
+        radius: f32,
+    },
+    Capsule {
+        node_id: GameNodeId,
+        start: Vec3,
+        end: Vec3,
+        radius: f32,
+    },
+}
+
+#[derive(Debug, Copy, Clone)]
+struct DistanceConstraint {
+    particles: (usize, usize),
+    rest_length: f32,
+    stiffness: f32,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Pin {
+    particle: usize,
+    /// None if the particle is pinned in place
+    node_id: Option<GameNodeId>,
+    /// in the space of the node
+    position: Vec3,
+}
+
+/// Position based dynamics on a grid of particles, for capes and flags. The particles are
+/// simulated in world space, so the node of its DynamicMesh should be at the origin.
+///
+/// Call step in the fixed update and write_to_mesh + DynamicMesh::upload once per frame
+#[derive(Debug, Clone)]
+pub struct Cloth {
+    params: ClothParams,
+    positions: Vec<Vec3>,
+    previous_positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    /// 0 for the pinned particles
+    inverse_masses: Vec<f32>,
+    constraints: Vec<DistanceConstraint>,
+    pins: Vec<Pin>,
+    pub colliders: Vec<ClothCollider>,
+}
+
+impl Cloth {
+    /// The cloth hangs down from its top edge, in the xy plane of the transform and facing +z
+    pub fn new(params: ClothParams, transform: Transform) -> Self {
+        let columns = params.columns.max(2);
+        let rows = params.rows.max(2);
+        let params = ClothParams {
+            columns,
+            rows,
+            ..params
+        };
+
+        let positions: Vec<Vec3> = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .map(|(column, row)| {
+                let local_position = Vec3::new(
+                    (column as f32 / (columns - 1) as f32 - 0.5) * params.size.x,
+                    -(row as f32 / (rows - 1) as f32) * params.size.y,
+                    0.0,
+                );
+                transform.transform_point3(local_position)
+            })
+            .collect();
+
+        let mut constraints = vec![];
+        let mut add_constraint = |a: (usize, usize), b: (usize, usize), stiffness: f32| {
+            if b.0 >= columns || b.1 >= rows {
+                return;
+            }
+            let particles = (a.1 * columns + a.0, b.1 * columns + b.0);
+            constraints.push(DistanceConstraint {
+                particles,
+                rest_length: positions[particles.0].distance(positions[particles.1]),
+                stiffness,
+            });
+        };
+        for row in 0..rows {
+            for column in 0..columns {
+                let stiffness = params.stiffness;
+                add_constraint((column, row), (column + 1, row), stiffness);
+                add_constraint((column, row), (column, row + 1), stiffness);
+                // shear
+                add_constraint((column, row), (column + 1, row + 1), stiffness);
+                add_constraint((column + 1, row), (column, row + 1), stiffness);
+                // bend
+                add_constraint((column, row), (column + 2, row), params.bend_stiffness);
+                add_constraint((column, row), (column, row + 2), params.bend_stiffness);
+            }
+        }
+
+        let mut cloth = Self {
+            params,
+            previous_positions: positions.clone(),
+            normals: vec![Vec3::Z; positions.len()],
+            inverse_masses: vec![1.0; positions.len()],
+            positions,
+            constraints,
+            pins: vec![],
+            colliders: vec![],
+        };
+        cloth.compute_normals();
+        cloth
+    }
+
+    pub fn params(&self) -> &ClothParams {
+        &self.params
+    }
+
+    pub fn particle_index(&self, column: usize, row: usize) -> usize {
+        row * self.params.columns + column
+    }
+
+    pub fn positions(&self) -> &[Vec3] {
+        &self.positions
+    }
+
+    /// Keeps the particle where it currently is
+    pub fn pin_in_place(&mut self, particle: usize) {
+        self.pin(Pin {
+            particle,
+            node_id: None,
+            position: self.positions[particle],
+        });
+    }
+
+    /// Makes the particle follow the node, e.g. the shoulder bones for a cape or a flag pole
+    pub fn pin_to_node(&mut self, scene: &Scene, particle: usize, node_id: GameNodeId) {
+        let node_transform = scene.get_global_transform_for_node(node_id);
+        self.pin(Pin {
+            particle,
+            node_id: Some(node_id),
+            position: node_transform
+                .inverse()
+                .transform_point3(self.positions[particle]),
+        });
+    }
+
+    /// e.g. to tear a flag loose
+    pub fn unpin(&mut self, particle: usize) {
+        self.pins.retain(|pin| pin.particle != particle);
+        self.inverse_masses[particle] = 1.0;
+    }
+
+    fn pin(&mut self, pin: Pin) {
+        self.unpin(pin.particle);
+        self.inverse_masses[pin.particle] = 0.0;
+        self.pins.push(pin);
+    }
+
+    #[profiling::function]
+    pub fn step(&mut self, scene: &Scene, wind: &Wind, time_seconds: f32, delta_seconds: f32) {
+        if delta_seconds <= 0.0 {
+            return;
+        }
+
+        for pin in &self.pins {
+            let position = match pin.node_id {
+                Some(node_id) => {
+                    if scene.get_node(node_id).is_none() {
+                        continue;
+                    }
+                    scene
+                        .get_global_transform_for_node(node_id)
+                        .transform_point3(pin.position)
+                }
+                None => pin.position,
+            };
+            self.previous_positions[pin.particle] = self.positions[pin.particle];
+            self.positions[pin.particle] = position;
+        }
+
+        // verlet integration
+        for particle in 0..self.positions.len() {
+            if self.inverse_masses[particle] == 0.0 {
+                continue;
+            }
+            let position = self.positions[particle];
+            let velocity = (position - self.previous_positions[particle]) / delta_seconds;
+            let normal = self.normals[particle];
+            // the wind only pushes on the part of the cloth that faces it
+            let relative_wind = wind.velocity_at(position, time_seconds) - velocity;
+            let wind_acceleration = normal * normal.dot(relative_wind) * self.params.drag;
+            let acceleration = self.params.gravity + wind_acceleration;
+
+            self.previous_positions[particle] = position;
+            self.positions[particle] = position
+                + velocity * delta_seconds * (1.0 - self.params.damping)
+                + acceleration * delta_seconds * delta_seconds;
+        }
+
+        let colliders: Vec<_> = self
+            .colliders
+            .iter()
+            .filter_map(|collider| world_space_collider(scene, collider))
+            .collect();
+        for _ in 0..self.params.iterations {
+            self.solve_constraints();
+            self.solve_collisions(&colliders);
+        }
+
+        self.compute_normals();
+    }
+
+    fn solve_constraints(&mut self) {
+        for constraint in &self.constraints {
+            let (a, b) = constraint.particles;
+            let total_inverse_mass = self.inverse_masses[a] + self.inverse_masses[b];
+            if total_inverse_mass == 0.0 {
+                continue;
+            }
+            let delta = self.positions[b] - self.positions[a];
+            let length = delta.length();
+            if length <= f32::EPSILON {
+                continue;
+            }
+            let correction =
+                delta * ((length - constraint.rest_length) / length) * constraint.stiffness
+                    / total_inverse_mass;
+            self.positions[a] += correction * self.inverse_masses[a];
+            self.positions[b] -= correction * self.inverse_masses[b];
+        }
+    }
+
+    fn solve_collisions(&mut self, colliders: &[(Vec3, Vec3, f32)]) {
+        for (position, inverse_mass) in self.positions.iter_mut().zip(&self.inverse_masses) {
+            if *inverse_mass == 0.0 {
+                continue;
+            }
+            for (start, end, radius) in colliders {
+                // spheres are capsules with the same start and end
+                let segment = *end - *start;
+                let t = if segment.length_squared() > 0.0 {
+                    ((*position - *start).dot(segment) / segment.length_squared()).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                let closest_point = *start + segment * t;
+                let offset = *position - closest_point;
+                let min_distance = radius + self.params.thickness;
+                let distance = offset.length();
+                if distance < min_distance && distance > f32::EPSILON {
+                    *position = closest_point + offset * (min_distance / distance);
+                }
+            }
+        }
+    }
+
+    fn compute_normals(&mut self) {
+        self.normals
+            .iter_mut()
+            .for_each(|normal| *normal = Vec3::ZERO);
+        for triangle in self.front_indices().chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| triangle[i] as usize);
+            let normal = (self.positions[b] - self.positions[a])
+                .cross(self.positions[c] - self.positions[a]);
+            self.normals[a] += normal;
+            self.normals[b] += normal;
+            self.normals[c] += normal;
+        }
+        self.normals
+            .iter_mut()
+            .for_each(|normal| *normal = normal.normalize_or_zero());
+    }
+
+    fn front_indices(&self) -> Vec<u32> {
+        let ClothParams { columns, rows, .. } = self.params;
+        let mut indices = Vec::with_capacity((columns - 1) * (rows - 1) * 6);
+        for row in 0..rows - 1 {
+            for column in 0..columns - 1 {
+                let top_left = (row * columns + column) as u32;
+                let top_right = top_left + 1;
+                let bottom_left = top_left + columns as u32;
+                let bottom_right = bottom_left + 1;
+                indices.extend([
+                    bottom_left,
+                    bottom_right,
+                    top_right,
+                    bottom_left,
+                    top_right,
+                    top_left,
+                ]);
+            }
+        }
+        indices
+    }
+
+    /// Both sides of the cloth are in the mesh so it doesn't disappear when seen from behind
+    pub fn make_dynamic_mesh(&self, base: &BaseRenderer, data: &mut RendererData) -> DynamicMesh {
+        let front_indices = self.front_indices();
+        let particle_count = self.positions.len() as u32;
+        let back_indices = front_indices
+            .chunks_exact(3)
+            .flat_map(|triangle| {
+                [triangle[0], triangle[2], triangle[1]].map(|index| index + particle_count)
+            })
+            .collect::<Vec<_>>();
+        let indices = [front_indices, back_indices].concat();
+
+        let mut dynamic_mesh = DynamicMesh::new(
+            base,
+            data,
+            vec![Vertex::default(); self.positions.len() * 2],
+            indices,
+        );
+        self.write_to_mesh(&mut dynamic_mesh);
+        dynamic_mesh
+    }
+
+    /// For a mesh made with make_dynamic_mesh
+    pub fn write_to_mesh(&self, dynamic_mesh: &mut DynamicMesh) {
+        let ClothParams { columns, rows, .. } = self.params;
+        let particle_count = self.positions.len();
+        let vertices = dynamic_mesh.vertices_mut(0..particle_count * 2);
+        for row in 0..rows {
+            for column in 0..columns {
+                let particle = row * columns + column;
+                let neighbor = |column: usize, row: usize| self.positions[row * columns + column];
+                let tangent = (neighbor((column + 1).min(columns - 1), row)
+                    - neighbor(column.saturating_sub(1), row))
+                .normalize_or_zero();
+                let bitangent = (neighbor(column, (row + 1).min(rows - 1))
+                    - neighbor(column, row.saturating_sub(1)))
+                .normalize_or_zero();
+                let normal = self.normals[particle];
+                let front_vertex = Vertex {
+                    position: self.positions[particle].to_array(),
+                    normal: normal.to_array(),
+                    tex_coords: [
+                        column as f32 / (columns - 1) as f32,
+                        row as f32 / (rows - 1) as f32,
+                    ],
+                    tangent: tangent.to_array(),
+                    bitangent: bitangent.to_array(),
+                    ..Default::default()
+                };
+                vertices[particle] = front_vertex;
+                vertices[particle + particle_count] = Vertex {
+                    normal: (-normal).to_array(),
+                    ..front_vertex
+                };
+            }
+        }
+    }
+}
+
+/// (start, end, radius) in world space
+fn world_space_collider(scene: &Scene, collider: &ClothCollider) -> Option<(Vec3, Vec3, f32)> {
+    let (node_id, start, end, radius) = match *collider {
+        ClothCollider::Sphere {
+            node_id,
+            center,
+            radius,
+        } => (node_id, center, center, radius),
+        ClothCollider::Capsule {
+            node_id,
+            start,
+            end,
+            radius,
+        } => (node_id, start, end, radius),
+    };
+    scene.get_node(node_id)?;
+    let node_transform = scene.get_global_transform_for_node(node_id);
+    Some((
+        node_transform.transform_point3(start),
+        node_transform.transform_point3(end),
+        radius,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::GameNodeDesc;
+    use crate::transform::TransformBuilder;
+
+    #[test]
+    fn pinned_cloth_hangs_around_a_collider() {
+        let mut scene = Scene::default();
+        let ball_node_id = scene
+            .add_node(GameNodeDesc {
+                transform: TransformBuilder::new()
+                    .position(Vec3::new(0.0, -0.75, 0.2))
+                    .build(),
+                ..Default::default()
+            })
+            .id();
+        let mut cloth = Cloth::new(ClothParams::default(), Transform::IDENTITY);
+        for column in 0..cloth.params().columns {
+            let particle = cloth.particle_index(column, 0);
+            cloth.pin_in_place(particle);
+        }
+        cloth.colliders.push(ClothCollider::Sphere {
+            node_id: ball_node_id,
+            center: Vec3::ZERO,
+            radius: 0.3,
+        });
+        let mut wind = Wind::default();
+        wind.strength = 0.0;
+        wind.gust_strength = 0.0;
+
+        for step in 0..300 {
+            cloth.step(&scene, &wind, step as f32 / 60.0, 1.0 / 60.0);
+        }
+
+        let top_left = cloth.positions()[0];
+        assert_eq!(top_left, Vec3::new(-0.5, 0.0, 0.0));
+        let bottom_left = cloth.positions()[cloth.particle_index(0, cloth.params().rows - 1)];
+        assert!(bottom_left.y < -1.3, "{bottom_left}");
+        for position in cloth.positions() {
+            let distance = position.distance(Vec3::new(0.0, -0.75, 0.2));
+            assert!(distance > 0.3, "{position} is inside the collider");
+        }
+    }
+}
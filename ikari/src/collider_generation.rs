@@ -0,0 +1,323 @@
+use crate::collisions::Aabb;
+use crate::physics::rapier3d_f64::prelude::*;
+use crate::physics::*;
+use crate::renderer::*;
+use crate::scene::*;
+use crate::transform::*;
+
+use glam::f32::{Quat, Vec3};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColliderGenerationMode {
+    /// convex hull of the mesh vertices, cheap enough for dynamic objects
+    ConvexHull,
+    /// exact triangle mesh, only meant for static level geometry
+    TriangleMesh,
+    /// the following are fitted to the mesh's bounding box
+    Box,
+    Sphere,
+    /// oriented along the longest axis of the bounding box
+    Capsule,
+}
+
+/// CPU-side collision data kept alongside the GPU buffers of a mesh, in mesh space
+#[derive(Debug, Clone)]
+pub enum CollisionMesh {
+    /// only the points on the hull are kept, the hull is rebuilt
+    /// from them once they're scaled by the node's transform
+    ConvexHull { points: Vec<Vec3> },
+    TriangleMesh {
+        points: Vec<Vec3>,
+        triangles: Vec<[u32; 3]>,
+    },
+}
+
+impl CollisionMesh {
+    /// returns None for the primitive modes since they only need the bounding box
+    pub fn from_geometry(
+        geometry: &BindableGeometryBuffers,
+        mode: ColliderGenerationMode,
+    ) -> Option<Self> {
+        let points: Vec<Vec3> = geometry
+            .vertices
+            .iter()
+            .map(|vertex| Vec3::from(vertex.position))
+            .collect();
+
+        match mode {
+            ColliderGenerationMode::ConvexHull => {
+                let (hull_points, _) = rapier3d_f64::parry::transformation::convex_hull(
+                    &points.iter().copied().map(to_point).collect::<Vec<_>>(),
+                );
+                (hull_points.len() >= 4).then(|| CollisionMesh::ConvexHull {
+                    points: hull_points.into_iter().map(from_point).collect(),
+                })
+            }
+            ColliderGenerationMode::TriangleMesh => {
+                let triangles: Vec<[u32; 3]> = geometry
+                    .indices
+                    .to_u32_vec()
+                    .chunks_exact(3)
+                    .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+                    .collect();
+                (!triangles.is_empty()).then_some(CollisionMesh::TriangleMesh { points, triangles })
+            }
+            ColliderGenerationMode::Box
+            | ColliderGenerationMode::Sphere
+            | ColliderGenerationMode::Capsule => None,
+        }
+    }
+
+    pub fn points(&self) -> &[Vec3] {
+        match self {
+            CollisionMesh::ConvexHull { points } => points,
+            CollisionMesh::TriangleMesh { points, .. } => points,
+        }
+    }
+}
+
+/// Fills in the collision meshes of a freshly loaded scene, done on the loader thread
+pub fn generate_collision_meshes(
+    bindable_scene_data: &mut BindableSceneData,
+    mode: ColliderGenerationMode,
+) {
+    profiling::scope!("generate_collision_meshes");
+
+    for geometry in &mut bindable_scene_data.bindable_meshes {
+        geometry.collision_mesh = CollisionMesh::from_geometry(geometry, mode).map(Into::into);
+    }
+}
+
+/// Creates a collider for each of the given nodes that has a mesh and attaches it to the node.
+/// Must be called after the scene is merged.
+///
+/// With RigidBodyType::Fixed the colliders are placed in world space and added to
+/// static_box_set. Otherwise each node gets a rigid body of that type at its global position
+/// and rotation, and a rigid body component so the node follows it. Dynamic bodies can't use
+/// triangle meshes, they get the convex hull instead.
+///
+/// ConvexHull and TriangleMesh use the collision meshes generated at load time (see
+/// SceneAssetLoadParams::generate_colliders) and fall back to a box if they're missing.
+/// A TriangleMesh request on a mesh that only has hull data gives a convex hull.
+pub fn generate_node_colliders(
+    physics_state: &mut PhysicsState,
+    scene: &Scene,
+    renderer_data: &RendererData,
+    node_ids: impl IntoIterator<Item = GameNodeId>,
+    mode: ColliderGenerationMode,
+    body_type: RigidBodyType,
+) -> Vec<ColliderHandle> {
+    profiling::scope!("generate_node_colliders");
+
+    let mode = if body_type.is_dynamic() && mode == ColliderGenerationMode::TriangleMesh {
+        ColliderGenerationMode::ConvexHull
+    } else {
+        mode
+    };
+    let mut collider_handles = vec![];
+
+    for node_id in node_ids {
+        let Some(node) = scene.get_node(node_id) else {
+            continue;
+        };
+        let Some(visual) = node.visual.as_ref() else {
+            continue;
+        };
+        // skinned meshes don't stay in their bind pose, so their bounds can't be trusted
+        if node.skin_index.is_some() {
+            continue;
+        }
+        let Some(geometry) = renderer_data.binded_meshes.get(visual.mesh.index()) else {
+            continue;
+        };
+
+        let transform: Transform = scene.get_global_transform_for_node(node_id);
+        let transform_decomposed = transform.decompose();
+        let is_fixed = body_type == RigidBodyType::Fixed;
+
+        // the bodies carry the position and rotation of the other types
+        let (position, rotation) = if is_fixed {
+            (transform_decomposed.position, transform_decomposed.rotation)
+        } else {
+            (Vec3::ZERO, Quat::IDENTITY)
+        };
+        let Some(collider) = make_collider(
+            geometry,
+            mode,
+            position,
+            rotation,
+            transform_decomposed.scale,
+        ) else {
+            log::warn!(
+                "Failed to generate a {:?} collider for node {:?}",
+                mode,
+                node.name
+            );
+            continue;
+        };
+
+        if is_fixed {
+            let collider_handle = physics_state.collider_set.insert(collider);
+            physics_state
+                .static_box_set
+                .entry(node_id)
+                .or_default()
+                .push(collider_handle);
+            physics_state.attach_collider_to_node(collider_handle, node_id);
+            collider_handles.push(collider_handle);
+            continue;
+        }
+
+        let rotation = transform_decomposed.rotation;
+        let rigid_body = RigidBodyBuilder::new(body_type)
+            .position(Isometry::from_parts(
+                to_vector(transform_decomposed.position).into(),
+                nalgebra::UnitQuaternion::from_quaternion(nalgebra::Quaternion::new(
+                    rotation.w as f64,
+                    rotation.x as f64,
+                    rotation.y as f64,
+                    rotation.z as f64,
+                )),
+            ))
+            .build();
+        let rigid_body_handle = physics_state.rigid_body_set.insert(rigid_body);
+        let collider_handle = physics_state.collider_set.insert_with_parent(
+            collider,
+            rigid_body_handle,
+            &mut physics_state.rigid_body_set,
+        );
+        physics_state.add_rigid_body_component(node_id, rigid_body_handle);
+        collider_handles.push(collider_handle);
+    }
+
+    collider_handles
+}
+
+/// A collider for a dynamic body placed at the node's global position and rotation, e.g. for
+/// debris. Falls back to a box like generate_node_colliders
+pub fn make_dynamic_node_collider(
+    scene: &Scene,
+    renderer_data: &RendererData,
+    node_id: GameNodeId,
+    mode: ColliderGenerationMode,
+) -> Option<Collider> {
+    let visual = scene.get_node(node_id)?.visual.as_ref()?;
+    let geometry = renderer_data.binded_meshes.get(visual.mesh.index())?;
+    let scale = scene.get_global_transform_for_node(node_id).scale();
+    make_collider(geometry, mode, Vec3::ZERO, Quat::IDENTITY, scale)
+}
+
+/// The node's scale is baked into the shape since colliders can't be scaled
+fn make_collider(
+    geometry: &BindedGeometryBuffers,
+    mode: ColliderGenerationMode,
+    position: Vec3,
+    rotation: Quat,
+    scale: Vec3,
+) -> Option<Collider> {
+    let node_isometry = Isometry::from_parts(
+        to_vector(position).into(),
+        nalgebra::UnitQuaternion::from_quaternion(nalgebra::Quaternion::new(
+            rotation.w as f64,
+            rotation.x as f64,
+            rotation.y as f64,
+            rotation.z as f64,
+        )),
+    );
+
+    let scale_points = |points: &[Vec3]| -> Vec<Point<Real>> {
+        points
+            .iter()
+            .map(|point| to_point(*point * scale))
+            .collect()
+    };
+
+    let mesh_shape = match (mode, geometry.collision_mesh.as_deref()) {
+        (
+            ColliderGenerationMode::TriangleMesh,
+            Some(CollisionMesh::TriangleMesh { points, triangles }),
+        ) => Some(SharedShape::trimesh(
+            scale_points(points),
+            triangles.clone(),
+        )),
+        (
+            ColliderGenerationMode::ConvexHull | ColliderGenerationMode::TriangleMesh,
+            Some(collision_mesh),
+        ) => SharedShape::convex_hull(&scale_points(collision_mesh.points())),
+        _ => None,
+    };
+
+    if let Some(mesh_shape) = mesh_shape {
+        return Some(
+            ColliderBuilder::new(mesh_shape)
+                .position(node_isometry)
+                .build(),
+        );
+    }
+
+    let (shape, center) = fit_primitive(&geometry.bounding_box, mode, scale)?;
+    let shape_isometry = node_isometry * Translation::from(to_vector(center));
+
+    Some(ColliderBuilder::new(shape).position(shape_isometry).build())
+}
+
+/// returns the shape and its center relative to the node, in scaled mesh space
+fn fit_primitive(
+    bounding_box: &Aabb,
+    mode: ColliderGenerationMode,
+    scale: Vec3,
+) -> Option<(SharedShape, Vec3)> {
+    let center = (bounding_box.max + bounding_box.min) / 2.0 * scale;
+    let half_extents = ((bounding_box.max - bounding_box.min) / 2.0 * scale).abs();
+
+    if half_extents.max_element() <= 0.0 {
+        return None;
+    }
+
+    let shape = match mode {
+        ColliderGenerationMode::Sphere => SharedShape::ball(half_extents.max_element() as f64),
+        ColliderGenerationMode::Capsule => {
+            let longest_axis =
+                if half_extents.x >= half_extents.y && half_extents.x >= half_extents.z {
+                    0
+                } else if half_extents.y >= half_extents.z {
+                    1
+                } else {
+                    2
+                };
+            let radius = (0..3)
+                .filter(|axis| *axis != longest_axis)
+                .map(|axis| half_extents[axis])
+                .fold(0.0, f32::max);
+            let half_height = (half_extents[longest_axis] - radius).max(0.0) as f64;
+            let radius = radius as f64;
+            match longest_axis {
+                0 => SharedShape::capsule_x(half_height, radius),
+                1 => SharedShape::capsule_y(half_height, radius),
+                _ => SharedShape::capsule_z(half_height, radius),
+            }
+        }
+        // also the fallback for meshes without collision mesh data
+        ColliderGenerationMode::Box
+        | ColliderGenerationMode::ConvexHull
+        | ColliderGenerationMode::TriangleMesh => SharedShape::cuboid(
+            half_extents.x as f64,
+            half_extents.y as f64,
+            half_extents.z as f64,
+        ),
+    };
+
+    Some((shape, center))
+}
+
+fn to_point(point: Vec3) -> Point<Real> {
+    point![point.x as f64, point.y as f64, point.z as f64]
+}
+
+fn from_point(point: Point<Real>) -> Vec3 {
+    Vec3::new(point.x as f32, point.y as f32, point.z as f32)
+}
+
+fn to_vector(vector: Vec3) -> Vector<Real> {
+    vector![vector.x as f64, vector.y as f64, vector.z as f64]
+}
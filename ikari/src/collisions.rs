@@ -35,6 +35,8 @@ pub struct CameraFrustumDescriptor {
     pub near_plane_distance: f32,
     pub far_plane_distance: f32,
     pub fov_y_rad: f32,
+    /// for orthographic cameras, the height of the view box. fov_y_rad is ignored if set
+    pub orthographic_height: Option<f32>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -338,6 +340,30 @@ impl From<CameraFrustumDescriptor> for Frustum {
 
         // see https://learnopengl.com/Guest-Articles/2021/Scene/Frustum-Culling
         let up = right.cross(forward).normalize();
+
+        let near = Plane::from_normal_and_point(
+            forward,
+            desc.focal_point + desc.near_plane_distance * forward,
+        );
+        let far = Plane::from_normal_and_point(
+            -forward,
+            desc.focal_point + desc.far_plane_distance * forward,
+        );
+
+        if let Some(height) = desc.orthographic_height {
+            // the sides are parallel to the view direction
+            let half_v_side = height / 2.0;
+            let half_h_side = half_v_side * desc.aspect_ratio;
+            return Self {
+                left: Plane::from_normal_and_point(right, desc.focal_point - right * half_h_side),
+                right: Plane::from_normal_and_point(-right, desc.focal_point + right * half_h_side),
+                bottom: Plane::from_normal_and_point(up, desc.focal_point - up * half_v_side),
+                top: Plane::from_normal_and_point(-up, desc.focal_point + up * half_v_side),
+                near,
+                far,
+            };
+        }
+
         let half_v_side = desc.far_plane_distance * (desc.fov_y_rad * 0.5).tan();
         let half_h_side = half_v_side * desc.aspect_ratio;
         let front_mult_far = desc.far_plane_distance * forward;
@@ -359,16 +385,23 @@ impl From<CameraFrustumDescriptor> for Frustum {
                 right.cross(front_mult_far - up * half_v_side),
                 desc.focal_point,
             ),
-            near: Plane::from_normal_and_point(
-                forward,
-                desc.focal_point + desc.near_plane_distance * forward,
-            ),
-            far: Plane::from_normal_and_point(-forward, desc.focal_point + front_mult_far),
+            near,
+            far,
         }
     }
 }
 
 impl CameraFrustumDescriptor {
+    /// half of the width and height of the frustum's cross section at this distance,
+    /// along the right and up vectors
+    fn half_extents_at(&self, distance: f32, right: Vec3, up: Vec3) -> (Vec3, Vec3) {
+        let half_height = match self.orthographic_height {
+            Some(height) => height / 2.0,
+            None => distance * (self.fov_y_rad / 2.0).tan(),
+        };
+        (half_height * self.aspect_ratio * right, half_height * up)
+    }
+
     /// this is SLOW. takes about 2 microseconds. consider caching the result
     pub fn to_convex_polyhedron(&self) -> ConvexPolyhedron {
         let points: Vec<_> = self
@@ -401,13 +434,8 @@ impl CameraFrustumDescriptor {
             .cross(Vec3::new(0.0, 1.0, 0.0))
             .normalize();
         let up = right.cross(self.forward_vector);
-        let tan_half_fovy = (self.fov_y_rad / 2.0).tan();
-
-        let d_x = tan_half_fovy * right * self.aspect_ratio;
-        let d_y = tan_half_fovy * up;
 
-        let d_x_far = self.far_plane_distance * d_x;
-        let d_y_far = self.far_plane_distance * d_y;
+        let (d_x_far, d_y_far) = self.half_extents_at(self.far_plane_distance, right, up);
 
         let center = Aabb::make_from_points(
             self.to_basic_mesh()
@@ -421,10 +449,17 @@ impl CameraFrustumDescriptor {
         let far_plane_side_length_x = 2.0 * d_x_far.length();
         let far_plane_side_length_y = 2.0 * d_y_far.length();
         let forward_length = self.far_plane_distance - self.near_plane_distance;
-        let diagonal_length = ((self.far_plane_distance - self.near_plane_distance)
-            / self.far_plane_distance)
-            * self.far_plane_distance
-            / (self.fov_y_rad / 2.0).cos();
+        let diagonal_length = match self.orthographic_height {
+            Some(_) => (forward_length.powi(2)
+                + far_plane_side_length_x.powi(2)
+                + far_plane_side_length_y.powi(2))
+            .sqrt(),
+            None => {
+                ((self.far_plane_distance - self.near_plane_distance) / self.far_plane_distance)
+                    * self.far_plane_distance
+                    / (self.fov_y_rad / 2.0).cos()
+            }
+        };
         let longest_side_length = [
             far_plane_side_length_x,
             far_plane_side_length_y,
@@ -447,13 +482,8 @@ impl CameraFrustumDescriptor {
             .cross(Vec3::new(0.0, 1.0, 0.0))
             .normalize();
         let up = right.cross(self.forward_vector);
-        let tan_half_fovy = (self.fov_y_rad / 2.0).tan();
-
-        let d_x = tan_half_fovy * right * self.aspect_ratio;
-        let d_y = tan_half_fovy * up;
 
-        let d_x_near = self.near_plane_distance * d_x;
-        let d_y_near = self.near_plane_distance * d_y;
+        let (d_x_near, d_y_near) = self.half_extents_at(self.near_plane_distance, right, up);
         let near_vertices = {
             let near_plane_center =
                 self.focal_point + self.near_plane_distance * self.forward_vector;
@@ -465,8 +495,7 @@ impl CameraFrustumDescriptor {
             (top_left, top_right, bottom_right, bottom_left)
         };
 
-        let d_x_far = self.far_plane_distance * d_x;
-        let d_y_far = self.far_plane_distance * d_y;
+        let (d_x_far, d_y_far) = self.half_extents_at(self.far_plane_distance, right, up);
         let far_vertices = {
             let far_plane_center = self.focal_point + self.far_plane_distance * self.forward_vector;
             let top_left = far_plane_center + d_y_far - d_x_far;
@@ -0,0 +1,463 @@
+use crate::file_manager::{FileManager, GameFilePath};
+use crate::renderer::{BaseRenderer, RendererConstantData, USE_LABELS};
+use crate::texture::Texture;
+use crate::time::Duration;
+
+use anyhow::{bail, Result};
+use wgpu::util::DeviceExt;
+
+/// A 3d color lookup table, as exported by grading tools like Resolve or Photoshop
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lut3d {
+    pub size: u32,
+    /// the input range of the table, [0, 1] for most luts
+    pub domain_min: [f32; 3],
+    pub domain_max: [f32; 3],
+    /// size^3 output colors, red varies fastest then green then blue
+    pub data: Vec<[f32; 3]>,
+}
+
+impl Lut3d {
+    /// Leaves the colors unchanged
+    pub fn identity(size: u32) -> Self {
+        let max = (size - 1) as f32;
+        let mut data = Vec::with_capacity(size.pow(3) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.push([r as f32 / max, g as f32 / max, b as f32 / max]);
+                }
+            }
+        }
+        Self {
+            size,
+            domain_min: [0.0; 3],
+            domain_max: [1.0; 3],
+            data,
+        }
+    }
+
+    /// Parses a .cube file. Only 3d tables are supported
+    pub fn from_cube_file(text: &str) -> Result<Self> {
+        let mut size = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut data = vec![];
+
+        for (line_index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut tokens = line.split_whitespace();
+            let keyword = tokens.next().unwrap_or_default();
+            let parse_rgb = |tokens: std::str::SplitWhitespace| -> Result<[f32; 3]> {
+                let values = tokens
+                    .map(|token| token.parse::<f32>())
+                    .collect::<Result<Vec<_>, _>>()?;
+                match values[..] {
+                    [r, g, b] => Ok([r, g, b]),
+                    _ => bail!("Expected 3 values on line {}", line_index + 1),
+                }
+            };
+            match keyword {
+                "TITLE" => {}
+                "LUT_3D_SIZE" => {
+                    let value: u32 = tokens.next().unwrap_or_default().parse()?;
+                    if value < 2 {
+                        bail!("Invalid LUT_3D_SIZE {value}");
+                    }
+                    size = Some(value);
+                }
+                "LUT_1D_SIZE" => bail!("1d luts aren't supported"),
+                "DOMAIN_MIN" => domain_min = parse_rgb(tokens)?,
+                "DOMAIN_MAX" => domain_max = parse_rgb(tokens)?,
+                _ => data.push(parse_rgb(line.split_whitespace())?),
+            }
+        }
+
+        let Some(size) = size else {
+            bail!("The file has no LUT_3D_SIZE");
+        };
+        if data.len() != size.pow(3) as usize {
+            bail!(
+                "Expected {} entries for a lut of size {size}, found {}",
+                size.pow(3),
+                data.len()
+            );
+        }
+        if (0..3).any(|channel| domain_max[channel] <= domain_min[channel]) {
+            bail!("Invalid domain {domain_min:?} - {domain_max:?}");
+        }
+
+        Ok(Self {
+            size,
+            domain_min,
+            domain_max,
+            data,
+        })
+    }
+
+    /// Parses the slices of a lut laid side by side in a size^2 x size image, each slice being
+    /// a blue value, e.g. the strips used by Unity and Unreal
+    pub fn from_strip_image(image: &image::RgbaImage) -> Result<Self> {
+        let size = image.height();
+        if size < 2 || image.width() != size * size {
+            bail!(
+                "A lut strip must be size^2 x size pixels, got {}x{}",
+                image.width(),
+                image.height()
+            );
+        }
+        let mut data = Vec::with_capacity(size.pow(3) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let [red, green, blue, _] = image.get_pixel(b * size + r, g).0;
+                    data.push([red, green, blue].map(|channel| channel as f32 / 255.0));
+                }
+            }
+        }
+        Ok(Self {
+            size,
+            domain_min: [0.0; 3],
+            domain_max: [1.0; 3],
+            data,
+        })
+    }
+
+    /// Loads a .cube file or a strip image, depending on the extension
+    pub async fn load(path: &GameFilePath) -> Result<Self> {
+        let is_cube_file = path
+            .relative_path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("cube"));
+        if is_cube_file {
+            Self::from_cube_file(&FileManager::read_to_string(path).await?)
+        } else {
+            let image = image::load_from_memory(&FileManager::read(path).await?)?;
+            Self::from_strip_image(&image.to_rgba8())
+        }
+    }
+}
+
+/// See ColorGrading::add_lut
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct LutId(usize);
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ColorGradingUniform {
+    domain_min: [[f32; 4]; 2],
+    domain_max: [[f32; 4]; 2],
+    blend_factor: [f32; 4],
+}
+
+struct BindedLut {
+    lut: Lut3d,
+    texture: Texture,
+}
+
+/// Grades the tone mapped image with up to two luts at once, so that the look can be swapped
+/// or blended per area or mood. None stands for no grading, so blending from None to a lut
+/// fades it in. Lives in RendererData::color_grading, the luts are added with
+/// Renderer::add_color_grading_lut
+pub struct ColorGrading {
+    luts: Vec<BindedLut>,
+    identity_lut: Texture,
+    from: Option<LutId>,
+    to: Option<LutId>,
+    /// 0 is fully from, 1 is fully to
+    blend_factor: f32,
+    /// blend factor per second of the fade started with fade_to
+    fade_speed: Option<f32>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    /// the luts bind_group was made with
+    bind_group_luts: (Option<LutId>, Option<LutId>),
+}
+
+impl ColorGrading {
+    pub(crate) fn new(base: &BaseRenderer, constant_data: &RendererConstantData) -> Self {
+        let device = &base.device;
+        let identity_lut = Texture::create_lut_texture(base, &Lut3d::identity(2));
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                lut_layout_entry(0),
+                lut_layout_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: USE_LABELS.then_some("color_grading_bind_group_layout"),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: USE_LABELS.then_some("Color grading shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/color_grading.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: USE_LABELS.then_some("Color grading pipeline layout"),
+            bind_group_layouts: &[
+                &constant_data.single_texture_bind_group_layout,
+                &bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: USE_LABELS.then_some("Color grading pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: wgpu::TextureFormat::Rgba16Float,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: USE_LABELS.then_some("Color grading uniform buffer"),
+            contents: bytemuck::cast_slice(&[ColorGradingUniform {
+                domain_min: [[0.0; 4]; 2],
+                domain_max: [[1.0; 4]; 2],
+                blend_factor: [0.0; 4],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group = create_bind_group(
+            base,
+            &bind_group_layout,
+            [&identity_lut, &identity_lut],
+            &uniform_buffer,
+        );
+
+        Self {
+            luts: vec![],
+            identity_lut,
+            from: None,
+            to: None,
+            blend_factor: 0.0,
+            fade_speed: None,
+            bind_group_layout,
+            pipeline,
+            uniform_buffer,
+            bind_group,
+            bind_group_luts: (None, None),
+        }
+    }
+
+    /// See Renderer::add_color_grading_lut
+    pub(crate) fn add_lut(&mut self, base: &BaseRenderer, lut: Lut3d) -> LutId {
+        let texture = Texture::create_lut_texture(base, &lut);
+        self.luts.push(BindedLut { lut, texture });
+        LutId(self.luts.len() - 1)
+    }
+
+    /// Switches to a lut right away
+    pub fn set_lut(&mut self, lut: Option<LutId>) {
+        self.blend(lut, None, 0.0);
+    }
+
+    /// Mixes two luts, e.g. based on the player's position between two areas.
+    /// Cancels the current fade
+    pub fn blend(&mut self, from: Option<LutId>, to: Option<LutId>, blend_factor: f32) {
+        self.from = from;
+        self.to = to;
+        self.blend_factor = blend_factor.clamp(0.0, 1.0);
+        self.fade_speed = None;
+    }
+
+    /// Blends from the current lut to another one over time, e.g. when the mood of the scene
+    /// changes. If a blend is in progress, the fade starts from its dominant lut
+    pub fn fade_to(&mut self, lut: Option<LutId>, duration: Duration) {
+        let current = self.dominant_lut();
+        if duration.is_zero() || current == lut {
+            self.set_lut(lut);
+            return;
+        }
+        self.from = current;
+        self.to = lut;
+        self.blend_factor = 0.0;
+        self.fade_speed = Some(1.0 / duration.as_secs_f32());
+    }
+
+    /// The lut that contributes the most to the image
+    pub fn dominant_lut(&self) -> Option<LutId> {
+        if self.blend_factor >= 0.5 {
+            self.to
+        } else {
+            self.from
+        }
+    }
+
+    /// Whether the color grading pass runs at all
+    pub fn is_active(&self) -> bool {
+        let from_active = self.from.is_some() && self.blend_factor < 1.0;
+        let to_active = self.to.is_some() && self.blend_factor > 0.0;
+        from_active || to_active
+    }
+
+    /// Advances the fade and sends the current luts to the gpu
+    pub(crate) fn update(&mut self, base: &BaseRenderer, delta_time: Duration) {
+        if let Some(fade_speed) = self.fade_speed {
+            self.blend_factor += fade_speed * delta_time.as_secs_f32();
+            if self.blend_factor >= 1.0 {
+                self.set_lut(self.to);
+            }
+        }
+
+        if self.bind_group_luts != (self.from, self.to) {
+            self.bind_group = create_bind_group(
+                base,
+                &self.bind_group_layout,
+                [self.lut_texture(self.from), self.lut_texture(self.to)],
+                &self.uniform_buffer,
+            );
+            self.bind_group_luts = (self.from, self.to);
+        }
+
+        let domain = |lut_id: Option<LutId>| {
+            let lut = lut_id.and_then(|lut_id| self.luts.get(lut_id.0));
+            let (min, max) = lut.map_or(([0.0; 3], [1.0; 3]), |binded| {
+                (binded.lut.domain_min, binded.lut.domain_max)
+            });
+            ([min[0], min[1], min[2], 0.0], [max[0], max[1], max[2], 0.0])
+        };
+        let (from_min, from_max) = domain(self.from);
+        let (to_min, to_max) = domain(self.to);
+        base.queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[ColorGradingUniform {
+                domain_min: [from_min, to_min],
+                domain_max: [from_max, to_max],
+                blend_factor: [self.blend_factor, 0.0, 0.0, 0.0],
+            }]),
+        );
+    }
+
+    pub(crate) fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    pub(crate) fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// After the gpu device was recreated. The luts are uploaded again from their cpu copies
+    pub(crate) fn recreate(&mut self, base: &BaseRenderer, constant_data: &RendererConstantData) {
+        let luts: Vec<_> = self.luts.drain(..).map(|binded| binded.lut).collect();
+        let (from, to, blend_factor, fade_speed) =
+            (self.from, self.to, self.blend_factor, self.fade_speed);
+        *self = Self::new(base, constant_data);
+        for lut in luts {
+            self.add_lut(base, lut);
+        }
+        (self.from, self.to, self.blend_factor, self.fade_speed) =
+            (from, to, blend_factor, fade_speed);
+    }
+
+    fn lut_texture(&self, lut_id: Option<LutId>) -> &Texture {
+        lut_id
+            .and_then(|lut_id| self.luts.get(lut_id.0))
+            .map_or(&self.identity_lut, |binded| &binded.texture)
+    }
+}
+
+fn lut_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D3,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    }
+}
+
+fn create_bind_group(
+    base: &BaseRenderer,
+    layout: &wgpu::BindGroupLayout,
+    luts: [&Texture; 2],
+    uniform_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    let sampler_cache_guard = base.sampler_cache.lock().unwrap();
+    base.device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&luts[0].view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&luts[1].view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(
+                    sampler_cache_guard.get_sampler_by_index(luts[0].sampler_index),
+                ),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+        ],
+        label: USE_LABELS.then_some("color_grading_bind_group"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cube_file_entries_are_red_major() {
+        let lut = Lut3d::from_cube_file(
+            "TITLE \"test\"\n\
+             # comment\n\
+             LUT_3D_SIZE 2\n\
+             DOMAIN_MIN 0 0 0\n\
+             DOMAIN_MAX 1 1 1\n\
+             0 0 0\n1 0 0\n0 1 0\n1 1 0\n\
+             0 0 1\n1 0 1\n0 1 1\n1 1 1\n",
+        )
+        .unwrap();
+        assert_eq!(lut, Lut3d::identity(2));
+
+        assert!(Lut3d::from_cube_file("LUT_3D_SIZE 2\n0 0 0\n").is_err());
+        assert!(Lut3d::from_cube_file("LUT_1D_SIZE 2\n0 0 0\n1 1 1\n").is_err());
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::gpu_diagnostics::GpuDiagnostics;
+use crate::logging::recent_log_entries;
+
+use lazy_static::lazy_static;
+
+static IS_PANIC_HOOK_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref CRASH_HANDLER_STATE: Mutex<CrashHandlerState> = Mutex::new(CrashHandlerState {
+        output_dir: PathBuf::from("crash_reports"),
+        gpu_diagnostics: None,
+        sections: BTreeMap::new(),
+    });
+}
+
+struct CrashHandlerState {
+    output_dir: PathBuf,
+    gpu_diagnostics: Option<GpuDiagnostics>,
+    /// file name -> contents
+    sections: BTreeMap<String, String>,
+}
+
+/// The files of a diagnostic report, written to disk as a zip archive by write
+#[derive(Debug, Clone)]
+pub struct DiagnosticReport {
+    pub reason: String,
+    /// file name -> contents
+    pub files: BTreeMap<String, String>,
+}
+
+impl DiagnosticReport {
+    /// Includes the recent log entries, the sections set with set_report_section
+    /// and the gpu diagnostics of the renderer if there is one
+    pub fn collect(reason: &str) -> Self {
+        let mut files = BTreeMap::new();
+
+        let mut summary = String::new();
+        let _ = writeln!(summary, "ikari diagnostic report");
+        let _ = writeln!(summary, "Reason: {reason}");
+        let _ = writeln!(summary, "Time: {}", chrono::Local::now().to_rfc3339());
+        let _ = writeln!(
+            summary,
+            "Version: {}, os: {}, arch: {}",
+            env!("CARGO_PKG_VERSION"),
+            std::env::consts::OS,
+            std::env::consts::ARCH
+        );
+        files.insert("summary.txt".to_string(), summary);
+
+        let mut log = String::new();
+        for entry in recent_log_entries() {
+            let _ = writeln!(log, "{entry}");
+        }
+        if log.is_empty() {
+            log = "(none captured, use logging::Logger to include log lines)\n".to_string();
+        }
+        files.insert("log.txt".to_string(), log);
+
+        // the panic can happen while the state is locked, e.g. from a bad section
+        if let Ok(state) = CRASH_HANDLER_STATE.try_lock() {
+            if let Some(gpu_diagnostics) = &state.gpu_diagnostics {
+                files.insert("gpu.txt".to_string(), gpu_diagnostics.build_report(reason));
+            }
+            for (name, contents) in &state.sections {
+                files.insert(name.clone(), contents.clone());
+            }
+        }
+
+        Self {
+            reason: reason.to_string(),
+            files,
+        }
+    }
+
+    /// Writes the report to a new zip file in the output dir and returns its path.
+    /// On the web the report is written to the console instead
+    pub fn write(&self) -> Option<PathBuf> {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let output_dir = CRASH_HANDLER_STATE
+                .try_lock()
+                .map(|state| state.output_dir.clone())
+                .unwrap_or_else(|_| PathBuf::from("crash_reports"));
+            let path = output_dir.join(format!(
+                "ikari_report_{}.zip",
+                chrono::Local::now().format("%Y%m%d_%H%M%S")
+            ));
+            match self.write_zip(&path) {
+                Ok(_) => {
+                    log::error!(
+                        "{}. Wrote a diagnostic report to {}",
+                        self.reason,
+                        path.display()
+                    );
+                    Some(path)
+                }
+                Err(err) => {
+                    log::error!(
+                        "{}. Failed to write a diagnostic report to {}: {err}\n{}",
+                        self.reason,
+                        path.display(),
+                        self.files.get("summary.txt").cloned().unwrap_or_default()
+                    );
+                    None
+                }
+            }
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            let mut report = String::new();
+            for (name, contents) in &self.files {
+                let _ = writeln!(report, "== {name} ==\n{contents}");
+            }
+            log::error!("{}\n{report}", self.reason);
+            None
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn write_zip(&self, path: &std::path::Path) -> anyhow::Result<()> {
+        use std::io::Write;
+
+        if let Some(output_dir) = path.parent() {
+            std::fs::create_dir_all(output_dir)?;
+        }
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(path)?);
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        for (name, contents) in &self.files {
+            zip.start_file(name.as_str(), options)?;
+            zip.write_all(contents.as_bytes())?;
+        }
+        zip.finish()?;
+        Ok(())
+    }
+}
+
+/// Where the reports get written, defaults to ./crash_reports
+pub fn set_output_dir(output_dir: PathBuf) {
+    CRASH_HANDLER_STATE.lock().unwrap().output_dir = output_dir;
+}
+
+/// Adds a file to the future reports or replaces it, e.g. the game's settings
+pub fn set_report_section(file_name: &str, contents: String) {
+    CRASH_HANDLER_STATE
+        .lock()
+        .unwrap()
+        .sections
+        .insert(file_name.to_string(), contents);
+}
+
+/// Called by the renderer so the reports include the adapter info and what it was doing
+pub(crate) fn set_gpu_diagnostics(gpu_diagnostics: GpuDiagnostics) {
+    CRASH_HANDLER_STATE.lock().unwrap().gpu_diagnostics = Some(gpu_diagnostics);
+}
+
+pub fn is_panic_hook_installed() -> bool {
+    IS_PANIC_HOOK_INSTALLED.load(Ordering::SeqCst)
+}
+
+/// Writes a DiagnosticReport with the panic message and its backtrace whenever a thread panics,
+/// then calls the hook that was set before, e.g. the one that prints the panic
+pub fn install_panic_hook() {
+    if IS_PANIC_HOOK_INSTALLED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let previous_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| info.payload().downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "(no message)".to_string());
+        let location = info
+            .location()
+            .map(|location| location.to_string())
+            .unwrap_or_default();
+        let thread = std::thread::current();
+        let thread_name = thread.name().unwrap_or("unnamed thread");
+
+        let mut report = DiagnosticReport::collect(&format!("Panic: {message}"));
+        report.files.insert(
+            "panic.txt".to_string(),
+            format!(
+                "Thread '{thread_name}' panicked at {location}:\n{message}\n\nBacktrace:\n{}",
+                std::backtrace::Backtrace::force_capture()
+            ),
+        );
+        report.write();
+
+        previous_hook(info);
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_include_the_sections() {
+        set_report_section("settings.toml", "vsync = true".to_string());
+
+        let report = DiagnosticReport::collect("test");
+
+        assert_eq!(report.files["settings.toml"], "vsync = true");
+        assert!(report.files["summary.txt"].contains("Reason: test"));
+        assert!(report.files.contains_key("log.txt"));
+    }
+}
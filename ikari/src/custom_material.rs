@@ -0,0 +1,295 @@
+use crate::mesh::Vertex;
+use crate::renderer::USE_LABELS;
+use crate::texture::Texture;
+use crate::wasm_not_sync::{WasmNotSend, WasmNotSync};
+
+use anyhow::{bail, Result};
+
+/// The vertex attributes read by a custom material's shader. They're at the same locations as in
+/// the built-in shaders: position 0, normal 1, tex_coords 2, tangent 3, bitangent 4, color 5,
+/// bone indices 6, bone weights 7 and lightmap_tex_coords 8
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct VertexRequirements {
+    pub normal: bool,
+    pub tex_coords: bool,
+    /// the tangent and the bitangent
+    pub tangents: bool,
+    pub color: bool,
+    /// the bone indices and weights
+    pub skinning: bool,
+    pub lightmap_tex_coords: bool,
+}
+
+impl VertexRequirements {
+    fn attributes(&self) -> Vec<wgpu::VertexAttribute> {
+        let [position, normal, tex_coords, tangent, bitangent, color, bone_indices, bone_weights, lightmap_tex_coords] =
+            Vertex::ATTRIBS;
+        let mut attributes = vec![position];
+        if self.normal {
+            attributes.push(normal);
+        }
+        if self.tex_coords {
+            attributes.push(tex_coords);
+        }
+        if self.tangents {
+            attributes.extend([tangent, bitangent]);
+        }
+        if self.color {
+            attributes.push(color);
+        }
+        if self.skinning {
+            attributes.extend([bone_indices, bone_weights]);
+        }
+        if self.lightmap_tex_coords {
+            attributes.push(lightmap_tex_coords);
+        }
+        attributes
+    }
+}
+
+/// A material written in WGSL by the game, see Material::Custom. Its shader has vs_main and
+/// fs_main entry points and gets the same bind groups as the built-in unlit shader:
+/// - group(0): the camera at binding(0) and the frame constants at binding(4)
+/// - group(1): the bones at binding(0) and the instances at binding(1), which are the model
+///   transform followed by Material::Custom::params
+/// - group(2): the user data, if user_data_layout_entries isn't empty
+///
+/// The meshes are opaque and drawn with the unlit ones, into a single Rgba16Float target
+pub trait CustomMaterial: WasmNotSend + WasmNotSync {
+    fn label(&self) -> &str;
+
+    fn shader_source(&self) -> String;
+
+    /// The layout of the user data bind group, whose bind groups are made with
+    /// CustomMaterials::bind_user_data
+    fn user_data_layout_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+        vec![]
+    }
+
+    fn vertex_requirements(&self) -> VertexRequirements {
+        VertexRequirements::default()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CustomMaterialId(usize);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CustomMaterialDataId(usize);
+
+struct RegisteredCustomMaterial {
+    material: Box<dyn CustomMaterial>,
+    user_data_layout: Option<wgpu::BindGroupLayout>,
+    /// created the first time the material is drawn
+    pipeline: Option<wgpu::RenderPipeline>,
+}
+
+struct CustomMaterialData {
+    material_id: CustomMaterialId,
+    bind_group: wgpu::BindGroup,
+}
+
+/// The custom materials registered by the game, in RendererData::custom_materials
+#[derive(Default)]
+pub struct CustomMaterials {
+    materials: Vec<RegisteredCustomMaterial>,
+    user_data: Vec<Option<CustomMaterialData>>,
+}
+
+impl CustomMaterials {
+    pub fn register(
+        &mut self,
+        device: &wgpu::Device,
+        material: impl CustomMaterial + 'static,
+    ) -> CustomMaterialId {
+        let material: Box<dyn CustomMaterial> = Box::new(material);
+        self.materials.push(RegisteredCustomMaterial {
+            user_data_layout: create_user_data_layout(device, material.as_ref()),
+            material,
+            pipeline: None,
+        });
+        CustomMaterialId(self.materials.len() - 1)
+    }
+
+    /// entries must match the material's user_data_layout_entries
+    pub fn bind_user_data(
+        &mut self,
+        device: &wgpu::Device,
+        material_id: CustomMaterialId,
+        entries: &[wgpu::BindGroupEntry],
+    ) -> Result<CustomMaterialDataId> {
+        let Some(registered) = self.materials.get(material_id.0) else {
+            bail!("There's no custom material {material_id:?}");
+        };
+        let Some(layout) = &registered.user_data_layout else {
+            bail!(
+                "Custom material {} doesn't take any user data",
+                registered.material.label()
+            );
+        };
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries,
+            label: USE_LABELS.then_some(registered.material.label()),
+        });
+        self.user_data.push(Some(CustomMaterialData {
+            material_id,
+            bind_group,
+        }));
+        Ok(CustomMaterialDataId(self.user_data.len() - 1))
+    }
+
+    /// The data id must not be used by any material anymore
+    pub fn unbind_user_data(&mut self, data_id: CustomMaterialDataId) {
+        if let Some(data) = self.user_data.get_mut(data_id.0) {
+            *data = None;
+        }
+    }
+
+    pub fn label(&self, material_id: CustomMaterialId) -> Option<&str> {
+        self.materials
+            .get(material_id.0)
+            .map(|registered| registered.material.label())
+    }
+
+    /// Creates the pipelines of the materials that are about to be drawn for the first time
+    pub(crate) fn prepare_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        shared_bind_group_layouts: [&wgpu::BindGroupLayout; 2],
+        material_ids: impl Iterator<Item = CustomMaterialId>,
+    ) {
+        for material_id in material_ids {
+            let Some(registered) = self.materials.get_mut(material_id.0) else {
+                continue;
+            };
+            if registered.pipeline.is_none() {
+                registered.pipeline = Some(create_pipeline(
+                    device,
+                    shared_bind_group_layouts,
+                    registered,
+                ));
+            }
+        }
+    }
+
+    pub(crate) fn pipeline(&self, material_id: CustomMaterialId) -> Option<&wgpu::RenderPipeline> {
+        self.materials.get(material_id.0)?.pipeline.as_ref()
+    }
+
+    /// None if the material takes user data and data_id is missing or belongs to another material
+    pub(crate) fn user_data_bind_group(
+        &self,
+        material_id: CustomMaterialId,
+        data_id: Option<CustomMaterialDataId>,
+    ) -> Option<Option<&wgpu::BindGroup>> {
+        let registered = self.materials.get(material_id.0)?;
+        if registered.user_data_layout.is_none() {
+            return Some(None);
+        }
+        let data = self.user_data.get(data_id?.0)?.as_ref()?;
+        (data.material_id == material_id).then_some(Some(&data.bind_group))
+    }
+
+    /// After the gpu device was recreated. The user data is made of the old device's resources so
+    /// it's dropped and has to be bound again
+    pub(crate) fn recreate(&mut self, device: &wgpu::Device) {
+        for registered in &mut self.materials {
+            registered.user_data_layout =
+                create_user_data_layout(device, registered.material.as_ref());
+            registered.pipeline = None;
+        }
+        for data in &mut self.user_data {
+            *data = None;
+        }
+    }
+}
+
+fn create_user_data_layout(
+    device: &wgpu::Device,
+    material: &dyn CustomMaterial,
+) -> Option<wgpu::BindGroupLayout> {
+    let entries = material.user_data_layout_entries();
+    (!entries.is_empty()).then(|| {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &entries,
+            label: USE_LABELS.then_some(material.label()),
+        })
+    })
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    shared_bind_group_layouts: [&wgpu::BindGroupLayout; 2],
+    registered: &RegisteredCustomMaterial,
+) -> wgpu::RenderPipeline {
+    let label = registered.material.label();
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: USE_LABELS.then_some(label),
+        source: wgpu::ShaderSource::Wgsl(registered.material.shader_source().into()),
+    });
+    let mut bind_group_layouts = shared_bind_group_layouts.to_vec();
+    bind_group_layouts.extend(registered.user_data_layout.as_ref());
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: USE_LABELS.then_some(label),
+        bind_group_layouts: &bind_group_layouts,
+        push_constant_ranges: &[],
+    });
+    let attributes = registered.material.vertex_requirements().attributes();
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: USE_LABELS.then_some(label),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[wgpu::VertexBufferLayout {
+                array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &attributes,
+            }],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: wgpu::TextureFormat::Rgba16Float,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            cull_mode: Some(wgpu::Face::Back),
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::GreaterEqual,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vertex_requirements_keep_the_builtin_locations() {
+        let requirements = VertexRequirements {
+            tex_coords: true,
+            skinning: true,
+            ..Default::default()
+        };
+        let locations: Vec<u32> = requirements
+            .attributes()
+            .iter()
+            .map(|attribute| attribute.shader_location)
+            .collect();
+        assert_eq!(locations, vec![0, 2, 6, 7]);
+    }
+}
@@ -0,0 +1,262 @@
+use crate::asset_registry::MeshHandle;
+use crate::collisions::Aabb;
+use crate::dynamic_mesh::DynamicMesh;
+use crate::mesh::Vertex;
+use crate::renderer::{BaseRenderer, RendererData};
+use crate::scene::{GameNodeDesc, GameNodeId, GameNodeVisual, Material, Scene};
+
+use std::collections::HashMap;
+
+use glam::f32::{Mat4, Vec3};
+
+pub const DEFAULT_DEBUG_LINE_THICKNESS: f32 = 0.02;
+const CIRCLE_SEGMENT_COUNT: usize = 24;
+
+/// the bits of the color, so it can be used as a key
+type ColorKey = [u32; 3];
+
+#[derive(Debug)]
+struct DebugDrawBatch {
+    mesh: DynamicMesh,
+    node_id: GameNodeId,
+}
+
+/// Immediate mode debug shapes: queue lines, boxes, spheres and arrows during the frame and
+/// flush them once it's done, they're gone on the next one unless queued again.
+/// The lines of each color end up in a DynamicMesh drawn unlit, so they're lit the same from
+/// any angle but get hidden behind the geometry like any other mesh
+#[derive(Debug)]
+pub struct DebugDraw {
+    /// in world units
+    pub line_thickness: f32,
+    lines: HashMap<ColorKey, Vec<(Vec3, Vec3)>>,
+    batches: HashMap<ColorKey, DebugDrawBatch>,
+}
+
+impl Default for DebugDraw {
+    fn default() -> Self {
+        Self {
+            line_thickness: DEFAULT_DEBUG_LINE_THICKNESS,
+            lines: HashMap::new(),
+            batches: HashMap::new(),
+        }
+    }
+}
+
+impl DebugDraw {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn line(&mut self, start: Vec3, end: Vec3, color: Vec3) {
+        self.lines
+            .entry(color_key(color))
+            .or_default()
+            .push((start, end));
+    }
+
+    /// the head is a fifth of the arrow's length
+    pub fn arrow(&mut self, start: Vec3, end: Vec3, color: Vec3) {
+        self.line(start, end, color);
+        let Some(direction) = (end - start).try_normalize() else {
+            return;
+        };
+        let head_length = (end - start).length() * 0.2;
+        let (side_a, side_b) = direction.any_orthonormal_pair();
+        for side in [side_a, -side_a, side_b, -side_b] {
+            self.line(
+                end,
+                end - head_length * direction + 0.5 * head_length * side,
+                color,
+            );
+        }
+    }
+
+    /// a small cross
+    pub fn point(&mut self, position: Vec3, size: f32, color: Vec3) {
+        for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+            self.line(
+                position - 0.5 * size * axis,
+                position + 0.5 * size * axis,
+                color,
+            );
+        }
+    }
+
+    pub fn circle(&mut self, center: Vec3, normal: Vec3, radius: f32, color: Vec3) {
+        let (axis_a, axis_b) = normal.normalize_or_zero().any_orthonormal_pair();
+        let point_at = |segment: usize| {
+            let angle = segment as f32 / CIRCLE_SEGMENT_COUNT as f32 * std::f32::consts::TAU;
+            center + radius * (angle.cos() * axis_a + angle.sin() * axis_b)
+        };
+        for segment in 0..CIRCLE_SEGMENT_COUNT {
+            self.line(point_at(segment), point_at(segment + 1), color);
+        }
+    }
+
+    /// three great circles
+    pub fn sphere(&mut self, center: Vec3, radius: f32, color: Vec3) {
+        for normal in [Vec3::X, Vec3::Y, Vec3::Z] {
+            self.circle(center, normal, radius, color);
+        }
+    }
+
+    pub fn aabb(&mut self, aabb: &Aabb, color: Vec3) {
+        self.oriented_box(
+            Mat4::from_translation(aabb.center()),
+            aabb.size() / 2.0,
+            color,
+        );
+    }
+
+    /// the edges of the box of the given half extents, centered on the transform's origin
+    pub fn oriented_box(&mut self, transform: Mat4, half_extents: Vec3, color: Vec3) {
+        let corner = |index: usize| {
+            let sign = |bit: usize| if index & bit == 0 { -1.0 } else { 1.0 };
+            transform.transform_point3(half_extents * Vec3::new(sign(1), sign(2), sign(4)))
+        };
+        for index in 0..8 {
+            for bit in [1, 2, 4] {
+                if index & bit == 0 {
+                    self.line(corner(index), corner(index | bit), color);
+                }
+            }
+        }
+    }
+
+    /// Drops what was queued since the last flush
+    pub fn clear(&mut self) {
+        for lines in self.lines.values_mut() {
+            lines.clear();
+        }
+    }
+
+    /// Call from GameState::on_device_recreated
+    pub fn on_device_recreated(&mut self) {
+        for batch in self.batches.values_mut() {
+            batch.mesh.on_device_recreated();
+        }
+    }
+
+    /// Uploads the queued shapes and shows them until the next flush, once per frame
+    #[profiling::function]
+    pub fn flush(&mut self, base: &BaseRenderer, data: &mut RendererData, scene: &mut Scene) {
+        for (color_key, lines) in self.lines.iter_mut() {
+            let mut vertices = vec![];
+            let mut indices = vec![];
+            for (start, end) in lines.drain(..) {
+                push_line(&mut vertices, &mut indices, start, end, self.line_thickness);
+            }
+
+            if !self.batches.contains_key(color_key) && !indices.is_empty() {
+                let mesh = DynamicMesh::new(base, data, vec![], vec![]);
+                let node_id = add_batch_node(scene, *color_key, mesh.mesh());
+                self.batches
+                    .insert(*color_key, DebugDrawBatch { mesh, node_id });
+            }
+            let Some(batch) = self.batches.get_mut(color_key) else {
+                continue;
+            };
+
+            // the node is gone if the scene was cleared, e.g. on a level change
+            if scene.get_node(batch.node_id).is_none() {
+                batch.node_id = add_batch_node(scene, *color_key, batch.mesh.mesh());
+            }
+            let is_visible = !indices.is_empty();
+            if let Some(node) = scene.get_node_mut(batch.node_id) {
+                node.visible = is_visible;
+            }
+            if is_visible {
+                batch.mesh.set_vertices(vertices);
+                batch.mesh.set_indices(indices);
+                batch.mesh.upload(base, data);
+            }
+        }
+    }
+}
+
+fn add_batch_node(scene: &mut Scene, color_key: ColorKey, mesh: MeshHandle) -> GameNodeId {
+    scene
+        .add_node(GameNodeDesc {
+            visual: Some(GameNodeVisual {
+                material: Material::Unlit {
+                    color: color_from_key(color_key),
+                },
+                mesh,
+                wireframe: false,
+                cullable: false,
+            }),
+            name: Some("Debug draw".to_string()),
+            casts_shadows: false,
+            ..Default::default()
+        })
+        .id()
+}
+
+fn color_key(color: Vec3) -> ColorKey {
+    color.to_array().map(f32::to_bits)
+}
+
+fn color_from_key(color_key: ColorKey) -> Vec3 {
+    Vec3::from_array(color_key.map(f32::from_bits))
+}
+
+/// Two crossed quads along the line, each with both windings so they show from any side
+fn push_line(
+    vertices: &mut Vec<Vertex>,
+    indices: &mut Vec<u32>,
+    start: Vec3,
+    end: Vec3,
+    thickness: f32,
+) {
+    let Some(direction) = (end - start).try_normalize() else {
+        return;
+    };
+    let (side_a, side_b) = direction.any_orthonormal_pair();
+    for side in [side_a, side_b] {
+        let offset = 0.5 * thickness * side;
+        let first_vertex = vertices.len() as u32;
+        vertices.extend(
+            [start - offset, start + offset, end + offset, end - offset].map(|position| Vertex {
+                position: position.into(),
+                ..Default::default()
+            }),
+        );
+        indices.extend([0, 1, 2, 0, 2, 3, 0, 2, 1, 0, 3, 2].map(|index| first_vertex + index));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_double_sided_and_degenerate_ones_are_skipped() {
+        let mut vertices = vec![];
+        let mut indices = vec![];
+
+        push_line(&mut vertices, &mut indices, Vec3::ZERO, Vec3::Y, 0.1);
+        push_line(&mut vertices, &mut indices, Vec3::X, Vec3::X, 0.1);
+
+        assert_eq!(vertices.len(), 8);
+        assert_eq!(indices.len(), 24);
+        for vertex in &vertices {
+            let position = Vec3::from(vertex.position);
+            let distance_to_line = Vec3::new(position.x, 0.0, position.z).length();
+            assert!((distance_to_line - 0.05).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn boxes_have_twelve_edges() {
+        let mut debug_draw = DebugDraw::new();
+
+        debug_draw.oriented_box(Mat4::IDENTITY, Vec3::ONE, Vec3::X);
+
+        let lines = &debug_draw.lines[&color_key(Vec3::X)];
+        assert_eq!(lines.len(), 12);
+        for (start, end) in lines {
+            assert!(((*end - *start).length() - 2.0).abs() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use crate::asset_registry::{MaterialHandle, MeshHandle};
+use crate::effects::DecalDefinition;
+use crate::mesh::{BasicMesh, DynamicPbrParams, Vertex};
+use crate::renderer::Renderer;
+use crate::scene::{GameNodeDesc, GameNodeId, GameNodeVisual, Material, Scene};
+use crate::transform::{Transform, TransformBuilder};
+use crate::weapon::WeaponHit;
+
+use std::collections::{HashMap, VecDeque};
+
+use glam::f32::{Quat, Vec2, Vec3, Vec4};
+
+/// per category, see Decals::set_max_count
+pub const DEFAULT_MAX_DECALS_PER_CATEGORY: usize = 256;
+/// the decals are pushed this far out of the surface so they don't z-fight with it
+const DECAL_SURFACE_OFFSET: f32 = 0.005;
+const DECAL_ALPHA_CUTOFF: f32 = 0.5;
+
+/// What a decal looks like and how long it stays
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DecalParams {
+    /// a pbr material whose base color alpha is the decal's shape, it's cut out at 0.5.
+    /// The decal isn't metallic, the other params of the material are ignored
+    pub material: MaterialHandle,
+    pub size: Vec2,
+    pub color: Vec4,
+    pub lifetime_seconds: f32,
+    /// the decal fades out during the last fade_out_seconds of its lifetime, its alpha
+    /// going down erodes it from the edges of its shape
+    pub fade_out_seconds: f32,
+    /// randomize the rotation of the decal around the surface's normal
+    pub random_rotation: bool,
+}
+
+impl DecalParams {
+    pub fn from_definition(definition: &DecalDefinition, material: MaterialHandle) -> Self {
+        Self {
+            material,
+            size: Vec2::from(definition.size),
+            color: Vec4::from(definition.color),
+            lifetime_seconds: definition.lifetime_seconds,
+            fade_out_seconds: definition.fade_out_seconds,
+            random_rotation: definition.random_rotation,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ActiveDecal {
+    node_id: GameNodeId,
+    params: DecalParams,
+    age_seconds: f32,
+}
+
+#[derive(Debug)]
+struct DecalPool {
+    max_count: usize,
+    /// oldest first
+    active: VecDeque<ActiveDecal>,
+    /// hidden nodes of the expired decals, reused by the next ones
+    free_node_ids: Vec<GameNodeId>,
+}
+
+impl DecalPool {
+    fn new(max_count: usize) -> Self {
+        Self {
+            max_count,
+            active: VecDeque::new(),
+            free_node_ids: vec![],
+        }
+    }
+}
+
+/// Bullet holes, blood splats, scorch marks... The decals are quads laid on the surfaces, grouped
+/// in categories that each have a maximum count: past it the oldest decal of the category is
+/// moved to the new spot, so shooting a wall for a while costs no more than max_count decals.
+/// The nodes of the expired decals are hidden and reused instead of removed
+#[derive(Debug)]
+pub struct Decals {
+    quad_mesh: MeshHandle,
+    pools: HashMap<String, DecalPool>,
+}
+
+impl Decals {
+    pub fn new(renderer: &Renderer) -> Self {
+        let quad_mesh = Renderer::bind_basic_mesh(
+            &renderer.base,
+            &mut renderer.data.lock().unwrap(),
+            &decal_quad(),
+            false,
+        );
+        Self {
+            quad_mesh,
+            pools: HashMap::new(),
+        }
+    }
+
+    /// Lowering it removes the oldest decals of the category on the next update
+    pub fn set_max_count(&mut self, category: &str, max_count: usize) {
+        self.pool_mut(category).max_count = max_count;
+    }
+
+    pub fn count(&self, category: &str) -> usize {
+        self.pools.get(category).map_or(0, |pool| pool.active.len())
+    }
+
+    /// Places a decal on the surface at position, facing along its normal. An attached decal
+    /// follows the node, e.g. a ball that was shot, and is removed along with it
+    pub fn spawn(
+        &mut self,
+        scene: &mut Scene,
+        category: &str,
+        params: DecalParams,
+        position: Vec3,
+        normal: Vec3,
+        attach_to: Option<GameNodeId>,
+    ) -> GameNodeId {
+        let quad_mesh = self.quad_mesh;
+        let pool = self.pool_mut(category);
+
+        let rotation = if params.random_rotation {
+            rand::random::<f32>() * std::f32::consts::TAU
+        } else {
+            0.0
+        };
+        let mut transform = decal_transform(position, normal, params.size, rotation);
+        let parent_id = attach_to.filter(|node_id| scene.get_node(*node_id).is_some());
+        if let Some(parent_id) = parent_id {
+            let parent_transform = scene.get_global_transform_for_node(parent_id);
+            transform = Transform::from(parent_transform.inverse() * *transform);
+        }
+        let visual = GameNodeVisual::from_mesh_mat(
+            quad_mesh,
+            Material::Pbr {
+                binded_material: params.material,
+                dynamic_pbr_params: Some(decal_pbr_params(params.color)),
+            },
+        );
+
+        let recycled_node_id = if pool.active.len() >= pool.max_count.max(1) {
+            pool.active.pop_front().map(|decal| decal.node_id)
+        } else {
+            pool.free_node_ids.pop()
+        };
+        let node_id = match recycled_node_id.and_then(|node_id| scene.get_node_mut(node_id)) {
+            Some(node) => {
+                node.transform = transform;
+                node.parent_id = parent_id;
+                node.visual = Some(visual);
+                node.visible = true;
+                node.reset_interpolation();
+                node.id()
+            }
+            None => scene
+                .add_node(GameNodeDesc {
+                    transform,
+                    visual: Some(visual),
+                    name: Some(format!("{category} decal")),
+                    parent_id,
+                    casts_shadows: false,
+                    ..Default::default()
+                })
+                .id(),
+        };
+        pool.active.push_back(ActiveDecal {
+            node_id,
+            params,
+            age_seconds: 0.0,
+        });
+        node_id
+    }
+
+    /// Places the decal where the shot hit, attached to the node that was hit
+    pub fn spawn_at_hit(
+        &mut self,
+        scene: &mut Scene,
+        category: &str,
+        params: DecalParams,
+        hit: &WeaponHit,
+    ) -> GameNodeId {
+        self.spawn(
+            scene,
+            category,
+            params,
+            hit.position,
+            hit.normal,
+            hit.node_id,
+        )
+    }
+
+    /// Ages the decals, fading them out and hiding the expired ones
+    pub fn update(&mut self, scene: &mut Scene, delta_time_seconds: f32) {
+        for pool in self.pools.values_mut() {
+            let excess_count = pool.active.len().saturating_sub(pool.max_count);
+            let mut expired: Vec<_> = pool.active.drain(..excess_count).collect();
+
+            let mut still_active = VecDeque::with_capacity(pool.active.len());
+            for mut decal in pool.active.drain(..) {
+                decal.age_seconds += delta_time_seconds;
+                let remaining_seconds = decal.params.lifetime_seconds - decal.age_seconds;
+                let Some(node) = scene.get_node(decal.node_id) else {
+                    continue;
+                };
+                // the node it was attached to is gone
+                let is_orphan = node
+                    .parent_id
+                    .is_some_and(|parent_id| scene.get_node(parent_id).is_none());
+                if remaining_seconds <= 0.0 || is_orphan {
+                    expired.push(decal);
+                    continue;
+                }
+                if remaining_seconds < decal.params.fade_out_seconds {
+                    let opacity = remaining_seconds / decal.params.fade_out_seconds;
+                    set_decal_color(
+                        scene,
+                        decal.node_id,
+                        decal.params.color * Vec4::new(1.0, 1.0, 1.0, opacity),
+                    );
+                }
+                still_active.push_back(decal);
+            }
+            pool.active = still_active;
+
+            for decal in expired {
+                if let Some(node) = scene.get_node_mut(decal.node_id) {
+                    node.visible = false;
+                    node.parent_id = None;
+                    pool.free_node_ids.push(decal.node_id);
+                }
+            }
+        }
+    }
+
+    /// Removes the nodes of all the decals
+    pub fn clear(&mut self, scene: &mut Scene) {
+        for pool in self.pools.values_mut() {
+            for decal in pool.active.drain(..) {
+                scene.remove_node(decal.node_id);
+            }
+            for node_id in pool.free_node_ids.drain(..) {
+                scene.remove_node(node_id);
+            }
+        }
+    }
+
+    fn pool_mut(&mut self, category: &str) -> &mut DecalPool {
+        self.pools
+            .entry(category.to_string())
+            .or_insert_with(|| DecalPool::new(DEFAULT_MAX_DECALS_PER_CATEGORY))
+    }
+}
+
+/// The transform of a decal quad of the given size lying on a surface, rotated by rotation
+/// radians around the surface's normal
+pub fn decal_transform(position: Vec3, normal: Vec3, size: Vec2, rotation: f32) -> Transform {
+    let normal = normal.try_normalize().unwrap_or(Vec3::Y);
+    TransformBuilder::new()
+        .position(position + normal * DECAL_SURFACE_OFFSET)
+        .rotation(Quat::from_rotation_arc(Vec3::Z, normal) * Quat::from_rotation_z(rotation))
+        .scale(size.extend(1.0))
+        .build()
+}
+
+fn decal_pbr_params(color: Vec4) -> DynamicPbrParams {
+    DynamicPbrParams {
+        base_color_factor: color,
+        metallic_factor: 0.0,
+        alpha_cutoff: DECAL_ALPHA_CUTOFF,
+        ..Default::default()
+    }
+}
+
+fn set_decal_color(scene: &mut Scene, node_id: GameNodeId, color: Vec4) {
+    let material = scene
+        .get_node_mut(node_id)
+        .and_then(|node| node.visual.as_mut())
+        .map(|visual| &mut visual.material);
+    if let Some(Material::Pbr {
+        dynamic_pbr_params: Some(dynamic_pbr_params),
+        ..
+    }) = material
+    {
+        dynamic_pbr_params.base_color_factor = color;
+    }
+}
+
+/// A unit quad on the xy plane, facing +z
+pub(crate) fn decal_quad() -> BasicMesh {
+    BasicMesh {
+        vertices: [[-0.5, -0.5], [0.5, -0.5], [0.5, 0.5], [-0.5, 0.5]]
+            .iter()
+            .map(|[x, y]| Vertex {
+                position: [*x, *y, 0.0],
+                normal: [0.0, 0.0, 1.0],
+                tex_coords: [x + 0.5, 0.5 - y],
+                tangent: [1.0, 0.0, 0.0],
+                bitangent: [0.0, -1.0, 0.0],
+                ..Default::default()
+            })
+            .collect(),
+        indices: vec![0, 1, 2, 0, 2, 3],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decals_are_recycled_once_a_category_is_full_and_fade_out() {
+        let mut scene = Scene::default();
+        let mut decals = Decals {
+            quad_mesh: MeshHandle::unbinded(0),
+            pools: HashMap::new(),
+        };
+        decals.set_max_count("bullet_hole", 2);
+        let params = DecalParams {
+            material: MaterialHandle::unbinded(0),
+            size: Vec2::new(0.1, 0.1),
+            color: Vec4::ONE,
+            lifetime_seconds: 10.0,
+            fade_out_seconds: 2.0,
+            random_rotation: false,
+        };
+        let spawn = |decals: &mut Decals, scene: &mut Scene, x: f32| {
+            decals.spawn(
+                scene,
+                "bullet_hole",
+                params,
+                Vec3::new(x, 0.0, 0.0),
+                Vec3::Y,
+                None,
+            )
+        };
+
+        let first_node_id = spawn(&mut decals, &mut scene, 0.0);
+        spawn(&mut decals, &mut scene, 1.0);
+        let third_node_id = spawn(&mut decals, &mut scene, 2.0);
+        assert_eq!(third_node_id, first_node_id);
+        assert_eq!(decals.count("bullet_hole"), 2);
+        assert_eq!(scene.node_count(), 2);
+        let transform = scene.get_node(third_node_id).unwrap().transform;
+        assert!(transform
+            .position()
+            .abs_diff_eq(Vec3::new(2.0, DECAL_SURFACE_OFFSET, 0.0), 1e-6));
+        assert!(transform
+            .transform_vector3(Vec3::Z)
+            .normalize()
+            .abs_diff_eq(Vec3::Y, 1e-6));
+
+        decals.update(&mut scene, 9.0);
+        let node = scene.get_node(third_node_id).unwrap();
+        let Some(Material::Pbr {
+            dynamic_pbr_params: Some(dynamic_pbr_params),
+            ..
+        }) = node.visual.as_ref().map(|visual| visual.material)
+        else {
+            panic!("The decal should have a pbr material");
+        };
+        assert!((dynamic_pbr_params.base_color_factor.w - 0.5).abs() < 1e-5);
+
+        decals.update(&mut scene, 1.5);
+        assert_eq!(decals.count("bullet_hole"), 0);
+        assert!(!scene.get_node(third_node_id).unwrap().visible);
+        spawn(&mut decals, &mut scene, 3.0);
+        assert_eq!(scene.node_count(), 2);
+    }
+}
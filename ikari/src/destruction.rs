@@ -0,0 +1,316 @@
+use crate::collider_generation::{make_dynamic_node_collider, ColliderGenerationMode};
+use crate::ecs::{Entity, PhysicsBody, SceneNode};
+use crate::engine_state::EngineState;
+use crate::physics::rapier3d_f64::prelude::*;
+use crate::renderer::RendererData;
+use crate::scene::{GameNodeId, Scene};
+use crate::transform::Transform;
+
+use glam::f32::Vec3;
+
+/// Component of the entities that break into pre-fractured pieces, see fracture and
+/// DeathBehavior::Fracture. The entity's SceneNode is the intact object and the pieces are
+/// hidden nodes authored along with it, usually its children
+#[derive(Debug, Clone, PartialEq)]
+pub struct Destructible {
+    pub pieces: Vec<GameNodeId>,
+    /// in meters per second, how fast the pieces fly away from the hit
+    pub burst_speed: f32,
+    pub piece_density: f32,
+    pub debris_lifetime_seconds: f32,
+}
+
+impl Destructible {
+    pub fn new(pieces: Vec<GameNodeId>) -> Self {
+        Self {
+            pieces,
+            burst_speed: 3.0,
+            piece_density: 1.0,
+            debris_lifetime_seconds: 10.0,
+        }
+    }
+
+    /// The pieces are the children of the node that have a visual
+    pub fn from_children(scene: &Scene, node_id: GameNodeId) -> Self {
+        Self::new(
+            scene
+                .nodes()
+                .filter(|node| node.parent_id == Some(node_id) && node.visual.is_some())
+                .map(|node| node.id())
+                .collect(),
+        )
+    }
+}
+
+/// Component of the pieces of the broken Destructibles, the entity also has a SceneNode and a
+/// PhysicsBody. The entities and their rigid bodies come from EngineState::debris_pool, they
+/// go back to it in update_debris along with the removal of the piece's node
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Debris {
+    pub age_seconds: f32,
+    pub lifetime_seconds: f32,
+}
+
+/// Swaps the entity for its pieces, which become dynamic rigid bodies flying away from the hit,
+/// or from the center of the object if there's no hit position. The entity is despawned and
+/// the debris entities are returned. Does nothing if the entity isn't Destructible
+pub fn fracture(
+    engine_state: &mut EngineState,
+    renderer_data: &RendererData,
+    entity: Entity,
+    hit_position: Option<Vec3>,
+) -> Vec<Entity> {
+    let Some(destructible) = engine_state.world.remove::<Destructible>(entity) else {
+        return vec![];
+    };
+
+    let intact_node_id = engine_state
+        .world
+        .get::<SceneNode>(entity)
+        .map(|SceneNode(node_id)| *node_id);
+    let intact_velocity = engine_state
+        .world
+        .get::<PhysicsBody>(entity)
+        .and_then(|PhysicsBody(rigid_body_handle)| {
+            engine_state
+                .physics_state
+                .rigid_body_set
+                .get(*rigid_body_handle)
+        })
+        .map(|rigid_body| *rigid_body.linvel())
+        .unwrap_or_else(Vector::zeros);
+    let burst_origin = hit_position
+        .or_else(|| {
+            intact_node_id.map(|node_id| {
+                engine_state
+                    .scene
+                    .get_global_transform_for_node(node_id)
+                    .position()
+            })
+        })
+        .unwrap_or(Vec3::ZERO);
+
+    let mut debris_entities = vec![];
+    for piece_node_id in destructible.pieces {
+        if engine_state.scene.get_node(piece_node_id).is_none() {
+            continue;
+        }
+        let transform = engine_state
+            .scene
+            .get_global_transform_for_node(piece_node_id);
+        let Some(mut collider) = make_dynamic_node_collider(
+            &engine_state.scene,
+            renderer_data,
+            piece_node_id,
+            ColliderGenerationMode::ConvexHull,
+        ) else {
+            engine_state.scene.remove_node(piece_node_id);
+            continue;
+        };
+        collider.set_density(destructible.piece_density as f64);
+
+        // the pieces move on their own from now on
+        if let Some(node) = engine_state.scene.get_node_mut(piece_node_id) {
+            node.parent_id = None;
+            node.transform = transform;
+            node.visible = true;
+            node.reset_interpolation();
+        }
+
+        let direction = (transform.position() - burst_origin)
+            .try_normalize()
+            .unwrap_or(Vec3::Y)
+            * destructible.burst_speed;
+        let (debris_entity, rigid_body_handle) = acquire_debris_body(engine_state, transform);
+
+        let physics_state = &mut engine_state.physics_state;
+        physics_state.collider_set.insert_with_parent(
+            collider,
+            rigid_body_handle,
+            &mut physics_state.rigid_body_set,
+        );
+        if let Some(rigid_body) = physics_state.rigid_body_set.get_mut(rigid_body_handle) {
+            rigid_body.set_linvel(
+                intact_velocity
+                    + vector![direction.x as f64, direction.y as f64, direction.z as f64],
+                true,
+            );
+        }
+        physics_state.add_rigid_body_component(piece_node_id, rigid_body_handle);
+
+        engine_state
+            .world
+            .insert(debris_entity, SceneNode(piece_node_id));
+        engine_state.world.insert(
+            debris_entity,
+            Debris {
+                age_seconds: 0.0,
+                lifetime_seconds: destructible.debris_lifetime_seconds,
+            },
+        );
+        debris_entities.push(debris_entity);
+    }
+
+    engine_state.despawn_entity(entity);
+    debris_entities
+}
+
+/// System that removes the debris past its lifetime, then the oldest pieces while there are
+/// more than max_debris of them. Their nodes are removed and their entities go back to
+/// EngineState::debris_pool. Meant for SystemStage::FixedUpdate, e.g. in a closure that
+/// passes the budget
+#[profiling::function]
+pub fn update_debris(engine_state: &mut EngineState, max_debris: usize) {
+    let delta_time_seconds = engine_state.simulation_timestep.timestep_seconds as f32;
+
+    let mut expired = vec![];
+    let mut remaining = vec![];
+    for (entity, debris) in engine_state.world.query_mut::<Debris>() {
+        debris.age_seconds += delta_time_seconds;
+        if debris.age_seconds >= debris.lifetime_seconds {
+            expired.push(entity);
+        } else {
+            remaining.push((entity, debris.age_seconds));
+        }
+    }
+    // the oldest first
+    remaining.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    let over_budget_count = remaining.len().saturating_sub(max_debris);
+
+    let mut debris_pool = std::mem::take(&mut engine_state.debris_pool);
+    for entity in expired.into_iter().chain(
+        remaining
+            .into_iter()
+            .take(over_budget_count)
+            .map(|(entity, _)| entity),
+    ) {
+        remove_piece(engine_state, entity);
+        if !debris_pool.release(engine_state, entity) {
+            engine_state.despawn_entity(entity);
+        }
+    }
+    engine_state.debris_pool = debris_pool;
+}
+
+/// An entity from EngineState::debris_pool with a dynamic rigid body at the transform, the
+/// piece it had when the pool was full is removed first
+fn acquire_debris_body(
+    engine_state: &mut EngineState,
+    transform: Transform,
+) -> (Entity, RigidBodyHandle) {
+    let mut debris_pool = std::mem::take(&mut engine_state.debris_pool);
+    let debris_entity = debris_pool.acquire(engine_state, transform, |engine_state| {
+        let rigid_body_handle = engine_state
+            .physics_state
+            .rigid_body_set
+            .insert(RigidBodyBuilder::dynamic().build());
+        let entity = engine_state.world.spawn();
+        engine_state
+            .world
+            .insert(entity, PhysicsBody(rigid_body_handle));
+        entity
+    });
+    engine_state.debris_pool = debris_pool;
+
+    remove_piece(engine_state, debris_entity);
+    let PhysicsBody(rigid_body_handle) = *engine_state
+        .world
+        .get::<PhysicsBody>(debris_entity)
+        .expect("The debris entities have a PhysicsBody");
+    (debris_entity, rigid_body_handle)
+}
+
+/// Removes the node and the colliders of the piece, the entity keeps its rigid body
+fn remove_piece(engine_state: &mut EngineState, entity: Entity) {
+    engine_state.world.remove::<Debris>(entity);
+    if let Some(SceneNode(node_id)) = engine_state.world.remove::<SceneNode>(entity) {
+        engine_state.scene_observers.remove_node_observers(node_id);
+        engine_state.scene.remove_node(node_id);
+        engine_state
+            .physics_state
+            .remove_rigid_body_component(node_id);
+    }
+    let physics_state = &mut engine_state.physics_state;
+    let collider_handles = engine_state
+        .world
+        .get::<PhysicsBody>(entity)
+        .and_then(|PhysicsBody(rigid_body_handle)| {
+            physics_state.rigid_body_set.get(*rigid_body_handle)
+        })
+        .map(|rigid_body| rigid_body.colliders().to_vec())
+        .unwrap_or_default();
+    for collider_handle in collider_handles {
+        physics_state.collider_set.remove(
+            collider_handle,
+            &mut physics_state.island_manager,
+            &mut physics_state.rigid_body_set,
+            true,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::GameNodeDesc;
+
+    #[test]
+    fn debris_is_despawned_when_too_old_or_over_budget() {
+        let mut engine_state = EngineState::new_headless();
+        let delta_time_seconds = engine_state.simulation_timestep.timestep_seconds as f32;
+        let mut spawn_debris = |age_seconds: f32, lifetime_seconds: f32| {
+            let node_id = engine_state.scene.add_node(GameNodeDesc::default()).id();
+            let rigid_body_handle = engine_state
+                .physics_state
+                .rigid_body_set
+                .insert(RigidBodyBuilder::dynamic().build());
+            let entity = engine_state.world.spawn();
+            engine_state.world.insert(entity, SceneNode(node_id));
+            engine_state
+                .world
+                .insert(entity, PhysicsBody(rigid_body_handle));
+            engine_state.world.insert(
+                entity,
+                Debris {
+                    age_seconds,
+                    lifetime_seconds,
+                },
+            );
+            entity
+        };
+        let expired = spawn_debris(2.0, 2.0 + delta_time_seconds / 2.0);
+        let oldest = spawn_debris(5.0, 10.0);
+        let newer = spawn_debris(1.0, 10.0);
+        let newest = spawn_debris(0.0, 10.0);
+
+        update_debris(&mut engine_state, 2);
+
+        assert!(!engine_state.world.is_alive(expired));
+        assert!(!engine_state.world.is_alive(oldest));
+        assert!(engine_state.world.is_alive(newer));
+        assert!(engine_state.world.is_alive(newest));
+        assert_eq!(engine_state.physics_state.rigid_body_set.len(), 2);
+
+        // the pieces of the fractures go back to the pool with their rigid body
+        let (pooled, rigid_body_handle) =
+            acquire_debris_body(&mut engine_state, Transform::default());
+        let node_id = engine_state.scene.add_node(GameNodeDesc::default()).id();
+        engine_state.world.insert(pooled, SceneNode(node_id));
+        engine_state.world.insert(
+            pooled,
+            Debris {
+                age_seconds: 0.0,
+                lifetime_seconds: 0.0,
+            },
+        );
+        update_debris(&mut engine_state, 2);
+        assert!(engine_state.world.is_alive(pooled));
+        assert!(engine_state.scene.get_node(node_id).is_none());
+        assert!(!engine_state.physics_state.rigid_body_set[rigid_body_handle].is_enabled());
+        assert_eq!(engine_state.debris_pool.free_count(), 1);
+        assert_eq!(
+            acquire_debris_body(&mut engine_state, Transform::default()),
+            (pooled, rigid_body_handle)
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use std::ops::Range;
+
+use crate::asset_registry::MeshHandle;
+use crate::buffer::GpuBuffer;
+use crate::collisions::Aabb;
+use crate::mesh::Vertex;
+use crate::renderer::{BaseRenderer, BindedGeometryBuffers, BindedIndexBuffer, RendererData};
+
+use glam::f32::Vec3;
+
+/// A mesh that's edited on the CPU every frame or so, e.g. trails, ribbons, deformable terrain
+/// or debug geometry. Only the parts that changed since the last upload are written to the
+/// gpu, and the bounding box used for culling follows the vertices.
+/// Uses 32 bit indices so any range of them can be uploaded on its own
+#[derive(Debug)]
+pub struct DynamicMesh {
+    mesh: MeshHandle,
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+    dirty_vertices: Option<Range<usize>>,
+    dirty_indices: Option<Range<usize>>,
+    /// the buffers need to be made again, e.g. after the device was recreated
+    needs_new_buffers: bool,
+}
+
+impl DynamicMesh {
+    pub fn new(
+        base: &BaseRenderer,
+        data: &mut RendererData,
+        vertices: Vec<Vertex>,
+        indices: Vec<u32>,
+    ) -> Self {
+        let geometry_buffers = make_geometry_buffers(base, &vertices, &indices);
+        data.binded_meshes.push(geometry_buffers);
+        let mesh = data.asset_registry.mesh(data.binded_meshes.len() - 1);
+        Self {
+            mesh,
+            vertices,
+            indices,
+            dirty_vertices: None,
+            dirty_indices: None,
+            needs_new_buffers: false,
+        }
+    }
+
+    /// for GameNodeVisual::mesh
+    pub fn mesh(&self) -> MeshHandle {
+        self.mesh
+    }
+
+    pub fn vertices(&self) -> &[Vertex] {
+        &self.vertices
+    }
+
+    pub fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+
+    /// only the given range is uploaded, e.g. the segment of a trail that moved
+    pub fn vertices_mut(&mut self, range: Range<usize>) -> &mut [Vertex] {
+        extend_dirty_range(&mut self.dirty_vertices, range.clone());
+        &mut self.vertices[range]
+    }
+
+    pub fn indices_mut(&mut self, range: Range<usize>) -> &mut [u32] {
+        extend_dirty_range(&mut self.dirty_indices, range.clone());
+        &mut self.indices[range]
+    }
+
+    /// the buffers grow as needed
+    pub fn set_vertices(&mut self, vertices: Vec<Vertex>) {
+        self.dirty_vertices = Some(0..vertices.len());
+        self.vertices = vertices;
+    }
+
+    pub fn set_indices(&mut self, indices: Vec<u32>) {
+        self.dirty_indices = Some(0..indices.len());
+        self.indices = indices;
+    }
+
+    pub fn push_triangle(&mut self, triangle: [Vertex; 3]) {
+        let first_vertex = self.vertices.len();
+        let first_index = self.indices.len();
+        self.vertices.extend(triangle);
+        self.indices
+            .extend((first_vertex..first_vertex + 3).map(|index| index as u32));
+        extend_dirty_range(&mut self.dirty_vertices, first_vertex..first_vertex + 3);
+        extend_dirty_range(&mut self.dirty_indices, first_index..first_index + 3);
+    }
+
+    pub fn clear(&mut self) {
+        self.set_vertices(vec![]);
+        self.set_indices(vec![]);
+    }
+
+    /// Call from GameState::on_device_recreated, the whole mesh is then uploaded again
+    pub fn on_device_recreated(&mut self) {
+        self.needs_new_buffers = true;
+    }
+
+    /// Writes the changes to the gpu, once per frame after editing the mesh
+    #[profiling::function]
+    pub fn upload(&mut self, base: &BaseRenderer, data: &mut RendererData) {
+        let Some(geometry_buffers) = data.binded_meshes.get_mut(self.mesh.index()) else {
+            return;
+        };
+        if self.needs_new_buffers {
+            *geometry_buffers = make_geometry_buffers(base, &self.vertices, &self.indices);
+            self.needs_new_buffers = false;
+            self.dirty_vertices = None;
+            self.dirty_indices = None;
+            return;
+        }
+
+        if let Some(dirty_vertices) = self.dirty_vertices.take() {
+            write_dirty_range(
+                base,
+                &mut geometry_buffers.vertex_buffer,
+                bytemuck::cast_slice(&self.vertices),
+                dirty_vertices,
+            );
+            if let Some(bounding_box) = make_bounding_box(&self.vertices) {
+                geometry_buffers.bounding_box = bounding_box;
+            }
+            // the node didn't move so the cached shadow maps wouldn't see the change
+            data.shadow_updates.invalidate();
+        }
+        if let Some(dirty_indices) = self.dirty_indices.take() {
+            write_dirty_range(
+                base,
+                &mut geometry_buffers.index_buffer.buffer,
+                bytemuck::cast_slice(&self.indices),
+                dirty_indices,
+            );
+        }
+    }
+}
+
+fn make_geometry_buffers(
+    base: &BaseRenderer,
+    vertices: &[Vertex],
+    indices: &[u32],
+) -> BindedGeometryBuffers {
+    // some headroom so a mesh that grows a bit every frame doesn't get new buffers every frame
+    let vertex_buffer = GpuBuffer::from_bytes_and_capacity(
+        &base.device,
+        bytemuck::cast_slice(vertices),
+        std::mem::size_of::<Vertex>(),
+        (vertices.len() * 2).max(1),
+        wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+    );
+    let index_buffer = GpuBuffer::from_bytes_and_capacity(
+        &base.device,
+        bytemuck::cast_slice(indices),
+        std::mem::size_of::<u32>(),
+        (indices.len() * 2).max(1),
+        wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
+    );
+    BindedGeometryBuffers {
+        vertex_buffer,
+        index_buffer: BindedIndexBuffer {
+            buffer: index_buffer,
+            format: wgpu::IndexFormat::Uint32,
+        },
+        bounding_box: make_bounding_box(vertices).unwrap_or(Aabb {
+            min: Vec3::ZERO,
+            max: Vec3::ZERO,
+        }),
+        collision_mesh: None,
+    }
+}
+
+fn make_bounding_box(vertices: &[Vertex]) -> Option<Aabb> {
+    Aabb::make_from_points(vertices.iter().map(|vertex| vertex.position.into()))
+}
+
+/// Only writes the dirty range, the whole contents are only written when they outgrow the
+/// buffer and a bigger one has to be made
+fn write_dirty_range(
+    base: &BaseRenderer,
+    buffer: &mut GpuBuffer,
+    contents: &[u8],
+    dirty_range: Range<usize>,
+) {
+    let stride = buffer.stride();
+    let Some(dirty_bytes) =
+        dirty_byte_range(stride, buffer.capacity_bytes(), contents.len(), dirty_range)
+    else {
+        buffer.write(&base.device, &base.queue, contents);
+        return;
+    };
+    buffer.set_length(contents.len() / stride);
+    if !dirty_bytes.is_empty() {
+        buffer.write_at(
+            &base.queue,
+            dirty_bytes.start / stride,
+            &contents[dirty_bytes],
+        );
+    }
+}
+
+/// The bytes of the contents to write for the dirty elements, None if the contents don't fit in
+/// the capacity. The elements appended past the old length are in the dirty range already
+fn dirty_byte_range(
+    stride: usize,
+    capacity_bytes: usize,
+    contents_length_bytes: usize,
+    dirty_range: Range<usize>,
+) -> Option<Range<usize>> {
+    if contents_length_bytes > capacity_bytes {
+        return None;
+    }
+    let start = (dirty_range.start * stride).min(contents_length_bytes);
+    let end = (dirty_range.end * stride).clamp(start, contents_length_bytes);
+    Some(start..end)
+}
+
+fn extend_dirty_range(dirty_range: &mut Option<Range<usize>>, range: Range<usize>) {
+    *dirty_range = Some(match dirty_range.take() {
+        Some(dirty_range) => dirty_range.start.min(range.start)..dirty_range.end.max(range.end),
+        None => range,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dirty_ranges_cover_all_the_edits() {
+        let mut dirty_range = None;
+
+        extend_dirty_range(&mut dirty_range, 10..12);
+        assert_eq!(dirty_range, Some(10..12));
+        extend_dirty_range(&mut dirty_range, 3..5);
+        extend_dirty_range(&mut dirty_range, 11..20);
+
+        assert_eq!(dirty_range, Some(3..20));
+    }
+
+    #[test]
+    fn only_the_dirty_bytes_are_written_while_they_fit() {
+        // 2 elements appended to the 4 already in a buffer with room for 8
+        assert_eq!(dirty_byte_range(4, 32, 24, 4..6), Some(16..24));
+        // shrunk by clear, nothing left to write
+        assert_eq!(dirty_byte_range(4, 32, 0, 0..0), Some(0..0));
+        // edits past the end of the shrunk contents are skipped
+        assert_eq!(dirty_byte_range(4, 32, 8, 1..5), Some(4..8));
+        assert_eq!(dirty_byte_range(4, 32, 36, 8..9), None);
+    }
+}
@@ -0,0 +1,126 @@
+use crate::time::Duration;
+
+/// how much each new frame time weighs in the average
+const FRAME_TIME_SMOOTHING: f32 = 0.1;
+
+/// Adjusts RendererData::render_scale to hold a target frame rate from the gpu frame times.
+/// Lives in EngineState::dynamic_resolution, the game feeds it the timings it gets from
+/// Renderer::process_profiler_frame so it needs the timestamp queries to be supported
+#[derive(Debug, Clone)]
+pub struct DynamicResolution {
+    pub is_enabled: bool,
+    pub target_fps: f32,
+    pub min_render_scale: f32,
+    pub max_render_scale: f32,
+    /// the scale only goes down once the frame time is this fraction above the target and
+    /// back up once it's this fraction below it
+    pub hysteresis: f32,
+    /// largest change of the render scale in one adjustment
+    pub max_step: f32,
+    /// frames to wait after an adjustment, the gpu timings come in a few frames late and the
+    /// average needs to settle
+    pub cooldown_frames: u32,
+    average_frame_time: Option<f32>,
+    frames_since_adjustment: u32,
+}
+
+impl Default for DynamicResolution {
+    fn default() -> Self {
+        Self {
+            is_enabled: false,
+            target_fps: 60.0,
+            min_render_scale: 0.5,
+            max_render_scale: 1.0,
+            hysteresis: 0.1,
+            max_step: 0.1,
+            cooldown_frames: 30,
+            average_frame_time: None,
+            frames_since_adjustment: 0,
+        }
+    }
+}
+
+impl DynamicResolution {
+    /// Call it with the gpu time of each frame. Returns the render scale to switch to, after
+    /// which Renderer::resize_surface must be called
+    pub fn update(&mut self, gpu_frame_time: Duration, render_scale: f32) -> Option<f32> {
+        if !self.is_enabled {
+            self.average_frame_time = None;
+            return None;
+        }
+        let frame_time = gpu_frame_time.as_secs_f32();
+        let average_frame_time = match self.average_frame_time {
+            Some(average) => average + (frame_time - average) * FRAME_TIME_SMOOTHING,
+            None => frame_time,
+        };
+        self.average_frame_time = Some(average_frame_time);
+        self.frames_since_adjustment += 1;
+
+        let min_render_scale = self.min_render_scale.max(0.1);
+        let max_render_scale = self.max_render_scale.max(min_render_scale);
+        // the user may have moved the bounds
+        let clamped_render_scale = render_scale.clamp(min_render_scale, max_render_scale);
+        if clamped_render_scale != render_scale {
+            return Some(self.adjusted(clamped_render_scale));
+        }
+        if self.frames_since_adjustment < self.cooldown_frames {
+            return None;
+        }
+
+        let target_frame_time = 1.0 / self.target_fps.max(1.0);
+        let load = average_frame_time / target_frame_time;
+        if load < 1.0 + self.hysteresis && load > 1.0 - self.hysteresis {
+            return None;
+        }
+        // the render scale scales the pixel count, which the gpu time is roughly proportional to.
+        // aims for the middle of the hysteresis band so the next frames don't bounce back out
+        let new_render_scale = (render_scale / load)
+            .clamp(render_scale - self.max_step, render_scale + self.max_step)
+            .clamp(min_render_scale, max_render_scale);
+        // e.g. when already at a bound
+        if (new_render_scale - render_scale).abs() < 0.01 {
+            return None;
+        }
+        Some(self.adjusted(new_render_scale))
+    }
+
+    fn adjusted(&mut self, render_scale: f32) -> f32 {
+        self.average_frame_time = None;
+        self.frames_since_adjustment = 0;
+        render_scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_scale_follows_the_gpu_load() {
+        let mut dynamic_resolution = DynamicResolution {
+            is_enabled: true,
+            cooldown_frames: 2,
+            ..Default::default()
+        };
+        let slow_frame = Duration::from_secs_f32(1.0 / 30.0);
+        let on_target_frame = Duration::from_secs_f32(1.0 / 62.0);
+
+        assert_eq!(dynamic_resolution.update(slow_frame, 1.0), None);
+        let render_scale = dynamic_resolution.update(slow_frame, 1.0).unwrap();
+        assert!((render_scale - 0.9).abs() < 1e-4);
+
+        // inside the hysteresis band
+        for _ in 0..10 {
+            assert_eq!(
+                dynamic_resolution.update(on_target_frame, render_scale),
+                None
+            );
+        }
+
+        dynamic_resolution.min_render_scale = 0.95;
+        assert_eq!(
+            dynamic_resolution.update(on_target_frame, render_scale),
+            Some(0.95)
+        );
+    }
+}
@@ -0,0 +1,392 @@
+use crate::engine_state::EngineState;
+use crate::physics::rapier3d_f64::prelude::*;
+use crate::scene::GameNodeId;
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+/// Entities are recycled when despawned, the generation makes sure that a stale
+/// entity doesn't refer to the new one that took its place
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Entity {
+    index: u32,
+    generation: u32,
+}
+
+/// Links the entity to a node of the scene, which holds its transform and visual
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SceneNode(pub GameNodeId);
+
+/// Links the entity to a rigid body of the physics state. The transform of its scene node
+/// is kept in sync if a rigid body component was added with PhysicsState::add_rigid_body_component
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PhysicsBody(pub RigidBodyHandle);
+
+/// Free-form labels used to filter entities, e.g. by TriggerVolume::required_tag
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tags(pub Vec<String>);
+
+impl Tags {
+    pub fn new(tags: &[&str]) -> Self {
+        Self(tags.iter().map(|tag| tag.to_string()).collect())
+    }
+
+    pub fn has(&self, tag: &str) -> bool {
+        self.0.iter().any(|other| other == tag)
+    }
+}
+
+trait ComponentStorage {
+    fn remove_entity(&mut self, entity: Entity);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+/// Sparse set, the components are kept packed together so iterating
+/// over them doesn't need to skip over the entities that don't have one
+struct SparseSet<T> {
+    components: Vec<T>,
+    entities: Vec<Entity>,
+    /// maps an entity index to a position in components
+    sparse: Vec<Option<usize>>,
+}
+
+impl<T> Default for SparseSet<T> {
+    fn default() -> Self {
+        Self {
+            components: vec![],
+            entities: vec![],
+            sparse: vec![],
+        }
+    }
+}
+
+impl<T> SparseSet<T> {
+    fn dense_index(&self, entity: Entity) -> Option<usize> {
+        let dense_index = (*self.sparse.get(entity.index as usize)?)?;
+        (self.entities[dense_index] == entity).then_some(dense_index)
+    }
+
+    fn insert(&mut self, entity: Entity, component: T) -> Option<T> {
+        if let Some(dense_index) = self.dense_index(entity) {
+            return Some(std::mem::replace(
+                &mut self.components[dense_index],
+                component,
+            ));
+        }
+
+        let sparse_index = entity.index as usize;
+        if self.sparse.len() <= sparse_index {
+            self.sparse.resize(sparse_index + 1, None);
+        }
+        self.sparse[sparse_index] = Some(self.components.len());
+        self.components.push(component);
+        self.entities.push(entity);
+        None
+    }
+
+    fn remove(&mut self, entity: Entity) -> Option<T> {
+        let dense_index = self.dense_index(entity)?;
+        self.sparse[entity.index as usize] = None;
+        let component = self.components.swap_remove(dense_index);
+        self.entities.swap_remove(dense_index);
+        if let Some(moved_entity) = self.entities.get(dense_index) {
+            self.sparse[moved_entity.index as usize] = Some(dense_index);
+        }
+        Some(component)
+    }
+
+    fn get(&self, entity: Entity) -> Option<&T> {
+        self.dense_index(entity)
+            .map(|dense_index| &self.components[dense_index])
+    }
+
+    fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        self.dense_index(entity)
+            .map(|dense_index| &mut self.components[dense_index])
+    }
+}
+
+impl<T: 'static> ComponentStorage for SparseSet<T> {
+    fn remove_entity(&mut self, entity: Entity) {
+        self.remove(entity);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Holds the entities of the game and their components. Any 'static type can be used as a component,
+/// an entity has at most one component of each type
+#[derive(Default)]
+pub struct World {
+    generations: Vec<u32>,
+    alive: Vec<bool>,
+    free_indices: Vec<u32>,
+    storages: HashMap<TypeId, Box<dyn ComponentStorage>>,
+}
+
+impl World {
+    pub fn spawn(&mut self) -> Entity {
+        if let Some(index) = self.free_indices.pop() {
+            self.alive[index as usize] = true;
+            return Entity {
+                index,
+                generation: self.generations[index as usize],
+            };
+        }
+
+        let index = self.generations.len() as u32;
+        self.generations.push(0);
+        self.alive.push(true);
+        Entity {
+            index,
+            generation: 0,
+        }
+    }
+
+    /// Only removes the components from the world, see EngineState::despawn_entity
+    /// to also remove the entity's scene node and rigid body
+    pub fn despawn(&mut self, entity: Entity) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        }
+
+        for storage in self.storages.values_mut() {
+            storage.remove_entity(entity);
+        }
+        let index = entity.index as usize;
+        self.alive[index] = false;
+        self.generations[index] = self.generations[index].wrapping_add(1);
+        self.free_indices.push(entity.index);
+        true
+    }
+
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        let index = entity.index as usize;
+        self.alive.get(index).copied().unwrap_or(false)
+            && self.generations[index] == entity.generation
+    }
+
+    pub fn entity_count(&self) -> usize {
+        self.alive.len() - self.free_indices.len()
+    }
+
+    /// returns the component that was replaced if the entity already had one of this type
+    pub fn insert<T: 'static>(&mut self, entity: Entity, component: T) -> Option<T> {
+        if !self.is_alive(entity) {
+            log::error!("Tried to insert a component on an entity that was despawned");
+            return None;
+        }
+        self.storage_mut::<T>().insert(entity, component)
+    }
+
+    pub fn remove<T: 'static>(&mut self, entity: Entity) -> Option<T> {
+        self.storages
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|storage| storage.as_any_mut().downcast_mut::<SparseSet<T>>())
+            .and_then(|storage| storage.remove(entity))
+    }
+
+    pub fn get<T: 'static>(&self, entity: Entity) -> Option<&T> {
+        self.storage::<T>()?.get(entity)
+    }
+
+    pub fn get_mut<T: 'static>(&mut self, entity: Entity) -> Option<&mut T> {
+        self.storages
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|storage| storage.as_any_mut().downcast_mut::<SparseSet<T>>())
+            .and_then(|storage| storage.get_mut(entity))
+    }
+
+    pub fn has<T: 'static>(&self, entity: Entity) -> bool {
+        self.get::<T>(entity).is_some()
+    }
+
+    /// iterates over all entities that have a component of type T, in no particular order
+    pub fn query<T: 'static>(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.storage::<T>()
+            .into_iter()
+            .flat_map(|storage| storage.entities.iter().copied().zip(&storage.components))
+    }
+
+    pub fn query_mut<T: 'static>(&mut self) -> impl Iterator<Item = (Entity, &mut T)> {
+        self.storages
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|storage| storage.as_any_mut().downcast_mut::<SparseSet<T>>())
+            .into_iter()
+            .flat_map(|storage| {
+                storage
+                    .entities
+                    .iter()
+                    .copied()
+                    .zip(storage.components.iter_mut())
+            })
+    }
+
+    /// collected up front so the world can be modified while going through them,
+    /// e.g. to despawn some of the entities
+    pub fn entities_with<T: 'static>(&self) -> Vec<Entity> {
+        self.storage::<T>()
+            .map(|storage| storage.entities.clone())
+            .unwrap_or_default()
+    }
+
+    /// calls f for each entity that has both a component of type A and one of type B
+    pub fn for_each_pair_mut<A: 'static, B: 'static>(
+        &mut self,
+        mut f: impl FnMut(Entity, &mut A, &mut B),
+    ) {
+        assert_ne!(
+            TypeId::of::<A>(),
+            TypeId::of::<B>(),
+            "Can't query the same component type twice"
+        );
+
+        // B is taken out of the map so both storages can be borrowed mutably at the same time
+        let Some(mut storage_b) = self.storages.remove(&TypeId::of::<B>()) else {
+            return;
+        };
+        if let Some(set_b) = storage_b.as_any_mut().downcast_mut::<SparseSet<B>>() {
+            for (entity, a) in self.query_mut::<A>() {
+                if let Some(b) = set_b.get_mut(entity) {
+                    f(entity, a, b);
+                }
+            }
+        }
+        self.storages.insert(TypeId::of::<B>(), storage_b);
+    }
+
+    fn storage<T: 'static>(&self) -> Option<&SparseSet<T>> {
+        self.storages
+            .get(&TypeId::of::<T>())
+            .and_then(|storage| storage.as_any().downcast_ref::<SparseSet<T>>())
+    }
+
+    fn storage_mut<T: 'static>(&mut self) -> &mut SparseSet<T> {
+        self.storages
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::<SparseSet<T>>::default())
+            .as_any_mut()
+            .downcast_mut::<SparseSet<T>>()
+            .unwrap()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SystemStage {
+    /// run by the gameloop after each call to on_fixed_update, while the simulation isn't paused
+    FixedUpdate,
+    /// run by the gameloop once per frame, right before on_update
+    Update,
+}
+
+pub type System = Box<dyn FnMut(&mut EngineState)>;
+
+struct NamedSystem {
+    name: String,
+    system: System,
+}
+
+/// Systems run in the order in which they were added within their stage
+#[derive(Default)]
+pub struct Schedule {
+    fixed_update_systems: Vec<NamedSystem>,
+    update_systems: Vec<NamedSystem>,
+}
+
+impl Schedule {
+    /// the name shows up in the profiler
+    pub fn add_system(
+        &mut self,
+        stage: SystemStage,
+        name: &str,
+        system: impl FnMut(&mut EngineState) + 'static,
+    ) {
+        self.stage_systems_mut(stage).push(NamedSystem {
+            name: name.to_string(),
+            system: Box::new(system),
+        });
+    }
+
+    /// returns false if there was no system with this name
+    pub fn remove_system(&mut self, stage: SystemStage, name: &str) -> bool {
+        let systems = self.stage_systems_mut(stage);
+        let prev_len = systems.len();
+        systems.retain(|system| system.name != name);
+        systems.len() != prev_len
+    }
+
+    fn stage_systems_mut(&mut self, stage: SystemStage) -> &mut Vec<NamedSystem> {
+        match stage {
+            SystemStage::FixedUpdate => &mut self.fixed_update_systems,
+            SystemStage::Update => &mut self.update_systems,
+        }
+    }
+
+    pub(crate) fn run(engine_state: &mut EngineState, stage: SystemStage) {
+        // the schedule is taken out so the systems can borrow the engine state mutably.
+        // systems that are added while running are kept and run from the next time onwards
+        let mut schedule = std::mem::take(&mut engine_state.systems);
+        for named_system in schedule.stage_systems_mut(stage) {
+            profiling::scope!("System", named_system.name.as_str());
+            (named_system.system)(engine_state);
+        }
+        let added_systems = std::mem::replace(&mut engine_state.systems, schedule);
+        engine_state
+            .systems
+            .fixed_update_systems
+            .extend(added_systems.fixed_update_systems);
+        engine_state
+            .systems
+            .update_systems
+            .extend(added_systems.update_systems);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn despawned_entities_are_recycled_with_a_new_generation() {
+        let mut world = World::default();
+        let a = world.spawn();
+        world.insert(a, 1u32);
+        assert!(world.despawn(a));
+
+        let b = world.spawn();
+        assert_ne!(a, b);
+        assert!(!world.is_alive(a));
+        assert!(world.get::<u32>(b).is_none());
+        assert!(world.get::<u32>(a).is_none());
+    }
+
+    #[test]
+    fn pair_queries_only_visit_entities_with_both_components() {
+        let mut world = World::default();
+        let a = world.spawn();
+        let b = world.spawn();
+        let c = world.spawn();
+        world.insert(a, 1u32);
+        world.insert(b, 2u32);
+        world.insert(b, 2.0f32);
+        world.insert(c, 3.0f32);
+        world.remove::<u32>(a);
+        world.insert(a, 1u32);
+
+        let mut visited = vec![];
+        world.for_each_pair_mut::<u32, f32>(|entity, int, float| {
+            *float += *int as f32;
+            visited.push(entity);
+        });
+        assert_eq!(visited, vec![b]);
+        assert_eq!(world.get::<f32>(b), Some(&4.0));
+        assert_eq!(world.query::<u32>().count(), 2);
+    }
+}
@@ -0,0 +1,651 @@
+use crate::engine_state::EngineState;
+use crate::outline::Outlines;
+use crate::picking::{camera_transform, cursor_ray, pick, Ray};
+use crate::ragdoll::transform_to_isometry;
+use crate::renderer::*;
+use crate::scene::*;
+use crate::transform::{SimpleTransform, Transform, TransformBuilder};
+
+use std::collections::HashMap;
+use std::fmt;
+
+use glam::f32::{Quat, Vec2, Vec3, Vec4};
+use winit::{
+    event::{ElementState, MouseButton, WindowEvent},
+    window::Window,
+};
+
+/// length of the gizmo's handles relative to their distance from the camera,
+/// so the gizmo keeps the same size on screen
+const GIZMO_SCREEN_SCALE: f32 = 0.15;
+/// relative to the handle length
+const GIZMO_HANDLE_THICKNESS: f32 = 0.02;
+/// how close to a handle the cursor's ray must pass to grab it, relative to the handle length
+const GIZMO_GRAB_RADIUS: f32 = 0.08;
+const GIZMO_DRAGGED_AXIS_COLOR: Vec3 = Vec3::new(1.0, 0.9, 0.1);
+const SELECTION_OUTLINE_COLOR: Vec4 = Vec4::new(1.0, 0.55, 0.1, 1.0);
+const MIN_NODE_SCALE: f32 = 0.001;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum GizmoMode {
+    #[default]
+    Translate,
+    /// dragging the cursor horizontally turns the node around the grabbed axis
+    Rotate,
+    /// along the node's own axes
+    Scale,
+}
+
+impl GizmoMode {
+    pub const ALL: [GizmoMode; 3] = [GizmoMode::Translate, GizmoMode::Rotate, GizmoMode::Scale];
+}
+
+impl fmt::Display for GizmoMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                GizmoMode::Translate => "Translate",
+                GizmoMode::Rotate => "Rotate",
+                GizmoMode::Scale => "Scale",
+            }
+        )
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GizmoAxis {
+    X,
+    Y,
+    Z,
+}
+
+impl GizmoAxis {
+    pub const ALL: [GizmoAxis; 3] = [GizmoAxis::X, GizmoAxis::Y, GizmoAxis::Z];
+
+    fn index(self) -> usize {
+        match self {
+            GizmoAxis::X => 0,
+            GizmoAxis::Y => 1,
+            GizmoAxis::Z => 2,
+        }
+    }
+
+    fn unit(self) -> Vec3 {
+        Vec3::AXES[self.index()]
+    }
+
+    fn color(self) -> Vec3 {
+        match self {
+            GizmoAxis::X => Vec3::new(0.9, 0.15, 0.15),
+            GizmoAxis::Y => Vec3::new(0.15, 0.9, 0.15),
+            GizmoAxis::Z => Vec3::new(0.15, 0.3, 0.95),
+        }
+    }
+}
+
+/// A node listed in the hierarchy panel, see Editor::node_hierarchy
+#[derive(Debug, Clone)]
+pub struct EditorNodeEntry {
+    pub node_id: GameNodeId,
+    pub name: String,
+    /// number of ancestors
+    pub depth: usize,
+}
+
+/// What the inspector shows for the selected node
+#[derive(Debug, Clone)]
+pub struct NodeProperties {
+    pub node_id: GameNodeId,
+    pub name: Option<String>,
+    pub transform: SimpleTransform,
+    pub point_light: Option<PointLight>,
+    /// for pbr materials, dynamic_pbr_params is always set, from the
+    /// binded material if the node doesn't override it
+    pub material: Option<Material>,
+    pub wireframe: Option<bool>,
+    pub visible: bool,
+    pub casts_shadows: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NodeEdit {
+    PointLightColor(Vec3),
+    PointLightIntensity(f32),
+    /// the base color of pbr materials, the alpha is ignored by unlit ones
+    MaterialColor(Vec4),
+    MaterialMetallic(f32),
+    MaterialRoughness(f32),
+    Wireframe(bool),
+    Visible(bool),
+    CastsShadows(bool),
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Gizmo {
+    origin: Vec3,
+    axis_directions: [Vec3; 3],
+    handle_length: f32,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct GizmoDrag {
+    node_id: GameNodeId,
+    mode: GizmoMode,
+    axis: GizmoAxis,
+    gizmo: Gizmo,
+    /// where the handle was grabbed along its axis
+    start_axis_distance: f32,
+    start_cursor_position: Vec2,
+    start_transform: Transform,
+}
+
+/// Level editing on top of a running game: nodes are selected by clicking on them and moved
+/// around with a gizmo. The game is responsible for showing the hierarchy and the inspector,
+/// e.g. in its ui overlay, from node_hierarchy and selected_node_properties
+#[derive(Debug, Default)]
+pub struct Editor {
+    is_enabled: bool,
+    pub gizmo_mode: GizmoMode,
+    selected_node_id: Option<GameNodeId>,
+    cursor_position: Option<Vec2>,
+    drag: Option<GizmoDrag>,
+    /// the gizmo's handles, recreated every frame
+    helper_node_ids: Vec<GameNodeId>,
+    /// the node whose outline was set in RendererData::outlines
+    outlined_node_id: Option<GameNodeId>,
+}
+
+impl Editor {
+    pub fn is_enabled(&self) -> bool {
+        self.is_enabled
+    }
+
+    pub fn set_enabled(&mut self, scene: &mut Scene, is_enabled: bool) {
+        self.is_enabled = is_enabled;
+        if !is_enabled {
+            self.drag = None;
+            self.remove_helper_nodes(scene);
+        }
+    }
+
+    pub fn selected_node_id(&self) -> Option<GameNodeId> {
+        self.selected_node_id
+    }
+
+    pub fn select(&mut self, node_id: Option<GameNodeId>) {
+        self.selected_node_id = node_id;
+        self.drag = None;
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    pub fn is_helper_node(&self, node_id: GameNodeId) -> bool {
+        self.helper_node_ids.contains(&node_id)
+    }
+
+    /// The gizmo is added back on the next update, call this before saving the scene
+    pub fn remove_helper_nodes(&mut self, scene: &mut Scene) {
+        for node_id in self.helper_node_ids.drain(..) {
+            scene.remove_node(node_id);
+        }
+    }
+
+    /// Outlines the selected node while the editor is enabled
+    fn update_selection_outline(&mut self, outlines: &mut Outlines) {
+        let node_id = self.selected_node_id.filter(|_| self.is_enabled);
+        if node_id == self.outlined_node_id {
+            return;
+        }
+        if let Some(outlined_node_id) = self.outlined_node_id {
+            outlines.remove(outlined_node_id);
+        }
+        if let Some(node_id) = node_id {
+            outlines.set(node_id, SELECTION_OUTLINE_COLOR);
+        }
+        self.outlined_node_id = node_id;
+    }
+
+    /// Clicking selects the node under the cursor or grabs one of the gizmo's handles.
+    /// Clicks are ignored when cursor_captured_by_ui is set, but releasing the button
+    /// always ends the drag. Locks renderer.data
+    pub fn process_window_event(
+        &mut self,
+        event: &WindowEvent,
+        window: &Window,
+        cursor_captured_by_ui: bool,
+        engine_state: &mut EngineState,
+        renderer: &Renderer,
+    ) {
+        if !self.is_enabled {
+            return;
+        }
+
+        let window_size = window.inner_size();
+        let aspect_ratio = window_size.width as f32 / window_size.height.max(1) as f32;
+
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                let cursor_position = Vec2::new(
+                    position.x as f32 / window_size.width.max(1) as f32,
+                    position.y as f32 / window_size.height.max(1) as f32,
+                );
+                self.cursor_position = Some(cursor_position);
+
+                if self.drag.is_some() {
+                    let ray = cursor_ray(
+                        &engine_state.scene,
+                        &renderer.data.lock().unwrap(),
+                        cursor_position,
+                        aspect_ratio,
+                    );
+                    if let Some(ray) = ray {
+                        self.update_drag(engine_state, ray, cursor_position);
+                    }
+                }
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } if !cursor_captured_by_ui => {
+                let Some(cursor_position) = self.cursor_position else {
+                    return;
+                };
+                let renderer_data_guard = renderer.data.lock().unwrap();
+                let Some(ray) = cursor_ray(
+                    &engine_state.scene,
+                    &renderer_data_guard,
+                    cursor_position,
+                    aspect_ratio,
+                ) else {
+                    return;
+                };
+
+                self.drag = self.grab_gizmo(
+                    &engine_state.scene,
+                    &renderer_data_guard,
+                    ray,
+                    cursor_position,
+                );
+                if self.drag.is_none() {
+                    self.selected_node_id = pick(
+                        &engine_state.scene,
+                        &renderer_data_guard,
+                        &engine_state.physics_state,
+                        ray,
+                        f32::MAX,
+                        |node| !self.is_helper_node(node.id()),
+                    )
+                    .map(|hit| hit.node_id);
+                }
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Released,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.drag = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// Recreates the gizmo of the selected node, should be called every frame. Locks renderer.data
+    pub fn update(&mut self, scene: &mut Scene, renderer: &Renderer) {
+        self.remove_helper_nodes(scene);
+
+        if self
+            .selected_node_id
+            .is_some_and(|node_id| scene.get_node(node_id).is_none())
+        {
+            self.select(None);
+        }
+        self.update_selection_outline(&mut renderer.data.lock().unwrap().outlines);
+
+        if !self.is_enabled {
+            return;
+        }
+
+        let Some(gizmo) = self.gizmo(scene, &renderer.data.lock().unwrap()) else {
+            return;
+        };
+
+        let thickness = gizmo.handle_length * GIZMO_HANDLE_THICKNESS;
+        let tip_mesh = match self.gizmo_mode {
+            GizmoMode::Scale => renderer.constant_data.cube_mesh,
+            GizmoMode::Translate | GizmoMode::Rotate => renderer.constant_data.sphere_mesh,
+        };
+
+        for (axis, direction) in GizmoAxis::ALL.into_iter().zip(gizmo.axis_directions) {
+            let color = if self.drag.is_some_and(|drag| drag.axis == axis) {
+                GIZMO_DRAGGED_AXIS_COLOR
+            } else {
+                axis.color()
+            };
+            let rotation = Quat::from_rotation_arc(Vec3::X, direction);
+
+            let shaft = TransformBuilder::new()
+                .position(gizmo.origin + direction * gizmo.handle_length / 2.0)
+                .rotation(rotation)
+                .scale(Vec3::new(gizmo.handle_length / 2.0, thickness, thickness))
+                .build();
+            let tip = TransformBuilder::new()
+                .position(gizmo.origin + direction * gizmo.handle_length)
+                .rotation(rotation)
+                .scale(Vec3::splat(thickness * 3.0))
+                .build();
+
+            for (transform, mesh) in [(shaft, renderer.constant_data.cube_mesh), (tip, tip_mesh)] {
+                let node = scene.add_node(
+                    GameNodeDescBuilder::new()
+                        .transform(transform)
+                        .visual(Some(GameNodeVisual {
+                            material: Material::Unlit { color },
+                            mesh,
+                            wireframe: false,
+                            cullable: false,
+                        }))
+                        .build(),
+                );
+                self.helper_node_ids.push(node.id());
+            }
+        }
+    }
+
+    /// Depth-first, children come right after their parent
+    pub fn node_hierarchy(&self, scene: &Scene) -> Vec<EditorNodeEntry> {
+        let mut children: HashMap<Option<GameNodeId>, Vec<GameNodeId>> = HashMap::new();
+        for node in scene.nodes() {
+            if self.is_helper_node(node.id()) {
+                continue;
+            }
+            // nodes whose parent was removed are listed with the roots
+            let parent_id = node
+                .parent_id
+                .filter(|parent_id| scene.get_node(*parent_id).is_some());
+            children.entry(parent_id).or_default().push(node.id());
+        }
+
+        let mut entries = vec![];
+        let mut stack: Vec<_> = children
+            .get(&None)
+            .into_iter()
+            .flatten()
+            .rev()
+            .map(|node_id| (*node_id, 0))
+            .collect();
+        while let Some((node_id, depth)) = stack.pop() {
+            let name = scene
+                .get_node(node_id)
+                .and_then(|node| node.name.clone())
+                .unwrap_or_else(|| format!("{node_id:?}"));
+            entries.push(EditorNodeEntry {
+                node_id,
+                name,
+                depth,
+            });
+            stack.extend(
+                children
+                    .get(&Some(node_id))
+                    .into_iter()
+                    .flatten()
+                    .rev()
+                    .map(|child_id| (*child_id, depth + 1)),
+            );
+        }
+        entries
+    }
+
+    pub fn selected_node_properties(
+        &self,
+        scene: &Scene,
+        renderer_data: &RendererData,
+    ) -> Option<NodeProperties> {
+        let node = scene.get_node(self.selected_node_id?)?;
+        let material = node.visual.as_ref().map(|visual| match visual.material {
+            Material::Pbr {
+                binded_material,
+                dynamic_pbr_params,
+            } => Material::Pbr {
+                binded_material,
+                dynamic_pbr_params: dynamic_pbr_params.or_else(|| {
+                    renderer_data
+                        .binded_pbr_materials
+                        .get(binded_material.index())
+                        .map(|material| material.dynamic_pbr_params)
+                }),
+            },
+            material => material,
+        });
+        Some(NodeProperties {
+            node_id: node.id(),
+            name: node.name.clone(),
+            transform: node.transform.decompose(),
+            point_light: scene
+                .point_lights
+                .iter()
+                .find(|point_light| point_light.node_id == node.id())
+                .cloned(),
+            material,
+            wireframe: node.visual.as_ref().map(|visual| visual.wireframe),
+            visible: node.visible,
+            casts_shadows: node.casts_shadows,
+        })
+    }
+
+    /// Material edits only apply to the selected node, pbr materials get their
+    /// own dynamic_pbr_params so the other nodes that use the material don't change
+    pub fn edit_selected_node(
+        &self,
+        scene: &mut Scene,
+        renderer_data: &RendererData,
+        edit: NodeEdit,
+    ) {
+        let Some(node_id) = self.selected_node_id else {
+            return;
+        };
+
+        match edit {
+            NodeEdit::PointLightColor(_) | NodeEdit::PointLightIntensity(_) => {
+                let Some(point_light) = scene
+                    .point_lights
+                    .iter_mut()
+                    .find(|point_light| point_light.node_id == node_id)
+                else {
+                    return;
+                };
+                match edit {
+                    NodeEdit::PointLightColor(color) => point_light.color = color,
+                    NodeEdit::PointLightIntensity(intensity) => point_light.intensity = intensity,
+                    _ => {}
+                }
+            }
+            NodeEdit::Wireframe(wireframe) => {
+                if let Some(visual) = scene
+                    .get_node_mut(node_id)
+                    .and_then(|node| node.visual.as_mut())
+                {
+                    visual.wireframe = wireframe;
+                }
+            }
+            NodeEdit::Visible(visible) => {
+                if let Some(node) = scene.get_node_mut(node_id) {
+                    node.visible = visible;
+                }
+            }
+            NodeEdit::CastsShadows(casts_shadows) => {
+                if let Some(node) = scene.get_node_mut(node_id) {
+                    node.casts_shadows = casts_shadows;
+                }
+            }
+            NodeEdit::MaterialColor(_)
+            | NodeEdit::MaterialMetallic(_)
+            | NodeEdit::MaterialRoughness(_) => {
+                let Some(visual) = scene
+                    .get_node_mut(node_id)
+                    .and_then(|node| node.visual.as_mut())
+                else {
+                    return;
+                };
+                match (&mut visual.material, edit) {
+                    (
+                        Material::Pbr {
+                            binded_material,
+                            dynamic_pbr_params,
+                        },
+                        edit,
+                    ) => {
+                        let binded_material_index = binded_material.index();
+                        let params = dynamic_pbr_params.get_or_insert_with(|| {
+                            renderer_data
+                                .binded_pbr_materials
+                                .get(binded_material_index)
+                                .map(|material| material.dynamic_pbr_params)
+                                .unwrap_or_default()
+                        });
+                        match edit {
+                            NodeEdit::MaterialColor(color) => params.base_color_factor = color,
+                            NodeEdit::MaterialMetallic(metallic) => {
+                                params.metallic_factor = metallic
+                            }
+                            NodeEdit::MaterialRoughness(roughness) => {
+                                params.roughness_factor = roughness
+                            }
+                            _ => {}
+                        }
+                    }
+                    (Material::Unlit { color }, NodeEdit::MaterialColor(new_color)) => {
+                        *color = new_color.truncate();
+                    }
+                    (Material::Transparent { color, .. }, NodeEdit::MaterialColor(new_color)) => {
+                        *color = new_color;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn gizmo(&self, scene: &Scene, renderer_data: &RendererData) -> Option<Gizmo> {
+        let node_id = self.selected_node_id?;
+        scene.get_node(node_id)?;
+        let camera_position = camera_transform(scene, renderer_data)?.position();
+        let global_transform = scene.get_global_transform_for_node(node_id);
+        let origin = global_transform.position();
+        let axis_directions = GizmoAxis::ALL.map(|axis| match self.gizmo_mode {
+            GizmoMode::Scale => (global_transform.rotation() * axis.unit()).normalize(),
+            GizmoMode::Translate | GizmoMode::Rotate => axis.unit(),
+        });
+        Some(Gizmo {
+            origin,
+            axis_directions,
+            handle_length: origin
+                .distance(camera_position)
+                .max(renderer_data.camera_lens.near_plane_distance)
+                * GIZMO_SCREEN_SCALE,
+        })
+    }
+
+    fn grab_gizmo(
+        &self,
+        scene: &Scene,
+        renderer_data: &RendererData,
+        ray: Ray,
+        cursor_position: Vec2,
+    ) -> Option<GizmoDrag> {
+        let node_id = self.selected_node_id?;
+        let start_transform = scene.get_node(node_id)?.transform;
+        let gizmo = self.gizmo(scene, renderer_data)?;
+        let grab_radius = gizmo.handle_length * GIZMO_GRAB_RADIUS;
+
+        let (axis, axis_distance, _) = GizmoAxis::ALL
+            .into_iter()
+            .zip(gizmo.axis_directions)
+            .filter_map(|(axis, direction)| {
+                let (ray_distance, axis_distance, distance) =
+                    ray.closest_points_to_line(gizmo.origin, direction)?;
+                let is_on_handle = ray_distance > 0.0
+                    && (0.0..=gizmo.handle_length + grab_radius).contains(&axis_distance)
+                    && distance <= grab_radius;
+                is_on_handle.then_some((axis, axis_distance, ray_distance))
+            })
+            .min_by(|(_, _, a), (_, _, b)| a.total_cmp(b))?;
+
+        Some(GizmoDrag {
+            node_id,
+            mode: self.gizmo_mode,
+            axis,
+            gizmo,
+            // scaling divides by it
+            start_axis_distance: axis_distance.max(gizmo.handle_length * 0.1),
+            start_cursor_position: cursor_position,
+            start_transform,
+        })
+    }
+
+    fn update_drag(&mut self, engine_state: &mut EngineState, ray: Ray, cursor_position: Vec2) {
+        let Some(drag) = self.drag else {
+            return;
+        };
+        let scene = &mut engine_state.scene;
+        let Some(node) = scene.get_node(drag.node_id) else {
+            self.drag = None;
+            return;
+        };
+        let parent_transform = node
+            .parent_id
+            .map(|parent_id| scene.get_global_transform_for_node(parent_id))
+            .unwrap_or(Transform::IDENTITY);
+        let direction = drag.gizmo.axis_directions[drag.axis.index()];
+        let axis_distance = ray
+            .closest_points_to_line(drag.gizmo.origin, direction)
+            .map(|(_, axis_distance, _)| axis_distance);
+
+        let mut transform = drag.start_transform;
+        match drag.mode {
+            GizmoMode::Translate => {
+                let Some(axis_distance) = axis_distance else {
+                    return;
+                };
+                let world_offset = direction * (axis_distance - drag.start_axis_distance);
+                let local_offset = parent_transform.inverse().transform_vector3(world_offset);
+                transform.set_position(drag.start_transform.position() + local_offset);
+            }
+            GizmoMode::Rotate => {
+                // a full turn when dragging across the whole window
+                let angle =
+                    (cursor_position.x - drag.start_cursor_position.x) * std::f32::consts::TAU;
+                let parent_rotation = parent_transform.rotation();
+                let world_rotation = Quat::from_axis_angle(direction, angle);
+                transform.set_rotation(
+                    (parent_rotation.inverse()
+                        * world_rotation
+                        * parent_rotation
+                        * drag.start_transform.rotation())
+                    .normalize(),
+                );
+            }
+            GizmoMode::Scale => {
+                let Some(axis_distance) = axis_distance else {
+                    return;
+                };
+                let factor = axis_distance / drag.start_axis_distance;
+                let mut scale = drag.start_transform.scale();
+                scale[drag.axis.index()] = (scale[drag.axis.index()] * factor).max(MIN_NODE_SCALE);
+                transform.set_scale(scale);
+            }
+        }
+
+        if let Some(node) = scene.get_node_mut(drag.node_id) {
+            node.transform = transform;
+        }
+        let global_transform = scene.get_global_transform_for_node(drag.node_id);
+        engine_state
+            .physics_state
+            .teleport_rigid_body_component(drag.node_id, transform_to_isometry(&global_transform));
+    }
+}
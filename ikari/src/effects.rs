@@ -0,0 +1,505 @@
+use crate::asset_loader::*;
+use crate::decals::DecalParams;
+use crate::file_manager::{FileManager, GameFilePath};
+use crate::file_watcher::FileWatcher;
+use crate::mesh::PbrTextures;
+use crate::renderer::Renderer;
+use crate::texture::Texture;
+use crate::time::*;
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use serde::Deserialize;
+
+/// how often the effect files are checked for changes on disk
+const HOT_RELOAD_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// An effect file is a RON map of effect name -> effect definition, e.g.:
+/// ```ron
+/// {
+///     "muzzle_flash": (
+///         particle_emitters: [(burst_count: 12, lifetime_seconds: (0.05, 0.15))],
+///         light_flashes: [(color: (1.0, 0.8, 0.5), intensity: 4.0, duration_seconds: 0.08)],
+///     ),
+/// }
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EffectDefinition {
+    pub particle_emitters: Vec<ParticleEmitterDefinition>,
+    pub decals: Vec<DecalDefinition>,
+    pub light_flashes: Vec<LightFlashDefinition>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ParticleEmitterDefinition {
+    /// relative to the effect's spawn transform
+    pub offset: [f32; 3],
+    /// particles per second, emitted for duration_seconds
+    pub spawn_rate: f32,
+    /// particles emitted all at once when the effect starts
+    pub burst_count: u32,
+    /// None means the emitter runs until the effect is stopped
+    pub duration_seconds: Option<f32>,
+    pub max_particles: u32,
+    /// (min, max), randomized per particle
+    pub lifetime_seconds: (f32, f32),
+    /// (min, max), randomized per particle
+    pub initial_speed: (f32, f32),
+    /// half-angle of the emission cone around the effect's forward direction
+    pub spread_angle_deg: f32,
+    pub gravity_scale: f32,
+    pub start_color: [f32; 4],
+    pub end_color: [f32; 4],
+    pub start_size: f32,
+    pub end_size: f32,
+}
+
+impl Default for ParticleEmitterDefinition {
+    fn default() -> Self {
+        Self {
+            offset: [0.0, 0.0, 0.0],
+            spawn_rate: 0.0,
+            burst_count: 0,
+            duration_seconds: None,
+            max_particles: 256,
+            lifetime_seconds: (1.0, 1.0),
+            initial_speed: (1.0, 1.0),
+            spread_angle_deg: 15.0,
+            gravity_scale: 0.0,
+            start_color: [1.0, 1.0, 1.0, 1.0],
+            end_color: [1.0, 1.0, 1.0, 0.0],
+            start_size: 0.1,
+            end_size: 0.1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DecalDefinition {
+    /// path of the decal texture, relative to the effect file
+    pub texture: String,
+    pub size: [f32; 2],
+    pub color: [f32; 4],
+    pub lifetime_seconds: f32,
+    /// the decal fades out during the last fade_out_seconds of its lifetime
+    pub fade_out_seconds: f32,
+    /// randomize the rotation of the decal around its projection axis
+    pub random_rotation: bool,
+}
+
+impl Default for DecalDefinition {
+    fn default() -> Self {
+        Self {
+            texture: String::new(),
+            size: [1.0, 1.0],
+            color: [1.0, 1.0, 1.0, 1.0],
+            lifetime_seconds: 10.0,
+            fade_out_seconds: 1.0,
+            random_rotation: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LightFlashDefinition {
+    pub offset: [f32; 3],
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub duration_seconds: f32,
+    pub falloff: LightFlashFalloff,
+}
+
+impl Default for LightFlashDefinition {
+    fn default() -> Self {
+        Self {
+            offset: [0.0, 0.0, 0.0],
+            color: [1.0, 1.0, 1.0],
+            intensity: 1.0,
+            duration_seconds: 0.1,
+            falloff: LightFlashFalloff::Linear,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+pub enum LightFlashFalloff {
+    /// full intensity for the whole duration
+    Constant,
+    Linear,
+    Quadratic,
+}
+
+impl LightFlashFalloff {
+    /// t is the proportion of the flash's duration that has elapsed, in [0, 1]
+    pub fn intensity_factor(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            LightFlashFalloff::Constant => 1.0,
+            LightFlashFalloff::Linear => 1.0 - t,
+            LightFlashFalloff::Quadratic => (1.0 - t) * (1.0 - t),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EffectFile {
+    pub path: GameFilePath,
+    pub effects: HashMap<String, EffectDefinition>,
+    /// keyed by DecalDefinition::texture, they're read by load but not by parse
+    pub decal_textures: HashMap<String, image::RgbaImage>,
+}
+
+impl EffectFile {
+    pub async fn load(path: GameFilePath) -> Result<Self> {
+        let text = FileManager::read_to_string(&path).await?;
+        let mut effect_file = Self::parse(path, &text)?;
+
+        let textures: HashSet<_> = effect_file
+            .effects
+            .values()
+            .flat_map(|effect| effect.decals.iter().map(|decal| decal.texture.clone()))
+            .collect();
+        for texture in textures {
+            let texture_path = effect_file.relative_path(&texture);
+            let bytes = FileManager::read(&texture_path).await?;
+            let image = image::load_from_memory(&bytes)?.to_rgba8();
+            effect_file.decal_textures.insert(texture, image);
+        }
+        Ok(effect_file)
+    }
+
+    pub fn parse(path: GameFilePath, text: &str) -> Result<Self> {
+        let effects: HashMap<String, EffectDefinition> = ron::from_str(text)?;
+
+        let errors: Vec<_> = effects
+            .iter()
+            .flat_map(|(name, effect)| {
+                effect
+                    .validate()
+                    .into_iter()
+                    .map(move |error| format!("  - {name}: {error}"))
+            })
+            .collect();
+
+        if !errors.is_empty() {
+            anyhow::bail!("Invalid effect definitions:\n{}", errors.join("\n"));
+        }
+
+        Ok(Self {
+            path,
+            effects,
+            decal_textures: HashMap::new(),
+        })
+    }
+
+    /// The path of a file referenced by the effects, e.g. a decal texture
+    pub fn relative_path(&self, path: &str) -> GameFilePath {
+        let mut result = self.path.clone();
+        result.relative_path = self
+            .path
+            .relative_path
+            .parent()
+            .unwrap_or(std::path::Path::new(""))
+            .join(path);
+        result
+    }
+}
+
+impl EffectDefinition {
+    /// returns a list of human-readable problems with the definition
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+
+        let mut check = |condition: bool, message: String| {
+            if !condition {
+                errors.push(message);
+            }
+        };
+
+        let is_range = |(min, max): (f32, f32)| min >= 0.0 && min <= max;
+        let is_color = |color: &[f32]| color.iter().all(|c| c.is_finite() && *c >= 0.0);
+
+        for (i, emitter) in self.particle_emitters.iter().enumerate() {
+            check(
+                emitter.spawn_rate >= 0.0,
+                format!("particle_emitters[{i}]: spawn_rate must not be negative"),
+            );
+            check(
+                emitter.spawn_rate > 0.0 || emitter.burst_count > 0,
+                format!("particle_emitters[{i}]: spawn_rate or burst_count must be set"),
+            );
+            check(
+                emitter.max_particles > 0,
+                format!("particle_emitters[{i}]: max_particles must be greater than 0"),
+            );
+            check(
+                emitter
+                    .duration_seconds
+                    .map_or(true, |duration| duration > 0.0),
+                format!("particle_emitters[{i}]: duration_seconds must be positive"),
+            );
+            check(
+                is_range(emitter.lifetime_seconds),
+                format!(
+                    "particle_emitters[{i}]: lifetime_seconds must be a non-negative (min, max) range"
+                ),
+            );
+            check(
+                is_range(emitter.initial_speed),
+                format!(
+                    "particle_emitters[{i}]: initial_speed must be a non-negative (min, max) range"
+                ),
+            );
+            check(
+                (0.0..=180.0).contains(&emitter.spread_angle_deg),
+                format!("particle_emitters[{i}]: spread_angle_deg must be in [0, 180]"),
+            );
+            check(
+                is_color(&emitter.start_color) && is_color(&emitter.end_color),
+                format!("particle_emitters[{i}]: colors must be non-negative"),
+            );
+            check(
+                emitter.start_size >= 0.0 && emitter.end_size >= 0.0,
+                format!("particle_emitters[{i}]: sizes must not be negative"),
+            );
+        }
+
+        for (i, decal) in self.decals.iter().enumerate() {
+            check(
+                !decal.texture.is_empty(),
+                format!("decals[{i}]: texture must be set"),
+            );
+            check(
+                decal.size[0] > 0.0 && decal.size[1] > 0.0,
+                format!("decals[{i}]: size must be positive"),
+            );
+            check(
+                is_color(&decal.color),
+                format!("decals[{i}]: color must be non-negative"),
+            );
+            check(
+                decal.lifetime_seconds > 0.0,
+                format!("decals[{i}]: lifetime_seconds must be positive"),
+            );
+            check(
+                (0.0..=decal.lifetime_seconds).contains(&decal.fade_out_seconds),
+                format!("decals[{i}]: fade_out_seconds must be in [0, lifetime_seconds]"),
+            );
+        }
+
+        for (i, flash) in self.light_flashes.iter().enumerate() {
+            check(
+                is_color(&flash.color),
+                format!("light_flashes[{i}]: color must be non-negative"),
+            );
+            check(
+                flash.intensity >= 0.0,
+                format!("light_flashes[{i}]: intensity must not be negative"),
+            );
+            check(
+                flash.duration_seconds > 0.0,
+                format!("light_flashes[{i}]: duration_seconds must be positive"),
+            );
+        }
+
+        errors
+    }
+}
+
+/// Holds all loaded effect files and lets gameplay code look effects up by name.
+/// Call `update` every frame to pick up newly loaded and hot-reloaded files.
+#[derive(Debug)]
+pub struct EffectLibrary {
+    files: HashMap<AssetId, EffectFile>,
+    /// every file given to load_file, including the ones that failed to parse so that
+    /// fixing them reloads them
+    paths: HashMap<AssetId, GameFilePath>,
+    file_watcher: FileWatcher<AssetId>,
+    /// the decals of each effect with their textures bound, see decals
+    decal_params: HashMap<String, Vec<DecalParams>>,
+    pub enable_hot_reload: bool,
+}
+
+impl Default for EffectLibrary {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EffectLibrary {
+    pub fn new() -> Self {
+        Self {
+            files: HashMap::new(),
+            paths: HashMap::new(),
+            file_watcher: FileWatcher::new(HOT_RELOAD_POLL_INTERVAL),
+            decal_params: HashMap::new(),
+            enable_hot_reload: cfg!(debug_assertions),
+        }
+    }
+
+    /// Starts loading the file and watches it for changes
+    pub fn load_file(&mut self, asset_loader: &AssetLoader, path: GameFilePath) -> AssetId {
+        let asset_id = asset_loader.load_effect_file(path.clone());
+        self.watch(asset_id, path);
+        asset_id
+    }
+
+    fn watch(&mut self, asset_id: AssetId, path: GameFilePath) {
+        self.file_watcher.watch(asset_id, [path.resolve().into()]);
+        self.paths.insert(asset_id, path);
+    }
+
+    /// effect names are expected to be unique across all loaded files
+    pub fn get(&self, name: &str) -> Option<&EffectDefinition> {
+        self.files
+            .values()
+            .find_map(|effect_file| effect_file.effects.get(name))
+    }
+
+    /// The decals of the effect, ready to be given to Decals::spawn. Empty until the file
+    /// that defines the effect is loaded
+    pub fn decals(&self, name: &str) -> &[DecalParams] {
+        self.decal_params
+            .get(name)
+            .map_or(&[], |params| params.as_slice())
+    }
+
+    pub fn effect_names(&self) -> impl Iterator<Item = &String> {
+        self.files
+            .values()
+            .flat_map(|effect_file| effect_file.effects.keys())
+    }
+
+    #[profiling::function]
+    pub fn update(&mut self, asset_loader: &AssetLoader, renderer: &Renderer) {
+        for (asset_id, effect_file) in asset_loader.loaded_effects.lock().unwrap().drain() {
+            if self.files.contains_key(&asset_id) {
+                log::info!("Reloaded effect file {:?}", effect_file.path.relative_path);
+            }
+            // loaded directly with AssetLoader::load_effect_file
+            if !self.paths.contains_key(&asset_id) {
+                self.watch(asset_id, effect_file.path.clone());
+            }
+            self.bind_decals(renderer, &effect_file);
+            self.files.insert(asset_id, effect_file);
+        }
+
+        if !self.enable_hot_reload {
+            return;
+        }
+
+        // a file that failed to parse is still watched so that fixing it reloads it
+        for asset_id in self.file_watcher.poll() {
+            if let Some(path) = self.paths.get(&asset_id) {
+                asset_loader.reload_effect_file(asset_id, path.clone());
+            }
+        }
+    }
+
+    /// Each texture gets one material that's shared by all the decals using it
+    fn bind_decals(&mut self, renderer: &Renderer, effect_file: &EffectFile) {
+        let mut materials = HashMap::new();
+        for (texture, image) in &effect_file.decal_textures {
+            let bind_material = || {
+                let texture = Texture::from_decoded_image(
+                    &renderer.base,
+                    &image.clone().into(),
+                    Some(texture.as_str()),
+                    None,
+                    true,
+                    &Default::default(),
+                )?;
+                Renderer::bind_pbr_material(
+                    &renderer.base,
+                    &renderer.constant_data,
+                    &mut renderer.data.lock().unwrap(),
+                    &PbrTextures {
+                        base_color: Some(&texture),
+                        ..Default::default()
+                    },
+                    Default::default(),
+                )
+            };
+            match bind_material() {
+                Ok(material) => {
+                    materials.insert(texture, material);
+                }
+                Err(err) => log::error!("Error binding decal texture {texture:?}: {err}"),
+            }
+        }
+
+        for (name, effect) in &effect_file.effects {
+            let decal_params = effect
+                .decals
+                .iter()
+                .filter_map(|decal| {
+                    let material = materials.get(&decal.texture)?;
+                    Some(DecalParams::from_definition(decal, *material))
+                })
+                .collect();
+            self.decal_params.insert(name.clone(), decal_params);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manager::GamePathMaker;
+
+    #[test]
+    fn effect_files_are_parsed_and_validated() {
+        let path = GamePathMaker::new(None).make("effects.ron");
+        let effect_file = EffectFile::parse(
+            path.clone(),
+            r#"{
+                "muzzle_flash": (
+                    particle_emitters: [(burst_count: 12, lifetime_seconds: (0.05, 0.15))],
+                    light_flashes: [(color: (1.0, 0.8, 0.5), intensity: 4.0)],
+                ),
+            }"#,
+        )
+        .unwrap();
+        let muzzle_flash = &effect_file.effects["muzzle_flash"];
+        assert_eq!(muzzle_flash.particle_emitters[0].burst_count, 12);
+        assert_eq!(muzzle_flash.particle_emitters[0].max_particles, 256);
+        assert_eq!(muzzle_flash.light_flashes[0].intensity, 4.0);
+
+        let error = EffectFile::parse(
+            path.clone(),
+            r#"{ "smoke": (particle_emitters: [(lifetime_seconds: (2.0, 1.0))]) }"#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("smoke: particle_emitters[0]"));
+        assert!(EffectFile::parse(path, r#"{ "smoke": (unknown_field: 1) }"#).is_err());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn files_that_failed_to_load_are_still_watched() {
+        let directory = std::env::temp_dir().join(format!("ikari_effects_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("broken.ron"), "{ oops").unwrap();
+        let path = GamePathMaker::new(Some(directory.clone())).make("broken.ron");
+
+        let mut library = EffectLibrary {
+            file_watcher: FileWatcher::new(Duration::ZERO),
+            ..Default::default()
+        };
+        library.watch(AssetId::default(), path);
+        // nothing was loaded but the file is still polled
+        assert!(library.get("smoke").is_none());
+        assert!(library.file_watcher.poll().is_empty());
+
+        std::fs::remove_file(directory.join("broken.ron")).unwrap();
+        assert_eq!(library.file_watcher.poll(), vec![AssetId::default()]);
+        assert!(library.file_watcher.poll().is_empty());
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
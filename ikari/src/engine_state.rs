@@ -2,39 +2,138 @@ use std::sync::{Arc, Mutex};
 
 use crate::{
     audio::{AudioManager, AudioStreams},
+    bone_overrides::BoneOverrides,
+    dynamic_resolution::DynamicResolution,
+    ecs::{Entity, PhysicsBody, SceneNode, Schedule, World},
+    entity_pool::EntityPool,
+    event_bus::EventBus,
+    frame_limiter::FrameLimiter,
+    frame_profiler::FrameProfiler,
+    game_state_stack::GameStateStack,
+    large_world::LargeWorld,
+    light_animation::LightAnimations,
+    observers::SceneObservers,
     physics::PhysicsState,
     scene::Scene,
+    simulation::{FixedTimestep, SceneSnapshot, SimulationSnapshot},
+    time_control::TimeControl,
     time_tracker::TimeTracker,
+    tween::Tweens,
+    wind::Wind,
 };
 
 pub struct EngineState {
     pub scene: Scene,
     pub(crate) time_tracker: Option<TimeTracker>,
+    /// pause and slow motion for the game world, see TimeControl
+    pub time_control: TimeControl,
     pub physics_state: PhysicsState,
     pub audio_streams: AudioStreams,
     pub audio_manager: Arc<Mutex<AudioManager>>,
+    pub game_state_stack: GameStateStack,
+    pub scene_observers: SceneObservers,
+    /// rate at which gameloop calls the fixed update of the game
+    pub simulation_timestep: FixedTimestep,
+    pub world: World,
+    pub systems: Schedule,
+    pub frame_limiter: FrameLimiter,
+    pub frame_profiler: FrameProfiler,
+    pub dynamic_resolution: DynamicResolution,
+    /// see HealthSystem for the events sent by the engine
+    pub events: EventBus,
+    pub tweens: Tweens,
+    /// procedural bone rotations applied after on_update, see BoneOverrides
+    pub bone_overrides: BoneOverrides,
+    pub wind: Wind,
+    /// flicker, pulse and color cycling of the point lights, updated after each fixed update
+    pub light_animations: LightAnimations,
+    /// f64 positions and camera-relative rendering, off by default
+    pub large_world: LargeWorld,
+    /// the entities of the projectiles fired by the weapons, see update_projectiles
+    pub projectile_pool: EntityPool,
+    /// the rigid bodies of the pieces of the broken Destructibles, see update_debris
+    pub debris_pool: EntityPool,
 }
 
 impl EngineState {
     pub fn new() -> anyhow::Result<Self> {
-        let (audio_manager, audio_streams) = AudioManager::new()?;
+        Ok(Self::with_audio(AudioManager::new()?))
+    }
+
+    /// Without an audio output device, for headless rendering
+    pub fn new_headless() -> Self {
+        Self::with_audio(AudioManager::new_without_output())
+    }
 
+    fn with_audio((audio_manager, audio_streams): (AudioManager, AudioStreams)) -> Self {
         let audio_manager_mutex = Arc::new(Mutex::new(audio_manager));
 
-        Ok(EngineState {
+        EngineState {
             scene: Scene::default(),
             audio_streams,
             audio_manager: audio_manager_mutex,
             time_tracker: None,
+            time_control: TimeControl::default(),
             physics_state: PhysicsState::new(),
-        })
+            game_state_stack: GameStateStack::default(),
+            scene_observers: SceneObservers::default(),
+            simulation_timestep: FixedTimestep::default(),
+            world: World::default(),
+            systems: Schedule::default(),
+            frame_limiter: FrameLimiter::default(),
+            frame_profiler: FrameProfiler::default(),
+            dynamic_resolution: DynamicResolution::default(),
+            events: EventBus::default(),
+            tweens: Tweens::default(),
+            bone_overrides: BoneOverrides::default(),
+            wind: Wind::default(),
+            light_animations: LightAnimations::default(),
+            large_world: LargeWorld::default(),
+            projectile_pool: EntityPool::default(),
+            debris_pool: EntityPool::default(),
+        }
     }
 
     pub(crate) fn on_frame_started(&mut self) {
         self.time_tracker = self.time_tracker.or_else(|| TimeTracker::new().into());
         if let Some(time_tracker) = &mut self.time_tracker {
             time_tracker.on_frame_started();
+            self.time_control.on_frame_started(
+                time_tracker.last_frame_time(),
+                self.simulation_timestep.timestep_seconds,
+                !self.game_state_stack.should_run_simulation(),
+            );
+        }
+        self.frame_profiler.on_frame_started();
+    }
+
+    /// also removes the entity's scene node and rigid body, if it has them
+    pub fn despawn_entity(&mut self, entity: Entity) {
+        if let Some(SceneNode(node_id)) = self.world.get::<SceneNode>(entity).copied() {
+            self.scene_observers.remove_node_observers(node_id);
+            self.scene.remove_node(node_id);
+        }
+        if let Some(PhysicsBody(rigid_body_handle)) = self.world.get::<PhysicsBody>(entity).copied()
+        {
+            self.physics_state.remove_rigid_body(rigid_body_handle);
         }
+        self.world.despawn(entity);
+    }
+
+    pub fn snapshot_simulation(&self) -> SimulationSnapshot {
+        SimulationSnapshot {
+            tick: self.simulation_timestep.tick(),
+            scene: SceneSnapshot::new(&self.scene),
+            physics: self.physics_state.snapshot(),
+        }
+    }
+
+    /// The game is then expected to restore its own state. When rolling back, it reruns its fixed
+    /// update for each tick that followed the snapshot, calling increment_tick after each one
+    pub fn restore_simulation(&mut self, snapshot: &SimulationSnapshot) {
+        snapshot.scene.restore(&mut self.scene);
+        self.physics_state.restore(&snapshot.physics);
+        self.simulation_timestep.set_tick(snapshot.tick);
     }
 
     pub fn time(&self) -> TimeTracker {
@@ -0,0 +1,277 @@
+use crate::ecs::{Entity, PhysicsBody, SceneNode};
+use crate::engine_state::EngineState;
+use crate::physics::rapier3d_f64::prelude::*;
+use crate::ragdoll::transform_to_isometry;
+use crate::transform::Transform;
+
+use std::collections::{HashMap, VecDeque};
+
+/// max_size of the pools made with default
+pub const DEFAULT_MAX_POOL_SIZE: usize = 256;
+
+/// Marker component of the entities sitting unused in an EntityPool,
+/// the systems that go through all the entities of a kind may want to skip them
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Pooled;
+
+/// Recycles the entities that are spawned and despawned all the time, e.g. bullets, debris or
+/// pickups, so their nodes and rigid bodies are made once instead of on every spawn. The
+/// released entities stay in the world with a Pooled marker, their node hidden and their rigid
+/// body disabled. When max_size entities are out at once, acquire reuses the oldest one.
+/// EngineState has the pools of the projectiles and the debris, take them out with
+/// std::mem::take to pass them the engine state
+#[derive(Debug, Clone)]
+pub struct EntityPool {
+    pub max_size: usize,
+    /// (entity, acquisition), oldest first. Released entities are left behind and skipped
+    /// since their acquisition doesn't match anymore
+    acquisition_order: VecDeque<(Entity, u64)>,
+    /// the active entities with the acquisition that took them out
+    active: HashMap<Entity, u64>,
+    next_acquisition: u64,
+    free: Vec<Entity>,
+}
+
+impl Default for EntityPool {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_POOL_SIZE)
+    }
+}
+
+impl EntityPool {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            max_size,
+            acquisition_order: VecDeque::new(),
+            active: HashMap::new(),
+            next_acquisition: 0,
+            free: vec![],
+        }
+    }
+
+    /// Spawns entities up front until the pool holds count of them, e.g. while the level loads.
+    /// spawner makes a new entity, with its SceneNode and PhysicsBody if it has any
+    pub fn warm_up(
+        &mut self,
+        engine_state: &mut EngineState,
+        count: usize,
+        mut spawner: impl FnMut(&mut EngineState) -> Entity,
+    ) {
+        while self.active.len() + self.free.len() < count.min(self.max_size) {
+            let entity = spawner(engine_state);
+            deactivate(engine_state, entity);
+            self.free.push(entity);
+        }
+    }
+
+    /// Takes an entity out of the pool and gives its node the transform, its rigid body is moved
+    /// there at rest. A new entity is only made with spawner if none is free and the pool isn't
+    /// full
+    pub fn acquire(
+        &mut self,
+        engine_state: &mut EngineState,
+        transform: Transform,
+        spawner: impl FnOnce(&mut EngineState) -> Entity,
+    ) -> Entity {
+        let recycled_entity = loop {
+            let entity = match self.free.pop() {
+                Some(entity) => Some(entity),
+                None if self.active.len() >= self.max_size.max(1) => self.pop_oldest_active(),
+                None => None,
+            };
+            // despawned by someone else
+            if entity.is_some_and(|entity| !engine_state.world.is_alive(entity)) {
+                continue;
+            }
+            break entity;
+        };
+        let entity = recycled_entity.unwrap_or_else(|| spawner(engine_state));
+
+        activate(engine_state, entity, transform);
+        let acquisition = self.next_acquisition;
+        self.next_acquisition += 1;
+        self.active.insert(entity, acquisition);
+        self.acquisition_order.push_back((entity, acquisition));
+        entity
+    }
+
+    /// Puts the entity back in the pool, returns false if it isn't one of its active entities
+    pub fn release(&mut self, engine_state: &mut EngineState, entity: Entity) -> bool {
+        if self.active.remove(&entity).is_none() {
+            return false;
+        }
+        // the released entries are dropped once they outnumber the active ones
+        if self.acquisition_order.len() > self.active.len() * 2 + 16 {
+            let active = &self.active;
+            self.acquisition_order
+                .retain(|(entity, acquisition)| active.get(entity) == Some(acquisition));
+        }
+        deactivate(engine_state, entity);
+        self.free.push(entity);
+        true
+    }
+
+    pub fn is_active(&self, entity: Entity) -> bool {
+        self.active.contains_key(&entity)
+    }
+
+    pub fn active_count(&self) -> usize {
+        self.active.len()
+    }
+
+    pub fn free_count(&self) -> usize {
+        self.free.len()
+    }
+
+    /// Despawns all the entities of the pool, the active ones too
+    pub fn clear(&mut self, engine_state: &mut EngineState) {
+        self.acquisition_order.clear();
+        for entity in self
+            .active
+            .drain()
+            .map(|(entity, _)| entity)
+            .chain(self.free.drain(..))
+        {
+            engine_state.despawn_entity(entity);
+        }
+    }
+
+    fn pop_oldest_active(&mut self) -> Option<Entity> {
+        while let Some((entity, acquisition)) = self.acquisition_order.pop_front() {
+            if self.active.get(&entity) == Some(&acquisition) {
+                self.active.remove(&entity);
+                return Some(entity);
+            }
+        }
+        None
+    }
+}
+
+fn activate(engine_state: &mut EngineState, entity: Entity, transform: Transform) {
+    engine_state.world.remove::<Pooled>(entity);
+    let node_id = engine_state
+        .world
+        .get::<SceneNode>(entity)
+        .map(|SceneNode(node_id)| *node_id);
+
+    if let Some(node) = node_id.and_then(|node_id| engine_state.scene.get_node_mut(node_id)) {
+        node.transform = transform;
+        node.visible = true;
+        node.reset_interpolation();
+    }
+
+    if let Some(PhysicsBody(rigid_body_handle)) = engine_state.world.get::<PhysicsBody>(entity) {
+        let physics_state = &mut engine_state.physics_state;
+        let isometry = transform_to_isometry(&transform);
+        if let Some(rigid_body) = physics_state.rigid_body_set.get_mut(*rigid_body_handle) {
+            rigid_body.set_enabled(true);
+            rigid_body.set_position(isometry, true);
+            rigid_body.set_linvel(Vector::zeros(), true);
+            rigid_body.set_angvel(Vector::zeros(), true);
+        }
+        // so the node doesn't slide from where the body was released
+        if let Some(node_id) = node_id {
+            physics_state.teleport_rigid_body_component(node_id, isometry);
+        }
+    }
+}
+
+fn deactivate(engine_state: &mut EngineState, entity: Entity) {
+    engine_state.world.insert(entity, Pooled);
+
+    if let Some(node) = engine_state
+        .world
+        .get::<SceneNode>(entity)
+        .and_then(|SceneNode(node_id)| engine_state.scene.get_node_mut(*node_id))
+    {
+        node.visible = false;
+    }
+
+    if let Some(rigid_body) =
+        engine_state
+            .world
+            .get::<PhysicsBody>(entity)
+            .and_then(|PhysicsBody(rigid_body_handle)| {
+                engine_state
+                    .physics_state
+                    .rigid_body_set
+                    .get_mut(*rigid_body_handle)
+            })
+    {
+        rigid_body.set_linvel(Vector::zeros(), false);
+        rigid_body.set_angvel(Vector::zeros(), false);
+        rigid_body.set_enabled(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::GameNodeDesc;
+    use crate::transform::TransformBuilder;
+
+    use glam::f32::Vec3;
+
+    fn spawn_bullet(engine_state: &mut EngineState) -> Entity {
+        let node_id = engine_state.scene.add_node(GameNodeDesc::default()).id();
+        let rigid_body_handle = engine_state
+            .physics_state
+            .rigid_body_set
+            .insert(RigidBodyBuilder::dynamic().build());
+        engine_state
+            .physics_state
+            .add_rigid_body_component(node_id, rigid_body_handle);
+        let entity = engine_state.world.spawn();
+        engine_state.world.insert(entity, SceneNode(node_id));
+        engine_state
+            .world
+            .insert(entity, PhysicsBody(rigid_body_handle));
+        entity
+    }
+
+    #[test]
+    fn entities_are_recycled_instead_of_respawned() {
+        let mut engine_state = EngineState::new_headless();
+        let mut pool = EntityPool::new(2);
+
+        pool.warm_up(&mut engine_state, 5, spawn_bullet);
+        assert_eq!(pool.free_count(), 2);
+        let node_count = engine_state.scene.node_count();
+
+        let position = Vec3::new(1.0, 2.0, 3.0);
+        let transform = TransformBuilder::new().position(position).build();
+        let first = pool.acquire(&mut engine_state, transform, spawn_bullet);
+        let second = pool.acquire(&mut engine_state, transform, spawn_bullet);
+        assert!(!engine_state.world.has::<Pooled>(first));
+        let SceneNode(node_id) = *engine_state.world.get::<SceneNode>(first).unwrap();
+        assert!(engine_state.scene.get_node(node_id).unwrap().visible);
+        let PhysicsBody(rigid_body_handle) = *engine_state.world.get::<PhysicsBody>(first).unwrap();
+        let rigid_body = &engine_state.physics_state.rigid_body_set[rigid_body_handle];
+        assert!(rigid_body.is_enabled());
+        assert_eq!(rigid_body.translation().y, 2.0);
+
+        // full, the oldest one is reused
+        assert_eq!(
+            pool.acquire(&mut engine_state, transform, spawn_bullet),
+            first
+        );
+
+        assert!(pool.release(&mut engine_state, second));
+        assert!(!pool.release(&mut engine_state, second));
+        assert!(engine_state.world.has::<Pooled>(second));
+        let SceneNode(node_id) = *engine_state.world.get::<SceneNode>(second).unwrap();
+        assert!(!engine_state.scene.get_node(node_id).unwrap().visible);
+        assert_eq!(
+            pool.acquire(&mut engine_state, transform, spawn_bullet),
+            second
+        );
+        // the earlier acquisition of the released entity doesn't count anymore
+        assert_eq!(
+            pool.acquire(&mut engine_state, transform, spawn_bullet),
+            first
+        );
+
+        assert_eq!(engine_state.scene.node_count(), node_count);
+        assert_eq!(engine_state.world.entity_count(), 2);
+    }
+}
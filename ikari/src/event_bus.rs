@@ -0,0 +1,55 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+/// Queues of events keyed by their type, in EngineState::events. Systems send events and
+/// whoever handles them drains them, e.g. the health system drains the DamageEvents sent by
+/// the weapons. Events that nobody drains pile up, so only send the ones that are handled
+#[derive(Default)]
+pub struct EventBus {
+    queues: HashMap<TypeId, Box<dyn Any>>,
+}
+
+impl EventBus {
+    pub fn send<T: 'static>(&mut self, event: T) {
+        self.queue_mut::<T>().push(event);
+    }
+
+    /// Takes the events of type T in the order they were sent
+    pub fn drain<T: 'static>(&mut self) -> Vec<T> {
+        std::mem::take(self.queue_mut::<T>())
+    }
+
+    /// The events of type T sent since they were last drained
+    pub fn peek<T: 'static>(&self) -> &[T] {
+        self.queues
+            .get(&TypeId::of::<T>())
+            .and_then(|queue| queue.downcast_ref::<Vec<T>>())
+            .map_or(&[], |queue| queue.as_slice())
+    }
+
+    fn queue_mut<T: 'static>(&mut self) -> &mut Vec<T> {
+        self.queues
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::<Vec<T>>::default())
+            .downcast_mut::<Vec<T>>()
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_are_queued_per_type() {
+        let mut events = EventBus::default();
+        events.send(1u32);
+        events.send("hit");
+        events.send(2u32);
+
+        assert_eq!(events.peek::<u32>(), &[1, 2]);
+        assert_eq!(events.drain::<u32>(), vec![1, 2]);
+        assert!(events.drain::<u32>().is_empty());
+        assert_eq!(events.drain::<&str>(), vec!["hit"]);
+    }
+}
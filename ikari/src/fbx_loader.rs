@@ -0,0 +1,693 @@
+use crate::asset_loader::SceneAssetLoadParams;
+use crate::asset_registry::{MaterialHandle, MeshHandle};
+use crate::collisions::Aabb;
+use crate::file_manager::{FileManager, GameFilePath};
+use crate::mesh::*;
+use crate::renderer::*;
+use crate::sampler_cache::*;
+use crate::scene::*;
+use crate::texture::RawImage;
+use crate::transform::*;
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use approx::abs_diff_eq;
+use glam::f32::{Mat4, Quat, Vec2, Vec3, Vec4};
+
+/// fbx animations are stored as curves with arbitrary tangents so we bake them
+/// down to linear keyframes at this rate
+const ANIMATION_SAMPLE_RATE_HZ: f64 = 30.0;
+
+/// the engine's skinning supports at most 4 bone influences per vertex
+const MAX_BONE_INFLUENCES: usize = 4;
+
+#[profiling::function]
+pub async fn build_scene(
+    fbx_bytes: &[u8],
+    params: SceneAssetLoadParams,
+) -> Result<(Scene, BindableSceneData)> {
+    let fbx_scene = {
+        profiling::scope!("Parse fbx");
+        ufbx::load_memory(
+            fbx_bytes,
+            ufbx::LoadOpts {
+                target_axes: ufbx::CoordinateAxes::right_handed_y_up(),
+                target_unit_meters: 1.0,
+                generate_missing_normals: true,
+                ..Default::default()
+            },
+        )
+        .map_err(|err| anyhow::anyhow!("Failed to parse fbx file: {:?}", err))?
+    };
+
+    let mut textures: Vec<BindableTexture> = vec![];
+    // (fbx texture typed id, is srgb) -> bindable texture index
+    let mut texture_index_map: HashMap<(u32, bool), usize> = HashMap::new();
+
+    let mut bindable_pbr_materials: Vec<BindablePbrMaterial> =
+        Vec::with_capacity(fbx_scene.materials.len());
+    {
+        profiling::scope!("materials");
+
+        for material in fbx_scene.materials.iter() {
+            let pbr = &material.pbr;
+            let indexed_textures = IndexedPbrTextures {
+                base_color: get_texture_index(
+                    &pbr.base_color,
+                    true,
+                    &params.path,
+                    &mut textures,
+                    &mut texture_index_map,
+                )
+                .await,
+                normal: get_texture_index(
+                    &pbr.normal_map,
+                    false,
+                    &params.path,
+                    &mut textures,
+                    &mut texture_index_map,
+                )
+                .await,
+                // fbx stores metalness and roughness in separate textures which
+                // would need to be packed together, so only the factors are used
+                metallic_roughness: None,
+                emissive: get_texture_index(
+                    &pbr.emission_color,
+                    true,
+                    &params.path,
+                    &mut textures,
+                    &mut texture_index_map,
+                )
+                .await,
+                ambient_occlusion: get_texture_index(
+                    &pbr.ambient_occlusion,
+                    false,
+                    &params.path,
+                    &mut textures,
+                    &mut texture_index_map,
+                )
+                .await,
+                lightmap: None,
+            };
+
+            let base_color = pbr.base_color.value_vec4;
+            let base_color_factor = pbr.base_color_factor.value_real as f32;
+            let emission = pbr.emission_color.value_vec4;
+            let emission_factor = pbr.emission_factor.value_real as f32;
+
+            bindable_pbr_materials.push(BindablePbrMaterial {
+                textures: indexed_textures,
+                dynamic_pbr_params: DynamicPbrParams {
+                    base_color_factor: Vec4::new(
+                        base_color.x as f32 * base_color_factor,
+                        base_color.y as f32 * base_color_factor,
+                        base_color.z as f32 * base_color_factor,
+                        base_color.w as f32,
+                    ),
+                    emissive_factor: Vec3::new(
+                        emission.x as f32,
+                        emission.y as f32,
+                        emission.z as f32,
+                    ) * emission_factor,
+                    metallic_factor: pbr.metalness.value_real as f32,
+                    roughness_factor: pbr.roughness.value_real as f32,
+                    ..Default::default()
+                },
+            });
+        }
+    }
+
+    // fbx node typed id -> indexed node index
+    let node_index_map: HashMap<u32, usize> = fbx_scene
+        .nodes
+        .iter()
+        .enumerate()
+        .map(|(node_index, node)| (node.element.typed_id, node_index))
+        .collect();
+
+    let mut nodes: Vec<IndexedGameNodeDesc> = fbx_scene
+        .nodes
+        .iter()
+        .map(|node| IndexedGameNodeDesc {
+            transform: ufbx_transform_to_transform(&node.local_transform),
+            skin_index: None,
+            visual: None, // will be added later
+            name: (!node.element.name.is_empty()).then(|| node.element.name.to_string()),
+            parent_index: node
+                .parent
+                .as_ref()
+                .and_then(|parent| node_index_map.get(&parent.element.typed_id).copied()),
+        })
+        .collect();
+
+    let mut bindable_meshes: Vec<BindableGeometryBuffers> = vec![];
+    let mut bindable_wireframe_meshes: Vec<BindableWireframeMesh> = vec![];
+    let mut skins: Vec<IndexedSkin> = vec![];
+
+    {
+        profiling::scope!("meshes");
+
+        for (node_index, node) in fbx_scene.nodes.iter().enumerate() {
+            let Some(mesh) = node.mesh.as_ref() else {
+                continue;
+            };
+
+            let skin_deformer = mesh.skin_deformers.first();
+            let mesh_parts = build_geometry(mesh, skin_deformer.map(|skin| &**skin))?;
+
+            let skin_index = skin_deformer.map(|skin| {
+                skins.push(build_skin(skin, &node_index_map));
+                skins.len() - 1
+            });
+
+            let mut visuals = vec![];
+            for (material_index, (geometry, wireframe_indices)) in mesh_parts {
+                bindable_meshes.push(geometry);
+                let mesh_index = bindable_meshes.len() - 1;
+
+                if params.generate_wireframe_meshes {
+                    bindable_wireframe_meshes.push(BindableWireframeMesh {
+                        source_mesh_index: mesh_index,
+                        indices: wireframe_indices,
+                    });
+                }
+
+                let pbr_material_index = match mesh
+                    .materials
+                    .get(material_index)
+                    .map(|material| material.element.typed_id as usize)
+                {
+                    Some(pbr_material_index) => pbr_material_index,
+                    None => {
+                        bindable_pbr_materials.push(BindablePbrMaterial {
+                            textures: Default::default(),
+                            dynamic_pbr_params: Default::default(),
+                        });
+                        bindable_pbr_materials.len() - 1
+                    }
+                };
+
+                visuals.push(GameNodeVisual::from_mesh_mat(
+                    MeshHandle::unbinded(mesh_index),
+                    Material::Pbr {
+                        binded_material: MaterialHandle::unbinded(pbr_material_index),
+                        dynamic_pbr_params: None,
+                    },
+                ));
+            }
+
+            // same 'auto-child' scheme as the gltf loader. skinned meshes are always
+            // split by material in fbx files so the skin goes on each child
+            if visuals.len() == 1 {
+                nodes[node_index].visual = visuals.pop();
+                nodes[node_index].skin_index = skin_index;
+            } else {
+                for (i, visual) in visuals.into_iter().enumerate() {
+                    nodes.push(IndexedGameNodeDesc {
+                        transform: Default::default(),
+                        skin_index,
+                        visual: Some(visual),
+                        name: nodes[node_index]
+                            .name
+                            .as_ref()
+                            .map(|name| format!("{name} (auto-child {i})")),
+                        parent_index: Some(node_index),
+                    });
+                }
+            }
+        }
+    }
+
+    for (skin_index, skin) in skins.iter_mut().enumerate() {
+        let Some(skeleton_mesh_index) = nodes
+            .iter()
+            .find(|node| node.skin_index == Some(skin_index))
+            .and_then(|node| node.visual.as_ref())
+            .map(|visual| visual.mesh.index())
+        else {
+            continue;
+        };
+        skin.bone_bounding_box_transforms = get_bone_bounding_box_transforms(
+            &skin.bone_inverse_bind_matrices,
+            &bindable_meshes[skeleton_mesh_index].vertices,
+        );
+    }
+
+    let animations = get_animations(&fbx_scene, &node_index_map);
+
+    let bindable_scene_data = BindableSceneData {
+        bindable_meshes,
+        bindable_wireframe_meshes,
+        bindable_pbr_materials,
+        textures,
+    };
+
+    log::debug!("Scene loaded ({:?}):", params.path.relative_path);
+
+    log::debug!("  - node count: {:?}", nodes.len());
+    log::debug!("  - skin count: {:?}", skins.len());
+    log::debug!("  - animation count: {:?}", animations.len());
+    log::debug!(
+        "  - mesh count: {:?}",
+        bindable_scene_data.bindable_meshes.len()
+    );
+
+    let scene = Scene::new(nodes, skins, animations);
+
+    Ok((scene, bindable_scene_data))
+}
+
+fn ufbx_transform_to_transform(transform: &ufbx::Transform) -> Transform {
+    TransformBuilder::new()
+        .position(Vec3::new(
+            transform.translation.x as f32,
+            transform.translation.y as f32,
+            transform.translation.z as f32,
+        ))
+        .rotation(ufbx_quat_to_quat(&transform.rotation))
+        .scale(Vec3::new(
+            transform.scale.x as f32,
+            transform.scale.y as f32,
+            transform.scale.z as f32,
+        ))
+        .build()
+}
+
+fn ufbx_quat_to_quat(quat: &ufbx::Quat) -> Quat {
+    Quat::from_xyzw(quat.x as f32, quat.y as f32, quat.z as f32, quat.w as f32)
+}
+
+fn ufbx_matrix_to_mat4(matrix: &ufbx::Matrix) -> Mat4 {
+    Mat4::from_cols_array_2d(&[
+        [matrix.m00 as f32, matrix.m10 as f32, matrix.m20 as f32, 0.0],
+        [matrix.m01 as f32, matrix.m11 as f32, matrix.m21 as f32, 0.0],
+        [matrix.m02 as f32, matrix.m12 as f32, matrix.m22 as f32, 0.0],
+        [matrix.m03 as f32, matrix.m13 as f32, matrix.m23 as f32, 1.0],
+    ])
+}
+
+async fn get_texture_index(
+    map: &ufbx::MaterialMap,
+    is_srgb: bool,
+    fbx_path: &GameFilePath,
+    textures: &mut Vec<BindableTexture>,
+    texture_index_map: &mut HashMap<(u32, bool), usize>,
+) -> Option<usize> {
+    let texture = map.texture.as_ref()?;
+    let key = (texture.element.typed_id, is_srgb);
+    if let Some(texture_index) = texture_index_map.get(&key) {
+        return Some(*texture_index);
+    }
+    match load_texture(texture, fbx_path, is_srgb).await {
+        Ok(bindable_texture) => {
+            textures.push(bindable_texture);
+            texture_index_map.insert(key, textures.len() - 1);
+            Some(textures.len() - 1)
+        }
+        Err(err) => {
+            log::warn!(
+                "{:?}: Failed to load texture {:?}, it will be skipped: {}",
+                fbx_path.relative_path,
+                texture.relative_filename,
+                err
+            );
+            None
+        }
+    }
+}
+
+async fn load_texture(
+    texture: &ufbx::Texture,
+    fbx_path: &GameFilePath,
+    is_srgb: bool,
+) -> Result<BindableTexture> {
+    let image_bytes = if !texture.content.is_empty() {
+        texture.content.to_vec()
+    } else {
+        let mut texture_path = fbx_path.clone();
+        texture_path.relative_path = fbx_path
+            .relative_path
+            .parent()
+            .unwrap()
+            .join(PathBuf::from(texture.relative_filename.to_string()));
+        FileManager::read(&texture_path).await?
+    };
+
+    let image = image::load_from_memory(&image_bytes)?.to_rgba8();
+
+    Ok(BindableTexture {
+        raw_image: RawImage::from(image),
+        name: (!texture.element.name.is_empty()).then(|| texture.element.name.to_string()),
+        format: Some(if is_srgb {
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        } else {
+            wgpu::TextureFormat::Rgba8Unorm
+        }),
+        sampler_descriptor: SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            use_texture_quality: true,
+            ..Default::default()
+        },
+    })
+}
+
+/// splits the mesh into one geometry per material, returning
+/// (mesh material index, (geometry, wireframe indices)) pairs
+#[profiling::function]
+fn build_geometry(
+    mesh: &ufbx::Mesh,
+    skin: Option<&ufbx::SkinDeformer>,
+) -> Result<Vec<(usize, (BindableGeometryBuffers, BindableIndices))>> {
+    let mut triangle_indices = vec![0u32; mesh.max_face_triangles * 3];
+
+    // mesh material index -> vertices
+    let mut vertices_by_material: HashMap<usize, Vec<Vertex>> = HashMap::new();
+
+    for (face_index, face) in mesh.faces.iter().enumerate() {
+        let material_index = mesh
+            .face_material
+            .get(face_index)
+            .map(|material_index| *material_index as usize)
+            .unwrap_or(0);
+        let vertices = vertices_by_material.entry(material_index).or_default();
+
+        let triangle_count = ufbx::triangulate_face(&mut triangle_indices, mesh, *face) as usize;
+        for index in &triangle_indices[..triangle_count * 3] {
+            let index = *index as usize;
+            let position = mesh.vertex_position[index];
+            let normal = mesh.vertex_normal[index];
+            let tex_coords = if mesh.vertex_uv.exists {
+                let uv = mesh.vertex_uv[index];
+                // fbx uvs have their origin at the bottom left
+                [uv.x as f32, 1.0 - uv.y as f32]
+            } else {
+                [0.0, 0.0]
+            };
+            let color = if mesh.vertex_color.exists {
+                let color = mesh.vertex_color[index];
+                [
+                    color.x as f32,
+                    color.y as f32,
+                    color.z as f32,
+                    color.w as f32,
+                ]
+            } else {
+                [1.0, 1.0, 1.0, 1.0]
+            };
+
+            let (bone_indices, bone_weights) = skin
+                .map(|skin| get_vertex_bone_data(skin, mesh.vertex_indices[index] as usize))
+                .unwrap_or(([0; 4], [1.0, 0.0, 0.0, 0.0]));
+
+            vertices.push(Vertex {
+                position: [position.x as f32, position.y as f32, position.z as f32],
+                normal: [normal.x as f32, normal.y as f32, normal.z as f32],
+                tex_coords,
+                color,
+                bone_indices,
+                bone_weights,
+                ..Default::default()
+            });
+        }
+    }
+
+    let mut material_indices: Vec<_> = vertices_by_material.keys().copied().collect();
+    material_indices.sort();
+
+    material_indices
+        .into_iter()
+        .map(|material_index| {
+            let mut vertices = vertices_by_material.remove(&material_index).unwrap();
+
+            compute_tangents(&mut vertices);
+
+            let bounding_box =
+                Aabb::make_from_points(vertices.iter().map(|vertex| Vec3::from(vertex.position)))
+                    .ok_or_else(|| {
+                    anyhow::anyhow!("Expected mesh to have at least two vertex positions")
+                })?;
+
+            // vertices are unrolled per triangle corner, so the index buffer is trivial
+            let indices: Vec<u32> = (0..vertices.len() as u32).collect();
+            let wireframe_indices: Vec<u32> = indices
+                .chunks(3)
+                .flat_map(|triangle| {
+                    [
+                        triangle[0],
+                        triangle[1],
+                        triangle[1],
+                        triangle[2],
+                        triangle[2],
+                        triangle[0],
+                    ]
+                })
+                .collect();
+
+            let (indices, wireframe_indices) = if vertices.len() <= u16::MAX as usize {
+                (
+                    BindableIndices::U16(indices.iter().map(|index| *index as u16).collect()),
+                    BindableIndices::U16(
+                        wireframe_indices
+                            .iter()
+                            .map(|index| *index as u16)
+                            .collect(),
+                    ),
+                )
+            } else {
+                (
+                    BindableIndices::U32(indices),
+                    BindableIndices::U32(wireframe_indices),
+                )
+            };
+
+            anyhow::Ok((
+                material_index,
+                (
+                    BindableGeometryBuffers {
+                        vertices,
+                        indices,
+                        bounding_box,
+                        collision_mesh: None,
+                    },
+                    wireframe_indices,
+                ),
+            ))
+        })
+        .collect()
+}
+
+fn compute_tangents(vertices: &mut [Vertex]) {
+    for triangle in vertices.chunks_mut(3) {
+        if triangle.len() < 3 {
+            continue;
+        }
+
+        let edge_1 = Vec3::from(triangle[1].position) - Vec3::from(triangle[0].position);
+        let edge_2 = Vec3::from(triangle[2].position) - Vec3::from(triangle[0].position);
+
+        let delta_uv_1 = Vec2::from(triangle[1].tex_coords) - Vec2::from(triangle[0].tex_coords);
+        let delta_uv_2 = Vec2::from(triangle[2].tex_coords) - Vec2::from(triangle[0].tex_coords);
+
+        let f = 1.0 / (delta_uv_1.x * delta_uv_2.y - delta_uv_2.x * delta_uv_1.y);
+
+        let (tangent, bitangent) = if abs_diff_eq!(f, 0.0, epsilon = 0.00001) || !f.is_finite() {
+            (Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0))
+        } else {
+            (
+                f * (delta_uv_2.y * edge_1 - delta_uv_1.y * edge_2),
+                f * (-delta_uv_2.x * edge_1 + delta_uv_1.x * edge_2),
+            )
+        };
+
+        for vertex in triangle.iter_mut() {
+            vertex.tangent = tangent.into();
+            vertex.bitangent = bitangent.into();
+        }
+    }
+}
+
+fn get_vertex_bone_data(skin: &ufbx::SkinDeformer, vertex_index: usize) -> ([u32; 4], [f32; 4]) {
+    let mut bone_indices = [0u32; 4];
+    let mut bone_weights = [0f32; 4];
+
+    let Some(skin_vertex) = skin.vertices.get(vertex_index) else {
+        return (bone_indices, [1.0, 0.0, 0.0, 0.0]);
+    };
+
+    // ufbx sorts the weights by decreasing influence
+    let weight_begin = skin_vertex.weight_begin as usize;
+    let weight_count = (skin_vertex.num_weights as usize).min(MAX_BONE_INFLUENCES);
+    let mut total_weight = 0.0;
+    for i in 0..weight_count {
+        let skin_weight = skin.weights[weight_begin + i];
+        bone_indices[i] = skin_weight.cluster_index;
+        bone_weights[i] = skin_weight.weight as f32;
+        total_weight += bone_weights[i];
+    }
+
+    if total_weight > 0.0 {
+        for weight in bone_weights.iter_mut() {
+            *weight /= total_weight;
+        }
+    } else {
+        bone_weights = [1.0, 0.0, 0.0, 0.0];
+    }
+
+    (bone_indices, bone_weights)
+}
+
+fn build_skin(skin: &ufbx::SkinDeformer, node_index_map: &HashMap<u32, usize>) -> IndexedSkin {
+    let (bone_node_indices, bone_inverse_bind_matrices) = skin
+        .clusters
+        .iter()
+        .map(|cluster| {
+            let bone_node_index = cluster
+                .bone_node
+                .as_ref()
+                .and_then(|bone_node| node_index_map.get(&bone_node.element.typed_id).copied())
+                .unwrap_or(0);
+            (
+                bone_node_index,
+                ufbx_matrix_to_mat4(&cluster.geometry_to_bone),
+            )
+        })
+        .unzip();
+
+    IndexedSkin {
+        bone_node_indices,
+        bone_inverse_bind_matrices,
+        bone_bounding_box_transforms: vec![], // computed once the skinned mesh is known
+    }
+}
+
+fn get_bone_bounding_box_transforms(
+    bone_inverse_bind_matrices: &[Mat4],
+    skeleton_mesh_vertices: &[Vertex],
+) -> Vec<Transform> {
+    bone_inverse_bind_matrices
+        .iter()
+        .enumerate()
+        .map(|(bone_index, bone_inv_bind_matrix)| {
+            let vertex_weight_threshold = 0.5f32;
+            let vertex_positions_for_node = skeleton_mesh_vertices
+                .iter()
+                .filter(|vertex| {
+                    vertex
+                        .bone_indices
+                        .iter()
+                        .zip(vertex.bone_weights.iter())
+                        .any(|(v_bone_index, v_bone_weight)| {
+                            *v_bone_index as usize == bone_index
+                                && *v_bone_weight > vertex_weight_threshold
+                        })
+                })
+                .map(|vertex| bone_inv_bind_matrix.transform_point3(Vec3::from(vertex.position)));
+            match Aabb::make_from_points(vertex_positions_for_node) {
+                Some(aabb) => TransformBuilder::new()
+                    .scale((aabb.max - aabb.min) / 2.0)
+                    .position(aabb.center())
+                    .build(),
+                None => TransformBuilder::new()
+                    .scale(Vec3::new(0.0, 0.0, 0.0))
+                    .build(),
+            }
+        })
+        .collect()
+}
+
+/// bakes every animation stack into linear translation/rotation/scale channels
+/// for each node that is affected by it
+#[profiling::function]
+fn get_animations(
+    fbx_scene: &ufbx::Scene,
+    node_index_map: &HashMap<u32, usize>,
+) -> Vec<IndexedAnimation> {
+    fbx_scene
+        .anim_stacks
+        .iter()
+        .map(|anim_stack| {
+            let length_seconds = (anim_stack.time_end - anim_stack.time_begin).max(0.0);
+            let keyframe_count = (length_seconds * ANIMATION_SAMPLE_RATE_HZ).ceil() as usize + 1;
+            let keyframe_timings: Vec<f32> = (0..keyframe_count)
+                .map(|i| (i as f64 / ANIMATION_SAMPLE_RATE_HZ).min(length_seconds) as f32)
+                .collect();
+
+            let animated_node_ids: Vec<u32> = anim_stack
+                .layers
+                .iter()
+                .flat_map(|layer| layer.anim_props.iter())
+                .filter_map(|anim_prop| anim_prop.element.as_node())
+                .map(|node| node.element.typed_id)
+                .collect::<std::collections::HashSet<_>>()
+                .into_iter()
+                .collect();
+
+            let mut channels = vec![];
+            for node_typed_id in animated_node_ids {
+                let Some(node_index) = node_index_map.get(&node_typed_id).copied() else {
+                    continue;
+                };
+                let node = &fbx_scene.nodes[node_index];
+
+                let samples: Vec<Transform> = keyframe_timings
+                    .iter()
+                    .map(|time| {
+                        ufbx_transform_to_transform(&ufbx::evaluate_transform(
+                            &anim_stack.anim,
+                            node,
+                            anim_stack.time_begin + *time as f64,
+                        ))
+                    })
+                    .collect();
+
+                let mut push_channel = |property, keyframe_values_u8: Vec<u8>| {
+                    channels.push(IndexedChannel {
+                        node_index,
+                        property,
+                        interpolation_type: gltf::animation::Interpolation::Linear,
+                        keyframe_timings: keyframe_timings.clone(),
+                        keyframe_values_u8,
+                    });
+                };
+
+                let translations: Vec<[f32; 3]> = samples
+                    .iter()
+                    .map(|sample| sample.position().into())
+                    .collect();
+                let rotations: Vec<[f32; 4]> = samples
+                    .iter()
+                    .map(|sample| sample.rotation().into())
+                    .collect();
+                let scales: Vec<[f32; 3]> =
+                    samples.iter().map(|sample| sample.scale().into()).collect();
+
+                push_channel(
+                    gltf::animation::Property::Translation,
+                    bytemuck::cast_slice(&translations).to_vec(),
+                );
+                push_channel(
+                    gltf::animation::Property::Rotation,
+                    bytemuck::cast_slice(&rotations).to_vec(),
+                );
+                push_channel(
+                    gltf::animation::Property::Scale,
+                    bytemuck::cast_slice(&scales).to_vec(),
+                );
+            }
+
+            IndexedAnimation {
+                name: (!anim_stack.element.name.is_empty())
+                    .then(|| anim_stack.element.name.to_string()),
+                length_seconds: length_seconds as f32,
+                channels,
+                events: vec![],
+            }
+        })
+        .collect()
+}
@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct GameFilePath {
@@ -16,11 +16,39 @@ pub struct GamePathMaker {
 
 pub struct FileManager;
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AssetKind {
+    /// gltf, glb or fbx
+    Model,
+    Texture,
+    Audio,
+}
+
+impl AssetKind {
+    /// None for files that can't be loaded on their own, e.g. the .bin files next to a .gltf
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "gltf" | "glb" | "fbx" => Some(AssetKind::Model),
+            "png" | "jpg" | "jpeg" | "hdr" => Some(AssetKind::Texture),
+            "mp3" | "wav" => Some(AssetKind::Audio),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AssetEntry {
+    pub path: GameFilePath,
+    pub kind: AssetKind,
+    pub size_bytes: u64,
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 mod native {
     use std::path::PathBuf;
 
-    use super::{FileManager, GameFilePath, GamePathMaker};
+    use super::{AssetEntry, AssetKind, FileManager, GameFilePath, GamePathMaker};
 
     pub mod native_fs {
         pub use std::fs::*;
@@ -61,6 +89,41 @@ mod native {
             std::fs::read_to_string(&path)
                 .map_err(|err| anyhow::anyhow!("{err} ({})", path.display()))
         }
+
+        /// Recursively finds the loadable assets in a directory, sorted by path
+        pub fn list_assets(directory: &GameFilePath) -> anyhow::Result<Vec<AssetEntry>> {
+            let mut assets = vec![];
+            let mut pending_relative_dirs = vec![directory.relative_path.clone()];
+
+            while let Some(relative_dir) = pending_relative_dirs.pop() {
+                let dir = directory.root.join(&relative_dir);
+                let dir_entries = std::fs::read_dir(&dir)
+                    .map_err(|err| anyhow::anyhow!("{err} ({})", dir.display()))?;
+                for dir_entry in dir_entries {
+                    let dir_entry = dir_entry?;
+                    let relative_path = relative_dir.join(dir_entry.file_name());
+                    let metadata = dir_entry.metadata()?;
+                    if metadata.is_dir() {
+                        pending_relative_dirs.push(relative_path);
+                        continue;
+                    }
+                    if let Some(kind) = AssetKind::from_path(&relative_path) {
+                        assets.push(AssetEntry {
+                            path: GameFilePath {
+                                root: directory.root.clone(),
+                                relative_path,
+                            },
+                            kind,
+                            size_bytes: metadata.len(),
+                        });
+                    }
+                }
+            }
+
+            assets.sort_by(|a, b| a.path.relative_path.cmp(&b.path.relative_path));
+
+            Ok(assets)
+        }
     }
 }
 
@@ -70,7 +133,7 @@ mod web {
     use wasm_bindgen::prelude::*;
     use wasm_bindgen_futures::JsFuture;
 
-    use super::{FileManager, GameFilePath, GamePathMaker};
+    use super::{AssetEntry, FileManager, GameFilePath, GamePathMaker};
 
     impl GameFilePath {
         pub fn resolve(&self) -> String {
@@ -168,6 +231,14 @@ mod web {
             let bytes = Self::read(path).await?;
             Ok(std::str::from_utf8(&bytes)?.to_string())
         }
+
+        /// the asset server is plain http, which doesn't give directory listings
+        pub fn list_assets(directory: &GameFilePath) -> anyhow::Result<Vec<AssetEntry>> {
+            anyhow::bail!(
+                "Can't list the assets in {} on the web",
+                directory.relative_path.display()
+            )
+        }
     }
 }
 
@@ -0,0 +1,121 @@
+use crate::time::*;
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Polls the modification times of files so that they can be hot reloaded. The files are
+/// grouped under a key chosen by the caller, e.g. an asset id, and poll returns the keys
+/// that have a changed file. Nothing ever changes on the web since there's no file system
+#[derive(Debug)]
+pub struct FileWatcher<K> {
+    /// the files of each key with the modification time they had when last polled,
+    /// None if they didn't exist
+    watched: HashMap<K, Vec<(PathBuf, Option<SystemTime>)>>,
+    poll_interval: Duration,
+    last_poll: Instant,
+}
+
+impl<K: Clone + Eq + Hash> FileWatcher<K> {
+    pub fn new(poll_interval: Duration) -> Self {
+        Self {
+            watched: HashMap::new(),
+            poll_interval,
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// Replaces the files watched under the key. Their current modification times are what
+    /// the next polls compare against
+    pub fn watch(&mut self, key: K, paths: impl IntoIterator<Item = PathBuf>) {
+        let files = paths
+            .into_iter()
+            .map(|path| {
+                let modified = get_modified_time(&path);
+                (path, modified)
+            })
+            .collect();
+        self.watched.insert(key, files);
+    }
+
+    pub fn unwatch(&mut self, key: &K) {
+        self.watched.remove(key);
+    }
+
+    pub fn is_watching(&self, key: &K) -> bool {
+        self.watched.contains_key(key)
+    }
+
+    /// The keys with a file that changed, appeared or disappeared since the last poll. Nothing
+    /// is checked until the poll interval has passed. The new times are stored right away so
+    /// a file with errors in it isn't reported over and over until it's fixed
+    pub fn poll(&mut self) -> Vec<K> {
+        if self.last_poll.elapsed() < self.poll_interval {
+            return vec![];
+        }
+        self.last_poll = Instant::now();
+
+        let mut changed_keys = vec![];
+        for (key, files) in &mut self.watched {
+            let mut is_changed = false;
+            for (path, last_modified) in files {
+                let modified = get_modified_time(path);
+                if modified != *last_modified {
+                    *last_modified = modified;
+                    is_changed = true;
+                }
+            }
+            if is_changed {
+                changed_keys.push(key.clone());
+            }
+        }
+        changed_keys
+    }
+}
+
+fn get_modified_time(path: &Path) -> Option<SystemTime> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = path;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn files_are_reported_once_per_change() {
+        let directory =
+            std::env::temp_dir().join(format!("ikari_file_watcher_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("watched.txt");
+
+        let mut file_watcher = FileWatcher::new(Duration::ZERO);
+        file_watcher.watch("a", [path.clone()]);
+        assert!(file_watcher.poll().is_empty());
+
+        std::fs::write(&path, "hello").unwrap();
+        assert_eq!(file_watcher.poll(), vec!["a"]);
+        assert!(file_watcher.poll().is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(file_watcher.poll(), vec!["a"]);
+
+        file_watcher.unwatch(&"a");
+        std::fs::write(&path, "hello").unwrap();
+        assert!(file_watcher.poll().is_empty());
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
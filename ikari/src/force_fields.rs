@@ -0,0 +1,366 @@
+use crate::ecs::{Entity, SceneNode};
+use crate::engine_state::EngineState;
+use crate::physics::rapier3d_f64::prelude::*;
+use crate::physics::PhysicsState;
+use crate::scene::GameNodeId;
+use crate::wind::Wind;
+
+use glam::f32::{Quat, Vec3};
+
+/// How the strength of a field goes down with the distance to its center
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ForceFalloff {
+    None,
+    /// down to 0 at the edge
+    Linear,
+    Quadratic,
+}
+
+impl ForceFalloff {
+    pub fn factor(&self, distance: f32, radius: f32) -> f32 {
+        let remaining = (1.0 - distance / radius.max(f32::EPSILON)).clamp(0.0, 1.0);
+        match self {
+            Self::None => 1.0,
+            Self::Linear => remaining,
+            Self::Quadratic => remaining * remaining,
+        }
+    }
+}
+
+/// The forces are in newtons, the directions in the local space of the field's node
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ForceFieldKind {
+    /// pushes away from the center, or pulls towards it if negative, e.g. attractors
+    Radial { strength: f32 },
+    /// always the same push, e.g. a conveyor or an updraft
+    Directional { force: Vec3 },
+    /// drags the bodies along with EngineState::wind, the same wind the cloth sways in.
+    /// drag is in newtons per unit of air speed relative to the body
+    Wind { drag: f32 },
+    /// swirls around the axis going through the center, e.g. whirlwinds or whirlpools.
+    /// A positive inward_strength pulls the bodies towards the axis
+    Vortex {
+        axis: Vec3,
+        strength: f32,
+        inward_strength: f32,
+    },
+}
+
+/// Component of the entities that push the dynamic rigid bodies within a sphere around their
+/// SceneNode on every fixed update, see update_force_fields. The node's rotation turns the
+/// directions of the field, its scale is ignored
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ForceField {
+    pub kind: ForceFieldKind,
+    pub radius: f32,
+    pub falloff: ForceFalloff,
+    /// sends a ForceFieldEvent for every body pushed by the field on every fixed update
+    pub send_events: bool,
+}
+
+impl ForceField {
+    pub fn new(kind: ForceFieldKind, radius: f32) -> Self {
+        Self {
+            kind,
+            radius,
+            falloff: ForceFalloff::None,
+            send_events: false,
+        }
+    }
+
+    /// None if the point is outside of the field. relative_wind is the velocity of the wind
+    /// relative to the body at the point, only Wind fields use it
+    pub fn force_at(
+        &self,
+        center: Vec3,
+        rotation: Quat,
+        point: Vec3,
+        relative_wind: Vec3,
+    ) -> Option<Vec3> {
+        let offset = point - center;
+        let distance = offset.length();
+        if distance > self.radius {
+            return None;
+        }
+        let factor = self.falloff.factor(distance, self.radius);
+        let force = match self.kind {
+            ForceFieldKind::Radial { strength } => offset.normalize_or_zero() * strength,
+            ForceFieldKind::Directional { force } => rotation * force,
+            ForceFieldKind::Wind { drag } => relative_wind * drag,
+            ForceFieldKind::Vortex {
+                axis,
+                strength,
+                inward_strength,
+            } => {
+                let axis = (rotation * axis).normalize_or_zero();
+                let to_axis = -(offset - offset.dot(axis) * axis);
+                let tangent = axis.cross(-to_axis).normalize_or_zero();
+                tangent * strength + to_axis.normalize_or_zero() * inward_strength
+            }
+        };
+        Some(force * factor)
+    }
+}
+
+/// A one-off radial impulse, see explode
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Explosion {
+    pub center: Vec3,
+    pub radius: f32,
+    /// in newton seconds, given to the bodies at the center
+    pub impulse: f32,
+    pub falloff: ForceFalloff,
+}
+
+/// A body was pushed by a force field or an explosion
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ForceFieldEvent {
+    /// None for explosions
+    pub field: Option<Entity>,
+    pub rigid_body_handle: RigidBodyHandle,
+    /// the node of the body's first collider, see PhysicsState::get_collider_node
+    pub node_id: Option<GameNodeId>,
+    /// in newton seconds, what the body got during the fixed update
+    pub impulse: Vec3,
+}
+
+/// System that applies the ForceFields to the rigid bodies, meant for SystemStage::FixedUpdate
+#[profiling::function]
+pub fn update_force_fields(engine_state: &mut EngineState) {
+    let delta_time_seconds = engine_state.simulation_timestep.timestep_seconds as f32;
+    let time_seconds = (engine_state.simulation_timestep.tick() as f64
+        * engine_state.simulation_timestep.timestep_seconds) as f32;
+
+    let fields: Vec<_> = engine_state
+        .world
+        .query::<ForceField>()
+        .filter_map(|(entity, field)| {
+            let SceneNode(node_id) = engine_state.world.get::<SceneNode>(entity)?;
+            engine_state.scene.get_node(*node_id)?;
+            let transform = engine_state.scene.get_global_transform_for_node(*node_id);
+            Some((entity, *field, transform.position(), transform.rotation()))
+        })
+        .collect();
+    if fields.is_empty() {
+        return;
+    }
+
+    let physics_state = &mut engine_state.physics_state;
+    let mut events = vec![];
+    for (rigid_body_handle, rigid_body) in physics_state.rigid_body_set.iter_mut() {
+        if !rigid_body.is_dynamic() {
+            continue;
+        }
+        let position = point_to_vec3(rigid_body.center_of_mass());
+        for (entity, field, center, rotation) in &fields {
+            let relative_wind = match field.kind {
+                ForceFieldKind::Wind { .. } => {
+                    relative_wind(&engine_state.wind, rigid_body, position, time_seconds)
+                }
+                _ => Vec3::ZERO,
+            };
+            let Some(force) = field.force_at(*center, *rotation, position, relative_wind) else {
+                continue;
+            };
+            let impulse = force * delta_time_seconds;
+            if impulse == Vec3::ZERO {
+                continue;
+            }
+            rigid_body.apply_impulse(vec3_to_vector(impulse), true);
+            if field.send_events {
+                events.push((*entity, rigid_body_handle, impulse));
+            }
+        }
+    }
+
+    for (field, rigid_body_handle, impulse) in events {
+        let node_id = body_node_id(&engine_state.physics_state, rigid_body_handle);
+        engine_state.events.send(ForceFieldEvent {
+            field: Some(field),
+            rigid_body_handle,
+            node_id,
+            impulse,
+        });
+    }
+}
+
+/// Pushes the dynamic bodies around the center away from it and sends a ForceFieldEvent for
+/// each of them
+pub fn explode(engine_state: &mut EngineState, explosion: &Explosion) {
+    let field = ForceField {
+        falloff: explosion.falloff,
+        ..ForceField::new(
+            ForceFieldKind::Radial {
+                strength: explosion.impulse,
+            },
+            explosion.radius,
+        )
+    };
+
+    let mut pushed = vec![];
+    for (rigid_body_handle, rigid_body) in engine_state.physics_state.rigid_body_set.iter_mut() {
+        if !rigid_body.is_dynamic() {
+            continue;
+        }
+        let position = point_to_vec3(rigid_body.center_of_mass());
+        let Some(impulse) = field.force_at(explosion.center, Quat::IDENTITY, position, Vec3::ZERO)
+        else {
+            continue;
+        };
+        if impulse == Vec3::ZERO {
+            continue;
+        }
+        rigid_body.apply_impulse(vec3_to_vector(impulse), true);
+        pushed.push((rigid_body_handle, impulse));
+    }
+
+    for (rigid_body_handle, impulse) in pushed {
+        let node_id = body_node_id(&engine_state.physics_state, rigid_body_handle);
+        engine_state.events.send(ForceFieldEvent {
+            field: None,
+            rigid_body_handle,
+            node_id,
+            impulse,
+        });
+    }
+}
+
+fn relative_wind(wind: &Wind, rigid_body: &RigidBody, position: Vec3, time_seconds: f32) -> Vec3 {
+    let velocity = rigid_body.linvel();
+    wind.velocity_at(position, time_seconds)
+        - Vec3::new(velocity.x as f32, velocity.y as f32, velocity.z as f32)
+}
+
+fn body_node_id(
+    physics_state: &PhysicsState,
+    rigid_body_handle: RigidBodyHandle,
+) -> Option<GameNodeId> {
+    let collider_handle = *physics_state
+        .rigid_body_set
+        .get(rigid_body_handle)?
+        .colliders()
+        .first()?;
+    physics_state.get_collider_node(collider_handle)
+}
+
+fn point_to_vec3(point: &Point<Real>) -> Vec3 {
+    Vec3::new(point.x as f32, point.y as f32, point.z as f32)
+}
+
+fn vec3_to_vector(vector: Vec3) -> Vector<Real> {
+    vector![vector.x as f64, vector.y as f64, vector.z as f64]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_push_inside_their_radius_with_falloff() {
+        let explosion = ForceField {
+            falloff: ForceFalloff::Linear,
+            ..ForceField::new(ForceFieldKind::Radial { strength: 10.0 }, 4.0)
+        };
+        let force = explosion
+            .force_at(
+                Vec3::ZERO,
+                Quat::IDENTITY,
+                Vec3::new(0.0, 2.0, 0.0),
+                Vec3::ZERO,
+            )
+            .unwrap();
+        assert!((force - Vec3::new(0.0, 5.0, 0.0)).length() < 1e-5);
+        assert_eq!(
+            explosion.force_at(
+                Vec3::ZERO,
+                Quat::IDENTITY,
+                Vec3::new(5.0, 0.0, 0.0),
+                Vec3::ZERO
+            ),
+            None
+        );
+
+        // turned by the node's rotation
+        let updraft = ForceField::new(
+            ForceFieldKind::Directional {
+                force: Vec3::new(1.0, 0.0, 0.0),
+            },
+            10.0,
+        );
+        let force = updraft
+            .force_at(
+                Vec3::ZERO,
+                Quat::from_rotation_y(std::f32::consts::FRAC_PI_2),
+                Vec3::ONE,
+                Vec3::ZERO,
+            )
+            .unwrap();
+        assert!((force - Vec3::new(0.0, 0.0, -1.0)).length() < 1e-5);
+
+        // the wind isn't turned
+        let wind = ForceField::new(ForceFieldKind::Wind { drag: 2.0 }, 10.0);
+        let force = wind
+            .force_at(
+                Vec3::ZERO,
+                Quat::from_rotation_y(std::f32::consts::FRAC_PI_2),
+                Vec3::ONE,
+                Vec3::new(3.0, 0.0, 0.0),
+            )
+            .unwrap();
+        assert!((force - Vec3::new(6.0, 0.0, 0.0)).length() < 1e-5);
+
+        let vortex = ForceField::new(
+            ForceFieldKind::Vortex {
+                axis: Vec3::Y,
+                strength: 1.0,
+                inward_strength: 0.5,
+            },
+            10.0,
+        );
+        let force = vortex
+            .force_at(
+                Vec3::ZERO,
+                Quat::IDENTITY,
+                Vec3::new(2.0, 3.0, 0.0),
+                Vec3::ZERO,
+            )
+            .unwrap();
+        assert!((force - Vec3::new(-0.5, 0.0, -1.0)).length() < 1e-5);
+    }
+
+    #[test]
+    fn explosions_send_an_event_for_each_pushed_body() {
+        let mut engine_state = EngineState::new_headless();
+        let physics_state = &mut engine_state.physics_state;
+        let mut add_ball = |x: f64| {
+            let rigid_body_handle = physics_state.rigid_body_set.insert(
+                RigidBodyBuilder::dynamic()
+                    .translation(vector![x, 0.0, 0.0])
+                    .build(),
+            );
+            physics_state.collider_set.insert_with_parent(
+                ColliderBuilder::ball(0.5).build(),
+                rigid_body_handle,
+                &mut physics_state.rigid_body_set,
+            );
+            rigid_body_handle
+        };
+        let near_body = add_ball(1.0);
+        add_ball(10.0);
+
+        explode(
+            &mut engine_state,
+            &Explosion {
+                center: Vec3::ZERO,
+                radius: 5.0,
+                impulse: 4.0,
+                falloff: ForceFalloff::None,
+            },
+        );
+        let events = engine_state.events.drain::<ForceFieldEvent>();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].rigid_body_handle, near_body);
+        assert_eq!(events[0].field, None);
+        assert!((events[0].impulse - Vec3::new(4.0, 0.0, 0.0)).length() < 1e-5);
+    }
+}
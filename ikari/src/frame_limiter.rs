@@ -0,0 +1,89 @@
+use crate::time::*;
+
+/// The OS usually wakes the thread up a bit late, so the end of the wait is spent spinning
+#[cfg(not(target_arch = "wasm32"))]
+const SPIN_DURATION: Duration = Duration::from_micros(1500);
+
+/// Caps the frame rate on the CPU side, e.g. to save battery on laptops when vsync is off.
+/// gameloop waits on it at the end of every frame. Does nothing on the web where the browser
+/// already paces the frames
+#[derive(Debug, Default)]
+pub struct FrameLimiter {
+    max_fps: Option<f32>,
+    next_frame_time: Option<Instant>,
+}
+
+impl FrameLimiter {
+    pub fn max_fps(&self) -> Option<f32> {
+        self.max_fps
+    }
+
+    /// None or a non-positive value removes the limit
+    pub fn set_max_fps(&mut self, max_fps: Option<f32>) {
+        self.max_fps = max_fps.filter(|max_fps| *max_fps > 0.0);
+        self.next_frame_time = None;
+    }
+
+    pub(crate) fn wait(&mut self) {
+        let Some(max_fps) = self.max_fps else {
+            return;
+        };
+        let now = Instant::now();
+        let frame_duration = Duration::from_secs_f32(1.0 / max_fps);
+        let target = next_frame_time(self.next_frame_time, now, frame_duration);
+        self.next_frame_time = Some(target + frame_duration);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            profiling::scope!("Frame limiter");
+            if let Some(sleep_duration) = target
+                .checked_duration_since(now)
+                .and_then(|remaining| remaining.checked_sub(SPIN_DURATION))
+            {
+                crate::thread::sleep(sleep_duration);
+            }
+            while Instant::now() < target {
+                std::hint::spin_loop();
+            }
+        }
+    }
+}
+
+/// Frames are paced from the previous deadline rather than from now so that the small
+/// oversleeps don't accumulate, unless we fell more than a frame behind
+fn next_frame_time(
+    previous_target: Option<Instant>,
+    now: Instant,
+    frame_duration: Duration,
+) -> Instant {
+    match previous_target {
+        Some(previous_target) if previous_target + frame_duration >= now => previous_target,
+        _ => now,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_are_paced_from_the_previous_deadline() {
+        let frame_duration = Duration::from_millis(10);
+        let start = Instant::now();
+
+        assert_eq!(next_frame_time(None, start, frame_duration), start);
+        let target = start + frame_duration;
+        // a bit late, the next deadline doesn't move
+        assert_eq!(
+            next_frame_time(
+                Some(target),
+                target + Duration::from_millis(2),
+                frame_duration
+            ),
+            target
+        );
+        // way behind, start over from now
+        let late = target + Duration::from_millis(50);
+        assert_eq!(next_frame_time(Some(target), late, frame_duration), late);
+    }
+}
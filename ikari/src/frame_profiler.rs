@@ -0,0 +1,394 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::time::{Duration, Instant};
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+
+pub const DEFAULT_PROFILER_HISTORY_SIZE: usize = 300;
+
+#[derive(Debug, Clone)]
+struct RawCpuScope {
+    name: &'static str,
+    thread: Arc<str>,
+    depth: u32,
+    start: Instant,
+    end: Instant,
+}
+
+lazy_static! {
+    static ref FINISHED_CPU_SCOPES: Mutex<Vec<RawCpuScope>> = Mutex::new(vec![]);
+}
+
+/// A profiling::scope or profiling::function that ended during the frame
+#[derive(Debug, Clone)]
+pub struct CpuScope {
+    pub name: &'static str,
+    pub thread: Arc<str>,
+    /// 0 for the outermost scopes of the thread
+    pub depth: u32,
+    /// since the start of the frame
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+/// A pass timed by the renderer's gpu profiler
+#[derive(Debug, Clone)]
+pub struct GpuScope {
+    pub label: String,
+    pub depth: u32,
+    /// since the start of the first pass of the frame
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProfiledFrame {
+    pub index: u64,
+    pub duration: Duration,
+    /// sorted by thread then by start time, so the children follow their parent
+    pub cpu_scopes: Vec<CpuScope>,
+    /// the gpu timings come in a few frames late, so these are from the last frame the gpu
+    /// finished when this one ended. Empty if there weren't any new ones
+    pub gpu_scopes: Vec<GpuScope>,
+}
+
+impl ProfiledFrame {
+    /// Sum of the scopes with this name, e.g. when a function is called many times in a frame
+    pub fn cpu_scope_total(&self, name: &str) -> Duration {
+        self.cpu_scopes
+            .iter()
+            .filter(|scope| scope.name == name)
+            .map(|scope| scope.duration)
+            .sum()
+    }
+
+    pub fn gpu_total(&self) -> Duration {
+        self.gpu_scopes
+            .iter()
+            .filter(|scope| scope.depth == 0)
+            .map(|scope| scope.duration)
+            .sum()
+    }
+}
+
+/// Keeps the CPU scopes and GPU pass timings of the last frames for in-app display.
+/// Lives in EngineState::frame_profiler and is advanced by the gameloop at the start of each frame.
+///
+/// The CPU scopes are the ones from the profiling crate, they're only recorded when the
+/// frame-profiler feature is enabled and install_cpu_scope_collector was called. The GPU timings
+/// need to be passed in with add_gpu_frame, see Renderer::process_profiler_frame
+#[derive(Debug)]
+pub struct FrameProfiler {
+    pub max_history: usize,
+    /// keeps the history as is, e.g. to look at a spike
+    pub is_paused: bool,
+    frames: VecDeque<ProfiledFrame>,
+    frame_index: u64,
+    frame_start: Option<Instant>,
+    pending_gpu_scopes: Vec<GpuScope>,
+}
+
+impl Default for FrameProfiler {
+    fn default() -> Self {
+        Self::new(DEFAULT_PROFILER_HISTORY_SIZE)
+    }
+}
+
+impl FrameProfiler {
+    pub fn new(max_history: usize) -> Self {
+        Self {
+            max_history,
+            is_paused: false,
+            frames: VecDeque::new(),
+            frame_index: 0,
+            frame_start: None,
+            pending_gpu_scopes: vec![],
+        }
+    }
+
+    /// Oldest first
+    pub fn frames(&self) -> impl DoubleEndedIterator<Item = &ProfiledFrame> + '_ {
+        self.frames.iter()
+    }
+
+    pub fn latest_frame(&self) -> Option<&ProfiledFrame> {
+        self.frames.back()
+    }
+
+    /// Total time spent in the scope over the history, oldest first, for graphs
+    pub fn cpu_scope_history<'a>(&'a self, name: &'a str) -> impl Iterator<Item = Duration> + 'a {
+        self.frames
+            .iter()
+            .map(move |frame| frame.cpu_scope_total(name))
+    }
+
+    /// Average time per frame of each CPU scope over the history
+    pub fn average_cpu_scope_times(&self) -> HashMap<&'static str, Duration> {
+        let mut totals: HashMap<&'static str, Duration> = HashMap::new();
+        for scope in self.frames.iter().flat_map(|frame| &frame.cpu_scopes) {
+            *totals.entry(scope.name).or_default() += scope.duration;
+        }
+        let frame_count = self.frames.len().max(1) as u32;
+        totals
+            .into_iter()
+            .map(|(name, total)| (name, total / frame_count))
+            .collect()
+    }
+
+    pub fn add_gpu_frame(&mut self, results: &[wgpu_profiler::GpuTimerQueryResult]) {
+        fn flatten(
+            results: &[wgpu_profiler::GpuTimerQueryResult],
+            depth: u32,
+            frame_start: f64,
+            scopes: &mut Vec<GpuScope>,
+        ) {
+            for result in results {
+                scopes.push(GpuScope {
+                    label: result.label.clone(),
+                    depth,
+                    start: Duration::from_secs_f64((result.time.start - frame_start).max(0.0)),
+                    duration: Duration::from_secs_f64(
+                        (result.time.end - result.time.start).max(0.0),
+                    ),
+                });
+                flatten(&result.nested_queries, depth + 1, frame_start, scopes);
+            }
+        }
+
+        let frame_start = results
+            .iter()
+            .map(|result| result.time.start)
+            .reduce(f64::min)
+            .unwrap_or_default();
+        self.pending_gpu_scopes.clear();
+        flatten(results, 0, frame_start, &mut self.pending_gpu_scopes);
+    }
+
+    pub(crate) fn on_frame_started(&mut self) {
+        let now = Instant::now();
+        let finished_scopes = std::mem::take(&mut *FINISHED_CPU_SCOPES.lock().unwrap());
+        let Some(frame_start) = self.frame_start.replace(now) else {
+            return;
+        };
+        if self.is_paused {
+            return;
+        }
+
+        let mut cpu_scopes: Vec<_> = finished_scopes
+            .into_iter()
+            // scopes that started in the previous frame are cut off
+            .map(|scope| CpuScope {
+                name: scope.name,
+                thread: scope.thread,
+                depth: scope.depth,
+                start: scope.start.max(frame_start) - frame_start,
+                duration: scope.end - scope.start.max(frame_start).min(scope.end),
+            })
+            .collect();
+        cpu_scopes.sort_by(|a, b| {
+            a.thread
+                .cmp(&b.thread)
+                .then(a.start.cmp(&b.start))
+                .then(a.depth.cmp(&b.depth))
+        });
+
+        self.frames.push_back(ProfiledFrame {
+            index: self.frame_index,
+            duration: now - frame_start,
+            cpu_scopes,
+            gpu_scopes: std::mem::take(&mut self.pending_gpu_scopes),
+        });
+        self.frame_index += 1;
+        while self.frames.len() > self.max_history {
+            self.frames.pop_front();
+        }
+    }
+
+    /// The history in the chrome trace event format, which can be opened in chrome://tracing or
+    /// https://ui.perfetto.dev. The GPU passes are on their own track
+    pub fn to_chrome_trace(&self) -> serde_json::Value {
+        let mut events = vec![];
+        let mut thread_ids: HashMap<Arc<str>, usize> = HashMap::new();
+        let mut frame_start = Duration::ZERO;
+        for frame in &self.frames {
+            let micros = |duration: Duration| (frame_start + duration).as_secs_f64() * 1_000_000.0;
+            for scope in &frame.cpu_scopes {
+                let thread_count = thread_ids.len();
+                let thread_id = *thread_ids
+                    .entry(scope.thread.clone())
+                    .or_insert(thread_count);
+                events.push(serde_json::json!({
+                    "name": scope.name,
+                    "ph": "X",
+                    "ts": micros(scope.start),
+                    "dur": scope.duration.as_secs_f64() * 1_000_000.0,
+                    "pid": 0,
+                    "tid": thread_id,
+                }));
+            }
+            for scope in &frame.gpu_scopes {
+                events.push(serde_json::json!({
+                    "name": scope.label,
+                    "ph": "X",
+                    "ts": micros(scope.start),
+                    "dur": scope.duration.as_secs_f64() * 1_000_000.0,
+                    "pid": 1,
+                    "tid": 0,
+                }));
+            }
+            frame_start += frame.duration;
+        }
+        for (thread, thread_id) in thread_ids {
+            events.push(serde_json::json!({
+                "name": "thread_name",
+                "ph": "M",
+                "pid": 0,
+                "tid": thread_id,
+                "args": { "name": thread.as_ref() },
+            }));
+        }
+        events.push(serde_json::json!({
+            "name": "process_name",
+            "ph": "M",
+            "pid": 1,
+            "args": { "name": "GPU" },
+        }));
+        serde_json::json!({ "traceEvents": events })
+    }
+
+    pub fn save_capture(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string(&self.to_chrome_trace())?)?;
+        Ok(())
+    }
+
+    /// Saves the capture to a new file named after the current time, returns its path
+    pub fn save_capture_in_dir(&self, dir: &Path) -> Result<PathBuf> {
+        let path = dir.join(format!(
+            "ikari_frame_profile_{}.json",
+            chrono::Local::now().format("%Y-%m-%d_%H-%M-%S")
+        ));
+        self.save_capture(&path)?;
+        Ok(path)
+    }
+}
+
+/// Whether the profiling crate's scopes end up in the FrameProfiler
+pub fn cpu_scopes_are_collected() -> bool {
+    cfg!(feature = "frame-profiler")
+}
+
+/// Sets a tracing subscriber that records the profiling crate's scopes for the FrameProfiler.
+/// Fails if another global subscriber was already set
+#[cfg(feature = "frame-profiler")]
+pub fn install_cpu_scope_collector() -> Result<()> {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    tracing::subscriber::set_global_default(
+        tracing_subscriber::registry().with(cpu_scope_collector::CpuScopeLayer),
+    )?;
+    Ok(())
+}
+
+#[cfg(feature = "frame-profiler")]
+mod cpu_scope_collector {
+    use std::cell::RefCell;
+
+    use tracing::span;
+    use tracing_subscriber::layer::Context;
+    use tracing_subscriber::registry::LookupSpan;
+
+    use super::*;
+
+    thread_local! {
+        static THREAD_NAME: Arc<str> = std::thread::current()
+            .name()
+            .unwrap_or("unnamed thread")
+            .into();
+        static OPEN_SCOPES: RefCell<Vec<(&'static str, Instant)>> = RefCell::new(vec![]);
+    }
+
+    pub(super) struct CpuScopeLayer;
+
+    impl<S> tracing_subscriber::Layer<S> for CpuScopeLayer
+    where
+        S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+            let Some(span) = ctx.span(id) else {
+                return;
+            };
+            let name = span.name();
+            OPEN_SCOPES.with(|open_scopes| open_scopes.borrow_mut().push((name, Instant::now())));
+        }
+
+        fn on_exit(&self, _id: &span::Id, _ctx: Context<'_, S>) {
+            let end = Instant::now();
+            let Some((name, start, depth)) = OPEN_SCOPES.with(|open_scopes| {
+                let mut open_scopes = open_scopes.borrow_mut();
+                let (name, start) = open_scopes.pop()?;
+                Some((name, start, open_scopes.len() as u32))
+            }) else {
+                return;
+            };
+            FINISHED_CPU_SCOPES.lock().unwrap().push(RawCpuScope {
+                name,
+                thread: THREAD_NAME.with(|thread_name| thread_name.clone()),
+                depth,
+                start,
+                end,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gpu_passes_are_flattened_depth_first() {
+        let query = |label: &str, time: std::ops::Range<f64>, nested_queries| {
+            wgpu_profiler::GpuTimerQueryResult {
+                label: label.to_string(),
+                pid: 0,
+                tid: std::thread::current().id(),
+                time,
+                nested_queries,
+            }
+        };
+        let mut profiler = FrameProfiler::new(2);
+
+        profiler.add_gpu_frame(&[
+            query(
+                "Shadows",
+                1.0..1.5,
+                vec![query("Point light shadow map", 1.1..1.2, vec![])],
+            ),
+            query("Pbr meshes", 1.5..2.0, vec![]),
+        ]);
+        for _ in 0..3 {
+            profiler.on_frame_started();
+        }
+
+        assert_eq!(profiler.frames().count(), 2);
+        let first_frame = profiler.frames().next().unwrap();
+        let labels: Vec<_> = first_frame
+            .gpu_scopes
+            .iter()
+            .map(|scope| (scope.label.as_str(), scope.depth))
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                ("Shadows", 0),
+                ("Point light shadow map", 1),
+                ("Pbr meshes", 0)
+            ]
+        );
+        assert_eq!(first_frame.gpu_total(), Duration::from_secs_f64(1.0));
+        assert!(profiler.latest_frame().unwrap().gpu_scopes.is_empty());
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum GameStateKind {
+    Playing,
+    Paused,
+    Menu,
+    Loading,
+    Cutscene,
+}
+
+/// Which set of input bindings should currently be handled, see input::InputBindings
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum InputContext {
+    Gameplay,
+    Menu,
+    /// e.g. the level editor, which isn't tied to a GameStateKind
+    Editor,
+    /// ignore player input, e.g. while loading
+    None,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GameStateSettings {
+    /// whether physics, animations and gameplay logic should be updated
+    pub run_simulation: bool,
+    /// whether the cursor should be visible and free to leave the window
+    pub release_cursor: bool,
+    pub input_context: InputContext,
+}
+
+impl GameStateKind {
+    pub fn default_settings(&self) -> GameStateSettings {
+        match self {
+            GameStateKind::Playing => GameStateSettings {
+                run_simulation: true,
+                release_cursor: false,
+                input_context: InputContext::Gameplay,
+            },
+            GameStateKind::Paused | GameStateKind::Menu => GameStateSettings {
+                run_simulation: false,
+                release_cursor: true,
+                input_context: InputContext::Menu,
+            },
+            GameStateKind::Loading => GameStateSettings {
+                run_simulation: false,
+                release_cursor: true,
+                input_context: InputContext::None,
+            },
+            GameStateKind::Cutscene => GameStateSettings {
+                run_simulation: true,
+                release_cursor: false,
+                input_context: InputContext::None,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GameStateTransition {
+    pub from: GameStateKind,
+    pub to: GameStateKind,
+}
+
+/// Stack of game states, the top one being active. For example, pausing during a
+/// cutscene pushes Paused on top of Cutscene and unpausing pops back to it.
+/// The stack is never empty.
+#[derive(Debug)]
+pub struct GameStateStack {
+    stack: Vec<GameStateKind>,
+    settings_overrides: HashMap<GameStateKind, GameStateSettings>,
+    pending_transitions: Vec<GameStateTransition>,
+}
+
+impl Default for GameStateStack {
+    fn default() -> Self {
+        Self::new(GameStateKind::Playing)
+    }
+}
+
+impl GameStateStack {
+    pub fn new(initial_state: GameStateKind) -> Self {
+        Self {
+            stack: vec![initial_state],
+            settings_overrides: HashMap::new(),
+            pending_transitions: vec![],
+        }
+    }
+
+    pub fn current(&self) -> GameStateKind {
+        *self.stack.last().unwrap()
+    }
+
+    pub fn contains(&self, state: GameStateKind) -> bool {
+        self.stack.contains(&state)
+    }
+
+    pub fn states(&self) -> &[GameStateKind] {
+        &self.stack
+    }
+
+    pub fn push(&mut self, state: GameStateKind) {
+        let from = self.current();
+        self.stack.push(state);
+        self.on_transition(from);
+    }
+
+    /// does nothing if only one state is left
+    pub fn pop(&mut self) -> Option<GameStateKind> {
+        if self.stack.len() == 1 {
+            return None;
+        }
+        let popped = self.stack.pop();
+        if let Some(popped) = popped {
+            self.on_transition(popped);
+        }
+        popped
+    }
+
+    /// replaces the top of the stack
+    pub fn replace(&mut self, state: GameStateKind) {
+        let from = self.current();
+        *self.stack.last_mut().unwrap() = state;
+        self.on_transition(from);
+    }
+
+    /// clears the whole stack, e.g. when going back to the main menu
+    pub fn reset(&mut self, state: GameStateKind) {
+        let from = self.current();
+        self.stack.clear();
+        self.stack.push(state);
+        self.on_transition(from);
+    }
+
+    /// pushes Paused, or pops it if it's already the current state
+    pub fn toggle_pause(&mut self) {
+        if self.current() == GameStateKind::Paused {
+            self.pop();
+        } else {
+            self.push(GameStateKind::Paused);
+        }
+    }
+
+    fn on_transition(&mut self, from: GameStateKind) {
+        let to = self.current();
+        if from != to {
+            self.pending_transitions
+                .push(GameStateTransition { from, to });
+        }
+    }
+
+    /// transitions that happened since the last call, oldest first
+    pub fn take_transitions(&mut self) -> Vec<GameStateTransition> {
+        std::mem::take(&mut self.pending_transitions)
+    }
+
+    pub fn set_settings(&mut self, state: GameStateKind, settings: GameStateSettings) {
+        self.settings_overrides.insert(state, settings);
+    }
+
+    pub fn settings(&self, state: GameStateKind) -> GameStateSettings {
+        self.settings_overrides
+            .get(&state)
+            .copied()
+            .unwrap_or_else(|| state.default_settings())
+    }
+
+    pub fn current_settings(&self) -> GameStateSettings {
+        self.settings(self.current())
+    }
+
+    pub fn should_run_simulation(&self) -> bool {
+        self.current_settings().run_simulation
+    }
+
+    /// games apply it with PlayerController::update_cursor_grab
+    pub fn is_cursor_released(&self) -> bool {
+        self.current_settings().release_cursor
+    }
+
+    pub fn input_context(&self) -> InputContext {
+        self.current_settings().input_context
+    }
+}
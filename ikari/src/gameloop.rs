@@ -1,8 +1,11 @@
 use std::sync::Arc;
 
+use crate::ecs::{Schedule, SystemStage};
 use crate::engine_state::EngineState;
+use crate::large_world::OriginShifted;
 use crate::renderer::*;
 use crate::time::*;
+use crate::tween::update_tweens;
 use crate::ui::IkariUiContainer;
 
 #[cfg(target_arch = "wasm32")]
@@ -19,6 +22,10 @@ where
     UiOverlay: iced_winit::runtime::Program<Renderer = iced::Renderer> + 'static,
 {
     fn get_ui_container(&mut self) -> &mut IkariUiContainer<UiOverlay>;
+
+    /// Called after the renderer recreated the gpu device, e.g. to set the skyboxes and bind
+    /// the materials that were made straight from the game's textures again
+    fn on_device_recreated(&mut self, _renderer: &mut Renderer, _engine_state: &mut EngineState) {}
 }
 
 pub struct GameContext<'a, GameState> {
@@ -30,8 +37,14 @@ pub struct GameContext<'a, GameState> {
     pub elwt: &'a winit::event_loop::EventLoopWindowTarget<()>,
 }
 
+/// on_fixed_update is called at the rate of EngineState::simulation_timestep, zero or more times
+/// per frame, and is where the game and physics simulation should be stepped.
+/// on_update is then called once per frame, where the rendered state can be interpolated
+/// with simulation_timestep.interpolation_alpha(). The systems in EngineState::systems run
+/// right after each on_fixed_update and right before each on_update, depending on their stage
 #[allow(clippy::too_many_arguments)]
 pub fn run<
+    OnFixedUpdateFunction,
     OnUpdateFunction,
     OnWindowEventFunction,
     OnDeviceEventFunction,
@@ -45,12 +58,14 @@ pub fn run<
     mut engine_state: EngineState,
     mut renderer: Renderer,
     mut surface_data: SurfaceData,
+    mut on_fixed_update: OnFixedUpdateFunction,
     mut on_update: OnUpdateFunction,
     mut on_window_event: OnWindowEventFunction,
     mut on_device_event: OnDeviceEventFunction,
     mut on_window_resize: OnWindowResizeFunction,
     application_start_time: Instant,
 ) where
+    OnFixedUpdateFunction: FnMut(GameContext<GameStateType>) + 'static,
     OnUpdateFunction: FnMut(GameContext<GameStateType>) + 'static,
     OnWindowEventFunction: FnMut(GameContext<GameStateType>, &winit::event::WindowEvent) + 'static,
     OnDeviceEventFunction: FnMut(GameContext<GameStateType>, &winit::event::DeviceEvent) + 'static,
@@ -94,9 +109,75 @@ pub fn run<
                         logged_start_time = true;
                     }
 
+                    if renderer.base.is_device_lost() {
+                        if !recreate_lost_device(
+                            &mut renderer,
+                            &mut surface_data,
+                            game_state.get_ui_container(),
+                        ) {
+                            elwt.exit();
+                            return;
+                        }
+
+                        let size = window.inner_size();
+                        renderer.resize_surface(&mut surface_data, size);
+                        game_state.on_device_recreated(&mut renderer, &mut engine_state);
+                        on_window_resize(
+                            GameContext {
+                                game_state: &mut game_state,
+                                engine_state: &mut engine_state,
+                                renderer: &mut renderer,
+                                surface_data: &mut surface_data,
+                                window: &mut window,
+                                elwt,
+                            },
+                            size,
+                        );
+                    }
+
                     engine_state.on_frame_started();
                     profiling::finish_frame!();
 
+                    // the simulation stays frozen in place while paused, at the same interpolation alpha,
+                    // unless a frame is being stepped
+                    if engine_state.game_state_stack.should_run_simulation()
+                        || engine_state.time_control.is_stepping()
+                    {
+                        profiling::scope!("Fixed updates");
+
+                        let step_count = engine_state
+                            .simulation_timestep
+                            .advance(engine_state.time_control.world_delta_seconds());
+                        for _ in 0..step_count {
+                            engine_state.scene.store_previous_transforms();
+                            on_fixed_update(GameContext {
+                                game_state: &mut game_state,
+                                engine_state: &mut engine_state,
+                                renderer: &mut renderer,
+                                surface_data: &mut surface_data,
+                                window: &mut window,
+                                elwt,
+                            });
+                            Schedule::run(&mut engine_state, SystemStage::FixedUpdate);
+                            engine_state.light_animations.update(
+                                &mut engine_state.scene,
+                                engine_state.simulation_timestep.timestep_seconds as f32,
+                            );
+                            engine_state.simulation_timestep.increment_tick();
+                        }
+                        let interpolation_alpha =
+                            engine_state.simulation_timestep.interpolation_alpha();
+                        engine_state
+                            .physics_state
+                            .set_interpolation_alpha(interpolation_alpha);
+                        engine_state
+                            .scene
+                            .set_interpolation_alpha(interpolation_alpha);
+                    }
+
+                    update_tweens(&mut engine_state);
+                    Schedule::run(&mut engine_state, SystemStage::Update);
+
                     on_update(GameContext {
                         game_state: &mut game_state,
                         engine_state: &mut engine_state,
@@ -105,13 +186,22 @@ pub fn run<
                         window: &mut window,
                         elwt,
                     });
+                    engine_state.bone_overrides.apply(&mut engine_state.scene);
+                    let camera_node_id = renderer.data.lock().unwrap().camera_node_id;
+                    if let Some(offset) = engine_state.large_world.update(
+                        &mut engine_state.scene,
+                        &mut engine_state.physics_state,
+                        camera_node_id,
+                    ) {
+                        engine_state.events.send(OriginShifted { offset });
+                    }
 
                     #[cfg(target_arch = "wasm32")]
                     {
-                        let new_size = winit::dpi::PhysicalSize::new(
-                            (canvas_container.offset_width() as f64 * window.scale_factor()) as u32,
-                            (canvas_container.offset_height() as f64 * window.scale_factor())
-                                as u32,
+                        let new_size = canvas_size(
+                            &canvas_container,
+                            window.scale_factor(),
+                            renderer.base.limits.max_texture_dimension_2d,
                         );
                         if window.inner_size() != new_size {
                             let _resized_immediately = window.request_inner_size(new_size);
@@ -126,7 +216,7 @@ pub fn run<
                         Ok(_) => {}
                         Err(err) => match err.downcast_ref::<wgpu::SurfaceError>() {
                             // Reconfigure the surface if lost
-                            Some(wgpu::SurfaceError::Lost) => {
+                            Some(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
                                 let size = window.inner_size();
 
                                 renderer.resize_surface(&mut surface_data, size);
@@ -142,10 +232,21 @@ pub fn run<
                                     size,
                                 );
                             }
-                            Some(wgpu::SurfaceError::OutOfMemory) => elwt.exit(),
+                            Some(wgpu::SurfaceError::OutOfMemory) => {
+                                renderer
+                                    .base
+                                    .diagnostics
+                                    .write_bundle("Surface error: out of memory");
+                                elwt.exit();
+                            }
+                            Some(wgpu::SurfaceError::Timeout) => {
+                                log::warn!("Timed out waiting for the next frame, skipping it");
+                            }
                             _ => log::error!("{err:?}"),
                         },
                     }
+
+                    engine_state.frame_limiter.wait();
                 }
                 Event::LoopExiting => {
                     #[cfg(target_arch = "wasm32")]
@@ -234,3 +335,47 @@ pub fn run<
         })
         .unwrap();
 }
+
+/// The size in physical pixels that fills the container, e.g. 2x its css size on a high dpi
+/// screen. Very large or zoomed out pages can get past the max texture size, the surface can't
+/// be larger than that
+#[cfg(target_arch = "wasm32")]
+fn canvas_size(
+    canvas_container: &web_sys::HtmlElement,
+    device_pixel_ratio: f64,
+    max_texture_dimension: u32,
+) -> winit::dpi::PhysicalSize<u32> {
+    let to_physical =
+        |css_size: i32| ((css_size as f64 * device_pixel_ratio) as u32).min(max_texture_dimension);
+    winit::dpi::PhysicalSize::new(
+        to_physical(canvas_container.offset_width()),
+        to_physical(canvas_container.offset_height()),
+    )
+}
+
+/// false if the device couldn't be recreated
+fn recreate_lost_device<UiOverlay>(
+    renderer: &mut Renderer,
+    surface_data: &mut SurfaceData,
+    ui_container: &mut IkariUiContainer<UiOverlay>,
+) -> bool
+where
+    UiOverlay: iced_winit::runtime::Program<Renderer = iced::Renderer> + 'static,
+{
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        log::warn!("The gpu device was lost, recreating it");
+        if let Err(err) = crate::block_on(renderer.recreate_device(surface_data)) {
+            log::error!("Failed to recreate the gpu device: {err:?}");
+            return false;
+        }
+        ui_container.recreate_renderer(&renderer.base.device, &renderer.base.queue);
+        true
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = (renderer, surface_data, ui_container);
+        log::error!("The gpu device was lost, recreating it isn't supported on the web");
+        false
+    }
+}
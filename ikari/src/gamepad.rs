@@ -0,0 +1,304 @@
+use crate::input::{ActionEvent, GamepadAxis, GamepadButton, InputMap};
+use crate::time::Duration;
+
+use gilrs::ff::{BaseEffect, BaseEffectType, Effect, EffectBuilder, Replay, Ticks};
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GamepadSettings {
+    /// fraction of the stick's range around its center that is ignored, applied to the
+    /// length of the x/y vector so that diagonals aren't snapped to the axes
+    pub stick_dead_zone: f32,
+    pub trigger_dead_zone: f32,
+    /// the stick values are raised to this power after the dead zone is removed, values above 1
+    /// give more precision for small movements
+    pub response_curve_exponent: f32,
+}
+
+impl Default for GamepadSettings {
+    fn default() -> Self {
+        Self {
+            stick_dead_zone: 0.15,
+            trigger_dead_zone: 0.05,
+            response_curve_exponent: 2.0,
+        }
+    }
+}
+
+/// Removes the dead zone and applies the response curve to a stick's raw values.
+/// The output is rescaled so that it still covers the whole -1..1 range
+pub fn apply_stick_response(raw: (f32, f32), dead_zone: f32, exponent: f32) -> (f32, f32) {
+    let (x, y) = raw;
+    let length = (x * x + y * y).sqrt();
+    if length <= dead_zone {
+        return (0.0, 0.0);
+    }
+    let scaled_length = ((length.min(1.0) - dead_zone) / (1.0 - dead_zone)).powf(exponent);
+    (x / length * scaled_length, y / length * scaled_length)
+}
+
+pub fn apply_trigger_response(raw: f32, dead_zone: f32) -> f32 {
+    if raw <= dead_zone {
+        return 0.0;
+    }
+    ((raw - dead_zone) / (1.0 - dead_zone)).min(1.0)
+}
+
+/// A gamepad being plugged in or unplugged
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GamepadConnectionEvent {
+    Connected { name: String },
+    Disconnected { name: String },
+}
+
+/// Polls the connected gamepads with gilrs and feeds the active one into an InputMap.
+/// The active gamepad is the last one that was used, so a second controller can take over
+/// without any setup
+pub struct Gamepads {
+    /// None if gilrs isn't supported on this platform
+    gilrs: Option<Gilrs>,
+    active_gamepad: Option<GamepadId>,
+    raw_left_stick: (f32, f32),
+    raw_right_stick: (f32, f32),
+    connection_events: Vec<GamepadConnectionEvent>,
+    /// rumble stops when the effect is dropped
+    rumble_effect: Option<Effect>,
+    pub settings: GamepadSettings,
+}
+
+impl Default for Gamepads {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Gamepads {
+    pub fn new() -> Self {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(gilrs::Error::NotImplemented(gilrs)) => {
+                log::warn!("Gamepads aren't supported on this platform");
+                Some(gilrs)
+            }
+            Err(err) => {
+                log::error!("Error initializing gamepad support: {err:?}");
+                None
+            }
+        };
+        let active_gamepad = gilrs.as_ref().and_then(|gilrs| {
+            gilrs
+                .gamepads()
+                .find(|(_, gamepad)| gamepad.is_connected())
+                .map(|(id, gamepad)| {
+                    log::info!("Using gamepad: {}", gamepad.name());
+                    id
+                })
+        });
+        Self {
+            gilrs,
+            active_gamepad,
+            raw_left_stick: (0.0, 0.0),
+            raw_right_stick: (0.0, 0.0),
+            connection_events: vec![],
+            rumble_effect: None,
+            settings: Default::default(),
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.active_gamepad.is_some()
+    }
+
+    pub fn active_gamepad_name(&self) -> Option<String> {
+        let gilrs = self.gilrs.as_ref()?;
+        self.active_gamepad
+            .map(|id| gilrs.gamepad(id).name().to_string())
+    }
+
+    /// The gamepads that were plugged in or unplugged since the last call
+    pub fn take_connection_events(&mut self) -> Vec<GamepadConnectionEvent> {
+        std::mem::take(&mut self.connection_events)
+    }
+
+    /// Feeds the pending gamepad events into the input map, should be called once per frame
+    /// before the actions are read. Returns the actions that were pressed or released
+    pub fn update(&mut self, input_map: &mut InputMap) -> Vec<ActionEvent> {
+        let mut action_events = vec![];
+        let Some(gilrs) = self.gilrs.as_mut() else {
+            return action_events;
+        };
+
+        while let Some(gilrs::Event { id, event, .. }) = gilrs.next_event() {
+            let name = gilrs.gamepad(id).name().to_string();
+            match event {
+                EventType::Connected => {
+                    log::info!("Gamepad connected: {name}");
+                    if self.active_gamepad.is_none() {
+                        self.active_gamepad = Some(id);
+                    }
+                    self.connection_events
+                        .push(GamepadConnectionEvent::Connected { name });
+                    continue;
+                }
+                EventType::Disconnected => {
+                    log::info!("Gamepad disconnected: {name}");
+                    if self.active_gamepad == Some(id) {
+                        // the release events of the held buttons won't come anymore
+                        input_map.release_gamepad();
+                        self.raw_left_stick = (0.0, 0.0);
+                        self.raw_right_stick = (0.0, 0.0);
+                        self.rumble_effect = None;
+                        self.active_gamepad = gilrs
+                            .gamepads()
+                            .find(|(other_id, gamepad)| *other_id != id && gamepad.is_connected())
+                            .map(|(other_id, _)| other_id);
+                    }
+                    self.connection_events
+                        .push(GamepadConnectionEvent::Disconnected { name });
+                    continue;
+                }
+                _ => {}
+            }
+
+            if self.active_gamepad != Some(id) {
+                // not on the axes since an idle stick can drift
+                if !matches!(event, EventType::ButtonPressed(..)) {
+                    continue;
+                }
+                log::info!("Switching to gamepad: {name}");
+                input_map.release_gamepad();
+                self.raw_left_stick = (0.0, 0.0);
+                self.raw_right_stick = (0.0, 0.0);
+                self.rumble_effect = None;
+                self.active_gamepad = Some(id);
+            }
+
+            match event {
+                EventType::ButtonPressed(button, _) => {
+                    if let Some(button) = map_button(button) {
+                        action_events.extend(input_map.set_gamepad_button(button, true));
+                    }
+                }
+                EventType::ButtonReleased(button, _) => {
+                    if let Some(button) = map_button(button) {
+                        action_events.extend(input_map.set_gamepad_button(button, false));
+                    }
+                }
+                // the analog value of the triggers comes as a button change
+                EventType::ButtonChanged(Button::LeftTrigger2, value, _) => {
+                    input_map.set_gamepad_axis(
+                        GamepadAxis::LeftTrigger,
+                        apply_trigger_response(value, self.settings.trigger_dead_zone),
+                    );
+                }
+                EventType::ButtonChanged(Button::RightTrigger2, value, _) => {
+                    input_map.set_gamepad_axis(
+                        GamepadAxis::RightTrigger,
+                        apply_trigger_response(value, self.settings.trigger_dead_zone),
+                    );
+                }
+                EventType::AxisChanged(axis, value, _) => {
+                    let (raw_stick, x_axis, y_axis) = match axis {
+                        Axis::LeftStickX | Axis::LeftStickY => (
+                            &mut self.raw_left_stick,
+                            GamepadAxis::LeftStickX,
+                            GamepadAxis::LeftStickY,
+                        ),
+                        Axis::RightStickX | Axis::RightStickY => (
+                            &mut self.raw_right_stick,
+                            GamepadAxis::RightStickX,
+                            GamepadAxis::RightStickY,
+                        ),
+                        _ => continue,
+                    };
+                    if matches!(axis, Axis::LeftStickX | Axis::RightStickX) {
+                        raw_stick.0 = value;
+                    } else {
+                        raw_stick.1 = value;
+                    }
+                    let (x, y) = apply_stick_response(
+                        *raw_stick,
+                        self.settings.stick_dead_zone,
+                        self.settings.response_curve_exponent,
+                    );
+                    input_map.set_gamepad_axis(x_axis, x);
+                    input_map.set_gamepad_axis(y_axis, y);
+                }
+                _ => {}
+            }
+        }
+
+        action_events
+    }
+
+    /// Rumbles the active gamepad, replacing the previous rumble. strength is in 0..1.
+    /// Does nothing if the gamepad doesn't support force feedback
+    pub fn rumble(&mut self, strength: f32, duration: Duration) {
+        let (Some(gilrs), Some(active_gamepad)) = (self.gilrs.as_mut(), self.active_gamepad) else {
+            return;
+        };
+        if !gilrs.gamepad(active_gamepad).is_ff_supported() {
+            return;
+        }
+        let magnitude = (strength.clamp(0.0, 1.0) * u16::MAX as f32) as u16;
+        let effect = EffectBuilder::new()
+            .add_effect(BaseEffect {
+                kind: BaseEffectType::Strong { magnitude },
+                scheduling: Replay {
+                    play_for: Ticks::from_ms(duration.as_millis() as u32),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .gamepads(&[active_gamepad])
+            .finish(gilrs)
+            .and_then(|effect| effect.play().map(|_| effect));
+        match effect {
+            Ok(effect) => {
+                self.rumble_effect = Some(effect);
+            }
+            Err(err) => {
+                log::error!("Error playing gamepad rumble: {err:?}");
+            }
+        }
+    }
+}
+
+fn map_button(button: Button) -> Option<GamepadButton> {
+    Some(match button {
+        Button::South => GamepadButton::South,
+        Button::East => GamepadButton::East,
+        Button::West => GamepadButton::West,
+        Button::North => GamepadButton::North,
+        Button::LeftTrigger => GamepadButton::LeftBumper,
+        Button::RightTrigger => GamepadButton::RightBumper,
+        Button::LeftTrigger2 => GamepadButton::LeftTrigger,
+        Button::RightTrigger2 => GamepadButton::RightTrigger,
+        Button::Select => GamepadButton::Select,
+        Button::Start => GamepadButton::Start,
+        Button::LeftThumb => GamepadButton::LeftStick,
+        Button::RightThumb => GamepadButton::RightStick,
+        Button::DPadUp => GamepadButton::DPadUp,
+        Button::DPadDown => GamepadButton::DPadDown,
+        Button::DPadLeft => GamepadButton::DPadLeft,
+        Button::DPadRight => GamepadButton::DPadRight,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stick_response_removes_the_dead_zone() {
+        assert_eq!(apply_stick_response((0.1, 0.05), 0.15, 2.0), (0.0, 0.0));
+
+        let (x, y) = apply_stick_response((1.0, 0.0), 0.15, 2.0);
+        assert!((x - 1.0).abs() < 1e-5 && y == 0.0);
+
+        // the direction is kept and the length follows the curve
+        let (x, y) = apply_stick_response((0.0, -0.575), 0.15, 2.0);
+        assert!(x == 0.0 && (y + 0.25).abs() < 1e-5);
+    }
+}
@@ -1,4 +1,6 @@
+use crate::animation::AnimationEventMarker;
 use crate::asset_loader::SceneAssetLoadParams;
+use crate::asset_registry::{MaterialHandle, MeshHandle};
 use crate::collisions::Aabb;
 use crate::file_manager::GameFilePath;
 use crate::mesh::*;
@@ -17,6 +19,7 @@ use std::path::PathBuf;
 use anyhow::{bail, Result};
 use approx::abs_diff_eq;
 use glam::f32::{Mat4, Vec2, Vec3, Vec4};
+use serde::Deserialize;
 
 #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
 pub struct ChannelPropertyStr<'a>(&'a str);
@@ -180,9 +183,9 @@ pub async fn build_scene(
         if let Some(visuals) = node_visual_map.get(&gltf_node.index()) {
             for (i, (mesh_index, pbr_material_index)) in visuals.iter().enumerate() {
                 let visual = GameNodeVisual::from_mesh_mat(
-                    *mesh_index,
+                    MeshHandle::unbinded(*mesh_index),
                     Material::Pbr {
-                        binded_material_index: *pbr_material_index,
+                        binded_material: MaterialHandle::unbinded(*pbr_material_index),
                         dynamic_pbr_params: None,
                     },
                 );
@@ -249,7 +252,8 @@ pub async fn build_scene(
                     .visual
                     .as_ref()
                     .expect("Skeleton skin node should have a mesh")
-                    .mesh_index;
+                    .mesh
+                    .index();
                 let skeleton_mesh_vertices = &bindable_meshes[skeleton_mesh_index].vertices;
 
                 let bone_bounding_box_transforms: Vec<_> = (0..bone_inverse_bind_matrices.len())
@@ -413,6 +417,7 @@ async fn get_textures(
                 mag_filter,
                 min_filter,
                 mipmap_filter,
+                use_texture_quality: true,
                 ..Default::default()
             },
         });
@@ -457,11 +462,51 @@ pub fn get_animations(
                 name: animation.name().map(String::from),
                 length_seconds,
                 channels,
+                events: get_animation_events(&animation),
             })
         })
         .collect::<Result<Vec<_>, _>>()
 }
 
+#[derive(Deserialize)]
+struct AnimationExtras {
+    #[serde(default)]
+    events: Vec<AnimationExtrasEvent>,
+}
+
+#[derive(Deserialize)]
+struct AnimationExtrasEvent {
+    time: f32,
+    name: String,
+}
+
+fn get_animation_events(animation: &gltf::Animation) -> Vec<AnimationEventMarker> {
+    let Some(extras) = animation.extras() else {
+        return vec![];
+    };
+    match serde_json::from_str::<AnimationExtras>(extras.get()) {
+        Ok(extras) => {
+            let mut events: Vec<_> = extras
+                .events
+                .into_iter()
+                .map(|event| AnimationEventMarker {
+                    time_seconds: event.time,
+                    name: event.name,
+                })
+                .collect();
+            events.sort_by(|a, b| a.time_seconds.partial_cmp(&b.time_seconds).unwrap());
+            events
+        }
+        Err(err) => {
+            log::warn!(
+                "Failed to read the events of animation {:?}: {err}",
+                animation.name()
+            );
+            vec![]
+        }
+    }
+}
+
 fn validate_channel_data_type(channel: &gltf::animation::Channel) -> Result<()> {
     let accessor = channel.sampler().output();
     let data_type = accessor.data_type();
@@ -699,6 +744,8 @@ fn get_indexed_pbr_material(material: &gltf::material::Material) -> IndexedPbrTe
                 .metallic_roughness_texture()
                 .map(|info| info.texture()),
         ),
+        // not part of gltf, lightmaps are baked and set separately
+        lightmap: None,
     }
 }
 
@@ -722,6 +769,20 @@ fn get_dynamic_pbr_params(material: &gltf::material::Material) -> DynamicPbrPara
             gltf::material::AlphaMode::Mask => material.alpha_cutoff().unwrap_or(0.5),
             _ => DynamicPbrParams::default().alpha_cutoff,
         },
+        // the transforms are per texture in gltf but they're all the same in practice so the
+        // base color's one is used for the whole material
+        uv_transform: pbr_info
+            .base_color_texture()
+            .and_then(|info| info.texture_transform())
+            .map(|transform| UvTransform {
+                offset: transform.offset().into(),
+                scale: transform.scale().into(),
+                rotation: transform.rotation(),
+                ..Default::default()
+            })
+            .unwrap_or_default(),
+        lightmap_intensity: DynamicPbrParams::default().lightmap_intensity,
+        parallax_height_scale: DynamicPbrParams::default().parallax_height_scale,
     }
 }
 
@@ -760,8 +821,13 @@ pub fn build_geometry(
         ));
     }
 
-    let vertex_tex_coords = get_vertex_tex_coords(primitive_group, buffers, vertex_position_count)?;
+    let vertex_tex_coords = get_vertex_tex_coords(primitive_group, buffers, 0)?
+        .unwrap_or_else(|| (0..vertex_position_count).map(|_| [0.5, 0.5]).collect());
     let vertex_tex_coord_count = vertex_tex_coords.len();
+    // meshes without a dedicated lightmap uv set are assumed to have non-overlapping main uvs
+    let vertex_lightmap_tex_coords = get_vertex_tex_coords(primitive_group, buffers, 1)?
+        .unwrap_or_else(|| vertex_tex_coords.clone());
+    let vertex_lightmap_tex_coord_count = vertex_lightmap_tex_coords.len();
 
     let vertex_colors = get_vertex_colors(primitive_group, buffers, vertex_position_count)?;
     let vertex_color_count = vertex_colors.len();
@@ -797,6 +863,13 @@ pub fn build_geometry(
           vertex_tex_coord_count
       );
     }
+    if vertex_lightmap_tex_coord_count != vertex_position_count {
+        bail!(
+          "Expected lightmap tex coords for every vertex but found: vertex_position_count({:?}) != vertex_lightmap_tex_coord_count({:?})",
+          vertex_position_count,
+          vertex_lightmap_tex_coord_count
+      );
+    }
     if vertex_color_count != vertex_position_count {
         bail!(
           "Expected vertex colors for every vertex but found: vertex_position_count({:?}) != vertex_color_count({:?})",
@@ -842,6 +915,7 @@ pub fn build_geometry(
             color: vertex_colors[index],
             bone_indices: vertex_bone_indices[index],
             bone_weights: vertex_bone_weights[index],
+            lightmap_tex_coords: vertex_lightmap_tex_coords[index],
         });
     }
 
@@ -881,6 +955,7 @@ pub fn build_geometry(
             vertices,
             indices: bindable_indices,
             bounding_box,
+            collision_mesh: None,
         },
         bindable_wireframe_indices,
     ))
@@ -1183,14 +1258,15 @@ fn get_vertex_colors(
     Ok(vertex_colors)
 }
 
+/// None if the primitive doesn't have the uv set
 fn get_vertex_tex_coords(
     primitive_group: &gltf::Primitive,
     buffers: &[gltf::buffer::Data],
-    vertex_position_count: usize,
-) -> Result<Vec<[f32; 2]>, anyhow::Error> {
-    let vertex_tex_coords: Vec<[f32; 2]> = primitive_group
+    set_index: u32,
+) -> Result<Option<Vec<[f32; 2]>>, anyhow::Error> {
+    primitive_group
         .attributes()
-        .find(|(semantic, _)| *semantic == gltf::Semantic::TexCoords(0))
+        .find(|(semantic, _)| *semantic == gltf::Semantic::TexCoords(set_index))
         .map(|(_, accessor)| {
             let data_type = accessor.data_type();
             let dimensions = accessor.dimensions();
@@ -1202,9 +1278,7 @@ fn get_vertex_tex_coords(
             }
             Ok(bytemuck::cast_slice(get_buffer_slice_from_accessor(accessor, buffers)).to_vec())
         })
-        .transpose()?
-        .unwrap_or_else(|| (0..vertex_position_count).map(|_| [0.5, 0.5]).collect());
-    Ok(vertex_tex_coords)
+        .transpose()
 }
 
 #[profiling::function]
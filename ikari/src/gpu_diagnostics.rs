@@ -0,0 +1,207 @@
+use crate::crash_handler::{self, DiagnosticReport};
+use crate::renderer::*;
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// number of render passes to keep in the history, across frames
+const PASS_HISTORY_LENGTH: usize = 64;
+
+/// subset of the renderer settings that is cheap to copy every frame
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RenderSettingsSnapshot {
+    pub render_scale: f32,
+    pub tone_mapping_exposure: f32,
+    pub bloom_type: Option<BloomType>,
+    pub enable_depth_prepass: bool,
+    pub enable_shadows: bool,
+    pub enable_soft_shadows: bool,
+    pub soft_shadow_grid_dims: u32,
+    pub enable_directional_shadow_culling: bool,
+    pub enable_wireframe_mode: bool,
+}
+
+impl From<&RendererData> for RenderSettingsSnapshot {
+    fn from(data: &RendererData) -> Self {
+        Self {
+            render_scale: data.render_scale,
+            tone_mapping_exposure: data.tone_mapping_exposure,
+            bloom_type: Some(data.bloom_type),
+            enable_depth_prepass: data.enable_depth_prepass,
+            enable_shadows: data.enable_shadows,
+            enable_soft_shadows: data.enable_soft_shadows,
+            soft_shadow_grid_dims: data.soft_shadow_grid_dims,
+            enable_directional_shadow_culling: data.enable_directional_shadow_culling,
+            enable_wireframe_mode: data.enable_wireframe_mode,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct GpuDiagnosticsState {
+    frame_index: u64,
+    /// (frame index, pass label)
+    pass_history: VecDeque<(u64, &'static str)>,
+    render_settings: RenderSettingsSnapshot,
+    surface_size: (u32, u32),
+    surface_format: Option<wgpu::TextureFormat>,
+    present_mode: Option<wgpu::PresentMode>,
+}
+
+/// Keeps track of what the renderer was doing so that a diagnostic report can
+/// be written to disk when the gpu device reports an error, see crash_handler
+#[derive(Clone)]
+pub struct GpuDiagnostics {
+    adapter_info: wgpu::AdapterInfo,
+    features: wgpu::Features,
+    limits: wgpu::Limits,
+    state: Arc<Mutex<GpuDiagnosticsState>>,
+    is_device_lost: Arc<AtomicBool>,
+}
+
+impl GpuDiagnostics {
+    pub fn new(adapter: &wgpu::Adapter, device: &wgpu::Device) -> Self {
+        Self {
+            adapter_info: adapter.get_info(),
+            features: device.features(),
+            limits: device.limits(),
+            state: Default::default(),
+            is_device_lost: Default::default(),
+        }
+    }
+
+    /// replaces wgpu's default handler, which just panics, by one that writes
+    /// a diagnostic report before panicking. The errors that follow a device loss are only
+    /// logged since the renderer can recover from it. Also makes the reports of the
+    /// crash_handler include these diagnostics
+    pub fn install_error_handler(&self, device: &wgpu::Device) {
+        crash_handler::set_gpu_diagnostics(self.clone());
+
+        let diagnostics = self.clone();
+        device.on_uncaptured_error(Box::new(move |error| {
+            if diagnostics.is_device_lost() {
+                log::warn!("wgpu error after the device was lost: {error}");
+                return;
+            }
+            let reason = format!("Uncaptured wgpu error: {error}");
+            // the panic hook writes its own report
+            if !crash_handler::is_panic_hook_installed() {
+                diagnostics.write_bundle(&reason);
+            }
+            panic!("{reason}");
+        }));
+
+        let diagnostics = self.clone();
+        device.set_device_lost_callback(move |reason, message| {
+            // the device is dropped on purpose when the renderer shuts down or recreates it
+            if matches!(reason, wgpu::DeviceLostReason::Dropped) {
+                return;
+            }
+            diagnostics.write_bundle(&format!("Device lost ({reason:?}): {message}"));
+            diagnostics.is_device_lost.store(true, Ordering::SeqCst);
+        });
+    }
+
+    pub fn is_device_lost(&self) -> bool {
+        self.is_device_lost.load(Ordering::SeqCst)
+    }
+
+    pub fn record_pass(&self, label: &'static str) {
+        let mut state = self.state.lock().unwrap();
+        if state.pass_history.len() == PASS_HISTORY_LENGTH {
+            state.pass_history.pop_front();
+        }
+        let frame_index = state.frame_index;
+        state.pass_history.push_back((frame_index, label));
+    }
+
+    /// present_mode is None when rendering offscreen
+    pub fn on_frame_started(
+        &self,
+        render_settings: RenderSettingsSnapshot,
+        surface_size: (u32, u32),
+        surface_format: wgpu::TextureFormat,
+        present_mode: Option<wgpu::PresentMode>,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        state.frame_index += 1;
+        state.render_settings = render_settings;
+        state.surface_size = surface_size;
+        state.surface_format = Some(surface_format);
+        state.present_mode = present_mode;
+    }
+
+    pub fn build_report(&self, reason: &str) -> String {
+        let mut report = String::new();
+
+        let _ = writeln!(report, "ikari gpu diagnostics");
+        let _ = writeln!(report, "Reason: {reason}");
+        let _ = writeln!(report, "Time: {}", chrono::Local::now().to_rfc3339());
+        let _ = writeln!(report);
+
+        let wgpu::AdapterInfo {
+            name,
+            vendor,
+            device,
+            device_type,
+            driver,
+            driver_info,
+            backend,
+        } = &self.adapter_info;
+        let _ = writeln!(report, "== Adapter ==");
+        let _ = writeln!(report, "Name: {name}");
+        let _ = writeln!(report, "Vendor: {vendor:#x}, device: {device:#x}");
+        let _ = writeln!(report, "Type: {device_type:?}");
+        let _ = writeln!(report, "Backend: {backend:?}");
+        let _ = writeln!(report, "Driver: {driver} ({driver_info})");
+        let _ = writeln!(report);
+
+        let _ = writeln!(report, "== Features ==");
+        let _ = writeln!(report, "{:?}", self.features);
+        let _ = writeln!(report);
+
+        let _ = writeln!(report, "== Limits ==");
+        let _ = writeln!(report, "{:#?}", self.limits);
+        let _ = writeln!(report);
+
+        // the error handler can fire while the state is locked by the render thread
+        match self.state.try_lock() {
+            Ok(state) => {
+                let _ = writeln!(report, "== Render settings ==");
+                let _ = writeln!(report, "{:#?}", state.render_settings);
+                let _ = writeln!(
+                    report,
+                    "Surface: {:?}, format: {:?}, present mode: {:?}",
+                    state.surface_size, state.surface_format, state.present_mode
+                );
+                let _ = writeln!(report);
+
+                let _ = writeln!(
+                    report,
+                    "== Last {} passes (current frame: {}) ==",
+                    state.pass_history.len(),
+                    state.frame_index
+                );
+                for (frame_index, label) in &state.pass_history {
+                    let _ = writeln!(report, "[frame {frame_index}] {label}");
+                }
+                let _ = writeln!(report);
+            }
+            Err(_) => {
+                let _ = writeln!(report, "(render state unavailable)");
+                let _ = writeln!(report);
+            }
+        }
+
+        report
+    }
+
+    /// writes a diagnostic report with these diagnostics, the recent log lines and the sections
+    /// of the crash_handler. Returns the path of the report, None on the web or if it failed
+    pub fn write_bundle(&self, reason: &str) -> Option<PathBuf> {
+        DiagnosticReport::collect(reason).write()
+    }
+}
@@ -0,0 +1,317 @@
+use crate::destruction::fracture;
+use crate::ecs::{Entity, SceneNode};
+use crate::engine_state::EngineState;
+use crate::mesh::DynamicPbrParams;
+use crate::ragdoll::Ragdoll;
+use crate::renderer::RendererData;
+use crate::scene::{GameNodeId, Material};
+
+use glam::f32::Vec3;
+use serde::{Deserialize, Serialize};
+
+/// The component of the entities that can take damage
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Health {
+    pub fn new(max: f32) -> Self {
+        Self { current: max, max }
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0.0
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DeathBehavior {
+    /// the game gets the Died event and takes care of the rest
+    None,
+    Despawn,
+    /// turns on the entity's Ragdoll component, if it has one
+    Ragdoll,
+    /// breaks the entity into the pieces of its Destructible component, see fracture.
+    /// It's despawned if it has none
+    Fracture,
+}
+
+/// How an entity with Health reacts to damage, optional
+#[derive(Debug, Clone)]
+pub struct HitReaction {
+    /// the nodes whose pbr materials flash when hit, the entity's SceneNode if empty
+    pub flash_nodes: Vec<GameNodeId>,
+    pub flash_emissive: Vec3,
+    pub flash_seconds: f32,
+    /// index into Scene::animations, restarted on every hit. Its channels override the ones of
+    /// the other animations while it plays, so it should only move the nodes that flinch
+    pub flinch_animation: Option<usize>,
+    /// name of an effect in the EffectLibrary, passed along with the Damaged event
+    pub hit_effect: Option<String>,
+    pub death: DeathBehavior,
+}
+
+impl Default for HitReaction {
+    fn default() -> Self {
+        Self {
+            flash_nodes: vec![],
+            flash_emissive: Vec3::new(4.0, 0.5, 0.5),
+            flash_seconds: 0.1,
+            flinch_animation: None,
+            hit_effect: None,
+            death: DeathBehavior::Despawn,
+        }
+    }
+}
+
+/// Sent to EngineState::events to hurt an entity, handled by HealthSystem::update
+#[derive(Debug, Clone, PartialEq)]
+pub struct DamageEvent {
+    pub target: Entity,
+    pub amount: f32,
+    /// where the hit landed, if it came from somewhere in particular
+    pub position: Option<Vec3>,
+}
+
+/// Sent to EngineState::events by HealthSystem::update
+#[derive(Debug, Clone, PartialEq)]
+pub enum HealthEvent {
+    Damaged {
+        entity: Entity,
+        amount: f32,
+        remaining: f32,
+        position: Option<Vec3>,
+        hit_effect: Option<String>,
+    },
+    /// sent before the entity is despawned, if its death behavior despawns it
+    Died { entity: Entity },
+}
+
+/// The original params of a flashing node, put back once the flash is over
+#[derive(Debug, Clone)]
+struct Flash {
+    node_id: GameNodeId,
+    original_params: Option<DynamicPbrParams>,
+    remaining_seconds: f32,
+}
+
+/// Applies the DamageEvents sent since the last update and the hit reactions that go with them.
+/// Meant to be called once per frame, before the game drains the HealthEvents
+#[derive(Debug, Default)]
+pub struct HealthSystem {
+    flashes: Vec<Flash>,
+    /// the entities that died with DeathBehavior::Fracture, along with the last hit's position
+    fractures: Vec<(Entity, Option<Vec3>)>,
+}
+
+impl HealthSystem {
+    #[profiling::function]
+    pub fn update(
+        &mut self,
+        engine_state: &mut EngineState,
+        renderer_data: &RendererData,
+        delta_time_seconds: f32,
+    ) {
+        self.flashes.retain_mut(|flash| {
+            flash.remaining_seconds -= delta_time_seconds;
+            if flash.remaining_seconds > 0.0 {
+                return true;
+            }
+            if let Some(node) = engine_state.scene.get_node_mut(flash.node_id) {
+                if let Some(Material::Pbr {
+                    dynamic_pbr_params, ..
+                }) = node.visual.as_mut().map(|visual| &mut visual.material)
+                {
+                    *dynamic_pbr_params = flash.original_params;
+                }
+            }
+            false
+        });
+
+        let hits = self.apply_damage(engine_state);
+        for (entity, hit_position) in std::mem::take(&mut self.fractures) {
+            if fracture(engine_state, renderer_data, entity, hit_position).is_empty() {
+                engine_state.despawn_entity(entity);
+            }
+        }
+
+        for (entity, reaction) in hits {
+            self.flash(engine_state, renderer_data, entity, &reaction);
+            if let Some(animation) = reaction
+                .flinch_animation
+                .and_then(|animation_index| engine_state.scene.animations.get_mut(animation_index))
+            {
+                animation.state.is_playing = true;
+                animation.state.current_time_seconds = 0.0;
+            }
+        }
+    }
+
+    /// Returns the entities that got hit and survived, with the reaction to play
+    fn apply_damage(&mut self, engine_state: &mut EngineState) -> Vec<(Entity, HitReaction)> {
+        let mut hits = vec![];
+        for damage_event in engine_state.events.drain::<DamageEvent>() {
+            let Some(health) = engine_state.world.get_mut::<Health>(damage_event.target) else {
+                continue;
+            };
+            if health.is_dead() {
+                continue;
+            }
+            health.current = (health.current - damage_event.amount).max(0.0);
+            let health = *health;
+
+            let reaction = engine_state
+                .world
+                .get::<HitReaction>(damage_event.target)
+                .cloned()
+                .unwrap_or_default();
+
+            engine_state.events.send(HealthEvent::Damaged {
+                entity: damage_event.target,
+                amount: damage_event.amount,
+                remaining: health.current,
+                position: damage_event.position,
+                hit_effect: reaction.hit_effect.clone(),
+            });
+
+            if health.is_dead() {
+                engine_state.events.send(HealthEvent::Died {
+                    entity: damage_event.target,
+                });
+                self.die(
+                    engine_state,
+                    damage_event.target,
+                    reaction.death,
+                    damage_event.position,
+                );
+            } else {
+                hits.push((damage_event.target, reaction));
+            }
+        }
+        hits
+    }
+
+    fn flash(
+        &mut self,
+        engine_state: &mut EngineState,
+        renderer_data: &RendererData,
+        entity: Entity,
+        reaction: &HitReaction,
+    ) {
+        let node_ids = if reaction.flash_nodes.is_empty() {
+            engine_state
+                .world
+                .get::<SceneNode>(entity)
+                .map(|SceneNode(node_id)| vec![*node_id])
+                .unwrap_or_default()
+        } else {
+            reaction.flash_nodes.clone()
+        };
+
+        for node_id in node_ids {
+            let Some(Material::Pbr {
+                binded_material,
+                dynamic_pbr_params,
+            }) = engine_state
+                .scene
+                .get_node_mut(node_id)
+                .and_then(|node| node.visual.as_mut())
+                .map(|visual| &mut visual.material)
+            else {
+                continue;
+            };
+
+            match self
+                .flashes
+                .iter_mut()
+                .find(|flash| flash.node_id == node_id)
+            {
+                // still flashing from the last hit, keep the params from before it
+                Some(flash) => flash.remaining_seconds = reaction.flash_seconds,
+                None => self.flashes.push(Flash {
+                    node_id,
+                    original_params: *dynamic_pbr_params,
+                    remaining_seconds: reaction.flash_seconds,
+                }),
+            }
+
+            let mut params = dynamic_pbr_params.unwrap_or_else(|| {
+                renderer_data
+                    .binded_pbr_materials
+                    .get(binded_material.index())
+                    .map(|material| material.dynamic_pbr_params)
+                    .unwrap_or_default()
+            });
+            params.emissive_factor = reaction.flash_emissive;
+            *dynamic_pbr_params = Some(params);
+        }
+    }
+
+    fn die(
+        &mut self,
+        engine_state: &mut EngineState,
+        entity: Entity,
+        death: DeathBehavior,
+        hit_position: Option<Vec3>,
+    ) {
+        match death {
+            DeathBehavior::None => {}
+            DeathBehavior::Despawn | DeathBehavior::Fracture => {
+                if let Some(SceneNode(node_id)) = engine_state.world.get::<SceneNode>(entity) {
+                    let node_id = *node_id;
+                    self.flashes.retain(|flash| flash.node_id != node_id);
+                }
+                // the pieces need the renderer data for their colliders
+                if death == DeathBehavior::Fracture {
+                    self.fractures.push((entity, hit_position));
+                } else {
+                    engine_state.despawn_entity(entity);
+                }
+            }
+            DeathBehavior::Ragdoll => {
+                if let Some(ragdoll) = engine_state.world.get_mut::<Ragdoll>(entity) {
+                    ragdoll.enable_ragdoll(&mut engine_state.physics_state);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn damage_stops_at_death() {
+        let mut engine_state = EngineState::new_headless();
+        let entity = engine_state.world.spawn();
+        engine_state.world.insert(entity, Health::new(3.0));
+        engine_state.world.insert(
+            entity,
+            HitReaction {
+                death: DeathBehavior::None,
+                ..Default::default()
+            },
+        );
+
+        for _ in 0..3 {
+            engine_state.events.send(DamageEvent {
+                target: entity,
+                amount: 2.0,
+                position: None,
+            });
+        }
+        let hits = HealthSystem::default().apply_damage(&mut engine_state);
+
+        assert_eq!(hits.len(), 1);
+        assert_eq!(
+            engine_state.world.get::<Health>(entity).unwrap().current,
+            0.0
+        );
+        let health_events = engine_state.events.drain::<HealthEvent>();
+        assert_eq!(health_events.len(), 3);
+        assert_eq!(health_events[2], HealthEvent::Died { entity });
+    }
+}
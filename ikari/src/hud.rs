@@ -0,0 +1,346 @@
+use iced::alignment::{Horizontal, Vertical};
+use iced::widget::{image, progress_bar, text, Column, Container, Row};
+use iced::{Alignment, Background, Color, Element, Font, Length, Padding, Theme};
+
+/// Where a widget is placed on the screen, widgets with the same anchor are stacked
+/// in the order they were added
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum HudAnchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl HudAnchor {
+    pub const ALL: [HudAnchor; 9] = [
+        HudAnchor::TopLeft,
+        HudAnchor::Top,
+        HudAnchor::TopRight,
+        HudAnchor::Left,
+        HudAnchor::Center,
+        HudAnchor::Right,
+        HudAnchor::BottomLeft,
+        HudAnchor::Bottom,
+        HudAnchor::BottomRight,
+    ];
+
+    fn horizontal(self) -> Horizontal {
+        match self {
+            HudAnchor::TopLeft | HudAnchor::Left | HudAnchor::BottomLeft => Horizontal::Left,
+            HudAnchor::Top | HudAnchor::Center | HudAnchor::Bottom => Horizontal::Center,
+            HudAnchor::TopRight | HudAnchor::Right | HudAnchor::BottomRight => Horizontal::Right,
+        }
+    }
+
+    fn vertical(self) -> Vertical {
+        match self {
+            HudAnchor::TopLeft | HudAnchor::Top | HudAnchor::TopRight => Vertical::Top,
+            HudAnchor::Left | HudAnchor::Center | HudAnchor::Right => Vertical::Center,
+            HudAnchor::BottomLeft | HudAnchor::Bottom | HudAnchor::BottomRight => Vertical::Bottom,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HudText {
+    pub text: String,
+    pub size: f32,
+    pub color: Color,
+    /// one of the fonts given to IkariUiContainer::new, None for the default font
+    pub font: Option<Font>,
+}
+
+#[derive(Debug, Clone)]
+pub struct HudImage {
+    pub handle: image::Handle,
+    /// in logical pixels, None to use the size of the image
+    pub size: Option<(f32, f32)>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct HudProgressBar {
+    /// 0..1
+    pub progress: f32,
+    /// in logical pixels
+    pub size: (f32, f32),
+    pub bar_color: Color,
+    pub background_color: Color,
+}
+
+#[derive(Debug, Clone)]
+pub enum HudWidgetKind {
+    Text(HudText),
+    Image(HudImage),
+    ProgressBar(HudProgressBar),
+}
+
+#[derive(Debug, Clone)]
+pub struct HudWidget {
+    pub kind: HudWidgetKind,
+    pub anchor: HudAnchor,
+    /// (horizontal, vertical) distance to the edges of the screen, in logical pixels
+    pub margin: (f32, f32),
+    pub is_visible: bool,
+}
+
+impl HudWidget {
+    pub fn text(text: &str, size: f32, anchor: HudAnchor) -> Self {
+        Self::new(
+            HudWidgetKind::Text(HudText {
+                text: text.to_string(),
+                size,
+                color: Color::WHITE,
+                font: None,
+            }),
+            anchor,
+        )
+    }
+
+    pub fn image(handle: image::Handle, anchor: HudAnchor) -> Self {
+        Self::new(
+            HudWidgetKind::Image(HudImage { handle, size: None }),
+            anchor,
+        )
+    }
+
+    pub fn progress_bar(progress: f32, size: (f32, f32), anchor: HudAnchor) -> Self {
+        Self::new(
+            HudWidgetKind::ProgressBar(HudProgressBar {
+                progress,
+                size,
+                bar_color: Color::from_rgb(0.2, 0.8, 0.3),
+                background_color: Color::from_rgba(0.1, 0.1, 0.1, 0.6),
+            }),
+            anchor,
+        )
+    }
+
+    pub fn new(kind: HudWidgetKind, anchor: HudAnchor) -> Self {
+        Self {
+            kind,
+            anchor,
+            margin: (16.0, 16.0),
+            is_visible: true,
+        }
+    }
+
+    fn view<'a, Message: 'a>(&self) -> Element<'a, Message, Theme, iced::Renderer> {
+        let element: Element<'a, Message, Theme, iced::Renderer> = match &self.kind {
+            HudWidgetKind::Text(hud_text) => {
+                let mut widget = text(&hud_text.text)
+                    .size(hud_text.size)
+                    .style(hud_text.color);
+                if let Some(font) = hud_text.font {
+                    widget = widget.font(font);
+                }
+                widget.into()
+            }
+            HudWidgetKind::Image(hud_image) => {
+                let mut widget = image(hud_image.handle.clone());
+                if let Some((width, height)) = hud_image.size {
+                    widget = widget
+                        .width(Length::Fixed(width))
+                        .height(Length::Fixed(height));
+                }
+                widget.into()
+            }
+            HudWidgetKind::ProgressBar(hud_progress_bar) => {
+                progress_bar(0.0..=1.0, hud_progress_bar.progress.clamp(0.0, 1.0))
+                    .width(Length::Fixed(hud_progress_bar.size.0))
+                    .height(Length::Fixed(hud_progress_bar.size.1))
+                    .style(iced::theme::ProgressBar::Custom(Box::new(
+                        ProgressBarStyle {
+                            bar_color: hud_progress_bar.bar_color,
+                            background_color: hud_progress_bar.background_color,
+                        },
+                    )))
+                    .into()
+            }
+        };
+        Container::new(element)
+            .padding(Padding::from([self.margin.1, self.margin.0]))
+            .into()
+    }
+}
+
+pub(crate) struct ProgressBarStyle {
+    pub bar_color: Color,
+    pub background_color: Color,
+}
+
+impl progress_bar::StyleSheet for ProgressBarStyle {
+    type Style = Theme;
+
+    fn appearance(&self, _: &Self::Style) -> progress_bar::Appearance {
+        progress_bar::Appearance {
+            background: Background::Color(self.background_color),
+            bar: Background::Color(self.bar_color),
+            border_radius: 2.0.into(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct HudWidgetId(usize);
+
+/// A retained set of HUD widgets laid out over the whole screen. It's meant to be kept by the
+/// game and sent to its iced program whenever it changes, the program then draws it with
+/// Hud::view as part of its own view so that the whole ui is still drawn in one pass
+#[derive(Debug, Clone, Default)]
+pub struct Hud {
+    widgets: Vec<Option<HudWidget>>,
+    has_changed: bool,
+}
+
+impl Hud {
+    pub fn add(&mut self, widget: HudWidget) -> HudWidgetId {
+        self.has_changed = true;
+        self.widgets.push(Some(widget));
+        HudWidgetId(self.widgets.len() - 1)
+    }
+
+    pub fn remove(&mut self, widget_id: HudWidgetId) {
+        if let Some(widget) = self.widgets.get_mut(widget_id.0) {
+            self.has_changed = true;
+            *widget = None;
+        }
+    }
+
+    pub fn get(&self, widget_id: HudWidgetId) -> Option<&HudWidget> {
+        self.widgets.get(widget_id.0).and_then(Option::as_ref)
+    }
+
+    /// Marks the hud as changed
+    pub fn get_mut(&mut self, widget_id: HudWidgetId) -> Option<&mut HudWidget> {
+        let widget = self.widgets.get_mut(widget_id.0).and_then(Option::as_mut);
+        self.has_changed |= widget.is_some();
+        widget
+    }
+
+    pub fn set_visible(&mut self, widget_id: HudWidgetId, is_visible: bool) {
+        if self.get(widget_id).map(|widget| widget.is_visible) == Some(!is_visible) {
+            self.get_mut(widget_id).unwrap().is_visible = is_visible;
+        }
+    }
+
+    /// Only changes the text widgets
+    pub fn set_text(&mut self, widget_id: HudWidgetId, new_text: &str) {
+        if let Some(HudWidgetKind::Text(hud_text)) = self.get(widget_id).map(|widget| &widget.kind)
+        {
+            if hud_text.text == new_text {
+                return;
+            }
+        }
+        if let Some(HudWidgetKind::Text(hud_text)) =
+            self.get_mut(widget_id).map(|widget| &mut widget.kind)
+        {
+            hud_text.text = new_text.to_string();
+        }
+    }
+
+    /// Only changes the progress bars
+    pub fn set_progress(&mut self, widget_id: HudWidgetId, progress: f32) {
+        if let Some(HudWidgetKind::ProgressBar(hud_progress_bar)) =
+            self.get(widget_id).map(|widget| &widget.kind)
+        {
+            if hud_progress_bar.progress == progress {
+                return;
+            }
+        }
+        if let Some(HudWidgetKind::ProgressBar(hud_progress_bar)) =
+            self.get_mut(widget_id).map(|widget| &mut widget.kind)
+        {
+            hud_progress_bar.progress = progress;
+        }
+    }
+
+    /// Only changes the image widgets
+    pub fn set_image(&mut self, widget_id: HudWidgetId, handle: image::Handle) {
+        if let Some(HudWidgetKind::Image(hud_image)) =
+            self.get_mut(widget_id).map(|widget| &mut widget.kind)
+        {
+            hud_image.handle = handle;
+        }
+    }
+
+    /// Whether the hud changed since the last call
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.has_changed)
+    }
+
+    fn visible_widgets(&self, anchor: HudAnchor) -> impl Iterator<Item = &HudWidget> {
+        self.widgets
+            .iter()
+            .flatten()
+            .filter(move |widget| widget.is_visible && widget.anchor == anchor)
+    }
+
+    /// Fills the screen, a 3x3 grid with the widgets of each anchor in its cell
+    pub fn view<'a, Message: 'a>(&self) -> Element<'a, Message, Theme, iced::Renderer> {
+        let mut rows = Column::new().width(Length::Fill).height(Length::Fill);
+        for anchors in HudAnchor::ALL.chunks(3) {
+            let mut row = Row::new().width(Length::Fill).height(Length::Fill);
+            for anchor in anchors {
+                let align_items = match anchor.horizontal() {
+                    Horizontal::Left => Alignment::Start,
+                    Horizontal::Center => Alignment::Center,
+                    Horizontal::Right => Alignment::End,
+                };
+                let mut column = Column::new().align_items(align_items);
+                for widget in self.visible_widgets(*anchor) {
+                    column = column.push(widget.view());
+                }
+                row = row.push(
+                    Container::new(column)
+                        .width(Length::Fill)
+                        .height(Length::Fill)
+                        .align_x(anchor.horizontal())
+                        .align_y(anchor.vertical()),
+                );
+            }
+            rows = rows.push(row);
+        }
+        rows.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_actual_changes_mark_the_hud() {
+        let mut hud = Hud::default();
+        let label = hud.add(HudWidget::text("100", 24.0, HudAnchor::BottomLeft));
+        let bar = hud.add(HudWidget::progress_bar(
+            1.0,
+            (200.0, 16.0),
+            HudAnchor::Bottom,
+        ));
+        assert!(hud.take_changed());
+        assert!(!hud.take_changed());
+
+        hud.set_text(label, "100");
+        hud.set_progress(bar, 1.0);
+        hud.set_visible(bar, true);
+        // wrong widget kind
+        hud.set_progress(label, 0.5);
+        assert!(!hud.take_changed());
+
+        hud.set_progress(bar, 0.5);
+        assert!(hud.take_changed());
+        hud.set_visible(label, false);
+        assert!(hud.take_changed());
+        assert_eq!(hud.visible_widgets(HudAnchor::BottomLeft).count(), 0);
+
+        hud.remove(label);
+        assert!(hud.get(label).is_none());
+        assert!(hud.take_changed());
+    }
+}
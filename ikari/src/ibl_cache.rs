@@ -0,0 +1,395 @@
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use twox_hash::XxHash64;
+
+use crate::renderer::{BaseRenderer, RendererConfig};
+use crate::texture::{RawImage, RawImageSlice, Texture};
+
+/// bump it when the generation of the maps changes so the old files aren't loaded anymore
+const IBL_CACHE_VERSION: u32 = 1;
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n',
+];
+const KTX2_HEADER_LENGTH: usize = 80;
+const VK_FORMAT_R16G16_SFLOAT: u32 = 83;
+const VK_FORMAT_R16G16B16A16_SFLOAT: u32 = 97;
+
+/// The diffuse and specular environment maps and the BRDF LUT saved as KTX2 files in
+/// RendererConfig::ibl_cache_folder so they're only generated on the first run. The
+/// environment maps are keyed by a hash of the source HDR image
+#[derive(Debug, Clone)]
+pub struct IblCache {
+    folder: PathBuf,
+}
+
+impl IblCache {
+    /// None when there's no cache folder configured or on the web, which has no file system
+    pub fn from_config(config: &RendererConfig) -> Option<Self> {
+        if cfg!(target_arch = "wasm32") {
+            return None;
+        }
+        config
+            .ibl_cache_folder
+            .clone()
+            .map(|folder| Self { folder })
+    }
+
+    pub fn source_key(source_image: &[u8]) -> String {
+        let mut hasher = XxHash64::with_seed(0);
+        hasher.write(source_image);
+        hasher.write_u32(IBL_CACHE_VERSION);
+        format!("{:016x}", hasher.finish())
+    }
+
+    pub fn load_env_maps(
+        &self,
+        base_renderer: &BaseRenderer,
+        key: &str,
+    ) -> Option<(Texture, Texture)> {
+        let diffuse = self.load(&format!("diffuse_env_map_{key}"))?;
+        let specular = self.load(&format!("specular_env_map_{key}"))?;
+        Some((
+            Texture::create_cubemap(
+                base_renderer,
+                diffuse.0.slice(),
+                Some("diffuse env map"),
+                diffuse.1,
+            ),
+            Texture::create_cubemap(
+                base_renderer,
+                specular.0.slice(),
+                Some("specular env map"),
+                specular.1,
+            ),
+        ))
+    }
+
+    pub fn save_env_maps(
+        &self,
+        base_renderer: &BaseRenderer,
+        key: &str,
+        (diffuse, specular): &(Texture, Texture),
+    ) {
+        self.save(base_renderer, &format!("diffuse_env_map_{key}"), diffuse);
+        self.save(base_renderer, &format!("specular_env_map_{key}"), specular);
+    }
+
+    pub fn load_brdf_lut(&self, base_renderer: &BaseRenderer) -> Option<Texture> {
+        let (image, _) = self.load(&format!("brdf_lut_{IBL_CACHE_VERSION}"))?;
+        Some(Texture::create_brdf_lut_from_image(
+            base_renderer,
+            image.slice(),
+        ))
+    }
+
+    pub fn save_brdf_lut(&self, base_renderer: &BaseRenderer, brdf_lut: &Texture) {
+        self.save(
+            base_renderer,
+            &format!("brdf_lut_{IBL_CACHE_VERSION}"),
+            brdf_lut,
+        );
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.folder.join(format!("{name}.ktx2"))
+    }
+
+    /// None when the file isn't there, the errors are logged
+    fn load(&self, name: &str) -> Option<(RawImage, wgpu::TextureFormat)> {
+        let path = self.path(name);
+        let bytes = std::fs::read(&path).ok()?;
+        match decode_ktx2(&bytes) {
+            Ok(image) => {
+                log::debug!("Loaded {} from the ibl cache", path.display());
+                Some(image)
+            }
+            Err(err) => {
+                log::warn!(
+                    "Failed to load {} from the ibl cache: {err}",
+                    path.display()
+                );
+                None
+            }
+        }
+    }
+
+    /// The errors are logged since the maps can still be used without the cache
+    fn save(&self, base_renderer: &BaseRenderer, name: &str, texture: &Texture) {
+        let path = self.path(name);
+        if let Err(err) = self.write(base_renderer, &path, texture) {
+            log::warn!("Failed to save {} to the ibl cache: {err}", path.display());
+        }
+    }
+
+    /// Reads the texture back from the gpu, which needs the COPY_SRC usage
+    fn write(&self, base_renderer: &BaseRenderer, path: &Path, texture: &Texture) -> Result<()> {
+        let layers = pollster::block_on(texture.to_bytes(base_renderer))?;
+        let image = RawImage {
+            width: texture.size.width,
+            height: texture.size.height,
+            depth: layers.len() as u32,
+            mip_count: texture.texture.mip_level_count(),
+            raw: layers.concat(),
+        };
+        std::fs::create_dir_all(&self.folder)?;
+        std::fs::write(path, encode_ktx2(image.slice(), texture.texture.format())?)?;
+        Ok(())
+    }
+}
+
+fn vk_format(format: wgpu::TextureFormat) -> Result<(u32, u32)> {
+    Ok(match format {
+        wgpu::TextureFormat::Rg16Float => (VK_FORMAT_R16G16_SFLOAT, 2),
+        wgpu::TextureFormat::Rgba16Float => (VK_FORMAT_R16G16B16A16_SFLOAT, 4),
+        _ => bail!("Unsupported ktx2 format {format:?}"),
+    })
+}
+
+fn align(offset: usize, alignment: usize) -> usize {
+    (offset + alignment - 1) / alignment * alignment
+}
+
+fn mip_level_length(
+    image_width: u32,
+    image_height: u32,
+    mip_level: u32,
+    bytes_per_pixel: u32,
+) -> usize {
+    ((image_width >> mip_level).max(1) * (image_height >> mip_level).max(1) * bytes_per_pixel)
+        as usize
+}
+
+/// An uncompressed half float texture in a KTX2 container, 6 layers make a cubemap. The raw
+/// image is layer major, like wgpu::util::TextureDataOrder::LayerMajor
+pub fn encode_ktx2(image: RawImageSlice, format: wgpu::TextureFormat) -> Result<Vec<u8>> {
+    let (vk_format, channel_count) = vk_format(format)?;
+    let bytes_per_pixel = 2 * channel_count;
+    let face_count = image.depth;
+    if face_count != 1 && face_count != 6 {
+        bail!("Only 2d textures and cubemaps can be saved as ktx2");
+    }
+    let level_count = image.mip_count.max(1);
+    let level_lengths: Vec<_> = (0..level_count)
+        .map(|mip_level| mip_level_length(image.width, image.height, mip_level, bytes_per_pixel))
+        .collect();
+    let layer_length: usize = level_lengths.iter().sum();
+    if image.raw.len() != layer_length * face_count as usize {
+        bail!("The raw image doesn't match its size");
+    }
+
+    // data format descriptor with a basic block, one sample per channel
+    let mut dfd = vec![];
+    let dfd_block_length = 24 + 16 * channel_count;
+    dfd.extend_from_slice(&(4 + dfd_block_length).to_le_bytes());
+    dfd.extend_from_slice(&0u32.to_le_bytes()); // vendor id and descriptor type
+    dfd.extend_from_slice(&2u16.to_le_bytes()); // version
+    dfd.extend_from_slice(&(dfd_block_length as u16).to_le_bytes());
+    // rgbsda color model, bt709 primaries, linear transfer function, straight alpha
+    dfd.extend_from_slice(&[1, 1, 1, 0]);
+    dfd.extend_from_slice(&[0; 4]); // texel block dimensions
+    dfd.extend_from_slice(&[bytes_per_pixel as u8, 0, 0, 0, 0, 0, 0, 0]);
+    for (channel_index, channel_id) in [0u8, 1, 2, 15]
+        .into_iter()
+        .take(channel_count as usize)
+        .enumerate()
+    {
+        dfd.extend_from_slice(&(channel_index as u16 * 16).to_le_bytes());
+        dfd.push(15); // bit length - 1
+        dfd.push(channel_id | 0x80 | 0x40); // float and signed
+        dfd.extend_from_slice(&[0; 4]); // sample position
+        dfd.extend_from_slice(&(-1.0f32).to_bits().to_le_bytes());
+        dfd.extend_from_slice(&1.0f32.to_bits().to_le_bytes());
+    }
+
+    let dfd_offset = KTX2_HEADER_LENGTH + 24 * level_count as usize;
+    let alignment = bytes_per_pixel as usize;
+    let mut data_offset = align(dfd_offset + dfd.len(), alignment);
+    // the smallest mip levels come first in the file
+    let mut level_offsets = vec![0; level_count as usize];
+    for mip_level in (0..level_count as usize).rev() {
+        level_offsets[mip_level] = data_offset;
+        data_offset = align(
+            data_offset + level_lengths[mip_level] * face_count as usize,
+            alignment,
+        );
+    }
+
+    let mut bytes = Vec::with_capacity(data_offset);
+    bytes.extend_from_slice(&KTX2_IDENTIFIER);
+    for value in [
+        vk_format,
+        2, // type size
+        image.width,
+        image.height,
+        0, // depth
+        0, // layer count
+        face_count,
+        level_count,
+        0, // supercompression scheme
+        dfd_offset as u32,
+        dfd.len() as u32,
+        0, // key/value data offset
+        0, // key/value data length
+    ] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes.extend_from_slice(&[0; 16]); // supercompression global data
+    for mip_level in 0..level_count as usize {
+        let level_length = (level_lengths[mip_level] * face_count as usize) as u64;
+        bytes.extend_from_slice(&(level_offsets[mip_level] as u64).to_le_bytes());
+        bytes.extend_from_slice(&level_length.to_le_bytes());
+        bytes.extend_from_slice(&level_length.to_le_bytes());
+    }
+    bytes.extend_from_slice(&dfd);
+
+    for mip_level in (0..level_count as usize).rev() {
+        bytes.resize(level_offsets[mip_level], 0);
+        let level_start: usize = level_lengths[..mip_level].iter().sum();
+        for face_index in 0..face_count as usize {
+            let start = face_index * layer_length + level_start;
+            bytes.extend_from_slice(&image.raw[start..start + level_lengths[mip_level]]);
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Reads the files written by encode_ktx2
+pub fn decode_ktx2(bytes: &[u8]) -> Result<(RawImage, wgpu::TextureFormat)> {
+    let read_u32 = |offset: usize| -> Result<u32> {
+        match bytes.get(offset..offset + 4) {
+            Some(value) => Ok(u32::from_le_bytes(value.try_into()?)),
+            None => bail!("Truncated ktx2 file"),
+        }
+    };
+    let read_u64 = |offset: usize| -> Result<usize> {
+        match bytes.get(offset..offset + 8) {
+            Some(value) => Ok(u64::from_le_bytes(value.try_into()?) as usize),
+            None => bail!("Truncated ktx2 file"),
+        }
+    };
+
+    if !bytes.starts_with(&KTX2_IDENTIFIER) {
+        bail!("Not a ktx2 file");
+    }
+    let format = match read_u32(12)? {
+        VK_FORMAT_R16G16_SFLOAT => wgpu::TextureFormat::Rg16Float,
+        VK_FORMAT_R16G16B16A16_SFLOAT => wgpu::TextureFormat::Rgba16Float,
+        vk_format => bail!("Unsupported ktx2 vk format {vk_format}"),
+    };
+    let bytes_per_pixel = format.block_copy_size(None).unwrap_or(1);
+    let width = read_u32(20)?;
+    let height = read_u32(24)?;
+    let face_count = read_u32(36)?;
+    let level_count = read_u32(40)?.max(1);
+    if read_u32(44)? != 0 {
+        bail!("Supercompressed ktx2 files aren't supported");
+    }
+
+    // the file could be corrupted, nothing is allocated before the sizes are checked
+    // against its length
+    if face_count != 1 && face_count != 6 {
+        bail!("Unsupported ktx2 face count {face_count}");
+    }
+    if width == 0 || height == 0 {
+        bail!("Empty ktx2 image");
+    }
+    let max_level_count = u32::BITS - width.max(height).leading_zeros();
+    if level_count > max_level_count {
+        bail!("Too many mip levels ({level_count}) in ktx2 file of {width}x{height}");
+    }
+    if KTX2_HEADER_LENGTH + 24 * level_count as usize > bytes.len() {
+        bail!("Truncated ktx2 file");
+    }
+    let checked_level_length = |mip_level: u32| -> Option<usize> {
+        ((width >> mip_level).max(1) as usize)
+            .checked_mul((height >> mip_level).max(1) as usize)?
+            .checked_mul(bytes_per_pixel as usize)
+    };
+    let raw_length = (0..level_count)
+        .try_fold(0usize, |sum, mip_level| {
+            sum.checked_add(checked_level_length(mip_level)?)
+        })
+        .and_then(|layer_length| layer_length.checked_mul(face_count as usize))
+        .filter(|raw_length| *raw_length <= bytes.len());
+    let Some(raw_length) = raw_length else {
+        bail!("Truncated ktx2 file");
+    };
+
+    let layer_length = raw_length / face_count as usize;
+    let mut raw = vec![0; raw_length];
+    let mut level_start = 0;
+    for mip_level in 0..level_count {
+        // can't overflow, the total was checked above
+        let level_length = checked_level_length(mip_level).unwrap();
+        let level_offset = read_u64(KTX2_HEADER_LENGTH + 24 * mip_level as usize)?;
+        for face_index in 0..face_count as usize {
+            let face = level_offset
+                .checked_add(face_index * level_length)
+                .and_then(|start| bytes.get(start..start.checked_add(level_length)?));
+            let Some(face) = face else {
+                bail!("Truncated ktx2 file");
+            };
+            let raw_start = face_index * layer_length + level_start;
+            raw[raw_start..raw_start + level_length].copy_from_slice(face);
+        }
+        level_start += level_length;
+    }
+
+    Ok((
+        RawImage {
+            width,
+            height,
+            depth: face_count,
+            mip_count: level_count,
+            raw,
+        },
+        format,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ktx2_cubemaps_round_trip() {
+        // 4x4 then 2x2 and 1x1 mips
+        let layer_length = (16 + 4 + 1) * 8;
+        let image = RawImage {
+            width: 4,
+            height: 4,
+            depth: 6,
+            mip_count: 3,
+            raw: (0..6 * layer_length).map(|i| (i % 251) as u8).collect(),
+        };
+
+        let bytes = encode_ktx2(image.slice(), wgpu::TextureFormat::Rgba16Float).unwrap();
+        assert!(bytes.starts_with(&KTX2_IDENTIFIER));
+        let (decoded, format) = decode_ktx2(&bytes).unwrap();
+
+        assert_eq!(format, wgpu::TextureFormat::Rgba16Float);
+        assert_eq!(
+            (
+                decoded.width,
+                decoded.height,
+                decoded.depth,
+                decoded.mip_count
+            ),
+            (4, 4, 6, 3)
+        );
+        assert_eq!(decoded.raw, image.raw);
+
+        assert!(decode_ktx2(&bytes[..bytes.len() - 1]).is_err());
+        let mut corrupted = bytes.clone();
+        corrupted[36..40].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(decode_ktx2(&corrupted).is_err());
+        let mut corrupted = bytes.clone();
+        corrupted[20..24].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(decode_ktx2(&corrupted).is_err());
+        assert_ne!(IblCache::source_key(&[1, 2]), IblCache::source_key(&[2, 1]));
+    }
+}
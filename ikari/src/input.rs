@@ -0,0 +1,445 @@
+use crate::file_manager::{FileManager, GameFilePath};
+use crate::game_state_stack::InputContext;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use winit::event::{ElementState, MouseButton, WindowEvent};
+use winit::keyboard::Key;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MouseBinding {
+    Left,
+    Right,
+    Middle,
+    Back,
+    Forward,
+    Other(u16),
+}
+
+impl From<MouseButton> for MouseBinding {
+    fn from(button: MouseButton) -> Self {
+        match button {
+            MouseButton::Left => MouseBinding::Left,
+            MouseButton::Right => MouseBinding::Right,
+            MouseButton::Middle => MouseBinding::Middle,
+            MouseButton::Back => MouseBinding::Back,
+            MouseButton::Forward => MouseBinding::Forward,
+            MouseButton::Other(other) => MouseBinding::Other(other),
+        }
+    }
+}
+
+/// Named after the position of the buttons on the controller
+/// so the bindings work the same for all layouts
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadButton {
+    South,
+    East,
+    West,
+    North,
+    LeftBumper,
+    RightBumper,
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+/// An input that can be bound to an action
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum InputBinding {
+    /// a character in lowercase, e.g. "w", or the name of a winit NamedKey, e.g. "Space"
+    Key(String),
+    Mouse(MouseBinding),
+    GamepadButton(GamepadButton),
+}
+
+impl InputBinding {
+    /// characters are lowercased, named keys are kept as they are, e.g. "ArrowUp"
+    pub fn key(name: &str) -> Self {
+        if name.chars().count() == 1 {
+            InputBinding::Key(name.to_lowercase())
+        } else {
+            InputBinding::Key(name.to_string())
+        }
+    }
+
+    /// None for the keys that can't be bound, e.g. dead keys
+    pub fn from_key(key: &Key) -> Option<Self> {
+        match key {
+            Key::Character(character) => Some(InputBinding::Key(character.to_lowercase())),
+            Key::Named(named_key) => Some(InputBinding::Key(format!("{named_key:?}"))),
+            _ => None,
+        }
+    }
+}
+
+/// The value of an axis is 1 while one of the positive bindings is held, -1 for the negative
+/// ones, or the value of the gamepad axis if no button is held
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AxisBinding {
+    pub positive: Vec<InputBinding>,
+    pub negative: Vec<InputBinding>,
+    pub gamepad_axis: Option<GamepadAxis>,
+}
+
+/// The bindings of one InputContext, by action or axis name
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BindingSet {
+    pub actions: BTreeMap<String, Vec<InputBinding>>,
+    pub axes: BTreeMap<String, AxisBinding>,
+}
+
+impl BindingSet {
+    pub fn action(mut self, action: &str, bindings: Vec<InputBinding>) -> Self {
+        self.actions.insert(action.to_string(), bindings);
+        self
+    }
+
+    pub fn axis(mut self, axis: &str, binding: AxisBinding) -> Self {
+        self.axes.insert(axis.to_string(), binding);
+        self
+    }
+
+    /// adds the other set's bindings, replacing the ones with the same name
+    pub fn merge(mut self, other: BindingSet) -> Self {
+        self.actions.extend(other.actions);
+        self.axes.extend(other.axes);
+        self
+    }
+}
+
+/// All the bindings of the game, saved as a RON config file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct InputBindings {
+    pub contexts: BTreeMap<InputContext, BindingSet>,
+}
+
+impl InputBindings {
+    pub fn context(mut self, context: InputContext, bindings: BindingSet) -> Self {
+        self.contexts.insert(context, bindings);
+        self
+    }
+
+    pub async fn load(path: &GameFilePath) -> Result<Self> {
+        let text = FileManager::read_to_string(path).await?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        Ok(ron::from_str(text)?)
+    }
+
+    pub fn to_ron_string(&self) -> Result<String> {
+        Ok(ron::ser::to_string_pretty(
+            self,
+            ron::ser::PrettyConfig::default(),
+        )?)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: &GameFilePath) -> Result<()> {
+        std::fs::write(path.resolve(), self.to_ron_string()?)?;
+        Ok(())
+    }
+}
+
+/// An action whose binding was pressed or released by a window event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActionEvent {
+    pub action: String,
+    pub is_pressed: bool,
+}
+
+/// Tracks the state of the bound inputs and maps them to the actions and axes of the current
+/// context. Actions can either be polled, e.g. with is_action_pressed, or handled as they come
+/// from process_window_event. Gamepad input is fed with set_gamepad_button/set_gamepad_axis
+#[derive(Debug)]
+pub struct InputMap {
+    bindings: InputBindings,
+    context: InputContext,
+    pressed: HashSet<InputBinding>,
+    just_pressed: HashSet<InputBinding>,
+    just_released: HashSet<InputBinding>,
+    gamepad_axes: HashMap<GamepadAxis, f32>,
+    /// the next binding that is pressed gets bound to this action
+    pending_rebinding: Option<(InputContext, String)>,
+}
+
+impl InputMap {
+    pub fn new(bindings: InputBindings, context: InputContext) -> Self {
+        Self {
+            bindings,
+            context,
+            pressed: HashSet::new(),
+            just_pressed: HashSet::new(),
+            just_released: HashSet::new(),
+            gamepad_axes: HashMap::new(),
+            pending_rebinding: None,
+        }
+    }
+
+    pub fn bindings(&self) -> &InputBindings {
+        &self.bindings
+    }
+
+    pub fn context(&self) -> InputContext {
+        self.context
+    }
+
+    pub fn set_context(&mut self, context: InputContext) {
+        self.context = context;
+    }
+
+    /// Replaces the bindings of the action, e.g. from a controls menu
+    pub fn rebind(&mut self, context: InputContext, action: &str, bindings: Vec<InputBinding>) {
+        self.bindings
+            .contexts
+            .entry(context)
+            .or_default()
+            .actions
+            .insert(action.to_string(), bindings);
+    }
+
+    /// The next key, mouse button or gamepad button that is pressed replaces the bindings of
+    /// the action instead of triggering anything
+    pub fn start_rebinding(&mut self, context: InputContext, action: &str) {
+        self.pending_rebinding = Some((context, action.to_string()));
+    }
+
+    pub fn cancel_rebinding(&mut self) {
+        self.pending_rebinding = None;
+    }
+
+    pub fn is_rebinding(&self) -> bool {
+        self.pending_rebinding.is_some()
+    }
+
+    /// returns the actions of the current context that were pressed or released by the event
+    pub fn process_window_event(&mut self, event: &WindowEvent) -> Vec<ActionEvent> {
+        match event {
+            WindowEvent::KeyboardInput { event, .. } if !event.repeat => {
+                match InputBinding::from_key(&event.logical_key) {
+                    Some(binding) => {
+                        self.set_binding_state(binding, event.state == ElementState::Pressed)
+                    }
+                    None => vec![],
+                }
+            }
+            WindowEvent::MouseInput { state, button, .. } => self.set_binding_state(
+                InputBinding::Mouse((*button).into()),
+                *state == ElementState::Pressed,
+            ),
+            WindowEvent::Focused(false) => {
+                // the release events would be missed while the window isn't focused
+                self.release_all();
+                vec![]
+            }
+            _ => vec![],
+        }
+    }
+
+    pub fn set_gamepad_button(
+        &mut self,
+        button: GamepadButton,
+        is_pressed: bool,
+    ) -> Vec<ActionEvent> {
+        self.set_binding_state(InputBinding::GamepadButton(button), is_pressed)
+    }
+
+    /// value in -1..1, or 0..1 for the triggers
+    pub fn set_gamepad_axis(&mut self, axis: GamepadAxis, value: f32) {
+        self.gamepad_axes.insert(axis, value);
+    }
+
+    fn set_binding_state(&mut self, binding: InputBinding, is_pressed: bool) -> Vec<ActionEvent> {
+        if is_pressed {
+            if let Some((context, action)) = self.pending_rebinding.take() {
+                self.rebind(context, &action, vec![binding]);
+                return vec![];
+            }
+            if !self.pressed.insert(binding.clone()) {
+                return vec![];
+            }
+            self.just_pressed.insert(binding.clone());
+        } else {
+            if !self.pressed.remove(&binding) {
+                return vec![];
+            }
+            self.just_released.insert(binding.clone());
+        }
+
+        self.current_bindings()
+            .map(|current_bindings| {
+                current_bindings
+                    .actions
+                    .iter()
+                    .filter(|(_, bindings)| bindings.contains(&binding))
+                    .map(|(action, _)| ActionEvent {
+                        action: action.clone(),
+                        is_pressed,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn release_all(&mut self) {
+        self.just_released.extend(self.pressed.drain());
+        self.gamepad_axes.clear();
+    }
+
+    /// e.g. when the gamepad is unplugged
+    pub fn release_gamepad(&mut self) {
+        let gamepad_buttons: Vec<_> = self
+            .pressed
+            .iter()
+            .filter(|binding| matches!(binding, InputBinding::GamepadButton(_)))
+            .cloned()
+            .collect();
+        for binding in gamepad_buttons {
+            self.pressed.remove(&binding);
+            self.just_released.insert(binding);
+        }
+        self.gamepad_axes.clear();
+    }
+
+    fn current_bindings(&self) -> Option<&BindingSet> {
+        self.bindings.contexts.get(&self.context)
+    }
+
+    fn action_bindings(&self, action: &str) -> &[InputBinding] {
+        self.current_bindings()
+            .and_then(|current_bindings| current_bindings.actions.get(action))
+            .map(|bindings| bindings.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn is_action_pressed(&self, action: &str) -> bool {
+        self.action_bindings(action)
+            .iter()
+            .any(|binding| self.pressed.contains(binding))
+    }
+
+    /// since the last call to end_frame
+    pub fn was_action_just_pressed(&self, action: &str) -> bool {
+        self.action_bindings(action)
+            .iter()
+            .any(|binding| self.just_pressed.contains(binding))
+    }
+
+    /// since the last call to end_frame
+    pub fn was_action_just_released(&self, action: &str) -> bool {
+        self.action_bindings(action)
+            .iter()
+            .any(|binding| self.just_released.contains(binding))
+    }
+
+    /// in -1..1, 0 if the axis isn't bound in the current context
+    pub fn axis_value(&self, axis: &str) -> f32 {
+        let Some(axis_binding) = self
+            .current_bindings()
+            .and_then(|current_bindings| current_bindings.axes.get(axis))
+        else {
+            return 0.0;
+        };
+        let is_any_pressed = |bindings: &[InputBinding]| {
+            bindings
+                .iter()
+                .any(|binding| self.pressed.contains(binding))
+        };
+        let button_value = is_any_pressed(&axis_binding.positive) as i32 as f32
+            - is_any_pressed(&axis_binding.negative) as i32 as f32;
+        if button_value != 0.0 {
+            return button_value;
+        }
+        axis_binding
+            .gamepad_axis
+            .and_then(|gamepad_axis| self.gamepad_axes.get(&gamepad_axis))
+            .map_or(0.0, |value| value.clamp(-1.0, 1.0))
+    }
+
+    /// Clears the just pressed/released state, should be called at the end of each frame
+    pub fn end_frame(&mut self) {
+        self.just_pressed.clear();
+        self.just_released.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_input_map() -> InputMap {
+        InputMap::new(
+            InputBindings::default().context(
+                InputContext::Gameplay,
+                BindingSet::default()
+                    .action("jump", vec![InputBinding::key("Space")])
+                    .axis(
+                        "move_x",
+                        AxisBinding {
+                            positive: vec![InputBinding::key("d")],
+                            negative: vec![InputBinding::key("a")],
+                            gamepad_axis: Some(GamepadAxis::LeftStickX),
+                        },
+                    ),
+            ),
+            InputContext::Gameplay,
+        )
+    }
+
+    #[test]
+    fn actions_only_trigger_in_their_context() {
+        let mut input_map = test_input_map();
+        input_map.set_context(InputContext::Menu);
+        assert!(input_map
+            .set_binding_state(InputBinding::key("Space"), true)
+            .is_empty());
+        assert!(!input_map.is_action_pressed("jump"));
+
+        input_map.set_context(InputContext::Gameplay);
+        assert!(input_map.is_action_pressed("jump"));
+        assert!(input_map.was_action_just_pressed("jump"));
+        input_map.end_frame();
+        assert!(!input_map.was_action_just_pressed("jump"));
+    }
+
+    #[test]
+    fn rebinding_uses_the_next_pressed_input() {
+        let mut input_map = test_input_map();
+        input_map.start_rebinding(InputContext::Gameplay, "jump");
+        input_map.set_binding_state(InputBinding::GamepadButton(GamepadButton::South), true);
+        assert!(!input_map.is_rebinding());
+        assert!(!input_map.is_action_pressed("jump"));
+
+        let events = input_map.set_gamepad_button(GamepadButton::South, false);
+        assert!(events.is_empty());
+        input_map.set_gamepad_button(GamepadButton::South, true);
+        assert!(input_map.is_action_pressed("jump"));
+
+        let bindings =
+            InputBindings::parse(&input_map.bindings().to_ron_string().unwrap()).unwrap();
+        assert_eq!(&bindings, input_map.bindings());
+    }
+}
@@ -0,0 +1,69 @@
+use crate::wasm_not_sync::{WasmNotSend, WasmNotSync};
+
+/// Below this many items the per-node work of a frame is cheaper to do on the calling thread
+/// than to split across the workers
+pub const MIN_PARALLEL_LEN: usize = 256;
+
+#[cfg(not(target_arch = "wasm32"))]
+lazy_static::lazy_static! {
+    static ref THREAD_POOL: rayon::ThreadPool = rayon::ThreadPoolBuilder::new()
+        .thread_name(|index| format!("ikari job worker {index}"))
+        .start_handler(|index| {
+            profiling::register_thread!(&format!("Job worker {index}"));
+        })
+        .build()
+        .expect("Failed to create the job thread pool");
+}
+
+/// Maps the items on the job workers, keeping their order. The items are split in chunks of at
+/// least min_len and it all runs on the calling thread if there aren't enough for two chunks.
+/// Always runs on the calling thread on the web, where the main thread isn't allowed to block
+/// on the workers
+pub fn par_map<T, R, F>(items: &[T], min_len: usize, f: F) -> Vec<R>
+where
+    T: WasmNotSync,
+    R: WasmNotSend,
+    F: Fn(usize, &T) -> R + WasmNotSync + WasmNotSend,
+{
+    #[cfg(not(target_arch = "wasm32"))]
+    if items.len() >= min_len.max(1) * 2 {
+        use rayon::prelude::*;
+
+        return THREAD_POOL.install(|| {
+            items
+                .par_iter()
+                .enumerate()
+                .with_min_len(min_len)
+                .map(|(index, item)| f(index, item))
+                .collect()
+        });
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    let _ = min_len;
+    items
+        .iter()
+        .enumerate()
+        .map(|(index, item)| f(index, item))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn par_map_keeps_the_order() {
+        let items: Vec<usize> = (0..10_000).collect();
+        let doubled = par_map(&items, 16, |index, item| {
+            assert_eq!(index, *item);
+            item * 2
+        });
+        assert_eq!(
+            doubled,
+            items.iter().map(|item| item * 2).collect::<Vec<_>>()
+        );
+
+        assert_eq!(par_map(&items[..3], 16, |_, item| *item), vec![0, 1, 2]);
+    }
+}
@@ -0,0 +1,326 @@
+use crate::ragdoll::transform_to_isometry;
+use crate::transform::TransformBuilder;
+
+use glam::f32::{Quat, Vec3};
+use rapier3d_f64::prelude::*;
+
+/// rapier's default of 4 lets long chains of bodies, e.g. ragdolls, stretch and jitter.
+/// PhysicsState::add_joint raises IntegrationParameters::max_velocity_iterations to it
+pub const MIN_JOINT_VELOCITY_ITERATIONS: usize = 8;
+
+/// What the joint lets the second body do relative to the first one
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum JointKind {
+    /// welds the bodies together
+    Fixed,
+    /// rotation around the axis, e.g. doors or wheels. The limits are in radians
+    Hinge { axis: Vec3 },
+    /// free rotation around the anchor, e.g. shoulders. The limits are in radians and apply to
+    /// the rotation around each axis
+    BallSocket,
+    /// translation along the axis, e.g. pistons or sliding doors. The limits are in world units
+    Prismatic { axis: Vec3 },
+    /// keeps the anchors of the two bodies at most max_distance apart, e.g. ropes or chains.
+    /// They can get closer to each other
+    Distance { max_distance: f32 },
+}
+
+/// Drives the free axis of a hinge or prismatic joint
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum JointMotorTarget {
+    /// in radians or units per second
+    Velocity { velocity: f32, factor: f32 },
+    /// a spring pulling towards the position, relative to where the joint was made
+    Position {
+        position: f32,
+        stiffness: f32,
+        damping: f32,
+    },
+}
+
+/// Describes a joint between two rigid bodies in world space. The joint is made from the poses
+/// the bodies have when it's added, which are its rest pose: the limits and motor positions
+/// are relative to it, so the joint starts out satisfied instead of yanking the bodies in place.
+/// The two bodies don't collide with each other unless contacts_enabled is set, their
+/// colliders often overlap around the anchor
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct JointBuilder {
+    kind: JointKind,
+    anchor1: Option<Vec3>,
+    anchor2: Option<Vec3>,
+    limits: Option<[f32; 2]>,
+    motor: Option<JointMotorTarget>,
+    max_motor_force: Option<f32>,
+    contacts_enabled: bool,
+}
+
+impl JointBuilder {
+    pub fn new(kind: JointKind) -> Self {
+        Self {
+            kind,
+            anchor1: None,
+            anchor2: None,
+            limits: None,
+            motor: None,
+            max_motor_force: None,
+            contacts_enabled: false,
+        }
+    }
+
+    pub fn fixed() -> Self {
+        Self::new(JointKind::Fixed)
+    }
+
+    pub fn hinge(axis: Vec3) -> Self {
+        Self::new(JointKind::Hinge { axis })
+    }
+
+    pub fn ball_socket() -> Self {
+        Self::new(JointKind::BallSocket)
+    }
+
+    pub fn prismatic(axis: Vec3) -> Self {
+        Self::new(JointKind::Prismatic { axis })
+    }
+
+    pub fn distance(max_distance: f32) -> Self {
+        Self::new(JointKind::Distance { max_distance })
+    }
+
+    /// In world space, defaults to the origin of the second body
+    pub fn anchor(mut self, anchor: Vec3) -> Self {
+        self.anchor1 = Some(anchor);
+        self.anchor2 = Some(anchor);
+        self
+    }
+
+    /// The attachment points on each body for distance joints, in world space.
+    /// Default to the origins of the bodies
+    pub fn anchors(mut self, anchor1: Vec3, anchor2: Vec3) -> Self {
+        self.anchor1 = Some(anchor1);
+        self.anchor2 = Some(anchor2);
+        self
+    }
+
+    /// [min, max], ignored by fixed and distance joints
+    pub fn limits(mut self, limits: [f32; 2]) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    /// only hinge and prismatic joints have a motor
+    pub fn motor(mut self, motor: JointMotorTarget) -> Self {
+        self.motor = Some(motor);
+        self
+    }
+
+    pub fn max_motor_force(mut self, max_motor_force: f32) -> Self {
+        self.max_motor_force = Some(max_motor_force);
+        self
+    }
+
+    pub fn contacts_enabled(mut self, contacts_enabled: bool) -> Self {
+        self.contacts_enabled = contacts_enabled;
+        self
+    }
+
+    pub fn kind(&self) -> JointKind {
+        self.kind
+    }
+
+    pub fn build(
+        &self,
+        body1_position: &Isometry<Real>,
+        body2_position: &Isometry<Real>,
+    ) -> GenericJoint {
+        let body2_origin = Vec3::new(
+            body2_position.translation.x as f32,
+            body2_position.translation.y as f32,
+            body2_position.translation.z as f32,
+        );
+        let (locked_axes, axis) = match self.kind {
+            JointKind::Fixed => (JointAxesMask::LOCKED_FIXED_AXES, Vec3::X),
+            JointKind::Hinge { axis } => (JointAxesMask::LOCKED_REVOLUTE_AXES, axis),
+            JointKind::BallSocket => (JointAxesMask::LOCKED_SPHERICAL_AXES, Vec3::X),
+            JointKind::Prismatic { axis } => (JointAxesMask::LOCKED_PRISMATIC_AXES, axis),
+            JointKind::Distance { .. } => (JointAxesMask::FREE_FIXED_AXES, Vec3::X),
+        };
+
+        // the joint's x axis is its free axis, the frames of both bodies line up at the start
+        let rotation = Quat::from_rotation_arc(Vec3::X, axis.try_normalize().unwrap_or(Vec3::X));
+        let world_frame = |anchor: Vec3| {
+            transform_to_isometry(
+                &TransformBuilder::new()
+                    .position(anchor)
+                    .rotation(rotation)
+                    .build(),
+            )
+        };
+        let (anchor1, anchor2) = match self.kind {
+            JointKind::Distance { .. } => {
+                let body1_origin = Vec3::new(
+                    body1_position.translation.x as f32,
+                    body1_position.translation.y as f32,
+                    body1_position.translation.z as f32,
+                );
+                (
+                    self.anchor1.unwrap_or(body1_origin),
+                    self.anchor2.unwrap_or(body2_origin),
+                )
+            }
+            _ => {
+                let anchor = self.anchor2.unwrap_or(body2_origin);
+                (anchor, anchor)
+            }
+        };
+
+        let mut joint = GenericJoint::new(locked_axes);
+        joint
+            .set_local_frame1(body1_position.inverse() * world_frame(anchor1))
+            .set_local_frame2(body2_position.inverse() * world_frame(anchor2))
+            .set_contacts_enabled(self.contacts_enabled);
+
+        match self.kind {
+            JointKind::Hinge { .. } | JointKind::Prismatic { .. } => {
+                let free_axis = motor_axis(&joint).expect("Hinges and prismatic joints have one");
+                if let Some(limits) = self.limits {
+                    joint.set_limits(free_axis, [limits[0] as f64, limits[1] as f64]);
+                }
+                set_motor(&mut joint, self.motor, self.max_motor_force);
+            }
+            JointKind::BallSocket => {
+                if let Some(limits) = self.limits {
+                    for axis in [JointAxis::AngX, JointAxis::AngY, JointAxis::AngZ] {
+                        joint.set_limits(axis, [limits[0] as f64, limits[1] as f64]);
+                    }
+                }
+            }
+            JointKind::Distance { max_distance } => {
+                // rapier limits the length of the vector made of the max limit of each
+                // coupled axis and ignores the min ones
+                let axis_max_distance = max_distance as f64 / 3.0f64.sqrt();
+                joint.coupled_axes = JointAxesMask::LIN_AXES;
+                for axis in [JointAxis::X, JointAxis::Y, JointAxis::Z] {
+                    joint.set_limits(axis, [0.0, axis_max_distance]);
+                }
+            }
+            JointKind::Fixed => {}
+        }
+
+        joint
+    }
+}
+
+/// The free axis of a hinge or prismatic joint
+pub fn motor_axis(joint: &GenericJoint) -> Option<JointAxis> {
+    if joint.locked_axes == JointAxesMask::LOCKED_REVOLUTE_AXES {
+        Some(JointAxis::AngX)
+    } else if joint.locked_axes == JointAxesMask::LOCKED_PRISMATIC_AXES {
+        Some(JointAxis::X)
+    } else {
+        None
+    }
+}
+
+/// None turns the motor off. Does nothing if the joint has no free axis to drive
+pub fn set_motor(
+    joint: &mut GenericJoint,
+    motor: Option<JointMotorTarget>,
+    max_motor_force: Option<f32>,
+) {
+    let Some(axis) = motor_axis(joint) else {
+        return;
+    };
+    match motor {
+        Some(JointMotorTarget::Velocity { velocity, factor }) => {
+            joint.set_motor_velocity(axis, velocity as f64, factor as f64);
+        }
+        Some(JointMotorTarget::Position {
+            position,
+            stiffness,
+            damping,
+        }) => {
+            joint.set_motor_position(axis, position as f64, stiffness as f64, damping as f64);
+        }
+        None => {
+            joint.motor_axes.remove(axis.into());
+            return;
+        }
+    }
+    if let Some(max_motor_force) = max_motor_force {
+        joint.set_motor_max_force(axis, max_motor_force as f64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::PhysicsState;
+
+    fn add_body(physics_state: &mut PhysicsState, body: RigidBodyBuilder) -> RigidBodyHandle {
+        let handle = physics_state.rigid_body_set.insert(body.build());
+        physics_state.collider_set.insert_with_parent(
+            ColliderBuilder::ball(0.25).build(),
+            handle,
+            &mut physics_state.rigid_body_set,
+        );
+        handle
+    }
+
+    fn body_origin(physics_state: &PhysicsState, handle: RigidBodyHandle) -> Vec3 {
+        let translation = physics_state.rigid_body_set[handle].translation();
+        Vec3::new(
+            translation.x as f32,
+            translation.y as f32,
+            translation.z as f32,
+        )
+    }
+
+    #[test]
+    fn hinged_bodies_swing_around_the_axis_within_the_limits() {
+        let mut physics_state = PhysicsState::new();
+        let support = add_body(&mut physics_state, RigidBodyBuilder::fixed());
+        // sticks out sideways so gravity swings it down around z
+        let arm = add_body(
+            &mut physics_state,
+            RigidBodyBuilder::dynamic().translation(vector![1.0, 0.0, 0.0]),
+        );
+        let max_angle = std::f32::consts::FRAC_PI_4;
+        physics_state.add_joint(
+            support,
+            arm,
+            &JointBuilder::hinge(Vec3::Z)
+                .anchor(Vec3::ZERO)
+                .limits([-max_angle, max_angle]),
+        );
+
+        for _ in 0..120 {
+            physics_state.step();
+        }
+
+        let arm_origin = body_origin(&physics_state, arm);
+        assert!(arm_origin.z.abs() < 0.01);
+        assert!(arm_origin.y < -0.5);
+        let angle = arm_origin.y.atan2(arm_origin.x);
+        assert!(angle >= -max_angle - 0.05, "{angle}");
+    }
+
+    #[test]
+    fn distance_joints_keep_the_bodies_in_range() {
+        let mut physics_state = PhysicsState::new();
+        let ceiling = add_body(&mut physics_state, RigidBodyBuilder::fixed());
+        let weight = add_body(
+            &mut physics_state,
+            RigidBodyBuilder::dynamic().translation(vector![0.5, -1.0, 0.0]),
+        );
+        physics_state.add_joint(ceiling, weight, &JointBuilder::distance(2.0));
+
+        for _ in 0..240 {
+            physics_state.step();
+        }
+
+        let distance = body_origin(&physics_state, weight).length();
+        assert!(distance <= 2.05, "{distance}");
+        assert!(distance > 1.5, "{distance}");
+    }
+}
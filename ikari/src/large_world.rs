@@ -0,0 +1,181 @@
+use crate::physics::PhysicsState;
+use crate::scene::{GameNodeId, Scene};
+
+use std::collections::HashMap;
+
+use glam::f32::Vec3;
+use glam::f64::DVec3;
+
+pub const DEFAULT_ORIGIN_SHIFT_DISTANCE: f32 = 1024.0;
+
+/// Sent through EngineState::events when the origin moved. The positions the game keeps
+/// outside of the scene and the physics bodies must be moved by the offset too
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct OriginShifted {
+    pub offset: Vec3,
+}
+
+/// The floating origin of worlds that are too big for f32 positions, shared with WorldStreamer.
+/// When enabled, gameloop moves the origin of the scene under the camera once the camera gets
+/// further than origin_shift_distance from it. The root nodes and the physics bodies are moved
+/// along, and the root nodes placed with set_position, which keep their position in f64, are
+/// put back at their exact offset from the new origin
+#[derive(Debug, Clone)]
+pub struct LargeWorld {
+    pub is_enabled: bool,
+    /// how far the camera can get from the origin before it's moved. The origin only moves
+    /// once in a while so the bodies aren't teleported every frame
+    pub origin_shift_distance: f32,
+    positions: HashMap<GameNodeId, DVec3>,
+    /// world position of the scene's origin
+    origin: DVec3,
+}
+
+impl Default for LargeWorld {
+    fn default() -> Self {
+        Self {
+            is_enabled: false,
+            origin_shift_distance: DEFAULT_ORIGIN_SHIFT_DISTANCE,
+            positions: HashMap::new(),
+            origin: DVec3::ZERO,
+        }
+    }
+}
+
+impl LargeWorld {
+    /// For root nodes. The position overwrites the translation of the node's transform
+    /// whenever the origin moves, so the node must be moved with this instead of through its
+    /// transform
+    pub fn set_position(&mut self, scene: &mut Scene, node_id: GameNodeId, position: DVec3) {
+        if let Some(node) = scene.get_node_mut(node_id) {
+            node.transform
+                .set_position((position - self.origin).as_vec3());
+            self.positions.insert(node_id, position);
+        }
+    }
+
+    /// The f64 position of the node if it has one, its global position otherwise
+    pub fn position(&self, scene: &Scene, node_id: GameNodeId) -> DVec3 {
+        self.positions.get(&node_id).copied().unwrap_or_else(|| {
+            self.to_world_position(scene.get_global_transform_for_node(node_id).position())
+        })
+    }
+
+    /// The node stays where it is but its position goes back to being an f32
+    pub fn remove(&mut self, node_id: GameNodeId) -> Option<DVec3> {
+        self.positions.remove(&node_id)
+    }
+
+    /// The world position of the scene's origin
+    pub fn origin(&self) -> DVec3 {
+        self.origin
+    }
+
+    pub fn to_world_position(&self, scene_position: Vec3) -> DVec3 {
+        self.origin + scene_position.as_dvec3()
+    }
+
+    pub fn to_scene_position(&self, world_position: DVec3) -> Vec3 {
+        (world_position - self.origin).as_vec3()
+    }
+
+    /// Called by gameloop after on_update, which sends the returned offset as an
+    /// OriginShifted event
+    pub fn update(
+        &mut self,
+        scene: &mut Scene,
+        physics_state: &mut PhysicsState,
+        camera_node_id: Option<GameNodeId>,
+    ) -> Option<Vec3> {
+        self.positions.retain(|node_id, _| {
+            scene
+                .get_node(*node_id)
+                .is_some_and(|node| node.parent_id.is_none())
+        });
+        if !self.is_enabled {
+            return None;
+        }
+        let camera_node_id =
+            camera_node_id.filter(|camera_node_id| scene.get_node(*camera_node_id).is_some())?;
+        let camera_position = scene
+            .get_global_transform_for_node(camera_node_id)
+            .position();
+        if camera_position.length() <= self.origin_shift_distance {
+            return None;
+        }
+
+        let origin_shift = (self.origin - self.position(scene, camera_node_id)).as_vec3();
+        scene.shift_origin(origin_shift);
+        physics_state.shift_origin(origin_shift);
+        // by exactly what the nodes were moved by
+        self.origin -= origin_shift.as_dvec3();
+        // the shifted f32 translations lose precision, the exact ones are written back
+        for (node_id, position) in &self.positions {
+            if let Some(node) = scene.get_node_mut(*node_id) {
+                node.transform
+                    .set_position((*position - self.origin).as_vec3());
+            }
+        }
+        Some(origin_shift)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::GameNodeDesc;
+
+    #[test]
+    fn nodes_far_from_the_origin_are_rendered_relative_to_the_camera() {
+        let mut scene = Scene::default();
+        let mut physics_state = PhysicsState::new();
+        let camera_node_id = scene.add_node(GameNodeDesc::default()).id();
+        let node_id = scene.add_node(GameNodeDesc::default()).id();
+        let mut large_world = LargeWorld {
+            is_enabled: true,
+            ..Default::default()
+        };
+        let far_away = DVec3::new(3.4e9, 0.0, -1.2e8);
+        large_world.set_position(&mut scene, camera_node_id, far_away);
+        large_world.set_position(&mut scene, node_id, far_away + DVec3::new(0.25, 0.0, 0.5));
+        // too far for f32 to tell them apart
+        assert_eq!(
+            scene.get_node(camera_node_id).unwrap().transform.position(),
+            scene.get_node(node_id).unwrap().transform.position()
+        );
+
+        assert_eq!(
+            large_world.update(&mut scene, &mut physics_state, Some(camera_node_id)),
+            Some(-far_away.as_vec3())
+        );
+        assert_eq!(large_world.origin(), far_away);
+        assert_eq!(
+            scene.get_node(camera_node_id).unwrap().transform.position(),
+            Vec3::ZERO
+        );
+        assert_eq!(
+            scene.get_node(node_id).unwrap().transform.position(),
+            Vec3::new(0.25, 0.0, 0.5)
+        );
+        assert_eq!(
+            large_world.to_world_position(Vec3::new(0.25, 0.0, 0.5)),
+            large_world.position(&scene, node_id)
+        );
+
+        // the origin stays put until the camera is far enough
+        large_world.set_position(&mut scene, camera_node_id, far_away + DVec3::X * 1000.0);
+        assert_eq!(
+            large_world.update(&mut scene, &mut physics_state, Some(camera_node_id)),
+            None
+        );
+        large_world.set_position(&mut scene, camera_node_id, far_away + DVec3::X * 2000.0);
+        assert_eq!(
+            large_world.update(&mut scene, &mut physics_state, Some(camera_node_id)),
+            Some(Vec3::new(-2000.0, 0.0, 0.0))
+        );
+        assert_eq!(
+            scene.get_node(node_id).unwrap().transform.position(),
+            Vec3::new(-1999.75, 0.0, 0.5)
+        );
+    }
+}
@@ -3,31 +3,113 @@ pub use pollster::block_on;
 #[cfg(target_arch = "wasm32")]
 pub use wasm_bindgen_futures::spawn_local as block_on;
 
+pub mod ai;
 pub mod animation;
+pub mod asset_hot_reload;
 pub mod asset_loader;
+pub mod asset_registry;
 pub mod audio;
+pub mod audio_mixer;
+pub mod bone_overrides;
 pub mod buffer;
+pub mod bvh;
 pub mod camera;
+pub mod camera_system;
+pub mod character_controller;
+pub mod cloth;
+pub mod collider_generation;
 pub mod collisions;
+pub mod color_grading;
+pub mod crash_handler;
+pub mod custom_material;
+pub mod debug_draw;
+pub mod decals;
+pub mod destruction;
+pub mod dynamic_mesh;
+pub mod dynamic_resolution;
+pub mod ecs;
+pub mod editor;
+pub mod effects;
 pub mod engine_state;
+pub mod entity_pool;
+pub mod event_bus;
+#[cfg(feature = "fbx")]
+pub mod fbx_loader;
 pub mod file_manager;
+pub mod file_watcher;
+pub mod force_fields;
+pub mod frame_limiter;
+pub mod frame_profiler;
+pub mod game_state_stack;
 pub mod gameloop;
+pub mod gamepad;
 pub mod gltf_loader;
+pub mod gpu_diagnostics;
+pub mod health;
+pub mod hud;
+pub mod ibl_cache;
+pub mod input;
+pub mod jobs;
+pub mod joints;
+pub mod large_world;
+pub mod light_animation;
+pub mod lightmap_baker;
+pub mod logging;
 pub mod math;
 pub mod mesh;
+pub mod minimap;
+pub mod music_player;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod network;
+pub mod noise;
+pub mod observers;
+pub mod outline;
+pub mod particles;
+pub mod path_follower;
+pub mod path_tracer;
+pub mod perf_advisor;
 pub mod physics;
+pub mod physics_debug;
+pub mod picking;
 pub mod player_controller;
+pub mod point_shadow_atlas;
+pub mod portal;
+pub mod post_process;
+pub mod prefab;
+pub mod procedural_sky;
 pub mod profile_dump;
+pub mod ragdoll;
 pub mod renderer;
 pub mod sampler_cache;
+pub mod save_game;
 pub mod scene;
+pub mod scene_file;
+pub mod scene_transition;
 pub mod scene_tree;
+#[cfg(all(feature = "scripting", not(target_arch = "wasm32")))]
+pub mod scripting;
+pub mod sdf;
+pub mod settings;
+pub mod shadow_updates;
+pub mod simulation;
 pub mod skinning;
+pub mod sound_cue;
+pub mod taa;
+pub mod text_mesh;
 pub mod texture;
 pub mod texture_compression;
 pub mod thread;
 pub mod time;
+pub mod time_control;
+pub mod time_of_day;
 pub mod time_tracker;
 pub mod transform;
+pub mod triggers;
+pub mod tween;
 pub mod ui;
 pub mod wasm_not_sync;
+pub mod water;
+pub mod weapon;
+pub mod wind;
+pub mod world_labels;
+pub mod world_streaming;
@@ -0,0 +1,377 @@
+use crate::effects::{EffectDefinition, LightFlashDefinition};
+use crate::math::{lerp, lerp_vec};
+use crate::noise::Noise;
+use crate::renderer::PointLight;
+use crate::scene::{GameNodeDescBuilder, GameNodeId, Scene};
+use crate::transform::{Transform, TransformBuilder};
+
+use glam::f32::Vec3;
+
+/// A change of a point light over time. The intensity scales of the behaviors of a light
+/// multiply together
+#[derive(Debug, Clone, PartialEq)]
+pub enum LightBehavior {
+    /// random dips of the intensity like a candle or a torch, amount is the deepest one from 0
+    /// to 1 and speed is about how many dips there are per second
+    Flicker { speed: f32, amount: f32 },
+    /// on for duty_cycle of each period and off for the rest
+    Strobe { frequency: f32, duty_cycle: f32 },
+    /// smooth oscillation of the intensity between min_scale and 1
+    Pulse { frequency: f32, min_scale: f32 },
+    /// replaces the color, blending from each color to the next and back to the first one
+    ColorCycle {
+        colors: Vec<Vec3>,
+        seconds_per_color: f32,
+    },
+    /// (seconds, intensity scale) keyframes sorted by time, linearly interpolated. holds the
+    /// last value after the end unless it loops
+    IntensityCurve {
+        keyframes: Vec<(f32, f32)>,
+        looping: bool,
+    },
+}
+
+impl LightBehavior {
+    /// phase offsets the noise so lights with the same flicker don't dip together
+    fn apply(
+        &self,
+        noise: &Noise,
+        phase: f32,
+        time_seconds: f32,
+        color: &mut Vec3,
+        intensity_scale: &mut f32,
+    ) {
+        match self {
+            Self::Flicker { speed, amount } => {
+                let x = time_seconds * speed + phase;
+                let value = 0.7 * noise.perlin_1d(x) + 0.3 * noise.perlin_1d(x * 2.7 + 31.0);
+                *intensity_scale *= 1.0 - amount.clamp(0.0, 1.0) * (0.5 + 0.5 * value);
+            }
+            Self::Strobe {
+                frequency,
+                duty_cycle,
+            } => {
+                if (time_seconds * frequency).fract() >= *duty_cycle {
+                    *intensity_scale = 0.0;
+                }
+            }
+            Self::Pulse {
+                frequency,
+                min_scale,
+            } => {
+                let wave = 0.5 + 0.5 * (std::f32::consts::TAU * frequency * time_seconds).cos();
+                *intensity_scale *= lerp(*min_scale, 1.0, wave);
+            }
+            Self::ColorCycle {
+                colors,
+                seconds_per_color,
+            } => {
+                if colors.is_empty() {
+                    return;
+                }
+                let position = (time_seconds / seconds_per_color.max(0.001)) % colors.len() as f32;
+                let index = position as usize % colors.len();
+                let t = position.fract();
+                *color = lerp_vec(
+                    colors[index],
+                    colors[(index + 1) % colors.len()],
+                    t * t * (3.0 - 2.0 * t),
+                );
+            }
+            Self::IntensityCurve { keyframes, looping } => {
+                let (Some(first), Some(last)) = (keyframes.first(), keyframes.last()) else {
+                    return;
+                };
+                let time_seconds = if *looping && last.0 > 0.0 {
+                    time_seconds % last.0
+                } else {
+                    time_seconds
+                };
+                let next_index = keyframes.partition_point(|(time, _)| *time <= time_seconds);
+                *intensity_scale *= match next_index {
+                    0 => first.1,
+                    index if index == keyframes.len() => last.1,
+                    index => {
+                        let (from_time, from_scale) = keyframes[index - 1];
+                        let (to_time, to_scale) = keyframes[index];
+                        let t = (time_seconds - from_time) / (to_time - from_time).max(0.0001);
+                        lerp(from_scale, to_scale, t)
+                    }
+                };
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct LightAnimation {
+    node_id: GameNodeId,
+    behaviors: Vec<LightBehavior>,
+    elapsed_seconds: f32,
+    /// the color and intensity of the light without the animation, read on the first update
+    base: Option<(Vec3, f32)>,
+}
+
+/// The animations of the point lights, keyed by the node of the light. EngineState has one that
+/// gameloop updates after each fixed update
+#[derive(Debug, Clone)]
+pub struct LightAnimations {
+    animations: Vec<LightAnimation>,
+    noise: Noise,
+}
+
+impl Default for LightAnimations {
+    fn default() -> Self {
+        Self {
+            animations: vec![],
+            noise: Noise::new(0),
+        }
+    }
+}
+
+impl LightAnimations {
+    /// Replaces the animation the light already had. The light must be in Scene::point_lights
+    /// by the next update, the animation is dropped otherwise
+    pub fn add(&mut self, scene: &mut Scene, node_id: GameNodeId, behaviors: Vec<LightBehavior>) {
+        self.remove(scene, node_id);
+        self.animations.push(LightAnimation {
+            node_id,
+            behaviors,
+            elapsed_seconds: 0.0,
+            base: None,
+        });
+    }
+
+    /// Gives the light back its color and intensity from before the animation
+    pub fn remove(&mut self, scene: &mut Scene, node_id: GameNodeId) {
+        let Some(index) = self
+            .animations
+            .iter()
+            .position(|animation| animation.node_id == node_id)
+        else {
+            return;
+        };
+        let animation = self.animations.remove(index);
+        if let (Some((color, intensity)), Some(point_light)) =
+            (animation.base, scene.get_point_light_mut(node_id))
+        {
+            point_light.color = color;
+            point_light.intensity = intensity;
+        }
+    }
+
+    pub fn has_animation(&self, node_id: GameNodeId) -> bool {
+        self.animations
+            .iter()
+            .any(|animation| animation.node_id == node_id)
+    }
+
+    pub fn update(&mut self, scene: &mut Scene, delta_time_seconds: f32) {
+        let noise = &self.noise;
+        self.animations.retain_mut(|animation| {
+            let Some(point_light) = scene.get_point_light_mut(animation.node_id) else {
+                return false;
+            };
+            animation.elapsed_seconds += delta_time_seconds;
+            let (mut color, base_intensity) = *animation
+                .base
+                .get_or_insert((point_light.color, point_light.intensity));
+            let mut intensity_scale = 1.0;
+            let phase = animation.node_id.index() as f32 * 17.31;
+            for behavior in &animation.behaviors {
+                behavior.apply(
+                    noise,
+                    phase,
+                    animation.elapsed_seconds,
+                    &mut color,
+                    &mut intensity_scale,
+                );
+            }
+            point_light.color = color;
+            point_light.intensity = base_intensity * intensity_scale.max(0.0);
+            true
+        });
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ActiveLightFlash {
+    node_id: GameNodeId,
+    definition: LightFlashDefinition,
+    age_seconds: f32,
+}
+
+/// The short lived point lights of the effects, e.g. a muzzle flash. Each one gets its own node
+/// that's removed along with the light once the flash is over
+#[derive(Debug, Clone, Default)]
+pub struct LightFlashes {
+    active: Vec<ActiveLightFlash>,
+}
+
+impl LightFlashes {
+    /// Spawns the light flashes of the effect, their offsets are relative to the transform
+    pub fn spawn_effect(
+        &mut self,
+        scene: &mut Scene,
+        effect: &EffectDefinition,
+        transform: Transform,
+    ) {
+        for definition in &effect.light_flashes {
+            let position =
+                transform.position() + transform.rotation() * Vec3::from(definition.offset);
+            let node_id = scene
+                .add_node(
+                    GameNodeDescBuilder::new()
+                        .transform(TransformBuilder::new().position(position).build())
+                        .name(Some("light flash".to_string()))
+                        .build(),
+                )
+                .id();
+            scene.add_point_light(PointLight {
+                node_id,
+                color: Vec3::from(definition.color),
+                intensity: definition.intensity,
+                casts_shadows: false,
+            });
+            self.active.push(ActiveLightFlash {
+                node_id,
+                definition: definition.clone(),
+                age_seconds: 0.0,
+            });
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.active.len()
+    }
+
+    /// Fades the flashes out following their falloff and removes the finished ones
+    pub fn update(&mut self, scene: &mut Scene, delta_time_seconds: f32) {
+        self.active.retain_mut(|flash| {
+            flash.age_seconds += delta_time_seconds;
+            let t = flash.age_seconds / flash.definition.duration_seconds;
+            match scene.get_point_light_mut(flash.node_id) {
+                Some(point_light) if t < 1.0 => {
+                    point_light.intensity =
+                        flash.definition.intensity * flash.definition.falloff.intensity_factor(t);
+                    true
+                }
+                _ => {
+                    scene.remove_point_light(flash.node_id);
+                    scene.remove_node(flash.node_id);
+                    false
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::effects::LightFlashFalloff;
+    use crate::scene::GameNodeDesc;
+
+    fn intensity_after(behavior: LightBehavior, time_seconds: f32) -> f32 {
+        let mut color = Vec3::ONE;
+        let mut intensity_scale = 1.0;
+        behavior.apply(
+            &Noise::new(0),
+            0.0,
+            time_seconds,
+            &mut color,
+            &mut intensity_scale,
+        );
+        intensity_scale
+    }
+
+    #[test]
+    fn behaviors_scale_the_intensity_and_restore_it_when_removed() {
+        let strobe = LightBehavior::Strobe {
+            frequency: 2.0,
+            duty_cycle: 0.25,
+        };
+        assert_eq!(intensity_after(strobe.clone(), 0.1), 1.0);
+        assert_eq!(intensity_after(strobe, 0.2), 0.0);
+
+        let pulse = LightBehavior::Pulse {
+            frequency: 1.0,
+            min_scale: 0.2,
+        };
+        assert!((intensity_after(pulse, 0.5) - 0.2).abs() < 1e-5);
+
+        let curve = LightBehavior::IntensityCurve {
+            keyframes: vec![(0.0, 0.0), (1.0, 1.0), (2.0, 0.5)],
+            looping: true,
+        };
+        assert!((intensity_after(curve.clone(), 1.5) - 0.75).abs() < 1e-5);
+        assert!((intensity_after(curve, 2.5) - 0.5).abs() < 1e-5);
+
+        let mut scene = Scene::default();
+        let node_id = scene.add_node(GameNodeDesc::default()).id();
+        scene.add_point_light(PointLight {
+            node_id,
+            color: Vec3::ONE,
+            intensity: 2.0,
+            casts_shadows: false,
+        });
+        let mut light_animations = LightAnimations::default();
+        light_animations.add(
+            &mut scene,
+            node_id,
+            vec![LightBehavior::ColorCycle {
+                colors: vec![Vec3::X, Vec3::Y],
+                seconds_per_color: 1.0,
+            }],
+        );
+        light_animations.update(&mut scene, 1.5);
+        let point_light = scene.get_point_light(node_id).unwrap();
+        assert!(point_light
+            .color
+            .abs_diff_eq(Vec3::new(0.5, 0.5, 0.0), 1e-5));
+        assert_eq!(point_light.intensity, 2.0);
+
+        light_animations.remove(&mut scene, node_id);
+        assert_eq!(scene.get_point_light(node_id).unwrap().color, Vec3::ONE);
+        assert!(!light_animations.has_animation(node_id));
+    }
+
+    #[test]
+    fn light_flashes_fade_out_and_are_removed() {
+        let mut scene = Scene::default();
+        let effect = EffectDefinition {
+            light_flashes: vec![LightFlashDefinition {
+                offset: [0.0, 0.0, -1.0],
+                intensity: 4.0,
+                duration_seconds: 1.0,
+                falloff: LightFlashFalloff::Linear,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut light_flashes = LightFlashes::default();
+        light_flashes.spawn_effect(
+            &mut scene,
+            &effect,
+            TransformBuilder::new()
+                .position(Vec3::X)
+                .rotation(glam::Quat::from_rotation_y(std::f32::consts::PI))
+                .build(),
+        );
+        let node_id = light_flashes.active[0].node_id;
+        assert!(scene
+            .get_node(node_id)
+            .unwrap()
+            .transform
+            .position()
+            .abs_diff_eq(Vec3::new(1.0, 0.0, 1.0), 1e-5));
+
+        light_flashes.update(&mut scene, 0.5);
+        assert_eq!(scene.get_point_light(node_id).unwrap().intensity, 2.0);
+
+        light_flashes.update(&mut scene, 0.5);
+        assert_eq!(light_flashes.count(), 0);
+        assert!(scene.get_point_light(node_id).is_none());
+        assert!(scene.get_node(node_id).is_none());
+    }
+}
@@ -0,0 +1,502 @@
+use crate::bvh::Bvh;
+use crate::collisions::Aabb;
+use crate::jobs;
+use crate::mesh::*;
+use crate::picking::Ray;
+use crate::renderer::*;
+use crate::scene::*;
+use crate::texture::RawImage;
+
+use anyhow::{bail, Result};
+use glam::f32::{Mat3, Mat4, Vec2, Vec3};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+/// offset along the normal of the ray origins so they don't hit the surface they start from
+const RAY_BIAS: f32 = 0.001;
+/// texels per job when baking in parallel
+const TEXELS_PER_JOB: usize = 64;
+
+#[derive(Debug, Copy, Clone)]
+pub struct LightmapBakeParams {
+    pub width: u32,
+    pub height: u32,
+    /// rays traced per texel
+    pub samples_per_texel: u32,
+    /// 0 only gives the light coming straight from the sky
+    pub max_bounces: u32,
+    /// radiance of the rays that escape the scene
+    pub sky_color: Vec3,
+    /// how many texels the result is grown into the empty space around the uv islands, so
+    /// bilinear filtering doesn't pull in black at their edges
+    pub dilation_texels: u32,
+    pub seed: u64,
+}
+
+impl Default for LightmapBakeParams {
+    fn default() -> Self {
+        Self {
+            width: 256,
+            height: 256,
+            samples_per_texel: 128,
+            max_bounces: 2,
+            sky_color: Vec3::new(0.5, 0.6, 0.8),
+            dilation_texels: 2,
+            seed: 0,
+        }
+    }
+}
+
+/// Indirect diffuse light of a mesh, laid out along its lightmap_tex_coords. It's used in
+/// place of the IBL diffuse term by the materials with a DynamicPbrParams::lightmap_intensity,
+/// the direct light is still computed at runtime
+#[derive(Debug, Clone)]
+pub struct Lightmap {
+    pub width: u32,
+    pub height: u32,
+    /// row-major, linear radiance
+    pub texels: Vec<Vec3>,
+}
+
+impl Lightmap {
+    /// For Texture::from_decoded_image with the Rgba16Float format and no mipmaps
+    pub fn to_raw_image(&self) -> RawImage {
+        RawImage {
+            width: self.width,
+            height: self.height,
+            depth: 1,
+            mip_count: 1,
+            raw: bytemuck::cast_slice(
+                &self
+                    .texels
+                    .iter()
+                    .flat_map(|texel| [texel.x, texel.y, texel.z, 1.0])
+                    .map(|channel| Float16(half::f16::from_f32(channel)))
+                    .collect::<Vec<_>>(),
+            )
+            .to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Triangle {
+    positions: [Vec3; 3],
+    /// of the front face, following the winding
+    normal: Vec3,
+    albedo: Vec3,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Light {
+    /// towards the light for directional lights
+    direction_or_position: Vec3,
+    radiance: Vec3,
+    is_directional: bool,
+}
+
+/// Offline path tracer that bakes the light bouncing between static meshes into lightmaps.
+/// The occluders and the lights are added first, then each mesh is baked separately
+#[derive(Debug, Default)]
+pub struct LightmapBaker {
+    triangles: Vec<Triangle>,
+    bvh: Bvh,
+    lights: Vec<Light>,
+}
+
+impl LightmapBaker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    /// albedo is the diffuse color that the light bouncing off of these triangles takes
+    pub fn add_triangles(
+        &mut self,
+        vertices: &[Vertex],
+        indices: &[u32],
+        transform: Mat4,
+        albedo: Vec3,
+    ) {
+        let positions: Vec<_> = vertices
+            .iter()
+            .map(|vertex| transform.transform_point3(Vec3::from(vertex.position)))
+            .collect();
+        for triangle in indices.chunks_exact(3) {
+            let positions = [
+                positions[triangle[0] as usize],
+                positions[triangle[1] as usize],
+                positions[triangle[2] as usize],
+            ];
+            let normal = (positions[1] - positions[0]).cross(positions[2] - positions[0]);
+            if normal.length_squared() < f32::EPSILON * f32::EPSILON {
+                continue;
+            }
+            let Some(aabb) = Aabb::make_from_points(positions.iter().copied()) else {
+                continue;
+            };
+            self.bvh.update(self.triangles.len(), aabb);
+            self.triangles.push(Triangle {
+                positions,
+                normal: normal.normalize(),
+                albedo,
+            });
+        }
+    }
+
+    /// Adds the meshes of all static nodes of a freshly loaded scene, with the base color
+    /// factor of their material as albedo
+    pub fn add_scene(&mut self, scene: &Scene, bindable_scene_data: &BindableSceneData) {
+        for node in scene.nodes() {
+            let Some(visual) = node.visual.as_ref() else {
+                continue;
+            };
+            if node.skin_index.is_some() {
+                continue;
+            }
+            let Some(geometry) = bindable_scene_data.bindable_meshes.get(visual.mesh.index())
+            else {
+                continue;
+            };
+            let albedo = match visual.material {
+                Material::Pbr {
+                    binded_material,
+                    dynamic_pbr_params,
+                } => dynamic_pbr_params
+                    .or_else(|| {
+                        bindable_scene_data
+                            .bindable_pbr_materials
+                            .get(binded_material.index())
+                            .map(|material| material.dynamic_pbr_params)
+                    })
+                    .map(|params| params.base_color_factor.truncate())
+                    .unwrap_or(Vec3::ONE),
+                _ => Vec3::ONE,
+            };
+            let transform: Mat4 = scene.get_global_transform_for_node(node.id()).into();
+            self.add_triangles(
+                &geometry.vertices,
+                &geometry.indices.to_u32_vec(),
+                transform,
+                albedo,
+            );
+        }
+    }
+
+    /// direction is the one the light travels in, like DirectionalLight::direction
+    pub fn add_directional_light(&mut self, direction: Vec3, color: Vec3, intensity: f32) {
+        self.lights.push(Light {
+            direction_or_position: -direction.normalize(),
+            radiance: color * intensity,
+            is_directional: true,
+        });
+    }
+
+    /// Falls off with the square of the distance
+    pub fn add_point_light(&mut self, position: Vec3, color: Vec3, intensity: f32) {
+        self.lights.push(Light {
+            direction_or_position: position,
+            radiance: color * intensity,
+            is_directional: false,
+        });
+    }
+
+    /// Bakes the mesh's lightmap. It's expected to have non-overlapping lightmap_tex_coords
+    /// and to be added as an occluder too, if it should shadow itself
+    #[profiling::function]
+    pub fn bake_mesh(
+        &self,
+        vertices: &[Vertex],
+        indices: &[u32],
+        transform: Mat4,
+        params: &LightmapBakeParams,
+    ) -> Result<Lightmap> {
+        if params.width == 0 || params.height == 0 {
+            bail!("Lightmap size must not be zero");
+        }
+        if let Some(index) = indices
+            .iter()
+            .find(|index| **index as usize >= vertices.len())
+        {
+            bail!(
+                "Index {index} is out of bounds of the {} vertices",
+                vertices.len()
+            );
+        }
+
+        let size = Vec2::new(params.width as f32, params.height as f32);
+        let normal_transform = Mat3::from_mat4(transform).inverse().transpose();
+        let texel_count = (params.width * params.height) as usize;
+
+        // world position and normal at the center of each covered texel
+        let mut texel_surfaces: Vec<Option<(Vec3, Vec3)>> = vec![None; texel_count];
+        for triangle in indices.chunks_exact(3) {
+            let triangle_vertices = [
+                &vertices[triangle[0] as usize],
+                &vertices[triangle[1] as usize],
+                &vertices[triangle[2] as usize],
+            ];
+            let uvs = triangle_vertices.map(|vertex| Vec2::from(vertex.lightmap_tex_coords) * size);
+            let uv_min = uvs[0].min(uvs[1]).min(uvs[2]).floor().max(Vec2::ZERO);
+            let uv_max = uvs[0].max(uvs[1]).max(uvs[2]).ceil().min(size);
+
+            for y in uv_min.y as u32..uv_max.y as u32 {
+                for x in uv_min.x as u32..uv_max.x as u32 {
+                    let Some(barycentric) =
+                        barycentric_coords(Vec2::new(x as f32 + 0.5, y as f32 + 0.5), &uvs)
+                    else {
+                        continue;
+                    };
+                    let interpolate = |values: [Vec3; 3]| {
+                        values[0] * barycentric.x
+                            + values[1] * barycentric.y
+                            + values[2] * barycentric.z
+                    };
+                    let position = transform.transform_point3(interpolate(
+                        triangle_vertices.map(|vertex| Vec3::from(vertex.position)),
+                    ));
+                    let normal = (normal_transform
+                        * interpolate(triangle_vertices.map(|vertex| Vec3::from(vertex.normal))))
+                    .normalize_or_zero();
+                    if normal == Vec3::ZERO {
+                        continue;
+                    }
+                    texel_surfaces[(x + y * params.width) as usize] = Some((position, normal));
+                }
+            }
+        }
+
+        let mut texels: Vec<Option<Vec3>> =
+            jobs::par_map(&texel_surfaces, TEXELS_PER_JOB, |texel_index, surface| {
+                let (position, normal) = (*surface)?;
+                let mut rng = SmallRng::seed_from_u64(params.seed ^ texel_index as u64);
+                let samples_per_texel = params.samples_per_texel.max(1);
+                let radiance_sum: Vec3 = (0..samples_per_texel)
+                    .map(|_| self.trace_path(position, normal, params, &mut rng))
+                    .sum();
+                Some(radiance_sum / samples_per_texel as f32)
+            });
+
+        for _ in 0..params.dilation_texels {
+            texels = dilate(&texels, params.width, params.height);
+        }
+
+        Ok(Lightmap {
+            width: params.width,
+            height: params.height,
+            texels: texels
+                .into_iter()
+                .map(|texel| texel.unwrap_or(Vec3::ZERO))
+                .collect(),
+        })
+    }
+
+    /// Radiance arriving at the surface from a random direction, the direct light excluded
+    fn trace_path(
+        &self,
+        position: Vec3,
+        normal: Vec3,
+        params: &LightmapBakeParams,
+        rng: &mut SmallRng,
+    ) -> Vec3 {
+        let mut origin = position + normal * RAY_BIAS;
+        let mut direction = cosine_weighted_direction(normal, rng);
+        let mut throughput = Vec3::ONE;
+        let mut radiance = Vec3::ZERO;
+
+        for bounce in 0..=params.max_bounces {
+            let Some((distance, triangle)) = self.closest_hit(Ray { origin, direction }) else {
+                radiance += throughput * params.sky_color;
+                break;
+            };
+            if bounce == params.max_bounces {
+                break;
+            }
+            let hit_position = origin + direction * distance;
+            let hit_normal = if triangle.normal.dot(direction) > 0.0 {
+                -triangle.normal
+            } else {
+                triangle.normal
+            };
+            origin = hit_position + hit_normal * RAY_BIAS;
+
+            // lambertian, the 1 / pi of the brdf cancels out with the cosine weighted pdf
+            throughput *= triangle.albedo;
+            radiance += throughput * self.direct_light(origin, hit_normal) / std::f32::consts::PI;
+            direction = cosine_weighted_direction(hit_normal, rng);
+        }
+        radiance
+    }
+
+    fn direct_light(&self, origin: Vec3, normal: Vec3) -> Vec3 {
+        self.lights
+            .iter()
+            .map(|light| {
+                let (to_light, distance, attenuation) = if light.is_directional {
+                    (light.direction_or_position, f32::MAX, 1.0)
+                } else {
+                    let to_light = light.direction_or_position - origin;
+                    let distance = to_light.length().max(RAY_BIAS);
+                    (to_light / distance, distance, 1.0 / (distance * distance))
+                };
+                let n_dot_l = normal.dot(to_light);
+                if n_dot_l <= 0.0
+                    || self.is_occluded(
+                        Ray {
+                            origin,
+                            direction: to_light,
+                        },
+                        distance,
+                    )
+                {
+                    return Vec3::ZERO;
+                }
+                light.radiance * n_dot_l * attenuation
+            })
+            .sum()
+    }
+
+    fn closest_hit(&self, ray: Ray) -> Option<(f32, &Triangle)> {
+        let mut closest: Option<(f32, usize)> = None;
+        self.bvh.query_ray(ray, |index| {
+            if let Some(distance) =
+                ray_triangle_intersection(&ray, &self.triangles[index].positions)
+            {
+                if closest.map_or(true, |(closest_distance, _)| distance < closest_distance) {
+                    closest = Some((distance, index));
+                }
+            }
+        });
+        closest.map(|(distance, index)| (distance, &self.triangles[index]))
+    }
+
+    fn is_occluded(&self, ray: Ray, max_distance: f32) -> bool {
+        let mut is_occluded = false;
+        self.bvh.query_ray(ray, |index| {
+            is_occluded |= ray_triangle_intersection(&ray, &self.triangles[index].positions)
+                .map_or(false, |distance| distance < max_distance);
+        });
+        is_occluded
+    }
+}
+
+fn cosine_weighted_direction(normal: Vec3, rng: &mut SmallRng) -> Vec3 {
+    let radius = rng.gen::<f32>().sqrt();
+    let angle = rng.gen::<f32>() * std::f32::consts::TAU;
+    let (tangent, bitangent) = normal.any_orthonormal_pair();
+    (tangent * radius * angle.cos()
+        + bitangent * radius * angle.sin()
+        + normal * (1.0 - radius * radius).max(0.0).sqrt())
+    .normalize()
+}
+
+/// Möller–Trumbore, both faces are hit
+fn ray_triangle_intersection(ray: &Ray, [a, b, c]: &[Vec3; 3]) -> Option<f32> {
+    let ab = *b - *a;
+    let ac = *c - *a;
+    let p = ray.direction.cross(ac);
+    let determinant = ab.dot(p);
+    if determinant.abs() < f32::EPSILON {
+        return None;
+    }
+    let inverse_determinant = 1.0 / determinant;
+    let to_origin = ray.origin - *a;
+    let u = to_origin.dot(p) * inverse_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = to_origin.cross(ab);
+    let v = ray.direction.dot(q) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let distance = ac.dot(q) * inverse_determinant;
+    (distance > 0.0).then_some(distance)
+}
+
+/// None if the point is outside of the triangle
+fn barycentric_coords(point: Vec2, [a, b, c]: &[Vec2; 3]) -> Option<Vec3> {
+    let ab = *b - *a;
+    let ac = *c - *a;
+    let area = ab.perp_dot(ac);
+    if area.abs() < f32::EPSILON {
+        return None;
+    }
+    let to_point = point - *a;
+    let v = to_point.perp_dot(ac) / area;
+    let w = ab.perp_dot(to_point) / area;
+    let u = 1.0 - v - w;
+    (u >= 0.0 && v >= 0.0 && w >= 0.0).then_some(Vec3::new(u, v, w))
+}
+
+/// Fills the empty texels next to baked ones with the average of their baked neighbors
+fn dilate(texels: &[Option<Vec3>], width: u32, height: u32) -> Vec<Option<Vec3>> {
+    let (width, height) = (width as i32, height as i32);
+    (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let texel = texels[(x + y * width) as usize];
+            if texel.is_some() {
+                return texel;
+            }
+            let neighbors: Vec<Vec3> = [(-1, 0), (1, 0), (0, -1), (0, 1)]
+                .iter()
+                .map(|(offset_x, offset_y)| (x + offset_x, y + offset_y))
+                .filter(|(x, y)| (0..width).contains(x) && (0..height).contains(y))
+                .filter_map(|(x, y)| texels[(x + y * width) as usize])
+                .collect();
+            (!neighbors.is_empty()).then(|| neighbors.iter().sum::<Vec3>() / neighbors.len() as f32)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quad_vertices(center: Vec3, half_size: f32) -> Vec<Vertex> {
+        [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+            .iter()
+            .map(|(x, z)| Vertex {
+                position: (center + Vec3::new(x * half_size, 0.0, z * half_size)).to_array(),
+                normal: [0.0, 1.0, 0.0],
+                lightmap_tex_coords: [0.5 * (x + 1.0), 0.5 * (z + 1.0)],
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn floating_quad_darkens_the_floor_below_it() {
+        let mut baker = LightmapBaker::new();
+        let indices = [0, 2, 1, 0, 3, 2];
+        let floor = quad_vertices(Vec3::ZERO, 10.0);
+        baker.add_triangles(&floor, &indices, Mat4::IDENTITY, Vec3::splat(0.5));
+        baker.add_triangles(
+            &quad_vertices(Vec3::new(0.0, 1.0, 0.0), 2.0),
+            &indices,
+            Mat4::IDENTITY,
+            Vec3::ZERO,
+        );
+
+        let params = LightmapBakeParams {
+            width: 16,
+            height: 16,
+            samples_per_texel: 64,
+            max_bounces: 0,
+            sky_color: Vec3::ONE,
+            ..Default::default()
+        };
+        let lightmap = baker
+            .bake_mesh(&floor, &indices, Mat4::IDENTITY, &params)
+            .unwrap();
+
+        let under_quad = lightmap.texels[8 + 8 * 16].x;
+        let corner = lightmap.texels[0].x;
+        assert!(under_quad < 0.5, "{under_quad}");
+        assert!(corner > 0.9, "{corner}");
+    }
+}
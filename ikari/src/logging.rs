@@ -0,0 +1,361 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{LineWriter, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+
+pub const DEFAULT_LOG_HISTORY_LENGTH: usize = 1000;
+
+lazy_static! {
+    static ref LOG_HISTORY: Mutex<LogHistory> =
+        Mutex::new(LogHistory::new(DEFAULT_LOG_HISTORY_LENGTH));
+}
+
+/// The part of the engine a log line comes from, see LogCategory::from_target
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LogCategory {
+    Renderer,
+    Audio,
+    Physics,
+    Asset,
+    Other,
+}
+
+impl LogCategory {
+    pub const ALL: [LogCategory; 5] = [
+        LogCategory::Renderer,
+        LogCategory::Audio,
+        LogCategory::Physics,
+        LogCategory::Asset,
+        LogCategory::Other,
+    ];
+
+    /// From the module path that the log macros use as target by default. A category can also
+    /// be picked explicitly with its lowercase name as target, e.g. log::info!(target: "audio", ..)
+    pub fn from_target(target: &str) -> Self {
+        let mut path = target.split("::");
+        let crate_name = path.next().unwrap_or(target);
+        let module = path.next().unwrap_or(crate_name);
+        CATEGORY_MODULES
+            .iter()
+            .find(|(_, names)| names.contains(&crate_name) || names.contains(&module))
+            .map_or(Self::Other, |(category, _)| *category)
+    }
+}
+
+/// crates and ikari modules of each category
+const CATEGORY_MODULES: [(LogCategory, &[&str]); 4] = [
+    (
+        LogCategory::Renderer,
+        &[
+            "renderer",
+            "texture",
+            "texture_compression",
+            "post_process",
+            "color_grading",
+            "custom_material",
+            "path_tracer",
+            "gpu_diagnostics",
+            "sampler_cache",
+            "wgpu",
+            "wgpu_core",
+            "wgpu_hal",
+            "naga",
+            "iced_wgpu",
+        ],
+    ),
+    (
+        LogCategory::Audio,
+        &[
+            "audio",
+            "audio_mixer",
+            "music_player",
+            "sound_cue",
+            "cpal",
+            "symphonia",
+            "oddio",
+        ],
+    ),
+    (
+        LogCategory::Physics,
+        &[
+            "physics",
+            "ragdoll",
+            "character_controller",
+            "collisions",
+            "collider_generation",
+            "rapier3d_f64",
+        ],
+    ),
+    (
+        LogCategory::Asset,
+        &[
+            "asset",
+            "asset_loader",
+            "asset_registry",
+            "asset_hot_reload",
+            "gltf_loader",
+            "fbx_loader",
+            "file_manager",
+            "scene_file",
+            "prefab",
+            "gltf",
+            "image",
+        ],
+    ),
+];
+
+impl fmt::Display for LogCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                LogCategory::Renderer => "Renderer",
+                LogCategory::Audio => "Audio",
+                LogCategory::Physics => "Physics",
+                LogCategory::Asset => "Asset",
+                LogCategory::Other => "Other",
+            }
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    pub timestamp: chrono::DateTime<chrono::Local>,
+    pub level: log::Level,
+    pub category: LogCategory,
+    pub target: String,
+    pub message: String,
+    /// consecutive identical lines are merged into one entry
+    pub repeat_count: u32,
+}
+
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{} {} {} {}] {}",
+            self.timestamp.format("%H:%M:%S%.3f"),
+            self.level,
+            self.category,
+            self.target,
+            self.message
+        )?;
+        if self.repeat_count > 1 {
+            write!(f, " (x{})", self.repeat_count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Ring buffer of the most recent log entries
+#[derive(Debug)]
+pub struct LogHistory {
+    capacity: usize,
+    entries: VecDeque<LogEntry>,
+    /// goes up every time the entries change
+    generation: u64,
+}
+
+impl LogHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::new(),
+            generation: 0,
+        }
+    }
+
+    pub fn push(&mut self, entry: LogEntry) {
+        self.generation += 1;
+        if let Some(last_entry) = self.entries.back_mut() {
+            if last_entry.level == entry.level
+                && last_entry.target == entry.target
+                && last_entry.message == entry.message
+            {
+                last_entry.repeat_count += entry.repeat_count;
+                last_entry.timestamp = entry.timestamp;
+                return;
+            }
+        }
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        if self.capacity > 0 {
+            self.entries.push_back(entry);
+        }
+    }
+
+    pub fn entries(&self) -> impl DoubleEndedIterator<Item = &LogEntry> + '_ {
+        self.entries.iter()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+/// Oldest first. Empty if Logger wasn't set as the logger
+pub fn recent_log_entries() -> Vec<LogEntry> {
+    LOG_HISTORY.lock().unwrap().entries().cloned().collect()
+}
+
+/// Changes whenever a line is logged, so the log window knows when to refresh
+pub fn log_generation() -> u64 {
+    LOG_HISTORY.lock().unwrap().generation()
+}
+
+#[derive(Debug, Clone)]
+pub struct LoggerConfig {
+    pub history_length: usize,
+    /// also appends each line to this file, with a timestamp
+    pub file_path: Option<PathBuf>,
+    /// the lines of these categories that are below the level are dropped,
+    /// on top of the filtering of the inner logger
+    pub category_levels: HashMap<LogCategory, log::LevelFilter>,
+}
+
+impl Default for LoggerConfig {
+    fn default() -> Self {
+        Self {
+            history_length: DEFAULT_LOG_HISTORY_LENGTH,
+            file_path: None,
+            category_levels: HashMap::new(),
+        }
+    }
+}
+
+/// Forwards to another logger, e.g. env_logger, while keeping the most recent entries around for
+/// the in-game log window and the diagnostic reports, see recent_log_entries
+pub struct Logger {
+    inner: Box<dyn log::Log>,
+    category_levels: HashMap<LogCategory, log::LevelFilter>,
+    file: Option<Mutex<LineWriter<std::fs::File>>>,
+}
+
+impl Logger {
+    pub fn init(
+        inner: Box<dyn log::Log>,
+        max_level: log::LevelFilter,
+        config: LoggerConfig,
+    ) -> anyhow::Result<()> {
+        let file = config
+            .file_path
+            .map(|file_path| {
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(file_path)
+                    .map(|file| Mutex::new(LineWriter::new(file)))
+            })
+            .transpose()?;
+        *LOG_HISTORY.lock().unwrap() = LogHistory::new(config.history_length);
+
+        log::set_boxed_logger(Box::new(Self {
+            inner,
+            category_levels: config.category_levels,
+            file,
+        }))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+
+    fn category_allows(&self, metadata: &log::Metadata) -> bool {
+        self.category_levels
+            .get(&LogCategory::from_target(metadata.target()))
+            .map_or(true, |level| metadata.level() <= *level)
+    }
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.category_allows(metadata) && self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let entry = LogEntry {
+            timestamp: chrono::Local::now(),
+            level: record.level(),
+            category: LogCategory::from_target(record.target()),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+            repeat_count: 1,
+        };
+
+        if let Some(file) = &self.file {
+            // nothing logs while holding the locks so waiting on them can't deadlock. A thread
+            // that panicked while holding one doesn't stop the others from logging
+            let mut file = file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let _ = writeln!(
+                file,
+                "{} {:<5} [{}] {}: {}",
+                entry.timestamp.format("%Y-%m-%d %H:%M:%S%.3f"),
+                entry.level,
+                entry.category,
+                entry.target,
+                entry.message
+            );
+        }
+
+        LOG_HISTORY
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(entry);
+
+        self.inner.log(record);
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.flush();
+            }
+        }
+        self.inner.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_lines_are_merged_and_old_ones_dropped() {
+        let entry = |message: &str| LogEntry {
+            timestamp: chrono::Local::now(),
+            level: log::Level::Info,
+            category: LogCategory::from_target("ikari::renderer"),
+            target: "ikari::renderer".to_string(),
+            message: message.to_string(),
+            repeat_count: 1,
+        };
+        let mut history = LogHistory::new(3);
+
+        for message in ["first", "adapter", "adapter", "a", "b"] {
+            history.push(entry(message));
+        }
+
+        let messages: Vec<_> = history
+            .entries()
+            .map(|entry| (entry.message.as_str(), entry.repeat_count))
+            .collect();
+        assert_eq!(messages, vec![("adapter", 2), ("a", 1), ("b", 1)]);
+        assert_eq!(
+            history.entries().next().unwrap().category,
+            LogCategory::Renderer
+        );
+        assert_eq!(
+            LogCategory::from_target("ikari::gltf_loader"),
+            LogCategory::Asset
+        );
+        assert_eq!(LogCategory::from_target("physics"), LogCategory::Physics);
+    }
+}
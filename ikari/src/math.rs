@@ -23,3 +23,208 @@ pub fn deg_to_rad(deg: f32) -> f32 {
 pub fn rad_to_deg(rad: f32) -> f32 {
     rad * 180.0 / std::f32::consts::PI
 }
+
+/// A piecewise cubic curve, evaluated with a t that goes from 0 to segment_count
+#[derive(Debug, Clone, PartialEq)]
+pub enum Spline {
+    /// start, control, control, end, control, control, end... so 3n + 1 points for n segments
+    Bezier(Vec<Vec3>),
+    /// goes through all the points, the first and last ones are repeated when it isn't closed
+    CatmullRom { points: Vec<Vec3>, is_closed: bool },
+    /// (point, tangent) pairs, goes through the points
+    Hermite(Vec<(Vec3, Vec3)>),
+}
+
+impl Spline {
+    pub fn segment_count(&self) -> usize {
+        match self {
+            Spline::Bezier(points) => points.len().saturating_sub(1) / 3,
+            Spline::CatmullRom { points, is_closed } => {
+                if *is_closed && points.len() > 1 {
+                    points.len()
+                } else {
+                    points.len().saturating_sub(1)
+                }
+            }
+            Spline::Hermite(points) => points.len().saturating_sub(1),
+        }
+    }
+
+    pub fn evaluate(&self, t: f32) -> Vec3 {
+        let Some((segment_index, local_t)) = self.locate(t) else {
+            return self.first_point().unwrap_or(Vec3::ZERO);
+        };
+        let [p0, m0, p1, m1] = self.hermite_segment(segment_index);
+        let t2 = local_t * local_t;
+        let t3 = t2 * local_t;
+        p0 * (2.0 * t3 - 3.0 * t2 + 1.0)
+            + m0 * (t3 - 2.0 * t2 + local_t)
+            + p1 * (-2.0 * t3 + 3.0 * t2)
+            + m1 * (t3 - t2)
+    }
+
+    /// derivative with respect to t, not normalized
+    pub fn tangent(&self, t: f32) -> Vec3 {
+        let Some((segment_index, local_t)) = self.locate(t) else {
+            return Vec3::ZERO;
+        };
+        let [p0, m0, p1, m1] = self.hermite_segment(segment_index);
+        let t2 = local_t * local_t;
+        p0 * (6.0 * t2 - 6.0 * local_t)
+            + m0 * (3.0 * t2 - 4.0 * local_t + 1.0)
+            + p1 * (-6.0 * t2 + 6.0 * local_t)
+            + m1 * (3.0 * t2 - 2.0 * local_t)
+    }
+
+    fn first_point(&self) -> Option<Vec3> {
+        match self {
+            Spline::Bezier(points) | Spline::CatmullRom { points, .. } => points.first().copied(),
+            Spline::Hermite(points) => points.first().map(|(point, _)| *point),
+        }
+    }
+
+    /// (segment index, t within the segment)
+    fn locate(&self, t: f32) -> Option<(usize, f32)> {
+        let segment_count = self.segment_count();
+        if segment_count == 0 {
+            return None;
+        }
+        let t = t.clamp(0.0, segment_count as f32);
+        let segment_index = (t.floor() as usize).min(segment_count - 1);
+        Some((segment_index, t - segment_index as f32))
+    }
+
+    /// every type is converted to the start point and tangent and end point and tangent
+    fn hermite_segment(&self, segment_index: usize) -> [Vec3; 4] {
+        match self {
+            Spline::Bezier(points) => {
+                let [p0, c0, c1, p1] = [0, 1, 2, 3].map(|i| points[segment_index * 3 + i]);
+                [p0, 3.0 * (c0 - p0), p1, 3.0 * (p1 - c1)]
+            }
+            Spline::CatmullRom { points, is_closed } => {
+                let point = |index: isize| {
+                    let len = points.len() as isize;
+                    if *is_closed {
+                        points[index.rem_euclid(len) as usize]
+                    } else {
+                        points[index.clamp(0, len - 1) as usize]
+                    }
+                };
+                let i = segment_index as isize;
+                let [p0, p1, p2, p3] = [i - 1, i, i + 1, i + 2].map(point);
+                [p1, 0.5 * (p2 - p0), p2, 0.5 * (p3 - p1)]
+            }
+            Spline::Hermite(points) => {
+                let (p0, m0) = points[segment_index];
+                let (p1, m1) = points[segment_index + 1];
+                [p0, m0, p1, m1]
+            }
+        }
+    }
+}
+
+/// A spline along with a table of its arc length, to move along it at a constant speed
+#[derive(Debug, Clone, PartialEq)]
+pub struct SplinePath {
+    pub spline: Spline,
+    /// distance from the start at t = i / samples_per_segment
+    cumulative_lengths: Vec<f32>,
+    samples_per_segment: usize,
+}
+
+impl SplinePath {
+    pub const DEFAULT_SAMPLES_PER_SEGMENT: usize = 32;
+
+    pub fn new(spline: Spline) -> Self {
+        Self::with_samples_per_segment(spline, Self::DEFAULT_SAMPLES_PER_SEGMENT)
+    }
+
+    pub fn with_samples_per_segment(spline: Spline, samples_per_segment: usize) -> Self {
+        let samples_per_segment = samples_per_segment.max(1);
+        let sample_count = spline.segment_count() * samples_per_segment;
+        let mut cumulative_lengths = vec![0.0];
+        let mut previous_point = spline.evaluate(0.0);
+        for sample_index in 1..=sample_count {
+            let point = spline.evaluate(sample_index as f32 / samples_per_segment as f32);
+            let length = cumulative_lengths[sample_index - 1] + point.distance(previous_point);
+            cumulative_lengths.push(length);
+            previous_point = point;
+        }
+        Self {
+            spline,
+            cumulative_lengths,
+            samples_per_segment,
+        }
+    }
+
+    pub fn length(&self) -> f32 {
+        self.cumulative_lengths.last().copied().unwrap_or(0.0)
+    }
+
+    /// The spline's t at this distance from the start, clamped to the ends
+    pub fn t_at_distance(&self, distance: f32) -> f32 {
+        let distance = distance.clamp(0.0, self.length());
+        let next_sample_index = self
+            .cumulative_lengths
+            .partition_point(|length| *length < distance)
+            .clamp(1, self.cumulative_lengths.len().max(2) - 1);
+        let Some(&next_length) = self.cumulative_lengths.get(next_sample_index) else {
+            return 0.0;
+        };
+        let previous_length = self.cumulative_lengths[next_sample_index - 1];
+        let sample_alpha = if next_length > previous_length {
+            (distance - previous_length) / (next_length - previous_length)
+        } else {
+            0.0
+        };
+        (next_sample_index - 1) as f32 / self.samples_per_segment as f32
+            + sample_alpha / self.samples_per_segment as f32
+    }
+
+    pub fn point_at_distance(&self, distance: f32) -> Vec3 {
+        self.spline.evaluate(self.t_at_distance(distance))
+    }
+
+    /// normalized, zero if the spline has no length there
+    pub fn direction_at_distance(&self, distance: f32) -> Vec3 {
+        self.spline
+            .tangent(self.t_at_distance(distance))
+            .normalize_or_zero()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spline_paths_are_parameterized_by_distance() {
+        let points = vec![
+            Vec3::ZERO,
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(3.0, 0.0, 0.0),
+        ];
+        let catmull_rom = Spline::CatmullRom {
+            points: points.clone(),
+            is_closed: false,
+        };
+        assert_eq!(catmull_rom.segment_count(), 3);
+        assert!(catmull_rom.evaluate(2.0).distance(points[2]) < 1e-5);
+
+        // evenly spaced control points on a line give a curve with uneven speed
+        let bezier = SplinePath::new(Spline::Bezier(vec![
+            Vec3::ZERO,
+            Vec3::new(0.1, 0.0, 0.0),
+            Vec3::new(0.2, 0.0, 0.0),
+            Vec3::new(3.0, 0.0, 0.0),
+        ]));
+        assert!((bezier.length() - 3.0).abs() < 1e-3);
+        for distance in [0.0, 0.5, 1.5, 2.9] {
+            let point = bezier.point_at_distance(distance);
+            assert!((point.x - distance).abs() < 0.02, "{point} at {distance}");
+        }
+        assert!(bezier.direction_at_distance(1.0).distance(Vec3::X) < 1e-5);
+        assert_eq!(bezier.point_at_distance(10.0), Vec3::new(3.0, 0.0, 0.0));
+    }
+}
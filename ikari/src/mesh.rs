@@ -1,7 +1,9 @@
+use crate::renderer::{BindableGeometryBuffers, BindableIndices};
 use crate::texture::*;
 
 use std::{
-    collections::{hash_map, HashMap},
+    cmp::Ordering,
+    collections::{hash_map, BinaryHeap, HashMap},
     io::{BufReader, Cursor},
 };
 
@@ -23,10 +25,12 @@ pub struct Vertex {
     pub color: [f32; 4],
     pub bone_indices: [u32; 4],
     pub bone_weights: [f32; 4],
+    /// second uv set, only used to sample lightmaps. TEXCOORD_1 in gltf
+    pub lightmap_tex_coords: [f32; 2],
 }
 
 impl Vertex {
-    const ATTRIBS: [wgpu::VertexAttribute; 8] = wgpu::vertex_attr_array![
+    pub(crate) const ATTRIBS: [wgpu::VertexAttribute; 9] = wgpu::vertex_attr_array![
         0 => Float32x3,
         1 => Float32x3,
         2 => Float32x2,
@@ -35,6 +39,7 @@ impl Vertex {
         5 => Float32x4,
         6 => Uint32x4,
         7 => Float32x4,
+        8 => Float32x2,
     ];
 
     pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
@@ -57,6 +62,7 @@ impl Default for Vertex {
             color: [1.0, 1.0, 1.0, 1.0],
             bone_indices: Default::default(),
             bone_weights: [1.0, 0.0, 0.0, 0.0],
+            lightmap_tex_coords: Default::default(),
         }
     }
 }
@@ -69,7 +75,12 @@ pub struct GpuPbrMeshInstance {
     pub emissive_factor: [f32; 4],
     pub mrno: [f32; 4], // metallic_factor, roughness_factor, normal scale, occlusion strength
     pub alpha_cutoff: f32,
-    pub padding: [f32; 3],
+    pub lightmap_intensity: f32,
+    pub parallax_height_scale: f32,
+    pub padding: f32,
+    // rows of the uv transform's affine matrix, with the scroll velocity in w
+    pub uv_transform_0: [f32; 4],
+    pub uv_transform_1: [f32; 4],
 }
 
 impl GpuPbrMeshInstance {
@@ -82,7 +93,11 @@ impl GpuPbrMeshInstance {
             normal_scale,
             occlusion_strength,
             alpha_cutoff,
+            uv_transform,
+            lightmap_intensity,
+            parallax_height_scale,
         } = pbr_params;
+        let [uv_row_0, uv_row_1] = uv_transform.affine_rows();
         Self {
             model_transform: transform,
             base_color_factor: base_color_factor.into(),
@@ -99,7 +114,21 @@ impl GpuPbrMeshInstance {
                 occlusion_strength,
             ],
             alpha_cutoff,
-            padding: [0.0, 0.0, 0.0],
+            lightmap_intensity,
+            parallax_height_scale,
+            padding: 0.0,
+            uv_transform_0: [
+                uv_row_0[0],
+                uv_row_0[1],
+                uv_row_0[2],
+                uv_transform.scroll_velocity.x,
+            ],
+            uv_transform_1: [
+                uv_row_1[0],
+                uv_row_1[1],
+                uv_row_1[2],
+                uv_transform.scroll_velocity.y,
+            ],
         }
     }
 }
@@ -124,6 +153,13 @@ pub struct DynamicPbrParams {
     pub normal_scale: f32,
     pub occlusion_strength: f32,
     pub alpha_cutoff: f32,
+    pub uv_transform: UvTransform,
+    /// 0 lights the material with the image based lighting. Otherwise the material is baked:
+    /// its lightmap, scaled by this, replaces the diffuse part of the image based lighting
+    pub lightmap_intensity: f32,
+    /// 0 disables the parallax occlusion mapping. Otherwise the alpha channel of the normal map
+    /// is a height map and this is how deep its lowest point goes, in uv units
+    pub parallax_height_scale: f32,
 }
 
 impl Default for DynamicPbrParams {
@@ -136,10 +172,53 @@ impl Default for DynamicPbrParams {
             normal_scale: 1.0,
             occlusion_strength: 1.0,
             alpha_cutoff: -1.0,
+            uv_transform: UvTransform::default(),
+            lightmap_intensity: 0.0,
+            parallax_height_scale: 0.0,
         }
     }
 }
 
+/// Applied to the texture coordinates of all the material's textures, with the same conventions
+/// as KHR_texture_transform: scaled first, then rotated counter-clockwise by rotation radians and
+/// then offset. The offset keeps moving by scroll_velocity uvs per second on the gpu, e.g. for
+/// conveyor belts or flowing lava
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct UvTransform {
+    pub offset: Vec2,
+    pub scale: Vec2,
+    pub rotation: f32,
+    pub scroll_velocity: Vec2,
+}
+
+impl Default for UvTransform {
+    fn default() -> Self {
+        Self {
+            offset: Vec2::ZERO,
+            scale: Vec2::ONE,
+            rotation: 0.0,
+            scroll_velocity: Vec2::ZERO,
+        }
+    }
+}
+
+impl UvTransform {
+    /// The rows of the 2x3 matrix translation * rotation * scale, without the scrolling
+    pub fn affine_rows(&self) -> [[f32; 3]; 2] {
+        let (sin, cos) = self.rotation.sin_cos();
+        [
+            [cos * self.scale.x, sin * self.scale.y, self.offset.x],
+            [-sin * self.scale.x, cos * self.scale.y, self.offset.y],
+        ]
+    }
+
+    pub fn apply(&self, uv: Vec2) -> Vec2 {
+        let [row_0, row_1] = self.affine_rows();
+        let uv = uv.extend(1.0);
+        Vec2::new(Vec3::from(row_0).dot(uv), Vec3::from(row_1).dot(uv))
+    }
+}
+
 #[derive(Debug, Default, Hash, PartialEq, Eq, Clone)]
 pub struct IndexedPbrTextures {
     pub base_color: Option<usize>,
@@ -147,6 +226,26 @@ pub struct IndexedPbrTextures {
     pub metallic_roughness: Option<usize>,
     pub emissive: Option<usize>,
     pub ambient_occlusion: Option<usize>,
+    pub lightmap: Option<usize>,
+}
+
+impl IndexedPbrTextures {
+    /// e.g. when the textures are appended to a list that already has some
+    pub fn offset(&mut self, texture_index_offset: usize) {
+        for texture_index in [
+            &mut self.base_color,
+            &mut self.normal,
+            &mut self.metallic_roughness,
+            &mut self.emissive,
+            &mut self.ambient_occlusion,
+            &mut self.lightmap,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *texture_index += texture_index_offset;
+        }
+    }
 }
 
 #[derive(Default)]
@@ -156,6 +255,8 @@ pub struct PbrTextures<'a> {
     pub metallic_roughness: Option<&'a Texture>,
     pub emissive: Option<&'a Texture>,
     pub ambient_occlusion: Option<&'a Texture>,
+    /// linear hdr, e.g. baked with LightmapBaker, sampled with Vertex::lightmap_tex_coords
+    pub lightmap: Option<&'a Texture>,
 }
 
 pub struct BasicMesh {
@@ -268,3 +369,421 @@ impl BasicMesh {
         Ok(BasicMesh { vertices, indices })
     }
 }
+
+#[derive(Debug, Copy, Clone)]
+pub struct MeshSimplificationParams {
+    /// each lod level keeps this proportion of the previous level's triangles. Every level is
+    /// simplified from the source mesh so the errors don't pile up from one level to the next
+    pub reduction_per_level: f32,
+    /// maximum geometric error allowed, relative to the size of the mesh's bounding box
+    pub max_error: f32,
+}
+
+impl Default for MeshSimplificationParams {
+    fn default() -> Self {
+        Self {
+            reduction_per_level: 0.5,
+            max_error: 0.02,
+        }
+    }
+}
+
+/// Generates up to lod_count reduced index buffers for the geometry, from most to least detailed.
+/// The lods share the source mesh's vertex buffer.
+/// Stops early if a level can't be reduced any further within the error bound.
+#[profiling::function]
+pub fn generate_lods(
+    geometry: &BindableGeometryBuffers,
+    lod_count: usize,
+    params: MeshSimplificationParams,
+) -> Vec<BindableIndices> {
+    let source_indices = geometry.indices.to_u32_vec();
+
+    let mesh_size = (geometry.bounding_box.max - geometry.bounding_box.min).length();
+    let max_error = params.max_error * mesh_size;
+
+    let mut lods = vec![];
+    let mut previous_index_count = source_indices.len();
+    for level in 1..=lod_count {
+        let kept_proportion = params.reduction_per_level.powi(level as i32);
+        let target_index_count = ((source_indices.len() as f32 * kept_proportion) as usize / 3) * 3;
+        let lod_indices = simplify_mesh_indices(
+            &geometry.vertices,
+            &source_indices,
+            target_index_count,
+            max_error,
+        );
+
+        if lod_indices.is_empty() || lod_indices.len() >= previous_index_count {
+            break;
+        }
+        previous_index_count = lod_indices.len();
+
+        lods.push(match geometry.indices {
+            BindableIndices::U16(_) => {
+                BindableIndices::U16(lod_indices.iter().map(|index| *index as u16).collect())
+            }
+            BindableIndices::U32(_) => BindableIndices::U32(lod_indices),
+        });
+    }
+
+    lods
+}
+
+/// Quadric error metric edge-collapse simplification (Garland & Heckbert), restricted
+/// to collapsing vertices onto their neighbors so the vertex buffer can be reused as-is.
+///
+/// Border vertices, which include the vertices along uv/normal seams since those are split
+/// in the vertex buffer, are never moved to avoid opening holes in the mesh.
+///
+/// max_error is in world units. A collapse is rejected when the new position's root mean
+/// square distance to the planes of the original triangles around it, weighted by their
+/// area, is larger
+#[profiling::function]
+pub fn simplify_mesh_indices(
+    vertices: &[Vertex],
+    indices: &[u32],
+    target_index_count: usize,
+    max_error: f32,
+) -> Vec<u32> {
+    let positions: Vec<Vec3> = vertices
+        .iter()
+        .map(|vertex| Vec3::from(vertex.position))
+        .collect();
+
+    let mut triangles: Vec<[u32; 3]> = indices
+        .chunks_exact(3)
+        .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+        .collect();
+    let mut triangle_alive = vec![true; triangles.len()];
+    let mut alive_triangle_count = triangles.len();
+
+    let mut quadrics = vec![Quadric::default(); vertices.len()];
+    let mut vertex_triangles: Vec<Vec<usize>> = vec![vec![]; vertices.len()];
+    // (min vertex, max vertex) -> number of triangles using the edge
+    let mut edge_use_counts: HashMap<(u32, u32), u32> = HashMap::new();
+
+    for (triangle_index, triangle) in triangles.iter().enumerate() {
+        let quadric = Quadric::from_triangle(
+            positions[triangle[0] as usize],
+            positions[triangle[1] as usize],
+            positions[triangle[2] as usize],
+        );
+        for i in 0..3 {
+            let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+            *edge_use_counts.entry((a.min(b), a.max(b))).or_default() += 1;
+            quadrics[a as usize].add(&quadric);
+            vertex_triangles[a as usize].push(triangle_index);
+        }
+    }
+
+    let mut is_locked = vec![false; vertices.len()];
+    for ((a, b), use_count) in &edge_use_counts {
+        if *use_count != 2 {
+            is_locked[*a as usize] = true;
+            is_locked[*b as usize] = true;
+        }
+    }
+
+    let max_error_sq = (max_error * max_error) as f64;
+
+    let mut collapse_queue = BinaryHeap::new();
+    for (a, b) in edge_use_counts.keys() {
+        for (from, to) in [(*a, *b), (*b, *a)] {
+            if is_locked[from as usize] {
+                continue;
+            }
+            let mut quadric = quadrics[from as usize];
+            quadric.add(&quadrics[to as usize]);
+            let cost = quadric.error(positions[to as usize]);
+            if cost <= max_error_sq {
+                collapse_queue.push(EdgeCollapse { cost, from, to });
+            }
+        }
+    }
+
+    // vertex -> vertex it was collapsed into, or itself
+    let mut remap: Vec<u32> = (0..vertices.len() as u32).collect();
+    let resolve = |remap: &[u32], mut vertex: u32| {
+        while remap[vertex as usize] != vertex {
+            vertex = remap[vertex as usize];
+        }
+        vertex
+    };
+
+    while alive_triangle_count * 3 > target_index_count {
+        let Some(EdgeCollapse { cost, from, to }) = collapse_queue.pop() else {
+            break;
+        };
+
+        if remap[from as usize] != from {
+            continue;
+        }
+        let to = resolve(&remap, to);
+        if to == from {
+            continue;
+        }
+
+        // the quadrics may have grown since the collapse was queued
+        let mut merged_quadric = quadrics[from as usize];
+        merged_quadric.add(&quadrics[to as usize]);
+        let current_cost = merged_quadric.error(positions[to as usize]);
+        if current_cost > max_error_sq {
+            continue;
+        }
+        if current_cost > cost + f64::EPSILON {
+            collapse_queue.push(EdgeCollapse {
+                cost: current_cost,
+                from,
+                to,
+            });
+            continue;
+        }
+
+        let flips_a_triangle = vertex_triangles[from as usize]
+            .iter()
+            .any(|triangle_index| {
+                if !triangle_alive[*triangle_index] {
+                    return false;
+                }
+                let triangle = triangles[*triangle_index];
+                if triangle.contains(&to) {
+                    return false; // will become degenerate and get removed
+                }
+                let [p0, p1, p2] = triangle.map(|vertex| positions[vertex as usize]);
+                let moved = triangle.map(|vertex| {
+                    if vertex == from {
+                        positions[to as usize]
+                    } else {
+                        positions[vertex as usize]
+                    }
+                });
+                let old_normal = (p1 - p0).cross(p2 - p0);
+                let new_normal = (moved[1] - moved[0]).cross(moved[2] - moved[0]);
+                old_normal.dot(new_normal) <= 0.0
+            });
+        if flips_a_triangle {
+            continue;
+        }
+
+        remap[from as usize] = to;
+        quadrics[to as usize] = merged_quadric;
+
+        let from_triangles = std::mem::take(&mut vertex_triangles[from as usize]);
+        for triangle_index in &from_triangles {
+            if !triangle_alive[*triangle_index] {
+                continue;
+            }
+            let triangle = &mut triangles[*triangle_index];
+            for vertex in triangle.iter_mut() {
+                if *vertex == from {
+                    *vertex = to;
+                }
+            }
+            if triangle[0] == triangle[1]
+                || triangle[1] == triangle[2]
+                || triangle[2] == triangle[0]
+            {
+                triangle_alive[*triangle_index] = false;
+                alive_triangle_count -= 1;
+            } else {
+                // queue up the collapses of the new edges around the merged vertex
+                for neighbor in *triangle {
+                    if neighbor == to || is_locked[to as usize] {
+                        continue;
+                    }
+                    let mut quadric = quadrics[to as usize];
+                    quadric.add(&quadrics[neighbor as usize]);
+                    let cost = quadric.error(positions[neighbor as usize]);
+                    if cost <= max_error_sq {
+                        collapse_queue.push(EdgeCollapse {
+                            cost,
+                            from: to,
+                            to: neighbor,
+                        });
+                    }
+                }
+            }
+        }
+        vertex_triangles[to as usize].extend(from_triangles);
+    }
+
+    triangles
+        .iter()
+        .zip(triangle_alive.iter())
+        .filter(|(_, alive)| **alive)
+        .flat_map(|(triangle, _)| *triangle)
+        .collect()
+}
+
+/// symmetric 4x4 matrix, stored as its upper triangle, along with the total area of the
+/// triangles that were summed into it
+#[derive(Debug, Copy, Clone, Default)]
+struct Quadric([f64; 10], f64);
+
+impl Quadric {
+    /// area-weighted so that large triangles dominate the error
+    fn from_triangle(p0: Vec3, p1: Vec3, p2: Vec3) -> Self {
+        let normal = (p1 - p0).cross(p2 - p0);
+        let area = normal.length() as f64 * 0.5;
+        if area <= f64::EPSILON {
+            return Self::default();
+        }
+        let normal = normal.normalize();
+        let (a, b, c) = (normal.x as f64, normal.y as f64, normal.z as f64);
+        let d = -(normal.dot(p0) as f64);
+        Self(
+            [
+                a * a * area,
+                a * b * area,
+                a * c * area,
+                a * d * area,
+                b * b * area,
+                b * c * area,
+                b * d * area,
+                c * c * area,
+                c * d * area,
+                d * d * area,
+            ],
+            area,
+        )
+    }
+
+    fn add(&mut self, other: &Self) {
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            *a += b;
+        }
+        self.1 += other.1;
+    }
+
+    /// mean squared distance to the planes, weighted by area. Dividing by the area keeps
+    /// it in world units squared no matter how big the triangles are
+    fn error(&self, point: Vec3) -> f64 {
+        if self.1 <= f64::EPSILON {
+            return 0.0;
+        }
+        let [aa, ab, ac, ad, bb, bc, bd, cc, cd, dd] = self.0;
+        let (x, y, z) = (point.x as f64, point.y as f64, point.z as f64);
+        let weighted_error = (aa * x * x + 2.0 * ab * x * y + 2.0 * ac * x * z + 2.0 * ad * x)
+            + (bb * y * y + 2.0 * bc * y * z + 2.0 * bd * y)
+            + (cc * z * z + 2.0 * cd * z)
+            + dd;
+        (weighted_error / self.1).max(0.0)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct EdgeCollapse {
+    cost: f64,
+    from: u32,
+    to: u32,
+}
+
+impl PartialEq for EdgeCollapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for EdgeCollapse {}
+
+impl PartialOrd for EdgeCollapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for EdgeCollapse {
+    // reversed so the BinaryHeap pops the cheapest collapse first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn simplifying_a_flat_grid_keeps_its_border() {
+        let grid_size = 8;
+        let vertices: Vec<Vertex> = (0..=grid_size)
+            .flat_map(|z| {
+                (0..=grid_size).map(move |x| Vertex {
+                    position: [x as f32, 0.0, z as f32],
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let row_length = grid_size + 1;
+        let indices: Vec<u32> = (0..grid_size)
+            .flat_map(|z| {
+                (0..grid_size).flat_map(move |x| {
+                    let i = z * row_length + x;
+                    [
+                        i,
+                        i + row_length,
+                        i + 1,
+                        i + 1,
+                        i + row_length,
+                        i + row_length + 1,
+                    ]
+                })
+            })
+            .collect();
+
+        let simplified = simplify_mesh_indices(&vertices, &indices, 0, 0.001);
+
+        assert!(simplified.len() < indices.len());
+        assert_eq!(simplified.len() % 3, 0);
+
+        // the area of a planar mesh is unchanged if no border vertex moved
+        let area = |indices: &[u32]| -> f32 {
+            indices
+                .chunks_exact(3)
+                .map(|triangle| {
+                    let [p0, p1, p2] =
+                        [0, 1, 2].map(|i| Vec3::from(vertices[triangle[i] as usize].position));
+                    (p1 - p0).cross(p2 - p0).length() * 0.5
+                })
+                .sum()
+        };
+        assert!((area(&indices) - area(&simplified)).abs() < 0.001);
+    }
+
+    #[test]
+    fn quadric_error_is_a_squared_distance() {
+        let mut quadric = Quadric::from_triangle(
+            Vec3::ZERO,
+            Vec3::new(0.0, 0.0, 10.0),
+            Vec3::new(10.0, 0.0, 0.0),
+        );
+        quadric.add(&Quadric::from_triangle(
+            Vec3::ZERO,
+            Vec3::new(0.0, 0.0, 0.1),
+            Vec3::new(0.1, 0.0, 0.0),
+        ));
+        // independent of the triangles' sizes
+        assert!((quadric.error(Vec3::new(3.0, 2.0, -1.0)) - 4.0).abs() < 1e-9);
+        assert!(quadric.error(Vec3::new(5.0, 0.0, 5.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn uv_transform_matches_khr_texture_transform() {
+        assert_eq!(
+            UvTransform::default().apply(Vec2::new(0.25, 0.75)),
+            Vec2::new(0.25, 0.75)
+        );
+
+        let transform = UvTransform {
+            offset: Vec2::new(0.5, 0.0),
+            scale: Vec2::new(2.0, 1.0),
+            rotation: std::f32::consts::FRAC_PI_2,
+            ..Default::default()
+        };
+        // u is scaled to 2, then rotated onto -v since v points down in uv space
+        let uv = transform.apply(Vec2::new(1.0, 0.0));
+        assert!(uv.abs_diff_eq(Vec2::new(0.5, -2.0), 1e-6));
+    }
+}
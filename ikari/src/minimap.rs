@@ -0,0 +1,505 @@
+use crate::ecs::{SceneNode, Tags, World};
+use crate::physics::PhysicsState;
+use crate::scene::Scene;
+
+use std::collections::HashSet;
+
+use glam::f32::{Vec2, Vec3};
+use iced::widget::image;
+use rapier3d_f64::parry::query::PointQuery;
+use rapier3d_f64::prelude::*;
+
+/// the direction at the top of the map when it doesn't turn with the player
+const NORTH: Vec2 = Vec2::new(0.0, -1.0);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MinimapMarkerShape {
+    Circle,
+    Square,
+    /// points where the node faces
+    Arrow,
+}
+
+#[derive(Debug, Clone)]
+pub struct MinimapMarker {
+    pub shape: MinimapMarkerShape,
+    pub color: [u8; 4],
+    /// in pixels of the map
+    pub radius: f32,
+    /// drawn instead of the shape, centered on the node
+    pub icon: Option<::image::RgbaImage>,
+}
+
+impl MinimapMarker {
+    pub fn new(shape: MinimapMarkerShape, color: [u8; 4], radius: f32) -> Self {
+        Self {
+            shape,
+            color,
+            radius,
+            icon: None,
+        }
+    }
+}
+
+/// Hides the parts of the map that the player hasn't been close to yet
+#[derive(Debug, Clone)]
+pub struct FogOfWar {
+    pub cell_size: f32,
+    /// the cells whose center is within this distance of the player are revealed
+    pub reveal_radius: f32,
+    pub fog_color: [u8; 4],
+    revealed_cells: HashSet<(i32, i32)>,
+}
+
+impl FogOfWar {
+    pub fn new(cell_size: f32, reveal_radius: f32) -> Self {
+        Self {
+            cell_size,
+            reveal_radius,
+            fog_color: [20, 20, 24, 230],
+            revealed_cells: HashSet::new(),
+        }
+    }
+
+    pub fn reveal(&mut self, position: Vec3) {
+        let cell_size = self.cell_size.max(0.001);
+        let cell_radius = (self.reveal_radius / cell_size).ceil() as i32;
+        let (center_x, center_z) = self.cell(position);
+        for cell_x in center_x - cell_radius..=center_x + cell_radius {
+            for cell_z in center_z - cell_radius..=center_z + cell_radius {
+                let cell_center = (Vec2::new(cell_x as f32, cell_z as f32) + 0.5) * cell_size;
+                if cell_center.distance(Vec2::new(position.x, position.z)) <= self.reveal_radius {
+                    self.revealed_cells.insert((cell_x, cell_z));
+                }
+            }
+        }
+    }
+
+    pub fn is_revealed(&self, position: Vec3) -> bool {
+        self.revealed_cells.contains(&self.cell(position))
+    }
+
+    pub fn clear(&mut self) {
+        self.revealed_cells.clear();
+    }
+
+    fn cell(&self, position: Vec3) -> (i32, i32) {
+        let cell_size = self.cell_size.max(0.001);
+        (
+            (position.x / cell_size).floor() as i32,
+            (position.z / cell_size).floor() as i32,
+        )
+    }
+}
+
+/// A top-down map of the area around the player, drawn on the cpu into an image for
+/// HudWidget::image. The level layout comes from the static colliders around the player's
+/// height, and the entities that have a SceneNode and one of the tags of markers are drawn
+/// over it
+#[derive(Debug, Clone)]
+pub struct Minimap {
+    /// width and height of the image, in pixels
+    pub resolution: u32,
+    /// distance from the player to the edges of the map, smaller values zoom in
+    pub view_radius: f32,
+    /// turns the map so the player faces up, otherwise north (-z) is up
+    pub rotate_with_player: bool,
+    /// cuts off the corners
+    pub is_round: bool,
+    pub background_color: [u8; 4],
+    /// the color of the static colliders, e.g. the walls
+    pub level_color: [u8; 4],
+    /// (below, above) the player's position. The static colliders that reach into this range
+    /// are part of the layout, the floor under the player and the ceiling above aren't
+    pub level_height_range: (f32, f32),
+    /// (tag, marker), an entity gets the marker of the first tag it has
+    pub markers: Vec<(String, MinimapMarker)>,
+    /// drawn at the center of the map
+    pub player_marker: Option<MinimapMarker>,
+    pub fog_of_war: Option<FogOfWar>,
+    /// the last image returned by render
+    pixels: Vec<u8>,
+    next_pixels: Vec<u8>,
+}
+
+impl Minimap {
+    pub fn new(resolution: u32, view_radius: f32) -> Self {
+        Self {
+            resolution,
+            view_radius,
+            rotate_with_player: true,
+            is_round: true,
+            background_color: [40, 48, 40, 200],
+            level_color: [150, 150, 140, 230],
+            level_height_range: (0.5, 2.0),
+            markers: vec![],
+            player_marker: Some(MinimapMarker::new(
+                MinimapMarkerShape::Arrow,
+                [255, 255, 255, 255],
+                6.0,
+            )),
+            fog_of_war: None,
+            pixels: vec![],
+            next_pixels: vec![],
+        }
+    }
+
+    /// Multiplies the zoom, e.g. 2 shows half the distance
+    pub fn zoom(&mut self, factor: f32) {
+        self.view_radius = (self.view_radius / factor.max(0.001)).max(0.1);
+    }
+
+    /// Draws the map around the player, who is at position and looking towards forward.
+    /// Reveals the fog of war around the player. Returns the new image only when it's
+    /// different from the last one, so it isn't uploaded again every frame
+    pub fn render(
+        &mut self,
+        scene: &Scene,
+        world: &World,
+        physics_state: &PhysicsState,
+        position: Vec3,
+        forward: Vec3,
+    ) -> Option<image::Handle> {
+        let up = if self.rotate_with_player {
+            Vec2::new(forward.x, forward.z)
+                .try_normalize()
+                .unwrap_or(NORTH)
+        } else {
+            NORTH
+        };
+        let half_size = self.resolution as f32 * 0.5;
+        let view = MapView {
+            center: Vec2::new(position.x, position.z),
+            up,
+            right: Vec2::new(-up.y, up.x),
+            half_size,
+            pixels_per_unit: half_size / self.view_radius.max(0.001),
+        };
+        if let Some(fog_of_war) = &mut self.fog_of_war {
+            fog_of_war.reveal(position);
+        }
+
+        let size = self.resolution as usize;
+        self.next_pixels.clear();
+        self.next_pixels.resize(size * size * 4, 0);
+        let mut canvas = Canvas {
+            pixels: &mut self.next_pixels,
+            size,
+            is_round: self.is_round,
+        };
+        for y in 0..size {
+            for x in 0..size {
+                let pixel_center = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+                let is_fogged = self.fog_of_war.as_ref().is_some_and(|fog_of_war| {
+                    !fog_of_war.is_revealed(view.to_world_position(pixel_center))
+                });
+                let color = match &self.fog_of_war {
+                    Some(fog_of_war) if is_fogged => fog_of_war.fog_color,
+                    _ => self.background_color,
+                };
+                canvas.blend(x as i32, y as i32, color);
+            }
+        }
+
+        let min_height = (position.y - self.level_height_range.0) as f64;
+        let max_height = (position.y + self.level_height_range.1) as f64;
+        for (_, collider) in physics_state.collider_set.iter() {
+            let is_static = collider.parent().map_or(true, |handle| {
+                physics_state
+                    .rigid_body_set
+                    .get(handle)
+                    .map_or(true, |rigid_body| rigid_body.is_fixed())
+            });
+            if !is_static || collider.is_sensor() {
+                continue;
+            }
+            let aabb = collider.compute_aabb();
+            if aabb.maxs.y < min_height || aabb.mins.y > max_height {
+                continue;
+            }
+            // the footprint is taken halfway through the part that's within the range
+            let height = (aabb.mins.y.max(min_height) + aabb.maxs.y.min(max_height)) / 2.0;
+            let corners = [
+                (aabb.mins.x, aabb.mins.z),
+                (aabb.mins.x, aabb.maxs.z),
+                (aabb.maxs.x, aabb.mins.z),
+                (aabb.maxs.x, aabb.maxs.z),
+            ]
+            .map(|(x, z)| view.to_pixel(Vec3::new(x as f32, 0.0, z as f32)));
+            let min = corners
+                .iter()
+                .fold(Vec2::splat(f32::MAX), |min, corner| min.min(*corner))
+                .max(Vec2::ZERO)
+                .floor();
+            let max = corners
+                .iter()
+                .fold(Vec2::splat(f32::MIN), |max, corner| max.max(*corner))
+                .min(Vec2::splat(size as f32))
+                .ceil();
+            for y in min.y as i32..max.y as i32 {
+                for x in min.x as i32..max.x as i32 {
+                    let world_position =
+                        view.to_world_position(Vec2::new(x as f32 + 0.5, y as f32 + 0.5));
+                    if self
+                        .fog_of_war
+                        .as_ref()
+                        .is_some_and(|fog_of_war| !fog_of_war.is_revealed(world_position))
+                    {
+                        continue;
+                    }
+                    let point = point![world_position.x as f64, height, world_position.z as f64];
+                    if collider.shape().contains_point(collider.position(), &point) {
+                        canvas.blend(x, y, self.level_color);
+                    }
+                }
+            }
+        }
+
+        for (entity, tags) in world.query::<Tags>() {
+            let Some(marker) = self
+                .markers
+                .iter()
+                .find(|(tag, _)| tags.has(tag))
+                .map(|(_, marker)| marker)
+            else {
+                continue;
+            };
+            let Some(SceneNode(node_id)) = world.get::<SceneNode>(entity).copied() else {
+                continue;
+            };
+            if scene.get_node(node_id).is_none() {
+                continue;
+            }
+            let transform = scene.get_global_transform_for_node(node_id);
+            let node_position = transform.position();
+            if self
+                .fog_of_war
+                .as_ref()
+                .is_some_and(|fog_of_war| !fog_of_war.is_revealed(node_position))
+            {
+                continue;
+            }
+            canvas.draw_marker(
+                marker,
+                view.to_pixel(node_position),
+                view.to_pixel_direction(-Vec3::from(transform.z_axis)),
+            );
+        }
+
+        if let Some(player_marker) = &self.player_marker {
+            canvas.draw_marker(
+                player_marker,
+                Vec2::splat(half_size),
+                view.to_pixel_direction(forward),
+            );
+        }
+
+        if self.next_pixels == self.pixels {
+            return None;
+        }
+        std::mem::swap(&mut self.pixels, &mut self.next_pixels);
+        Some(image::Handle::from_pixels(
+            self.resolution,
+            self.resolution,
+            self.pixels.clone(),
+        ))
+    }
+}
+
+struct MapView {
+    /// the player's position on the xz plane
+    center: Vec2,
+    /// the directions on the xz plane that point to the top and the right of the map
+    up: Vec2,
+    right: Vec2,
+    half_size: f32,
+    pixels_per_unit: f32,
+}
+
+impl MapView {
+    fn to_pixel(&self, position: Vec3) -> Vec2 {
+        let offset = Vec2::new(position.x, position.z) - self.center;
+        Vec2::new(
+            self.half_size + offset.dot(self.right) * self.pixels_per_unit,
+            self.half_size - offset.dot(self.up) * self.pixels_per_unit,
+        )
+    }
+
+    /// At a height of 0
+    fn to_world_position(&self, pixel: Vec2) -> Vec3 {
+        let right_distance = (pixel.x - self.half_size) / self.pixels_per_unit;
+        let up_distance = (self.half_size - pixel.y) / self.pixels_per_unit;
+        let position = self.center + self.right * right_distance + self.up * up_distance;
+        Vec3::new(position.x, 0.0, position.y)
+    }
+
+    fn to_pixel_direction(&self, direction: Vec3) -> Vec2 {
+        let direction = Vec2::new(direction.x, direction.z);
+        Vec2::new(direction.dot(self.right), -direction.dot(self.up))
+            .try_normalize()
+            .unwrap_or(Vec2::new(0.0, -1.0))
+    }
+}
+
+struct Canvas<'a> {
+    /// rgba
+    pixels: &'a mut [u8],
+    size: usize,
+    is_round: bool,
+}
+
+impl Canvas<'_> {
+    /// Draws the color over the pixel, the pixels outside of the map are skipped
+    fn blend(&mut self, x: i32, y: i32, color: [u8; 4]) {
+        if x < 0 || y < 0 || x as usize >= self.size || y as usize >= self.size {
+            return;
+        }
+        if self.is_round {
+            let half_size = self.size as f32 * 0.5;
+            let offset = Vec2::new(x as f32 + 0.5, y as f32 + 0.5) - half_size;
+            if offset.length() > half_size {
+                return;
+            }
+        }
+        let index = (y as usize * self.size + x as usize) * 4;
+        let pixel = &mut self.pixels[index..index + 4];
+        let alpha = color[3] as f32 / 255.0;
+        let old_alpha = pixel[3] as f32 / 255.0;
+        let new_alpha = alpha + old_alpha * (1.0 - alpha);
+        if new_alpha <= 0.0 {
+            return;
+        }
+        for channel in 0..3 {
+            let value = (color[channel] as f32 * alpha
+                + pixel[channel] as f32 * old_alpha * (1.0 - alpha))
+                / new_alpha;
+            pixel[channel] = value.round() as u8;
+        }
+        pixel[3] = (new_alpha * 255.0).round() as u8;
+    }
+
+    fn draw_marker(&mut self, marker: &MinimapMarker, position: Vec2, direction: Vec2) {
+        if let Some(icon) = &marker.icon {
+            let corner_x = (position.x - icon.width() as f32 * 0.5).round() as i32;
+            let corner_y = (position.y - icon.height() as f32 * 0.5).round() as i32;
+            for (x, y, pixel) in icon.enumerate_pixels() {
+                self.blend(corner_x + x as i32, corner_y + y as i32, pixel.0);
+            }
+            return;
+        }
+
+        let radius = marker.radius.max(0.5);
+        let side = Vec2::new(-direction.y, direction.x);
+        let arrow = [
+            position + direction * radius,
+            position - direction * radius * 0.6 + side * radius * 0.7,
+            position - direction * radius * 0.6 - side * radius * 0.7,
+        ];
+        let min = (position - radius).floor();
+        let max = (position + radius).ceil();
+        for y in min.y as i32..max.y as i32 {
+            for x in min.x as i32..max.x as i32 {
+                let pixel_center = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+                let offset = pixel_center - position;
+                let is_inside = match marker.shape {
+                    MinimapMarkerShape::Circle => offset.length() <= radius,
+                    MinimapMarkerShape::Square => offset.abs().max_element() <= radius * 0.8,
+                    MinimapMarkerShape::Arrow => is_in_triangle(pixel_center, arrow),
+                };
+                if is_inside {
+                    self.blend(x, y, marker.color);
+                }
+            }
+        }
+    }
+}
+
+fn is_in_triangle(point: Vec2, [a, b, c]: [Vec2; 3]) -> bool {
+    let edge_side = |from: Vec2, to: Vec2| (to - from).perp_dot(point - from);
+    let sides = [edge_side(a, b), edge_side(b, c), edge_side(c, a)];
+    sides.iter().all(|side| *side >= 0.0) || sides.iter().all(|side| *side <= 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::GameNodeDesc;
+    use crate::transform::TransformBuilder;
+
+    fn pixel(minimap: &Minimap, x: usize, y: usize) -> [u8; 4] {
+        let index = (y * minimap.resolution as usize + x) * 4;
+        minimap.pixels[index..index + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn tagged_nodes_and_walls_are_drawn_around_the_player_under_the_fog() {
+        let mut scene = Scene::default();
+        let mut world = World::default();
+        let mut physics_state = PhysicsState::new();
+        // a wall to the west and the floor under the player, which isn't drawn
+        physics_state.collider_set.insert(
+            ColliderBuilder::cuboid(1.0, 1.0, 1.0)
+                .translation(vector![-5.0, 0.0, 0.0])
+                .build(),
+        );
+        physics_state.collider_set.insert(
+            ColliderBuilder::cuboid(20.0, 0.5, 20.0)
+                .translation(vector![0.0, -1.5, 0.0])
+                .build(),
+        );
+        let enemy_node_id = scene
+            .add_node(GameNodeDesc {
+                transform: TransformBuilder::new()
+                    .position(Vec3::new(5.0, 0.0, 0.0))
+                    .build(),
+                ..Default::default()
+            })
+            .id();
+        let enemy = world.spawn();
+        world.insert(enemy, SceneNode(enemy_node_id));
+        world.insert(enemy, Tags::new(&["enemy"]));
+
+        let red = [255, 0, 0, 255];
+        let green = [0, 255, 0, 255];
+        let mut minimap = Minimap {
+            is_round: false,
+            player_marker: None,
+            background_color: [0, 0, 0, 255],
+            level_color: green,
+            markers: vec![(
+                "enemy".to_string(),
+                MinimapMarker::new(MinimapMarkerShape::Circle, red, 2.0),
+            )],
+            ..Minimap::new(20, 10.0)
+        };
+
+        // north is up so the enemy is to the right
+        minimap.rotate_with_player = false;
+        assert!(minimap
+            .render(&scene, &world, &physics_state, Vec3::ZERO, Vec3::X)
+            .is_some());
+        assert_eq!(pixel(&minimap, 15, 10), red);
+        assert_eq!(pixel(&minimap, 5, 10), green);
+        assert_eq!(pixel(&minimap, 10, 5), [0, 0, 0, 255]);
+        // nothing changed so the last image is kept
+        assert!(minimap
+            .render(&scene, &world, &physics_state, Vec3::ZERO, Vec3::X)
+            .is_none());
+
+        // the player faces the enemy, which is now up
+        minimap.rotate_with_player = true;
+        assert!(minimap
+            .render(&scene, &world, &physics_state, Vec3::ZERO, Vec3::X)
+            .is_some());
+        assert_eq!(pixel(&minimap, 10, 5), red);
+        assert_eq!(pixel(&minimap, 10, 15), green);
+        assert_eq!(pixel(&minimap, 15, 10), [0, 0, 0, 255]);
+
+        minimap.zoom(2.0);
+        minimap.fog_of_war = Some(FogOfWar::new(1.0, 2.0));
+        minimap.render(&scene, &world, &physics_state, Vec3::ZERO, Vec3::X);
+        // the enemy is hidden in the fog and the area around the player is revealed
+        assert_eq!(pixel(&minimap, 10, 0), FogOfWar::new(1.0, 2.0).fog_color);
+        assert_eq!(pixel(&minimap, 10, 10), [0, 0, 0, 255]);
+    }
+}
@@ -0,0 +1,149 @@
+use crate::audio::AudioManager;
+use crate::game_state_stack::GameStateKind;
+
+#[derive(Debug, Copy, Clone)]
+struct MusicTrack {
+    sound_index: usize,
+    /// 0..1, multiplied with MusicPlayer::volume
+    fade: f32,
+    fade_seconds: f32,
+}
+
+/// Plays one music track at a time, crossfading when the track changes.
+/// The tracks are regular sounds, ideally loaded with stream and looping set so that long
+/// tracks aren't decoded into memory all at once
+#[derive(Debug)]
+pub struct MusicPlayer {
+    current_track: Option<MusicTrack>,
+    fading_out_tracks: Vec<MusicTrack>,
+    is_paused: bool,
+    pub volume: f32,
+    /// the music is paused while one of these is the current game state
+    pub paused_states: Vec<GameStateKind>,
+}
+
+impl Default for MusicPlayer {
+    fn default() -> Self {
+        Self {
+            current_track: None,
+            fading_out_tracks: vec![],
+            is_paused: false,
+            volume: 1.0,
+            paused_states: vec![GameStateKind::Paused],
+        }
+    }
+}
+
+impl MusicPlayer {
+    pub fn current_track(&self) -> Option<usize> {
+        self.current_track.map(|track| track.sound_index)
+    }
+
+    /// Fades the current track out while the new one fades in, both over crossfade_seconds.
+    /// A track that was faded out resumes from where it stopped
+    pub fn play(
+        &mut self,
+        audio_manager: &mut AudioManager,
+        sound_index: usize,
+        crossfade_seconds: f32,
+    ) {
+        if self.current_track() == Some(sound_index) {
+            return;
+        }
+
+        let mut fade = 0.0;
+        // it might still be fading out from a previous switch
+        if let Some(position) = self
+            .fading_out_tracks
+            .iter()
+            .position(|track| track.sound_index == sound_index)
+        {
+            fade = self.fading_out_tracks.remove(position).fade;
+        }
+        if let Some(current_track) = self.current_track.take() {
+            self.fading_out_tracks.push(MusicTrack {
+                fade_seconds: crossfade_seconds,
+                ..current_track
+            });
+        }
+        if crossfade_seconds <= 0.0 {
+            fade = 1.0;
+        }
+        self.current_track = Some(MusicTrack {
+            sound_index,
+            fade,
+            fade_seconds: crossfade_seconds,
+        });
+
+        audio_manager.set_sound_volume(sound_index, self.volume * fade);
+        if !self.is_paused {
+            audio_manager.play_sound(sound_index);
+        }
+    }
+
+    /// Fades out the current track
+    pub fn stop(&mut self, fade_out_seconds: f32) {
+        if let Some(current_track) = self.current_track.take() {
+            self.fading_out_tracks.push(MusicTrack {
+                fade_seconds: fade_out_seconds,
+                ..current_track
+            });
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.is_paused
+    }
+
+    /// Advances the fades and pauses or resumes the music to follow the game state,
+    /// should be called once per frame
+    pub fn update(
+        &mut self,
+        audio_manager: &mut AudioManager,
+        game_state: GameStateKind,
+        frame_time_seconds: f32,
+    ) {
+        let should_pause = self.paused_states.contains(&game_state);
+        if should_pause != self.is_paused {
+            self.is_paused = should_pause;
+            for track in self
+                .current_track
+                .iter()
+                .chain(self.fading_out_tracks.iter())
+            {
+                if should_pause {
+                    audio_manager.pause_sound(track.sound_index);
+                } else {
+                    audio_manager.play_sound(track.sound_index);
+                }
+            }
+        }
+        if self.is_paused {
+            return;
+        }
+
+        let fade_step = |fade_seconds: f32| {
+            if fade_seconds > 0.0 {
+                frame_time_seconds / fade_seconds
+            } else {
+                1.0
+            }
+        };
+
+        if let Some(track) = self.current_track.as_mut() {
+            track.fade = (track.fade + fade_step(track.fade_seconds)).min(1.0);
+            audio_manager.set_sound_volume(track.sound_index, self.volume * track.fade);
+        }
+
+        let volume = self.volume;
+        self.fading_out_tracks.retain_mut(|track| {
+            track.fade = (track.fade - fade_step(track.fade_seconds)).max(0.0);
+            audio_manager.set_sound_volume(track.sound_index, volume * track.fade);
+            if track.fade == 0.0 {
+                audio_manager.pause_sound(track.sound_index);
+                return false;
+            }
+            true
+        });
+    }
+}
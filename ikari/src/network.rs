@@ -0,0 +1,1287 @@
+use crate::scene::*;
+use crate::time::*;
+use crate::transform::*;
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use anyhow::bail;
+use glam::f32::{Quat, Vec3};
+use serde::{Deserialize, Serialize};
+
+/// packets from other applications or from incompatible versions of the protocol are ignored
+const PROTOCOL_ID: u32 = 0x696b_6172;
+/// stays under the usual internet MTU to avoid IP fragmentation
+const MAX_PACKET_PAYLOAD_SIZE: usize = 1200;
+const MAX_RECEIVED_PACKET_SIZE: usize = 65536;
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+const CONNECT_REQUEST_INTERVAL: Duration = Duration::from_millis(250);
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_millis(250);
+const MIN_RELIABLE_RESEND_INTERVAL: Duration = Duration::from_millis(50);
+/// how many sent packets are remembered while waiting for their ack
+const MAX_TRACKED_SENT_PACKETS: usize = 1024;
+/// reliable messages are only sent and accepted up to this many ids past the oldest one that
+/// hasn't arrived yet, so a peer can't make the other side buffer an unbounded number of them
+const RELIABLE_MESSAGE_WINDOW_SIZE: u32 = 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Channel {
+    /// resent until acknowledged and delivered in the order they were sent
+    Reliable,
+    /// may be dropped or arrive out of order, for state that is sent continuously
+    Unreliable,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ClientId(pub u32);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum NetworkMessage {
+    Replication(ReplicationMessage),
+    /// anything specific to the game, e.g. player inputs serialized with rmp_serde
+    Game(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum PacketKind {
+    ConnectRequest,
+    ConnectAccepted {
+        client_id: ClientId,
+    },
+    ConnectRejected,
+    Disconnect,
+    Payload {
+        reliable_messages: Vec<(u32, NetworkMessage)>,
+        unreliable_messages: Vec<NetworkMessage>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Packet {
+    protocol_id: u32,
+    sequence: u32,
+    /// most recent sequence received from the other side and a bitfield
+    /// of the 32 sequences before it, 1 meaning received
+    ack: Option<u32>,
+    ack_bits: u32,
+    kind: PacketKind,
+}
+
+/// Reliability layer on top of UDP, one per peer
+#[derive(Debug)]
+struct Connection {
+    address: SocketAddr,
+
+    local_sequence: u32,
+    remote_sequence: Option<u32>,
+    remote_ack_bits: u32,
+    /// sequence -> (send time, ids of the reliable messages it contained)
+    sent_packets: HashMap<u32, (Instant, Vec<u32>)>,
+
+    next_outgoing_reliable_id: u32,
+    /// id -> (message, encoded size, last send time)
+    outgoing_reliable_messages: BTreeMap<u32, (NetworkMessage, usize, Option<Instant>)>,
+    outgoing_unreliable_messages: Vec<(NetworkMessage, usize)>,
+
+    next_incoming_reliable_id: u32,
+    /// reliable messages that arrived before some of the ones preceding them
+    incoming_reliable_messages: BTreeMap<u32, NetworkMessage>,
+
+    last_receive_time: Instant,
+    last_send_time: Option<Instant>,
+    rtt_seconds: f64,
+}
+
+impl Connection {
+    fn new(address: SocketAddr) -> Self {
+        Self {
+            address,
+
+            local_sequence: 0,
+            remote_sequence: None,
+            remote_ack_bits: 0,
+            sent_packets: HashMap::new(),
+
+            next_outgoing_reliable_id: 0,
+            outgoing_reliable_messages: BTreeMap::new(),
+            outgoing_unreliable_messages: vec![],
+
+            next_incoming_reliable_id: 0,
+            incoming_reliable_messages: BTreeMap::new(),
+
+            last_receive_time: Instant::now(),
+            last_send_time: None,
+            rtt_seconds: 0.1,
+        }
+    }
+
+    /// Fails for reliable messages that don't fit in a packet, the unreliable ones are dropped
+    fn queue_message(&mut self, channel: Channel, message: NetworkMessage) -> anyhow::Result<()> {
+        let encoded_size = rmp_serde::to_vec(&message)?.len();
+        match channel {
+            Channel::Reliable => {
+                if encoded_size > MAX_PACKET_PAYLOAD_SIZE {
+                    bail!(
+                        "A reliable network message of {encoded_size} bytes doesn't fit in a packet"
+                    );
+                }
+                let id = self.next_outgoing_reliable_id;
+                self.next_outgoing_reliable_id = self.next_outgoing_reliable_id.wrapping_add(1);
+                self.outgoing_reliable_messages
+                    .insert(id, (message, encoded_size, None));
+            }
+            Channel::Unreliable => {
+                if encoded_size > MAX_PACKET_PAYLOAD_SIZE {
+                    log::warn!(
+                        "Dropped an unreliable network message of {encoded_size} bytes, it doesn't fit in a packet"
+                    );
+                    return Ok(());
+                }
+                self.outgoing_unreliable_messages
+                    .push((message, encoded_size));
+            }
+        }
+        Ok(())
+    }
+
+    fn make_packet(&mut self, kind: PacketKind) -> Packet {
+        let sequence = self.local_sequence;
+        self.local_sequence = self.local_sequence.wrapping_add(1);
+        Packet {
+            protocol_id: PROTOCOL_ID,
+            sequence,
+            ack: self.remote_sequence,
+            ack_bits: self.remote_ack_bits,
+            kind,
+        }
+    }
+
+    /// Packs the queued messages into as few packets as possible. The reliable messages
+    /// that weren't acknowledged in time are sent again
+    fn make_payload_packets(&mut self, now: Instant) -> Vec<Packet> {
+        let resend_interval =
+            Duration::from_secs_f64(self.rtt_seconds * 1.5).max(MIN_RELIABLE_RESEND_INTERVAL);
+
+        let mut packets = vec![];
+        let mut reliable_messages = vec![];
+        let mut unreliable_messages = vec![];
+        let mut payload_size = 0;
+
+        let mut flush = |connection: &mut Connection,
+                         reliable_messages: &mut Vec<(u32, NetworkMessage)>,
+                         unreliable_messages: &mut Vec<NetworkMessage>| {
+            if reliable_messages.is_empty() && unreliable_messages.is_empty() {
+                return;
+            }
+            let reliable_ids = reliable_messages.iter().map(|(id, _)| *id).collect();
+            let packet = connection.make_packet(PacketKind::Payload {
+                reliable_messages: std::mem::take(reliable_messages),
+                unreliable_messages: std::mem::take(unreliable_messages),
+            });
+            connection
+                .sent_packets
+                .insert(packet.sequence, (now, reliable_ids));
+            packets.push(packet);
+        };
+
+        // the ones past the window wait until the oldest ones are acked, the other side
+        // would drop them
+        let window_start = self.outgoing_reliable_messages.keys().next().copied();
+        let due_reliable_ids: Vec<u32> = self
+            .outgoing_reliable_messages
+            .iter()
+            .filter(|(id, (_, _, last_send_time))| {
+                let is_in_window = window_start.map_or(true, |window_start| {
+                    id.wrapping_sub(window_start) < RELIABLE_MESSAGE_WINDOW_SIZE
+                });
+                is_in_window
+                    && last_send_time.map_or(true, |last_send_time| {
+                        now.duration_since(last_send_time) >= resend_interval
+                    })
+            })
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in due_reliable_ids {
+            let encoded_size = self.outgoing_reliable_messages[&id].1;
+            if payload_size + encoded_size > MAX_PACKET_PAYLOAD_SIZE {
+                flush(self, &mut reliable_messages, &mut unreliable_messages);
+                payload_size = 0;
+            }
+            let (message, _, last_send_time) =
+                self.outgoing_reliable_messages.get_mut(&id).unwrap();
+            *last_send_time = Some(now);
+            payload_size += encoded_size;
+            reliable_messages.push((id, message.clone()));
+        }
+
+        for (message, encoded_size) in std::mem::take(&mut self.outgoing_unreliable_messages) {
+            if payload_size + encoded_size > MAX_PACKET_PAYLOAD_SIZE {
+                flush(self, &mut reliable_messages, &mut unreliable_messages);
+                payload_size = 0;
+            }
+            payload_size += encoded_size;
+            unreliable_messages.push(message);
+        }
+
+        flush(self, &mut reliable_messages, &mut unreliable_messages);
+
+        // the other side needs to hear from us regularly to keep the connection alive
+        // and to get our acks
+        let needs_keep_alive = self.last_send_time.map_or(true, |last_send_time| {
+            now.duration_since(last_send_time) >= KEEP_ALIVE_INTERVAL
+        });
+        if packets.is_empty() && needs_keep_alive {
+            packets.push(self.make_packet(PacketKind::Payload {
+                reliable_messages: vec![],
+                unreliable_messages: vec![],
+            }));
+        }
+
+        if !packets.is_empty() {
+            self.last_send_time = Some(now);
+        }
+
+        if self.sent_packets.len() > MAX_TRACKED_SENT_PACKETS {
+            // the acks for these will never come, their messages are resent anyway
+            let oldest_kept_sequence = self
+                .local_sequence
+                .wrapping_sub(MAX_TRACKED_SENT_PACKETS as u32);
+            self.sent_packets
+                .retain(|sequence, _| !sequence_greater_than(oldest_kept_sequence, *sequence));
+        }
+
+        packets
+    }
+
+    /// returns the messages that are ready to be handled, in order for the reliable ones
+    fn process_packet(&mut self, packet: Packet, now: Instant) -> Vec<NetworkMessage> {
+        self.last_receive_time = now;
+
+        // duplicates are dropped, the reliable messages they contain were already received
+        if !self.record_received_sequence(packet.sequence) {
+            return vec![];
+        }
+
+        if let Some(ack) = packet.ack {
+            self.process_acks(ack, packet.ack_bits, now);
+        }
+
+        let PacketKind::Payload {
+            reliable_messages,
+            unreliable_messages,
+        } = packet.kind
+        else {
+            return vec![];
+        };
+
+        for (id, message) in reliable_messages {
+            if sequence_greater_than(self.next_incoming_reliable_id, id) {
+                continue;
+            }
+            if id.wrapping_sub(self.next_incoming_reliable_id) >= RELIABLE_MESSAGE_WINDOW_SIZE {
+                log::warn!(
+                    "Dropped reliable network message {id} from {}, it's too far ahead",
+                    self.address
+                );
+                continue;
+            }
+            self.incoming_reliable_messages.insert(id, message);
+        }
+
+        let mut messages = vec![];
+        while let Some(message) = self
+            .incoming_reliable_messages
+            .remove(&self.next_incoming_reliable_id)
+        {
+            messages.push(message);
+            self.next_incoming_reliable_id = self.next_incoming_reliable_id.wrapping_add(1);
+        }
+        messages.extend(unreliable_messages);
+
+        messages
+    }
+
+    /// returns false if the packet was already received or is too old to tell
+    fn record_received_sequence(&mut self, sequence: u32) -> bool {
+        let Some(remote_sequence) = self.remote_sequence else {
+            self.remote_sequence = Some(sequence);
+            self.remote_ack_bits = 0;
+            return true;
+        };
+
+        if sequence == remote_sequence {
+            return false;
+        }
+
+        if sequence_greater_than(sequence, remote_sequence) {
+            let shift = sequence.wrapping_sub(remote_sequence);
+            self.remote_ack_bits = if shift > 32 {
+                0
+            } else {
+                // the previous remote_sequence becomes bit shift - 1
+                (((self.remote_ack_bits as u64) << shift) | (1 << (shift - 1))) as u32
+            };
+            self.remote_sequence = Some(sequence);
+            true
+        } else {
+            let age = remote_sequence.wrapping_sub(sequence);
+            if age > 32 {
+                return false;
+            }
+            let bit = 1 << (age - 1);
+            let is_new = self.remote_ack_bits & bit == 0;
+            self.remote_ack_bits |= bit;
+            is_new
+        }
+    }
+
+    fn process_acks(&mut self, ack: u32, ack_bits: u32, now: Instant) {
+        let acked_sequences = std::iter::once(ack).chain(
+            (0..32)
+                .filter(|bit| ack_bits & (1 << bit) != 0)
+                .map(|bit| ack.wrapping_sub(bit + 1)),
+        );
+        for sequence in acked_sequences {
+            let Some((send_time, reliable_ids)) = self.sent_packets.remove(&sequence) else {
+                continue;
+            };
+            if sequence == ack {
+                let rtt_sample = now.duration_since(send_time).as_secs_f64();
+                self.rtt_seconds = self.rtt_seconds * 0.9 + rtt_sample * 0.1;
+            }
+            for id in reliable_ids {
+                self.outgoing_reliable_messages.remove(&id);
+            }
+        }
+    }
+
+    fn is_timed_out(&self, now: Instant) -> bool {
+        now.duration_since(self.last_receive_time) > CONNECTION_TIMEOUT
+    }
+}
+
+/// wrapping comparison, so the sequence numbers can overflow
+fn sequence_greater_than(a: u32, b: u32) -> bool {
+    a != b && a.wrapping_sub(b) < u32::MAX / 2
+}
+
+fn send_packet(socket: &UdpSocket, address: SocketAddr, packet: &Packet) {
+    let bytes = match rmp_serde::to_vec(packet) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            log::error!("Failed to encode network packet: {err}");
+            return;
+        }
+    };
+    if let Err(err) = socket.send_to(&bytes, address) {
+        if err.kind() != std::io::ErrorKind::WouldBlock {
+            log::warn!("Failed to send network packet to {address}: {err}");
+        }
+    }
+}
+
+/// returns None once there is nothing left to read. the inner option is None
+/// for datagrams that aren't valid packets of this protocol
+fn receive_packet(socket: &UdpSocket, buffer: &mut [u8]) -> Option<Option<(SocketAddr, Packet)>> {
+    match socket.recv_from(buffer) {
+        Ok((size, address)) => Some(
+            rmp_serde::from_slice::<Packet>(&buffer[..size])
+                .ok()
+                .filter(|packet| packet.protocol_id == PROTOCOL_ID)
+                .map(|packet| (address, packet)),
+        ),
+        Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => None,
+        // on windows, an ICMP port unreachable caused by a previous send shows up here
+        Err(err) if err.kind() == std::io::ErrorKind::ConnectionReset => Some(None),
+        Err(err) => {
+            log::warn!("Failed to receive network packet: {err}");
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ServerEvent {
+    ClientConnected(ClientId),
+    /// timed out or disconnected on purpose
+    ClientDisconnected(ClientId),
+    Message(ClientId, NetworkMessage),
+}
+
+pub struct NetworkServer {
+    socket: UdpSocket,
+    connections: HashMap<ClientId, Connection>,
+    client_ids: HashMap<SocketAddr, ClientId>,
+    next_client_id: u32,
+    pub max_clients: usize,
+    events: Vec<ServerEvent>,
+    receive_buffer: Vec<u8>,
+}
+
+impl NetworkServer {
+    pub fn bind(address: impl ToSocketAddrs) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind(address)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            connections: HashMap::new(),
+            client_ids: HashMap::new(),
+            next_client_id: 0,
+            max_clients: 16,
+            events: vec![],
+            receive_buffer: vec![0; MAX_RECEIVED_PACKET_SIZE],
+        })
+    }
+
+    pub fn local_address(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Receives the incoming packets and sends out the queued messages,
+    /// should be called once per frame or fixed update
+    #[profiling::function]
+    pub fn update(&mut self) {
+        let now = Instant::now();
+
+        while let Some(received) = receive_packet(&self.socket, &mut self.receive_buffer) {
+            if let Some((address, packet)) = received {
+                self.process_packet(address, packet, now);
+            }
+        }
+
+        let timed_out_client_ids: Vec<_> = self
+            .connections
+            .iter()
+            .filter(|(_, connection)| connection.is_timed_out(now))
+            .map(|(client_id, _)| *client_id)
+            .collect();
+        for client_id in timed_out_client_ids {
+            log::info!("Client {client_id:?} timed out");
+            self.remove_client(client_id);
+        }
+
+        for connection in self.connections.values_mut() {
+            for packet in connection.make_payload_packets(now) {
+                send_packet(&self.socket, connection.address, &packet);
+            }
+        }
+    }
+
+    fn process_packet(&mut self, address: SocketAddr, packet: Packet, now: Instant) {
+        if let PacketKind::ConnectRequest = packet.kind {
+            let client_id = match self.client_ids.get(&address) {
+                Some(client_id) => *client_id,
+                None => {
+                    if self.connections.len() >= self.max_clients {
+                        let mut connection = Connection::new(address);
+                        let packet = connection.make_packet(PacketKind::ConnectRejected);
+                        send_packet(&self.socket, address, &packet);
+                        return;
+                    }
+                    let client_id = ClientId(self.next_client_id);
+                    self.next_client_id += 1;
+                    self.client_ids.insert(address, client_id);
+                    self.connections.insert(client_id, Connection::new(address));
+                    self.events.push(ServerEvent::ClientConnected(client_id));
+                    client_id
+                }
+            };
+            // the request is repeated until the client gets this
+            let connection = self.connections.get_mut(&client_id).unwrap();
+            connection.last_receive_time = now;
+            let packet = connection.make_packet(PacketKind::ConnectAccepted { client_id });
+            send_packet(&self.socket, address, &packet);
+            return;
+        }
+
+        let Some(client_id) = self.client_ids.get(&address).copied() else {
+            return;
+        };
+
+        if let PacketKind::Disconnect = packet.kind {
+            self.remove_client(client_id);
+            return;
+        }
+
+        let connection = self.connections.get_mut(&client_id).unwrap();
+        for message in connection.process_packet(packet, now) {
+            self.events.push(ServerEvent::Message(client_id, message));
+        }
+    }
+
+    fn remove_client(&mut self, client_id: ClientId) {
+        if let Some(connection) = self.connections.remove(&client_id) {
+            self.client_ids.remove(&connection.address);
+            self.events.push(ServerEvent::ClientDisconnected(client_id));
+        }
+    }
+
+    /// Fails for reliable messages that don't fit in a packet
+    pub fn send(
+        &mut self,
+        client_id: ClientId,
+        channel: Channel,
+        message: NetworkMessage,
+    ) -> anyhow::Result<()> {
+        if let Some(connection) = self.connections.get_mut(&client_id) {
+            connection.queue_message(channel, message)?;
+        }
+        Ok(())
+    }
+
+    /// Fails for reliable messages that don't fit in a packet
+    pub fn broadcast(&mut self, channel: Channel, message: NetworkMessage) -> anyhow::Result<()> {
+        for connection in self.connections.values_mut() {
+            connection.queue_message(channel, message.clone())?;
+        }
+        Ok(())
+    }
+
+    pub fn disconnect(&mut self, client_id: ClientId) {
+        if let Some(connection) = self.connections.get_mut(&client_id) {
+            let packet = connection.make_packet(PacketKind::Disconnect);
+            send_packet(&self.socket, connection.address, &packet);
+        }
+        self.remove_client(client_id);
+    }
+
+    pub fn client_ids(&self) -> impl Iterator<Item = ClientId> + '_ {
+        self.connections.keys().copied()
+    }
+
+    /// smoothed round trip time
+    pub fn rtt(&self, client_id: ClientId) -> Option<Duration> {
+        self.connections
+            .get(&client_id)
+            .map(|connection| Duration::from_secs_f64(connection.rtt_seconds))
+    }
+
+    /// events that happened since the last call, in the order they were received
+    pub fn take_events(&mut self) -> Vec<ServerEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ClientConnectionState {
+    Connecting,
+    Connected(ClientId),
+    /// rejected, timed out or disconnected by either side
+    Disconnected,
+}
+
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    Connected(ClientId),
+    Disconnected,
+    Message(NetworkMessage),
+}
+
+pub struct NetworkClient {
+    socket: UdpSocket,
+    connection: Connection,
+    state: ClientConnectionState,
+    connect_start_time: Instant,
+    last_connect_request_time: Option<Instant>,
+    events: Vec<ClientEvent>,
+    receive_buffer: Vec<u8>,
+}
+
+impl NetworkClient {
+    /// starts connecting, ClientEvent::Connected is sent once the server accepted
+    pub fn connect(server_address: impl ToSocketAddrs) -> anyhow::Result<Self> {
+        let server_address = server_address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Invalid server address"))?;
+        let local_address: SocketAddr = if server_address.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local_address)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            connection: Connection::new(server_address),
+            state: ClientConnectionState::Connecting,
+            connect_start_time: Instant::now(),
+            last_connect_request_time: None,
+            events: vec![],
+            receive_buffer: vec![0; MAX_RECEIVED_PACKET_SIZE],
+        })
+    }
+
+    pub fn state(&self) -> ClientConnectionState {
+        self.state
+    }
+
+    pub fn client_id(&self) -> Option<ClientId> {
+        match self.state {
+            ClientConnectionState::Connected(client_id) => Some(client_id),
+            _ => None,
+        }
+    }
+
+    #[profiling::function]
+    pub fn update(&mut self) {
+        let now = Instant::now();
+        let server_address = self.connection.address;
+
+        while let Some(received) = receive_packet(&self.socket, &mut self.receive_buffer) {
+            if let Some((address, packet)) = received {
+                if address == server_address {
+                    self.process_packet(packet, now);
+                }
+            }
+        }
+
+        match self.state {
+            ClientConnectionState::Connecting => {
+                if now.duration_since(self.connect_start_time) > CONNECTION_TIMEOUT {
+                    log::warn!("Timed out connecting to {server_address}");
+                    self.set_disconnected();
+                    return;
+                }
+                let should_send_request =
+                    self.last_connect_request_time
+                        .map_or(true, |last_connect_request_time| {
+                            now.duration_since(last_connect_request_time)
+                                >= CONNECT_REQUEST_INTERVAL
+                        });
+                if should_send_request {
+                    let packet = self.connection.make_packet(PacketKind::ConnectRequest);
+                    send_packet(&self.socket, server_address, &packet);
+                    self.last_connect_request_time = Some(now);
+                }
+            }
+            ClientConnectionState::Connected(_) => {
+                if self.connection.is_timed_out(now) {
+                    log::warn!("Connection to {server_address} timed out");
+                    self.set_disconnected();
+                    return;
+                }
+                for packet in self.connection.make_payload_packets(now) {
+                    send_packet(&self.socket, server_address, &packet);
+                }
+            }
+            ClientConnectionState::Disconnected => {}
+        }
+    }
+
+    fn process_packet(&mut self, packet: Packet, now: Instant) {
+        match (&packet.kind, self.state) {
+            (PacketKind::ConnectAccepted { client_id }, ClientConnectionState::Connecting) => {
+                let client_id = *client_id;
+                self.state = ClientConnectionState::Connected(client_id);
+                self.events.push(ClientEvent::Connected(client_id));
+                self.connection.last_receive_time = now;
+            }
+            (PacketKind::ConnectRejected, ClientConnectionState::Connecting) => {
+                log::warn!("Connection to {} was rejected", self.connection.address);
+                self.set_disconnected();
+            }
+            (PacketKind::Disconnect, ClientConnectionState::Connected(_)) => {
+                self.set_disconnected();
+            }
+            (PacketKind::Payload { .. }, ClientConnectionState::Connected(_)) => {
+                for message in self.connection.process_packet(packet, now) {
+                    self.events.push(ClientEvent::Message(message));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn set_disconnected(&mut self) {
+        if self.state != ClientConnectionState::Disconnected {
+            self.state = ClientConnectionState::Disconnected;
+            self.events.push(ClientEvent::Disconnected);
+        }
+    }
+
+    /// messages sent before the connection is established are queued until then. Fails for
+    /// reliable messages that don't fit in a packet
+    pub fn send(&mut self, channel: Channel, message: NetworkMessage) -> anyhow::Result<()> {
+        self.connection.queue_message(channel, message)
+    }
+
+    pub fn disconnect(&mut self) {
+        if let ClientConnectionState::Connected(_) = self.state {
+            let packet = self.connection.make_packet(PacketKind::Disconnect);
+            send_packet(&self.socket, self.connection.address, &packet);
+        }
+        self.set_disconnected();
+    }
+
+    pub fn rtt(&self) -> Duration {
+        Duration::from_secs_f64(self.connection.rtt_seconds)
+    }
+
+    pub fn take_events(&mut self) -> Vec<ClientEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NetworkId(pub u32);
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkTransform {
+    pub position: [f32; 3],
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+}
+
+impl From<Transform> for NetworkTransform {
+    fn from(transform: Transform) -> Self {
+        let SimpleTransform {
+            position,
+            rotation,
+            scale,
+        } = transform.decompose();
+        Self {
+            position: position.to_array(),
+            rotation: rotation.to_array(),
+            scale: scale.to_array(),
+        }
+    }
+}
+
+impl From<NetworkTransform> for Transform {
+    fn from(transform: NetworkTransform) -> Self {
+        TransformBuilder::new()
+            .position(Vec3::from(transform.position))
+            .rotation(Quat::from_array(transform.rotation))
+            .scale(Vec3::from(transform.scale))
+            .build()
+    }
+}
+
+impl NetworkTransform {
+    fn lerp(&self, other: &Self, alpha: f32) -> Self {
+        Self {
+            position: Vec3::from(self.position)
+                .lerp(Vec3::from(other.position), alpha)
+                .to_array(),
+            rotation: Quat::from_array(self.rotation)
+                .slerp(Quat::from_array(other.rotation), alpha)
+                .to_array(),
+            scale: Vec3::from(self.scale)
+                .lerp(Vec3::from(other.scale), alpha)
+                .to_array(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReplicationMessage {
+    /// kind tells the client what to spawn, e.g. the path of a gltf file or "ball"
+    Spawn {
+        network_id: NetworkId,
+        kind: String,
+        transform: NetworkTransform,
+    },
+    Despawn {
+        network_id: NetworkId,
+    },
+    /// global transforms of all the replicated nodes
+    Transforms {
+        server_time_seconds: f64,
+        transforms: Vec<(NetworkId, NetworkTransform)>,
+    },
+}
+
+/// Server side of the replication, tells the clients which nodes exist and where they are
+#[derive(Debug, Default)]
+pub struct ReplicationServer {
+    next_network_id: u32,
+    replicated_nodes: BTreeMap<NetworkId, (GameNodeId, String)>,
+    pending_spawns: Vec<NetworkId>,
+    pending_despawns: Vec<NetworkId>,
+}
+
+impl ReplicationServer {
+    pub fn replicate_node(&mut self, node_id: GameNodeId, kind: impl Into<String>) -> NetworkId {
+        let network_id = NetworkId(self.next_network_id);
+        self.next_network_id += 1;
+        self.replicated_nodes
+            .insert(network_id, (node_id, kind.into()));
+        self.pending_spawns.push(network_id);
+        network_id
+    }
+
+    pub fn stop_replicating(&mut self, network_id: NetworkId) {
+        if self.replicated_nodes.remove(&network_id).is_some() {
+            self.pending_spawns.retain(|pending| *pending != network_id);
+            self.pending_despawns.push(network_id);
+        }
+    }
+
+    pub fn network_id(&self, node_id: GameNodeId) -> Option<NetworkId> {
+        self.replicated_nodes
+            .iter()
+            .find(|(_, (replicated_node_id, _))| *replicated_node_id == node_id)
+            .map(|(network_id, _)| *network_id)
+    }
+
+    /// Sends every replicated node to a client that just connected
+    pub fn on_client_connected(
+        &self,
+        server: &mut NetworkServer,
+        scene: &Scene,
+        client_id: ClientId,
+    ) -> anyhow::Result<()> {
+        for network_id in self.replicated_nodes.keys() {
+            if let Some(message) = self.make_spawn_message(scene, *network_id) {
+                server.send(
+                    client_id,
+                    Channel::Reliable,
+                    NetworkMessage::Replication(message),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn make_spawn_message(
+        &self,
+        scene: &Scene,
+        network_id: NetworkId,
+    ) -> Option<ReplicationMessage> {
+        let (node_id, kind) = self.replicated_nodes.get(&network_id)?;
+        scene.get_node(*node_id)?;
+        Some(ReplicationMessage::Spawn {
+            network_id,
+            kind: kind.clone(),
+            transform: scene.get_global_transform_for_node(*node_id).into(),
+        })
+    }
+
+    /// Queues the spawns/despawns since the last call and a snapshot of the transforms.
+    /// Nodes that were removed from the scene are despawned automatically
+    #[profiling::function]
+    pub fn update(
+        &mut self,
+        server: &mut NetworkServer,
+        scene: &Scene,
+        server_time_seconds: f64,
+    ) -> anyhow::Result<()> {
+        let removed_network_ids: Vec<_> = self
+            .replicated_nodes
+            .iter()
+            .filter(|(_, (node_id, _))| scene.get_node(*node_id).is_none())
+            .map(|(network_id, _)| *network_id)
+            .collect();
+        for network_id in removed_network_ids {
+            self.stop_replicating(network_id);
+        }
+
+        for network_id in std::mem::take(&mut self.pending_spawns) {
+            if let Some(message) = self.make_spawn_message(scene, network_id) {
+                server.broadcast(Channel::Reliable, NetworkMessage::Replication(message))?;
+            }
+        }
+        for network_id in std::mem::take(&mut self.pending_despawns) {
+            server.broadcast(
+                Channel::Reliable,
+                NetworkMessage::Replication(ReplicationMessage::Despawn { network_id }),
+            )?;
+        }
+
+        // split up so each snapshot fits in a packet
+        let transforms: Vec<_> = self
+            .replicated_nodes
+            .iter()
+            .map(|(network_id, (node_id, _))| {
+                (
+                    *network_id,
+                    NetworkTransform::from(scene.get_global_transform_for_node(*node_id)),
+                )
+            })
+            .collect();
+        for transforms_chunk in transforms.chunks(24) {
+            server.broadcast(
+                Channel::Unreliable,
+                NetworkMessage::Replication(ReplicationMessage::Transforms {
+                    server_time_seconds,
+                    transforms: transforms_chunk.to_vec(),
+                }),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ReplicationEvent {
+    /// the game should create the node and give it to ReplicationClient::bind_node
+    Spawned {
+        network_id: NetworkId,
+        kind: String,
+        transform: Transform,
+    },
+    /// node_id is the node that was bound to the entity, which the game should remove
+    Despawned {
+        network_id: NetworkId,
+        node_id: Option<GameNodeId>,
+    },
+}
+
+#[derive(Debug, Default)]
+struct RemoteEntity {
+    node_id: Option<GameNodeId>,
+    /// (server time, transform), oldest first
+    snapshots: VecDeque<(f64, NetworkTransform)>,
+}
+
+/// Client side of the replication. Remote entities are displayed slightly in the past,
+/// interpolating between the snapshots received from the server, so the motion stays
+/// smooth even when packets are late or lost
+#[derive(Debug)]
+pub struct ReplicationClient {
+    entities: HashMap<NetworkId, RemoteEntity>,
+    events: Vec<ReplicationEvent>,
+    /// server time minus local time, smoothed
+    clock_offset_seconds: Option<f64>,
+    pub interpolation_delay_seconds: f64,
+}
+
+impl Default for ReplicationClient {
+    fn default() -> Self {
+        Self {
+            entities: HashMap::new(),
+            events: vec![],
+            clock_offset_seconds: None,
+            interpolation_delay_seconds: 0.1,
+        }
+    }
+}
+
+impl ReplicationClient {
+    /// local_time_seconds must come from the same clock as the one passed to update
+    pub fn handle_message(&mut self, message: ReplicationMessage, local_time_seconds: f64) {
+        match message {
+            ReplicationMessage::Spawn {
+                network_id,
+                kind,
+                transform,
+            } => {
+                if self.entities.contains_key(&network_id) {
+                    return;
+                }
+                self.entities.insert(network_id, RemoteEntity::default());
+                self.events.push(ReplicationEvent::Spawned {
+                    network_id,
+                    kind,
+                    transform: transform.into(),
+                });
+            }
+            ReplicationMessage::Despawn { network_id } => {
+                if let Some(entity) = self.entities.remove(&network_id) {
+                    self.events.push(ReplicationEvent::Despawned {
+                        network_id,
+                        node_id: entity.node_id,
+                    });
+                }
+            }
+            ReplicationMessage::Transforms {
+                server_time_seconds,
+                transforms,
+            } => {
+                let clock_offset_sample = server_time_seconds - local_time_seconds;
+                self.clock_offset_seconds = Some(match self.clock_offset_seconds {
+                    Some(clock_offset_seconds) => {
+                        clock_offset_seconds * 0.95 + clock_offset_sample * 0.05
+                    }
+                    None => clock_offset_sample,
+                });
+
+                for (network_id, transform) in transforms {
+                    let Some(entity) = self.entities.get_mut(&network_id) else {
+                        continue;
+                    };
+                    // unreliable messages can arrive out of order
+                    let insert_index = entity
+                        .snapshots
+                        .iter()
+                        .rposition(|(time, _)| *time < server_time_seconds)
+                        .map(|index| index + 1)
+                        .unwrap_or(0);
+                    entity
+                        .snapshots
+                        .insert(insert_index, (server_time_seconds, transform));
+                }
+            }
+        }
+    }
+
+    pub fn bind_node(&mut self, network_id: NetworkId, node_id: GameNodeId) {
+        if let Some(entity) = self.entities.get_mut(&network_id) {
+            entity.node_id = Some(node_id);
+        }
+    }
+
+    pub fn node_id(&self, network_id: NetworkId) -> Option<GameNodeId> {
+        self.entities.get(&network_id)?.node_id
+    }
+
+    /// Moves the bound nodes to their interpolated transforms.
+    /// The nodes are expected to have no parent
+    #[profiling::function]
+    pub fn update(&mut self, scene: &mut Scene, local_time_seconds: f64) {
+        let Some(clock_offset_seconds) = self.clock_offset_seconds else {
+            return;
+        };
+        let render_time =
+            local_time_seconds + clock_offset_seconds - self.interpolation_delay_seconds;
+
+        for entity in self.entities.values_mut() {
+            // keep one snapshot before the render time to interpolate from
+            while entity.snapshots.len() > 2 && entity.snapshots[1].0 <= render_time {
+                entity.snapshots.pop_front();
+            }
+
+            let transform = match (entity.snapshots.front(), entity.snapshots.get(1)) {
+                (Some((from_time, from)), Some((to_time, to))) if render_time > *from_time => {
+                    let alpha = ((render_time - from_time) / (to_time - from_time)).min(1.0);
+                    from.lerp(to, alpha as f32)
+                }
+                (Some((_, from)), _) => *from,
+                _ => continue,
+            };
+
+            if let Some(node) = entity
+                .node_id
+                .and_then(|node_id| scene.get_node_mut(node_id))
+            {
+                node.transform = transform.into();
+            }
+        }
+    }
+
+    pub fn take_events(&mut self) -> Vec<ReplicationEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+/// Client-side prediction of the local player. The client moves its player right away and
+/// remembers where it predicted it to be after each input; when the server sends back the
+/// authoritative position for an input, the remaining predictions are shifted by the error.
+/// This assumes the movement doesn't depend on where the player is, which holds for
+/// PlayerController as long as it doesn't collide with something the server disagrees about
+#[derive(Debug)]
+pub struct PlayerPrediction {
+    next_input_sequence: u32,
+    /// (sequence, predicted position after the input was applied), oldest first
+    pending_inputs: VecDeque<(u32, Vec3)>,
+    /// errors smaller than this are ignored since they're mostly float imprecision
+    pub correction_threshold: f32,
+}
+
+impl Default for PlayerPrediction {
+    fn default() -> Self {
+        Self {
+            next_input_sequence: 0,
+            pending_inputs: VecDeque::new(),
+            correction_threshold: 0.05,
+        }
+    }
+}
+
+impl PlayerPrediction {
+    /// Call after applying an input locally, returns the sequence to send to the server
+    /// along with the input (see PlayerController::input)
+    pub fn record_input(&mut self, predicted_position: Vec3) -> u32 {
+        let sequence = self.next_input_sequence;
+        self.next_input_sequence = self.next_input_sequence.wrapping_add(1);
+        self.pending_inputs
+            .push_back((sequence, predicted_position));
+        // the server stopped answering, don't grow forever
+        if self.pending_inputs.len() > 1024 {
+            self.pending_inputs.pop_front();
+        }
+        sequence
+    }
+
+    /// Returns the corrected current position if the server disagreed with the
+    /// prediction, which the game should teleport the player's rigid body to
+    pub fn reconcile(
+        &mut self,
+        acked_input_sequence: u32,
+        authoritative_position: Vec3,
+        current_position: Vec3,
+    ) -> Option<Vec3> {
+        let mut predicted_position = None;
+        while let Some((sequence, position)) = self.pending_inputs.front().copied() {
+            if sequence_greater_than(sequence, acked_input_sequence) {
+                break;
+            }
+            if sequence == acked_input_sequence {
+                predicted_position = Some(position);
+            }
+            self.pending_inputs.pop_front();
+        }
+
+        let error = authoritative_position - predicted_position?;
+        if error.length() < self.correction_threshold {
+            return None;
+        }
+
+        for (_, position) in self.pending_inputs.iter_mut() {
+            *position += error;
+        }
+
+        Some(current_position + error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_connection() -> Connection {
+        Connection::new("127.0.0.1:0".parse().unwrap())
+    }
+
+    #[test]
+    fn sequences_compare_across_the_wraparound() {
+        assert!(sequence_greater_than(1, 0));
+        assert!(!sequence_greater_than(0, 1));
+        assert!(!sequence_greater_than(5, 5));
+        assert!(sequence_greater_than(0, u32::MAX));
+        assert!(sequence_greater_than(10, u32::MAX - 10));
+        assert!(!sequence_greater_than(u32::MAX, 0));
+    }
+
+    #[test]
+    fn received_sequences_are_tracked_in_the_ack_bits() {
+        let mut connection = test_connection();
+        assert!(connection.record_received_sequence(10));
+        assert!(!connection.record_received_sequence(10));
+
+        assert!(connection.record_received_sequence(12));
+        assert_eq!(connection.remote_sequence, Some(12));
+        assert_eq!(connection.remote_ack_bits, 0b10);
+
+        // late but not a duplicate
+        assert!(connection.record_received_sequence(11));
+        assert_eq!(connection.remote_ack_bits, 0b11);
+        assert!(!connection.record_received_sequence(11));
+
+        // too far ahead to keep any of the bits, then too old to tell
+        assert!(connection.record_received_sequence(52));
+        assert_eq!(connection.remote_ack_bits, 0);
+        assert!(!connection.record_received_sequence(12));
+
+        let mut connection = test_connection();
+        assert!(connection.record_received_sequence(u32::MAX));
+        assert!(connection.record_received_sequence(1));
+        assert_eq!(connection.remote_sequence, Some(1));
+        assert_eq!(connection.remote_ack_bits, 0b10);
+        assert!(connection.record_received_sequence(0));
+        assert_eq!(connection.remote_ack_bits, 0b11);
+    }
+
+    #[test]
+    fn acks_drop_the_reliable_messages_of_the_acked_packets() {
+        let mut connection = test_connection();
+        let now = Instant::now();
+        for (sequence, reliable_id) in [(5, 1), (6, 2), (7, 3), (8, 4)] {
+            connection
+                .sent_packets
+                .insert(sequence, (now, vec![reliable_id]));
+            connection
+                .outgoing_reliable_messages
+                .insert(reliable_id, (NetworkMessage::Game(vec![]), 0, Some(now)));
+        }
+
+        // 8 and 6, 7 was lost
+        connection.process_acks(8, 0b10, now);
+        let mut pending_sequences: Vec<_> = connection.sent_packets.keys().copied().collect();
+        pending_sequences.sort();
+        assert_eq!(pending_sequences, vec![5, 7]);
+        assert_eq!(
+            connection
+                .outgoing_reliable_messages
+                .keys()
+                .copied()
+                .collect::<Vec<_>>(),
+            vec![1, 3]
+        );
+    }
+
+    #[test]
+    fn reliable_messages_stay_within_the_window() {
+        let mut connection = test_connection();
+        assert!(connection
+            .queue_message(
+                Channel::Reliable,
+                NetworkMessage::Game(vec![0; MAX_PACKET_PAYLOAD_SIZE])
+            )
+            .is_err());
+        for _ in 0..RELIABLE_MESSAGE_WINDOW_SIZE + 1 {
+            connection
+                .queue_message(Channel::Reliable, NetworkMessage::Game(vec![]))
+                .unwrap();
+        }
+        let sent_ids: Vec<u32> = connection
+            .make_payload_packets(Instant::now())
+            .into_iter()
+            .flat_map(|packet| match packet.kind {
+                PacketKind::Payload {
+                    reliable_messages, ..
+                } => reliable_messages.into_iter().map(|(id, _)| id).collect(),
+                _ => vec![],
+            })
+            .collect();
+        assert_eq!(sent_ids.len(), RELIABLE_MESSAGE_WINDOW_SIZE as usize);
+
+        let mut connection = test_connection();
+        let packet = connection.make_packet(PacketKind::Payload {
+            reliable_messages: vec![
+                (1, NetworkMessage::Game(vec![1])),
+                (RELIABLE_MESSAGE_WINDOW_SIZE, NetworkMessage::Game(vec![])),
+            ],
+            unreliable_messages: vec![],
+        });
+        assert!(connection.process_packet(packet, Instant::now()).is_empty());
+        assert_eq!(
+            connection
+                .incoming_reliable_messages
+                .keys()
+                .copied()
+                .collect::<Vec<_>>(),
+            vec![1]
+        );
+    }
+
+    #[test]
+    fn reconcile_shifts_the_pending_predictions_by_the_error() {
+        let mut prediction = PlayerPrediction::default();
+        for x in [1.0, 2.0, 3.0] {
+            prediction.record_input(Vec3::new(x, 0.0, 0.0));
+        }
+
+        let corrected_position =
+            prediction.reconcile(0, Vec3::new(1.5, 0.0, 0.0), Vec3::new(3.0, 0.0, 0.0));
+        assert_eq!(corrected_position, Some(Vec3::new(3.5, 0.0, 0.0)));
+        assert_eq!(
+            prediction.pending_inputs,
+            VecDeque::from([(1, Vec3::new(2.5, 0.0, 0.0)), (2, Vec3::new(3.5, 0.0, 0.0))])
+        );
+
+        // the replayed predictions agree with the server now
+        assert_eq!(
+            prediction.reconcile(1, Vec3::new(2.5, 0.0, 0.0), Vec3::new(3.5, 0.0, 0.0)),
+            None
+        );
+        // already reconciled
+        assert_eq!(
+            prediction.reconcile(1, Vec3::new(9.0, 0.0, 0.0), Vec3::new(3.5, 0.0, 0.0)),
+            None
+        );
+        assert_eq!(prediction.pending_inputs.len(), 1);
+    }
+}
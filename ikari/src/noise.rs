@@ -0,0 +1,381 @@
+use std::ops::Mul;
+
+use glam::f32::{Vec2, Vec3};
+
+/// edges of a cube, used as the gradients of the simplex noise
+const GRADIENTS_3D: [[f32; 3]; 12] = [
+    [1.0, 1.0, 0.0],
+    [-1.0, 1.0, 0.0],
+    [1.0, -1.0, 0.0],
+    [-1.0, -1.0, 0.0],
+    [1.0, 0.0, 1.0],
+    [-1.0, 0.0, 1.0],
+    [1.0, 0.0, -1.0],
+    [-1.0, 0.0, -1.0],
+    [0.0, 1.0, 1.0],
+    [0.0, -1.0, 1.0],
+    [0.0, 1.0, -1.0],
+    [0.0, -1.0, -1.0],
+];
+
+/// Seeded Perlin, simplex and Worley noise. The same seed gives the same noise on every
+/// platform, including the web, so it can be used for terrain that has to match across clients.
+/// Perlin and simplex noise are in [-1, 1], see FbmParams for layering octaves of them
+#[derive(Debug, Clone)]
+pub struct Noise {
+    seed: u64,
+    /// twice the same permutation of 0..256, so the lookups don't need to wrap around
+    permutation: [u8; 512],
+}
+
+impl Noise {
+    pub fn new(seed: u64) -> Self {
+        // not the rand crate since its SmallRng isn't the same on 32 bit platforms
+        let mut state = seed;
+        let mut permutation_half: [u8; 256] = std::array::from_fn(|i| i as u8);
+        for i in (1..permutation_half.len()).rev() {
+            let j = (splitmix64(&mut state) % (i as u64 + 1)) as usize;
+            permutation_half.swap(i, j);
+        }
+        Self {
+            seed,
+            permutation: std::array::from_fn(|i| permutation_half[i % 256]),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    fn hash(&self, i: i32) -> usize {
+        self.permutation[(i & 255) as usize] as usize
+    }
+
+    pub fn perlin_1d(&self, x: f32) -> f32 {
+        let xi = x.floor() as i32;
+        let x = x - x.floor();
+        let gradient = |hash: usize, x: f32| if hash & 1 == 0 { x } else { -x };
+        // the gradients are +-1 so the extremes are +-0.5 halfway between the lattice points
+        2.0 * lerp(
+            gradient(self.hash(xi), x),
+            gradient(self.hash(xi + 1), x - 1.0),
+            fade(x),
+        )
+    }
+
+    pub fn perlin_2d(&self, position: Vec2) -> f32 {
+        self.perlin_3d(position.extend(0.0))
+    }
+
+    /// Ken Perlin's improved noise
+    pub fn perlin_3d(&self, position: Vec3) -> f32 {
+        let cell = position.floor();
+        let [xi, yi, zi] = [cell.x as i32, cell.y as i32, cell.z as i32];
+        let Vec3 { x, y, z } = position - cell;
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+
+        let p = &self.permutation;
+        let a = p[(xi & 255) as usize] as usize + (yi & 255) as usize;
+        let aa = p[a] as usize + (zi & 255) as usize;
+        let ab = p[a + 1] as usize + (zi & 255) as usize;
+        let b = p[((xi + 1) & 255) as usize] as usize + (yi & 255) as usize;
+        let ba = p[b] as usize + (zi & 255) as usize;
+        let bb = p[b + 1] as usize + (zi & 255) as usize;
+
+        lerp(
+            lerp(
+                lerp(
+                    perlin_gradient(p[aa], x, y, z),
+                    perlin_gradient(p[ba], x - 1.0, y, z),
+                    u,
+                ),
+                lerp(
+                    perlin_gradient(p[ab], x, y - 1.0, z),
+                    perlin_gradient(p[bb], x - 1.0, y - 1.0, z),
+                    u,
+                ),
+                v,
+            ),
+            lerp(
+                lerp(
+                    perlin_gradient(p[aa + 1], x, y, z - 1.0),
+                    perlin_gradient(p[ba + 1], x - 1.0, y, z - 1.0),
+                    u,
+                ),
+                lerp(
+                    perlin_gradient(p[ab + 1], x, y - 1.0, z - 1.0),
+                    perlin_gradient(p[bb + 1], x - 1.0, y - 1.0, z - 1.0),
+                    u,
+                ),
+                v,
+            ),
+            w,
+        )
+        .clamp(-1.0, 1.0)
+    }
+
+    pub fn simplex_1d(&self, x: f32) -> f32 {
+        let i0 = x.floor() as i32;
+        let x0 = x - i0 as f32;
+        let contribution = |hash: usize, x: f32| {
+            let t = 1.0 - x * x;
+            // gradients of 1 to 8 with a random sign
+            let gradient = (1 + (hash & 7)) as f32 * if hash & 8 == 0 { 1.0 } else { -1.0 };
+            t * t * t * t * gradient * x
+        };
+        0.395 * (contribution(self.hash(i0), x0) + contribution(self.hash(i0 + 1), x0 - 1.0))
+    }
+
+    /// Stefan Gustavson's implementation, cheaper than perlin_2d without the grid artifacts
+    pub fn simplex_2d(&self, position: Vec2) -> f32 {
+        let f2 = 0.5 * (3.0f32.sqrt() - 1.0);
+        let g2 = (3.0 - 3.0f32.sqrt()) / 6.0;
+
+        let skew = (position.x + position.y) * f2;
+        let i = (position.x + skew).floor() as i32;
+        let j = (position.y + skew).floor() as i32;
+        let unskew = (i + j) as f32 * g2;
+        let p0 = position - Vec2::new(i as f32 - unskew, j as f32 - unskew);
+        let (i1, j1) = if p0.x > p0.y { (1, 0) } else { (0, 1) };
+        let p1 = p0 - Vec2::new(i1 as f32, j1 as f32) + Vec2::splat(g2);
+        let p2 = p0 - Vec2::ONE + Vec2::splat(2.0 * g2);
+
+        let contribution = |gradient_index: usize, p: Vec2| {
+            let t = 0.5 - p.length_squared();
+            if t < 0.0 {
+                return 0.0;
+            }
+            let [gx, gy, _] = GRADIENTS_3D[gradient_index % 12];
+            t * t * t * t * (gx * p.x + gy * p.y)
+        };
+        70.0 * (contribution(self.hash(i + self.hash(j) as i32), p0)
+            + contribution(self.hash(i + i1 + self.hash(j + j1) as i32), p1)
+            + contribution(self.hash(i + 1 + self.hash(j + 1) as i32), p2))
+    }
+
+    pub fn simplex_3d(&self, position: Vec3) -> f32 {
+        let f3 = 1.0 / 3.0;
+        let g3 = 1.0 / 6.0;
+
+        let skew = (position.x + position.y + position.z) * f3;
+        let cell = (position + Vec3::splat(skew)).floor();
+        let [i, j, k] = [cell.x as i32, cell.y as i32, cell.z as i32];
+        let unskew = (i + j + k) as f32 * g3;
+        let p0 = position - (cell - Vec3::splat(unskew));
+
+        // which of the 6 tetrahedra of the skewed cube the point is in
+        let (offset_1, offset_2) = if p0.x >= p0.y {
+            if p0.y >= p0.z {
+                ([1, 0, 0], [1, 1, 0])
+            } else if p0.x >= p0.z {
+                ([1, 0, 0], [1, 0, 1])
+            } else {
+                ([0, 0, 1], [1, 0, 1])
+            }
+        } else if p0.y < p0.z {
+            ([0, 0, 1], [0, 1, 1])
+        } else if p0.x < p0.z {
+            ([0, 1, 0], [0, 1, 1])
+        } else {
+            ([0, 1, 0], [1, 1, 0])
+        };
+        let to_vec3 =
+            |offset: [i32; 3]| Vec3::new(offset[0] as f32, offset[1] as f32, offset[2] as f32);
+        let p1 = p0 - to_vec3(offset_1) + Vec3::splat(g3);
+        let p2 = p0 - to_vec3(offset_2) + Vec3::splat(2.0 * g3);
+        let p3 = p0 - Vec3::ONE + Vec3::splat(3.0 * g3);
+
+        let gradient_index = |offset: [i32; 3]| {
+            self.hash(
+                i + offset[0] + self.hash(j + offset[1] + self.hash(k + offset[2]) as i32) as i32,
+            ) % 12
+        };
+        let contribution = |gradient_index: usize, p: Vec3| {
+            let t = 0.6 - p.length_squared();
+            if t < 0.0 {
+                return 0.0;
+            }
+            t * t * t * t * Vec3::from(GRADIENTS_3D[gradient_index]).dot(p)
+        };
+        (32.0
+            * (contribution(gradient_index([0, 0, 0]), p0)
+                + contribution(gradient_index(offset_1), p1)
+                + contribution(gradient_index(offset_2), p2)
+                + contribution(gradient_index([1, 1, 1]), p3)))
+        .clamp(-1.0, 1.0)
+    }
+
+    /// Distance to the closest of the random points that are scattered one per cell
+    pub fn worley_1d(&self, x: f32) -> f32 {
+        let cell = x.floor() as i32;
+        (cell - 1..=cell + 1)
+            .map(|neighbor| (neighbor as f32 + self.cell_random([neighbor, 0, 0], 0) - x).abs())
+            .fold(f32::MAX, f32::min)
+    }
+
+    /// Distance to the closest of the random points that are scattered one per cell,
+    /// usually under 1. Gives the cellular look of stones, scales and caustics
+    pub fn worley_2d(&self, position: Vec2) -> f32 {
+        let cell = position.floor();
+        let [cx, cy] = [cell.x as i32, cell.y as i32];
+        let mut closest_distance = f32::MAX;
+        for y in cy - 1..=cy + 1 {
+            for x in cx - 1..=cx + 1 {
+                let feature_point = Vec2::new(
+                    x as f32 + self.cell_random([x, y, 0], 0),
+                    y as f32 + self.cell_random([x, y, 0], 1),
+                );
+                closest_distance = closest_distance.min(feature_point.distance(position));
+            }
+        }
+        closest_distance
+    }
+
+    pub fn worley_3d(&self, position: Vec3) -> f32 {
+        let cell = position.floor();
+        let [cx, cy, cz] = [cell.x as i32, cell.y as i32, cell.z as i32];
+        let mut closest_distance = f32::MAX;
+        for z in cz - 1..=cz + 1 {
+            for y in cy - 1..=cy + 1 {
+                for x in cx - 1..=cx + 1 {
+                    let feature_point = Vec3::new(
+                        x as f32 + self.cell_random([x, y, z], 0),
+                        y as f32 + self.cell_random([x, y, z], 1),
+                        z as f32 + self.cell_random([x, y, z], 2),
+                    );
+                    closest_distance = closest_distance.min(feature_point.distance(position));
+                }
+            }
+        }
+        closest_distance
+    }
+
+    /// in [0, 1), different for each cell and channel
+    fn cell_random(&self, cell: [i32; 3], channel: u32) -> f32 {
+        let mut state = self.seed
+            ^ (cell[0] as u32 as u64).wrapping_mul(0x9E37_79B1)
+            ^ ((cell[1] as u32 as u64).wrapping_mul(0x85EB_CA77) << 21)
+            ^ ((cell[2] as u32 as u64).wrapping_mul(0xC2B2_AE3D) << 42)
+            ^ channel as u64;
+        (splitmix64(&mut state) >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// Fractional Brownian motion: sums octaves of a noise with increasing frequencies and
+/// decreasing amplitudes, for detail at several scales, e.g. for terrain heightmaps
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FbmParams {
+    pub octaves: u32,
+    /// how much the frequency goes up with each octave
+    pub lacunarity: f32,
+    /// how much the amplitude goes down with each octave
+    pub gain: f32,
+}
+
+impl Default for FbmParams {
+    fn default() -> Self {
+        Self {
+            octaves: 5,
+            lacunarity: 2.0,
+            gain: 0.5,
+        }
+    }
+}
+
+impl FbmParams {
+    /// Normalized so a noise in [-1, 1] gives a result in [-1, 1], e.g.
+    /// params.sample(position, |position| noise.simplex_2d(position))
+    pub fn sample<P: Copy + Mul<f32, Output = P>>(
+        &self,
+        position: P,
+        noise: impl Fn(P) -> f32,
+    ) -> f32 {
+        let mut sum = 0.0;
+        let mut amplitude_sum = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = 1.0;
+        for _ in 0..self.octaves {
+            sum += amplitude * noise(position * frequency);
+            amplitude_sum += amplitude;
+            amplitude *= self.gain;
+            frequency *= self.lacunarity;
+        }
+        if amplitude_sum > 0.0 {
+            sum / amplitude_sum
+        } else {
+            0.0
+        }
+    }
+}
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(from: f32, to: f32, alpha: f32) -> f32 {
+    from + (to - from) * alpha
+}
+
+fn perlin_gradient(hash: u8, x: f32, y: f32, z: f32) -> f32 {
+    let hash = hash & 15;
+    let u = if hash < 8 { x } else { y };
+    let v = if hash < 4 {
+        y
+    } else if hash == 12 || hash == 14 {
+        x
+    } else {
+        z
+    };
+    (if hash & 1 == 0 { u } else { -u }) + (if hash & 2 == 0 { v } else { -v })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noise_is_seeded_and_in_range() {
+        let noise = Noise::new(7);
+        let same_noise = Noise::new(7);
+        let other_noise = Noise::new(8);
+
+        let mut differs_from_other_seed = false;
+        for i in 0..500 {
+            let position = Vec3::new(i as f32 * 0.37, i as f32 * -0.11, i as f32 * 0.053);
+            let samples = [
+                noise.perlin_1d(position.x),
+                noise.perlin_2d(position.truncate()),
+                noise.perlin_3d(position),
+                noise.simplex_1d(position.x),
+                noise.simplex_2d(position.truncate()),
+                noise.simplex_3d(position),
+            ];
+            for sample in samples {
+                assert!((-1.0..=1.0).contains(&sample), "{sample} at {position}");
+            }
+            let worley = noise.worley_3d(position);
+            assert!((0.0..2.0).contains(&worley), "{worley} at {position}");
+
+            assert_eq!(noise.simplex_3d(position), same_noise.simplex_3d(position));
+            assert_eq!(
+                noise.worley_2d(position.truncate()),
+                same_noise.worley_2d(position.truncate())
+            );
+            differs_from_other_seed |= noise.perlin_3d(position) != other_noise.perlin_3d(position);
+        }
+        assert!(differs_from_other_seed);
+
+        // perlin noise is 0 on the lattice points
+        assert_eq!(noise.perlin_2d(Vec2::new(3.0, -5.0)), 0.0);
+        let fbm = FbmParams::default().sample(Vec2::new(0.3, 0.6), |p| noise.simplex_2d(p));
+        assert!((-1.0..=1.0).contains(&fbm));
+    }
+}
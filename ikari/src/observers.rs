@@ -0,0 +1,235 @@
+use crate::animation::*;
+use crate::scene::*;
+use crate::transform::*;
+
+use std::collections::HashMap;
+
+use glam::f32::{Quat, Vec3};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ObserverId(u64);
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ObserverEvent {
+    /// the node moved or rotated past the observer's threshold since the last event.
+    /// position and rotation are in world space
+    NodeMoved {
+        observer_id: ObserverId,
+        node_id: GameNodeId,
+        position: Vec3,
+        rotation: Quat,
+    },
+    /// loop_count is how many times the animation looped since the last update,
+    /// usually 1. for PingPong animations, each change of direction counts as a loop
+    AnimationLooped {
+        observer_id: ObserverId,
+        animation_index: usize,
+        loop_count: u32,
+    },
+    AnimationStarted {
+        observer_id: ObserverId,
+        animation_index: usize,
+    },
+    /// stopped by the game or reached the end of a LoopType::Once animation
+    AnimationStopped {
+        observer_id: ObserverId,
+        animation_index: usize,
+    },
+}
+
+#[derive(Debug, Copy, Clone)]
+struct NodeObserver {
+    node_id: GameNodeId,
+    distance_threshold: f32,
+    /// in radians, None to ignore rotations
+    angle_threshold: Option<f32>,
+    /// transform at the time of the last event, None until the first update
+    last_position: Option<Vec3>,
+    last_rotation: Quat,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct AnimationObserver {
+    animation_index: usize,
+    last_state: Option<ObservedAnimationState>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct ObservedAnimationState {
+    is_playing: bool,
+    loop_index: i64,
+}
+
+impl ObservedAnimationState {
+    fn new(animation: &Animation) -> Self {
+        let loop_index = if animation.length_seconds > 0.0 {
+            (animation.state.current_time_seconds / animation.length_seconds).floor() as i64
+        } else {
+            0
+        };
+        Self {
+            is_playing: animation.state.is_playing,
+            loop_index,
+        }
+    }
+}
+
+/// Watches a few specific nodes and animations and queues up events when they change,
+/// so audio and VFX can react to them without scanning the whole scene every frame.
+/// SceneObservers::update must be called once per frame after the animations were stepped,
+/// the events are then polled with take_events.
+#[derive(Debug, Default)]
+pub struct SceneObservers {
+    next_observer_id: u64,
+    node_observers: HashMap<ObserverId, NodeObserver>,
+    animation_observers: HashMap<ObserverId, AnimationObserver>,
+    pending_events: Vec<ObserverEvent>,
+}
+
+impl SceneObservers {
+    /// fires a NodeMoved event each time the node moves distance_threshold away from
+    /// where it was during the previous event
+    pub fn observe_node_movement(
+        &mut self,
+        node_id: GameNodeId,
+        distance_threshold: f32,
+    ) -> ObserverId {
+        self.observe_node_transform(node_id, distance_threshold, None)
+    }
+
+    /// same as observe_node_movement but also fires when the node turns more than
+    /// angle_threshold radians
+    pub fn observe_node_transform(
+        &mut self,
+        node_id: GameNodeId,
+        distance_threshold: f32,
+        angle_threshold: Option<f32>,
+    ) -> ObserverId {
+        let observer_id = self.make_observer_id();
+        self.node_observers.insert(
+            observer_id,
+            NodeObserver {
+                node_id,
+                distance_threshold,
+                angle_threshold,
+                last_position: None,
+                last_rotation: Quat::IDENTITY,
+            },
+        );
+        observer_id
+    }
+
+    /// animation_index is an index into Scene::animations
+    pub fn observe_animation(&mut self, animation_index: usize) -> ObserverId {
+        let observer_id = self.make_observer_id();
+        self.animation_observers.insert(
+            observer_id,
+            AnimationObserver {
+                animation_index,
+                last_state: None,
+            },
+        );
+        observer_id
+    }
+
+    pub fn remove_observer(&mut self, observer_id: ObserverId) {
+        self.node_observers.remove(&observer_id);
+        self.animation_observers.remove(&observer_id);
+    }
+
+    /// removes all observers watching this node, e.g. before removing it from the scene
+    pub fn remove_node_observers(&mut self, node_id: GameNodeId) {
+        self.node_observers
+            .retain(|_, observer| observer.node_id != node_id);
+    }
+
+    fn make_observer_id(&mut self) -> ObserverId {
+        let observer_id = ObserverId(self.next_observer_id);
+        self.next_observer_id += 1;
+        observer_id
+    }
+
+    #[profiling::function]
+    pub fn update(&mut self, scene: &Scene) {
+        for (observer_id, observer) in self.node_observers.iter_mut() {
+            // observers of removed nodes stay around until they're removed by the game
+            if scene.get_node(observer.node_id).is_none() {
+                continue;
+            }
+
+            let SimpleTransform {
+                position, rotation, ..
+            } = scene
+                .get_global_transform_for_node(observer.node_id)
+                .decompose();
+
+            let Some(last_position) = observer.last_position else {
+                observer.last_position = Some(position);
+                observer.last_rotation = rotation;
+                continue;
+            };
+
+            let moved = last_position.distance(position) >= observer.distance_threshold;
+            let rotated = observer.angle_threshold.is_some_and(|angle_threshold| {
+                observer.last_rotation.angle_between(rotation) >= angle_threshold
+            });
+
+            if moved || rotated {
+                observer.last_position = Some(position);
+                observer.last_rotation = rotation;
+                self.pending_events.push(ObserverEvent::NodeMoved {
+                    observer_id: *observer_id,
+                    node_id: observer.node_id,
+                    position,
+                    rotation,
+                });
+            }
+        }
+
+        for (observer_id, observer) in self.animation_observers.iter_mut() {
+            let Some(animation) = scene.animations.get(observer.animation_index) else {
+                continue;
+            };
+
+            let new_state = ObservedAnimationState::new(animation);
+            let Some(last_state) = observer.last_state.replace(new_state) else {
+                continue;
+            };
+
+            let observer_id = *observer_id;
+            let animation_index = observer.animation_index;
+
+            if !last_state.is_playing && new_state.is_playing {
+                self.pending_events.push(ObserverEvent::AnimationStarted {
+                    observer_id,
+                    animation_index,
+                });
+            }
+
+            // the time of LoopType::Once animations is reset to 0 when they stop, which isn't a loop
+            if last_state.is_playing
+                && new_state.is_playing
+                && animation.state.loop_type != LoopType::Once
+                && new_state.loop_index > last_state.loop_index
+            {
+                self.pending_events.push(ObserverEvent::AnimationLooped {
+                    observer_id,
+                    animation_index,
+                    loop_count: (new_state.loop_index - last_state.loop_index) as u32,
+                });
+            }
+
+            if last_state.is_playing && !new_state.is_playing {
+                self.pending_events.push(ObserverEvent::AnimationStopped {
+                    observer_id,
+                    animation_index,
+                });
+            }
+        }
+    }
+
+    /// events that happened since the last call, in no particular order
+    pub fn take_events(&mut self) -> Vec<ObserverEvent> {
+        std::mem::take(&mut self.pending_events)
+    }
+}
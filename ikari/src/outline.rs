@@ -0,0 +1,474 @@
+use crate::asset_registry::{AssetRegistry, MeshHandle};
+use crate::buffer::GpuBuffer;
+use crate::mesh::Vertex;
+use crate::renderer::{BaseRenderer, BindedGeometryBuffers, USE_LABELS};
+use crate::scene::{GameNodeId, Scene};
+use crate::texture::Texture;
+
+use std::collections::HashMap;
+
+use glam::f32::{Mat4, Vec4};
+use wgpu::util::DeviceExt;
+
+/// Wider outlines are clamped to it
+pub const MAX_OUTLINE_WIDTH: f32 = 16.0;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuOutlineInstance {
+    model_view_proj: [[f32; 4]; 4],
+    color: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineConfigUniform {
+    params: [f32; 4],
+}
+
+/// Colored outlines around nodes and their descendants, e.g. for the editor's selection or the
+/// objects the player can interact with. The meshes of the outlined nodes are drawn into a mask
+/// which is dilated over the tone mapped image, so the outlines show through whatever is in
+/// front of them. Skinned meshes are outlined in their bind pose. Lives in
+/// RendererData::outlines
+pub struct Outlines {
+    /// in pixels of the render resolution, up to MAX_OUTLINE_WIDTH
+    pub width: f32,
+    colors: HashMap<GameNodeId, Vec4>,
+    /// (mesh index, instance index) of the meshes drawn into the mask, as of the last update
+    draws: Vec<(usize, u32)>,
+    instances_buffer: GpuBuffer,
+    instances_bind_group_layout: wgpu::BindGroupLayout,
+    instances_bind_group: wgpu::BindGroup,
+    mask_texture: Texture,
+    config_buffer: wgpu::Buffer,
+    composite_bind_group_layout: wgpu::BindGroupLayout,
+    composite_bind_group: wgpu::BindGroup,
+    mask_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+}
+
+impl Outlines {
+    pub(crate) fn new(base: &BaseRenderer) -> Self {
+        let device = &base.device;
+
+        let instances_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: USE_LABELS.then_some("outline_instances_bind_group_layout"),
+            });
+        let composite_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: USE_LABELS.then_some("outline_composite_bind_group_layout"),
+            });
+
+        let mask_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: USE_LABELS.then_some("Outline mask shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/outline_mask.wgsl").into()),
+        });
+        let mask_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: USE_LABELS.then_some("Outline mask pipeline layout"),
+            bind_group_layouts: &[&instances_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let mask_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: USE_LABELS.then_some("Outline mask pipeline"),
+            layout: Some(&mask_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &mask_shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &Vertex::ATTRIBS[..1],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &mask_shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: wgpu::TextureFormat::Rgba16Float,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            // the silhouette is the same from both sides
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let composite_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: USE_LABELS.then_some("Outline shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/outline.wgsl").into()),
+        });
+        let composite_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: USE_LABELS.then_some("Outline pipeline layout"),
+                bind_group_layouts: &[&composite_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let composite_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: USE_LABELS.then_some("Outline pipeline"),
+            layout: Some(&composite_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &composite_shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &composite_shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: wgpu::TextureFormat::Rgba16Float,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let instances_buffer = GpuBuffer::empty(
+            device,
+            std::mem::size_of::<GpuOutlineInstance>(),
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        );
+        let instances_bind_group =
+            create_instances_bind_group(base, &instances_bind_group_layout, &instances_buffer);
+        let mask_texture =
+            Texture::create_scaled_surface_texture(base, (1, 1), 1.0, "outline_mask_texture");
+        let config_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: USE_LABELS.then_some("Outline config buffer"),
+            contents: bytemuck::cast_slice(&[OutlineConfigUniform { params: [0.0; 4] }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let composite_bind_group = create_composite_bind_group(
+            base,
+            &composite_bind_group_layout,
+            &mask_texture,
+            &config_buffer,
+        );
+
+        Self {
+            width: 3.0,
+            colors: HashMap::new(),
+            draws: vec![],
+            instances_buffer,
+            instances_bind_group_layout,
+            instances_bind_group,
+            mask_texture,
+            config_buffer,
+            composite_bind_group_layout,
+            composite_bind_group,
+            mask_pipeline,
+            composite_pipeline,
+        }
+    }
+
+    /// Outlines the node and its descendants. The alpha of the color is the outline's
+    /// opacity. A descendant that has its own outline keeps it
+    pub fn set(&mut self, node_id: GameNodeId, color: Vec4) {
+        self.colors.insert(node_id, color);
+    }
+
+    pub fn remove(&mut self, node_id: GameNodeId) {
+        self.colors.remove(&node_id);
+    }
+
+    pub fn clear(&mut self) {
+        self.colors.clear();
+    }
+
+    pub fn color(&self, node_id: GameNodeId) -> Option<Vec4> {
+        self.colors.get(&node_id).copied()
+    }
+
+    /// Whether the outline passes run this frame
+    pub fn is_active(&self) -> bool {
+        !self.draws.is_empty()
+    }
+
+    /// Gathers the outlined meshes and sends them to the gpu. view_proj is the main camera's,
+    /// render_size the size of the image the outlines are drawn on
+    pub(crate) fn update(
+        &mut self,
+        base: &BaseRenderer,
+        scene: &Scene,
+        asset_registry: &AssetRegistry,
+        view_proj: Mat4,
+        render_size: wgpu::Extent3d,
+    ) {
+        self.colors
+            .retain(|node_id, _| scene.get_node(*node_id).is_some());
+        self.draws.clear();
+        let mut instances = vec![];
+        for (node_id, mesh, color) in outlined_meshes(scene, &self.colors) {
+            if !asset_registry.is_mesh_alive(mesh) {
+                continue;
+            }
+            let model = Mat4::from(scene.get_global_transform_for_node_opt(node_id));
+            self.draws.push((mesh.index(), instances.len() as u32));
+            instances.push(GpuOutlineInstance {
+                model_view_proj: (view_proj * model).to_cols_array_2d(),
+                color: color.to_array(),
+            });
+        }
+        if instances.is_empty() {
+            return;
+        }
+
+        let device = &base.device;
+        if self
+            .instances_buffer
+            .write(device, &base.queue, bytemuck::cast_slice(&instances))
+        {
+            self.instances_bind_group = create_instances_bind_group(
+                base,
+                &self.instances_bind_group_layout,
+                &self.instances_buffer,
+            );
+        }
+        if self.mask_texture.size != render_size {
+            self.mask_texture = Texture::create_scaled_surface_texture(
+                base,
+                (render_size.width, render_size.height),
+                1.0,
+                "outline_mask_texture",
+            );
+            self.composite_bind_group = create_composite_bind_group(
+                base,
+                &self.composite_bind_group_layout,
+                &self.mask_texture,
+                &self.config_buffer,
+            );
+        }
+        base.queue.write_buffer(
+            &self.config_buffer,
+            0,
+            bytemuck::cast_slice(&[OutlineConfigUniform {
+                params: [self.width.clamp(0.0, MAX_OUTLINE_WIDTH), 0.0, 0.0, 0.0],
+            }]),
+        );
+    }
+
+    /// Draws the outlines over target, which must be the size passed to update
+    pub(crate) fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        binded_meshes: &[BindedGeometryBuffers],
+        target_view: &wgpu::TextureView,
+    ) {
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: USE_LABELS.then_some("Outline mask"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.mask_texture.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            render_pass.set_pipeline(&self.mask_pipeline);
+            render_pass.set_bind_group(0, &self.instances_bind_group, &[]);
+            for (mesh_index, instance_index) in &self.draws {
+                let Some(geometry_buffers) = binded_meshes.get(*mesh_index) else {
+                    continue;
+                };
+                render_pass.set_vertex_buffer(0, geometry_buffers.vertex_buffer.src().slice(..));
+                render_pass.set_index_buffer(
+                    geometry_buffers.index_buffer.buffer.src().slice(..),
+                    geometry_buffers.index_buffer.format,
+                );
+                render_pass.draw_indexed(
+                    0..geometry_buffers.index_buffer.buffer.length() as u32,
+                    0,
+                    *instance_index..instance_index + 1,
+                );
+            }
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: USE_LABELS.then_some("Outline"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&self.composite_pipeline);
+        render_pass.set_bind_group(0, &self.composite_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    /// After the gpu device was recreated
+    pub(crate) fn recreate(&mut self, base: &BaseRenderer) {
+        let (width, colors) = (self.width, std::mem::take(&mut self.colors));
+        *self = Self::new(base);
+        (self.width, self.colors) = (width, colors);
+    }
+}
+
+/// The visible meshes of the outlined nodes and their descendants, with the color of their
+/// closest outlined ancestor
+fn outlined_meshes(
+    scene: &Scene,
+    colors: &HashMap<GameNodeId, Vec4>,
+) -> Vec<(GameNodeId, MeshHandle, Vec4)> {
+    if colors.is_empty() {
+        return vec![];
+    }
+    scene
+        .nodes()
+        .filter(|node| node.visible)
+        .filter_map(|node| {
+            let mesh = node.visual.as_ref()?.mesh;
+            let color = scene
+                .get_node_ancestry_list(node.id())
+                .find_map(|ancestor_id| colors.get(&ancestor_id))?;
+            Some((node.id(), mesh, *color))
+        })
+        .collect()
+}
+
+fn create_instances_bind_group(
+    base: &BaseRenderer,
+    layout: &wgpu::BindGroupLayout,
+    instances_buffer: &GpuBuffer,
+) -> wgpu::BindGroup {
+    base.device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: instances_buffer.src().as_entire_binding(),
+        }],
+        label: USE_LABELS.then_some("outline_instances_bind_group"),
+    })
+}
+
+fn create_composite_bind_group(
+    base: &BaseRenderer,
+    layout: &wgpu::BindGroupLayout,
+    mask_texture: &Texture,
+    config_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    base.device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&mask_texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: config_buffer.as_entire_binding(),
+            },
+        ],
+        label: USE_LABELS.then_some("outline_composite_bind_group"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::{GameNodeDescBuilder, GameNodeVisual, Material};
+    use glam::f32::Vec3;
+
+    #[test]
+    fn descendants_take_the_color_of_their_closest_outlined_ancestor() {
+        let mut scene = Scene::default();
+        let visual = || {
+            Some(GameNodeVisual::from_mesh_mat(
+                MeshHandle::unbinded(0),
+                Material::Unlit { color: Vec3::ONE },
+            ))
+        };
+        let root_id = scene.add_node(Default::default()).id();
+        let child_id = scene
+            .add_node(
+                GameNodeDescBuilder::new()
+                    .parent_id(Some(root_id))
+                    .visual(visual())
+                    .build(),
+            )
+            .id();
+        let grandchild_id = scene
+            .add_node(
+                GameNodeDescBuilder::new()
+                    .parent_id(Some(child_id))
+                    .visual(visual())
+                    .build(),
+            )
+            .id();
+        let hidden_id = scene
+            .add_node(
+                GameNodeDescBuilder::new()
+                    .parent_id(Some(root_id))
+                    .visual(visual())
+                    .visible(false)
+                    .build(),
+            )
+            .id();
+        scene.add_node(GameNodeDescBuilder::new().visual(visual()).build());
+
+        let selected = Vec4::new(1.0, 0.5, 0.0, 1.0);
+        let interactable = Vec4::new(0.0, 1.0, 1.0, 0.5);
+        let mut colors = HashMap::new();
+        colors.insert(root_id, selected);
+        colors.insert(grandchild_id, interactable);
+        let outlined: Vec<_> = outlined_meshes(&scene, &colors)
+            .into_iter()
+            .map(|(node_id, _, color)| (node_id, color))
+            .collect();
+        assert_eq!(
+            outlined,
+            vec![(child_id, selected), (grandchild_id, interactable)]
+        );
+        assert!(!outlined.iter().any(|(node_id, _)| *node_id == hidden_id));
+    }
+}
@@ -0,0 +1,355 @@
+use crate::asset_registry::MeshHandle;
+use crate::effects::{EffectDefinition, ParticleEmitterDefinition};
+use crate::renderer::Renderer;
+use crate::scene::{GameNodeDesc, GameNodeId, GameNodeVisual, Material, Scene};
+use crate::transform::{Transform, TransformBuilder};
+
+use glam::f32::{Quat, Vec3, Vec4};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Particle {
+    pub position: Vec3,
+    pub velocity: Vec3,
+    pub age_seconds: f32,
+    pub lifetime_seconds: f32,
+}
+
+impl Particle {
+    /// 0 when it's spawned, 1 when it dies
+    pub fn life_progress(&self) -> f32 {
+        if self.lifetime_seconds <= 0.0 {
+            return 1.0;
+        }
+        (self.age_seconds / self.lifetime_seconds).clamp(0.0, 1.0)
+    }
+}
+
+/// Simulates the particles of a ParticleEmitterDefinition on the cpu. The particles are shot
+/// within the definition's cone around the forward direction (-z) of the spawn transform
+#[derive(Debug, Clone)]
+pub struct ParticleEmitter {
+    pub definition: ParticleEmitterDefinition,
+    /// where the effect was spawned, the definition's offset is applied on top of it
+    pub transform: Transform,
+    age_seconds: f32,
+    /// fractional particles carried over to the next update
+    spawn_accumulator: f32,
+    is_stopped: bool,
+    particles: Vec<Particle>,
+}
+
+impl ParticleEmitter {
+    /// The burst is emitted on the first update
+    pub fn new(definition: ParticleEmitterDefinition, transform: Transform) -> Self {
+        Self {
+            spawn_accumulator: definition.burst_count as f32,
+            definition,
+            transform,
+            age_seconds: 0.0,
+            is_stopped: false,
+            particles: vec![],
+        }
+    }
+
+    /// No new particles are emitted, the live ones keep going until they die
+    pub fn stop(&mut self) {
+        self.is_stopped = true;
+    }
+
+    pub fn is_emitting(&self) -> bool {
+        !self.is_stopped
+            && self
+                .definition
+                .duration_seconds
+                .map_or(true, |duration| self.age_seconds < duration)
+    }
+
+    /// Done emitting and all its particles are dead
+    pub fn is_finished(&self) -> bool {
+        !self.is_emitting() && self.particles.is_empty() && self.spawn_accumulator < 1.0
+    }
+
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    /// gravity is the physics world's, scaled by the definition's gravity_scale
+    pub fn update(&mut self, delta_time_seconds: f32, gravity: Vec3) {
+        let gravity = gravity * self.definition.gravity_scale;
+        self.particles.retain_mut(|particle| {
+            particle.age_seconds += delta_time_seconds;
+            particle.velocity += gravity * delta_time_seconds;
+            particle.position += particle.velocity * delta_time_seconds;
+            particle.age_seconds < particle.lifetime_seconds
+        });
+
+        if self.is_emitting() {
+            self.spawn_accumulator += self.definition.spawn_rate * delta_time_seconds;
+        }
+        self.age_seconds += delta_time_seconds;
+
+        while self.spawn_accumulator >= 1.0 {
+            self.spawn_accumulator -= 1.0;
+            if self.particles.len() < self.definition.max_particles as usize {
+                self.particles.push(self.spawn_particle());
+            }
+        }
+    }
+
+    fn spawn_particle(&self) -> Particle {
+        let definition = &self.definition;
+        let random_in = |(min, max): (f32, f32)| min + (max - min) * rand::random::<f32>();
+
+        // uniform over the spherical cap of the cone
+        let cos_spread = definition.spread_angle_deg.to_radians().cos();
+        let cos_theta = 1.0 - rand::random::<f32>() * (1.0 - cos_spread);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = rand::random::<f32>() * std::f32::consts::TAU;
+        let local_direction = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), -cos_theta);
+
+        let rotation = self.transform.rotation();
+        Particle {
+            position: self.transform.position() + rotation * Vec3::from(definition.offset),
+            velocity: rotation * local_direction * random_in(definition.initial_speed),
+            age_seconds: 0.0,
+            lifetime_seconds: random_in(definition.lifetime_seconds),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ParticleEmitterId(u64);
+
+/// Runs the particle emitters and draws each particle as a transparent quad facing the camera.
+/// The nodes of the dead particles are hidden and reused by the next ones
+#[derive(Debug)]
+pub struct ParticleSystem {
+    quad_mesh: MeshHandle,
+    emitters: Vec<(ParticleEmitterId, ParticleEmitter, Vec<GameNodeId>)>,
+    free_node_ids: Vec<GameNodeId>,
+    next_emitter_id: u64,
+    particle_limit_scale: f32,
+}
+
+impl ParticleSystem {
+    pub fn new(renderer: &Renderer) -> Self {
+        let quad_mesh = Renderer::bind_basic_mesh(
+            &renderer.base,
+            &mut renderer.data.lock().unwrap(),
+            &crate::decals::decal_quad(),
+            false,
+        );
+        Self::with_quad_mesh(quad_mesh)
+    }
+
+    fn with_quad_mesh(quad_mesh: MeshHandle) -> Self {
+        Self {
+            quad_mesh,
+            emitters: vec![],
+            free_node_ids: vec![],
+            next_emitter_id: 0,
+            particle_limit_scale: 1.0,
+        }
+    }
+
+    /// Scales the max_particles of the emitters spawned from now on, e.g. with
+    /// GraphicsSettings::particle_limit_scale
+    pub fn set_particle_limit_scale(&mut self, particle_limit_scale: f32) {
+        self.particle_limit_scale = particle_limit_scale;
+    }
+
+    pub fn spawn(
+        &mut self,
+        mut definition: ParticleEmitterDefinition,
+        transform: Transform,
+    ) -> ParticleEmitterId {
+        definition.max_particles =
+            particle_limit(definition.max_particles, self.particle_limit_scale);
+        let emitter_id = ParticleEmitterId(self.next_emitter_id);
+        self.next_emitter_id += 1;
+        self.emitters.push((
+            emitter_id,
+            ParticleEmitter::new(definition, transform),
+            vec![],
+        ));
+        emitter_id
+    }
+
+    /// Spawns all the particle emitters of the effect, the rest of the effect is up to the caller
+    pub fn spawn_effect(
+        &mut self,
+        effect: &EffectDefinition,
+        transform: Transform,
+    ) -> Vec<ParticleEmitterId> {
+        effect
+            .particle_emitters
+            .iter()
+            .map(|definition| self.spawn(definition.clone(), transform))
+            .collect()
+    }
+
+    pub fn get_mut(&mut self, emitter_id: ParticleEmitterId) -> Option<&mut ParticleEmitter> {
+        self.emitters
+            .iter_mut()
+            .find(|(id, _, _)| *id == emitter_id)
+            .map(|(_, emitter, _)| emitter)
+    }
+
+    /// The emitter is removed once its last particle dies
+    pub fn stop(&mut self, emitter_id: ParticleEmitterId) {
+        if let Some(emitter) = self.get_mut(emitter_id) {
+            emitter.stop();
+        }
+    }
+
+    pub fn emitter_count(&self) -> usize {
+        self.emitters.len()
+    }
+
+    pub fn particle_count(&self) -> usize {
+        self.emitters
+            .iter()
+            .map(|(_, emitter, _)| emitter.particles().len())
+            .sum()
+    }
+
+    /// Simulates the particles and moves their nodes, turning them towards the camera
+    #[profiling::function]
+    pub fn update(
+        &mut self,
+        scene: &mut Scene,
+        delta_time_seconds: f32,
+        gravity: Vec3,
+        camera_position: Vec3,
+    ) {
+        for (_, emitter, node_ids) in &mut self.emitters {
+            emitter.update(delta_time_seconds, gravity);
+
+            while node_ids.len() > emitter.particles().len() {
+                let node_id = node_ids.pop().unwrap();
+                if let Some(node) = scene.get_node_mut(node_id) {
+                    node.visible = false;
+                    self.free_node_ids.push(node_id);
+                }
+            }
+            while node_ids.len() < emitter.particles().len() {
+                let node_id = self
+                    .free_node_ids
+                    .pop()
+                    .filter(|node_id| scene.get_node(*node_id).is_some())
+                    .unwrap_or_else(|| {
+                        scene
+                            .add_node(GameNodeDesc {
+                                name: Some("particle".to_string()),
+                                casts_shadows: false,
+                                ..Default::default()
+                            })
+                            .id()
+                    });
+                node_ids.push(node_id);
+            }
+
+            let definition = &emitter.definition;
+            for (particle, node_id) in emitter.particles().iter().zip(node_ids.iter()) {
+                let Some(node) = scene.get_node_mut(*node_id) else {
+                    continue;
+                };
+                let t = particle.life_progress();
+                let size =
+                    definition.start_size + (definition.end_size - definition.start_size) * t;
+                let color =
+                    Vec4::from(definition.start_color).lerp(Vec4::from(definition.end_color), t);
+                let to_camera = (camera_position - particle.position)
+                    .try_normalize()
+                    .unwrap_or(Vec3::Z);
+                node.transform = TransformBuilder::new()
+                    .position(particle.position)
+                    .rotation(Quat::from_rotation_arc(Vec3::Z, to_camera))
+                    .scale(Vec3::splat(size))
+                    .build();
+                node.visual = Some(GameNodeVisual::from_mesh_mat(
+                    self.quad_mesh,
+                    Material::Transparent {
+                        color,
+                        premultiplied_alpha: false,
+                    },
+                ));
+                node.visible = true;
+            }
+        }
+
+        let free_node_ids = &mut self.free_node_ids;
+        self.emitters.retain(|(_, emitter, node_ids)| {
+            if !emitter.is_finished() {
+                return true;
+            }
+            free_node_ids.extend(node_ids);
+            false
+        });
+    }
+
+    /// Removes all the emitters and the nodes of their particles
+    pub fn clear(&mut self, scene: &mut Scene) {
+        for (_, _, node_ids) in self.emitters.drain(..) {
+            for node_id in node_ids {
+                scene.remove_node(node_id);
+            }
+        }
+        for node_id in self.free_node_ids.drain(..) {
+            scene.remove_node(node_id);
+        }
+    }
+}
+
+/// How many particles an emitter with this max_particles may have alive once scaled, at
+/// least one
+pub fn particle_limit(max_particles: u32, particle_limit_scale: f32) -> u32 {
+    ((max_particles as f32 * particle_limit_scale).round() as u32).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emitters_burst_spawn_over_time_and_finish() {
+        let definition = ParticleEmitterDefinition {
+            spawn_rate: 10.0,
+            burst_count: 5,
+            duration_seconds: Some(1.0),
+            max_particles: 8,
+            lifetime_seconds: (0.5, 0.5),
+            initial_speed: (2.0, 2.0),
+            spread_angle_deg: 0.0,
+            ..Default::default()
+        };
+        let mut scene = Scene::default();
+        let mut particle_system = ParticleSystem::with_quad_mesh(MeshHandle::unbinded(0));
+        particle_system.spawn(definition.clone(), Transform::IDENTITY);
+
+        particle_system.update(&mut scene, 0.1, Vec3::ZERO, Vec3::Z);
+        // the burst and one from the spawn rate
+        assert_eq!(particle_system.particle_count(), 6);
+        let particle = particle_system.emitters[0].1.particles()[0];
+        assert!((particle.velocity - Vec3::new(0.0, 0.0, -2.0)).length() < 1e-4);
+
+        for _ in 0..3 {
+            particle_system.update(&mut scene, 0.1, Vec3::ZERO, Vec3::Z);
+        }
+        // capped
+        assert_eq!(particle_system.particle_count(), 8);
+        let node_count = scene.node_count();
+
+        for _ in 0..15 {
+            particle_system.update(&mut scene, 0.1, Vec3::ZERO, Vec3::Z);
+        }
+        assert_eq!(particle_system.emitter_count(), 0);
+        assert_eq!(scene.node_count(), node_count);
+        assert!(scene.nodes().all(|node| !node.visible));
+
+        particle_system.set_particle_limit_scale(0.5);
+        particle_system.spawn(definition, Transform::IDENTITY);
+        particle_system.update(&mut scene, 0.1, Vec3::ZERO, Vec3::Z);
+        assert_eq!(particle_system.particle_count(), 4);
+    }
+}
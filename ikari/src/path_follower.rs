@@ -0,0 +1,175 @@
+use crate::animation::LoopType;
+use crate::ecs::SceneNode;
+use crate::engine_state::EngineState;
+use crate::math::SplinePath;
+
+use glam::f32::{Mat3, Quat, Vec3};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PathOrientation {
+    /// the rotation of the node isn't touched
+    Keep,
+    /// faces the direction of travel, e.g. for platforms and projectiles
+    FollowPath { up: Vec3 },
+    /// e.g. for a camera on a rail that keeps looking at the same spot
+    LookAt { target: Vec3, up: Vec3 },
+}
+
+/// Component that moves the entity's SceneNode along a spline at a constant speed, see
+/// update_path_followers. The path is in the space of the node's parent
+#[derive(Debug, Clone)]
+pub struct PathFollower {
+    pub path: SplinePath,
+    /// units per second, negative to go backwards
+    pub speed: f32,
+    pub loop_type: LoopType,
+    pub orientation: PathOrientation,
+    pub is_playing: bool,
+    /// keeps growing past the length of the path like AnimationState::current_time_seconds
+    pub traveled_distance: f32,
+}
+
+impl PathFollower {
+    pub fn new(path: SplinePath, speed: f32) -> Self {
+        Self {
+            path,
+            speed,
+            loop_type: LoopType::Once,
+            orientation: PathOrientation::FollowPath { up: Vec3::Y },
+            is_playing: true,
+            traveled_distance: 0.0,
+        }
+    }
+
+    pub fn advance(&mut self, delta_time_seconds: f32) {
+        if self.is_playing {
+            self.traveled_distance += self.speed * delta_time_seconds;
+        }
+    }
+
+    /// only Once paths finish, at either end
+    pub fn is_finished(&self) -> bool {
+        self.loop_type == LoopType::Once
+            && if self.speed >= 0.0 {
+                self.traveled_distance >= self.path.length()
+            } else {
+                self.traveled_distance <= 0.0
+            }
+    }
+
+    /// where traveled_distance currently is on the path, taking the loop type into account
+    pub fn distance_along_path(&self) -> f32 {
+        let length = self.path.length();
+        if length <= 0.0 {
+            return 0.0;
+        }
+        match self.loop_type {
+            LoopType::Once => self.traveled_distance.clamp(0.0, length),
+            LoopType::Wrap => self.traveled_distance.rem_euclid(length),
+            LoopType::PingPong => {
+                let distance = self.traveled_distance.rem_euclid(2.0 * length);
+                if distance > length {
+                    2.0 * length - distance
+                } else {
+                    distance
+                }
+            }
+        }
+    }
+
+    /// going backwards along the path on the way back of a ping pong
+    fn is_reversed(&self) -> bool {
+        let length = self.path.length();
+        let is_on_way_back = self.loop_type == LoopType::PingPong
+            && length > 0.0
+            && self.traveled_distance.rem_euclid(2.0 * length) > length;
+        is_on_way_back != (self.speed < 0.0)
+    }
+
+    pub fn position(&self) -> Vec3 {
+        self.path.point_at_distance(self.distance_along_path())
+    }
+
+    /// None for PathOrientation::Keep or when the direction can't be worked out
+    pub fn rotation(&self) -> Option<Quat> {
+        let position = self.position();
+        let (forward, up) = match self.orientation {
+            PathOrientation::Keep => return None,
+            PathOrientation::FollowPath { up } => {
+                let direction = self.path.direction_at_distance(self.distance_along_path());
+                (
+                    if self.is_reversed() {
+                        -direction
+                    } else {
+                        direction
+                    },
+                    up,
+                )
+            }
+            PathOrientation::LookAt { target, up } => ((target - position).normalize_or_zero(), up),
+        };
+        let right = forward.cross(up).normalize_or_zero();
+        if forward == Vec3::ZERO || right == Vec3::ZERO {
+            return None;
+        }
+        // -z is forward, like the cameras
+        let up = right.cross(forward);
+        Some(Quat::from_mat3(&Mat3::from_cols(right, up, -forward)))
+    }
+}
+
+/// System that moves the nodes of the PathFollower components, meant for
+/// SystemStage::FixedUpdate along with interpolate_transform on the nodes
+#[profiling::function]
+pub fn update_path_followers(engine_state: &mut EngineState) {
+    let delta_time_seconds = engine_state.simulation_timestep.timestep_seconds as f32;
+    for entity in engine_state.world.entities_with::<PathFollower>() {
+        let Some(SceneNode(node_id)) = engine_state.world.get::<SceneNode>(entity).copied() else {
+            continue;
+        };
+        let Some(path_follower) = engine_state.world.get_mut::<PathFollower>(entity) else {
+            continue;
+        };
+        path_follower.advance(delta_time_seconds);
+        let position = path_follower.position();
+        let rotation = path_follower.rotation();
+
+        if let Some(node) = engine_state.scene.get_node_mut(node_id) {
+            node.transform.set_position(position);
+            if let Some(rotation) = rotation {
+                node.transform.set_rotation(rotation);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Spline;
+
+    #[test]
+    fn ping_pong_turns_around_at_the_ends() {
+        let path = SplinePath::new(Spline::CatmullRom {
+            points: vec![
+                Vec3::ZERO,
+                Vec3::new(0.0, 0.0, -5.0),
+                Vec3::new(0.0, 0.0, -10.0),
+            ],
+            is_closed: false,
+        });
+        let mut path_follower = PathFollower::new(path, 4.0);
+        path_follower.loop_type = LoopType::PingPong;
+
+        path_follower.advance(2.0);
+
+        assert!((path_follower.distance_along_path() - 8.0).abs() < 1e-3);
+        let forward = path_follower.rotation().unwrap() * Vec3::NEG_Z;
+        assert!(forward.distance(Vec3::NEG_Z) < 1e-3, "{forward}");
+        path_follower.advance(1.0);
+        assert!((path_follower.distance_along_path() - 8.0).abs() < 1e-3);
+        let forward = path_follower.rotation().unwrap() * Vec3::NEG_Z;
+        assert!(forward.distance(Vec3::Z) < 1e-3, "{forward}");
+        assert!(!path_follower.is_finished());
+    }
+}
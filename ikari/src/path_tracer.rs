@@ -0,0 +1,749 @@
+use crate::camera::ShaderCameraData;
+use crate::renderer::{BaseRenderer, BindedPbrMaterial, BindedSkybox, RetainedCpuData, USE_LABELS};
+use crate::scene::{Material, Scene};
+use crate::texture::Texture;
+
+use glam::f32::{Mat3, Mat4, Vec3};
+use wgpu::util::DeviceExt;
+
+pub const MAX_PATH_TRACER_DIRECTIONAL_LIGHTS: usize = 4;
+pub const MAX_PATH_TRACER_POINT_LIGHTS: usize = 16;
+
+const WORKGROUP_SIZE: u32 = 8;
+const MAX_TRIANGLES_PER_LEAF: usize = 4;
+
+#[derive(Debug, Copy, Clone)]
+pub struct PathTracerParams {
+    /// 0 only shows the emissive surfaces, the direct light and the environment
+    pub max_bounces: u32,
+    /// the image stops being refined once it has this many samples per pixel
+    pub max_samples: u32,
+}
+
+impl Default for PathTracerParams {
+    fn default() -> Self {
+        Self {
+            max_bounces: 4,
+            max_samples: 4096,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuTriangle {
+    /// the material index is in the w of the first position, as bits
+    positions: [[f32; 4]; 3],
+    normals: [[f32; 4]; 3],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuBvhNode {
+    aabb_min: [f32; 3],
+    /// first triangle for leaves, left child for branches. The right child is right after it
+    left_or_first: u32,
+    aabb_max: [f32; 3],
+    /// 0 for branches
+    triangle_count: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuMaterial {
+    base_color: [f32; 4],
+    emissive: [f32; 4],
+    /// metallic, roughness, padding
+    metallic_roughness: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuLight {
+    /// towards the light for directional lights
+    direction_or_position: [f32; 4],
+    /// color * intensity
+    radiance: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct PathTracerUniform {
+    inv_view_proj: [[f32; 4]; 4],
+    resolution: [u32; 2],
+    sample_index: u32,
+    max_bounces: u32,
+    /// directional, point, padding
+    light_counts: [u32; 4],
+    /// skybox weights, environment intensity, padding
+    environment: [f32; 4],
+    directional_lights: [GpuLight; MAX_PATH_TRACER_DIRECTIONAL_LIGHTS],
+    point_lights: [GpuLight; MAX_PATH_TRACER_POINT_LIGHTS],
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Triangle {
+    positions: [Vec3; 3],
+    normals: [Vec3; 3],
+    material_index: u32,
+}
+
+impl Triangle {
+    fn centroid(&self) -> Vec3 {
+        (self.positions[0] + self.positions[1] + self.positions[2]) / 3.0
+    }
+}
+
+struct SceneBuffers {
+    _buffers: [wgpu::Buffer; 3],
+    triangle_count: usize,
+    bind_group: wgpu::BindGroup,
+}
+
+struct Target {
+    size: wgpu::Extent3d,
+    accumulation_buffer: wgpu::Buffer,
+    output_texture: wgpu::Texture,
+    output_view: wgpu::TextureView,
+}
+
+/// A progressive path tracer for reference images of the main camera's view, e.g. to check the
+/// real-time PBR and IBL against or for beauty shots. It replaces the shaded image before bloom
+/// and tone mapping, so the transparent and unlit meshes are still drawn on top of it.
+///
+/// The scene is a snapshot of the static PBR meshes that's taken when the path tracer is enabled
+/// or when rebuild_scene is called. Only the materials' factors are used, not their textures,
+/// and the meshes need a CPU copy, see RendererConfig::retain_cpu_data.
+/// Lives in RendererData::path_tracer, the image restarts whenever the camera, the lights or
+/// the params change
+pub struct PathTracer {
+    pub params: PathTracerParams,
+    enabled: bool,
+    scene_is_dirty: bool,
+    sample_count: u32,
+    last_uniform: Option<PathTracerUniform>,
+    scene_bind_group_layout: wgpu::BindGroupLayout,
+    target_bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+    uniform_buffer: wgpu::Buffer,
+    scene: Option<SceneBuffers>,
+    target: Option<Target>,
+}
+
+impl PathTracer {
+    pub(crate) fn new(base: &BaseRenderer) -> Self {
+        let device = &base.device;
+
+        let storage_buffer_entry = |binding: u32, read_only: bool| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let cube_texture_entry = |binding: u32| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::Cube,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+
+        let scene_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    storage_buffer_entry(1, true),
+                    storage_buffer_entry(2, true),
+                    storage_buffer_entry(3, true),
+                ],
+                label: USE_LABELS.then_some("path_tracer_scene_bind_group_layout"),
+            });
+        let target_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    storage_buffer_entry(0, false),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format: wgpu::TextureFormat::Rgba16Float,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    cube_texture_entry(2),
+                    cube_texture_entry(3),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+                label: USE_LABELS.then_some("path_tracer_target_bind_group_layout"),
+            });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: USE_LABELS.then_some("Path tracer shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/path_tracer.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: USE_LABELS.then_some("Path tracer pipeline layout"),
+            bind_group_layouts: &[&scene_bind_group_layout, &target_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: USE_LABELS.then_some("Path tracer pipeline"),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "cs_main",
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: USE_LABELS.then_some("Path tracer uniform buffer"),
+            size: std::mem::size_of::<PathTracerUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            params: PathTracerParams::default(),
+            enabled: false,
+            scene_is_dirty: true,
+            sample_count: 0,
+            last_uniform: None,
+            scene_bind_group_layout,
+            target_bind_group_layout,
+            pipeline,
+            uniform_buffer,
+            scene: None,
+            target: None,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// The scene snapshot is taken again when the path tracer is turned on
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if enabled && !self.enabled {
+            self.rebuild_scene();
+        }
+        self.enabled = enabled;
+        if !enabled {
+            // frees the memory, the scene can be big
+            self.scene = None;
+            self.target = None;
+        }
+    }
+
+    /// Takes a new snapshot of the scene on the next update, e.g. after nodes moved
+    pub fn rebuild_scene(&mut self) {
+        self.scene_is_dirty = true;
+        self.reset();
+    }
+
+    /// Starts the image over
+    pub fn reset(&mut self) {
+        self.sample_count = 0;
+    }
+
+    /// Samples per pixel accumulated so far
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// Triangles in the current scene snapshot
+    pub fn triangle_count(&self) -> usize {
+        self.scene.as_ref().map_or(0, |scene| scene.triangle_count)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    #[profiling::function]
+    pub(crate) fn update(
+        &mut self,
+        base: &BaseRenderer,
+        scene: &Scene,
+        retained_cpu_data: &RetainedCpuData,
+        binded_pbr_materials: &[BindedPbrMaterial],
+        camera: &ShaderCameraData,
+        size: wgpu::Extent3d,
+        skybox_weights: [f32; 2],
+        environment_intensity: f32,
+    ) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.scene_is_dirty || self.scene.is_none() {
+            self.scene = Some(self.create_scene_buffers(
+                base,
+                scene,
+                retained_cpu_data,
+                binded_pbr_materials,
+            ));
+            self.scene_is_dirty = false;
+            self.sample_count = 0;
+        }
+
+        if self.target.as_ref().map(|target| target.size) != Some(size) {
+            self.target = Some(create_target(base, size));
+            self.sample_count = 0;
+        }
+
+        let mut uniform = PathTracerUniform {
+            inv_view_proj: (camera.proj * camera.view).inverse().to_cols_array_2d(),
+            resolution: [size.width, size.height],
+            sample_index: 0,
+            max_bounces: self.params.max_bounces,
+            light_counts: [0; 4],
+            environment: [
+                skybox_weights[0],
+                skybox_weights[1],
+                environment_intensity,
+                0.0,
+            ],
+            directional_lights: Default::default(),
+            point_lights: Default::default(),
+        };
+        for (light, gpu_light) in scene
+            .directional_lights
+            .iter()
+            .zip(uniform.directional_lights.iter_mut())
+        {
+            *gpu_light = GpuLight {
+                direction_or_position: (-light.direction.normalize()).extend(0.0).to_array(),
+                radiance: (light.color * light.intensity).extend(0.0).to_array(),
+            };
+            uniform.light_counts[0] += 1;
+        }
+        // the lights can be parented to other nodes, e.g. a lamp carried by a character
+        let point_lights = scene.point_lights.iter().filter_map(|light| {
+            scene.get_node(light.node_id)?;
+            let position = scene
+                .get_global_transform_for_node(light.node_id)
+                .position();
+            Some((light, position))
+        });
+        for ((light, position), gpu_light) in point_lights.zip(uniform.point_lights.iter_mut()) {
+            *gpu_light = GpuLight {
+                direction_or_position: position.extend(1.0).to_array(),
+                radiance: (light.color * light.intensity).extend(0.0).to_array(),
+            };
+            uniform.light_counts[1] += 1;
+        }
+
+        if self.last_uniform != Some(uniform) {
+            self.sample_count = 0;
+        }
+        self.last_uniform = Some(uniform);
+
+        if self.sample_count < self.params.max_samples {
+            uniform.sample_index = self.sample_count;
+            base.queue
+                .write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+        }
+    }
+
+    /// Adds a sample to every pixel and copies the result to the shading texture
+    pub(crate) fn render(
+        &mut self,
+        base: &BaseRenderer,
+        encoder: &mut wgpu::CommandEncoder,
+        skyboxes: &[BindedSkybox; 2],
+        shading_texture: &Texture,
+    ) {
+        let (Some(scene), Some(target)) = (&self.scene, &self.target) else {
+            return;
+        };
+        if target.size != shading_texture.size {
+            return;
+        }
+
+        if self.sample_count < self.params.max_samples {
+            let target_bind_group = {
+                let sampler_cache_guard = base.sampler_cache.lock().unwrap();
+                base.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &self.target_bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: target.accumulation_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(&target.output_view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(
+                                &skyboxes[0].background.view,
+                            ),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: wgpu::BindingResource::TextureView(
+                                &skyboxes[1].background.view,
+                            ),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 4,
+                            resource: wgpu::BindingResource::Sampler(
+                                sampler_cache_guard
+                                    .get_sampler_by_index(skyboxes[0].background.sampler_index),
+                            ),
+                        },
+                    ],
+                    label: USE_LABELS.then_some("path_tracer_target_bind_group"),
+                })
+            };
+
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: USE_LABELS.then_some("Path tracing"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &scene.bind_group, &[]);
+            compute_pass.set_bind_group(1, &target_bind_group, &[]);
+            compute_pass.dispatch_workgroups(
+                (target.size.width + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                (target.size.height + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                1,
+            );
+            drop(compute_pass);
+
+            self.sample_count += 1;
+        }
+
+        encoder.copy_texture_to_texture(
+            target.output_texture.as_image_copy(),
+            shading_texture.texture.as_image_copy(),
+            target.size,
+        );
+    }
+
+    /// After the gpu device was recreated
+    pub(crate) fn recreate(&mut self, base: &BaseRenderer) {
+        let (params, enabled) = (self.params, self.enabled);
+        *self = Self::new(base);
+        self.params = params;
+        self.enabled = enabled;
+    }
+
+    fn create_scene_buffers(
+        &self,
+        base: &BaseRenderer,
+        scene: &Scene,
+        retained_cpu_data: &RetainedCpuData,
+        binded_pbr_materials: &[BindedPbrMaterial],
+    ) -> SceneBuffers {
+        let mut triangles = vec![];
+        let mut materials = vec![];
+        let mut skipped_mesh_count = 0;
+
+        for node in scene.nodes() {
+            let Some(visual) = node.visual.as_ref() else {
+                continue;
+            };
+            let Material::Pbr {
+                binded_material,
+                dynamic_pbr_params,
+            } = visual.material
+            else {
+                continue;
+            };
+            if node.skin_index.is_some() {
+                continue;
+            }
+            let Some(geometry) = retained_cpu_data.mesh(visual.mesh.index()) else {
+                skipped_mesh_count += 1;
+                continue;
+            };
+            let params = dynamic_pbr_params
+                .or_else(|| {
+                    binded_pbr_materials
+                        .get(binded_material.index())
+                        .map(|material| material.dynamic_pbr_params)
+                })
+                .unwrap_or_default();
+            let material_index = materials.len() as u32;
+            materials.push(GpuMaterial {
+                base_color: params.base_color_factor.to_array(),
+                emissive: params.emissive_factor.extend(0.0).to_array(),
+                metallic_roughness: [params.metallic_factor, params.roughness_factor, 0.0, 0.0],
+            });
+
+            let transform: Mat4 = scene.get_global_transform_for_node(node.id()).into();
+            let normal_transform = Mat3::from_mat4(transform).inverse().transpose();
+            let vertices = &geometry.vertices;
+            for indices in geometry.indices.to_u32_vec().chunks_exact(3) {
+                let corners =
+                    [indices[0], indices[1], indices[2]].map(|index| &vertices[index as usize]);
+                triangles.push(Triangle {
+                    positions: corners
+                        .map(|vertex| transform.transform_point3(Vec3::from(vertex.position))),
+                    normals: corners.map(|vertex| {
+                        (normal_transform * Vec3::from(vertex.normal)).normalize_or_zero()
+                    }),
+                    material_index,
+                });
+            }
+        }
+
+        if skipped_mesh_count > 0 {
+            log::warn!(
+                "The path tracer skipped {skipped_mesh_count} meshes that have no CPU copy, see RendererConfig::retain_cpu_data"
+            );
+        }
+
+        let triangle_count = triangles.len();
+        let mut nodes = build_bvh(&mut triangles);
+        let mut gpu_triangles: Vec<GpuTriangle> = triangles
+            .iter()
+            .map(|triangle| {
+                let [p0, p1, p2] = triangle.positions;
+                let [n0, n1, n2] = triangle.normals;
+                GpuTriangle {
+                    positions: [
+                        p0.extend(f32::from_bits(triangle.material_index))
+                            .to_array(),
+                        p1.extend(0.0).to_array(),
+                        p2.extend(0.0).to_array(),
+                    ],
+                    normals: [
+                        n0.extend(0.0).to_array(),
+                        n1.extend(0.0).to_array(),
+                        n2.extend(0.0).to_array(),
+                    ],
+                }
+            })
+            .collect();
+
+        // storage buffers can't be empty
+        if nodes.is_empty() {
+            nodes.push(GpuBvhNode {
+                aabb_min: [1.0; 3],
+                left_or_first: 0,
+                aabb_max: [-1.0; 3],
+                triangle_count: 0,
+            });
+        }
+        if gpu_triangles.is_empty() {
+            gpu_triangles.push(bytemuck::Zeroable::zeroed());
+        }
+        if materials.is_empty() {
+            materials.push(bytemuck::Zeroable::zeroed());
+        }
+
+        let device = &base.device;
+        let make_storage_buffer = |label: &str, contents: &[u8]| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: USE_LABELS.then_some(label),
+                contents,
+                usage: wgpu::BufferUsages::STORAGE,
+            })
+        };
+        let buffers = [
+            make_storage_buffer(
+                "Path tracer triangles buffer",
+                bytemuck::cast_slice(&gpu_triangles),
+            ),
+            make_storage_buffer("Path tracer bvh buffer", bytemuck::cast_slice(&nodes)),
+            make_storage_buffer(
+                "Path tracer materials buffer",
+                bytemuck::cast_slice(&materials),
+            ),
+        ];
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.scene_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: buffers[0].as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: buffers[1].as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: buffers[2].as_entire_binding(),
+                },
+            ],
+            label: USE_LABELS.then_some("path_tracer_scene_bind_group"),
+        });
+
+        SceneBuffers {
+            _buffers: buffers,
+            triangle_count,
+            bind_group,
+        }
+    }
+}
+
+fn create_target(base: &BaseRenderer, size: wgpu::Extent3d) -> Target {
+    let accumulation_buffer = base.device.create_buffer(&wgpu::BufferDescriptor {
+        label: USE_LABELS.then_some("Path tracer accumulation buffer"),
+        // a vec4<f32> per pixel
+        size: (size.width * size.height) as u64 * 16,
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    });
+    let output_texture = base.device.create_texture(&wgpu::TextureDescriptor {
+        label: USE_LABELS.then_some("Path tracer output texture"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba16Float,
+        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let output_view = output_texture.create_view(&Default::default());
+    Target {
+        size,
+        accumulation_buffer,
+        output_texture,
+        output_view,
+    }
+}
+
+/// Sorts the triangles so that each leaf's are contiguous. The nodes are split in the middle of
+/// the longest axis of their triangles' centroids
+fn build_bvh(triangles: &mut [Triangle]) -> Vec<GpuBvhNode> {
+    if triangles.is_empty() {
+        return vec![];
+    }
+
+    let mut nodes = vec![GpuBvhNode {
+        aabb_min: [0.0; 3],
+        left_or_first: 0,
+        aabb_max: [0.0; 3],
+        triangle_count: triangles.len() as u32,
+    }];
+    let mut to_split = vec![0];
+
+    while let Some(node_index) = to_split.pop() {
+        let first = nodes[node_index].left_or_first as usize;
+        let count = nodes[node_index].triangle_count as usize;
+        let node_triangles = &mut triangles[first..first + count];
+
+        let (aabb_min, aabb_max) = node_triangles
+            .iter()
+            .flat_map(|triangle| triangle.positions)
+            .fold(
+                (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+                |(min, max), position| (min.min(position), max.max(position)),
+            );
+        nodes[node_index].aabb_min = aabb_min.to_array();
+        nodes[node_index].aabb_max = aabb_max.to_array();
+
+        if count <= MAX_TRIANGLES_PER_LEAF {
+            continue;
+        }
+
+        let (centroid_min, centroid_max) = node_triangles.iter().map(Triangle::centroid).fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), centroid| (min.min(centroid), max.max(centroid)),
+        );
+        let extent = centroid_max - centroid_min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        let split = 0.5 * (centroid_min[axis] + centroid_max[axis]);
+
+        let mut left_count = 0;
+        for index in 0..count {
+            if node_triangles[index].centroid()[axis] < split {
+                node_triangles.swap(index, left_count);
+                left_count += 1;
+            }
+        }
+        // all the centroids are on the same side, e.g. when they're all the same
+        if left_count == 0 || left_count == count {
+            left_count = count / 2;
+            node_triangles.select_nth_unstable_by(left_count, |a, b| {
+                a.centroid()[axis].total_cmp(&b.centroid()[axis])
+            });
+        }
+
+        let left_child = nodes.len();
+        for (child_first, child_count) in [
+            (first, left_count),
+            (first + left_count, count - left_count),
+        ] {
+            nodes.push(GpuBvhNode {
+                aabb_min: [0.0; 3],
+                left_or_first: child_first as u32,
+                aabb_max: [0.0; 3],
+                triangle_count: child_count as u32,
+            });
+            to_split.push(nodes.len() - 1);
+        }
+        nodes[node_index].left_or_first = left_child as u32;
+        nodes[node_index].triangle_count = 0;
+    }
+
+    nodes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bvh_leaves_cover_each_triangle_once() {
+        let mut triangles: Vec<Triangle> = (0..100)
+            .map(|index| {
+                let offset = Vec3::new((index % 10) as f32, (index / 10) as f32, 0.0);
+                Triangle {
+                    positions: [Vec3::ZERO, Vec3::X, Vec3::Y].map(|corner| corner + offset),
+                    normals: [Vec3::Z; 3],
+                    material_index: index,
+                }
+            })
+            .collect();
+
+        let nodes = build_bvh(&mut triangles);
+
+        let mut seen = vec![0; triangles.len()];
+        for node in nodes.iter().filter(|node| node.triangle_count > 0) {
+            assert!(node.triangle_count as usize <= MAX_TRIANGLES_PER_LEAF);
+            let first = node.left_or_first as usize;
+            for triangle in &triangles[first..first + node.triangle_count as usize] {
+                seen[triangle.material_index as usize] += 1;
+                for position in triangle.positions {
+                    assert!(Vec3::from(node.aabb_min).cmple(position).all());
+                    assert!(Vec3::from(node.aabb_max).cmpge(position).all());
+                }
+            }
+        }
+        assert!(seen.iter().all(|count| *count == 1));
+    }
+}
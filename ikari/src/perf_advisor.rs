@@ -0,0 +1,439 @@
+use crate::camera::CameraLens;
+use crate::renderer::*;
+use crate::scene::*;
+use crate::time::Duration;
+
+use std::collections::HashMap;
+use std::fmt;
+
+use glam::f32::Vec3;
+
+/// Thresholds used by analyze_scene to decide what's worth reporting
+#[derive(Debug, Copy, Clone)]
+pub struct PerfAdvisorConfig {
+    /// a texture is reported when its largest side has this many times more texels
+    /// than the pixels covered by the largest on-screen footprint of the nodes that use it
+    pub max_texels_per_pixel: f32,
+    /// textures smaller than this are never reported
+    pub min_reported_texture_size: u32,
+    /// a mesh is reported when it has more triangles than this per pixel
+    /// of its largest on-screen footprint
+    pub max_triangles_per_pixel: f32,
+    /// meshes with fewer triangles than this are never reported
+    pub min_reported_triangle_count: usize,
+    /// a material is reported when at least this many different meshes
+    /// that are only drawn once use it
+    pub min_batchable_mesh_count: usize,
+    /// a point light is reported when at least this many other point lights reach its position
+    pub max_overlapping_lights: usize,
+    /// the light's influence radius is the distance at which its brightness drops below this
+    pub light_influence_cutoff: f32,
+    pub target_frame_time: Duration,
+}
+
+impl Default for PerfAdvisorConfig {
+    fn default() -> Self {
+        Self {
+            max_texels_per_pixel: 4.0,
+            min_reported_texture_size: 512,
+            max_triangles_per_pixel: 1.0,
+            min_reported_triangle_count: 2000,
+            min_batchable_mesh_count: 8,
+            max_overlapping_lights: 3,
+            light_influence_cutoff: 0.01,
+            target_frame_time: Duration::from_secs_f64(1.0 / 60.0),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PerfSuggestionKind {
+    OversizedTexture,
+    DenseMesh,
+    BatchableMaterial,
+    OverlappingLights,
+    SlowFrame,
+}
+
+impl fmt::Display for PerfSuggestionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                PerfSuggestionKind::OversizedTexture => "Oversized texture",
+                PerfSuggestionKind::DenseMesh => "Dense mesh",
+                PerfSuggestionKind::BatchableMaterial => "Batchable material",
+                PerfSuggestionKind::OverlappingLights => "Overlapping lights",
+                PerfSuggestionKind::SlowFrame => "Slow frame",
+            }
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PerfSuggestion {
+    pub kind: PerfSuggestionKind,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PerfReport {
+    pub node_count: usize,
+    pub visual_node_count: usize,
+    pub point_light_count: usize,
+    pub frame_time: Option<Duration>,
+    /// false when there was no camera to measure the on-screen footprints from,
+    /// in which case the texture and mesh density checks were skipped
+    pub measured_footprints: bool,
+    pub suggestions: Vec<PerfSuggestion>,
+}
+
+impl fmt::Display for PerfReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "ikari performance report")?;
+        writeln!(
+            f,
+            "Nodes: {} ({} visible), point lights: {}",
+            self.node_count, self.visual_node_count, self.point_light_count
+        )?;
+        if let Some(frame_time) = self.frame_time {
+            writeln!(
+                f,
+                "Last frame time: {:.2}ms",
+                frame_time.as_secs_f64() * 1000.0
+            )?;
+        }
+        if !self.measured_footprints {
+            writeln!(
+                f,
+                "No camera was set, texture and mesh density checks were skipped"
+            )?;
+        }
+        if self.suggestions.is_empty() {
+            writeln!(f, "No suggestions")?;
+        }
+        for suggestion in &self.suggestions {
+            writeln!(f, "- [{}] {}", suggestion.kind, suggestion.message)?;
+        }
+        Ok(())
+    }
+}
+
+/// distance at which the point light's contribution drops below the cutoff,
+/// following the attenuation used in textured_mesh.wgsl
+pub fn point_light_influence_radius(light: &PointLight, cutoff: f32) -> f32 {
+    let brightness = light.intensity * light.color.max_element();
+    if brightness <= cutoff {
+        return 0.0;
+    }
+    // solves brightness / (1.0 + 0.007 * d + 0.0002 * d * d) = cutoff for d
+    let (a, b, c) = (0.0002, 0.007, 1.0 - brightness / cutoff);
+    (-b + (b * b - 4.0 * a * c).sqrt()) / (2.0 * a)
+}
+
+/// Looks through the scene for content that's likely costing more than it needs to, as seen from
+/// the current camera. render_height is the height of the render target in pixels, i.e. the surface
+/// height multiplied by the render scale. The suggestions are only hints, e.g. a texture can be
+/// oversized from where the camera is now but not when the player walks up to it.
+#[profiling::function]
+pub fn analyze_scene(
+    scene: &Scene,
+    renderer_data: &RendererData,
+    render_height: u32,
+    frame_time: Option<Duration>,
+    config: &PerfAdvisorConfig,
+) -> PerfReport {
+    let mut report = PerfReport {
+        node_count: scene.node_count(),
+        visual_node_count: scene.nodes().filter(|node| node.visual.is_some()).count(),
+        point_light_count: scene.point_lights.len(),
+        frame_time,
+        measured_footprints: false,
+        suggestions: vec![],
+    };
+
+    let camera_position = renderer_data
+        .camera_node_id
+        .filter(|camera_node_id| scene.get_node(*camera_node_id).is_some())
+        .map(|camera_node_id| {
+            scene
+                .get_global_transform_for_node(camera_node_id)
+                .position()
+        });
+    if let Some(camera_position) = camera_position {
+        report.measured_footprints = true;
+        check_footprints(
+            scene,
+            renderer_data,
+            camera_position,
+            render_height,
+            config,
+            &mut report.suggestions,
+        );
+    }
+
+    check_batchable_materials(scene, config, &mut report.suggestions);
+    check_overlapping_lights(scene, config, &mut report.suggestions);
+
+    if let Some(frame_time) = frame_time.filter(|frame_time| *frame_time > config.target_frame_time)
+    {
+        let mut expensive_settings = vec![];
+        if renderer_data.render_scale > 1.0 {
+            expensive_settings.push(format!("render scale is {}", renderer_data.render_scale));
+        }
+        if renderer_data.enable_shadows && !scene.point_lights.is_empty() {
+            expensive_settings.push(format!(
+                "shadows are enabled with {} point lights",
+                scene.point_lights.len()
+            ));
+        }
+        if renderer_data.enable_shadows && renderer_data.enable_soft_shadows {
+            expensive_settings.push(format!(
+                "soft shadows use a {0}x{0} grid",
+                renderer_data.soft_shadow_grid_dims
+            ));
+        }
+        report.suggestions.push(PerfSuggestion {
+            kind: PerfSuggestionKind::SlowFrame,
+            message: format!(
+                "The last frame took {:.2}ms, above the {:.2}ms target{}",
+                frame_time.as_secs_f64() * 1000.0,
+                config.target_frame_time.as_secs_f64() * 1000.0,
+                if expensive_settings.is_empty() {
+                    String::new()
+                } else {
+                    format!(". Costly settings: {}", expensive_settings.join(", "))
+                }
+            ),
+        });
+    }
+
+    report
+}
+
+/// diameter in pixels of the sphere's projection, approximated from its distance to the camera
+fn sphere_footprint_pixels(
+    center: Vec3,
+    radius: f32,
+    camera_position: Vec3,
+    render_height: u32,
+    lens: &CameraLens,
+) -> f32 {
+    let distance = center.distance(camera_position).max(radius).max(0.001);
+    // the footprints are only estimates so the usual aspect ratio is good enough
+    let view_height = lens.view_height_at_distance(distance, lens.aspect_ratio(16.0 / 9.0));
+    (2.0 * radius / view_height).min(1.0) * render_height as f32
+}
+
+fn check_footprints(
+    scene: &Scene,
+    renderer_data: &RendererData,
+    camera_position: Vec3,
+    render_height: u32,
+    config: &PerfAdvisorConfig,
+    suggestions: &mut Vec<PerfSuggestion>,
+) {
+    // largest footprint of any node that uses the mesh or texture, in pixels
+    let mut mesh_footprints: HashMap<usize, f32> = HashMap::new();
+    let mut texture_footprints: HashMap<usize, f32> = HashMap::new();
+
+    for node in scene.nodes() {
+        let Some(visual) = node.visual.as_ref() else {
+            continue;
+        };
+        let Some(bounding_sphere) = scene.get_node_bounding_sphere(node.id(), renderer_data) else {
+            continue;
+        };
+        let footprint = sphere_footprint_pixels(
+            bounding_sphere.center,
+            bounding_sphere.radius,
+            camera_position,
+            render_height,
+            &renderer_data.camera_lens,
+        );
+
+        let mesh_footprint = mesh_footprints.entry(visual.mesh.index()).or_default();
+        *mesh_footprint = mesh_footprint.max(footprint);
+
+        if let Material::Pbr {
+            binded_material, ..
+        } = visual.material
+        {
+            let texture_indices = renderer_data
+                .binded_pbr_materials
+                .get(binded_material.index())
+                .and_then(|material| material.texture_indices.as_ref());
+            if let Some(texture_indices) = texture_indices {
+                for texture_index in [
+                    texture_indices.base_color,
+                    texture_indices.normal,
+                    texture_indices.metallic_roughness,
+                    texture_indices.emissive,
+                    texture_indices.ambient_occlusion,
+                    texture_indices.lightmap,
+                ]
+                .into_iter()
+                .flatten()
+                {
+                    let texture_footprint = texture_footprints.entry(texture_index).or_default();
+                    *texture_footprint = texture_footprint.max(footprint);
+                }
+            }
+        }
+    }
+
+    let mut texture_footprints: Vec<_> = texture_footprints.into_iter().collect();
+    texture_footprints.sort_by_key(|(texture_index, _)| *texture_index);
+    for (texture_index, footprint) in texture_footprints {
+        let Some(texture) = renderer_data.textures.get(texture_index) else {
+            continue;
+        };
+        let texture_size = texture.size.width.max(texture.size.height);
+        if texture_size < config.min_reported_texture_size {
+            continue;
+        }
+        if texture_size as f32 > config.max_texels_per_pixel * footprint.max(1.0) {
+            suggestions.push(PerfSuggestion {
+                kind: PerfSuggestionKind::OversizedTexture,
+                message: format!(
+                    "Texture {texture_index} is {}x{} but covers at most {:.0} pixels on screen, \
+                     consider a smaller version or a higher texture resolution bias",
+                    texture.size.width, texture.size.height, footprint
+                ),
+            });
+        }
+    }
+
+    let mut mesh_footprints: Vec<_> = mesh_footprints.into_iter().collect();
+    mesh_footprints.sort_by_key(|(mesh_index, _)| *mesh_index);
+    for (mesh_index, footprint) in mesh_footprints {
+        let Some(mesh) = renderer_data.binded_meshes.get(mesh_index) else {
+            continue;
+        };
+        let triangle_count = mesh.index_buffer.buffer.length() / 3;
+        if triangle_count < config.min_reported_triangle_count {
+            continue;
+        }
+        let footprint_area = std::f32::consts::PI * (footprint / 2.0).powi(2);
+        let triangles_per_pixel = triangle_count as f32 / footprint_area.max(1.0);
+        if triangles_per_pixel > config.max_triangles_per_pixel {
+            suggestions.push(PerfSuggestion {
+                kind: PerfSuggestionKind::DenseMesh,
+                message: format!(
+                    "Mesh {mesh_index} has {triangle_count} triangles for about {:.0} pixels \
+                     on screen ({triangles_per_pixel:.1} per pixel), consider a lower detail version",
+                    footprint_area
+                ),
+            });
+        }
+    }
+}
+
+/// the renderer instances the nodes that share both a mesh and a material, so many
+/// different meshes with the same material each cost a draw call
+fn check_batchable_materials(
+    scene: &Scene,
+    config: &PerfAdvisorConfig,
+    suggestions: &mut Vec<PerfSuggestion>,
+) {
+    let mut instance_counts: HashMap<(usize, usize), usize> = HashMap::new();
+    for node in scene.nodes() {
+        // skinned meshes can't be merged together
+        if node.skin_index.is_some() {
+            continue;
+        }
+        if let Some(GameNodeVisual {
+            material: Material::Pbr {
+                binded_material, ..
+            },
+            mesh,
+            ..
+        }) = node.visual
+        {
+            *instance_counts
+                .entry((binded_material.index(), mesh.index()))
+                .or_default() += 1;
+        }
+    }
+
+    let mut single_instance_mesh_counts: HashMap<usize, usize> = HashMap::new();
+    for ((material_index, _), instance_count) in instance_counts {
+        if instance_count == 1 {
+            *single_instance_mesh_counts
+                .entry(material_index)
+                .or_default() += 1;
+        }
+    }
+
+    let mut single_instance_mesh_counts: Vec<_> = single_instance_mesh_counts.into_iter().collect();
+    single_instance_mesh_counts.sort_by_key(|(material_index, _)| *material_index);
+    for (material_index, mesh_count) in single_instance_mesh_counts {
+        if mesh_count >= config.min_batchable_mesh_count {
+            suggestions.push(PerfSuggestion {
+                kind: PerfSuggestionKind::BatchableMaterial,
+                message: format!(
+                    "Material {material_index} is used by {mesh_count} different meshes that are \
+                     each drawn once, merging the static ones into a single mesh would save {} draw calls",
+                    mesh_count - 1
+                ),
+            });
+        }
+    }
+}
+
+fn check_overlapping_lights(
+    scene: &Scene,
+    config: &PerfAdvisorConfig,
+    suggestions: &mut Vec<PerfSuggestion>,
+) {
+    let lights: Vec<_> = scene
+        .point_lights
+        .iter()
+        .filter(|light| scene.get_node(light.node_id).is_some())
+        .map(|light| {
+            (
+                light,
+                scene
+                    .get_global_transform_for_node(light.node_id)
+                    .position(),
+                point_light_influence_radius(light, config.light_influence_cutoff),
+            )
+        })
+        .collect();
+
+    for (light_index, (light, position, radius)) in lights.iter().enumerate() {
+        let mut overlapping_count = 0;
+        for (other_index, (other_light, other_position, other_radius)) in lights.iter().enumerate()
+        {
+            if other_index == light_index {
+                continue;
+            }
+            let distance = position.distance(*other_position);
+            if distance <= *other_radius {
+                overlapping_count += 1;
+            }
+            // only report each pair once
+            if other_index > light_index && distance < 0.1 * radius.min(*other_radius) {
+                suggestions.push(PerfSuggestion {
+                    kind: PerfSuggestionKind::OverlappingLights,
+                    message: format!(
+                        "Point lights of nodes {:?} and {:?} are {distance:.2}m apart, \
+                         they could be merged into a single brighter light",
+                        light.node_id, other_light.node_id
+                    ),
+                });
+            }
+        }
+        if overlapping_count >= config.max_overlapping_lights {
+            suggestions.push(PerfSuggestion {
+                kind: PerfSuggestionKind::OverlappingLights,
+                message: format!(
+                    "The point light of node {:?} is within reach of {overlapping_count} other \
+                     point lights, consider lowering their intensity or removing some of them",
+                    light.node_id
+                ),
+            });
+        }
+    }
+}
@@ -1,11 +1,159 @@
+use crate::joints::*;
 use crate::scene::*;
+use crate::simulation::*;
 
-use std::collections::HashMap;
+use glam::f32::{Quat, Vec3};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
 
 use rapier3d_f64::prelude::*;
 
 pub use rapier3d_f64;
 
+/// Bitfield of collision layers. A collider belongs to one or more layers and
+/// only collides with colliders whose layers are in its mask, and vice-versa
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CollisionLayers(pub u32);
+
+impl CollisionLayers {
+    pub const NONE: Self = Self(0);
+    pub const ALL: Self = Self(u32::MAX);
+
+    pub const DEFAULT: Self = Self(1 << 0);
+    pub const STATIC: Self = Self(1 << 1);
+    pub const DYNAMIC: Self = Self(1 << 2);
+    pub const CHARACTER: Self = Self(1 << 3);
+    pub const TRIGGER: Self = Self(1 << 4);
+    pub const PROJECTILE: Self = Self(1 << 5);
+    /// layers from here on up are free for games to use
+    pub const FIRST_USER_LAYER: u32 = 16;
+    pub const USER_LAYER_COUNT: u32 = u32::BITS - Self::FIRST_USER_LAYER;
+
+    /// Panics if index is USER_LAYER_COUNT or more
+    pub const fn user_layer(index: u32) -> Self {
+        assert!(
+            index < Self::USER_LAYER_COUNT,
+            "There are only 16 user collision layers"
+        );
+        Self(1 << (Self::FIRST_USER_LAYER + index))
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn interaction_groups(memberships: Self, mask: Self) -> InteractionGroups {
+        InteractionGroups::new(
+            Group::from_bits_truncate(memberships.0),
+            Group::from_bits_truncate(mask.0),
+        )
+    }
+}
+
+impl std::ops::BitOr for CollisionLayers {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        self.union(rhs)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PhysicsEventKind {
+    Began,
+    /// sent every step for as long as the contact lasts, after the step it began in
+    Persisted,
+    Ended,
+}
+
+/// A contact or trigger event, from the point of view of node_id.
+/// If both colliders are attached to nodes, there is one event for each of them.
+#[derive(Debug, Copy, Clone)]
+pub struct PhysicsEvent {
+    pub kind: PhysicsEventKind,
+    /// true if one of the colliders is a sensor, in which case there is no physical contact
+    pub is_trigger: bool,
+    pub node_id: GameNodeId,
+    pub collider_handle: ColliderHandle,
+    pub other_node_id: Option<GameNodeId>,
+    pub other_collider_handle: ColliderHandle,
+}
+
+/// Links a rigid body to a GameNode so the node follows the simulation.
+/// When the physics is stepped at a fixed rate with PhysicsState::update, the node's
+/// transform is interpolated between the last two steps.
+#[derive(Debug, Copy, Clone)]
+pub struct RigidBodyComponent {
+    pub node_id: GameNodeId,
+    pub rigid_body_handle: RigidBodyHandle,
+    pub interpolate: bool,
+    previous_isometry: Isometry<Real>,
+    current_isometry: Isometry<Real>,
+}
+
+impl RigidBodyComponent {
+    /// the isometry that should be displayed, alpha being the fraction of a step
+    /// that has elapsed since the last one
+    pub fn interpolated_isometry(&self, alpha: f64) -> Isometry<Real> {
+        if self.interpolate {
+            self.previous_isometry
+                .lerp_slerp(&self.current_isometry, alpha)
+        } else {
+            self.current_isometry
+        }
+    }
+}
+
+/// Copy of the simulation state, see PhysicsState::snapshot
+#[derive(Clone)]
+pub struct PhysicsSnapshot {
+    island_manager: IslandManager,
+    broad_phase: BroadPhase,
+    narrow_phase: NarrowPhase,
+    rigid_body_set: RigidBodySet,
+    collider_set: ColliderSet,
+    impulse_joint_set: ImpulseJointSet,
+    multibody_joint_set: MultibodyJointSet,
+    ccd_solver: CCDSolver,
+    rigid_body_components: HashMap<GameNodeId, RigidBodyComponent>,
+    collider_nodes: HashMap<ColliderHandle, GameNodeId>,
+    active_collision_pairs: HashMap<(ColliderHandle, ColliderHandle), bool>,
+}
+
+/// rapier only reports when contacts start and stop, so the events are buffered
+/// here during the step and turned into PhysicsEvents afterwards
+#[derive(Default)]
+struct CollisionEventCollector {
+    events: Mutex<Vec<CollisionEvent>>,
+}
+
+impl EventHandler for CollisionEventCollector {
+    fn handle_collision_event(
+        &self,
+        _bodies: &RigidBodySet,
+        _colliders: &ColliderSet,
+        event: CollisionEvent,
+        _contact_pair: Option<&ContactPair>,
+    ) {
+        self.events.lock().unwrap().push(event);
+    }
+
+    fn handle_contact_force_event(
+        &self,
+        _dt: f64,
+        _bodies: &RigidBodySet,
+        _colliders: &ColliderSet,
+        _contact_pair: &ContactPair,
+        _total_force_magnitude: f64,
+    ) {
+    }
+}
+
 pub struct PhysicsState {
     pub gravity: nalgebra::Vector3<f64>,
     pub integration_parameters: IntegrationParameters,
@@ -22,6 +170,17 @@ pub struct PhysicsState {
     pub query_pipeline: QueryPipeline,
 
     pub static_box_set: HashMap<GameNodeId, Vec<ColliderHandle>>,
+
+    /// used when stepping with update()
+    pub timestep: FixedTimestep,
+    interpolation_alpha: f64,
+    rigid_body_components: HashMap<GameNodeId, RigidBodyComponent>,
+
+    collider_nodes: HashMap<ColliderHandle, GameNodeId>,
+    /// value is whether the pair is a trigger (sensor) intersection
+    active_collision_pairs: HashMap<(ColliderHandle, ColliderHandle), bool>,
+    event_collector: CollisionEventCollector,
+    events: Vec<PhysicsEvent>,
 }
 
 impl PhysicsState {
@@ -42,11 +201,93 @@ impl PhysicsState {
             query_pipeline: QueryPipeline::new(),
 
             static_box_set: HashMap::new(),
+
+            timestep: FixedTimestep::default(),
+            interpolation_alpha: 1.0,
+            rigid_body_components: HashMap::new(),
+
+            collider_nodes: HashMap::new(),
+            active_collision_pairs: HashMap::new(),
+            event_collector: Default::default(),
+            events: vec![],
         }
     }
 
+    /// Steps the simulation as many times as needed to catch up with the elapsed time,
+    /// using self.timestep. Returns the number of steps taken.
+    /// Not needed if the physics is stepped from gameloop's fixed update
+    #[profiling::function]
+    pub fn update(&mut self, delta_time_seconds: f64) -> u32 {
+        self.integration_parameters.dt = self.timestep.timestep_seconds;
+
+        let step_count = self.timestep.advance(delta_time_seconds);
+        for _ in 0..step_count {
+            self.step();
+            self.timestep.increment_tick();
+        }
+
+        self.interpolation_alpha = self.timestep.interpolation_alpha();
+
+        step_count
+    }
+
+    /// fraction of a fixed step that has elapsed since the last one. Is always 1 if
+    /// the simulation is only stepped manually with step(), unless it's set by the caller
+    pub fn interpolation_alpha(&self) -> f64 {
+        self.interpolation_alpha
+    }
+
+    /// for when step() is called at a fixed rate by the caller, gameloop sets it
+    /// from EngineState::simulation_timestep before each frame's update
+    pub fn set_interpolation_alpha(&mut self, interpolation_alpha: f64) {
+        self.interpolation_alpha = interpolation_alpha;
+    }
+
+    pub fn snapshot(&self) -> PhysicsSnapshot {
+        PhysicsSnapshot {
+            island_manager: self.island_manager.clone(),
+            broad_phase: self.broad_phase.clone(),
+            narrow_phase: self.narrow_phase.clone(),
+            rigid_body_set: self.rigid_body_set.clone(),
+            collider_set: self.collider_set.clone(),
+            impulse_joint_set: self.impulse_joint_set.clone(),
+            multibody_joint_set: self.multibody_joint_set.clone(),
+            ccd_solver: self.ccd_solver.clone(),
+            rigid_body_components: self.rigid_body_components.clone(),
+            collider_nodes: self.collider_nodes.clone(),
+            active_collision_pairs: self.active_collision_pairs.clone(),
+        }
+    }
+
+    /// Bodies and colliders created after the snapshot was taken are removed, so handles
+    /// to them that are kept by the game become invalid
+    #[profiling::function]
+    pub fn restore(&mut self, snapshot: &PhysicsSnapshot) {
+        self.island_manager = snapshot.island_manager.clone();
+        self.broad_phase = snapshot.broad_phase.clone();
+        self.narrow_phase = snapshot.narrow_phase.clone();
+        self.rigid_body_set = snapshot.rigid_body_set.clone();
+        self.collider_set = snapshot.collider_set.clone();
+        self.impulse_joint_set = snapshot.impulse_joint_set.clone();
+        self.multibody_joint_set = snapshot.multibody_joint_set.clone();
+        self.ccd_solver = snapshot.ccd_solver.clone();
+        self.rigid_body_components = snapshot.rigid_body_components.clone();
+        self.active_collision_pairs = snapshot.active_collision_pairs.clone();
+
+        self.collider_nodes = snapshot.collider_nodes.clone();
+        self.event_collector.events.lock().unwrap().clear();
+        self.events.clear();
+
+        self.query_pipeline
+            .update(&self.rigid_body_set, &self.collider_set);
+    }
+
     #[profiling::function]
     pub fn step(&mut self) {
+        for component in self.rigid_body_components.values_mut() {
+            component.previous_isometry = component.current_isometry;
+        }
+
         self.physics_pipeline.step(
             &self.gravity,
             &self.integration_parameters,
@@ -60,14 +301,326 @@ impl PhysicsState {
             &mut self.ccd_solver,
             None,
             &(),
-            &(),
+            &self.event_collector,
         );
 
         self.query_pipeline
             .update(&self.rigid_body_set, &self.collider_set);
+
+        for component in self.rigid_body_components.values_mut() {
+            if let Some(rigid_body) = self.rigid_body_set.get(component.rigid_body_handle) {
+                component.current_isometry = *rigid_body.position();
+            }
+        }
+
+        self.update_events();
+    }
+
+    /// The node's transform will follow the rigid body from now on, see sync_rigid_body_components.
+    /// The body's colliders are also attached to the node so their events are reported for it
+    pub fn add_rigid_body_component(
+        &mut self,
+        node_id: GameNodeId,
+        rigid_body_handle: RigidBodyHandle,
+    ) {
+        let Some(rigid_body) = self.rigid_body_set.get(rigid_body_handle) else {
+            log::error!("Tried to add a component for a rigid body that doesn't exist");
+            return;
+        };
+        let isometry = *rigid_body.position();
+        let collider_handles = rigid_body.colliders().to_vec();
+
+        self.rigid_body_components.insert(
+            node_id,
+            RigidBodyComponent {
+                node_id,
+                rigid_body_handle,
+                interpolate: true,
+                previous_isometry: isometry,
+                current_isometry: isometry,
+            },
+        );
+
+        for collider_handle in collider_handles {
+            self.attach_collider_to_node(collider_handle, node_id);
+        }
+    }
+
+    /// the rigid body itself is left in the simulation
+    pub fn remove_rigid_body_component(
+        &mut self,
+        node_id: GameNodeId,
+    ) -> Option<RigidBodyComponent> {
+        self.rigid_body_components.remove(&node_id)
+    }
+
+    pub fn get_rigid_body_component(&self, node_id: GameNodeId) -> Option<&RigidBodyComponent> {
+        self.rigid_body_components.get(&node_id)
+    }
+
+    pub fn get_rigid_body_component_mut(
+        &mut self,
+        node_id: GameNodeId,
+    ) -> Option<&mut RigidBodyComponent> {
+        self.rigid_body_components.get_mut(&node_id)
+    }
+
+    pub fn rigid_body_components(&self) -> impl Iterator<Item = &RigidBodyComponent> {
+        self.rigid_body_components.values()
+    }
+
+    /// Moves the body without interpolating from its previous position,
+    /// which would otherwise make the node visibly slide to its new location
+    pub fn teleport_rigid_body_component(&mut self, node_id: GameNodeId, isometry: Isometry<Real>) {
+        let Some(component) = self.rigid_body_components.get_mut(&node_id) else {
+            return;
+        };
+        if let Some(rigid_body) = self.rigid_body_set.get_mut(component.rigid_body_handle) {
+            rigid_body.set_position(isometry, true);
+        }
+        component.previous_isometry = isometry;
+        component.current_isometry = isometry;
+    }
+
+    /// Copies the simulation results into the transforms of the nodes that have a RigidBodyComponent.
+    /// Should be called once per frame after stepping. Components whose node was removed are dropped
+    #[profiling::function]
+    pub fn sync_rigid_body_components(&mut self, scene: &mut Scene) {
+        let alpha = self.interpolation_alpha;
+
+        self.rigid_body_components
+            .retain(|node_id, _| scene.get_node(*node_id).is_some());
+
+        for component in self.rigid_body_components.values() {
+            let isometry = component.interpolated_isometry(alpha);
+            let mut position = Vec3::new(
+                isometry.translation.x as f32,
+                isometry.translation.y as f32,
+                isometry.translation.z as f32,
+            );
+            let mut rotation = Quat::from_xyzw(
+                isometry.rotation.i as f32,
+                isometry.rotation.j as f32,
+                isometry.rotation.k as f32,
+                isometry.rotation.w as f32,
+            );
+
+            // the simulation is in world space but node transforms are relative to their parent
+            let parent_id = scene
+                .get_node(component.node_id)
+                .and_then(|node| node.parent_id);
+            if let Some(parent_id) = parent_id {
+                let parent_transform = scene.get_global_transform_for_node(parent_id).decompose();
+                let parent_rotation_inverse = parent_transform.rotation.inverse();
+                position = (parent_rotation_inverse * (position - parent_transform.position))
+                    / parent_transform.scale;
+                rotation = parent_rotation_inverse * rotation;
+            }
+
+            if let Some(node) = scene.get_node_mut(component.node_id) {
+                node.transform.set_position(position);
+                node.transform.set_rotation(rotation);
+            }
+        }
+    }
+
+    fn update_events(&mut self) {
+        self.events.clear();
+
+        let collision_events: Vec<_> = self
+            .event_collector
+            .events
+            .lock()
+            .unwrap()
+            .drain(..)
+            .collect();
+        let mut began_pairs = HashSet::new();
+        for collision_event in collision_events {
+            let (collider_a, collider_b, flags, kind) = match collision_event {
+                CollisionEvent::Started(a, b, flags) => (a, b, flags, PhysicsEventKind::Began),
+                CollisionEvent::Stopped(a, b, flags) => (a, b, flags, PhysicsEventKind::Ended),
+            };
+            let is_trigger = flags.contains(CollisionEventFlags::SENSOR);
+
+            if kind == PhysicsEventKind::Began {
+                self.active_collision_pairs
+                    .insert((collider_a, collider_b), is_trigger);
+                began_pairs.insert((collider_a, collider_b));
+            } else {
+                self.active_collision_pairs
+                    .remove(&(collider_a, collider_b));
+                self.active_collision_pairs
+                    .remove(&(collider_b, collider_a));
+            }
+
+            self.events.extend(Self::make_events(
+                &self.collider_nodes,
+                kind,
+                is_trigger,
+                collider_a,
+                collider_b,
+            ));
+        }
+
+        for (&(collider_a, collider_b), &is_trigger) in &self.active_collision_pairs {
+            if began_pairs.contains(&(collider_a, collider_b)) {
+                continue;
+            }
+            self.events.extend(Self::make_events(
+                &self.collider_nodes,
+                PhysicsEventKind::Persisted,
+                is_trigger,
+                collider_a,
+                collider_b,
+            ));
+        }
+
+        // the node association can be forgotten once rapier reported the removal
+        self.collider_nodes
+            .retain(|collider_handle, _| self.collider_set.contains(*collider_handle));
+    }
+
+    fn make_events(
+        collider_nodes: &HashMap<ColliderHandle, GameNodeId>,
+        kind: PhysicsEventKind,
+        is_trigger: bool,
+        collider_a: ColliderHandle,
+        collider_b: ColliderHandle,
+    ) -> impl Iterator<Item = PhysicsEvent> {
+        let node_a = collider_nodes.get(&collider_a).copied();
+        let node_b = collider_nodes.get(&collider_b).copied();
+        [
+            (node_a, collider_a, node_b, collider_b),
+            (node_b, collider_b, node_a, collider_a),
+        ]
+        .into_iter()
+        .filter_map(
+            move |(node_id, collider_handle, other_node_id, other_collider_handle)| {
+                Some(PhysicsEvent {
+                    kind,
+                    is_trigger,
+                    node_id: node_id?,
+                    collider_handle,
+                    other_node_id,
+                    other_collider_handle,
+                })
+            },
+        )
+    }
+
+    /// all the contact and trigger events from the last step
+    pub fn events(&self) -> &[PhysicsEvent] {
+        &self.events
+    }
+
+    pub fn events_for_node(&self, node_id: GameNodeId) -> impl Iterator<Item = &PhysicsEvent> {
+        self.events
+            .iter()
+            .filter(move |event| event.node_id == node_id)
+    }
+
+    /// Events for the collider will be reported with this node id. This also turns on
+    /// collision events for the collider, which rapier doesn't report by default
+    pub fn attach_collider_to_node(
+        &mut self,
+        collider_handle: ColliderHandle,
+        node_id: GameNodeId,
+    ) {
+        if let Some(collider) = self.collider_set.get_mut(collider_handle) {
+            collider.set_active_events(collider.active_events() | ActiveEvents::COLLISION_EVENTS);
+            self.collider_nodes.insert(collider_handle, node_id);
+        }
+    }
+
+    pub fn get_collider_node(&self, collider_handle: ColliderHandle) -> Option<GameNodeId> {
+        self.collider_nodes.get(&collider_handle).copied()
+    }
+
+    /// Like get_collider_node but also finds the node of colliders that belong to a
+    /// RigidBodyComponent or to the static_box_set
+    pub fn find_collider_node(&self, collider_handle: ColliderHandle) -> Option<GameNodeId> {
+        if let Some(node_id) = self.get_collider_node(collider_handle) {
+            return Some(node_id);
+        }
+        let rigid_body_handle = self.collider_set.get(collider_handle)?.parent();
+        if let Some(component) = rigid_body_handle.and_then(|rigid_body_handle| {
+            self.rigid_body_components
+                .values()
+                .find(|component| component.rigid_body_handle == rigid_body_handle)
+        }) {
+            return Some(component.node_id);
+        }
+        self.static_box_set
+            .iter()
+            .find(|(_, collider_handles)| collider_handles.contains(&collider_handle))
+            .map(|(node_id, _)| *node_id)
+    }
+
+    pub fn set_collision_layers(
+        &mut self,
+        collider_handle: ColliderHandle,
+        memberships: CollisionLayers,
+        mask: CollisionLayers,
+    ) {
+        if let Some(collider) = self.collider_set.get_mut(collider_handle) {
+            let groups = CollisionLayers::interaction_groups(memberships, mask);
+            collider.set_collision_groups(groups);
+            collider.set_solver_groups(groups);
+        }
+    }
+
+    /// See JointBuilder. Also makes sure the solver does enough iterations for the joints
+    /// to hold, see MIN_JOINT_VELOCITY_ITERATIONS
+    pub fn add_joint(
+        &mut self,
+        rigid_body_handle1: RigidBodyHandle,
+        rigid_body_handle2: RigidBodyHandle,
+        joint: &JointBuilder,
+    ) -> Option<ImpulseJointHandle> {
+        let (Some(rigid_body1), Some(rigid_body2)) = (
+            self.rigid_body_set.get(rigid_body_handle1),
+            self.rigid_body_set.get(rigid_body_handle2),
+        ) else {
+            log::error!("Tried to add a joint to a rigid body that doesn't exist");
+            return None;
+        };
+        let joint = joint.build(rigid_body1.position(), rigid_body2.position());
+
+        self.integration_parameters.max_velocity_iterations = self
+            .integration_parameters
+            .max_velocity_iterations
+            .max(MIN_JOINT_VELOCITY_ITERATIONS);
+        Some(
+            self.impulse_joint_set
+                .insert(rigid_body_handle1, rigid_body_handle2, joint, true),
+        )
+    }
+
+    pub fn remove_joint(&mut self, joint_handle: ImpulseJointHandle) {
+        self.impulse_joint_set.remove(joint_handle, true);
+    }
+
+    /// For hinge and prismatic joints, None turns the motor off
+    pub fn set_joint_motor(
+        &mut self,
+        joint_handle: ImpulseJointHandle,
+        motor: Option<JointMotorTarget>,
+        max_motor_force: Option<f32>,
+    ) {
+        let Some(joint) = self.impulse_joint_set.get_mut(joint_handle) else {
+            return;
+        };
+        set_motor(&mut joint.data, motor, max_motor_force);
+        for rigid_body_handle in [joint.body1, joint.body2] {
+            if let Some(rigid_body) = self.rigid_body_set.get_mut(rigid_body_handle) {
+                rigid_body.wake_up(true);
+            }
+        }
     }
 
     pub fn remove_rigid_body(&mut self, rigid_body_handle: RigidBodyHandle) {
+        self.rigid_body_components
+            .retain(|_, component| component.rigid_body_handle != rigid_body_handle);
         self.rigid_body_set.remove(
             rigid_body_handle,
             &mut self.island_manager,
@@ -78,6 +631,38 @@ impl PhysicsState {
         );
     }
 
+    /// Removes the colliders that were added to the static_box_set for the node
+    pub fn remove_static_boxes(&mut self, node_id: GameNodeId) {
+        for collider_handle in self.static_box_set.remove(&node_id).unwrap_or_default() {
+            self.collider_set.remove(
+                collider_handle,
+                &mut self.island_manager,
+                &mut self.rigid_body_set,
+                true,
+            );
+        }
+    }
+
+    /// Moves all the bodies and the colliders that aren't attached to one by the offset,
+    /// see Scene::shift_origin
+    pub fn shift_origin(&mut self, offset: Vec3) {
+        let offset = vector![offset.x as f64, offset.y as f64, offset.z as f64];
+        for (_, rigid_body) in self.rigid_body_set.iter_mut() {
+            let mut position = *rigid_body.position();
+            position.translation.vector += offset;
+            rigid_body.set_position(position, false);
+        }
+        for (_, collider) in self.collider_set.iter_mut() {
+            if collider.parent().is_none() {
+                collider.set_translation(collider.translation() + offset);
+            }
+        }
+        for component in self.rigid_body_components.values_mut() {
+            component.previous_isometry.translation.vector += offset;
+            component.current_isometry.translation.vector += offset;
+        }
+    }
+
     pub fn set_gravity_is_enabled(&mut self, is_enabled: bool) {
         self.gravity = vector![0.0, if is_enabled { -9.8 } else { 0.0 }, 0.0];
     }
@@ -88,3 +673,15 @@ impl Default for PhysicsState {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_layers_stay_within_the_bits() {
+        assert_eq!(CollisionLayers::user_layer(0).0, 1 << 16);
+        assert_eq!(CollisionLayers::user_layer(15).0, 1 << 31);
+        assert!(std::panic::catch_unwind(|| CollisionLayers::user_layer(16)).is_err());
+    }
+}
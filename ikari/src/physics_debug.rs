@@ -0,0 +1,197 @@
+use crate::debug_draw::DebugDraw;
+use crate::physics::PhysicsState;
+
+use glam::f32::{Mat4, Quat, Vec3};
+use rapier3d_f64::prelude::*;
+
+pub const AWAKE_BODY_COLOR: Vec3 = Vec3::new(0.2, 1.0, 0.2);
+pub const SLEEPING_BODY_COLOR: Vec3 = Vec3::new(0.3, 0.4, 1.0);
+pub const KINEMATIC_BODY_COLOR: Vec3 = Vec3::new(1.0, 1.0, 0.2);
+/// also the colliders without a rigid body
+pub const FIXED_BODY_COLOR: Vec3 = Vec3::new(0.6, 0.6, 0.6);
+pub const SENSOR_COLOR: Vec3 = Vec3::new(0.8, 0.3, 1.0);
+pub const AABB_COLOR: Vec3 = Vec3::new(1.0, 0.5, 0.0);
+pub const CONTACT_COLOR: Vec3 = Vec3::new(1.0, 0.1, 0.1);
+pub const VELOCITY_COLOR: Vec3 = Vec3::new(0.0, 1.0, 1.0);
+
+const CONTACT_POINT_SIZE: f32 = 0.1;
+const CONTACT_NORMAL_LENGTH: f32 = 0.3;
+
+/// Draws the physics world through DebugDraw: the collider shapes colored by the state of their
+/// body, their AABBs, the contact points with their normals and the velocities of the moving
+/// bodies. Shapes other than balls, cuboids and capsules are drawn as their local bounding box
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PhysicsDebugDraw {
+    pub colliders: bool,
+    pub aabbs: bool,
+    pub contacts: bool,
+    pub velocities: bool,
+    /// the velocity arrows show how far the bodies go in that many seconds
+    pub velocity_scale: f32,
+}
+
+impl Default for PhysicsDebugDraw {
+    fn default() -> Self {
+        Self {
+            colliders: true,
+            aabbs: false,
+            contacts: true,
+            velocities: true,
+            velocity_scale: 0.25,
+        }
+    }
+}
+
+impl PhysicsDebugDraw {
+    #[profiling::function]
+    pub fn draw(&self, physics_state: &PhysicsState, debug_draw: &mut DebugDraw) {
+        for (_, collider) in physics_state.collider_set.iter() {
+            if self.colliders {
+                let rigid_body = collider
+                    .parent()
+                    .and_then(|handle| physics_state.rigid_body_set.get(handle));
+                let color = collider_color(rigid_body, collider.is_sensor());
+                draw_collider_shape(debug_draw, collider, color);
+            }
+            if self.aabbs {
+                let aabb = collider.compute_aabb();
+                let min = point_to_vec3(&aabb.mins);
+                let max = point_to_vec3(&aabb.maxs);
+                debug_draw.oriented_box(
+                    Mat4::from_translation((min + max) / 2.0),
+                    (max - min) / 2.0,
+                    AABB_COLOR,
+                );
+            }
+        }
+
+        if self.contacts {
+            for contact_pair in physics_state.narrow_phase.contact_pairs() {
+                if !contact_pair.has_any_active_contact {
+                    continue;
+                }
+                for manifold in &contact_pair.manifolds {
+                    let normal = vector_to_vec3(&manifold.data.normal);
+                    for solver_contact in &manifold.data.solver_contacts {
+                        let point = point_to_vec3(&solver_contact.point);
+                        debug_draw.point(point, CONTACT_POINT_SIZE, CONTACT_COLOR);
+                        debug_draw.arrow(
+                            point,
+                            point + CONTACT_NORMAL_LENGTH * normal,
+                            CONTACT_COLOR,
+                        );
+                    }
+                }
+            }
+        }
+
+        if self.velocities {
+            for (_, rigid_body) in physics_state.rigid_body_set.iter() {
+                if !rigid_body.is_dynamic() || rigid_body.is_sleeping() {
+                    continue;
+                }
+                let center = point_to_vec3(rigid_body.center_of_mass());
+                let velocity = vector_to_vec3(rigid_body.linvel());
+                if velocity.length_squared() > 0.0 {
+                    debug_draw.arrow(
+                        center,
+                        center + self.velocity_scale * velocity,
+                        VELOCITY_COLOR,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// The color code of the collider shapes
+pub fn collider_color(rigid_body: Option<&RigidBody>, is_sensor: bool) -> Vec3 {
+    if is_sensor {
+        return SENSOR_COLOR;
+    }
+    match rigid_body {
+        Some(rigid_body) if rigid_body.is_dynamic() => {
+            if rigid_body.is_sleeping() {
+                SLEEPING_BODY_COLOR
+            } else {
+                AWAKE_BODY_COLOR
+            }
+        }
+        Some(rigid_body) if rigid_body.is_kinematic() => KINEMATIC_BODY_COLOR,
+        _ => FIXED_BODY_COLOR,
+    }
+}
+
+fn draw_collider_shape(debug_draw: &mut DebugDraw, collider: &Collider, color: Vec3) {
+    let transform = isometry_to_mat4(collider.position());
+    let shape = collider.shape();
+    if let Some(ball) = shape.as_ball() {
+        debug_draw.sphere(
+            transform.transform_point3(Vec3::ZERO),
+            ball.radius as f32,
+            color,
+        );
+    } else if let Some(cuboid) = shape.as_cuboid() {
+        debug_draw.oriented_box(transform, vector_to_vec3(&cuboid.half_extents), color);
+    } else if let Some(capsule) = shape.as_capsule() {
+        let radius = capsule.radius as f32;
+        let start = transform.transform_point3(point_to_vec3(&capsule.segment.a));
+        let end = transform.transform_point3(point_to_vec3(&capsule.segment.b));
+        debug_draw.sphere(start, radius, color);
+        debug_draw.sphere(end, radius, color);
+        let (side_a, side_b) = (end - start).normalize_or_zero().any_orthonormal_pair();
+        for side in [side_a, -side_a, side_b, -side_b] {
+            debug_draw.line(start + radius * side, end + radius * side, color);
+        }
+    } else {
+        let aabb = shape.compute_local_aabb();
+        let min = point_to_vec3(&aabb.mins);
+        let max = point_to_vec3(&aabb.maxs);
+        debug_draw.oriented_box(
+            transform * Mat4::from_translation((min + max) / 2.0),
+            (max - min) / 2.0,
+            color,
+        );
+    }
+}
+
+fn isometry_to_mat4(isometry: &Isometry<Real>) -> Mat4 {
+    Mat4::from_rotation_translation(
+        Quat::from_xyzw(
+            isometry.rotation.i as f32,
+            isometry.rotation.j as f32,
+            isometry.rotation.k as f32,
+            isometry.rotation.w as f32,
+        ),
+        vector_to_vec3(&isometry.translation.vector),
+    )
+}
+
+fn point_to_vec3(point: &Point<Real>) -> Vec3 {
+    Vec3::new(point.x as f32, point.y as f32, point.z as f32)
+}
+
+fn vector_to_vec3(vector: &Vector<Real>) -> Vec3 {
+    Vec3::new(vector.x as f32, vector.y as f32, vector.z as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collider_colors_follow_the_state_of_the_body() {
+        let awake = RigidBodyBuilder::dynamic().build();
+        let sleeping = RigidBodyBuilder::dynamic().sleeping(true).build();
+        let kinematic = RigidBodyBuilder::kinematic_position_based().build();
+
+        assert_eq!(collider_color(Some(&awake), false), AWAKE_BODY_COLOR);
+        assert_eq!(collider_color(Some(&sleeping), false), SLEEPING_BODY_COLOR);
+        assert_eq!(
+            collider_color(Some(&kinematic), false),
+            KINEMATIC_BODY_COLOR
+        );
+        assert_eq!(collider_color(None, false), FIXED_BODY_COLOR);
+        assert_eq!(collider_color(Some(&awake), true), SENSOR_COLOR);
+    }
+}
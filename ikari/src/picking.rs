@@ -0,0 +1,266 @@
+use crate::camera::Projection;
+use crate::physics::rapier3d_f64::prelude::{
+    point, vector, Collider, ColliderHandle, QueryFilter, Ray as PhysicsRay, Real,
+};
+use crate::physics::PhysicsState;
+use crate::renderer::*;
+use crate::scene::*;
+use crate::transform::Transform;
+
+use glam::f32::{Vec2, Vec3};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    /// normalized
+    pub direction: Vec3,
+}
+
+impl Ray {
+    pub fn point_at(&self, distance: f32) -> Vec3 {
+        self.origin + self.direction * distance
+    }
+
+    /// distance along the ray at which it enters the sphere, or at which it leaves it
+    /// if the ray starts inside, so that the nodes inside of a large one can still be picked
+    pub fn sphere_intersection(&self, center: Vec3, radius: f32) -> Option<f32> {
+        let to_center = center - self.origin;
+        let projection = to_center.dot(self.direction);
+        let distance_squared = to_center.length_squared() - projection * projection;
+        let radius_squared = radius * radius;
+        if distance_squared > radius_squared {
+            return None;
+        }
+        let half_chord = (radius_squared - distance_squared).sqrt();
+        let (enter, exit) = (projection - half_chord, projection + half_chord);
+        if exit < 0.0 {
+            return None;
+        }
+        Some(if enter >= 0.0 { enter } else { exit })
+    }
+
+    /// (distance along the ray, distance along the line, distance between the two) for the
+    /// closest points of the ray and the infinite line, None if they're parallel.
+    /// line_direction must be normalized
+    pub fn closest_points_to_line(
+        &self,
+        line_origin: Vec3,
+        line_direction: Vec3,
+    ) -> Option<(f32, f32, f32)> {
+        let offset = self.origin - line_origin;
+        let b = self.direction.dot(line_direction);
+        let denominator = 1.0 - b * b;
+        if denominator < 1e-6 {
+            return None;
+        }
+        let d = self.direction.dot(offset);
+        let e = line_direction.dot(offset);
+        let ray_distance = (b * e - d) / denominator;
+        let line_distance = (e - b * d) / denominator;
+        let distance = (self.origin + self.direction * ray_distance)
+            .distance(line_origin + line_direction * line_distance);
+        Some((ray_distance, line_distance, distance))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PickHit {
+    pub node_id: GameNodeId,
+    /// along the ray
+    pub distance: f32,
+    pub point: Vec3,
+}
+
+pub(crate) fn camera_transform(scene: &Scene, renderer_data: &RendererData) -> Option<Transform> {
+    renderer_data
+        .camera_node_id
+        .filter(|camera_node_id| scene.get_node(*camera_node_id).is_some())
+        .map(|camera_node_id| scene.get_global_transform_for_node(camera_node_id))
+}
+
+/// The ray going from the camera through the cursor. cursor_position is relative
+/// to the window, from (0, 0) at the top left to (1, 1) at the bottom right
+pub fn cursor_ray(
+    scene: &Scene,
+    renderer_data: &RendererData,
+    cursor_position: Vec2,
+    surface_aspect_ratio: f32,
+) -> Option<Ray> {
+    let camera_transform = camera_transform(scene, renderer_data)?;
+    let lens = &renderer_data.camera_lens;
+    let aspect_ratio = lens.aspect_ratio(surface_aspect_ratio);
+    let ndc = Vec2::new(cursor_position.x * 2.0 - 1.0, 1.0 - cursor_position.y * 2.0);
+    if let Projection::Orthographic { height } = lens.projection {
+        // all the rays are parallel, they start from where the cursor is on the view plane
+        let half_height = height / 2.0;
+        return Some(Ray {
+            origin: camera_transform.transform_point3(Vec3::new(
+                ndc.x * half_height * aspect_ratio,
+                ndc.y * half_height,
+                -lens.near_plane_distance,
+            )),
+            direction: camera_transform
+                .transform_vector3(Vec3::new(0.0, 0.0, -1.0))
+                .normalize(),
+        });
+    }
+    let half_view_height = (lens.fov_y(aspect_ratio) / 2.0).tan();
+    // the camera looks down its -z axis
+    let view_space_direction = Vec3::new(
+        ndc.x * half_view_height * aspect_ratio,
+        ndc.y * half_view_height,
+        -1.0,
+    );
+    Some(Ray {
+        origin: camera_transform.position(),
+        direction: camera_transform
+            .transform_vector3(view_space_direction)
+            .normalize(),
+    })
+}
+
+/// The closest node with a visual whose bounding sphere is hit by the ray. This is cheap and
+/// needs no colliders but is coarse, nodes in front can hide the ones the cursor is really over.
+/// The candidates come from the scene's BVH so the nodes added since the last frame are skipped
+pub fn pick_node(
+    scene: &Scene,
+    renderer_data: &RendererData,
+    ray: Ray,
+    filter: impl Fn(&GameNode) -> bool,
+) -> Option<PickHit> {
+    scene
+        .nodes_along_ray(ray)
+        .into_iter()
+        .filter_map(|node_id| scene.get_node(node_id))
+        .filter(|node| node.visual.is_some() && filter(node))
+        .filter_map(|node| {
+            let bounding_sphere = scene.get_node_bounding_sphere(node.id(), renderer_data)?;
+            let distance =
+                ray.sphere_intersection(bounding_sphere.center, bounding_sphere.radius)?;
+            Some(PickHit {
+                node_id: node.id(),
+                distance,
+                point: ray.point_at(distance),
+            })
+        })
+        .min_by(|a, b| a.distance.total_cmp(&b.distance))
+}
+
+/// The node of the first physics collider hit by the ray, see PhysicsState::find_collider_node.
+/// Colliders that don't belong to a node are skipped
+pub fn pick_collider(
+    physics_state: &PhysicsState,
+    ray: Ray,
+    max_distance: f32,
+    filter: QueryFilter,
+) -> Option<PickHit> {
+    let physics_ray = PhysicsRay::new(
+        point![
+            ray.origin.x as Real,
+            ray.origin.y as Real,
+            ray.origin.z as Real
+        ],
+        vector![
+            ray.direction.x as Real,
+            ray.direction.y as Real,
+            ray.direction.z as Real
+        ],
+    );
+    let mut hit = None;
+    physics_state.query_pipeline.intersections_with_ray(
+        &physics_state.rigid_body_set,
+        &physics_state.collider_set,
+        &physics_ray,
+        max_distance as Real,
+        true,
+        filter,
+        |collider_handle, intersection| {
+            let distance = intersection.toi as f32;
+            let is_closer = hit.map_or(true, |hit: PickHit| distance < hit.distance);
+            if let Some(node_id) = physics_state
+                .find_collider_node(collider_handle)
+                .filter(|_| is_closer)
+            {
+                hit = Some(PickHit {
+                    node_id,
+                    distance,
+                    point: ray.point_at(distance),
+                });
+            }
+            true
+        },
+    );
+    hit
+}
+
+/// The closest of pick_node and pick_collider, so that nodes without colliders can be picked
+/// while the ones that have them are picked precisely
+pub fn pick(
+    scene: &Scene,
+    renderer_data: &RendererData,
+    physics_state: &PhysicsState,
+    ray: Ray,
+    max_distance: f32,
+    filter: impl Fn(&GameNode) -> bool,
+) -> Option<PickHit> {
+    // filtered during the ray cast so that a collider that's filtered out doesn't hide the
+    // ones behind it
+    let collider_predicate = |collider_handle: ColliderHandle, _: &Collider| {
+        physics_state
+            .find_collider_node(collider_handle)
+            .and_then(|node_id| scene.get_node(node_id))
+            .is_some_and(&filter)
+    };
+    let collider_hit = pick_collider(
+        physics_state,
+        ray,
+        max_distance,
+        QueryFilter::default().predicate(&collider_predicate),
+    );
+    let node_hit = pick_node(scene, renderer_data, ray, |node| {
+        physics_state.get_rigid_body_component(node.id()).is_none() && filter(node)
+    })
+    .filter(|hit| hit.distance <= max_distance);
+    [collider_hit, node_hit]
+        .into_iter()
+        .flatten()
+        .min_by(|a, b| a.distance.total_cmp(&b.distance))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rays_find_the_closest_point_on_a_line() {
+        let ray = Ray {
+            origin: Vec3::new(2.0, 1.0, 5.0),
+            direction: Vec3::new(0.0, 0.0, -1.0),
+        };
+        let (ray_distance, line_distance, distance) =
+            ray.closest_points_to_line(Vec3::ZERO, Vec3::X).unwrap();
+        assert!((ray_distance - 5.0).abs() < 1e-5);
+        assert!((line_distance - 2.0).abs() < 1e-5);
+        assert!((distance - 1.0).abs() < 1e-5);
+
+        assert!(ray.closest_points_to_line(Vec3::ZERO, Vec3::Z).is_none());
+    }
+
+    #[test]
+    fn rays_starting_inside_a_sphere_hit_its_far_side() {
+        let ray = Ray {
+            origin: Vec3::ZERO,
+            direction: Vec3::X,
+        };
+        assert_eq!(
+            ray.sphere_intersection(Vec3::new(5.0, 0.0, 0.0), 1.0),
+            Some(4.0)
+        );
+        assert_eq!(ray.sphere_intersection(Vec3::ZERO, 2.0), Some(2.0));
+        assert_eq!(
+            ray.sphere_intersection(Vec3::new(-5.0, 0.0, 0.0), 1.0),
+            None
+        );
+        assert_eq!(ray.sphere_intersection(Vec3::new(5.0, 2.0, 0.0), 1.0), None);
+    }
+}
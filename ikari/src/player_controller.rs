@@ -1,17 +1,16 @@
+use crate::camera::CameraLens;
 use crate::collisions::*;
+use crate::input::{AxisBinding, BindingSet, GamepadAxis, GamepadButton, InputBinding, InputMap};
 use crate::math::*;
 use crate::physics::*;
-use crate::renderer::*;
 use crate::time::*;
 use crate::transform::*;
 
 use rapier3d_f64::prelude::*;
 
-use glam::f32::{Quat, Vec3};
+use glam::f32::{Quat, Vec2, Vec3};
 use glam::EulerRot;
 use winit::event::MouseButton;
-use winit::keyboard::Key;
-use winit::keyboard::NamedKey;
 use winit::window::CursorGrabMode;
 use winit::{
     dpi::PhysicalPosition,
@@ -19,6 +18,20 @@ use winit::{
     window::Window,
 };
 
+pub const MOVE_FORWARD_ACTION: &str = "move_forward";
+pub const MOVE_BACKWARD_ACTION: &str = "move_backward";
+pub const MOVE_LEFT_ACTION: &str = "move_left";
+pub const MOVE_RIGHT_ACTION: &str = "move_right";
+pub const MOVE_UP_ACTION: &str = "move_up";
+pub const MOVE_DOWN_ACTION: &str = "move_down";
+pub const JUMP_ACTION: &str = "jump";
+pub const INCREASE_SPEED_ACTION: &str = "increase_speed";
+pub const DECREASE_SPEED_ACTION: &str = "decrease_speed";
+pub const MOVE_X_AXIS: &str = "move_x";
+pub const MOVE_Y_AXIS: &str = "move_y";
+pub const LOOK_X_AXIS: &str = "look_x";
+pub const LOOK_Y_AXIS: &str = "look_y";
+
 #[derive(Clone, Debug)]
 pub struct PlayerController {
     unprocessed_delta: Option<(f64, f64)>,
@@ -34,8 +47,12 @@ pub struct PlayerController {
     is_jump_pressed: bool,
     is_up_pressed: bool,
     is_down_pressed: bool,
+    /// from the gamepad's stick, used when none of the movement keys are pressed
+    analog_movement: Vec2,
 
     pub mouse_button_pressed: bool,
+    /// radians per second with the look stick fully pushed
+    pub look_speed: f32,
 
     pub view_direction: ControlledViewDirection,
     pub speed: f32,
@@ -44,12 +61,34 @@ pub struct PlayerController {
     pub last_jump_time: Option<Instant>,
 }
 
+/// The state of the controls at a given moment, e.g. to send the local player's
+/// input to a server or to drive a remote player's controller
+#[derive(Copy, Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PlayerInput {
+    pub forward: bool,
+    pub backward: bool,
+    pub left: bool,
+    pub right: bool,
+    pub jump: bool,
+    pub up: bool,
+    pub down: bool,
+    pub view_horizontal: f32,
+    pub view_vertical: f32,
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct ControlledViewDirection {
     pub horizontal: f32,
     pub vertical: f32,
 }
 
+fn gamepad_axis(axis: GamepadAxis) -> AxisBinding {
+    AxisBinding {
+        gamepad_axis: Some(axis),
+        ..Default::default()
+    }
+}
+
 impl ControlledViewDirection {
     pub fn to_quat(self) -> Quat {
         Quat::from_euler(EulerRot::XYZ, 0.0, self.horizontal, 0.0)
@@ -106,6 +145,9 @@ impl PlayerController {
             is_jump_pressed: false,
             is_up_pressed: false,
             is_down_pressed: false,
+            analog_movement: Vec2::ZERO,
+
+            look_speed: 3.0,
 
             view_direction,
             speed,
@@ -129,6 +171,38 @@ impl PlayerController {
         }
     }
 
+    pub fn input(&self) -> PlayerInput {
+        PlayerInput {
+            forward: self.is_forward_pressed,
+            backward: self.is_backward_pressed,
+            left: self.is_left_pressed,
+            right: self.is_right_pressed,
+            jump: self.is_jump_pressed,
+            up: self.is_up_pressed,
+            down: self.is_down_pressed,
+            view_horizontal: self.view_direction.horizontal,
+            view_vertical: self.view_direction.vertical,
+        }
+    }
+
+    /// Overrides the movement keys and the view direction and drops the mouse motion that
+    /// wasn't applied yet, e.g. for a server replaying a client's inputs. process_input
+    /// overwrites the keys with the InputMap's, so don't call both on the same controller
+    pub fn set_input(&mut self, input: PlayerInput) {
+        self.is_forward_pressed = input.forward;
+        self.is_backward_pressed = input.backward;
+        self.is_left_pressed = input.left;
+        self.is_right_pressed = input.right;
+        self.is_jump_pressed = input.jump;
+        self.is_up_pressed = input.up;
+        self.is_down_pressed = input.down;
+        self.view_direction = ControlledViewDirection {
+            horizontal: input.view_horizontal,
+            vertical: input.view_vertical,
+        };
+        self.unprocessed_delta = None;
+    }
+
     pub fn set_is_gravity_enabled(&self, physics_state: &mut PhysicsState, is_enabled: bool) {
         let rigid_body = physics_state
             .rigid_body_set
@@ -169,52 +243,95 @@ impl PlayerController {
         };
     }
 
-    pub fn process_window_event(&mut self, event: &WindowEvent, _window: &Window) {
-        match event {
-            WindowEvent::KeyboardInput { event, .. } => {
-                let key = event.logical_key.as_ref();
-                if event.state == ElementState::Pressed && key == Key::Named(NamedKey::ArrowUp) {
-                    self.increment_speed(true);
-                }
+    /// the bindings of the actions read by process_input, for the gameplay context
+    pub fn default_bindings() -> BindingSet {
+        BindingSet::default()
+            .action(MOVE_FORWARD_ACTION, vec![InputBinding::key("w")])
+            .action(MOVE_BACKWARD_ACTION, vec![InputBinding::key("s")])
+            .action(MOVE_LEFT_ACTION, vec![InputBinding::key("a")])
+            .action(MOVE_RIGHT_ACTION, vec![InputBinding::key("d")])
+            .action(
+                MOVE_UP_ACTION,
+                vec![
+                    InputBinding::key("e"),
+                    InputBinding::GamepadButton(GamepadButton::RightBumper),
+                ],
+            )
+            .action(
+                MOVE_DOWN_ACTION,
+                vec![
+                    InputBinding::key("q"),
+                    InputBinding::key("Control"),
+                    InputBinding::GamepadButton(GamepadButton::LeftBumper),
+                ],
+            )
+            .action(
+                JUMP_ACTION,
+                vec![
+                    InputBinding::key("Space"),
+                    InputBinding::GamepadButton(GamepadButton::South),
+                ],
+            )
+            .action(
+                INCREASE_SPEED_ACTION,
+                vec![
+                    InputBinding::key("ArrowUp"),
+                    InputBinding::GamepadButton(GamepadButton::DPadUp),
+                ],
+            )
+            .action(
+                DECREASE_SPEED_ACTION,
+                vec![
+                    InputBinding::key("ArrowDown"),
+                    InputBinding::GamepadButton(GamepadButton::DPadDown),
+                ],
+            )
+            .axis(MOVE_X_AXIS, gamepad_axis(GamepadAxis::LeftStickX))
+            .axis(MOVE_Y_AXIS, gamepad_axis(GamepadAxis::LeftStickY))
+            .axis(LOOK_X_AXIS, gamepad_axis(GamepadAxis::RightStickX))
+            .axis(LOOK_Y_AXIS, gamepad_axis(GamepadAxis::RightStickY))
+    }
 
-                if event.state == ElementState::Pressed && key == Key::Named(NamedKey::ArrowUp) {
-                    self.increment_speed(false);
-                }
+    /// Reads the movement actions and axes, should be called once per frame before update
+    pub fn process_input(&mut self, input_map: &InputMap, frame_time_seconds: f32) {
+        if !self.is_enabled {
+            return;
+        }
 
-                if self.is_enabled {
-                    let is_pressed = event.state == ElementState::Pressed;
-                    match key {
-                        Key::Character(character) => match character.to_lowercase().as_str() {
-                            "w" => {
-                                self.is_forward_pressed = is_pressed;
-                            }
-                            "a" => {
-                                self.is_left_pressed = is_pressed;
-                            }
-                            "s" => {
-                                self.is_backward_pressed = is_pressed;
-                            }
-                            "d" => {
-                                self.is_right_pressed = is_pressed;
-                            }
-                            "q" => {
-                                self.is_down_pressed = is_pressed;
-                            }
-                            "e" => {
-                                self.is_up_pressed = is_pressed;
-                            }
-                            _ => {}
-                        },
-                        Key::Named(NamedKey::Space) => {
-                            self.is_jump_pressed = is_pressed;
-                        }
-                        Key::Named(NamedKey::Control) => {
-                            self.is_down_pressed = is_pressed;
-                        }
-                        _ => {}
-                    }
-                }
-            }
+        self.analog_movement = Vec2::new(
+            input_map.axis_value(MOVE_X_AXIS),
+            input_map.axis_value(MOVE_Y_AXIS),
+        );
+
+        let look = Vec2::new(
+            input_map.axis_value(LOOK_X_AXIS),
+            input_map.axis_value(LOOK_Y_AXIS),
+        );
+        if self.window_focused && look != Vec2::ZERO {
+            let look_amount = self.look_speed * frame_time_seconds;
+            self.view_direction.horizontal += -look.x * look_amount;
+            self.view_direction.vertical = (self.view_direction.vertical + look.y * look_amount)
+                .clamp(deg_to_rad(-89.5), deg_to_rad(89.5));
+        }
+
+        self.is_forward_pressed = input_map.is_action_pressed(MOVE_FORWARD_ACTION);
+        self.is_backward_pressed = input_map.is_action_pressed(MOVE_BACKWARD_ACTION);
+        self.is_left_pressed = input_map.is_action_pressed(MOVE_LEFT_ACTION);
+        self.is_right_pressed = input_map.is_action_pressed(MOVE_RIGHT_ACTION);
+        self.is_up_pressed = input_map.is_action_pressed(MOVE_UP_ACTION);
+        self.is_down_pressed = input_map.is_action_pressed(MOVE_DOWN_ACTION);
+        self.is_jump_pressed = input_map.is_action_pressed(JUMP_ACTION);
+
+        if input_map.was_action_just_pressed(INCREASE_SPEED_ACTION) {
+            self.increment_speed(true);
+        }
+        if input_map.was_action_just_pressed(DECREASE_SPEED_ACTION) {
+            self.increment_speed(false);
+        }
+    }
+
+    pub fn process_window_event(&mut self, event: &WindowEvent, _window: &Window) {
+        match event {
             WindowEvent::Focused(focused) => {
                 #[cfg(not(target_arch = "wasm32"))]
                 if *focused {
@@ -322,8 +439,15 @@ impl PlayerController {
                 add_movement(up_direction);
             }
 
-            res.map(|res| res.normalize() * self.speed)
-                .unwrap_or(Vec3::new(0.0, 0.0, 0.0))
+            if res.is_none() && self.analog_movement != Vec2::ZERO {
+                // not normalized so that the stick's tilt controls the speed
+                let movement = forward_direction * self.analog_movement.y
+                    + right_direction * self.analog_movement.x;
+                movement.clamp_length_max(1.0) * self.speed
+            } else {
+                res.map(|res| res.normalize() * self.speed)
+                    .unwrap_or(Vec3::new(0.0, 0.0, 0.0))
+            }
         };
 
         let current_linear_velocity = rigid_body.linvel();
@@ -382,18 +506,16 @@ impl PlayerController {
 
     pub fn view_frustum_with_position(
         &self,
-        aspect_ratio: f32,
+        lens: &CameraLens,
+        surface_aspect_ratio: f32,
         camera_position: Vec3,
     ) -> CameraFrustumDescriptor {
-        let camera_forward = self.view_direction.to_vector();
-
-        CameraFrustumDescriptor {
-            focal_point: camera_position,
-            forward_vector: camera_forward,
-            aspect_ratio,
-            near_plane_distance: NEAR_PLANE_DISTANCE,
-            far_plane_distance: FAR_PLANE_DISTANCE,
-            fov_y_rad: deg_to_rad(FOV_Y_DEG),
-        }
+        lens.frustum_descriptor(
+            TransformBuilder::new()
+                .position(camera_position)
+                .rotation(self.view_direction.to_quat())
+                .build(),
+            surface_aspect_ratio,
+        )
     }
 }
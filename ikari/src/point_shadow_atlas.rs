@@ -0,0 +1,99 @@
+use crate::scene::GameNodeId;
+
+/// Hands out the layers of the point light shadow map array to the shadow casting lights. A
+/// light keeps its layer for as long as it casts shadows and the freed layers go to the next
+/// lights that need one. The array doubles in size when it's full, up to a maximum
+#[derive(Debug)]
+pub(crate) struct PointShadowAtlas {
+    /// the node of the light that has each layer
+    layers: Vec<Option<GameNodeId>>,
+    min_layer_count: u32,
+}
+
+impl PointShadowAtlas {
+    pub fn new(min_layer_count: u32) -> Self {
+        let min_layer_count = min_layer_count.max(1);
+        Self {
+            layers: vec![None; min_layer_count as usize],
+            min_layer_count,
+        }
+    }
+
+    pub fn layer_count(&self) -> u32 {
+        self.layers.len() as u32
+    }
+
+    /// Takes the node of each light, None for the lights that don't cast shadows. Returns the
+    /// layer of each light, None when it has no shadow map or when all max_layer_count layers
+    /// are taken by the lights before it. The array must be recreated when the layer count
+    /// changed, which clears all the shadow maps
+    pub fn allocate(
+        &mut self,
+        lights: &[Option<GameNodeId>],
+        max_layer_count: u32,
+    ) -> Vec<Option<u32>> {
+        let max_layer_count = max_layer_count.max(self.min_layer_count) as usize;
+        if self.layers.len() > max_layer_count {
+            self.layers.truncate(max_layer_count);
+        }
+        for layer in &mut self.layers {
+            if layer.is_some_and(|node_id| !lights.contains(&Some(node_id))) {
+                *layer = None;
+            }
+        }
+
+        lights
+            .iter()
+            .map(|light| {
+                let node_id = (*light)?;
+                if let Some(layer) = self.layers.iter().position(|layer| *layer == Some(node_id)) {
+                    return Some(layer as u32);
+                }
+                let layer = match self.layers.iter().position(|layer| layer.is_none()) {
+                    Some(layer) => layer,
+                    None if self.layers.len() < max_layer_count => {
+                        let layer = self.layers.len();
+                        self.layers
+                            .resize((layer * 2).clamp(1, max_layer_count), None);
+                        layer
+                    }
+                    None => return None,
+                };
+                self.layers[layer] = Some(node_id);
+                Some(layer as u32)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::{GameNodeDesc, Scene};
+
+    #[test]
+    fn layers_are_reused_and_the_array_grows() {
+        let mut scene = Scene::default();
+        let [a, b, c, d] = [(); 4].map(|_| Some(scene.add_node(GameNodeDesc::default()).id()));
+        let mut atlas = PointShadowAtlas::new(2);
+
+        assert_eq!(atlas.allocate(&[a, None, b], 8), [Some(0), None, Some(1)]);
+        assert_eq!(atlas.layer_count(), 2);
+
+        // a is removed, c takes its layer
+        assert_eq!(atlas.allocate(&[b, c], 8), [Some(1), Some(0)]);
+
+        assert_eq!(
+            atlas.allocate(&[b, c, a, d], 8),
+            [Some(1), Some(0), Some(2), Some(3)]
+        );
+        assert_eq!(atlas.layer_count(), 4);
+
+        // the lights past the maximum go without shadows
+        assert_eq!(
+            atlas.allocate(&[b, c, a, d], 3),
+            [Some(1), Some(0), Some(2), None]
+        );
+        assert_eq!(atlas.layer_count(), 3);
+    }
+}
@@ -0,0 +1,401 @@
+use crate::camera::make_clip_plane;
+use crate::custom_material::{CustomMaterial, CustomMaterialDataId, CustomMaterialId};
+use crate::engine_state::EngineState;
+use crate::renderer::Renderer;
+use crate::scene::{GameNodeDesc, GameNodeId, Material, Scene};
+use crate::texture::Texture;
+use crate::transform::Transform;
+
+use anyhow::Result;
+use glam::f32::{Affine3A, Mat3, Vec3, Vec4};
+
+/// the clipping starts this far in front of the exit so the surfaces lying on it are clipped too
+const CLIP_PLANE_OFFSET: f32 = 0.01;
+
+/// The material of the portal surfaces, registered by Portals::new. Shows the portal's render
+/// target at the same screen position as the fragment
+pub struct PortalMaterial;
+
+impl CustomMaterial for PortalMaterial {
+    fn label(&self) -> &str {
+        "Portal"
+    }
+
+    fn shader_source(&self) -> String {
+        include_str!("shaders/portal.wgsl").to_string()
+    }
+
+    fn user_data_layout_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+        vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ]
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PortalKind {
+    /// reflects the scene across the surface
+    Mirror,
+    /// shows what's in front of the exit node, which can be the surface of another portal
+    Portal { exit_node_id: GameNodeId },
+}
+
+struct PortalTarget {
+    texture: Texture,
+    user_data: CustomMaterialDataId,
+}
+
+struct Portal {
+    surface_node_id: GameNodeId,
+    kind: PortalKind,
+    /// each level of recursion renders into one and reads the other, see Portals::render
+    targets: Vec<PortalTarget>,
+}
+
+/// Planar mirrors and portals. Each one renders the scene again with render_scene_hdr, from the
+/// camera seen through the portal and clipped at the exit, and its surface is drawn with that
+/// render. The surface node's mesh should be flat on its local xy plane, facing +z
+pub struct Portals {
+    /// how many times the portals are rendered inside each other, e.g. for two portals that
+    /// face each other. At the deepest level they show what they showed in the previous frame.
+    /// Every level renders each portal once more so it's best kept low
+    pub max_recursion_depth: u32,
+    portals: Vec<Portal>,
+    material_id: CustomMaterialId,
+    /// the camera of the portal renders
+    camera_node_id: Option<GameNodeId>,
+    framebuffer_size: (u32, u32),
+}
+
+impl Portals {
+    pub fn new(renderer: &Renderer) -> Self {
+        let material_id = renderer
+            .data
+            .lock()
+            .unwrap()
+            .custom_materials
+            .register(&renderer.base.device, PortalMaterial);
+        Self {
+            max_recursion_depth: 2,
+            portals: vec![],
+            material_id,
+            camera_node_id: None,
+            framebuffer_size: (0, 0),
+        }
+    }
+
+    /// The material of the surface node's visual is replaced when the portal is rendered
+    pub fn add(&mut self, surface_node_id: GameNodeId, kind: PortalKind) {
+        self.portals.push(Portal {
+            surface_node_id,
+            kind,
+            targets: vec![],
+        });
+    }
+
+    /// The surface node keeps the portal material, change it or remove the node
+    pub fn remove(&mut self, renderer: &Renderer, surface_node_id: GameNodeId) {
+        let mut data_guard = renderer.data.lock().unwrap();
+        self.portals.retain(|portal| {
+            let is_removed = portal.surface_node_id == surface_node_id;
+            if is_removed {
+                for target in &portal.targets {
+                    data_guard
+                        .custom_materials
+                        .unbind_user_data(target.user_data);
+                }
+            }
+            !is_removed
+        });
+    }
+
+    /// The render targets were made with the old device, they're recreated on the next render
+    pub fn on_device_recreated(&mut self) {
+        for portal in &mut self.portals {
+            portal.targets.clear();
+        }
+    }
+
+    /// Call it before Renderer::render with the size of the framebuffer
+    pub fn render(
+        &mut self,
+        renderer: &mut Renderer,
+        engine_state: &mut EngineState,
+        framebuffer_size: (u32, u32),
+    ) -> Result<()> {
+        let removed_surface_node_ids: Vec<_> = self
+            .portals
+            .iter()
+            .map(|portal| portal.surface_node_id)
+            .filter(|node_id| engine_state.scene.get_node(*node_id).is_none())
+            .collect();
+        for surface_node_id in removed_surface_node_ids {
+            self.remove(renderer, surface_node_id);
+        }
+        if self.portals.is_empty() {
+            return Ok(());
+        }
+        self.prepare_targets(renderer, framebuffer_size)?;
+
+        let (main_camera_node_id, main_clip_planes) = {
+            let data_guard = renderer.data.lock().unwrap();
+            (data_guard.camera_node_id, data_guard.clip_planes.clone())
+        };
+        let Some(main_camera_transform) = main_camera_node_id
+            .and_then(|camera_node_id| engine_state.scene.get_node(camera_node_id))
+            .map(|camera_node| camera_node.transform)
+        else {
+            return Ok(());
+        };
+        let camera_node_id = match self
+            .camera_node_id
+            .filter(|camera_node_id| engine_state.scene.get_node(*camera_node_id).is_some())
+        {
+            Some(camera_node_id) => camera_node_id,
+            None => engine_state.scene.add_node(GameNodeDesc::default()).id(),
+        };
+        self.camera_node_id = Some(camera_node_id);
+
+        let result = self.render_levels(
+            renderer,
+            engine_state,
+            main_camera_transform,
+            camera_node_id,
+        );
+
+        {
+            let mut data_guard = renderer.data.lock().unwrap();
+            data_guard.camera_node_id = main_camera_node_id;
+            data_guard.clip_planes = main_clip_planes;
+        }
+        self.set_surface_materials(&mut engine_state.scene, 0);
+        result
+    }
+
+    /// The deepest level is rendered first so each level shows the one behind it
+    fn render_levels(
+        &self,
+        renderer: &mut Renderer,
+        engine_state: &mut EngineState,
+        main_camera_transform: Transform,
+        camera_node_id: GameNodeId,
+    ) -> Result<()> {
+        for level in (0..self.max_recursion_depth.max(1)).rev() {
+            self.set_surface_materials(&mut engine_state.scene, level + 1);
+            for portal in &self.portals {
+                let Some((camera_transform, clip_plane)) =
+                    portal.view(&engine_state.scene, main_camera_transform, level + 1)
+                else {
+                    continue;
+                };
+                if let Some(camera_node) = engine_state.scene.get_node_mut(camera_node_id) {
+                    camera_node.transform = camera_transform;
+                }
+                {
+                    let mut data_guard = renderer.data.lock().unwrap();
+                    data_guard.camera_node_id = Some(camera_node_id);
+                    data_guard.clip_planes = vec![clip_plane];
+                }
+                renderer
+                    .render_scene_hdr(engine_state, &portal.targets[level as usize % 2].texture)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn prepare_targets(&mut self, renderer: &Renderer, framebuffer_size: (u32, u32)) -> Result<()> {
+        let mut data_guard = renderer.data.lock().unwrap();
+        if self.framebuffer_size != framebuffer_size {
+            self.framebuffer_size = framebuffer_size;
+            for portal in &mut self.portals {
+                for target in portal.targets.drain(..) {
+                    data_guard
+                        .custom_materials
+                        .unbind_user_data(target.user_data);
+                }
+            }
+        }
+
+        for portal in self
+            .portals
+            .iter_mut()
+            .filter(|portal| portal.targets.is_empty())
+        {
+            for _ in 0..2 {
+                // hdr so the portal goes through the tone mapping and post-processing only
+                // once, with the rest of the frame
+                let texture = Texture::create_scaled_surface_texture(
+                    &renderer.base,
+                    framebuffer_size,
+                    1.0,
+                    "portal_texture",
+                );
+                let sampler_cache_guard = renderer.base.sampler_cache.lock().unwrap();
+                let user_data = data_guard.custom_materials.bind_user_data(
+                    &renderer.base.device,
+                    self.material_id,
+                    &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&texture.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(
+                                sampler_cache_guard.get_sampler_by_index(texture.sampler_index),
+                            ),
+                        },
+                    ],
+                )?;
+                portal.targets.push(PortalTarget { texture, user_data });
+            }
+        }
+        Ok(())
+    }
+
+    /// Points the surfaces at the targets written by a level
+    fn set_surface_materials(&self, scene: &mut Scene, level: u32) {
+        for portal in &self.portals {
+            let Some(visual) = scene
+                .get_node_mut(portal.surface_node_id)
+                .and_then(|node| node.visual.as_mut())
+            else {
+                continue;
+            };
+            let mirror_flag = if portal.kind == PortalKind::Mirror {
+                1.0
+            } else {
+                0.0
+            };
+            visual.material = Material::Custom {
+                material: self.material_id,
+                user_data: Some(portal.targets[level as usize % 2].user_data),
+                params: Vec4::new(mirror_flag, 0.0, 0.0, 0.0),
+            };
+        }
+    }
+}
+
+impl Portal {
+    /// The transform of the camera that sees through the portal depth times and the plane that
+    /// clips what's in between it and the exit. None for the mirrors past the first level, two
+    /// reflections in the same mirror cancel out
+    fn view(
+        &self,
+        scene: &Scene,
+        camera_transform: Transform,
+        depth: u32,
+    ) -> Option<(Transform, Vec4)> {
+        scene.get_node(self.surface_node_id)?;
+        let surface = *scene.get_global_transform_for_node(self.surface_node_id);
+        let camera = *camera_transform;
+
+        let (view, exit) = match self.kind {
+            PortalKind::Mirror if depth > 1 => return None,
+            PortalKind::Mirror => {
+                let normal = Vec3::from(surface.z_axis).normalize();
+                let point = Vec3::from(surface.translation);
+                let reflection = Affine3A::from_mat3_translation(
+                    Mat3::IDENTITY
+                        - Mat3::from_cols(normal * normal.x, normal * normal.y, normal * normal.z)
+                            * 2.0,
+                    2.0 * normal * normal.dot(point),
+                );
+                // flipped horizontally so the camera isn't mirrored, the shader flips it back
+                (
+                    reflection * camera * Affine3A::from_scale(Vec3::new(-1.0, 1.0, 1.0)),
+                    surface,
+                )
+            }
+            PortalKind::Portal { exit_node_id } => {
+                scene.get_node(exit_node_id)?;
+                let exit = *scene.get_global_transform_for_node(exit_node_id);
+                // going in the front of the surface comes out of the front of the exit
+                let step =
+                    exit * Affine3A::from_rotation_y(std::f32::consts::PI) * surface.inverse();
+                let mut view = step * camera;
+                let mut deepest_exit = exit;
+                for _ in 1..depth {
+                    view = step * view;
+                    deepest_exit = step * deepest_exit;
+                }
+                (view, deepest_exit)
+            }
+        };
+
+        let normal = Vec3::from(exit.z_axis).normalize();
+        let clip_plane = make_clip_plane(
+            Vec3::from(exit.translation) + normal * CLIP_PLANE_OFFSET,
+            normal,
+        );
+        Some((Transform::from(view), clip_plane))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::TransformBuilder;
+    use glam::f32::Quat;
+
+    fn add_node(scene: &mut Scene, transform: Transform) -> GameNodeId {
+        scene
+            .add_node(GameNodeDesc {
+                transform,
+                ..Default::default()
+            })
+            .id()
+    }
+
+    #[test]
+    fn cameras_are_reflected_and_moved_to_the_exit() {
+        let mut scene = Scene::default();
+        let mirror_node_id = add_node(&mut scene, Transform::IDENTITY);
+        let exit_node_id = add_node(
+            &mut scene,
+            TransformBuilder::new()
+                .position(Vec3::new(10.0, 0.0, 0.0))
+                .rotation(Quat::from_rotation_y(std::f32::consts::FRAC_PI_2))
+                .build(),
+        );
+        let camera_transform = TransformBuilder::new()
+            .position(Vec3::new(0.0, 1.0, 3.0))
+            .build();
+
+        let mirror = Portal {
+            surface_node_id: mirror_node_id,
+            kind: PortalKind::Mirror,
+            targets: vec![],
+        };
+        let (view, clip_plane) = mirror.view(&scene, camera_transform, 1).unwrap();
+        assert!(view.position().abs_diff_eq(Vec3::new(0.0, 1.0, -3.0), 1e-5));
+        // looks back at the mirror
+        assert!(Vec3::from(-view.z_axis).abs_diff_eq(Vec3::Z, 1e-5));
+        assert!(clip_plane.abs_diff_eq(Vec4::new(0.0, 0.0, 1.0, -CLIP_PLANE_OFFSET), 1e-5));
+        assert!(mirror.view(&scene, camera_transform, 2).is_none());
+
+        let portal = Portal {
+            surface_node_id: mirror_node_id,
+            kind: PortalKind::Portal { exit_node_id },
+            targets: vec![],
+        };
+        let (view, _) = portal.view(&scene, camera_transform, 1).unwrap();
+        assert!(view.position().abs_diff_eq(Vec3::new(7.0, 1.0, 0.0), 1e-5));
+        assert!(Vec3::from(-view.z_axis).abs_diff_eq(Vec3::X, 1e-5));
+    }
+}
@@ -0,0 +1,236 @@
+use crate::renderer::{BaseRenderer, RendererConstantData, USE_LABELS};
+
+use anyhow::{bail, Result};
+use wgpu::util::DeviceExt;
+
+/// Prepended to the fragment shader of every post-process pass. The input image is sampled with
+/// INPUT_TEXTURE and INPUT_SAMPLER at in.tex_coords, and the pass's uniform buffer, if any, is
+/// declared by the pass at @group(2) @binding(0)
+pub const POST_PROCESS_SHADER_PRELUDE: &str = r#"
+struct FrameConstants {
+    time_seconds: f32,
+    delta_time_seconds: f32,
+    frame_index: u32,
+    render_resolution: vec4<f32>,
+    jitter: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var INPUT_TEXTURE: texture_2d<f32>;
+@group(0) @binding(1)
+var INPUT_SAMPLER: sampler;
+
+@group(1) @binding(0)
+var<uniform> FRAME: FrameConstants;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let tc = vec2<f32>(f32(i32(vertex_index) / 2) * 2.0, f32(i32(vertex_index) & 1) * 2.0);
+    out.position = vec4<f32>(tc.x * 2.0 - 1.0, 1.0 - tc.y * 2.0, 0.0, 1.0);
+    out.tex_coords = tc;
+    return out;
+}
+"#;
+
+/// Where a post-process pass runs in the frame
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PostProcessStage {
+    /// On the linear hdr image of the opaque meshes, before bloom and tone mapping
+    AfterShading,
+    /// On the tone mapped and color graded image, transparent meshes included, right before it's
+    /// drawn to the surface. The ui isn't in it yet
+    AfterToneMapping,
+}
+
+#[derive(Debug, Clone)]
+pub struct PostProcessPassDescriptor {
+    pub label: String,
+    pub stage: PostProcessStage,
+    /// Appended to POST_PROCESS_SHADER_PRELUDE, must have an fs_main entry point
+    pub fragment_shader_source: String,
+    /// The initial contents of the pass's uniform buffer, None for no uniform buffer
+    pub uniform_data: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PostProcessPassId(usize);
+
+/// the uniform buffer and its bind group
+type PassUniform = (wgpu::Buffer, wgpu::BindGroup);
+
+struct PostProcessPass {
+    descriptor: PostProcessPassDescriptor,
+    pipeline: wgpu::RenderPipeline,
+    uniform: Option<PassUniform>,
+    enabled: bool,
+}
+
+/// The game's full-screen passes, e.g. vignettes, color grading or damage overlays. They run in
+/// the order they were added, each one reading the output of the previous one
+#[derive(Default)]
+pub struct PostProcessPasses {
+    passes: Vec<PostProcessPass>,
+}
+
+impl PostProcessPasses {
+    /// See Renderer::add_post_process_pass
+    pub(crate) fn add(
+        &mut self,
+        base: &BaseRenderer,
+        constant_data: &RendererConstantData,
+        descriptor: PostProcessPassDescriptor,
+    ) -> PostProcessPassId {
+        let (pipeline, uniform) = create_pass_resources(base, constant_data, &descriptor);
+        self.passes.push(PostProcessPass {
+            descriptor,
+            pipeline,
+            uniform,
+            enabled: true,
+        });
+        PostProcessPassId(self.passes.len() - 1)
+    }
+
+    pub fn set_enabled(&mut self, pass_id: PostProcessPassId, enabled: bool) {
+        if let Some(pass) = self.passes.get_mut(pass_id.0) {
+            pass.enabled = enabled;
+        }
+    }
+
+    pub fn is_enabled(&self, pass_id: PostProcessPassId) -> bool {
+        self.passes.get(pass_id.0).is_some_and(|pass| pass.enabled)
+    }
+
+    /// e.g. every frame for an overlay that fades out. data must be as big as the initial
+    /// uniform_data of the pass
+    pub fn write_uniform(
+        &self,
+        queue: &wgpu::Queue,
+        pass_id: PostProcessPassId,
+        data: &[u8],
+    ) -> Result<()> {
+        let Some(pass) = self.passes.get(pass_id.0) else {
+            bail!("There's no post-process pass {pass_id:?}");
+        };
+        let Some((buffer, _)) = &pass.uniform else {
+            bail!(
+                "Post-process pass {} has no uniform buffer",
+                pass.descriptor.label
+            );
+        };
+        if data.len() as u64 != buffer.size() {
+            bail!(
+                "Post-process pass {} expects {} bytes of uniform data, got {}",
+                pass.descriptor.label,
+                buffer.size(),
+                data.len()
+            );
+        }
+        queue.write_buffer(buffer, 0, data);
+        Ok(())
+    }
+
+    /// label, pipeline and uniform bind group of the enabled passes of the stage
+    pub(crate) fn enabled_passes(
+        &self,
+        stage: PostProcessStage,
+    ) -> impl Iterator<Item = (&str, &wgpu::RenderPipeline, Option<&wgpu::BindGroup>)> {
+        self.passes
+            .iter()
+            .filter(move |pass| pass.enabled && pass.descriptor.stage == stage)
+            .map(|pass| {
+                (
+                    pass.descriptor.label.as_str(),
+                    &pass.pipeline,
+                    pass.uniform.as_ref().map(|(_, bind_group)| bind_group),
+                )
+            })
+    }
+
+    /// After the gpu device was recreated. The uniform buffers go back to their initial contents
+    pub(crate) fn recreate(&mut self, base: &BaseRenderer, constant_data: &RendererConstantData) {
+        for pass in &mut self.passes {
+            (pass.pipeline, pass.uniform) =
+                create_pass_resources(base, constant_data, &pass.descriptor);
+        }
+    }
+}
+
+fn create_pass_resources(
+    base: &BaseRenderer,
+    constant_data: &RendererConstantData,
+    descriptor: &PostProcessPassDescriptor,
+) -> (wgpu::RenderPipeline, Option<PassUniform>) {
+    let label = descriptor.label.as_str();
+    let device = &base.device;
+
+    let uniform = descriptor.uniform_data.as_ref().map(|uniform_data| {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: USE_LABELS.then_some(label),
+            contents: uniform_data,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &constant_data.single_uniform_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: USE_LABELS.then_some(label),
+        });
+        (buffer, bind_group)
+    });
+
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: USE_LABELS.then_some(label),
+        source: wgpu::ShaderSource::Wgsl(
+            format!(
+                "{POST_PROCESS_SHADER_PRELUDE}\n{}",
+                descriptor.fragment_shader_source
+            )
+            .into(),
+        ),
+    });
+    let mut bind_group_layouts = vec![
+        &constant_data.single_texture_bind_group_layout,
+        // frame constants
+        &constant_data.single_uniform_bind_group_layout,
+    ];
+    if uniform.is_some() {
+        bind_group_layouts.push(&constant_data.single_uniform_bind_group_layout);
+    }
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: USE_LABELS.then_some(label),
+        bind_group_layouts: &bind_group_layouts,
+        push_constant_ranges: &[],
+    });
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: USE_LABELS.then_some(label),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: wgpu::TextureFormat::Rgba16Float,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    });
+
+    (pipeline, uniform)
+}
@@ -0,0 +1,402 @@
+use crate::renderer::*;
+use crate::scene::*;
+use crate::transform::Transform;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+
+/// A sub-tree of nodes that can be instantiated many times. Its meshes, materials and textures
+/// are bound once when the prefab is created and are shared by all of its instances
+#[derive(Debug, Clone)]
+pub struct Prefab {
+    pub name: String,
+    /// the prefab holds a reference to it, see asset_id
+    asset_id: BindedAssetId,
+    /// parents come before their children, the visuals point into the renderer data
+    nodes: Vec<IndexedGameNodeDesc>,
+    skins: Vec<IndexedSkin>,
+    animations: Vec<IndexedAnimation>,
+    nested: Vec<NestedPrefab>,
+}
+
+#[derive(Debug, Clone)]
+struct NestedPrefab {
+    name: String,
+    prefab: Arc<Prefab>,
+    /// None to attach it to the root of the outer prefab's instances
+    parent_index: Option<usize>,
+    transform: Transform,
+    overrides: PrefabOverrides,
+}
+
+/// Changes to the nodes of a single instance, looked up by node name
+#[derive(Debug, Clone, Default)]
+pub struct NodeOverride {
+    pub transform: Option<Transform>,
+    /// the mesh stays shared with the other instances
+    pub material: Option<Material>,
+    pub wireframe: Option<bool>,
+    /// false removes the node's visual from this instance
+    pub visible: Option<bool>,
+}
+
+impl NodeOverride {
+    fn apply(&self, node: &mut GameNode) {
+        if let Some(transform) = self.transform {
+            node.transform = transform;
+        }
+        if self.visible == Some(false) {
+            node.visual = None;
+        }
+        if let Some(visual) = node.visual.as_mut() {
+            if let Some(material) = self.material {
+                visual.material = material;
+            }
+            if let Some(wireframe) = self.wireframe {
+                visual.wireframe = wireframe;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PrefabOverrides {
+    nodes: HashMap<String, NodeOverride>,
+}
+
+impl PrefabOverrides {
+    /// the nodes of nested prefabs are named "<nested prefab name>/<node name>"
+    pub fn node(mut self, node_name: &str, node_override: NodeOverride) -> Self {
+        self.nodes.insert(node_name.to_string(), node_override);
+        self
+    }
+
+    /// the overrides set for a nested prefab when it was added, plus the ones
+    /// from this instance that target its nodes, which take precedence
+    fn for_nested(&self, nested: &NestedPrefab) -> PrefabOverrides {
+        let prefix = format!("{}/", nested.name);
+        let mut overrides = nested.overrides.clone();
+        for (node_name, node_override) in &self.nodes {
+            if let Some(nested_node_name) = node_name.strip_prefix(&prefix) {
+                overrides
+                    .nodes
+                    .insert(nested_node_name.to_string(), node_override.clone());
+            }
+        }
+        overrides
+    }
+}
+
+impl Prefab {
+    /// Takes a scene loaded by the asset loader, e.g. from AssetBinder::loaded_scenes, and binds
+    /// its render buffers. If root_node_name is set, only the sub-tree under the first node with
+    /// that name is kept, otherwise the whole scene is
+    pub fn from_loaded_scene(
+        name: &str,
+        mut scene: Scene,
+        render_buffers: BindedSceneData,
+        renderer_data: &mut RendererData,
+        root_node_name: Option<&str>,
+    ) -> Result<Self> {
+        let asset_id = scene.bind_render_buffers(renderer_data, render_buffers);
+
+        let root_node_id = match root_node_name {
+            Some(root_node_name) => Some(
+                scene
+                    .nodes()
+                    .find(|node| node.name.as_deref() == Some(root_node_name))
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Prefab {name} has no node named {root_node_name}")
+                    })?
+                    .id(),
+            ),
+            None => None,
+        };
+
+        // parents must come before their children
+        let mut kept_nodes: Vec<_> = scene
+            .nodes()
+            .filter_map(|node| {
+                let ancestry: Vec<_> = scene.get_node_ancestry_list(node.id()).collect();
+                let is_kept =
+                    root_node_id.map_or(true, |root_node_id| ancestry.contains(&root_node_id));
+                is_kept.then_some((ancestry.len(), node))
+            })
+            .collect();
+        kept_nodes.sort_by_key(|(depth, _)| *depth);
+
+        let node_indices: HashMap<GameNodeId, usize> = kept_nodes
+            .iter()
+            .enumerate()
+            .map(|(node_index, (_, node))| (node.id(), node_index))
+            .collect();
+
+        // skins can only be kept if their whole skeleton was
+        let mut skin_indices: HashMap<usize, usize> = HashMap::new();
+        let mut skins = vec![];
+        for (old_skin_index, skin) in scene.skins.iter().enumerate() {
+            let bone_node_indices: Option<Vec<_>> = skin
+                .bone_node_ids
+                .iter()
+                .map(|bone_node_id| node_indices.get(bone_node_id).copied())
+                .collect();
+            let (Some(bone_node_indices), true) =
+                (bone_node_indices, node_indices.contains_key(&skin.node_id))
+            else {
+                continue;
+            };
+            skin_indices.insert(old_skin_index, skins.len());
+            skins.push(IndexedSkin {
+                bone_node_indices,
+                bone_inverse_bind_matrices: skin.bone_inverse_bind_matrices.clone(),
+                bone_bounding_box_transforms: skin.bone_bounding_box_transforms.clone(),
+            });
+        }
+
+        let nodes = kept_nodes
+            .iter()
+            .map(|(_, node)| IndexedGameNodeDesc {
+                // the root of the sub-tree is placed relative to the instance
+                transform: if Some(node.id()) == root_node_id {
+                    Transform::IDENTITY
+                } else {
+                    node.transform
+                },
+                skin_index: node
+                    .skin_index
+                    .and_then(|skin_index| skin_indices.get(&skin_index).copied()),
+                visual: node.visual.clone(),
+                name: node.name.clone(),
+                parent_index: node
+                    .parent_id
+                    .and_then(|parent_id| node_indices.get(&parent_id).copied()),
+            })
+            .collect();
+
+        let animations = scene
+            .animations
+            .iter()
+            .filter_map(|animation| {
+                let channels: Vec<_> = animation
+                    .channels
+                    .iter()
+                    .filter_map(|channel| {
+                        Some(IndexedChannel {
+                            node_index: *node_indices.get(&channel.node_id)?,
+                            property: channel.property,
+                            interpolation_type: channel.interpolation_type,
+                            keyframe_timings: channel.keyframe_timings.clone(),
+                            keyframe_values_u8: channel.keyframe_values_u8.clone(),
+                        })
+                    })
+                    .collect();
+                (!channels.is_empty()).then(|| IndexedAnimation {
+                    name: animation.name.clone(),
+                    length_seconds: animation.length_seconds,
+                    channels,
+                    events: animation.events.clone(),
+                })
+            })
+            .collect();
+
+        Ok(Self {
+            name: name.to_string(),
+            asset_id,
+            nodes,
+            skins,
+            animations,
+            nested: vec![],
+        })
+    }
+
+    /// The binded asset shared by all the instances. The prefab holds one reference to it,
+    /// give it back with Scene::unload_asset once the prefab and its instances are gone.
+    /// Anything else that keeps the meshes after that should take its own with
+    /// BindedAssets::retain
+    pub fn asset_id(&self) -> BindedAssetId {
+        self.asset_id
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Makes each instance of this prefab also instantiate the other one, under the node named
+    /// parent_node_name or under the instance's root. The name is used to address the nested
+    /// prefab's nodes in PrefabOverrides
+    pub fn add_nested(
+        &mut self,
+        name: &str,
+        prefab: Arc<Prefab>,
+        parent_node_name: Option<&str>,
+        transform: Transform,
+        overrides: PrefabOverrides,
+    ) -> Result<()> {
+        let parent_index = match parent_node_name {
+            Some(parent_node_name) => Some(
+                self.nodes
+                    .iter()
+                    .position(|node| node.name.as_deref() == Some(parent_node_name))
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Prefab {} has no node named {parent_node_name}", self.name)
+                    })?,
+            ),
+            None => None,
+        };
+        self.nested.push(NestedPrefab {
+            name: name.to_string(),
+            prefab,
+            parent_index,
+            transform,
+            overrides,
+        });
+        Ok(())
+    }
+
+    /// Adds a root node with the given transform to the scene and the prefab's nodes under it.
+    /// The animations of the prefab are added to the scene for each instance, stopped
+    pub fn instantiate(
+        &self,
+        scene: &mut Scene,
+        parent_id: Option<GameNodeId>,
+        transform: Transform,
+        overrides: &PrefabOverrides,
+    ) -> PrefabInstance {
+        let root_node_id = scene
+            .add_node(
+                GameNodeDescBuilder::new()
+                    .name(Some(self.name.clone()))
+                    .transform(transform)
+                    .parent_id(parent_id)
+                    .build(),
+            )
+            .id();
+
+        let mut prefab_scene = Scene::new(
+            self.nodes.clone(),
+            self.skins.clone(),
+            self.animations.clone(),
+        );
+        for node in prefab_scene.nodes_mut() {
+            if let Some(node_override) = node
+                .name
+                .as_ref()
+                .and_then(|name| overrides.nodes.get(name))
+            {
+                node_override.apply(node);
+            }
+        }
+
+        let node_ids = scene.merge_scene_nodes(prefab_scene);
+        for (node_desc, node_id) in self.nodes.iter().zip(&node_ids) {
+            if node_desc.parent_index.is_none() {
+                if let Some(node) = scene.get_node_mut(*node_id) {
+                    node.parent_id = Some(root_node_id);
+                }
+            }
+        }
+
+        let nested = self
+            .nested
+            .iter()
+            .map(|nested| {
+                let parent_id = nested
+                    .parent_index
+                    .map_or(root_node_id, |parent_index| node_ids[parent_index]);
+                nested.prefab.instantiate(
+                    scene,
+                    Some(parent_id),
+                    nested.transform,
+                    &overrides.for_nested(nested),
+                )
+            })
+            .collect();
+
+        PrefabInstance {
+            root_node_id,
+            node_ids,
+            nested,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PrefabInstance {
+    pub root_node_id: GameNodeId,
+    /// the node spawned for each of the prefab's nodes
+    pub node_ids: Vec<GameNodeId>,
+    pub nested: Vec<PrefabInstance>,
+}
+
+impl PrefabInstance {
+    /// looks through the nodes of this instance and then through the nested ones
+    pub fn find_node(&self, scene: &Scene, name: &str) -> Option<GameNodeId> {
+        self.node_ids
+            .iter()
+            .copied()
+            .find(|node_id| {
+                scene
+                    .get_node(*node_id)
+                    .is_some_and(|node| node.name.as_deref() == Some(name))
+            })
+            .or_else(|| {
+                self.nested
+                    .iter()
+                    .find_map(|nested| nested.find_node(scene, name))
+            })
+    }
+
+    /// the root node, the prefab's nodes and those of the nested instances
+    pub fn all_node_ids(&self) -> Vec<GameNodeId> {
+        let mut node_ids = vec![self.root_node_id];
+        node_ids.extend(self.node_ids.iter().copied());
+        for nested in &self.nested {
+            node_ids.extend(nested.all_node_ids());
+        }
+        node_ids
+    }
+
+    /// The skins and animations of the instance stay in the scene but have no effect once
+    /// their nodes are gone. The shared render data is kept for the other instances
+    pub fn remove(&self, scene: &mut Scene) {
+        for node_id in self.all_node_ids() {
+            scene.remove_node(node_id);
+        }
+    }
+}
+
+/// Prefabs by name, so scene code can instantiate them without passing them around
+#[derive(Debug, Default)]
+pub struct PrefabLibrary {
+    prefabs: HashMap<String, Arc<Prefab>>,
+}
+
+impl PrefabLibrary {
+    /// replaces any prefab with the same name, the existing instances are left as they are
+    pub fn insert(&mut self, prefab: Prefab) -> Arc<Prefab> {
+        let prefab = Arc::new(prefab);
+        self.prefabs.insert(prefab.name.clone(), prefab.clone());
+        prefab
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Prefab>> {
+        self.prefabs.get(name).cloned()
+    }
+
+    pub fn instantiate(
+        &self,
+        name: &str,
+        scene: &mut Scene,
+        parent_id: Option<GameNodeId>,
+        transform: Transform,
+        overrides: &PrefabOverrides,
+    ) -> Option<PrefabInstance> {
+        let Some(prefab) = self.prefabs.get(name) else {
+            log::error!("Tried to instantiate prefab {name} which isn't in the library");
+            return None;
+        };
+        Some(prefab.instantiate(scene, parent_id, transform, overrides))
+    }
+}
@@ -0,0 +1,135 @@
+use crate::renderer::{BaseRenderer, BindedSkybox, RendererConstantData};
+use crate::texture::Texture;
+
+use glam::f32::Vec3;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ProceduralSkyModel {
+    /// Blends from the horizon color up to the zenith color, with a glow around the sun. The
+    /// ground colors multiply the brightness of the horizon
+    Gradient {
+        zenith_color: Vec3,
+        horizon_color: Vec3,
+        ground_color: Vec3,
+    },
+    /// Preetham's analytic model of the Rayleigh and Mie scattering of daylight
+    Physical { ground_color: Vec3 },
+}
+
+/// A sky computed from the sun direction instead of loaded from images. Bind it again when the
+/// sun moves, e.g. every few in-game minutes with TimeOfDay::sun_direction, so the image based
+/// lighting follows the sky
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ProceduralSky {
+    pub model: ProceduralSkyModel,
+    /// unit vector pointing from the ground towards the sun
+    pub sun_direction: Vec3,
+    /// haziness of the air, from 2 for a clear sky to 10 or more for a hazy one
+    pub turbidity: f32,
+    /// radiance of the sun disk, also scales the glow of the gradient model
+    pub sun_intensity: f32,
+    /// in radians
+    pub sun_angular_radius: f32,
+    /// multiplies the radiance of the sky, the physical model is in kcd/m²
+    pub exposure: f32,
+    /// resolution of the cubemap faces in pixels
+    pub face_size: u32,
+}
+
+impl Default for ProceduralSky {
+    fn default() -> Self {
+        Self {
+            model: ProceduralSkyModel::Physical {
+                ground_color: Vec3::new(0.3, 0.28, 0.25),
+            },
+            sun_direction: Vec3::new(0.5, 0.5, 0.0).normalize(),
+            turbidity: 3.0,
+            sun_intensity: 50.0,
+            sun_angular_radius: 0.01,
+            exposure: 0.1,
+            face_size: 256,
+        }
+    }
+}
+
+impl ProceduralSky {
+    /// The background is an hdr cubemap of the sky and the diffuse and specular environment
+    /// maps are filtered from the same sky. Set it with Renderer::set_skybox
+    pub fn bind(
+        &self,
+        base_renderer: &BaseRenderer,
+        renderer_constant_data: &RendererConstantData,
+    ) -> BindedSkybox {
+        let environment = Texture::create_cubemap_from_procedural_sky(
+            base_renderer,
+            renderer_constant_data,
+            None,
+            self,
+            false,
+        );
+        BindedSkybox {
+            background: Texture::create_cubemap_from_procedural_sky(
+                base_renderer,
+                renderer_constant_data,
+                Some("procedural_sky_texture"),
+                self,
+                true,
+            ),
+            diffuse_environment_map: Texture::create_diffuse_env_map(
+                base_renderer,
+                renderer_constant_data,
+                Some("diffuse env map"),
+                &environment,
+            ),
+            specular_environment_map: Texture::create_specular_env_map(
+                base_renderer,
+                renderer_constant_data,
+                Some("specular env map"),
+                &environment,
+            ),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ProceduralSkyUniform {
+    // xyz, turbidity in w
+    sun_direction: [f32; 4],
+    // model in w: 0 for the gradient and 1 for the physical model
+    zenith_color: [f32; 4],
+    // sun intensity in w
+    horizon_color: [f32; 4],
+    // exposure in w
+    ground_color: [f32; 4],
+    // x: cos of the sun angular radius, y: 1 to draw the sun disk
+    sun_disk: [f32; 4],
+}
+
+impl ProceduralSkyUniform {
+    pub fn new(sky: &ProceduralSky, draw_sun_disk: bool) -> Self {
+        let (model, zenith_color, horizon_color, ground_color) = match sky.model {
+            ProceduralSkyModel::Gradient {
+                zenith_color,
+                horizon_color,
+                ground_color,
+            } => (0.0, zenith_color, horizon_color, ground_color),
+            ProceduralSkyModel::Physical { ground_color } => {
+                (1.0, Vec3::ZERO, Vec3::ZERO, ground_color)
+            }
+        };
+        let sun_direction = sky.sun_direction.normalize_or_zero();
+        Self {
+            sun_direction: sun_direction.extend(sky.turbidity.max(1.0)).to_array(),
+            zenith_color: zenith_color.extend(model).to_array(),
+            horizon_color: horizon_color.extend(sky.sun_intensity).to_array(),
+            ground_color: ground_color.extend(sky.exposure).to_array(),
+            sun_disk: [
+                sky.sun_angular_radius.cos(),
+                if draw_sun_disk { 1.0 } else { 0.0 },
+                0.0,
+                0.0,
+            ],
+        }
+    }
+}
@@ -0,0 +1,397 @@
+use crate::joints::JointBuilder;
+use crate::physics::*;
+use crate::scene::*;
+use crate::transform::*;
+
+use rapier3d_f64::prelude::*;
+
+use glam::f32::{Quat, Vec3};
+
+#[derive(Copy, Clone, Debug)]
+pub struct RagdollConfig {
+    pub density: f32,
+    /// bones whose bounding box is smaller than this on all axes (e.g. fingers) don't get a rigid body
+    /// and simply follow their parent
+    pub min_bone_half_extent: f32,
+    /// max rotation of a bone relative to its parent around each axis, in radians
+    pub max_joint_angle: f32,
+    pub linear_damping: f32,
+    pub angular_damping: f32,
+    pub recovery_duration_seconds: f32,
+}
+
+impl Default for RagdollConfig {
+    fn default() -> Self {
+        Self {
+            density: 1.0,
+            min_bone_half_extent: 0.02,
+            max_joint_angle: std::f32::consts::FRAC_PI_3,
+            linear_damping: 0.1,
+            angular_damping: 1.0,
+            recovery_duration_seconds: 0.5,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RagdollState {
+    /// the bodies follow the animated bones, so they can still be hit
+    Animated,
+    /// the bones follow the simulated bodies
+    Ragdoll,
+    /// blending from the last simulated pose back to the animation
+    Recovering { elapsed_seconds: f32 },
+}
+
+#[derive(Debug)]
+struct RagdollBone {
+    node_id: GameNodeId,
+    /// None if the bone is too small to get a body
+    rigid_body_handle: Option<RigidBodyHandle>,
+}
+
+/// Jointed rigid bodies built from a skin's bone hierarchy, one box per bone
+/// sized from the bone bounding boxes computed by the asset loader.
+///
+/// Each body has the same position and rotation as its bone so the simulated
+/// poses can be copied straight back onto the bones.
+#[derive(Debug)]
+pub struct Ragdoll {
+    skin_index: usize,
+    bones: Vec<RagdollBone>,
+    /// bone indices sorted so that parents come before their children
+    bone_update_order: Vec<usize>,
+    joint_handles: Vec<ImpulseJointHandle>,
+    state: RagdollState,
+    /// local transforms of the bones when the recovery started
+    recovery_start_pose: Vec<Transform>,
+    config: RagdollConfig,
+}
+
+impl Ragdoll {
+    pub fn new(
+        scene: &Scene,
+        physics_state: &mut PhysicsState,
+        skin_index: usize,
+        config: RagdollConfig,
+    ) -> Option<Self> {
+        let skin = scene.skins.get(skin_index)?;
+
+        let mut bones = vec![];
+        for (bone_node_id, bone_bounding_box_transform) in skin
+            .bone_node_ids
+            .iter()
+            .copied()
+            .zip(skin.bone_bounding_box_transforms.iter())
+        {
+            let bone_global_transform = scene.get_global_transform_for_node(bone_node_id);
+            let bone_global_scale = bone_global_transform.scale();
+            let half_extents = (bone_bounding_box_transform.scale() * bone_global_scale).abs();
+
+            if half_extents.max_element() < config.min_bone_half_extent {
+                bones.push(RagdollBone {
+                    node_id: bone_node_id,
+                    rigid_body_handle: None,
+                });
+                continue;
+            }
+
+            let rigid_body = RigidBodyBuilder::kinematic_position_based()
+                .position(transform_to_isometry(&bone_global_transform))
+                .linear_damping(config.linear_damping as f64)
+                .angular_damping(config.angular_damping as f64)
+                .build();
+            let rigid_body_handle = physics_state.rigid_body_set.insert(rigid_body);
+
+            let box_center = bone_bounding_box_transform.position() * bone_global_scale;
+            let collider = ColliderBuilder::cuboid(
+                half_extents.x as f64,
+                half_extents.y as f64,
+                half_extents.z as f64,
+            )
+            .translation(vector![
+                box_center.x as f64,
+                box_center.y as f64,
+                box_center.z as f64
+            ])
+            .density(config.density as f64)
+            .build();
+            let collider_handle = physics_state.collider_set.insert_with_parent(
+                collider,
+                rigid_body_handle,
+                &mut physics_state.rigid_body_set,
+            );
+            physics_state.attach_collider_to_node(collider_handle, bone_node_id);
+
+            bones.push(RagdollBone {
+                node_id: bone_node_id,
+                rigid_body_handle: Some(rigid_body_handle),
+            });
+        }
+
+        let bone_depth = |bone_node_id: GameNodeId| {
+            std::iter::successors(scene.get_node(bone_node_id), |node| {
+                node.parent_id
+                    .and_then(|parent_id| scene.get_node(parent_id))
+            })
+            .count()
+        };
+        let mut bone_update_order: Vec<usize> = (0..bones.len()).collect();
+        bone_update_order.sort_by_key(|bone_index| bone_depth(bones[*bone_index].node_id));
+
+        let mut joint_handles = vec![];
+        for bone in &bones {
+            let Some(rigid_body_handle) = bone.rigid_body_handle else {
+                continue;
+            };
+            let Some(parent_rigid_body_handle) =
+                Self::find_parent_rigid_body(scene, &bones, bone.node_id)
+            else {
+                continue;
+            };
+
+            // the joint sits at the origin of the child bone, which is the default anchor
+            let max_joint_angle = config.max_joint_angle;
+            if let Some(joint_handle) = physics_state.add_joint(
+                parent_rigid_body_handle,
+                rigid_body_handle,
+                &JointBuilder::ball_socket().limits([-max_joint_angle, max_joint_angle]),
+            ) {
+                joint_handles.push(joint_handle);
+            }
+        }
+
+        Some(Self {
+            skin_index,
+            bones,
+            bone_update_order,
+            joint_handles,
+            state: RagdollState::Animated,
+            recovery_start_pose: vec![],
+            config,
+        })
+    }
+
+    /// the closest ancestor bone that has a body
+    fn find_parent_rigid_body(
+        scene: &Scene,
+        bones: &[RagdollBone],
+        bone_node_id: GameNodeId,
+    ) -> Option<RigidBodyHandle> {
+        let mut current_node_id = scene.get_node(bone_node_id)?.parent_id;
+        while let Some(node_id) = current_node_id {
+            let bone = bones.iter().find(|bone| bone.node_id == node_id)?;
+            if bone.rigid_body_handle.is_some() {
+                return bone.rigid_body_handle;
+            }
+            current_node_id = scene.get_node(node_id)?.parent_id;
+        }
+        None
+    }
+
+    pub fn skin_index(&self) -> usize {
+        self.skin_index
+    }
+
+    pub fn state(&self) -> RagdollState {
+        self.state
+    }
+
+    pub fn is_ragdoll(&self) -> bool {
+        self.state == RagdollState::Ragdoll
+    }
+
+    pub fn rigid_body_handles(&self) -> impl Iterator<Item = RigidBodyHandle> + '_ {
+        self.bones.iter().filter_map(|bone| bone.rigid_body_handle)
+    }
+
+    /// Hands the bones over to the simulation. The bodies keep the velocity they had
+    /// while following the animation, so the motion carries on naturally
+    pub fn enable_ragdoll(&mut self, physics_state: &mut PhysicsState) {
+        self.set_body_type(physics_state, RigidBodyType::Dynamic);
+        self.state = RagdollState::Ragdoll;
+    }
+
+    /// Applies an impulse to the body of the given bone, e.g. where a bullet hit
+    pub fn apply_impulse(
+        &self,
+        physics_state: &mut PhysicsState,
+        bone_node_id: GameNodeId,
+        impulse: Vec3,
+    ) {
+        let rigid_body = self
+            .bones
+            .iter()
+            .find(|bone| bone.node_id == bone_node_id)
+            .and_then(|bone| bone.rigid_body_handle)
+            .and_then(|handle| physics_state.rigid_body_set.get_mut(handle));
+        if let Some(rigid_body) = rigid_body {
+            rigid_body.apply_impulse(
+                vector![impulse.x as f64, impulse.y as f64, impulse.z as f64],
+                true,
+            );
+        }
+    }
+
+    /// Blends from the current simulated pose back to the animation over recovery_duration_seconds.
+    /// The character's root node should usually be moved to where the ragdoll landed beforehand,
+    /// see root_bone_position
+    pub fn start_recovery(&mut self, scene: &Scene, physics_state: &mut PhysicsState) {
+        if self.state != RagdollState::Ragdoll {
+            return;
+        }
+        self.recovery_start_pose = self
+            .bones
+            .iter()
+            .map(|bone| {
+                scene
+                    .get_node(bone.node_id)
+                    .map(|node| node.transform)
+                    .unwrap_or(Transform::IDENTITY)
+            })
+            .collect();
+        self.set_body_type(physics_state, RigidBodyType::KinematicPositionBased);
+        self.state = RagdollState::Recovering {
+            elapsed_seconds: 0.0,
+        };
+    }
+
+    /// world position of the top-most bone that has a body, usually the hips
+    pub fn root_bone_position(&self, physics_state: &PhysicsState) -> Option<Vec3> {
+        let rigid_body_handle = self
+            .bone_update_order
+            .iter()
+            .find_map(|bone_index| self.bones[*bone_index].rigid_body_handle)?;
+        let translation = physics_state
+            .rigid_body_set
+            .get(rigid_body_handle)?
+            .translation();
+        Some(Vec3::new(
+            translation.x as f32,
+            translation.y as f32,
+            translation.z as f32,
+        ))
+    }
+
+    fn set_body_type(&self, physics_state: &mut PhysicsState, body_type: RigidBodyType) {
+        for rigid_body_handle in self.rigid_body_handles() {
+            if let Some(rigid_body) = physics_state.rigid_body_set.get_mut(rigid_body_handle) {
+                rigid_body.set_body_type(body_type, true);
+            }
+        }
+    }
+
+    /// Must be called every frame after the animations were stepped, since it
+    /// overrides the animated bone transforms while the ragdoll is active
+    pub fn update(
+        &mut self,
+        scene: &mut Scene,
+        physics_state: &mut PhysicsState,
+        delta_time_seconds: f32,
+    ) {
+        match self.state {
+            RagdollState::Animated => {
+                self.move_bodies_to_bones(scene, physics_state);
+            }
+            RagdollState::Ragdoll => {
+                self.move_bones_to_bodies(scene, physics_state);
+            }
+            RagdollState::Recovering { elapsed_seconds } => {
+                let elapsed_seconds = elapsed_seconds + delta_time_seconds;
+                let alpha = (elapsed_seconds / self.config.recovery_duration_seconds.max(0.001))
+                    .clamp(0.0, 1.0);
+                // smoothstep
+                let alpha = alpha * alpha * (3.0 - 2.0 * alpha);
+
+                for (bone, start_transform) in
+                    self.bones.iter().zip(self.recovery_start_pose.iter())
+                {
+                    if let Some(node) = scene.get_node_mut(bone.node_id) {
+                        node.transform = blend_transforms(start_transform, &node.transform, alpha);
+                    }
+                }
+                self.move_bodies_to_bones(scene, physics_state);
+
+                self.state = if alpha >= 1.0 {
+                    RagdollState::Animated
+                } else {
+                    RagdollState::Recovering { elapsed_seconds }
+                };
+            }
+        }
+    }
+
+    fn move_bodies_to_bones(&self, scene: &Scene, physics_state: &mut PhysicsState) {
+        for bone in &self.bones {
+            let Some(rigid_body) = bone
+                .rigid_body_handle
+                .and_then(|handle| physics_state.rigid_body_set.get_mut(handle))
+            else {
+                continue;
+            };
+            let bone_global_transform = scene.get_global_transform_for_node(bone.node_id);
+            rigid_body.set_next_kinematic_position(transform_to_isometry(&bone_global_transform));
+        }
+    }
+
+    fn move_bones_to_bodies(&self, scene: &mut Scene, physics_state: &PhysicsState) {
+        for bone_index in self.bone_update_order.iter().copied() {
+            let bone = &self.bones[bone_index];
+            let Some(rigid_body) = bone
+                .rigid_body_handle
+                .and_then(|handle| physics_state.rigid_body_set.get(handle))
+            else {
+                continue;
+            };
+            let Some(parent_id) = scene.get_node(bone.node_id).map(|node| node.parent_id) else {
+                continue;
+            };
+
+            // the parents are updated first so their global transforms are already up to date
+            let parent_global_transform = parent_id
+                .map(|parent_id| scene.get_global_transform_for_node(parent_id))
+                .unwrap_or(Transform::IDENTITY);
+            let mut new_global_transform = scene.get_global_transform_for_node(bone.node_id);
+            new_global_transform.apply_isometry(*rigid_body.position());
+            let new_local_transform =
+                Transform::from(parent_global_transform.inverse() * *new_global_transform);
+
+            if let Some(node) = scene.get_node_mut(bone.node_id) {
+                node.transform = new_local_transform;
+            }
+        }
+    }
+
+    pub fn remove(self, physics_state: &mut PhysicsState) {
+        for joint_handle in self.joint_handles.iter().copied() {
+            physics_state.impulse_joint_set.remove(joint_handle, false);
+        }
+        for rigid_body_handle in self.rigid_body_handles() {
+            physics_state.remove_rigid_body(rigid_body_handle);
+        }
+    }
+}
+
+pub(crate) fn transform_to_isometry(transform: &Transform) -> Isometry<Real> {
+    let position = transform.position();
+    let rotation = transform.rotation();
+    Isometry::from_parts(
+        nalgebra::Translation3::new(position.x as f64, position.y as f64, position.z as f64),
+        nalgebra::UnitQuaternion::from_quaternion(nalgebra::Quaternion::new(
+            rotation.w as f64,
+            rotation.x as f64,
+            rotation.y as f64,
+            rotation.z as f64,
+        )),
+    )
+}
+
+fn blend_transforms(from: &Transform, to: &Transform, alpha: f32) -> Transform {
+    let from = from.decompose();
+    let to = to.decompose();
+    TransformBuilder::new()
+        .position(from.position.lerp(to.position, alpha))
+        .rotation(Quat::slerp(from.rotation, to.rotation, alpha))
+        .scale(from.scale.lerp(to.scale, alpha))
+        .build()
+}
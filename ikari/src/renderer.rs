@@ -1,22 +1,38 @@
+use crate::asset_registry::*;
 use crate::buffer::*;
 use crate::camera::*;
+use crate::collider_generation::CollisionMesh;
 use crate::collisions::*;
+use crate::color_grading::*;
+use crate::custom_material::*;
 use crate::engine_state::EngineState;
 use crate::file_manager::GameFilePath;
+use crate::gpu_diagnostics::*;
+use crate::ibl_cache::IblCache;
 use crate::math::*;
 use crate::mesh::*;
+use crate::outline::Outlines;
+use crate::path_tracer::*;
 use crate::physics::rapier3d_f64::na::Vector3;
 use crate::physics::rapier3d_f64::prelude::*;
+use crate::point_shadow_atlas::PointShadowAtlas;
+use crate::post_process::*;
 use crate::sampler_cache::*;
 use crate::scene::*;
+use crate::sdf::SdfVolume;
+use crate::shadow_updates::*;
 use crate::skinning::*;
+use crate::taa::TemporalAntiAliasing;
 use crate::texture::*;
+use crate::time_control::TimeChannel;
 use crate::transform::*;
 use crate::ui::*;
 use crate::wasm_not_sync::WasmNotArc;
 
-use std::collections::{hash_map::Entry, HashMap};
+use std::collections::{hash_map::DefaultHasher, hash_map::Entry, HashMap};
+use std::hash::{Hash, Hasher};
 use std::num::NonZeroU64;
+use std::ops::Range;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::Mutex;
@@ -24,7 +40,7 @@ use std::sync::Mutex;
 use bitvec::prelude::*;
 
 use anyhow::Result;
-use glam::f32::{Mat4, Vec3};
+use glam::f32::{Mat4, Vec2, Vec3};
 use glam::Vec4;
 
 use rapier3d_f64::parry::query::PointQuery;
@@ -32,29 +48,37 @@ use smallvec::{smallvec, SmallVec};
 use wgpu::util::DeviceExt;
 
 pub(crate) const USE_LABELS: bool = true;
-pub(crate) const USE_ORTHOGRAPHIC_CAMERA: bool = false;
 pub(crate) const DISABLE_FRUSTUM_CULLING: bool = false;
 pub(crate) const USE_EXTRA_SHADOW_MAP_CULLING: bool = true;
 pub(crate) const DRAW_FRUSTUM_BOUNDING_SPHERE_FOR_SHADOW_MAPS: bool = false;
 pub(crate) const ENABLE_GRAPHICS_API_VALIDATION: bool = false;
 pub(crate) const PRESORT_INSTANCES_BY_MESH_MATERIAL: bool = false;
 
+/// of directional lights, there's no limit on the point lights
 pub const MAX_LIGHT_COUNT: usize = 32;
 pub const MAX_SHADOW_CASCADES: usize = 4;
-pub const NEAR_PLANE_DISTANCE: f32 = 0.001;
-pub const FAR_PLANE_DISTANCE: f32 = 100000.0;
-pub const FOV_Y_DEG: f32 = 45.0;
 pub const DEFAULT_WIREFRAME_COLOR: [f32; 4] = [0.0, 1.0, 1.0, 1.0];
 pub const POINT_LIGHT_SHADOW_MAP_FRUSTUM_NEAR_PLANE: f32 = 0.1;
 pub const POINT_LIGHT_SHADOW_MAP_FRUSTUM_FAR_PLANE: f32 = 1000.0;
-pub const POINT_LIGHT_SHADOW_MAP_RESOLUTION: u32 = 1024;
-pub const DIRECTIONAL_LIGHT_SHADOW_MAP_RESOLUTION: u32 = 2048;
-// pub const DIRECTIONAL_LIGHT_SHADOW_MAP_RESOLUTION: u32 = 512;
+/// of each face, see RendererData::point_shadow_map_resolution
+pub const DEFAULT_POINT_LIGHT_SHADOW_MAP_RESOLUTION: u32 = 1024;
+/// see RendererData::directional_shadow_map_resolution
+pub const DEFAULT_DIRECTIONAL_LIGHT_SHADOW_MAP_RESOLUTION: u32 = 2048;
+/// initial size of the point light shadow map array, see RendererData::max_point_light_shadow_maps
 pub const POINT_LIGHT_SHOW_MAP_COUNT: u32 = 2;
 pub const DIRECTIONAL_LIGHT_SHOW_MAP_COUNT: u32 = 2;
 pub const DIRECTIONAL_LIGHT_PROJ_BOX_LENGTH: f32 = 50.0;
 pub const MIN_SHADOW_MAP_BIAS: f32 = 0.00005;
 pub const NEW_BLOOM_MIP_LEVEL_COUNT: u32 = 5;
+pub const MAX_BLOB_SHADOW_COUNT: usize = 32;
+
+/// What a call to render_internal produces. SceneHdr is the shaded scene without any
+/// post-processing, for images that are drawn back into the scene like the portal views
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum FrameKind {
+    Final,
+    SceneHdr,
+}
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
@@ -70,31 +94,26 @@ struct PointLightUniform {
     color: [f32; 4],
 }
 
-impl Default for PointLightUniform {
-    fn default() -> Self {
-        Self {
-            position: [0.0, 0.0, 0.0, 1.0],
-            color: [0.0, 0.0, 0.0, 0.0],
-        }
-    }
-}
-
-fn make_point_light_uniform_buffer(engine_state: &EngineState) -> Vec<PointLightUniform> {
-    let mut light_uniforms = Vec::new();
-
-    let active_light_count = engine_state.scene.point_lights.len();
-    let mut active_lights = engine_state
+/// The light count followed by the lights, for the point lights storage buffer
+fn make_point_light_uniform_buffer(
+    engine_state: &EngineState,
+    shadow_layers: &[Option<u32>],
+) -> Vec<u8> {
+    let light_uniforms = engine_state
         .scene
         .point_lights
         .iter()
-        .flat_map(|point_light| {
+        .zip(shadow_layers)
+        .flat_map(|(point_light, shadow_layer)| {
             engine_state
                 .scene
                 .get_node(point_light.node_id)
                 .map(|light_node| {
                     let position = light_node.transform.position();
+                    // -1 for the lights without a shadow map
+                    let shadow_layer = shadow_layer.map_or(-1.0, |layer| layer as f32);
                     PointLightUniform {
-                        position: [position.x, position.y, position.z, 1.0],
+                        position: [position.x, position.y, position.z, shadow_layer],
                         color: [
                             point_light.color.x,
                             point_light.color.y,
@@ -105,14 +124,11 @@ fn make_point_light_uniform_buffer(engine_state: &EngineState) -> Vec<PointLight
                 })
         })
         .collect::<Vec<_>>();
-    light_uniforms.append(&mut active_lights);
 
-    let mut inactive_lights = (0..(MAX_LIGHT_COUNT - active_light_count))
-        .map(|_| PointLightUniform::default())
-        .collect::<Vec<_>>();
-    light_uniforms.append(&mut inactive_lights);
+    let mut result_bytes = bytemuck::cast_slice(&[light_uniforms.len() as u32, 0, 0, 0]).to_vec();
+    result_bytes.extend_from_slice(bytemuck::cast_slice(&light_uniforms));
 
-    light_uniforms
+    result_bytes
 }
 
 #[repr(C)]
@@ -122,6 +138,25 @@ struct PbrShaderOptionsUniform {
     options_2: [f32; 4],
     options_3: [f32; 4],
     options_4: [f32; 4],
+    options_5: [f32; 4],
+    options_6: [f32; 4],
+    options_7: [f32; 4],
+    // bottom center xyz, radius
+    blob_shadows: [[f32; 4]; MAX_BLOB_SHADOW_COUNT],
+}
+
+/// globals shared by all the pipelines, see FrameConstants in the shaders
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Default)]
+struct FrameConstantsUniform {
+    time_seconds: f32,
+    delta_time_seconds: f32,
+    frame_index: u32,
+    _padding: u32,
+    // width, height, 1 / width, 1 / height
+    render_resolution: [f32; 4],
+    // xy in pixels, zw in clip space
+    jitter: [f32; 4],
 }
 
 #[repr(C)]
@@ -183,15 +218,26 @@ impl CascadeProjectionVolume {
         transform
     }
 
+    /// the projection is offset by a fraction of a texel so the world origin always lands exactly
+    /// on a texel of the shadow map, which keeps the shadows from shimmering as the camera moves
     pub fn shader_orthographic_projection(&self) -> ShaderCameraData {
-        ShaderCameraData::orthographic(
+        let mut camera_data = ShaderCameraData::orthographic(
             look_in_dir(self.center, self.direction),
             self.half_thickness * 2.0,
             self.half_thickness * 2.0,
             -self.half_depth,
             self.half_depth,
             false,
-        )
+        );
+
+        let half_resolution = self.half_thickness / self.pixel_size;
+        let origin_texel_coords =
+            (camera_data.proj * camera_data.view).transform_point3(Vec3::ZERO) * half_resolution;
+        let texel_offset = (origin_texel_coords.round() - origin_texel_coords) / half_resolution;
+        camera_data.proj.w_axis.x += texel_offset.x;
+        camera_data.proj.w_axis.y += texel_offset.y;
+
+        camera_data
     }
 }
 
@@ -209,27 +255,24 @@ struct DirectionalLightCascadeUniform {
     world_space_to_light_space: [[f32; 4]; 4],
     frustum_slice_far_distance: f32,
     pixel_size: f32,
-    _padding: [f32; 2],
+    /// distance between the near and far planes in world units, for the PCSS penumbra size
+    depth_range: f32,
+    _padding: f32,
 }
 
 impl DirectionalLightCascadeUniform {
-    pub fn new(resolved_cascade: ResolvedDirectionalLightCascade, light_direction: Vec3) -> Self {
+    pub fn new(resolved_cascade: ResolvedDirectionalLightCascade) -> Self {
         let projection_volume = resolved_cascade.projection_volume;
 
-        let shader_camera_data = ShaderCameraData::orthographic(
-            look_in_dir(projection_volume.center, light_direction),
-            projection_volume.half_thickness * 2.0,
-            projection_volume.half_thickness * 2.0,
-            -projection_volume.half_depth,
-            projection_volume.half_depth,
-            false,
-        );
+        // must match the projection used when rendering the shadow map
+        let shader_camera_data = projection_volume.shader_orthographic_projection();
 
         Self {
             world_space_to_light_space: (shader_camera_data.proj * shader_camera_data.view)
                 .to_cols_array_2d(),
             frustum_slice_far_distance: resolved_cascade.frustum_slice_far_distance,
             pixel_size: projection_volume.pixel_size,
+            depth_range: projection_volume.half_depth * 2.0,
             _padding: Default::default(),
         }
     }
@@ -257,12 +300,9 @@ fn make_directional_light_uniform_buffer(
     tmp_cascade_distances.reserve_exact(MAX_SHADOW_CASCADES);
 
     for light_index in 0..active_light_count {
-        let light_direction = lights[light_index].direction;
-
         for i in 0..all_resolved_cascades[light_index].len() {
             tmp_cascade_distances.push(DirectionalLightCascadeUniform::new(
                 all_resolved_cascades[light_index][i],
-                light_direction,
             ));
         }
 
@@ -325,6 +365,7 @@ impl std::fmt::Display for CullingFrustumLockMode {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn make_pbr_shader_options_uniform_buffer(
     enable_soft_shadows: bool,
     shadow_bias: f32,
@@ -332,6 +373,12 @@ fn make_pbr_shader_options_uniform_buffer(
     enable_shadow_debug: bool,
     enable_cascade_debug: bool,
     soft_shadow_grid_dims: u32,
+    sdf_shadows: Option<SdfShadowShaderParams>,
+    enable_point_shadows: bool,
+    blob_shadows: &BlobShadowShaderParams,
+    shadow_normal_offset: f32,
+    shadow_filter: &ShadowFilter,
+    parallax_max_step_count: u32,
 ) -> PbrShaderOptionsUniform {
     let options_1 = [
         if enable_soft_shadows { 1.0 } else { 0.0 },
@@ -343,15 +390,159 @@ fn make_pbr_shader_options_uniform_buffer(
     let options_2 = [
         shadow_bias,
         if enable_cascade_debug { 1.0 } else { 0.0 },
-        0.0,
-        0.0,
+        // a softness of 0 disables the sdf shadows in the shader
+        sdf_shadows.map(|params| params.softness).unwrap_or(0.0),
+        sdf_shadows.map(|params| params.ao_strength).unwrap_or(0.0),
     ];
 
+    let (options_3, options_4) = match sdf_shadows {
+        Some(params) => (
+            [
+                params.origin.x,
+                params.origin.y,
+                params.origin.z,
+                params.voxel_size,
+            ],
+            [
+                params.dims.0 as f32,
+                params.dims.1 as f32,
+                params.dims.2 as f32,
+                shadow_normal_offset,
+            ],
+        ),
+        None => (Default::default(), [0.0, 0.0, 0.0, shadow_normal_offset]),
+    };
+
+    let options_5 = [
+        if enable_point_shadows { 1.0 } else { 0.0 },
+        blob_shadows.blobs.len() as f32,
+        blob_shadows.fade_height,
+        blob_shadows.opacity,
+    ];
+
+    let options_6 = [
+        match shadow_filter.mode {
+            ShadowFilterMode::Jittered => 0.0,
+            ShadowFilterMode::Pcf => 1.0,
+            ShadowFilterMode::Pcss => 2.0,
+        },
+        shadow_filter.pcf_kernel_radius,
+        shadow_filter.pcf_sample_count as f32,
+        if shadow_filter.enable_poisson_rotation {
+            1.0
+        } else {
+            0.0
+        },
+    ];
+
+    let options_7 = [
+        shadow_filter.pcss_directional_light_angular_size,
+        shadow_filter.pcss_point_light_radius,
+        shadow_filter.pcss_max_kernel_radius,
+        parallax_max_step_count as f32,
+    ];
+
+    let mut blob_shadow_uniforms = [[0.0; 4]; MAX_BLOB_SHADOW_COUNT];
+    for (blob_uniform, blob) in blob_shadow_uniforms
+        .iter_mut()
+        .zip(blob_shadows.blobs.iter())
+    {
+        *blob_uniform = *blob;
+    }
+
     PbrShaderOptionsUniform {
         options_1,
         options_2,
-        ..Default::default()
+        options_3,
+        options_4,
+        options_5,
+        options_6,
+        options_7,
+        blob_shadows: blob_shadow_uniforms,
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct BlobShadowShaderParams {
+    // bottom center xyz, radius
+    blobs: Vec<[f32; 4]>,
+    fade_height: f32,
+    opacity: f32,
+}
+
+/// Only the casters closest to the camera are kept if there are more than MAX_BLOB_SHADOW_COUNT
+fn make_blob_shadow_shader_params(
+    engine_state: &EngineState,
+    data: &RendererData,
+) -> BlobShadowShaderParams {
+    let scene = &engine_state.scene;
+
+    let camera_position = data
+        .camera_node_id
+        .filter(|camera_node_id| scene.get_node(*camera_node_id).is_some())
+        .map(|camera_node_id| {
+            scene
+                .get_global_transform_for_node(camera_node_id)
+                .position()
+        });
+
+    let mut blobs: Vec<[f32; 4]> = data
+        .blob_shadow_casters
+        .iter()
+        .filter_map(|caster| {
+            let node = scene.get_node(caster.node_id)?;
+            let global_transform = scene.get_global_transform_for_node(caster.node_id);
+
+            let (bottom_center, footprint_radius) = match node.visual.as_ref() {
+                Some(visual) => {
+                    let global_transform_mat: Mat4 = global_transform.into();
+                    let world_bounding_box = crate::collisions::Aabb::make_from_points(
+                        data.binded_meshes[visual.mesh.index()]
+                            .bounding_box
+                            .vertices()
+                            .into_iter()
+                            .map(|vertex| global_transform_mat.transform_point3(vertex)),
+                    )?;
+                    let center = world_bounding_box.center();
+                    let half_size = world_bounding_box.size() / 2.0;
+                    (
+                        Vec3::new(center.x, world_bounding_box.min.y, center.z),
+                        half_size.x.max(half_size.z),
+                    )
+                }
+                // e.g. the root node of a character, assume its origin is at the feet
+                None => (global_transform.position(), 0.5),
+            };
+
+            let radius = caster.radius.unwrap_or(footprint_radius);
+            (radius > 0.0).then_some([bottom_center.x, bottom_center.y, bottom_center.z, radius])
+        })
+        .collect();
+
+    if blobs.len() > MAX_BLOB_SHADOW_COUNT {
+        if let Some(camera_position) = camera_position {
+            let distance_to_camera = |blob: &[f32; 4]| {
+                Vec3::new(blob[0], blob[1], blob[2]).distance_squared(camera_position)
+            };
+            blobs.sort_by(|a, b| distance_to_camera(a).total_cmp(&distance_to_camera(b)));
+        }
+        blobs.truncate(MAX_BLOB_SHADOW_COUNT);
     }
+
+    BlobShadowShaderParams {
+        blobs,
+        fade_height: data.blob_shadow_fade_height.max(0.001),
+        opacity: data.blob_shadow_opacity,
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct SdfShadowShaderParams {
+    origin: Vec3,
+    voxel_size: f32,
+    dims: (u32, u32, u32),
+    softness: f32,
+    ao_strength: f32,
 }
 
 #[derive(Debug)]
@@ -372,6 +563,9 @@ pub struct BindablePbrMaterial {
 pub struct BindedPbrMaterial {
     pub textures_bind_group: WasmNotArc<wgpu::BindGroup>,
     pub dynamic_pbr_params: DynamicPbrParams,
+    /// indices into RendererData::textures for materials that come from a loaded scene,
+    /// used to recreate the bind group when the texture filtering changes
+    pub texture_indices: Option<IndexedPbrTextures>,
 }
 
 #[derive(Debug, Clone)]
@@ -387,6 +581,13 @@ impl BindableIndices {
             BindableIndices::U32(_) => wgpu::IndexFormat::Uint32,
         }
     }
+
+    pub fn to_u32_vec(&self) -> Vec<u32> {
+        match self {
+            BindableIndices::U16(indices) => indices.iter().map(|index| *index as u32).collect(),
+            BindableIndices::U32(indices) => indices.clone(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -394,6 +595,7 @@ pub struct BindableGeometryBuffers {
     pub vertices: Vec<Vertex>,
     pub indices: BindableIndices,
     pub bounding_box: crate::collisions::Aabb,
+    pub collision_mesh: Option<Arc<CollisionMesh>>,
 }
 
 #[derive(Debug)]
@@ -407,6 +609,8 @@ pub struct BindedGeometryBuffers {
     pub vertex_buffer: GpuBuffer,
     pub index_buffer: BindedIndexBuffer,
     pub bounding_box: crate::collisions::Aabb,
+    /// kept on the CPU for collider generation, see SceneAssetLoadParams::generate_colliders
+    pub collision_mesh: Option<Arc<CollisionMesh>>,
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -414,6 +618,7 @@ pub enum MaterialType {
     Pbr,
     Unlit,
     Transparent,
+    Custom,
 }
 
 impl From<Material> for MaterialType {
@@ -422,10 +627,19 @@ impl From<Material> for MaterialType {
             Material::Pbr { .. } => MaterialType::Pbr,
             Material::Unlit { .. } => MaterialType::Unlit,
             Material::Transparent { .. } => MaterialType::Transparent,
+            Material::Custom { .. } => MaterialType::Custom,
         }
     }
 }
 
+/// A chunk of RendererPrivateData::all_unlit_instances, drawn with the custom material's
+/// pipeline if there's one
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct UnlitInstancesKey {
+    pub custom_material: Option<(CustomMaterialId, Option<CustomMaterialDataId>)>,
+    pub mesh_index: usize,
+}
+
 #[derive(Debug)]
 pub struct BindableWireframeMesh {
     pub source_mesh_index: usize,
@@ -454,6 +668,8 @@ pub struct PointLight {
     pub node_id: GameNodeId,
     pub color: Vec3,
     pub intensity: f32,
+    /// can be changed at any time, the shadow map layer is given back when it's turned off
+    pub casts_shadows: bool,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -481,7 +697,28 @@ pub struct DirectionalLight {
     pub shadow_mapping_config: DirectionalLightShadowMappingConfig,
 }
 
+/// The highest tier of the wgpu default limits that the adapter supports, so that weaker
+/// adapters like some browsers' WebGPU implementation still work. The texture size limits are
+/// raised to the adapter's. There's no WebGL2 tier since the bone and instance buffers are
+/// storage buffers read in the vertex shaders, which WebGL2 doesn't have
+fn pick_limits(adapter_limits: &wgpu::Limits) -> Result<wgpu::Limits> {
+    let limits = [wgpu::Limits::default(), wgpu::Limits::downlevel_defaults()]
+        .into_iter()
+        .find(|limits| limits.check_limits(adapter_limits))
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "The graphics adapter's limits are too low, e.g. it only supports WebGL2"
+            )
+        })?;
+    if limits != wgpu::Limits::default() {
+        log::warn!("The graphics adapter doesn't support the default limits, using lower ones");
+    }
+    Ok(limits.using_resolution(adapter_limits.clone()))
+}
+
 pub struct BaseRenderer {
+    pub config: RendererConfig,
+    instance: WasmNotArc<wgpu::Instance>,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub adapter: wgpu::Adapter,
@@ -489,6 +726,7 @@ pub struct BaseRenderer {
     pub mip_pipeline_cache: Mutex<HashMap<wgpu::TextureFormat, WasmNotArc<wgpu::RenderPipeline>>>,
     default_texture_cache: Mutex<HashMap<DefaultTextureType, WasmNotArc<Texture>>>,
     pub sampler_cache: Mutex<SamplerCache>,
+    pub diagnostics: GpuDiagnostics,
 }
 
 pub struct SurfaceData {
@@ -496,23 +734,84 @@ pub struct SurfaceData {
     pub surface_config: wgpu::SurfaceConfiguration,
 }
 
+/// How the graphics device is picked and created
+#[derive(Debug, Clone)]
+pub struct RendererConfig {
+    pub backends: wgpu::Backends,
+    pub dxc_path: Option<PathBuf>,
+    pub power_preference: wgpu::PowerPreference,
+    /// one of the names from BaseRenderer::enumerate_adapters, e.g. picked in a settings menu.
+    /// Falls back to the power preference if it's missing or lacks the required features
+    pub adapter_name: Option<String>,
+    /// the renderer fails to initialize without these
+    pub required_features: wgpu::Features,
+    /// enabled if the adapter supports them, check BaseRenderer::device.features() for which are
+    pub optional_features: wgpu::Features,
+    /// keeps a CPU copy of the loaded meshes and textures so that they can be bound again if the
+    /// gpu device is lost, see Renderer::recreate_device
+    pub retain_cpu_data: bool,
+    /// the generated image based lighting maps are saved there and loaded on the next runs,
+    /// see IblCache
+    pub ibl_cache_folder: Option<PathBuf>,
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            backends: wgpu::Backends::PRIMARY,
+            dxc_path: None,
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            adapter_name: None,
+            // textures are never compressed on the web since there's no basis transcoder there
+            required_features: if cfg!(target_arch = "wasm32") {
+                wgpu::Features::empty()
+            } else {
+                wgpu::Features::TEXTURE_COMPRESSION_BC
+            },
+            // RG11B10UFLOAT_RENDERABLE uses half of the memory of a rgba16f texture, so it saves
+            // a nice chunk of VRAM for bloom effect without a big difference in visual quality
+            // it should be available "everywhere we would care about". see https://github.com/gpuweb/gpuweb/issues/3566
+            optional_features: wgpu_profiler::GpuProfiler::ALL_WGPU_TIMER_FEATURES
+                | wgpu::Features::RG11B10UFLOAT_RENDERABLE,
+            retain_cpu_data: false,
+            ibl_cache_folder: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdapterDescription {
+    pub name: String,
+    pub backend: wgpu::Backend,
+    pub device_type: wgpu::DeviceType,
+}
+
+impl From<wgpu::AdapterInfo> for AdapterDescription {
+    fn from(info: wgpu::AdapterInfo) -> Self {
+        Self {
+            name: info.name,
+            backend: info.backend,
+            device_type: info.device_type,
+        }
+    }
+}
+
 impl BaseRenderer {
-    pub async fn offscreen(backends: wgpu::Backends, dxc_path: Option<PathBuf>) -> Result<Self> {
-        let instance = Self::make_instance(backends, dxc_path);
-        Self::new(instance, None).await
+    pub async fn offscreen(config: &RendererConfig) -> Result<Self> {
+        let instance = WasmNotArc::new(Self::make_instance(config));
+        Self::new(instance, None, config).await
     }
 
     pub async fn with_window(
-        backends: wgpu::Backends,
-        dxc_path: Option<PathBuf>,
+        config: &RendererConfig,
         window: Arc<winit::window::Window>,
     ) -> Result<(Self, SurfaceData)> {
         let window_size = window.inner_size();
 
-        let instance = Self::make_instance(backends, dxc_path);
+        let instance = WasmNotArc::new(Self::make_instance(config));
         let surface = instance.create_surface(window).unwrap();
 
-        let base = Self::new(instance, Some(&surface)).await?;
+        let base = Self::new(instance, Some(&surface), config).await?;
 
         let mut surface_config = surface
             .get_default_config(&base.adapter, window_size.width, window_size.height)
@@ -544,12 +843,34 @@ impl BaseRenderer {
         Ok((base, surface_data))
     }
 
-    fn make_instance(backends: wgpu::Backends, dxc_path: Option<PathBuf>) -> wgpu::Instance {
+    /// The adapters that are available with these backends, always empty on the web where the
+    /// browser picks the adapter
+    pub fn enumerate_adapters(backends: wgpu::Backends) -> Vec<AdapterDescription> {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+                backends,
+                ..Default::default()
+            });
+            instance
+                .enumerate_adapters(backends)
+                .into_iter()
+                .map(|adapter| adapter.get_info().into())
+                .collect()
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            let _ = backends;
+            vec![]
+        }
+    }
+
+    fn make_instance(config: &RendererConfig) -> wgpu::Instance {
         wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends,
+            backends: config.backends,
             dx12_shader_compiler: wgpu::Dx12Compiler::Dxc {
-                dxil_path: dxc_path.clone(),
-                dxc_path,
+                dxil_path: config.dxc_path.clone(),
+                dxc_path: config.dxc_path.clone(),
             },
             flags: if ENABLE_GRAPHICS_API_VALIDATION {
                 wgpu::InstanceFlags::debugging()
@@ -560,44 +881,80 @@ impl BaseRenderer {
         })
     }
 
-    async fn new(instance: wgpu::Instance, surface: Option<&wgpu::Surface<'_>>) -> Result<Self> {
+    /// The adapter with the configured name if it's usable, otherwise the one wgpu picks
+    async fn pick_adapter(
+        instance: &wgpu::Instance,
+        surface: Option<&wgpu::Surface<'_>>,
+        config: &RendererConfig,
+    ) -> Result<wgpu::Adapter> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(adapter_name) = &config.adapter_name {
+            let named_adapter = instance
+                .enumerate_adapters(config.backends)
+                .into_iter()
+                .find(|adapter| adapter.get_info().name == *adapter_name);
+            match named_adapter {
+                Some(adapter)
+                    if surface.map_or(false, |surface| !adapter.is_surface_supported(surface)) =>
+                {
+                    log::warn!(
+                        "Adapter {adapter_name} can't present to the window, using the default one"
+                    );
+                }
+                Some(adapter) if !adapter.features().contains(config.required_features) => {
+                    log::warn!(
+                        "Adapter {adapter_name} is missing features {:?}, using the default one",
+                        config.required_features - adapter.features()
+                    );
+                }
+                Some(adapter) => return Ok(adapter),
+                None => {
+                    log::warn!(
+                        "There's no adapter named {adapter_name}, using the default adapter"
+                    );
+                }
+            }
+        }
+
         let request_adapter_options = wgpu::RequestAdapterOptions {
-            power_preference: wgpu::PowerPreference::HighPerformance,
+            power_preference: config.power_preference,
             compatible_surface: surface,
             force_fallback_adapter: false,
         };
-        let adapter = instance
+        instance
             .request_adapter(&request_adapter_options)
             .await
             .ok_or_else(|| {
                 anyhow::anyhow!(
                     "Failed to find a wgpu adapter with options: {request_adapter_options:?}"
                 )
-            })?;
-
-        let mut optional_features = wgpu::Features::empty();
-
-        // used by wgpu_profiler
-        optional_features |= wgpu_profiler::GpuProfiler::ALL_WGPU_TIMER_FEATURES;
-
-        // uses half of the memory of a rgba16f texture, so it saves a nice chunk of VRAM for bloom effect
-        // without a big difference in visual quality
-        // it should be available "everywhere we would care about". see https://github.com/gpuweb/gpuweb/issues/3566
-        optional_features |= wgpu::Features::RG11B10UFLOAT_RENDERABLE;
+            })
+    }
 
-        // panic if these features are missing
-        let mut required_features = wgpu::Features::empty();
+    async fn new(
+        instance: WasmNotArc<wgpu::Instance>,
+        surface: Option<&wgpu::Surface<'_>>,
+        config: &RendererConfig,
+    ) -> Result<Self> {
+        let adapter = Self::pick_adapter(&instance, surface, config).await?;
 
-        required_features |= wgpu::Features::TEXTURE_COMPRESSION_BC;
+        let missing_features = config.required_features - adapter.features();
+        if !missing_features.is_empty() {
+            anyhow::bail!(
+                "Graphics adapter {:?} is missing the required features {missing_features:?}",
+                adapter.get_info().name
+            );
+        }
 
-        let features = (adapter.features() & optional_features) | required_features;
+        let features = (adapter.features() & config.optional_features) | config.required_features;
+        let required_limits = pick_limits(&adapter.limits())?;
 
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
                     required_features: features,
-                    required_limits: Default::default(),
+                    required_limits,
                 },
                 None,
             )
@@ -612,17 +969,29 @@ impl BaseRenderer {
 
         let limits = device.limits();
 
+        let diagnostics = GpuDiagnostics::new(&adapter, &device);
+        diagnostics.install_error_handler(&device);
+
         Ok(Self {
+            config: config.clone(),
+            instance,
             device,
             adapter,
             queue,
             limits,
+            diagnostics,
             mip_pipeline_cache: Mutex::new(HashMap::new()),
             default_texture_cache: Mutex::new(HashMap::new()),
             sampler_cache: Mutex::new(SamplerCache::default()),
         })
     }
 
+    /// Set once the device is lost, e.g. after a driver reset. Nothing can be rendered anymore
+    /// until Renderer::recreate_device is called, which gameloop does automatically
+    pub fn is_device_lost(&self) -> bool {
+        self.diagnostics.is_device_lost()
+    }
+
     pub fn get_default_texture(
         &self,
         default_texture_type: DefaultTextureType,
@@ -664,19 +1033,29 @@ pub struct RendererPrivateData {
     all_bone_transforms: AllBoneTransforms,
     all_pbr_instances: ChunkedBuffer<GpuPbrMeshInstance, MeshMaterialIndexPair>,
     all_pbr_instances_culling_masks: Vec<BitVec>,
+    /// whether each chunk has instances with an alpha cutoff, drawn in the shadow maps with the
+    /// pipelines that sample the base color
+    all_pbr_instances_alpha_masked: Vec<bool>,
     pbr_mesh_index_to_gpu_instances:
         HashMap<MeshMaterialIndexPair, (SmallVec<[GpuPbrMeshInstance; 1]>, BitVec, f32)>,
-    all_unlit_instances: ChunkedBuffer<GpuUnlitMeshInstance, usize>,
+    all_unlit_instances: ChunkedBuffer<GpuUnlitMeshInstance, UnlitInstancesKey>,
     all_transparent_instances: ChunkedBuffer<GpuUnlitMeshInstance, usize>,
     all_wireframe_instances: ChunkedBuffer<GpuWireframeMeshInstance, usize>,
     debug_node_bounding_spheres_nodes: Vec<GameNodeId>,
     debug_culling_frustum_nodes: Vec<GameNodeId>,
-    debug_culling_frustum_mesh_index: Option<usize>,
+    debug_culling_frustum_mesh: Option<MeshHandle>,
 
     bloom_threshold_cleared: bool,
     new_bloom_cleared: bool,
     frustum_culling_lock: CullingFrustumLock, // for debug
     skybox_weights: [f32; 2],
+    frame_index: u32,
+    point_light_casters_fingerprints: Vec<u64>,
+    /// which point light shadow maps are rendered this frame, see ShadowUpdates
+    point_light_shadow_updates: Vec<bool>,
+    point_shadow_atlas: PointShadowAtlas,
+    /// the layer of point_shadow_map_textures of each point light
+    point_light_shadow_layers: Vec<Option<u32>>,
 
     // gpu
     camera_lights_and_pbr_shader_options_bind_group_layout: wgpu::BindGroupLayout,
@@ -690,6 +1069,7 @@ pub struct RendererPrivateData {
     new_bloom_downscale_config_bind_groups: Vec<wgpu::BindGroup>,
     new_bloom_upscale_config_bind_group: wgpu::BindGroup,
     tone_mapping_config_bind_group: wgpu::BindGroup,
+    frame_constants_bind_group: wgpu::BindGroup,
     environment_textures_bind_group: wgpu::BindGroup,
     shading_and_bloom_textures_bind_group: wgpu::BindGroup,
     shading_and_new_bloom_texture_bind_group: wgpu::BindGroup,
@@ -700,9 +1080,11 @@ pub struct RendererPrivateData {
     new_bloom_texture_mip_bind_groups: Vec<wgpu::BindGroup>,
 
     camera_buffers: Vec<wgpu::Buffer>,
-    point_lights_buffer: wgpu::Buffer,
+    /// grows with the number of point lights, the bind groups that use it are recreated then
+    point_lights_buffer: GpuBuffer,
     directional_lights_buffer: wgpu::Buffer,
     pbr_shader_options_buffer: wgpu::Buffer,
+    frame_constants_buffer: wgpu::Buffer,
     bloom_config_buffers: [wgpu::Buffer; 2],
     new_bloom_downscale_config_buffers: Vec<wgpu::Buffer>,
     new_bloom_upscale_config_buffer: wgpu::Buffer,
@@ -721,11 +1103,17 @@ pub struct RendererPrivateData {
 
     shading_texture: Texture,
     tone_mapping_texture: Texture,
+    /// written by the post-process passes and copied back to their input
+    post_process_texture: Texture,
     depth_texture: Texture,
     bloom_pingpong_textures: [Texture; 2],
     new_bloom_texture: Texture,
     new_bloom_texture_mip_views: Vec<wgpu::TextureView>,
     brdf_lut: Texture,
+
+    sdf_volume_texture: Texture,
+    /// (origin, voxel size, dims) of the volume in sdf_volume_texture
+    sdf_volume_info: Option<(Vec3, f32, (u32, u32, u32))>,
 }
 
 #[derive(Debug)]
@@ -742,6 +1130,229 @@ pub struct BindedSceneData {
     pub binded_wireframe_meshes: Vec<BindedWireframeMesh>,
     pub binded_pbr_materials: Vec<BindedPbrMaterial>,
     pub textures: Vec<Texture>,
+    /// what the scene was binded from, see RendererConfig::retain_cpu_data
+    pub cpu_data: Option<BindableSceneData>,
+}
+
+/// CPU copies of the meshes and textures in RendererData, by the same indices.
+/// The slots that have no copy are replaced by placeholders when the device is recreated
+#[derive(Debug, Default)]
+pub struct RetainedCpuData {
+    meshes: Vec<Option<BindableGeometryBuffers>>,
+    wireframe_meshes: Vec<Option<BindableIndices>>,
+    textures: Vec<Option<BindableTexture>>,
+}
+
+impl RetainedCpuData {
+    /// The offsets are the lengths of the RendererData lists before the scene was added to them
+    pub(crate) fn add_scene(
+        &mut self,
+        mesh_index_offset: usize,
+        wireframe_mesh_index_offset: usize,
+        texture_index_offset: usize,
+        cpu_data: BindableSceneData,
+    ) {
+        self.meshes.resize_with(mesh_index_offset, || None);
+        self.meshes
+            .extend(cpu_data.bindable_meshes.into_iter().map(Some));
+        self.wireframe_meshes
+            .resize_with(wireframe_mesh_index_offset, || None);
+        self.wireframe_meshes.extend(
+            cpu_data
+                .bindable_wireframe_meshes
+                .into_iter()
+                .map(|wireframe_mesh| Some(wireframe_mesh.indices)),
+        );
+        self.textures.resize_with(texture_index_offset, || None);
+        self.textures
+            .extend(cpu_data.textures.into_iter().map(Some));
+    }
+
+    fn add_basic_mesh(
+        &mut self,
+        mesh_index: usize,
+        wireframe_mesh_index: Option<usize>,
+        mesh: &BasicMesh,
+        bounding_box: crate::collisions::Aabb,
+    ) {
+        self.meshes.resize_with(mesh_index, || None);
+        self.meshes.push(Some(BindableGeometryBuffers {
+            vertices: mesh.vertices.clone(),
+            indices: BindableIndices::U16(mesh.indices.clone()),
+            bounding_box,
+            collision_mesh: None,
+        }));
+        if let Some(wireframe_mesh_index) = wireframe_mesh_index {
+            self.wireframe_meshes
+                .resize_with(wireframe_mesh_index, || None);
+            self.wireframe_meshes
+                .push(Some(BindableIndices::U16(wireframe_indices(mesh))));
+        }
+    }
+
+    pub(crate) fn mesh(&self, mesh_index: usize) -> Option<&BindableGeometryBuffers> {
+        self.meshes.get(mesh_index)?.as_ref()
+    }
+
+    fn free(&mut self, resources: &BindedAssetResources) {
+        fn clear<T>(slots: &mut [Option<T>], range: &Range<usize>) {
+            for slot in slots.iter_mut().take(range.end).skip(range.start) {
+                *slot = None;
+            }
+        }
+        clear(&mut self.meshes, &resources.meshes);
+        clear(&mut self.wireframe_meshes, &resources.wireframe_meshes);
+        clear(&mut self.textures, &resources.textures);
+    }
+
+    /// Overwrites the copies of an asset's resources, which keep their indices
+    fn replace(&mut self, resources: &BindedAssetResources, cpu_data: BindableSceneData) {
+        fn replace_range<T>(slots: &mut Vec<Option<T>>, range: &Range<usize>, items: Vec<T>) {
+            if slots.len() < range.end {
+                slots.resize_with(range.end, || None);
+            }
+            for (slot, item) in slots[range.clone()].iter_mut().zip(items) {
+                *slot = Some(item);
+            }
+        }
+        replace_range(
+            &mut self.meshes,
+            &resources.meshes,
+            cpu_data.bindable_meshes,
+        );
+        replace_range(
+            &mut self.wireframe_meshes,
+            &resources.wireframe_meshes,
+            cpu_data
+                .bindable_wireframe_meshes
+                .into_iter()
+                .map(|wireframe_mesh| wireframe_mesh.indices)
+                .collect(),
+        );
+        replace_range(&mut self.textures, &resources.textures, cpu_data.textures);
+    }
+
+    fn truncate(&mut self, mesh_count: usize, wireframe_mesh_count: usize, texture_count: usize) {
+        self.meshes.truncate(mesh_count);
+        self.wireframe_meshes.truncate(wireframe_mesh_count);
+        self.textures.truncate(texture_count);
+    }
+}
+
+/// Identifies the meshes, materials and textures that a scene added to RendererData,
+/// see Scene::bind_render_buffers and Scene::unload_asset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BindedAssetId(usize);
+
+/// Ranges of indices into the RendererData lists
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BindedAssetResources {
+    pub meshes: Range<usize>,
+    pub wireframe_meshes: Range<usize>,
+    pub pbr_materials: Range<usize>,
+    pub textures: Range<usize>,
+}
+
+impl BindedAssetResources {
+    pub fn is_used_by(&self, visual: &GameNodeVisual) -> bool {
+        self.meshes.contains(&visual.mesh.index())
+            || matches!(
+                visual.material,
+                Material::Pbr { binded_material, .. }
+                    if self.pbr_materials.contains(&binded_material.index())
+            )
+    }
+}
+
+#[derive(Debug)]
+struct BindedAsset {
+    resources: BindedAssetResources,
+    /// the asset is freed when the last reference is released, see BindedAssets::retain
+    reference_count: usize,
+}
+
+/// The resources of each binded asset. The ones of the unloaded assets stay in the RendererData
+/// lists, with their gpu memory freed, until everything that was added after them is gone too
+/// since the indices of the other resources can't change
+#[derive(Debug, Default)]
+pub struct BindedAssets {
+    assets: Vec<Option<BindedAsset>>,
+    freed: Vec<BindedAssetResources>,
+}
+
+impl BindedAssets {
+    /// The asset starts with a single reference, held by the caller
+    pub(crate) fn add(&mut self, resources: BindedAssetResources) -> BindedAssetId {
+        // ids aren't reused so that a stale one can't unload another asset
+        self.assets.push(Some(BindedAsset {
+            resources,
+            reference_count: 1,
+        }));
+        BindedAssetId(self.assets.len() - 1)
+    }
+
+    pub(crate) fn get(&self, asset_id: BindedAssetId) -> Option<&BindedAssetResources> {
+        self.get_asset(asset_id).map(|asset| &asset.resources)
+    }
+
+    fn get_asset(&self, asset_id: BindedAssetId) -> Option<&BindedAsset> {
+        self.assets.get(asset_id.0).and_then(Option::as_ref)
+    }
+
+    /// Adds a reference for another owner of the asset, e.g. something that keeps using the
+    /// meshes of a prefab. Each reference must be given back with Scene::unload_asset
+    pub fn retain(&mut self, asset_id: BindedAssetId) -> Result<()> {
+        let Some(asset) = self.assets.get_mut(asset_id.0).and_then(Option::as_mut) else {
+            anyhow::bail!("The asset {asset_id:?} isn't loaded");
+        };
+        asset.reference_count += 1;
+        Ok(())
+    }
+
+    /// None once the asset is unloaded
+    pub fn reference_count(&self, asset_id: BindedAssetId) -> Option<usize> {
+        self.get_asset(asset_id).map(|asset| asset.reference_count)
+    }
+
+    /// Returns true when that was the last reference, the asset must then be freed
+    pub(crate) fn release(&mut self, asset_id: BindedAssetId) -> Result<bool> {
+        let Some(asset) = self.assets.get_mut(asset_id.0).and_then(Option::as_mut) else {
+            anyhow::bail!("The asset {asset_id:?} was already unloaded");
+        };
+        asset.reference_count = asset.reference_count.saturating_sub(1);
+        Ok(asset.reference_count == 0)
+    }
+
+    pub fn loaded_count(&self) -> usize {
+        self.assets.iter().flatten().count()
+    }
+}
+
+/// The length that a list can be truncated to once the freed ranges at its end are removed
+fn reclaimable_len(freed_ranges: impl Iterator<Item = Range<usize>>, len: usize) -> usize {
+    let freed_ranges: Vec<_> = freed_ranges.filter(|range| !range.is_empty()).collect();
+    let mut new_len = len;
+    while let Some(range) = freed_ranges.iter().find(|range| range.end == new_len) {
+        new_len = range.start;
+    }
+    new_len
+}
+
+/// the edges of each triangle of the mesh, as a line list
+fn wireframe_indices(mesh: &BasicMesh) -> Vec<u16> {
+    mesh.indices
+        .chunks(3)
+        .flat_map(|triangle| {
+            vec![
+                triangle[0],
+                triangle[1],
+                triangle[1],
+                triangle[2],
+                triangle[2],
+                triangle[0],
+            ]
+        })
+        .collect()
 }
 
 #[derive(Debug, Clone)]
@@ -860,6 +1471,71 @@ impl std::fmt::Display for BloomType {
     }
 }
 
+/// How the shadow maps are sampled when RendererData::enable_soft_shadows is on
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ShadowFilterMode {
+    /// jittered grid, see soft_shadow_factor and soft_shadow_grid_dims
+    Jittered,
+    /// percentage-closer filtering over a poisson disk
+    Pcf,
+    /// percentage-closer soft shadows, the penumbra widens with the distance to the caster
+    Pcss,
+}
+
+impl ShadowFilterMode {
+    pub const ALL: [ShadowFilterMode; 3] = [
+        ShadowFilterMode::Jittered,
+        ShadowFilterMode::Pcf,
+        ShadowFilterMode::Pcss,
+    ];
+}
+
+impl std::fmt::Display for ShadowFilterMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                ShadowFilterMode::Jittered => "Jittered",
+                ShadowFilterMode::Pcf => "PCF",
+                ShadowFilterMode::Pcss => "PCSS",
+            }
+        )
+    }
+}
+
+/// Applies to the directional and the point light shadows
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ShadowFilter {
+    pub mode: ShadowFilterMode,
+    /// in shadow map texels
+    pub pcf_kernel_radius: f32,
+    /// taps per shadow lookup, up to 16
+    pub pcf_sample_count: u32,
+    /// rotates the poisson disk per pixel, which turns the banding of the kernel into noise
+    pub enable_poisson_rotation: bool,
+    /// in radians, the sun is about 0.01 but a bigger size gives softer shadows
+    pub pcss_directional_light_angular_size: f32,
+    /// in world units
+    pub pcss_point_light_radius: f32,
+    /// caps the size of the penumbra in PCSS mode, in shadow map texels
+    pub pcss_max_kernel_radius: f32,
+}
+
+impl Default for ShadowFilter {
+    fn default() -> Self {
+        Self {
+            mode: ShadowFilterMode::Pcf,
+            pcf_kernel_radius: 1.5,
+            pcf_sample_count: 16,
+            enable_poisson_rotation: true,
+            pcss_directional_light_angular_size: 0.05,
+            pcss_point_light_radius: 0.2,
+            pcss_max_kernel_radius: 12.0,
+        }
+    }
+}
+
 pub struct RendererData {
     pub binded_meshes: Vec<BindedGeometryBuffers>,
     pub binded_wireframe_meshes: Vec<BindedWireframeMesh>,
@@ -867,6 +1543,8 @@ pub struct RendererData {
     pub textures: Vec<Texture>,
 
     pub tone_mapping_exposure: f32,
+    /// scales the skybox and the image based lighting, e.g. to darken them at night
+    pub environment_intensity: f32,
     pub bloom_threshold: f32,
     pub bloom_ramp_size: f32,
     pub new_bloom_radius: f32,
@@ -883,11 +1561,233 @@ pub struct RendererData {
     pub draw_directional_light_culling_frusta: bool,
     pub enable_soft_shadows: bool,
     pub shadow_bias: f32,
+    /// how far the shadow receivers are pushed out along their normal before sampling the directional
+    /// shadow maps, in shadow map texels. fixes acne on surfaces at grazing angles to the light
+    pub shadow_normal_offset: f32,
+    /// pulls the near plane of each shadow cascade in to the closest shadow caster that can affect it
+    pub enable_shadow_depth_fitting: bool,
     pub soft_shadow_factor: f32,
     pub enable_shadow_debug: bool,
     pub enable_cascade_debug: bool,
     pub soft_shadow_grid_dims: u32,
+    pub shadow_filter: ShadowFilter,
+    pub shadow_updates: ShadowUpdates,
+    /// the point light shadow map array grows up to this many layers as shadow casting lights
+    /// are added, the lights past it have no shadows. each layer takes 6 faces of
+    /// point_shadow_map_resolution²
+    pub max_point_light_shadow_maps: u32,
+    /// side length of each face of the point light shadow maps, the array is made again
+    /// when it changes. capped by the device's max texture size
+    pub point_shadow_map_resolution: u32,
+    /// side length of each cascade of the directional light shadow maps, the array is made
+    /// again when it changes
+    pub directional_shadow_map_resolution: u32,
+    /// quality of the parallax occlusion mapping, see DynamicPbrParams::parallax_height_scale.
+    /// 0 turns it off for all the materials
+    pub parallax_max_step_count: u32,
+    /// only has an effect once a volume was set with `Renderer::set_sdf_volume`
+    pub enable_sdf_shadows: bool,
+    pub sdf_shadow_softness: f32,
+    pub sdf_ao_strength: f32,
+    /// cheap stand-in for the point light shadows, drawn under the casters
+    /// whenever enable_shadows is false, e.g. on low-spec hardware
+    pub enable_blob_shadows: bool,
+    pub blob_shadow_casters: Vec<BlobShadowCaster>,
+    pub blob_shadow_opacity: f32,
+    /// height of the caster above the ground at which its blob shadow has completely faded out
+    pub blob_shadow_fade_height: f32,
+    /// plane equations applied to the main camera view, see make_clip_plane.
+    /// useful for cutaway views or for rendering only what's above/below a water surface
+    pub clip_planes: Vec<Vec4>,
+    /// subpixel offset applied to the main camera's projection, in pixels of the render resolution.
+    /// meant to be changed every frame by temporal effects, it's also exposed to the shaders
+    pub camera_jitter: Vec2,
     pub camera_node_id: Option<GameNodeId>,
+    /// projection of the main camera, see CameraSystem::active_lens for per-camera lenses
+    pub camera_lens: CameraLens,
+    /// the layers seen by the main camera, see GameNode::render_layers and
+    /// CameraSystem::active_render_layers for per-camera masks
+    pub camera_render_layers: RenderLayers,
+    pub(crate) retained_cpu_data: RetainedCpuData,
+    pub binded_assets: BindedAssets,
+    pub asset_registry: AssetRegistry,
+    pub custom_materials: CustomMaterials,
+    pub post_process_passes: PostProcessPasses,
+    pub color_grading: ColorGrading,
+    pub outlines: Outlines,
+    pub path_tracer: PathTracer,
+    pub taa: TemporalAntiAliasing,
+}
+
+impl RendererData {
+    /// Swaps the resources of a binded asset for the ones of a new version of it, e.g. after its
+    /// file changed on disk. The nodes keep pointing at the same indices so the new version
+    /// must have as many meshes, materials and textures as the old one, in the same order
+    pub fn replace_binded_asset(
+        &mut self,
+        asset_id: BindedAssetId,
+        mut render_buffers: BindedSceneData,
+    ) -> Result<()> {
+        let Some(resources) = self.binded_assets.get(asset_id).cloned() else {
+            anyhow::bail!("The asset {asset_id:?} isn't loaded");
+        };
+        let old_counts = (
+            resources.meshes.len(),
+            resources.wireframe_meshes.len(),
+            resources.pbr_materials.len(),
+            resources.textures.len(),
+        );
+        let new_counts = (
+            render_buffers.binded_meshes.len(),
+            render_buffers.binded_wireframe_meshes.len(),
+            render_buffers.binded_pbr_materials.len(),
+            render_buffers.textures.len(),
+        );
+        if old_counts != new_counts {
+            anyhow::bail!(
+                "The new version of the asset has a different number of (meshes, wireframe \
+                 meshes, materials, textures): {new_counts:?} instead of {old_counts:?}"
+            );
+        }
+
+        if let Some(cpu_data) = render_buffers.cpu_data.take() {
+            self.retained_cpu_data.replace(&resources, cpu_data);
+        }
+
+        for (index, mut mesh) in resources.meshes.clone().zip(render_buffers.binded_meshes) {
+            std::mem::swap(&mut self.binded_meshes[index], &mut mesh);
+            mesh.vertex_buffer.destroy();
+            mesh.index_buffer.buffer.destroy();
+        }
+        for (index, mut wireframe_mesh) in resources
+            .wireframe_meshes
+            .clone()
+            .zip(render_buffers.binded_wireframe_meshes)
+        {
+            wireframe_mesh.source_mesh_index += resources.meshes.start;
+            std::mem::swap(
+                &mut self.binded_wireframe_meshes[index],
+                &mut wireframe_mesh,
+            );
+            wireframe_mesh.index_buffer.buffer.destroy();
+        }
+        for (index, mut material) in resources
+            .pbr_materials
+            .clone()
+            .zip(render_buffers.binded_pbr_materials)
+        {
+            if let Some(texture_indices) = &mut material.texture_indices {
+                texture_indices.offset(resources.textures.start);
+            }
+            self.binded_pbr_materials[index] = material;
+        }
+        for (index, mut texture) in resources.textures.clone().zip(render_buffers.textures) {
+            std::mem::swap(&mut self.textures[index], &mut texture);
+            texture.texture.destroy();
+        }
+
+        Ok(())
+    }
+
+    /// Frees the gpu memory of the asset's resources and gives their indices back when nothing
+    /// was added after them, whatever its reference count. Nothing may use them anymore, see
+    /// Scene::unload_asset
+    pub(crate) fn free_binded_asset(&mut self, asset_id: BindedAssetId) -> Result<()> {
+        let Some(BindedAsset { resources, .. }) = self
+            .binded_assets
+            .assets
+            .get_mut(asset_id.0)
+            .and_then(Option::take)
+        else {
+            anyhow::bail!("The asset {asset_id:?} was already unloaded");
+        };
+
+        for mesh in &mut self.binded_meshes[resources.meshes.clone()] {
+            mesh.vertex_buffer.destroy();
+            mesh.index_buffer.buffer.destroy();
+            mesh.collision_mesh = None;
+        }
+        for wireframe_mesh in &mut self.binded_wireframe_meshes[resources.wireframe_meshes.clone()]
+        {
+            wireframe_mesh.index_buffer.buffer.destroy();
+            // so that the lookups by source mesh don't find it once the mesh index is reused
+            wireframe_mesh.source_mesh_index = usize::MAX;
+        }
+        for material in &mut self.binded_pbr_materials[resources.pbr_materials.clone()] {
+            // keeps set_texture_quality from rebinding the destroyed textures
+            material.texture_indices = None;
+        }
+        for texture in &self.textures[resources.textures.clone()] {
+            texture.texture.destroy();
+        }
+        self.retained_cpu_data.free(&resources);
+        self.asset_registry.free(
+            resources.meshes.clone(),
+            resources.pbr_materials.clone(),
+            resources.textures.clone(),
+        );
+        self.binded_assets.freed.push(resources);
+
+        let freed = &self.binded_assets.freed;
+        let mesh_count = reclaimable_len(
+            freed.iter().map(|freed| freed.meshes.clone()),
+            self.binded_meshes.len(),
+        );
+        let wireframe_mesh_count = reclaimable_len(
+            freed.iter().map(|freed| freed.wireframe_meshes.clone()),
+            self.binded_wireframe_meshes.len(),
+        );
+        let pbr_material_count = reclaimable_len(
+            freed.iter().map(|freed| freed.pbr_materials.clone()),
+            self.binded_pbr_materials.len(),
+        );
+        let texture_count = reclaimable_len(
+            freed.iter().map(|freed| freed.textures.clone()),
+            self.textures.len(),
+        );
+        self.binded_meshes.truncate(mesh_count);
+        self.binded_wireframe_meshes.truncate(wireframe_mesh_count);
+        self.binded_pbr_materials.truncate(pbr_material_count);
+        self.textures.truncate(texture_count);
+        self.retained_cpu_data
+            .truncate(mesh_count, wireframe_mesh_count, texture_count);
+
+        // the truncated ranges are forgotten so that they don't match the indices once reused
+        for freed in &mut self.binded_assets.freed {
+            let truncate = |range: &mut Range<usize>, len: usize| {
+                range.end = range.end.min(len);
+                range.start = range.start.min(range.end);
+            };
+            truncate(&mut freed.meshes, mesh_count);
+            truncate(&mut freed.wireframe_meshes, wireframe_mesh_count);
+            truncate(&mut freed.pbr_materials, pbr_material_count);
+            truncate(&mut freed.textures, texture_count);
+        }
+        self.binded_assets.freed.retain(|freed| {
+            !(freed.meshes.is_empty()
+                && freed.wireframe_meshes.is_empty()
+                && freed.pbr_materials.is_empty()
+                && freed.textures.is_empty())
+        });
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct BlobShadowCaster {
+    pub node_id: GameNodeId,
+    /// defaults to the horizontal extent of the node's bounding box
+    pub radius: Option<f32>,
+}
+
+impl BlobShadowCaster {
+    pub fn new(node_id: GameNodeId) -> Self {
+        Self {
+            node_id,
+            radius: None,
+        }
+    }
 }
 
 pub struct RendererConstantData {
@@ -910,20 +1810,27 @@ pub struct RendererConstantData {
     pub skybox_pipeline: wgpu::RenderPipeline,
     pub tone_mapping_pipeline: wgpu::RenderPipeline,
     pub surface_blit_pipeline: wgpu::RenderPipeline,
+    pub hdr_blit_pipeline: wgpu::RenderPipeline,
     pub point_shadow_map_pipeline: wgpu::RenderPipeline,
+    pub point_shadow_map_alpha_masked_pipeline: wgpu::RenderPipeline,
     pub directional_shadow_map_pipeline: wgpu::RenderPipeline,
+    pub directional_shadow_map_alpha_masked_pipeline: wgpu::RenderPipeline,
     pub bloom_threshold_pipeline: wgpu::RenderPipeline,
     pub bloom_blur_pipeline: wgpu::RenderPipeline,
     pub new_bloom_downscale_pipeline: wgpu::RenderPipeline,
     pub new_bloom_upscale_pipeline: wgpu::RenderPipeline,
     pub equirectangular_to_cubemap_pipeline: wgpu::RenderPipeline,
     pub equirectangular_to_cubemap_hdr_pipeline: wgpu::RenderPipeline,
+    pub procedural_sky_pipeline: wgpu::RenderPipeline,
     pub diffuse_env_map_gen_pipeline: wgpu::RenderPipeline,
     pub specular_env_map_gen_pipeline: wgpu::RenderPipeline,
 
-    pub cube_mesh_index: usize,
-    pub sphere_mesh_index: usize,
-    pub plane_mesh_index: usize,
+    /// the surface blit pipeline outputs this format, with the srgb suffix
+    pub framebuffer_format: wgpu::TextureFormat,
+
+    pub cube_mesh: MeshHandle,
+    pub sphere_mesh: MeshHandle,
+    pub plane_mesh: MeshHandle,
 }
 
 pub struct Renderer {
@@ -1186,6 +2093,22 @@ impl Renderer {
                             ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                             count: None,
                         },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 10,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Texture {
+                                multisampled: false,
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 11,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                            count: None,
+                        },
                     ],
                     label: USE_LABELS.then_some("pbr_textures_bind_group_layout"),
                 });
@@ -1325,6 +2248,24 @@ impl Renderer {
                             ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
                             count: None,
                         },
+                        // sdf_volume_texture
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 13,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Texture {
+                                multisampled: false,
+                                view_dimension: wgpu::TextureViewDimension::D3,
+                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            },
+                            count: None,
+                        },
+                        // sdf_volume_sampler
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 14,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                            count: None,
+                        },
                     ],
                     label: USE_LABELS.then_some("environment_textures_bind_group_layout"),
                 });
@@ -1375,7 +2316,7 @@ impl Renderer {
                         binding: 1,
                         visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                         ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
                             has_dynamic_offset: false,
                             min_binding_size: None,
                         },
@@ -1401,6 +2342,17 @@ impl Renderer {
                         },
                         count: None,
                     },
+                    // frame constants
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
                 label: USE_LABELS
                     .then_some("camera_lights_and_pbr_shader_options_bind_group_layout"),
@@ -1542,6 +2494,8 @@ impl Renderer {
                     bind_group_layouts: &[
                         &single_texture_bind_group_layout,
                         &single_uniform_bind_group_layout,
+                        // frame constants
+                        &single_uniform_bind_group_layout,
                     ],
                     push_constant_ranges: &[],
                 });
@@ -1628,6 +2582,8 @@ impl Renderer {
                     bind_group_layouts: &[
                         &single_texture_bind_group_layout,
                         &single_uniform_bind_group_layout,
+                        // frame constants
+                        &single_uniform_bind_group_layout,
                     ],
                     push_constant_ranges: &[],
                 });
@@ -1679,6 +2635,8 @@ impl Renderer {
                     bind_group_layouts: &[
                         &single_texture_bind_group_layout,
                         &single_uniform_bind_group_layout,
+                        // frame constants
+                        &single_uniform_bind_group_layout,
                     ],
                     push_constant_ranges: &[],
                 });
@@ -1707,6 +2665,22 @@ impl Renderer {
             .device
             .create_render_pipeline(&surface_blit_pipeline_descriptor);
 
+        let hdr_blit_color_targets = &[Some(wgpu::ColorTargetState {
+            format: wgpu::TextureFormat::Rgba16Float,
+            blend: Some(wgpu::BlendState::REPLACE),
+            write_mask: wgpu::ColorWrites::ALL,
+        })];
+        let mut hdr_blit_pipeline_descriptor = surface_blit_pipeline_descriptor.clone();
+        hdr_blit_pipeline_descriptor.label = USE_LABELS.then_some("HDR Blit Render Pipeline");
+        hdr_blit_pipeline_descriptor.fragment = Some(wgpu::FragmentState {
+            module: &blit_shader,
+            entry_point: "inverse_tone_mapping_fs_main",
+            targets: hdr_blit_color_targets,
+        });
+        let hdr_blit_pipeline = base
+            .device
+            .create_render_pipeline(&hdr_blit_pipeline_descriptor);
+
         let tone_mapping_colors_targets = &[Some(wgpu::ColorTargetState {
             format: wgpu::TextureFormat::Rgba16Float,
             blend: Some(wgpu::BlendState {
@@ -1726,6 +2700,8 @@ impl Renderer {
                     bind_group_layouts: &[
                         &two_texture_bind_group_layout,
                         &single_uniform_bind_group_layout,
+                        // frame constants
+                        &single_uniform_bind_group_layout,
                     ],
                     push_constant_ranges: &[],
                 });
@@ -1850,7 +2826,31 @@ impl Renderer {
         })];
         let equirectangular_to_cubemap_hdr_pipeline = base
             .device
-            .create_render_pipeline(&equirectangular_to_cubemap_hdr_pipeline_descriptor);
+            .create_render_pipeline(&equirectangular_to_cubemap_hdr_pipeline_descriptor);
+
+        let procedural_sky_pipeline_layout =
+            base.device
+                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: USE_LABELS.then_some("Procedural Sky Render Pipeline Layout"),
+                    bind_group_layouts: &[
+                        &single_uniform_bind_group_layout,
+                        &single_uniform_bind_group_layout,
+                    ],
+                    push_constant_ranges: &[],
+                });
+        let mut procedural_sky_pipeline_descriptor =
+            equirectangular_to_cubemap_hdr_pipeline_descriptor.clone();
+        procedural_sky_pipeline_descriptor.label =
+            USE_LABELS.then_some("Procedural Sky Render Pipeline");
+        procedural_sky_pipeline_descriptor.layout = Some(&procedural_sky_pipeline_layout);
+        procedural_sky_pipeline_descriptor
+            .fragment
+            .as_mut()
+            .unwrap()
+            .entry_point = "procedural_sky_fs_main";
+        let procedural_sky_pipeline = base
+            .device
+            .create_render_pipeline(&procedural_sky_pipeline_descriptor);
 
         let diffuse_env_map_color_targets = &[Some(wgpu::ColorTargetState {
             format: wgpu::TextureFormat::Rgba16Float,
@@ -2017,6 +3017,19 @@ impl Renderer {
             .device
             .create_render_pipeline(&point_shadow_map_pipeline_descriptor);
 
+        let mut point_shadow_map_alpha_masked_pipeline_descriptor =
+            point_shadow_map_pipeline_descriptor.clone();
+        point_shadow_map_alpha_masked_pipeline_descriptor.label =
+            USE_LABELS.then_some("Point Shadow Map Alpha Masked Pipeline");
+        point_shadow_map_alpha_masked_pipeline_descriptor.fragment = Some(wgpu::FragmentState {
+            module: &textured_mesh_shader,
+            entry_point: "point_shadow_map_alpha_masked_fs_main",
+            targets: &[],
+        });
+        let point_shadow_map_alpha_masked_pipeline = base
+            .device
+            .create_render_pipeline(&point_shadow_map_alpha_masked_pipeline_descriptor);
+
         let directional_shadow_map_pipeline_descriptor = wgpu::RenderPipelineDescriptor {
             label: USE_LABELS.then_some("Directional Shadow Map Pipeline"),
             layout: Some(&shadow_map_pipeline_layout),
@@ -2054,6 +3067,20 @@ impl Renderer {
             .device
             .create_render_pipeline(&directional_shadow_map_pipeline_descriptor);
 
+        let mut directional_shadow_map_alpha_masked_pipeline_descriptor =
+            directional_shadow_map_pipeline_descriptor.clone();
+        directional_shadow_map_alpha_masked_pipeline_descriptor.label =
+            USE_LABELS.then_some("Directional Shadow Map Alpha Masked Pipeline");
+        directional_shadow_map_alpha_masked_pipeline_descriptor.fragment =
+            Some(wgpu::FragmentState {
+                module: &textured_mesh_shader,
+                entry_point: "directional_shadow_map_alpha_masked_fs_main",
+                targets: &[],
+            });
+        let directional_shadow_map_alpha_masked_pipeline = base
+            .device
+            .create_render_pipeline(&directional_shadow_map_alpha_masked_pipeline_descriptor);
+
         let initial_render_scale = 1.0;
 
         let cube_mesh = BasicMesh::new(include_bytes!("models/cube.obj"))?;
@@ -2080,20 +3107,27 @@ impl Renderer {
             skybox_pipeline,
             tone_mapping_pipeline,
             surface_blit_pipeline,
+            hdr_blit_pipeline,
             point_shadow_map_pipeline,
+            point_shadow_map_alpha_masked_pipeline,
             directional_shadow_map_pipeline,
+            directional_shadow_map_alpha_masked_pipeline,
             bloom_threshold_pipeline,
             bloom_blur_pipeline,
             new_bloom_downscale_pipeline,
             new_bloom_upscale_pipeline,
             equirectangular_to_cubemap_pipeline,
             equirectangular_to_cubemap_hdr_pipeline,
+            procedural_sky_pipeline,
             diffuse_env_map_gen_pipeline,
             specular_env_map_gen_pipeline,
 
-            cube_mesh_index: 0,
-            sphere_mesh_index: 0,
-            plane_mesh_index: 0,
+            framebuffer_format,
+
+            // set once the meshes are binded below
+            cube_mesh: MeshHandle::unbinded(0),
+            sphere_mesh: MeshHandle::unbinded(0),
+            plane_mesh: MeshHandle::unbinded(0),
         };
 
         let shading_texture = Texture::create_scaled_surface_texture(
@@ -2146,6 +3180,12 @@ impl Renderer {
             initial_render_scale,
             "tone_mapping_texture",
         );
+        let post_process_texture = Texture::create_scaled_surface_texture(
+            &base,
+            framebuffer_size,
+            initial_render_scale,
+            "post_process_texture",
+        );
 
         let shading_texture_bind_group;
         let tone_mapping_texture_bind_group;
@@ -2441,6 +3481,24 @@ impl Renderer {
                 label: USE_LABELS.then_some("tone_mapping_config_bind_group"),
             });
 
+        let frame_constants_buffer =
+            base.device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: USE_LABELS.then_some("Frame Constants Buffer"),
+                    contents: bytemuck::cast_slice(&[FrameConstantsUniform::default()]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                });
+
+        let frame_constants_bind_group =
+            base.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &constant_data.single_uniform_bind_group_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: frame_constants_buffer.as_entire_binding(),
+                }],
+                label: USE_LABELS.then_some("frame_constants_bind_group"),
+            });
+
         let depth_texture = Texture::create_depth_texture(
             &base,
             framebuffer_size,
@@ -2560,28 +3618,38 @@ impl Renderer {
                     contents: bytemuck::cast_slice(&[
                         skybox_weights[0],
                         skybox_weights[1],
-                        0.0,
+                        1.0,
                         0.0,
                     ]),
                     usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
                 });
 
-        let brdf_lut = Texture::create_brdf_lut(&base, &brdf_lut_gen_pipeline);
+        let ibl_cache = IblCache::from_config(&base.config);
+        let brdf_lut = match ibl_cache
+            .as_ref()
+            .and_then(|ibl_cache| ibl_cache.load_brdf_lut(&base))
+        {
+            Some(brdf_lut) => brdf_lut,
+            None => {
+                let brdf_lut = Texture::create_brdf_lut(&base, &brdf_lut_gen_pipeline);
+                if let Some(ibl_cache) = &ibl_cache {
+                    ibl_cache.save_brdf_lut(&base, &brdf_lut);
+                }
+                brdf_lut
+            }
+        };
 
         let skybox_gen_time = start.elapsed();
         log::debug!("skybox_gen_time={skybox_gen_time:?}");
 
-        let initial_point_lights_buffer: Vec<u8> = (0..(MAX_LIGHT_COUNT
-            * std::mem::size_of::<PointLightUniform>()))
-            .map(|_| 0u8)
-            .collect();
-        let point_lights_buffer =
-            base.device
-                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: USE_LABELS.then_some("Point Lights Buffer"),
-                    contents: &initial_point_lights_buffer,
-                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-                });
+        // starts out with no lights
+        let point_lights_buffer = GpuBuffer::from_bytes_and_capacity(
+            &base.device,
+            bytemuck::cast_slice(&[0u32; 4]),
+            std::mem::size_of::<PointLightUniform>(),
+            MAX_LIGHT_COUNT,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        );
 
         let directional_lights_buffer =
             base.device
@@ -2597,6 +3665,7 @@ impl Renderer {
         let enable_shadow_debug = Default::default();
         let enable_cascade_debug = Default::default();
         let soft_shadow_grid_dims = Default::default();
+        let shadow_normal_offset = 1.0;
         let initial_pbr_shader_options_buffer = make_pbr_shader_options_uniform_buffer(
             enable_soft_shadows,
             shadow_bias,
@@ -2604,6 +3673,12 @@ impl Renderer {
             enable_shadow_debug,
             enable_cascade_debug,
             soft_shadow_grid_dims,
+            None,
+            true,
+            &BlobShadowShaderParams::default(),
+            shadow_normal_offset,
+            &ShadowFilter::default(),
+            0,
         );
         let pbr_shader_options_buffer =
             base.device
@@ -2756,8 +3831,8 @@ impl Renderer {
         let point_shadow_map_textures = Texture::create_depth_texture_array(
             &base,
             (
-                6 * POINT_LIGHT_SHADOW_MAP_RESOLUTION,
-                POINT_LIGHT_SHADOW_MAP_RESOLUTION,
+                6 * DEFAULT_POINT_LIGHT_SHADOW_MAP_RESOLUTION,
+                DEFAULT_POINT_LIGHT_SHADOW_MAP_RESOLUTION,
             ),
             Some("point_shadow_map_texture"),
             POINT_LIGHT_SHOW_MAP_COUNT,
@@ -2766,13 +3841,15 @@ impl Renderer {
         let directional_shadow_map_textures = Texture::create_depth_texture_array(
             &base,
             (
-                DIRECTIONAL_LIGHT_SHADOW_MAP_RESOLUTION,
-                DIRECTIONAL_LIGHT_SHADOW_MAP_RESOLUTION,
+                DEFAULT_DIRECTIONAL_LIGHT_SHADOW_MAP_RESOLUTION,
+                DEFAULT_DIRECTIONAL_LIGHT_SHADOW_MAP_RESOLUTION,
             ),
             Some("directional_shadow_map_texture"),
             DIRECTIONAL_LIGHT_SHOW_MAP_COUNT * MAX_SHADOW_CASCADES as u32,
         );
 
+        let sdf_volume_texture = Texture::create_sdf_volume_texture(&base, None);
+
         let environment_textures_bind_group = Self::get_environment_textures_bind_group(
             &base,
             &constant_data,
@@ -2781,6 +3858,7 @@ impl Renderer {
             &brdf_lut,
             &point_shadow_map_textures,
             &directional_shadow_map_textures,
+            &sdf_volume_texture,
         );
 
         let mut data = RendererData {
@@ -2790,6 +3868,7 @@ impl Renderer {
             textures: vec![],
 
             tone_mapping_exposure: 1.0,
+            environment_intensity: 1.0,
             bloom_threshold: 0.8,
             bloom_ramp_size: 0.2,
             new_bloom_radius: 0.005,
@@ -2806,21 +3885,48 @@ impl Renderer {
             draw_directional_light_culling_frusta: false,
             enable_soft_shadows,
             shadow_bias,
+            shadow_normal_offset,
+            enable_shadow_depth_fitting: true,
             soft_shadow_factor,
             enable_shadow_debug,
             enable_cascade_debug,
             soft_shadow_grid_dims,
+            shadow_filter: ShadowFilter::default(),
+            shadow_updates: ShadowUpdates::default(),
+            max_point_light_shadow_maps: 8,
+            point_shadow_map_resolution: DEFAULT_POINT_LIGHT_SHADOW_MAP_RESOLUTION,
+            directional_shadow_map_resolution: DEFAULT_DIRECTIONAL_LIGHT_SHADOW_MAP_RESOLUTION,
+            parallax_max_step_count: 32,
+            enable_sdf_shadows: true,
+            sdf_shadow_softness: 8.0,
+            sdf_ao_strength: 1.0,
+            enable_blob_shadows: true,
+            blob_shadow_casters: vec![],
+            blob_shadow_opacity: 0.7,
+            blob_shadow_fade_height: 3.0,
+            clip_planes: vec![],
+            camera_jitter: Vec2::ZERO,
             camera_node_id: None,
+            camera_lens: CameraLens::default(),
+            camera_render_layers: RenderLayers::DEFAULT,
+            retained_cpu_data: RetainedCpuData::default(),
+            binded_assets: BindedAssets::default(),
+            asset_registry: AssetRegistry::default(),
+            custom_materials: CustomMaterials::default(),
+            post_process_passes: PostProcessPasses::default(),
+            color_grading: ColorGrading::new(&base, &constant_data),
+            outlines: Outlines::new(&base),
+            path_tracer: PathTracer::new(&base),
+            taa: TemporalAntiAliasing::new(&base),
         };
 
-        constant_data.cube_mesh_index = Self::bind_basic_mesh(&base, &mut data, &cube_mesh, true);
+        constant_data.cube_mesh = Self::bind_basic_mesh(&base, &mut data, &cube_mesh, true);
 
         let sphere_mesh = BasicMesh::new(include_bytes!("models/sphere.obj"))?;
-        constant_data.sphere_mesh_index =
-            Self::bind_basic_mesh(&base, &mut data, &sphere_mesh, true);
+        constant_data.sphere_mesh = Self::bind_basic_mesh(&base, &mut data, &sphere_mesh, true);
 
         let plane_mesh = BasicMesh::new(include_bytes!("models/plane.obj"))?;
-        constant_data.plane_mesh_index = Self::bind_basic_mesh(&base, &mut data, &plane_mesh, true);
+        constant_data.plane_mesh = Self::bind_basic_mesh(&base, &mut data, &plane_mesh, true);
 
         let profiler = wgpu_profiler::GpuProfiler::new(wgpu_profiler::GpuProfilerSettings {
             enable_timer_queries: !cfg!(target_arch = "wasm32"),
@@ -2843,18 +3949,24 @@ impl Renderer {
                 pbr_mesh_index_to_gpu_instances: HashMap::new(),
                 all_pbr_instances: ChunkedBuffer::new(),
                 all_pbr_instances_culling_masks: vec![],
+                all_pbr_instances_alpha_masked: vec![],
                 all_unlit_instances: ChunkedBuffer::new(),
                 all_transparent_instances: ChunkedBuffer::new(),
                 all_wireframe_instances: ChunkedBuffer::new(),
                 debug_node_bounding_spheres_nodes: vec![],
                 debug_culling_frustum_nodes: vec![],
-                debug_culling_frustum_mesh_index: None,
+                debug_culling_frustum_mesh: None,
 
                 // TODO: instead of clearing the texture when bloom is disabled, just don't read from it in the tone mapping shader
                 bloom_threshold_cleared: true,
                 new_bloom_cleared: true,
                 frustum_culling_lock: CullingFrustumLock::None,
                 skybox_weights,
+                frame_index: 0,
+                point_light_casters_fingerprints: vec![],
+                point_light_shadow_updates: vec![],
+                point_shadow_atlas: PointShadowAtlas::new(POINT_LIGHT_SHOW_MAP_COUNT),
+                point_light_shadow_layers: vec![],
 
                 camera_lights_and_pbr_shader_options_bind_group_layout,
 
@@ -2867,6 +3979,7 @@ impl Renderer {
                 new_bloom_downscale_config_bind_groups,
                 new_bloom_upscale_config_bind_group,
                 tone_mapping_config_bind_group,
+                frame_constants_bind_group,
                 environment_textures_bind_group,
                 shading_and_bloom_textures_bind_group,
                 shading_and_new_bloom_texture_bind_group,
@@ -2884,6 +3997,7 @@ impl Renderer {
                 new_bloom_upscale_config_buffer,
                 tone_mapping_config_buffer,
                 pbr_shader_options_buffer,
+                frame_constants_buffer,
                 bones_buffer,
                 pbr_instances_buffer,
                 unlit_instances_buffer,
@@ -2898,11 +4012,15 @@ impl Renderer {
 
                 shading_texture,
                 tone_mapping_texture,
+                post_process_texture,
                 depth_texture,
                 bloom_pingpong_textures,
                 new_bloom_texture,
                 new_bloom_texture_mip_views,
                 brdf_lut,
+
+                sdf_volume_texture,
+                sdf_volume_info: None,
             }),
 
             profiler: Mutex::new(profiler),
@@ -2969,6 +4087,15 @@ impl Renderer {
                 &auto_generated_ambient_occlusion_map
             }
         };
+        // black, only sampled by materials with a lightmap_intensity
+        let auto_generated_lightmap;
+        let lightmap = match pbr_textures.lightmap {
+            Some(lightmap) => lightmap,
+            None => {
+                auto_generated_lightmap = base.get_default_texture(DefaultTextureType::Emissive)?;
+                &auto_generated_lightmap
+            }
+        };
 
         let sampler_cache_guard = base.sampler_cache.lock().unwrap();
 
@@ -3027,6 +4154,16 @@ impl Renderer {
                             .get_sampler_by_index(ambient_occlusion_map.sampler_index),
                     ),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: wgpu::BindingResource::TextureView(&lightmap.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 11,
+                    resource: wgpu::BindingResource::Sampler(
+                        sampler_cache_guard.get_sampler_by_index(lightmap.sampler_index),
+                    ),
+                },
             ],
             label: USE_LABELS.then_some("InstancedMeshComponent textures_bind_group"),
         });
@@ -3040,9 +4177,18 @@ impl Renderer {
         data: &mut RendererData,
         mesh: &BasicMesh,
         generate_wireframe_mesh: bool,
-    ) -> usize {
+    ) -> MeshHandle {
         let geometry_buffers = Self::bind_geometry_buffers_for_basic_mesh(base, mesh);
 
+        if base.config.retain_cpu_data {
+            data.retained_cpu_data.add_basic_mesh(
+                data.binded_meshes.len(),
+                generate_wireframe_mesh.then_some(data.binded_wireframe_meshes.len()),
+                mesh,
+                geometry_buffers.bounding_box,
+            );
+        }
+
         data.binded_meshes.push(geometry_buffers);
         let mesh_index = data.binded_meshes.len() - 1;
 
@@ -3058,10 +4204,11 @@ impl Renderer {
             });
         }
 
-        mesh_index
+        data.asset_registry.mesh(mesh_index)
     }
 
-    pub fn unbind_mesh(data: &RendererData, mesh_index: usize) {
+    pub fn unbind_mesh(data: &RendererData, mesh: MeshHandle) {
+        let mesh_index = mesh.index();
         let geometry_buffers = &data.binded_meshes[mesh_index];
         let wireframe_mesh = data
             .binded_wireframe_meshes
@@ -3074,24 +4221,24 @@ impl Renderer {
         wireframe_mesh.index_buffer.buffer.destroy();
     }
 
-    // returns index of mesh in the RenderScene::binded_pbr_meshes list
     pub fn bind_pbr_material(
         base: &BaseRenderer,
         constant_data: &RendererConstantData,
         data: &mut RendererData,
         pbr_textures: &PbrTextures,
         dynamic_pbr_params: DynamicPbrParams,
-    ) -> Result<usize> {
+    ) -> Result<MaterialHandle> {
         let textures_bind_group =
             Self::make_pbr_textures_bind_group(base, constant_data, pbr_textures, false)?;
 
         data.binded_pbr_materials.push(BindedPbrMaterial {
             dynamic_pbr_params,
             textures_bind_group: WasmNotArc::new(textures_bind_group),
+            texture_indices: None,
         });
         let material_index = data.binded_pbr_materials.len() - 1;
 
-        Ok(material_index)
+        Ok(data.asset_registry.material(material_index))
     }
 
     fn bind_geometry_buffers_for_basic_mesh(
@@ -3131,6 +4278,7 @@ impl Renderer {
                 format: wgpu::IndexFormat::Uint16,
             },
             bounding_box,
+            collision_mesh: None,
         }
     }
 
@@ -3147,22 +4295,7 @@ impl Renderer {
     ) -> GpuBuffer {
         let index_buffer = GpuBuffer::from_bytes(
             device,
-            bytemuck::cast_slice(
-                &mesh
-                    .indices
-                    .chunks(3)
-                    .flat_map(|triangle| {
-                        vec![
-                            triangle[0],
-                            triangle[1],
-                            triangle[1],
-                            triangle[2],
-                            triangle[2],
-                            triangle[0],
-                        ]
-                    })
-                    .collect::<Vec<_>>(),
-            ),
+            bytemuck::cast_slice(&wireframe_indices(mesh)),
             std::mem::size_of::<u16>(),
             wgpu::BufferUsages::INDEX,
         );
@@ -3170,6 +4303,28 @@ impl Renderer {
         index_buffer
     }
 
+    /// See PostProcessPassDescriptor, the pass can be turned off and fed with
+    /// RendererData::post_process_passes
+    pub fn add_post_process_pass(
+        &self,
+        descriptor: PostProcessPassDescriptor,
+    ) -> PostProcessPassId {
+        self.data.lock().unwrap().post_process_passes.add(
+            &self.base,
+            &self.constant_data,
+            descriptor,
+        )
+    }
+
+    /// Uploads a lut, which can then be applied and blended with RendererData::color_grading
+    pub fn add_color_grading_lut(&self, lut: Lut3d) -> LutId {
+        self.data
+            .lock()
+            .unwrap()
+            .color_grading
+            .add_lut(&self.base, lut)
+    }
+
     pub fn set_vsync(&self, vsync: bool, surface_data: &mut SurfaceData) {
         let new_present_mode = if vsync {
             wgpu::PresentMode::AutoVsync
@@ -3177,15 +4332,307 @@ impl Renderer {
             wgpu::PresentMode::AutoNoVsync
         };
 
-        if surface_data.surface_config.present_mode == new_present_mode {
+        self.set_present_mode(new_present_mode, surface_data);
+    }
+
+    /// The Auto modes are always supported since wgpu picks one of these
+    pub fn supported_present_modes(&self, surface_data: &SurfaceData) -> Vec<wgpu::PresentMode> {
+        surface_data
+            .surface
+            .get_capabilities(&self.base.adapter)
+            .present_modes
+    }
+
+    /// Falls back to Fifo if the surface doesn't support the mode, returns the mode that was set
+    pub fn set_present_mode(
+        &self,
+        present_mode: wgpu::PresentMode,
+        surface_data: &mut SurfaceData,
+    ) -> wgpu::PresentMode {
+        let is_supported = matches!(
+            present_mode,
+            wgpu::PresentMode::AutoVsync | wgpu::PresentMode::AutoNoVsync
+        ) || self
+            .supported_present_modes(surface_data)
+            .contains(&present_mode);
+        let new_present_mode = if is_supported {
+            present_mode
+        } else {
+            log::warn!("Present mode {present_mode:?} isn't supported by the surface, using Fifo");
+            wgpu::PresentMode::Fifo
+        };
+
+        if surface_data.surface_config.present_mode != new_present_mode {
+            surface_data.surface_config.present_mode = new_present_mode;
+            surface_data
+                .surface
+                .configure(&self.base.device, &surface_data.surface_config);
+        }
+
+        new_present_mode
+    }
+
+    /// The new filtering is applied right away to the materials of the loaded scenes,
+    /// see TextureQuality::resolution_bias for the rest
+    pub fn set_texture_quality(&self, texture_quality: TextureQuality) {
+        let recreated_samplers = self
+            .base
+            .sampler_cache
+            .lock()
+            .unwrap()
+            .set_texture_quality(&self.base.device, texture_quality);
+        if !recreated_samplers {
             return;
         }
 
-        surface_data.surface_config.present_mode = new_present_mode;
+        let mut data_guard = self.data.lock().unwrap();
+        let data: &mut RendererData = &mut data_guard;
+
+        // materials that shared a bind group keep sharing one
+        let mut textures_bind_groups: HashMap<IndexedPbrTextures, WasmNotArc<wgpu::BindGroup>> =
+            HashMap::new();
+        for material in data.binded_pbr_materials.iter_mut() {
+            let Some(texture_indices) = &material.texture_indices else {
+                continue;
+            };
+            if let Some(textures_bind_group) = textures_bind_groups.get(texture_indices) {
+                material.textures_bind_group = textures_bind_group.clone();
+                continue;
+            }
+
+            let get_texture =
+                |texture_index: Option<usize>| texture_index.map(|index| &data.textures[index]);
+            let pbr_textures = PbrTextures {
+                base_color: get_texture(texture_indices.base_color),
+                normal: get_texture(texture_indices.normal),
+                metallic_roughness: get_texture(texture_indices.metallic_roughness),
+                emissive: get_texture(texture_indices.emissive),
+                ambient_occlusion: get_texture(texture_indices.ambient_occlusion),
+                lightmap: get_texture(texture_indices.lightmap),
+            };
+            match Self::make_pbr_textures_bind_group(
+                &self.base,
+                &self.constant_data,
+                &pbr_textures,
+                true,
+            ) {
+                Ok(textures_bind_group) => {
+                    material.textures_bind_group = WasmNotArc::new(textures_bind_group);
+                    textures_bind_groups.insert(
+                        texture_indices.clone(),
+                        material.textures_bind_group.clone(),
+                    );
+                }
+                Err(err) => {
+                    log::error!("Failed to recreate material bind group: {err:?}");
+                }
+            }
+        }
+    }
+
+    /// Replaces the device after the old one was lost, see BaseRenderer::is_device_lost.
+    /// The renderer's own resources are rebuilt, the settings in RendererData are kept and
+    /// the meshes, textures and materials are bound again from their CPU copies, or replaced by
+    /// placeholders when there are none. The skyboxes, the sdf volume and anything the game
+    /// binded itself need to be set again. Renderer::resize_surface must be called afterwards
+    pub async fn recreate_device(&mut self, surface_data: &mut SurfaceData) -> Result<()> {
+        let base = BaseRenderer::new(
+            self.base.instance.clone(),
+            Some(&surface_data.surface),
+            &self.base.config,
+        )
+        .await?;
 
+        let capabilities = surface_data.surface.get_capabilities(&base.adapter);
+        if !capabilities
+            .formats
+            .contains(&surface_data.surface_config.format)
+        {
+            anyhow::bail!(
+                "The new device can't present with the surface format {:?}",
+                surface_data.surface_config.format
+            );
+        }
         surface_data
             .surface
-            .configure(&self.base.device, &surface_data.surface_config);
+            .configure(&base.device, &surface_data.surface_config);
+
+        let Renderer {
+            base,
+            data: new_data,
+            constant_data,
+            private_data,
+            profiler,
+        } = Renderer::new(
+            base,
+            surface_data.surface_config.format,
+            (
+                surface_data.surface_config.width,
+                surface_data.surface_config.height,
+            ),
+        )
+        .await?;
+
+        // the data is shared with the game so only its gpu resources are swapped
+        {
+            let mut data_guard = self.data.lock().unwrap();
+            let mut new_data_guard = new_data.lock().unwrap();
+            Self::restore_scene_resources(&base, &constant_data, &data_guard, &mut new_data_guard)?;
+            let data: &mut RendererData = &mut data_guard;
+            let new_data: &mut RendererData = &mut new_data_guard;
+            std::mem::swap(&mut data.binded_meshes, &mut new_data.binded_meshes);
+            std::mem::swap(
+                &mut data.binded_wireframe_meshes,
+                &mut new_data.binded_wireframe_meshes,
+            );
+            std::mem::swap(
+                &mut data.binded_pbr_materials,
+                &mut new_data.binded_pbr_materials,
+            );
+            std::mem::swap(&mut data.textures, &mut new_data.textures);
+            data.custom_materials.recreate(&base.device);
+            data.post_process_passes.recreate(&base, &constant_data);
+            data.color_grading.recreate(&base, &constant_data);
+            data.outlines.recreate(&base);
+            data.path_tracer.recreate(&base);
+            data.taa.recreate(&base);
+        }
+
+        {
+            let private_data_guard = self.private_data.lock().unwrap();
+            let mut new_private_data_guard = private_data.lock().unwrap();
+            new_private_data_guard.skybox_weights = private_data_guard.skybox_weights;
+            new_private_data_guard.frustum_culling_lock =
+                private_data_guard.frustum_culling_lock.clone();
+        }
+
+        self.base = base;
+        self.constant_data = constant_data;
+        self.private_data = private_data;
+        self.profiler = profiler;
+
+        log::info!(
+            "Recreated the gpu device on adapter {:?}",
+            self.base.adapter.get_info().name
+        );
+
+        Ok(())
+    }
+
+    /// Binds everything that's in old_data but not in new_data yet, which only has the renderer's
+    /// built-in meshes, onto the new device
+    fn restore_scene_resources(
+        base: &BaseRenderer,
+        constant_data: &RendererConstantData,
+        old_data: &RendererData,
+        new_data: &mut RendererData,
+    ) -> Result<()> {
+        let retained_cpu_data = &old_data.retained_cpu_data;
+        let placeholder_mesh = BasicMesh::new(include_bytes!("models/cube.obj"))?;
+        let mut placeholder_count = 0;
+
+        for (mesh_index, old_mesh) in old_data
+            .binded_meshes
+            .iter()
+            .enumerate()
+            .skip(new_data.binded_meshes.len())
+        {
+            let restored_mesh = retained_cpu_data
+                .meshes
+                .get(mesh_index)
+                .and_then(Option::as_ref)
+                .map(|mesh| crate::asset_loader::bind_mesh(base, mesh))
+                .transpose()?;
+            let mut mesh = restored_mesh.unwrap_or_else(|| {
+                placeholder_count += 1;
+                Self::bind_geometry_buffers_for_basic_mesh(base, &placeholder_mesh)
+            });
+            mesh.bounding_box = old_mesh.bounding_box;
+            mesh.collision_mesh = old_mesh.collision_mesh.clone();
+            new_data.binded_meshes.push(mesh);
+        }
+
+        for (wireframe_mesh_index, old_wireframe_mesh) in old_data
+            .binded_wireframe_meshes
+            .iter()
+            .enumerate()
+            .skip(new_data.binded_wireframe_meshes.len())
+        {
+            let restored_index_buffer = retained_cpu_data
+                .wireframe_meshes
+                .get(wireframe_mesh_index)
+                .and_then(Option::as_ref)
+                .map(|indices| crate::asset_loader::bind_index_buffer(base, indices))
+                .transpose()?;
+            let index_buffer = restored_index_buffer.unwrap_or_else(|| {
+                placeholder_count += 1;
+                BindedIndexBuffer {
+                    buffer: Self::make_wireframe_index_buffer_for_basic_mesh(
+                        base,
+                        &placeholder_mesh,
+                    ),
+                    format: wgpu::IndexFormat::Uint16,
+                }
+            });
+            new_data.binded_wireframe_meshes.push(BindedWireframeMesh {
+                source_mesh_index: old_wireframe_mesh.source_mesh_index,
+                index_buffer,
+            });
+        }
+
+        for texture_index in 0..old_data.textures.len() {
+            let texture = match retained_cpu_data
+                .textures
+                .get(texture_index)
+                .and_then(Option::as_ref)
+            {
+                Some(texture) => crate::asset_loader::bind_texture(base, texture)?,
+                None => {
+                    placeholder_count += 1;
+                    Texture::from_color(base, [255, 255, 255, 255])?
+                }
+            };
+            new_data.textures.push(texture);
+        }
+
+        let mut bind_group_cache = crate::asset_loader::BindGroupCache::new();
+        for old_material in &old_data.binded_pbr_materials {
+            let material = match &old_material.texture_indices {
+                Some(texture_indices) => crate::asset_loader::bind_pbr_material(
+                    base,
+                    constant_data,
+                    &new_data.textures,
+                    &mut bind_group_cache,
+                    &BindablePbrMaterial {
+                        textures: texture_indices.clone(),
+                        dynamic_pbr_params: old_material.dynamic_pbr_params,
+                    },
+                )?,
+                // binded straight from the game's textures, which are gone
+                None => {
+                    placeholder_count += 1;
+                    BindedPbrMaterial {
+                        textures_bind_group: WasmNotArc::new(Self::make_pbr_textures_bind_group(
+                            base,
+                            constant_data,
+                            &PbrTextures::default(),
+                            false,
+                        )?),
+                        dynamic_pbr_params: old_material.dynamic_pbr_params,
+                        texture_indices: None,
+                    }
+                }
+            };
+            new_data.binded_pbr_materials.push(material);
+        }
+
+        if placeholder_count > 0 {
+            log::warn!(
+                "Replaced {placeholder_count} gpu resources that had no CPU copy by placeholders"
+            );
+        }
+
+        Ok(())
     }
 
     pub fn resize_surface(
@@ -3206,6 +4653,12 @@ impl Renderer {
             .surface
             .configure(&self.base.device, &surface_data.surface_config);
 
+        self.resize_framebuffer(new_unscaled_framebuffer_size);
+    }
+
+    /// Recreates the textures that depend on the framebuffer size. resize_surface calls it,
+    /// it's only needed on its own when rendering offscreen
+    pub fn resize_framebuffer(&mut self, new_unscaled_framebuffer_size: (u32, u32)) {
         let data_guard = self.data.lock().unwrap();
         let mut private_data_guard = self.private_data.lock().unwrap();
         let render_scale = data_guard.render_scale;
@@ -3261,6 +4714,12 @@ impl Renderer {
             render_scale,
             "tone_mapping_texture",
         );
+        private_data_guard.post_process_texture = Texture::create_scaled_surface_texture(
+            &self.base,
+            new_unscaled_framebuffer_size,
+            render_scale,
+            "post_process_texture",
+        );
         private_data_guard.depth_texture = Texture::create_depth_texture(
             &self.base,
             new_unscaled_framebuffer_size,
@@ -3513,8 +4972,8 @@ impl Renderer {
         }
         private_data.debug_culling_frustum_nodes.clear();
 
-        if let Some(mesh_index) = private_data.debug_culling_frustum_mesh_index.take() {
-            Self::unbind_mesh(data, mesh_index);
+        if let Some(mesh) = private_data.debug_culling_frustum_mesh.take() {
+            Self::unbind_mesh(data, mesh);
         }
 
         if data.draw_node_bounding_spheres {
@@ -3554,7 +5013,7 @@ impl Renderer {
                                             color: debug_sphere_color.into(),
                                             premultiplied_alpha: false,
                                         },
-                                        mesh_index: self.constant_data.sphere_mesh_index,
+                                        mesh: self.constant_data.sphere_mesh,
                                         wireframe: false,
                                         cullable: false,
                                     }))
@@ -3577,7 +5036,7 @@ impl Renderer {
             let debug_main_camera_frustum_mesh =
                 debug_main_camera_frustum_descriptor.to_basic_mesh();
 
-            private_data.debug_culling_frustum_mesh_index = Some(Self::bind_basic_mesh(
+            private_data.debug_culling_frustum_mesh = Some(Self::bind_basic_mesh(
                 &self.base,
                 data,
                 &debug_main_camera_frustum_mesh,
@@ -3589,8 +5048,8 @@ impl Renderer {
                     color: Vec4::new(1.0, 0.0, 0.0, 0.1),
                     premultiplied_alpha: false,
                 },
-                mesh_index: private_data
-                    .debug_culling_frustum_mesh_index
+                mesh: private_data
+                    .debug_culling_frustum_mesh
                     .expect("Mesh index should have just been set above"),
                 wireframe: false,
                 cullable: false,
@@ -3634,6 +5093,7 @@ impl Renderer {
                         far_plane_distance: POINT_LIGHT_SHADOW_MAP_FRUSTUM_FAR_PLANE,
                         fov_y_rad: deg_to_rad(90.0),
                         aspect_ratio: 1.0,
+                        orthographic_height: None,
                     };
 
                     let debug_frustum_descriptor = CameraFrustumDescriptor {
@@ -3658,7 +5118,7 @@ impl Renderer {
                         Vec4::new(1.0, 0.0, 0.0, 0.1)
                     };
 
-                    private_data.debug_culling_frustum_mesh_index = Some(Self::bind_basic_mesh(
+                    private_data.debug_culling_frustum_mesh = Some(Self::bind_basic_mesh(
                         &self.base,
                         data,
                         &debug_culling_frustum_mesh,
@@ -3670,8 +5130,8 @@ impl Renderer {
                             color: collision_based_color,
                             premultiplied_alpha: false,
                         },
-                        mesh_index: private_data
-                            .debug_culling_frustum_mesh_index
+                        mesh: private_data
+                            .debug_culling_frustum_mesh
                             .expect("Mesh index should have just been set above"),
                         wireframe: false,
                         cullable: false,
@@ -3724,7 +5184,7 @@ impl Renderer {
                             },
                             premultiplied_alpha: false,
                         },
-                        mesh_index: self.constant_data.cube_mesh_index,
+                        mesh: self.constant_data.cube_mesh,
                         wireframe: false,
                         cullable: false,
                     };
@@ -3753,7 +5213,7 @@ impl Renderer {
                                 color: Vec4::new(0.0, 0.0, 1.0, 0.1),
                                 premultiplied_alpha: false,
                             },
-                            mesh_index: self.constant_data.sphere_mesh_index,
+                            mesh: self.constant_data.sphere_mesh,
                             wireframe: false,
                             cullable: false,
                         };
@@ -3818,15 +5278,11 @@ impl Renderer {
             CullingFrustumLock::None => camera_transform.position(),
         };
 
+        let camera_lens = self.data.lock().unwrap().camera_lens;
         private_data_guard.frustum_culling_lock = match lock_mode {
-            CullingFrustumLockMode::Full => CullingFrustumLock::Full(CameraFrustumDescriptor {
-                focal_point: camera_transform.position(),
-                forward_vector: (-camera_transform.z_axis).into(),
-                aspect_ratio,
-                near_plane_distance: NEAR_PLANE_DISTANCE,
-                far_plane_distance: FAR_PLANE_DISTANCE,
-                fov_y_rad: deg_to_rad(FOV_Y_DEG),
-            }),
+            CullingFrustumLockMode::Full => CullingFrustumLock::Full(
+                camera_lens.frustum_descriptor(camera_transform, aspect_ratio),
+            ),
             CullingFrustumLockMode::FocalPoint => CullingFrustumLock::FocalPoint(position),
             CullingFrustumLockMode::None => CullingFrustumLock::None,
         };
@@ -3841,11 +5297,93 @@ impl Renderer {
     where
         UiOverlay: iced_winit::runtime::Program<Renderer = iced::Renderer> + 'static,
     {
-        self.update_internal(engine_state, &surface_data.surface_config);
+        let surface_config = &surface_data.surface_config;
+        self.base.diagnostics.on_frame_started(
+            RenderSettingsSnapshot::from(&*self.data.lock().unwrap()),
+            (surface_config.width, surface_config.height),
+            surface_config.format,
+            Some(surface_config.present_mode),
+        );
+        self.update_internal(
+            engine_state,
+            (surface_config.width, surface_config.height),
+            FrameKind::Final,
+        );
+
+        let surface_texture = surface_data.surface.get_current_texture()?;
+        let surface_texture_view =
+            surface_texture
+                .texture
+                .create_view(&wgpu::TextureViewDescriptor {
+                    format: Some(surface_texture.texture.format().add_srgb_suffix()),
+                    ..Default::default()
+                });
         self.render_internal(
             engine_state,
-            surface_data.surface.get_current_texture()?,
-            ui_overlay,
+            &surface_texture_view,
+            FrameKind::Final,
+            Some(ui_overlay),
+        )?;
+        surface_texture.present();
+
+        Ok(())
+    }
+
+    /// Renders a frame into target instead of the window's surface, no window or surface is
+    /// needed. target must have the framebuffer format and the size given to
+    /// resize_framebuffer, see Texture::create_render_target. Read it back with
+    /// Texture::to_rgba_image
+    pub fn render_offscreen(
+        &mut self,
+        engine_state: &mut EngineState,
+        target: &Texture,
+    ) -> anyhow::Result<()> {
+        let target_size = (target.size.width, target.size.height);
+        let target_format = target.texture.format();
+        let framebuffer_format = self.constant_data.framebuffer_format;
+        if target_format.remove_srgb_suffix() != framebuffer_format.remove_srgb_suffix() {
+            anyhow::bail!(
+                "Render target format {target_format:?} should be {framebuffer_format:?}"
+            );
+        }
+        self.base.diagnostics.on_frame_started(
+            RenderSettingsSnapshot::from(&*self.data.lock().unwrap()),
+            target_size,
+            target_format,
+            None,
+        );
+        self.update_internal(engine_state, target_size, FrameKind::Final);
+        self.render_internal::<EmptyUiOverlay>(engine_state, &target.view, FrameKind::Final, None)
+    }
+
+    /// Renders only the shaded scene into target, in linear hdr like the shading texture and
+    /// without bloom, anti-aliasing, color grading, outlines or the post-process passes, so
+    /// it can be drawn back into the scene and go through those with the rest of the frame.
+    /// target must be Rgba16Float, see Texture::create_scaled_surface_texture. The skybox and
+    /// the transparent meshes are drawn after the tone mapping so the frame is still tone
+    /// mapped and the last pass undoes it
+    pub fn render_scene_hdr(
+        &mut self,
+        engine_state: &mut EngineState,
+        target: &Texture,
+    ) -> anyhow::Result<()> {
+        let target_size = (target.size.width, target.size.height);
+        let target_format = target.texture.format();
+        if target_format != wgpu::TextureFormat::Rgba16Float {
+            anyhow::bail!("Scene hdr target format {target_format:?} should be Rgba16Float");
+        }
+        self.base.diagnostics.on_frame_started(
+            RenderSettingsSnapshot::from(&*self.data.lock().unwrap()),
+            target_size,
+            target_format,
+            None,
+        );
+        self.update_internal(engine_state, target_size, FrameKind::SceneHdr);
+        self.render_internal::<EmptyUiOverlay>(
+            engine_state,
+            &target.view,
+            FrameKind::SceneHdr,
+            None,
         )
     }
 
@@ -3875,17 +5413,15 @@ impl Renderer {
     // per point light.
     fn get_node_culling_mask(
         node: &GameNode,
-        data: &RendererData,
-        engine_state: &EngineState,
+        is_on_screen_candidate: bool,
+        enable_directional_shadow_culling: bool,
+        scene: &Scene,
         camera_culling_frustum: &Frustum,
         point_lights_frusta: &PointLightFrustaWithCullingInfo,
         resolved_directional_light_cascades: &[Vec<ResolvedDirectionalLightCascade>],
         culling_mask: &mut BitVec,
     ) {
-        if DISABLE_FRUSTUM_CULLING
-            || USE_ORTHOGRAPHIC_CAMERA
-            || !data.enable_directional_shadow_culling
-        {
+        if DISABLE_FRUSTUM_CULLING || !enable_directional_shadow_culling {
             culling_mask.set_elements(usize::MAX);
         }
 
@@ -3898,7 +5434,7 @@ impl Renderer {
             culling_mask.set_elements(usize::MAX);
         }
 
-        let node_bounding_sphere = engine_state.scene.get_node_bounding_sphere_opt(node.id());
+        let node_bounding_sphere = scene.get_node_bounding_sphere_opt(node.id());
         let node_bounding_sphere_point = rapier3d_f64::na::Point3::new(
             node_bounding_sphere.center.x as f64,
             node_bounding_sphere.center.y as f64,
@@ -3915,7 +5451,8 @@ impl Renderer {
         culling_mask.set_elements(0);
         let mut mask_pos = 0;
 
-        let is_node_on_screen = is_touching_frustum(camera_culling_frustum);
+        let is_node_on_screen =
+            is_on_screen_candidate && is_touching_frustum(camera_culling_frustum);
 
         if is_node_on_screen {
             culling_mask.set(mask_pos, true);
@@ -3923,7 +5460,7 @@ impl Renderer {
 
         mask_pos += 1;
 
-        for (light_index, _) in engine_state.scene.directional_lights.iter().enumerate() {
+        for (light_index, _) in scene.directional_lights.iter().enumerate() {
             let light_cascades = &resolved_directional_light_cascades[light_index];
 
             // let mut fully_contained_cascades = 0;
@@ -3991,6 +5528,7 @@ impl Renderer {
         brdf_lut: &Texture,
         point_shadow_map_textures: &Texture,
         directional_shadow_map_textures: &Texture,
+        sdf_volume_texture: &Texture,
     ) -> wgpu::BindGroup {
         let sampler_cache_guard = base.sampler_cache.lock().unwrap();
 
@@ -4067,6 +5605,16 @@ impl Renderer {
                             .get_sampler_by_index(point_shadow_map_textures.sampler_index),
                     ),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 13,
+                    resource: wgpu::BindingResource::TextureView(&sdf_volume_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 14,
+                    resource: wgpu::BindingResource::Sampler(
+                        sampler_cache_guard.get_sampler_by_index(sdf_volume_texture.sampler_index),
+                    ),
+                },
             ],
             label: USE_LABELS.then_some("environment_textures_bind_group"),
         })
@@ -4086,6 +5634,31 @@ impl Renderer {
                 &private_data_guard.brdf_lut,
                 &private_data_guard.point_shadow_map_textures,
                 &private_data_guard.directional_shadow_map_textures,
+                &private_data_guard.sdf_volume_texture,
+            );
+    }
+
+    /// Uploads a baked distance field of the static geometry, which is then used for
+    /// directional shadows beyond the range of the shadow cascades and for ambient occlusion.
+    /// Pass None to remove it
+    pub fn set_sdf_volume(&self, volume: Option<&SdfVolume>) {
+        let mut private_data_guard = self.private_data.lock().unwrap();
+
+        private_data_guard.sdf_volume_texture =
+            Texture::create_sdf_volume_texture(&self.base, volume);
+        private_data_guard.sdf_volume_info =
+            volume.map(|volume| (volume.origin, volume.voxel_size, volume.dims));
+
+        private_data_guard.environment_textures_bind_group =
+            Self::get_environment_textures_bind_group(
+                &self.base,
+                &self.constant_data,
+                &private_data_guard.skyboxes,
+                &private_data_guard.skybox_weights_buffer,
+                &private_data_guard.brdf_lut,
+                &private_data_guard.point_shadow_map_textures,
+                &private_data_guard.directional_shadow_map_textures,
+                &private_data_guard.sdf_volume_texture,
             );
     }
 
@@ -4106,7 +5679,8 @@ impl Renderer {
     fn update_internal(
         &mut self,
         engine_state: &mut EngineState,
-        surface_config: &wgpu::SurfaceConfiguration,
+        framebuffer_size: (u32, u32),
+        frame_kind: FrameKind,
     ) {
         let mut data_guard = self.data.lock().unwrap();
         let data: &mut RendererData = &mut data_guard;
@@ -4114,7 +5688,7 @@ impl Renderer {
         let mut private_data_guard = self.private_data.lock().unwrap();
         let private_data: &mut RendererPrivateData = &mut private_data_guard;
 
-        let aspect_ratio = surface_config.width as f32 / surface_config.height as f32;
+        let surface_aspect_ratio = framebuffer_size.0 as f32 / framebuffer_size.1 as f32;
 
         let camera_transform = data
             .camera_node_id
@@ -4124,14 +5698,9 @@ impl Renderer {
 
         let camera_position = camera_transform.position();
 
-        let camera_frustum_desc = CameraFrustumDescriptor {
-            focal_point: camera_position,
-            forward_vector: (-camera_transform.z_axis).into(),
-            aspect_ratio,
-            near_plane_distance: NEAR_PLANE_DISTANCE,
-            far_plane_distance: FAR_PLANE_DISTANCE,
-            fov_y_rad: deg_to_rad(FOV_Y_DEG),
-        };
+        let camera_frustum_desc = data
+            .camera_lens
+            .frustum_descriptor(camera_transform, surface_aspect_ratio);
 
         let culling_frustum_desc = match private_data.frustum_culling_lock {
             CullingFrustumLock::Full(locked) => locked,
@@ -4144,6 +5713,53 @@ impl Renderer {
 
         let culling_frustum = Frustum::from(culling_frustum_desc);
 
+        // bounding spheres are from the last time the global transforms were computed,
+        // the fitting margin below covers for casters that moved since then
+        let shadow_caster_spheres: Vec<Sphere> = if data.enable_shadow_depth_fitting {
+            engine_state
+                .scene
+                .nodes()
+                .filter(|node| {
+                    matches!(
+                        node.visual.as_ref().map(|visual| &visual.material),
+                        Some(Material::Pbr { .. })
+                    )
+                })
+                .map(|node| engine_state.scene.get_node_bounding_sphere_opt(node.id()))
+                .collect()
+        } else {
+            vec![]
+        };
+
+        let directional_shadow_map_resolution = data
+            .directional_shadow_map_resolution
+            .clamp(1, self.base.limits.max_texture_dimension_2d);
+        if private_data.directional_shadow_map_textures.size.width
+            != directional_shadow_map_resolution
+        {
+            private_data.directional_shadow_map_textures = Texture::create_depth_texture_array(
+                &self.base,
+                (
+                    directional_shadow_map_resolution,
+                    directional_shadow_map_resolution,
+                ),
+                Some("directional_shadow_map_texture"),
+                DIRECTIONAL_LIGHT_SHOW_MAP_COUNT * MAX_SHADOW_CASCADES as u32,
+            );
+            private_data.environment_textures_bind_group =
+                Self::get_environment_textures_bind_group(
+                    &self.base,
+                    &self.constant_data,
+                    &private_data.skyboxes,
+                    &private_data.skybox_weights_buffer,
+                    &private_data.brdf_lut,
+                    &private_data.point_shadow_map_textures,
+                    &private_data.directional_shadow_map_textures,
+                    &private_data.sdf_volume_texture,
+                );
+            data.shadow_updates.invalidate();
+        }
+
         let mut resolved_directional_light_cascades = vec![];
         for directional_light in &engine_state.scene.directional_lights {
             let from_light_space =
@@ -4201,8 +5817,8 @@ impl Renderer {
                 let bounding_sphere =
                     light_space_frustum_slice.make_rotation_independent_bounding_sphere();
 
-                let pixel_size =
-                    2.0 * bounding_sphere.radius / DIRECTIONAL_LIGHT_SHADOW_MAP_RESOLUTION as f32;
+                let pixel_size = 2.0 * bounding_sphere.radius
+                    / private_data.directional_shadow_map_textures.size.width as f32;
 
                 let rounded_bounding_sphere_center = Vec3::new(
                     bounding_sphere.center.x - bounding_sphere.center.x % pixel_size,
@@ -4212,16 +5828,51 @@ impl Renderer {
 
                 let projection_volume_half_thickness = bounding_sphere.radius;
 
-                let projection_half_depth = (DIRECTIONAL_LIGHT_PROJ_BOX_LENGTH
+                let max_projection_half_depth = (DIRECTIONAL_LIGHT_PROJ_BOX_LENGTH
                     * projection_volume_half_thickness.sqrt()
                     + projection_volume_half_thickness)
                     / 2.0;
 
+                // depths are distances along the light direction.
                 // make sure the box's "far plane" is roughly at the edge of the frustum slice
-                let projection_center = from_light_space
-                    .transform_point3(rounded_bounding_sphere_center)
-                    - directional_light.direction
-                        * (projection_half_depth - projection_volume_half_thickness);
+                let world_space_bounding_sphere_center =
+                    from_light_space.transform_point3(rounded_bounding_sphere_center);
+                let far_depth = world_space_bounding_sphere_center.dot(directional_light.direction)
+                    + projection_volume_half_thickness;
+                let mut near_depth = far_depth - 2.0 * max_projection_half_depth;
+
+                // pull the "near plane" in to the closest caster whose shadow could land in the box,
+                // which gives the shadow map a lot more depth precision
+                if data.enable_shadow_depth_fitting {
+                    let max_lateral_distance =
+                        projection_volume_half_thickness * std::f32::consts::SQRT_2;
+                    let closest_caster_depth = shadow_caster_spheres
+                        .iter()
+                        .filter(|caster_sphere| {
+                            let to_caster =
+                                caster_sphere.center - world_space_bounding_sphere_center;
+                            let lateral_offset = to_caster
+                                - directional_light.direction
+                                    * to_caster.dot(directional_light.direction);
+                            lateral_offset.length() <= max_lateral_distance + caster_sphere.radius
+                        })
+                        .map(|caster_sphere| {
+                            caster_sphere.center.dot(directional_light.direction)
+                                - caster_sphere.radius
+                        })
+                        .fold(far_depth - 2.0 * projection_volume_half_thickness, f32::min);
+                    let fitting_margin = projection_volume_half_thickness * 0.1;
+                    near_depth = near_depth.max(closest_caster_depth - fitting_margin);
+                    // snap the depth range so it doesn't change a tiny bit every frame
+                    // as the casters move around
+                    near_depth =
+                        far_depth - ((far_depth - near_depth) / pixel_size).ceil() * pixel_size;
+                }
+
+                let projection_half_depth = (far_depth - near_depth) / 2.0;
+                let projection_center = world_space_bounding_sphere_center
+                    + directional_light.direction
+                        * (projection_volume_half_thickness - projection_half_depth);
 
                 let transform = {
                     let mut transform = Transform::from(
@@ -4308,8 +5959,10 @@ impl Renderer {
         // for large groups of instances because if only one of them is visible by the camera
         // then all the rest of them need to be drawn
         private_data.pbr_mesh_index_to_gpu_instances.clear();
-        let mut unlit_mesh_index_to_gpu_instances: HashMap<usize, Vec<GpuUnlitMeshInstance>> =
-            HashMap::new();
+        let mut unlit_mesh_index_to_gpu_instances: HashMap<
+            UnlitInstancesKey,
+            Vec<GpuUnlitMeshInstance>,
+        > = HashMap::new();
         let mut wireframe_mesh_index_to_gpu_instances: HashMap<
             usize,
             Vec<GpuWireframeMeshInstance>,
@@ -4327,10 +5980,13 @@ impl Renderer {
                     .scene
                     .get_node(point_light.node_id)
                     .map(|point_light_node| {
+                        // the far plane used for culling is pulled in to the shadow distance
                         let frustum_descriptors = build_cubemap_face_frusta(
                             point_light_node.transform.position(),
                             POINT_LIGHT_SHADOW_MAP_FRUSTUM_NEAR_PLANE,
-                            POINT_LIGHT_SHADOW_MAP_FRUSTUM_FAR_PLANE,
+                            data.shadow_updates
+                                .point_light_shadow_distance
+                                .min(POINT_LIGHT_SHADOW_MAP_FRUSTUM_FAR_PLANE),
                         );
 
                         // if the point light is inside the main camera view this means that
@@ -4385,6 +6041,85 @@ impl Renderer {
             camera_position,
         );
 
+        let shadow_casting_lights: Vec<_> = engine_state
+            .scene
+            .point_lights
+            .iter()
+            .map(|point_light| point_light.casts_shadows.then_some(point_light.node_id))
+            .collect();
+        let previous_layer_count = private_data.point_shadow_atlas.layer_count();
+        private_data.point_light_shadow_layers = private_data.point_shadow_atlas.allocate(
+            &shadow_casting_lights,
+            data.max_point_light_shadow_maps
+                .min(self.base.limits.max_texture_array_layers),
+        );
+        let point_shadow_map_resolution = data
+            .point_shadow_map_resolution
+            .clamp(1, self.base.limits.max_texture_dimension_2d / 6);
+        if private_data.point_shadow_atlas.layer_count() != previous_layer_count
+            || private_data.point_shadow_map_textures.size.height != point_shadow_map_resolution
+        {
+            private_data.point_shadow_map_textures = Texture::create_depth_texture_array(
+                &self.base,
+                (6 * point_shadow_map_resolution, point_shadow_map_resolution),
+                Some("point_shadow_map_texture"),
+                private_data.point_shadow_atlas.layer_count(),
+            );
+            private_data.environment_textures_bind_group =
+                Self::get_environment_textures_bind_group(
+                    &self.base,
+                    &self.constant_data,
+                    &private_data.skyboxes,
+                    &private_data.skybox_weights_buffer,
+                    &private_data.brdf_lut,
+                    &private_data.point_shadow_map_textures,
+                    &private_data.directional_shadow_map_textures,
+                    &private_data.sdf_volume_texture,
+                );
+            // the new array starts out empty
+            data.shadow_updates.invalidate();
+        }
+
+        let point_light_shadow_infos: Vec<_> = engine_state
+            .scene
+            .point_lights
+            .iter()
+            .zip(&private_data.point_light_casters_fingerprints)
+            .zip(&private_data.point_light_shadow_layers)
+            .map(|((point_light, casters_fingerprint), shadow_layer)| {
+                let shadow_layer = (*shadow_layer)?;
+                engine_state
+                    .scene
+                    .get_node(point_light.node_id)
+                    .map(|node| PointLightShadowInfo {
+                        node_id: point_light.node_id,
+                        position: node.transform.position(),
+                        casters_fingerprint: *casters_fingerprint,
+                        shadow_layer,
+                    })
+            })
+            .collect();
+        if data.enable_shadows {
+            private_data.point_light_shadow_updates = data
+                .shadow_updates
+                .schedule(&point_light_shadow_infos, camera_position);
+        } else {
+            // the shadow maps aren't kept up to date while the shadows are off
+            data.shadow_updates.invalidate();
+        }
+
+        data.custom_materials.prepare_pipelines(
+            device,
+            [
+                &private_data.camera_lights_and_pbr_shader_options_bind_group_layout,
+                &self.constant_data.bones_and_instances_bind_group_layout,
+            ],
+            unlit_mesh_index_to_gpu_instances
+                .keys()
+                .filter_map(|key| key.custom_material)
+                .map(|(material_id, _)| material_id),
+        );
+
         let min_storage_buffer_offset_alignment =
             self.base.limits.min_storage_buffer_offset_alignment;
 
@@ -4411,6 +6146,14 @@ impl Renderer {
             .iter()
             .map(|(_, (_, culling_mask, _))| culling_mask.clone())
             .collect();
+        private_data.all_pbr_instances_alpha_masked = pbr_mesh_instances
+            .iter()
+            .map(|(_, (instances, _, _))| {
+                instances
+                    .iter()
+                    .any(|instance| instance.alpha_cutoff >= 0.0)
+            })
+            .collect();
 
         private_data.all_pbr_instances.replace(
             pbr_mesh_instances
@@ -4452,8 +6195,11 @@ impl Renderer {
             );
         }
 
+        let mut unlit_instances: Vec<_> = unlit_mesh_index_to_gpu_instances.into_iter().collect();
+        // grouped by pipeline, the built-in one first
+        unlit_instances.sort_by_key(|(key, _)| *key);
         private_data.all_unlit_instances.replace(
-            unlit_mesh_index_to_gpu_instances
+            unlit_instances
                 .into_iter()
                 .map(|(key, instances)| (key, instances.into_boxed_slice())),
             min_storage_buffer_offset_alignment as usize,
@@ -4669,26 +6415,45 @@ impl Renderer {
 
         // collect all camera data
 
-        // main camera
-        let main_camera_shader_data = if USE_ORTHOGRAPHIC_CAMERA {
-            ShaderCameraData::orthographic(
-                camera_transform.into(),
-                20.0 * aspect_ratio,
-                20.0,
-                -1000.0,
-                1000.0,
-                false,
-            )
+        let render_resolution = Vec2::new(
+            private_data.shading_texture.size.width as f32,
+            private_data.shading_texture.size.height as f32,
+        );
+        let is_final_frame = frame_kind == FrameKind::Final;
+        let camera_jitter = if is_final_frame {
+            data.camera_jitter + data.taa.next_jitter()
         } else {
-            ShaderCameraData::perspective(
-                camera_transform.into(),
-                aspect_ratio,
-                NEAR_PLANE_DISTANCE,
-                FAR_PLANE_DISTANCE,
-                deg_to_rad(FOV_Y_DEG),
-                true,
-            )
+            Vec2::ZERO
         };
+        // pixel y goes down while clip space y goes up
+        let camera_jitter_clip_space = Vec2::new(2.0, -2.0) * camera_jitter / render_resolution;
+
+        // main camera
+        let mut main_camera_shader_data = data
+            .camera_lens
+            .shader_camera_data(camera_transform.into(), surface_aspect_ratio)
+            .with_clip_planes(&data.clip_planes);
+        let unjittered_camera_shader_data = main_camera_shader_data;
+        if camera_jitter != Vec2::ZERO {
+            main_camera_shader_data.proj =
+                Mat4::from_translation(camera_jitter_clip_space.extend(0.0))
+                    * main_camera_shader_data.proj;
+        }
+        if is_final_frame {
+            data.outlines.update(
+                &self.base,
+                &engine_state.scene,
+                &data.asset_registry,
+                main_camera_shader_data.proj * main_camera_shader_data.view,
+                private_data.tone_mapping_texture.size,
+            );
+            data.taa.update(
+                &self.base,
+                unjittered_camera_shader_data.proj * unjittered_camera_shader_data.view,
+                camera_jitter_clip_space,
+                private_data.shading_texture.size,
+            );
+        }
         all_camera_data.push(main_camera_shader_data);
 
         // directional lights
@@ -4720,7 +6485,24 @@ impl Renderer {
         // main camera but only rotation, for skybox
         all_camera_data.push(all_camera_data[0]);
 
-        // write all camera data, adding new buffers if necessary
+        let point_lights_buffer_changed_capacity = private_data.point_lights_buffer.write(
+            device,
+            queue,
+            &make_point_light_uniform_buffer(engine_state, &private_data.point_light_shadow_layers),
+        );
+        if point_lights_buffer_changed_capacity {
+            log::debug!(
+                "Resized point lights buffer capacity to {:?} bytes, light count={:?}",
+                private_data.point_lights_buffer.capacity_bytes(),
+                engine_state.scene.point_lights.len(),
+            );
+            // they're recreated below with the new buffer
+            private_data
+                .camera_lights_and_pbr_shader_options_bind_groups
+                .clear();
+        }
+
+        // write all camera data, adding new buffers and bind groups if necessary
         for (i, camera_data) in all_camera_data.iter().enumerate() {
             let contents = if i == all_camera_data.len() - 1 {
                 bytemuck::cast_slice(&[SkyboxShaderCameraRaw::from(*camera_data)]).to_vec()
@@ -4739,6 +6521,14 @@ impl Renderer {
                                 usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
                             }),
                     );
+            } else {
+                queue.write_buffer(&private_data.camera_buffers[i], 0, &contents)
+            }
+            if private_data
+                .camera_lights_and_pbr_shader_options_bind_groups
+                .len()
+                == i
+            {
                 private_data
                     .camera_lights_and_pbr_shader_options_bind_groups
                     .push(
@@ -4757,6 +6547,7 @@ impl Renderer {
                                         binding: 1,
                                         resource: private_data
                                             .point_lights_buffer
+                                            .src()
                                             .as_entire_binding(),
                                     },
                                     wgpu::BindGroupEntry {
@@ -4771,13 +6562,17 @@ impl Renderer {
                                             .pbr_shader_options_buffer
                                             .as_entire_binding(),
                                     },
+                                    wgpu::BindGroupEntry {
+                                        binding: 4,
+                                        resource: private_data
+                                            .frame_constants_buffer
+                                            .as_entire_binding(),
+                                    },
                                 ],
                                 label: USE_LABELS
                                     .then_some("camera_lights_and_pbr_shader_options_bind_group"),
                             }),
                     );
-            } else {
-                queue.write_buffer(&private_data.camera_buffers[i], 0, &contents)
             }
         }
 
@@ -4815,11 +6610,6 @@ impl Renderer {
             ),
         );
 
-        queue.write_buffer(
-            &private_data.point_lights_buffer,
-            0,
-            bytemuck::cast_slice(&make_point_light_uniform_buffer(engine_state)),
-        );
         queue.write_buffer(
             &private_data.directional_lights_buffer,
             0,
@@ -4832,8 +6622,10 @@ impl Renderer {
             &private_data.tone_mapping_config_buffer,
             0,
             bytemuck::cast_slice(&[
-                data.tone_mapping_exposure,
-                if data.bloom_type == BloomType::New {
+                data.tone_mapping_exposure * data.camera_lens.exposure_multiplier(),
+                if !is_final_frame {
+                    0.0
+                } else if data.bloom_type == BloomType::New {
                     data.new_bloom_intensity
                 } else {
                     -1.0f32
@@ -4869,6 +6661,42 @@ impl Renderer {
             0,
             bytemuck::cast_slice(&[data.new_bloom_radius, 0.0f32, 0.0f32, 0.0f32]),
         );
+        let time_tracker = engine_state.time_tracker.unwrap_or_default();
+        // the animated materials follow the slow motion and the pause
+        let time_control = &engine_state.time_control;
+        queue.write_buffer(
+            &private_data.frame_constants_buffer,
+            0,
+            bytemuck::cast_slice(&[FrameConstantsUniform {
+                time_seconds: time_control.elapsed_seconds(TimeChannel::Effects) as f32,
+                delta_time_seconds: time_control.delta_seconds(TimeChannel::Effects) as f32,
+                frame_index: private_data.frame_index,
+                _padding: 0,
+                render_resolution: [
+                    render_resolution.x,
+                    render_resolution.y,
+                    1.0 / render_resolution.x,
+                    1.0 / render_resolution.y,
+                ],
+                jitter: [
+                    camera_jitter.x,
+                    camera_jitter.y,
+                    camera_jitter_clip_space.x,
+                    camera_jitter_clip_space.y,
+                ],
+            }]),
+        );
+        private_data.frame_index = private_data.frame_index.wrapping_add(1);
+        if is_final_frame {
+            data.color_grading
+                .update(&self.base, time_tracker.last_frame_time());
+        }
+
+        let blob_shadow_params = if data.enable_blob_shadows && !data.enable_shadows {
+            make_blob_shadow_shader_params(engine_state, data)
+        } else {
+            BlobShadowShaderParams::default()
+        };
         queue.write_buffer(
             &private_data.pbr_shader_options_buffer,
             0,
@@ -4879,6 +6707,21 @@ impl Renderer {
                 data.enable_shadow_debug,
                 data.enable_cascade_debug,
                 data.soft_shadow_grid_dims,
+                private_data
+                    .sdf_volume_info
+                    .filter(|_| data.enable_sdf_shadows)
+                    .map(|(origin, voxel_size, dims)| SdfShadowShaderParams {
+                        origin,
+                        voxel_size,
+                        dims,
+                        softness: data.sdf_shadow_softness.max(0.001),
+                        ao_strength: data.sdf_ao_strength,
+                    }),
+                data.enable_shadows,
+                &blob_shadow_params,
+                data.shadow_normal_offset,
+                &data.shadow_filter,
+                data.parallax_max_step_count,
             )]),
         );
         queue.write_buffer(
@@ -4887,18 +6730,31 @@ impl Renderer {
             bytemuck::cast_slice(&[
                 private_data.skybox_weights[0],
                 private_data.skybox_weights[1],
-                0.0,
+                data.environment_intensity,
                 0.0,
             ]),
         );
+        if is_final_frame {
+            data.path_tracer.update(
+                &self.base,
+                &engine_state.scene,
+                &data.retained_cpu_data,
+                &data.binded_pbr_materials,
+                &unjittered_camera_shader_data,
+                private_data.shading_texture.size,
+                private_data.skybox_weights,
+                data.environment_intensity,
+            );
+        }
     }
 
     #[profiling::function]
-    pub fn render_internal<UiOverlay>(
+    fn render_internal<UiOverlay>(
         &mut self,
         engine_state: &mut EngineState,
-        surface_texture: wgpu::SurfaceTexture,
-        ui_overlay: &mut IkariUiContainer<UiOverlay>,
+        target_view: &wgpu::TextureView,
+        frame_kind: FrameKind,
+        ui_overlay: Option<&mut IkariUiContainer<UiOverlay>>,
     ) -> anyhow::Result<()>
     where
         UiOverlay: iced_winit::runtime::Program<Renderer = iced::Renderer> + 'static,
@@ -4912,13 +6768,12 @@ impl Renderer {
         let mut profiler_guard = self.profiler.lock().unwrap();
         let profiler: &mut wgpu_profiler::GpuProfiler = &mut profiler_guard;
 
-        let surface_texture_view =
-            surface_texture
-                .texture
-                .create_view(&wgpu::TextureViewDescriptor {
-                    format: Some(surface_texture.texture.format().add_srgb_suffix()),
-                    ..Default::default()
-                });
+        let is_final_frame = frame_kind == FrameKind::Final;
+        let bloom_type = if is_final_frame {
+            data.bloom_type
+        } else {
+            BloomType::Disabled
+        };
 
         let mut encoder = self
             .base
@@ -4962,6 +6817,7 @@ impl Renderer {
                         timestamp_writes: None, // overwritten by wgpu_profiler
                     };
 
+                    self.base.diagnostics.record_pass(pass_label);
                     let mut profiler_scope =
                         profiler.scope(pass_label, &mut encoder, &self.base.device);
 
@@ -4976,6 +6832,11 @@ impl Renderer {
                         private_data,
                         &mut render_pass,
                         &self.constant_data.directional_shadow_map_pipeline,
+                        Some(
+                            &self
+                                .constant_data
+                                .directional_shadow_map_alpha_masked_pipeline,
+                        ),
                         &private_data.camera_lights_and_pbr_shader_options_bind_groups
                             [culling_mask_camera_index],
                         true,
@@ -4987,19 +6848,27 @@ impl Renderer {
             }
 
             for light_index in 0..engine_state.scene.point_lights.len() {
-                if light_index >= POINT_LIGHT_SHOW_MAP_COUNT as usize {
+                let Some(shadow_layer) = private_data.point_light_shadow_layers[light_index] else {
+                    culling_mask_camera_index += 6;
                     continue;
-                }
+                };
                 if let Some(light_node) = engine_state
                     .scene
                     .get_node(engine_state.scene.point_lights[light_index].node_id)
                 {
+                    // the shadow map from the last update is still in the texture
+                    if !private_data.point_light_shadow_updates[light_index] {
+                        culling_mask_camera_index += 6;
+                        continue;
+                    }
+
                     let pass_label = "Point light shadow map";
+                    let face_resolution = private_data.point_shadow_map_textures.size.height as f32;
 
                     let texture_view = private_data.point_shadow_map_textures.texture.create_view(
                         &wgpu::TextureViewDescriptor {
                             dimension: Some(wgpu::TextureViewDimension::D2),
-                            base_array_layer: light_index.try_into().unwrap(),
+                            base_array_layer: shadow_layer,
                             array_layer_count: Some(1),
                             ..Default::default()
                         },
@@ -5020,6 +6889,7 @@ impl Renderer {
                         timestamp_writes: None, // overwritten by wgpu_profiler
                     };
 
+                    self.base.diagnostics.record_pass(pass_label);
                     let mut profiler_scope =
                         profiler.scope(pass_label, &mut encoder, &self.base.device);
 
@@ -5040,10 +6910,10 @@ impl Renderer {
                     .enumerate()
                     .for_each(|(face_index, _face_view_proj_matrices)| {
                         render_pass.set_viewport(
-                            (face_index * POINT_LIGHT_SHADOW_MAP_RESOLUTION as usize) as f32,
+                            face_index as f32 * face_resolution,
                             0.0,
-                            POINT_LIGHT_SHADOW_MAP_RESOLUTION as f32,
-                            POINT_LIGHT_SHADOW_MAP_RESOLUTION as f32,
+                            face_resolution,
+                            face_resolution,
                             0.0,
                             1.0,
                         );
@@ -5053,6 +6923,7 @@ impl Renderer {
                             private_data,
                             &mut render_pass,
                             &self.constant_data.point_shadow_map_pipeline,
+                            Some(&self.constant_data.point_shadow_map_alpha_masked_pipeline),
                             &private_data.camera_lights_and_pbr_shader_options_bind_groups
                                 [culling_mask_camera_index],
                             true,
@@ -5090,6 +6961,7 @@ impl Renderer {
                 timestamp_writes: None, // overwritten by wgpu_profiler
             };
 
+            self.base.diagnostics.record_pass(depth_prepass_pass_label);
             let mut profiler_scope =
                 profiler.scope(depth_prepass_pass_label, &mut encoder, &self.base.device);
 
@@ -5104,6 +6976,7 @@ impl Renderer {
                 private_data,
                 &mut render_pass,
                 &self.constant_data.depth_prepass_pipeline,
+                None,
                 &private_data.camera_lights_and_pbr_shader_options_bind_groups[0],
                 false,
                 0, // use main camera culling mask
@@ -5139,6 +7012,7 @@ impl Renderer {
                 timestamp_writes: None, // overwritten by wgpu_profiler
             };
 
+            self.base.diagnostics.record_pass(pbr_meshes_pass_label);
             let mut profiler_scope =
                 profiler.scope(pbr_meshes_pass_label, &mut encoder, &self.base.device);
 
@@ -5153,15 +7027,31 @@ impl Renderer {
                 private_data,
                 &mut render_pass,
                 &self.constant_data.mesh_pipeline,
+                None,
                 &private_data.camera_lights_and_pbr_shader_options_bind_groups[0],
                 false,
                 0, // use main camera culling mask
             );
         }
 
+        if is_final_frame && data.path_tracer.is_enabled() {
+            let pass_label = "Path tracer";
+
+            self.base.diagnostics.record_pass(pass_label);
+            let profiler_scope = profiler.scope(pass_label, &mut encoder, &self.base.device);
+
+            data.path_tracer.render(
+                &self.base,
+                profiler_scope.recorder,
+                &private_data.skyboxes,
+                &private_data.shading_texture,
+            );
+        }
+
         {
             let pass_label = "Unlit and wireframe";
 
+            self.base.diagnostics.record_pass(pass_label);
             let mut profiler_scope = profiler.scope(pass_label, &mut encoder, &self.base.device);
 
             let mut render_pass = profiler_scope.scoped_render_pass(
@@ -5197,19 +7087,71 @@ impl Renderer {
                 &private_data.camera_lights_and_pbr_shader_options_bind_groups[0],
                 &[],
             );
+            let mut current_custom_material = None;
             for unlit_instance_chunk in private_data.all_unlit_instances.chunks() {
-                let binded_unlit_mesh_index = unlit_instance_chunk.id;
+                let UnlitInstancesKey {
+                    custom_material,
+                    mesh_index: binded_unlit_mesh_index,
+                } = unlit_instance_chunk.id;
                 let instances_buffer_start_index = unlit_instance_chunk.start_index as u32;
                 let instance_count = (unlit_instance_chunk.end_index
                     - unlit_instance_chunk.start_index)
                     / private_data.all_unlit_instances.stride();
 
+                match custom_material {
+                    Some((material_id, user_data)) => {
+                        let label = data.custom_materials.label(material_id);
+                        let Some(pipeline) = data.custom_materials.pipeline(material_id) else {
+                            log::warn!(
+                                "Custom material {label:?} has no pipeline, e.g. because its \
+                                 shader failed to compile, its meshes are skipped"
+                            );
+                            continue;
+                        };
+                        let Some(user_data_bind_group) = data
+                            .custom_materials
+                            .user_data_bind_group(material_id, user_data)
+                        else {
+                            log::warn!(
+                                "Custom material {label:?} is missing its user data, its \
+                                 meshes are skipped"
+                            );
+                            continue;
+                        };
+                        if current_custom_material != custom_material {
+                            render_pass.set_pipeline(pipeline);
+                            if let Some(user_data_bind_group) = user_data_bind_group {
+                                render_pass.set_bind_group(2, user_data_bind_group, &[]);
+                            }
+                            current_custom_material = custom_material;
+                        }
+                    }
+                    None => {
+                        if current_custom_material.is_some() {
+                            render_pass.set_pipeline(&self.constant_data.unlit_mesh_pipeline);
+                            current_custom_material = None;
+                        }
+                    }
+                }
+
                 let geometry_buffers = &data.binded_meshes[binded_unlit_mesh_index];
 
+                // custom materials that ask for skinning read the bones of their own mesh
+                let bone_transforms_buffer_start_index = private_data
+                    .all_bone_transforms
+                    .animated_bone_transforms
+                    .iter()
+                    .find(|bone_slice| bone_slice.mesh_index == binded_unlit_mesh_index)
+                    .map(|bone_slice| bone_slice.start_index.try_into().unwrap())
+                    .unwrap_or(0);
+
                 render_pass.set_bind_group(
                     1,
                     &private_data.bones_and_unlit_instances_bind_group,
-                    &[0, instances_buffer_start_index],
+                    &[
+                        bone_transforms_buffer_start_index,
+                        instances_buffer_start_index,
+                    ],
                 );
                 render_pass.set_vertex_buffer(0, geometry_buffers.vertex_buffer.src().slice(..));
                 render_pass.set_index_buffer(
@@ -5271,13 +7213,39 @@ impl Renderer {
             }
         }
 
-        match data.bloom_type {
+        if is_final_frame {
+            self.render_post_process_passes(
+                data,
+                private_data,
+                profiler,
+                &mut encoder,
+                PostProcessStage::AfterShading,
+            );
+        }
+
+        if is_final_frame && data.taa.is_enabled() {
+            let pass_label = "TAA";
+
+            self.base.diagnostics.record_pass(pass_label);
+            let profiler_scope = profiler.scope(pass_label, &mut encoder, &self.base.device);
+
+            data.taa.render(
+                &self.base,
+                profiler_scope.recorder,
+                &private_data.shading_texture,
+                &private_data.depth_texture,
+                &private_data.post_process_texture,
+            );
+        }
+
+        match bloom_type {
             BloomType::Old => {
                 private_data.bloom_threshold_cleared = false;
 
                 {
                     let pass_label = "Bloom threshold";
 
+                    self.base.diagnostics.record_pass(pass_label);
                     let mut profiler_scope =
                         profiler.scope(pass_label, &mut encoder, &self.base.device);
 
@@ -5303,6 +7271,7 @@ impl Renderer {
                     render_pass.set_pipeline(&self.constant_data.bloom_threshold_pipeline);
                     render_pass.set_bind_group(0, &private_data.shading_texture_bind_group, &[]);
                     render_pass.set_bind_group(1, &private_data.bloom_config_bind_groups[0], &[]);
+                    render_pass.set_bind_group(2, &private_data.frame_constants_bind_group, &[]);
                     render_pass.draw(0..3, 0..1);
                 }
 
@@ -5312,6 +7281,7 @@ impl Renderer {
                      horizontal: bool| {
                         let pass_label = "Bloom blur";
 
+                        self.base.diagnostics.record_pass(pass_label);
                         let mut profiler_scope =
                             profiler.scope(pass_label, &mut encoder, &self.base.device);
 
@@ -5341,6 +7311,11 @@ impl Renderer {
                             &private_data.bloom_config_bind_groups[if horizontal { 0 } else { 1 }],
                             &[],
                         );
+                        render_pass.set_bind_group(
+                            2,
+                            &private_data.frame_constants_bind_group,
+                            &[],
+                        );
                         render_pass.draw(0..3, 0..1);
                     };
 
@@ -5361,6 +7336,7 @@ impl Renderer {
                 for mip_index in 0..NEW_BLOOM_MIP_LEVEL_COUNT as usize {
                     let pass_label = "New Bloom Downscale";
 
+                    self.base.diagnostics.record_pass(pass_label);
                     let mut profiler_scope =
                         profiler.scope(pass_label, &mut encoder, &self.base.device);
 
@@ -5397,12 +7373,14 @@ impl Renderer {
                         &private_data.new_bloom_downscale_config_bind_groups[mip_index],
                         &[],
                     );
+                    render_pass.set_bind_group(2, &private_data.frame_constants_bind_group, &[]);
                     render_pass.draw(0..3, 0..1);
                 }
 
                 for mip_index in (1..NEW_BLOOM_MIP_LEVEL_COUNT as usize).rev() {
                     let pass_label = "New Bloom Upscale";
 
+                    self.base.diagnostics.record_pass(pass_label);
                     let mut profiler_scope =
                         profiler.scope(pass_label, &mut encoder, &self.base.device);
 
@@ -5436,17 +7414,19 @@ impl Renderer {
                         &private_data.new_bloom_upscale_config_bind_group,
                         &[],
                     );
+                    render_pass.set_bind_group(2, &private_data.frame_constants_bind_group, &[]);
                     render_pass.draw(0..3, 0..1);
                 }
             }
             BloomType::Disabled => {}
         };
 
-        if (data.bloom_type == BloomType::New || data.bloom_type == BloomType::Disabled)
+        if (bloom_type == BloomType::New || bloom_type == BloomType::Disabled)
             && !private_data.bloom_threshold_cleared
         {
             let pass_label = "Bloom clear";
 
+            self.base.diagnostics.record_pass(pass_label);
             let mut profiler_scope = profiler.scope(pass_label, &mut encoder, &self.base.device);
 
             profiler_scope.scoped_render_pass(
@@ -5469,11 +7449,12 @@ impl Renderer {
             );
             private_data.bloom_threshold_cleared = true;
         }
-        if (data.bloom_type == BloomType::Old || data.bloom_type == BloomType::Disabled)
+        if (bloom_type == BloomType::Old || bloom_type == BloomType::Disabled)
             && !private_data.new_bloom_cleared
         {
             let pass_label = "New Bloom clear";
 
+            self.base.diagnostics.record_pass(pass_label);
             let mut profiler_scope = profiler.scope(pass_label, &mut encoder, &self.base.device);
 
             profiler_scope.scoped_render_pass(
@@ -5500,6 +7481,7 @@ impl Renderer {
         {
             let pass_label = "Skybox";
 
+            self.base.diagnostics.record_pass(pass_label);
             let mut profiler_scope = profiler.scope(pass_label, &mut encoder, &self.base.device);
 
             let mut render_pass = profiler_scope.scoped_render_pass(
@@ -5560,6 +7542,7 @@ impl Renderer {
         {
             let pass_label = "Tone mapping";
 
+            self.base.diagnostics.record_pass(pass_label);
             let mut profiler_scope = profiler.scope(pass_label, &mut encoder, &self.base.device);
 
             let mut render_pass = profiler_scope.scoped_render_pass(
@@ -5583,7 +7566,7 @@ impl Renderer {
             render_pass.set_pipeline(&self.constant_data.tone_mapping_pipeline);
             render_pass.set_bind_group(
                 0,
-                if data.bloom_type == BloomType::New {
+                if bloom_type == BloomType::New {
                     &private_data.shading_and_new_bloom_texture_bind_group
                 } else {
                     &private_data.shading_and_bloom_textures_bind_group
@@ -5591,12 +7574,14 @@ impl Renderer {
                 &[],
             );
             render_pass.set_bind_group(1, &private_data.tone_mapping_config_bind_group, &[]);
+            render_pass.set_bind_group(2, &private_data.frame_constants_bind_group, &[]);
             render_pass.draw(0..3, 0..1);
         }
 
         {
             let pass_label = "Transparent";
 
+            self.base.diagnostics.record_pass(pass_label);
             let mut profiler_scope = profiler.scope(pass_label, &mut encoder, &self.base.device);
 
             let mut render_pass = profiler_scope.scoped_render_pass(
@@ -5660,9 +7645,74 @@ impl Renderer {
             }
         }
 
+        if is_final_frame && data.color_grading.is_active() {
+            let pass_label = "Color grading";
+
+            self.base.diagnostics.record_pass(pass_label);
+            let mut profiler_scope = profiler.scope(pass_label, &mut encoder, &self.base.device);
+
+            {
+                let mut render_pass = profiler_scope.scoped_render_pass(
+                    pass_label,
+                    &self.base.device,
+                    wgpu::RenderPassDescriptor {
+                        label: USE_LABELS.then_some(pass_label),
+                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                            view: &private_data.post_process_texture.view,
+                            resolve_target: None,
+                            ops: wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                                store: wgpu::StoreOp::Store,
+                            },
+                        })],
+                        depth_stencil_attachment: None,
+                        occlusion_query_set: None,
+                        timestamp_writes: None, // overwritten by wgpu_profiler
+                    },
+                );
+                render_pass.set_pipeline(data.color_grading.pipeline());
+                render_pass.set_bind_group(0, &private_data.tone_mapping_texture_bind_group, &[]);
+                render_pass.set_bind_group(1, data.color_grading.bind_group(), &[]);
+                render_pass.draw(0..3, 0..1);
+            }
+
+            profiler_scope.recorder.copy_texture_to_texture(
+                private_data.post_process_texture.texture.as_image_copy(),
+                private_data.tone_mapping_texture.texture.as_image_copy(),
+                private_data.tone_mapping_texture.size,
+            );
+        }
+
+        if is_final_frame && data.outlines.is_active() {
+            let pass_label = "Outlines";
+
+            self.base.diagnostics.record_pass(pass_label);
+            let profiler_scope = profiler.scope(pass_label, &mut encoder, &self.base.device);
+
+            data.outlines.render(
+                profiler_scope.recorder,
+                &data.binded_meshes,
+                &private_data.tone_mapping_texture.view,
+            );
+        }
+
+        if is_final_frame {
+            self.render_post_process_passes(
+                data,
+                private_data,
+                profiler,
+                &mut encoder,
+                PostProcessStage::AfterToneMapping,
+            );
+        }
+
         {
-            let pass_label = "Surface blit";
+            let pass_label = match frame_kind {
+                FrameKind::Final => "Surface blit",
+                FrameKind::SceneHdr => "HDR blit",
+            };
 
+            self.base.diagnostics.record_pass(pass_label);
             let mut profiler_scope = profiler.scope(pass_label, &mut encoder, &self.base.device);
 
             let mut render_pass = profiler_scope.scoped_render_pass(
@@ -5671,7 +7721,7 @@ impl Renderer {
                 wgpu::RenderPassDescriptor {
                     label: USE_LABELS.then_some(pass_label),
                     color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                        view: &surface_texture_view,
+                        view: target_view,
                         resolve_target: None,
                         ops: wgpu::Operations {
                             load: wgpu::LoadOp::Clear(black),
@@ -5684,20 +7734,25 @@ impl Renderer {
                 },
             );
 
-            render_pass.set_pipeline(&self.constant_data.surface_blit_pipeline);
+            render_pass.set_pipeline(match frame_kind {
+                FrameKind::Final => &self.constant_data.surface_blit_pipeline,
+                FrameKind::SceneHdr => &self.constant_data.hdr_blit_pipeline,
+            });
             render_pass.set_bind_group(0, &private_data.tone_mapping_texture_bind_group, &[]);
             render_pass.set_bind_group(1, &private_data.tone_mapping_config_bind_group, &[]);
+            render_pass.set_bind_group(2, &private_data.frame_constants_bind_group, &[]);
             render_pass.draw(0..3, 0..1);
         }
 
-        {
+        if let Some(ui_overlay) = ui_overlay {
+            self.base.diagnostics.record_pass("UI overlay");
             let profiler_scope = profiler.scope("UI overlay", &mut encoder, &self.base.device);
 
             ui_overlay.render(
                 &self.base.device,
                 &self.base.queue,
                 profiler_scope.recorder,
-                &surface_texture_view,
+                target_view,
             );
         }
 
@@ -5705,8 +7760,6 @@ impl Renderer {
 
         self.base.queue.submit(std::iter::once(encoder.finish()));
 
-        surface_texture.present();
-
         profiler.end_frame()?;
 
         Ok(())
@@ -5718,6 +7771,8 @@ impl Renderer {
         private_data: &'a RendererPrivateData,
         render_pass: &mut wgpu::RenderPass<'a>,
         pipeline: &'a wgpu::RenderPipeline,
+        // used instead of pipeline for the chunks with an alpha cutoff
+        alpha_masked_pipeline: Option<&'a wgpu::RenderPipeline>,
         camera_lights_shader_options_bind_group: &'a wgpu::BindGroup,
         is_shadow: bool,
         culling_mask_camera_index: usize,
@@ -5731,6 +7786,7 @@ impl Renderer {
         }
 
         render_pass.set_pipeline(pipeline);
+        let mut is_alpha_masked_pipeline_set = false;
 
         render_pass.set_bind_group(0, camera_lights_shader_options_bind_group, &[]);
         if !is_shadow {
@@ -5746,6 +7802,19 @@ impl Renderer {
                 continue;
             }
 
+            if let Some(alpha_masked_pipeline) = alpha_masked_pipeline {
+                let is_alpha_masked =
+                    private_data.all_pbr_instances_alpha_masked[pbr_instance_chunk_index];
+                if is_alpha_masked != is_alpha_masked_pipeline_set {
+                    render_pass.set_pipeline(if is_alpha_masked {
+                        alpha_masked_pipeline
+                    } else {
+                        pipeline
+                    });
+                    is_alpha_masked_pipeline_set = is_alpha_masked;
+                }
+            }
+
             let (mesh_index, pbr_material_index) = pbr_instance_chunk.id;
             let bone_transforms_buffer_start_index = private_data
                 .all_bone_transforms
@@ -5796,6 +7865,69 @@ impl Renderer {
     }
 
     #[profiling::function]
+    /// Each pass reads the stage's texture and writes to the post-process texture, which is
+    /// copied back for the next one
+    fn render_post_process_passes(
+        &self,
+        data: &RendererData,
+        private_data: &RendererPrivateData,
+        profiler: &mut wgpu_profiler::GpuProfiler,
+        encoder: &mut wgpu::CommandEncoder,
+        stage: PostProcessStage,
+    ) {
+        let (texture, texture_bind_group) = match stage {
+            PostProcessStage::AfterShading => (
+                &private_data.shading_texture,
+                &private_data.shading_texture_bind_group,
+            ),
+            PostProcessStage::AfterToneMapping => (
+                &private_data.tone_mapping_texture,
+                &private_data.tone_mapping_texture_bind_group,
+            ),
+        };
+
+        for (pass_label, pipeline, uniform_bind_group) in
+            data.post_process_passes.enabled_passes(stage)
+        {
+            self.base.diagnostics.record_pass("Post-process");
+            let mut profiler_scope = profiler.scope(pass_label, encoder, &self.base.device);
+
+            {
+                let mut render_pass = profiler_scope.scoped_render_pass(
+                    pass_label,
+                    &self.base.device,
+                    wgpu::RenderPassDescriptor {
+                        label: USE_LABELS.then_some(pass_label),
+                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                            view: &private_data.post_process_texture.view,
+                            resolve_target: None,
+                            ops: wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                                store: wgpu::StoreOp::Store,
+                            },
+                        })],
+                        depth_stencil_attachment: None,
+                        occlusion_query_set: None,
+                        timestamp_writes: None, // overwritten by wgpu_profiler
+                    },
+                );
+                render_pass.set_pipeline(pipeline);
+                render_pass.set_bind_group(0, texture_bind_group, &[]);
+                render_pass.set_bind_group(1, &private_data.frame_constants_bind_group, &[]);
+                if let Some(uniform_bind_group) = uniform_bind_group {
+                    render_pass.set_bind_group(2, uniform_bind_group, &[]);
+                }
+                render_pass.draw(0..3, 0..1);
+            }
+
+            profiler_scope.recorder.copy_texture_to_texture(
+                private_data.post_process_texture.texture.as_image_copy(),
+                texture.texture.as_image_copy(),
+                texture.size,
+            );
+        }
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn prepare_and_cull_instances(
         engine_state: &EngineState,
@@ -5805,7 +7937,10 @@ impl Renderer {
         point_lights_frusta: &PointLightFrustaWithCullingInfo,
         resolved_directional_light_cascades: &[Vec<ResolvedDirectionalLightCascade>],
         wireframe_mesh_index_to_gpu_instances: &mut HashMap<usize, Vec<GpuWireframeMeshInstance>>,
-        unlit_mesh_index_to_gpu_instances: &mut HashMap<usize, Vec<GpuUnlitMeshInstance>>,
+        unlit_mesh_index_to_gpu_instances: &mut HashMap<
+            UnlitInstancesKey,
+            Vec<GpuUnlitMeshInstance>,
+        >,
         transparent_meshes: &mut Vec<(usize, GpuTransparentMeshInstance, f32)>,
         camera_position: Vec3,
     ) {
@@ -5823,22 +7958,92 @@ impl Renderer {
         let point_light_camera_count = engine_state.scene.point_lights.len() * 6;
         let camera_count = 1 + directional_light_camera_count + point_light_camera_count;
 
-        let mut tmp_node_culling_mask = BitVec::repeat(false, camera_count);
         let mut culled_object_counts: Vec<usize> = vec![0; camera_count];
+        let mut point_light_casters_hashers =
+            vec![DefaultHasher::new(); engine_state.scene.point_lights.len()];
+
+        // the transforms and culling masks of the nodes are independent from each other so
+        // they're computed on the job workers, the instances are then grouped on this thread
+        let nodes: Vec<&GameNode> = engine_state.scene.nodes().collect();
+        // the BVH rules out most of the off screen nodes before their spheres are tested
+        let mut on_screen_candidates: BitVec = BitVec::repeat(false, nodes.len());
+        engine_state
+            .scene
+            .bvh()
+            .query_frustum(culling_frustum, |node_index| {
+                if node_index >= on_screen_candidates.len() {
+                    on_screen_candidates.resize(node_index + 1, false);
+                }
+                on_screen_candidates.set(node_index, true);
+            });
+        let node_transforms_and_culling_masks = {
+            profiling::scope!("Compute transforms and culling masks");
+            let scene = &engine_state.scene;
+            let enable_directional_shadow_culling = data.enable_directional_shadow_culling;
+            let enable_wireframe_mode = data.enable_wireframe_mode;
+            let camera_render_layers = data.camera_render_layers;
+            crate::jobs::par_map(&nodes, crate::jobs::MIN_PARALLEL_LEN, |_, node| {
+                let transform = Mat4::from(scene.get_global_transform_for_node_opt(node.id()));
+                let culling_mask = node
+                    .visual
+                    .as_ref()
+                    .filter(|visual| {
+                        matches!(visual.material, Material::Pbr { .. })
+                            && !enable_wireframe_mode
+                            && !visual.wireframe
+                    })
+                    .map(|_| {
+                        let mut culling_mask = BitVec::repeat(false, camera_count);
+                        let is_on_screen_candidate =
+                            node.render_layers.intersects(camera_render_layers)
+                                && on_screen_candidates
+                                    .get(node.id().index())
+                                    .map_or(false, |bit| *bit);
+                        Self::get_node_culling_mask(
+                            node,
+                            is_on_screen_candidate,
+                            enable_directional_shadow_culling,
+                            scene,
+                            culling_frustum,
+                            point_lights_frusta,
+                            resolved_directional_light_cascades,
+                            &mut culling_mask,
+                        );
+                        // only the main camera's bit is left
+                        if !node.casts_shadows {
+                            culling_mask[1..].fill(false);
+                        }
+                        culling_mask
+                    });
+                (transform, culling_mask)
+            })
+        };
 
-        for node in engine_state.scene.nodes() {
-            let transform = Mat4::from(
-                engine_state
-                    .scene
-                    .get_global_transform_for_node_opt(node.id()),
-            );
+        for (node, (transform, culling_mask)) in
+            nodes.into_iter().zip(node_transforms_and_culling_masks)
+        {
+            if !node.visible {
+                continue;
+            }
             if let Some(GameNodeVisual {
-                mesh_index,
+                mesh,
                 material,
                 wireframe,
                 ..
             }) = node.visual.clone()
             {
+                // the asset it came from was unloaded
+                let is_material_alive = match material {
+                    Material::Pbr {
+                        binded_material, ..
+                    } => data.asset_registry.is_material_alive(binded_material),
+                    _ => true,
+                };
+                if !data.asset_registry.is_mesh_alive(mesh) || !is_material_alive {
+                    continue;
+                }
+                let mesh_index = mesh.index();
+
                 let node_bounding_sphere =
                     engine_state.scene.get_node_bounding_sphere_opt(node.id());
                 let dist_sq_from_player = node_bounding_sphere
@@ -5849,23 +8054,17 @@ impl Renderer {
                 match (material, data.enable_wireframe_mode, wireframe) {
                     (
                         Material::Pbr {
-                            binded_material_index,
+                            binded_material,
                             dynamic_pbr_params,
                         },
                         false,
                         false,
                     ) => {
+                        let binded_material_index = binded_material.index();
                         total_object_count += 1;
 
-                        Self::get_node_culling_mask(
-                            node,
-                            data,
-                            engine_state,
-                            culling_frustum,
-                            point_lights_frusta,
-                            resolved_directional_light_cascades,
-                            &mut tmp_node_culling_mask,
-                        );
+                        let tmp_node_culling_mask =
+                            culling_mask.expect("Culling masks are computed for all the pbr nodes");
 
                         let mut completely_culled = true;
 
@@ -5892,6 +8091,24 @@ impl Renderer {
                             continue;
                         }
 
+                        for (light_index, hasher) in
+                            point_light_casters_hashers.iter_mut().enumerate()
+                        {
+                            let first_camera_index =
+                                1 + directional_light_camera_count + light_index * 6;
+                            if tmp_node_culling_mask[first_camera_index..first_camera_index + 6]
+                                .any()
+                            {
+                                node.id().hash(hasher);
+                                (mesh_index, binded_material_index).hash(hasher);
+                                transform.to_cols_array().map(f32::to_bits).hash(hasher);
+                                // the bones aren't part of the hash
+                                if node.skin_index.is_some() {
+                                    private_data.frame_index.hash(hasher);
+                                }
+                            }
+                        }
+
                         let gpu_instance = GpuPbrMeshInstance::new(
                             transform,
                             dynamic_pbr_params.unwrap_or_else(|| {
@@ -5911,15 +8128,21 @@ impl Renderer {
                             Entry::Vacant(entry) => {
                                 entry.insert((
                                     smallvec![gpu_instance],
-                                    tmp_node_culling_mask.clone(),
+                                    tmp_node_culling_mask,
                                     dist_sq_from_player,
                                 ));
                             }
                         }
                     }
                     (material, enable_wireframe_mode, is_node_wireframe) => {
+                        // these are only drawn by the main camera
+                        if !node.render_layers.intersects(data.camera_render_layers) {
+                            continue;
+                        }
+
                         let (color, is_transparent) = match material {
                             Material::Unlit { color } => ([color.x, color.y, color.z, 1.0], false),
+                            Material::Custom { params, .. } => (params.into(), false),
                             Material::Transparent {
                                 color,
                                 premultiplied_alpha,
@@ -5940,7 +8163,7 @@ impl Renderer {
                                 )
                             }
                             Material::Pbr {
-                                binded_material_index,
+                                binded_material,
                                 dynamic_pbr_params,
                             } => {
                                 // fancy logic for picking what the wireframe lines
@@ -5950,7 +8173,7 @@ impl Renderer {
                                     emissive_factor,
                                     ..
                                 } = dynamic_pbr_params.unwrap_or_else(|| {
-                                    data.binded_pbr_materials[binded_material_index]
+                                    data.binded_pbr_materials[binded_material.index()]
                                         .dynamic_pbr_params
                                 });
                                 let should_take_color = |as_slice: &[f32]| {
@@ -6031,7 +8254,18 @@ impl Renderer {
                                 color,
                                 model_transform: transform,
                             };
-                            match unlit_mesh_index_to_gpu_instances.entry(mesh_index) {
+                            let custom_material = match material {
+                                Material::Custom {
+                                    material,
+                                    user_data,
+                                    ..
+                                } => Some((material, user_data)),
+                                _ => None,
+                            };
+                            match unlit_mesh_index_to_gpu_instances.entry(UnlitInstancesKey {
+                                custom_material,
+                                mesh_index,
+                            }) {
                                 Entry::Occupied(mut entry) => {
                                     entry.get_mut().push(gpu_instance);
                                 }
@@ -6045,6 +8279,11 @@ impl Renderer {
             }
         }
 
+        private_data.point_light_casters_fingerprints = point_light_casters_hashers
+            .iter()
+            .map(|hasher| hasher.finish())
+            .collect();
+
         // TODO: move to UI?
 
         log::debug!("Culling time: {:?}", start.elapsed());
@@ -6102,3 +8341,25 @@ impl Renderer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binded_assets_are_freed_with_their_last_reference() {
+        let mut binded_assets = BindedAssets::default();
+        let asset_id = binded_assets.add(BindedAssetResources::default());
+        binded_assets.retain(asset_id).unwrap();
+        assert_eq!(binded_assets.reference_count(asset_id), Some(2));
+
+        assert!(!binded_assets.release(asset_id).unwrap());
+        assert!(binded_assets.release(asset_id).unwrap());
+        assert_eq!(binded_assets.reference_count(asset_id), Some(0));
+
+        binded_assets.assets[asset_id.0] = None;
+        assert!(binded_assets.retain(asset_id).is_err());
+        assert!(binded_assets.release(asset_id).is_err());
+        assert_eq!(binded_assets.loaded_count(), 0);
+    }
+}
@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use wgpu::{Device, Sampler};
 
 // same as wgpu::SamplerDescriptor but without the label
@@ -14,6 +15,8 @@ pub struct SamplerDescriptor {
     pub compare: Option<wgpu::CompareFunction>,
     pub anisotropy_clamp: u16,
     pub border_color: Option<wgpu::SamplerBorderColor>,
+    /// set on material textures so the global TextureQuality overrides their filtering
+    pub use_texture_quality: bool,
 }
 
 impl Default for SamplerDescriptor {
@@ -31,6 +34,7 @@ impl Default for SamplerDescriptor {
             compare: def.compare,
             anisotropy_clamp: def.anisotropy_clamp,
             border_color: def.border_color,
+            use_texture_quality: false,
         }
     }
 }
@@ -54,9 +58,87 @@ impl SamplerDescriptor {
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TextureFiltering {
+    Bilinear,
+    Trilinear,
+    Anisotropic2x,
+    Anisotropic4x,
+    Anisotropic8x,
+    Anisotropic16x,
+}
+
+impl TextureFiltering {
+    pub const ALL: [TextureFiltering; 6] = [
+        TextureFiltering::Bilinear,
+        TextureFiltering::Trilinear,
+        TextureFiltering::Anisotropic2x,
+        TextureFiltering::Anisotropic4x,
+        TextureFiltering::Anisotropic8x,
+        TextureFiltering::Anisotropic16x,
+    ];
+
+    fn anisotropy_clamp(&self) -> u16 {
+        match self {
+            TextureFiltering::Bilinear | TextureFiltering::Trilinear => 1,
+            TextureFiltering::Anisotropic2x => 2,
+            TextureFiltering::Anisotropic4x => 4,
+            TextureFiltering::Anisotropic8x => 8,
+            TextureFiltering::Anisotropic16x => 16,
+        }
+    }
+
+    pub fn apply(&self, descriptor: SamplerDescriptor) -> SamplerDescriptor {
+        let mipmap_filter = match self {
+            TextureFiltering::Bilinear => wgpu::FilterMode::Nearest,
+            _ => wgpu::FilterMode::Linear,
+        };
+        // wgpu only allows anisotropy when all the filters are linear,
+        // so textures that asked for nearest filtering (e.g. pixel art) keep it
+        let is_linear = descriptor.mag_filter == wgpu::FilterMode::Linear
+            && descriptor.min_filter == wgpu::FilterMode::Linear;
+        SamplerDescriptor {
+            mipmap_filter,
+            anisotropy_clamp: if is_linear {
+                self.anisotropy_clamp()
+            } else {
+                1
+            },
+            ..descriptor
+        }
+    }
+}
+
+impl std::fmt::Display for TextureFiltering {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                TextureFiltering::Bilinear => "Bilinear",
+                TextureFiltering::Trilinear => "Trilinear",
+                TextureFiltering::Anisotropic2x => "Anisotropic 2x",
+                TextureFiltering::Anisotropic4x => "Anisotropic 4x",
+                TextureFiltering::Anisotropic8x => "Anisotropic 8x",
+                TextureFiltering::Anisotropic16x => "Anisotropic 16x",
+            }
+        )
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TextureQuality {
+    /// None keeps the filtering requested by the assets
+    pub filtering: Option<TextureFiltering>,
+    /// number of top mip levels that are dropped when material textures are uploaded, halving
+    /// their resolution each time to save VRAM. only affects the textures loaded after it's changed
+    pub resolution_bias: u32,
+}
+
 #[derive(Default, Debug)]
 pub struct SamplerCache {
     samplers: Vec<(SamplerDescriptor, Sampler)>,
+    texture_quality: TextureQuality,
 }
 
 impl SamplerCache {
@@ -74,11 +156,47 @@ impl SamplerCache {
             return existing_sampler_index;
         }
         let new_sampler_index = self.samplers.len();
-        let new_sampler = device.create_sampler(&a_descriptor.into_wgpu());
+        let new_sampler = self.make_sampler(device, a_descriptor);
         self.samplers.push((*a_descriptor, new_sampler));
         new_sampler_index
     }
 
+    fn make_sampler(&self, device: &Device, descriptor: &SamplerDescriptor) -> Sampler {
+        let descriptor = match self.texture_quality.filtering {
+            Some(filtering) if descriptor.use_texture_quality => filtering.apply(*descriptor),
+            _ => *descriptor,
+        };
+        device.create_sampler(&descriptor.into_wgpu())
+    }
+
+    pub fn texture_quality(&self) -> TextureQuality {
+        self.texture_quality
+    }
+
+    /// The affected samplers are recreated in place so their indices stay valid. Returns true
+    /// if that happened, in which case the bind groups that use them need to be recreated too
+    pub fn set_texture_quality(
+        &mut self,
+        device: &Device,
+        texture_quality: TextureQuality,
+    ) -> bool {
+        let filtering_changed = self.texture_quality.filtering != texture_quality.filtering;
+        self.texture_quality = texture_quality;
+        if !filtering_changed {
+            return false;
+        }
+
+        let mut recreated_samplers = false;
+        for sampler_index in 0..self.samplers.len() {
+            let descriptor = self.samplers[sampler_index].0;
+            if descriptor.use_texture_quality {
+                self.samplers[sampler_index].1 = self.make_sampler(device, &descriptor);
+                recreated_samplers = true;
+            }
+        }
+        recreated_samplers
+    }
+
     pub fn get_sampler_by_index(&self, sampler_index: usize) -> &Sampler {
         let (_, sampler) = &self.samplers[sampler_index];
         sampler
@@ -0,0 +1,510 @@
+use crate::ecs::{PhysicsBody, SceneNode};
+use crate::engine_state::EngineState;
+use crate::health::Health;
+use crate::physics::rapier3d_f64::prelude::*;
+use crate::physics::PhysicsState;
+use crate::player_controller::PlayerController;
+use crate::ragdoll::transform_to_isometry;
+use crate::transform::{Transform, TransformBuilder};
+use crate::weapon::Weapon;
+
+use std::collections::{BTreeMap, BTreeSet};
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use glam::f32::{Quat, Vec3};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// Bumped whenever a field is renamed or changes meaning, along with a step in migrate
+pub const SAVE_GAME_VERSION: i64 = 1;
+
+/// Component giving an entity a name that stays the same from one run of the game to the next,
+/// so it can be found again when a save is loaded, see SaveGame::capture_objects
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SaveId(pub String);
+
+/// Unlike SceneFileTransform the rotation is kept as a quaternion so it survives the round trip
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedTransform {
+    pub position: [f32; 3],
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+}
+
+impl From<Transform> for SavedTransform {
+    fn from(transform: Transform) -> Self {
+        Self {
+            position: transform.position().into(),
+            rotation: transform.rotation().into(),
+            scale: transform.scale().into(),
+        }
+    }
+}
+
+impl From<SavedTransform> for Transform {
+    fn from(transform: SavedTransform) -> Self {
+        TransformBuilder::new()
+            .position(transform.position.into())
+            .rotation(Quat::from_array(transform.rotation))
+            .scale(transform.scale.into())
+            .build()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedAmmo {
+    /// None if the weapon has no magazine
+    pub in_magazine: Option<u32>,
+    pub reserve: Option<u32>,
+}
+
+impl SavedAmmo {
+    pub fn from_weapon(weapon: &Weapon) -> Self {
+        Self {
+            in_magazine: weapon.ammo_in_magazine(),
+            reserve: weapon.reserve_ammo,
+        }
+    }
+
+    pub fn apply(&self, weapon: &mut Weapon) {
+        if let Some(in_magazine) = self.in_magazine {
+            weapon.set_ammo_in_magazine(in_magazine);
+        }
+        weapon.reserve_ammo = self.reserve;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavedPlayer {
+    pub position: [f32; 3],
+    /// in radians, see ControlledViewDirection
+    pub view_horizontal: f32,
+    pub view_vertical: f32,
+    pub health: Option<Health>,
+    pub ammo: Option<SavedAmmo>,
+}
+
+impl SavedPlayer {
+    /// The health and the ammo are left for the game to fill in
+    pub fn from_player_controller(
+        player_controller: &PlayerController,
+        physics_state: &PhysicsState,
+    ) -> Self {
+        Self {
+            position: player_controller.position(physics_state).into(),
+            view_horizontal: player_controller.view_direction.horizontal,
+            view_vertical: player_controller.view_direction.vertical,
+            ..Default::default()
+        }
+    }
+
+    /// Moves the player back where they were, at rest
+    pub fn apply_to_player_controller(
+        &self,
+        player_controller: &mut PlayerController,
+        physics_state: &mut PhysicsState,
+    ) {
+        player_controller.view_direction.horizontal = self.view_horizontal;
+        player_controller.view_direction.vertical = self.view_vertical;
+        if let Some(rigid_body) = physics_state
+            .rigid_body_set
+            .get_mut(player_controller.rigid_body_handle)
+        {
+            let [x, y, z] = self.position;
+            rigid_body.set_translation(vector![x as f64, y as f64, z as f64], true);
+            rigid_body.set_linvel(Vector::zeros(), true);
+        }
+    }
+}
+
+/// The state of an entity with a SaveId
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedObject {
+    /// in world space
+    pub transform: SavedTransform,
+    #[serde(default)]
+    pub linear_velocity: [f32; 3],
+    #[serde(default)]
+    pub angular_velocity: [f32; 3],
+    #[serde(default)]
+    pub health: Option<Health>,
+}
+
+/// One save file. The engine fills in what it knows about through the capture functions and the
+/// game adds the rest, its own state going in custom. Written as RON, see SaveSlots
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SaveGame {
+    pub version: i64,
+    /// shown in the list of the slots, e.g. the name of the level
+    pub description: String,
+    /// seconds since the unix epoch
+    pub saved_at: i64,
+    pub player: Option<SavedPlayer>,
+    /// by SaveId
+    pub objects: BTreeMap<String, SavedObject>,
+    /// the one-off events that already happened, e.g. the cutscenes that were played
+    pub triggered_events: BTreeSet<String>,
+    pub time_of_day_hours: Option<f32>,
+    /// game specific data by key, see set_custom
+    pub custom: BTreeMap<String, String>,
+}
+
+impl Default for SaveGame {
+    fn default() -> Self {
+        Self {
+            version: SAVE_GAME_VERSION,
+            description: String::new(),
+            saved_at: 0,
+            player: None,
+            objects: BTreeMap::new(),
+            triggered_events: BTreeSet::new(),
+            time_of_day_hours: None,
+            custom: BTreeMap::new(),
+        }
+    }
+}
+
+impl SaveGame {
+    /// Stamped with the current time
+    pub fn new(description: &str) -> Self {
+        Self {
+            description: description.to_string(),
+            saved_at: chrono::Utc::now().timestamp(),
+            ..Default::default()
+        }
+    }
+
+    /// Saves the transform, velocities and health of all the entities that have a SaveId
+    pub fn capture_objects(&mut self, engine_state: &EngineState) {
+        for (entity, SaveId(id)) in engine_state.world.query::<SaveId>() {
+            let Some(SceneNode(node_id)) = engine_state.world.get::<SceneNode>(entity) else {
+                continue;
+            };
+            if engine_state.scene.get_node(*node_id).is_none() {
+                continue;
+            }
+            let rigid_body = engine_state.world.get::<PhysicsBody>(entity).and_then(
+                |PhysicsBody(rigid_body_handle)| {
+                    engine_state
+                        .physics_state
+                        .rigid_body_set
+                        .get(*rigid_body_handle)
+                },
+            );
+            self.objects.insert(
+                id.clone(),
+                SavedObject {
+                    transform: engine_state
+                        .scene
+                        .get_global_transform_for_node(*node_id)
+                        .into(),
+                    linear_velocity: rigid_body
+                        .map(|rigid_body| vector_to_array(rigid_body.linvel()))
+                        .unwrap_or_default(),
+                    angular_velocity: rigid_body
+                        .map(|rigid_body| vector_to_array(rigid_body.angvel()))
+                        .unwrap_or_default(),
+                    health: engine_state.world.get::<Health>(entity).copied(),
+                },
+            );
+        }
+    }
+
+    /// Puts the entities with a SaveId back in their saved state. The ones that aren't in the
+    /// save, e.g. because they were destroyed or picked up before it was made, are despawned.
+    /// Returns the ids of the saved objects that have no entity, for the game to respawn them
+    pub fn restore_objects(&self, engine_state: &mut EngineState) -> Vec<String> {
+        let mut restored_ids = BTreeSet::new();
+        let entities: Vec<_> = engine_state
+            .world
+            .query::<SaveId>()
+            .map(|(entity, SaveId(id))| (entity, id.clone()))
+            .collect();
+
+        for (entity, id) in entities {
+            let Some(object) = self.objects.get(&id) else {
+                engine_state.despawn_entity(entity);
+                continue;
+            };
+            restored_ids.insert(id);
+
+            if let Some(health) = object.health {
+                engine_state.world.insert(entity, health);
+            }
+            let transform = Transform::from(object.transform);
+            let node_id = engine_state
+                .world
+                .get::<SceneNode>(entity)
+                .map(|SceneNode(node_id)| *node_id);
+            let rigid_body_handle = engine_state
+                .world
+                .get::<PhysicsBody>(entity)
+                .map(|PhysicsBody(rigid_body_handle)| *rigid_body_handle);
+
+            if let Some(node_id) = node_id {
+                let parent_transform = engine_state
+                    .scene
+                    .get_node(node_id)
+                    .and_then(|node| node.parent_id)
+                    .map(|parent_id| engine_state.scene.get_global_transform_for_node(parent_id));
+                if let Some(node) = engine_state.scene.get_node_mut(node_id) {
+                    node.transform = match parent_transform {
+                        Some(parent_transform) => {
+                            Transform::from(parent_transform.inverse() * *transform)
+                        }
+                        None => transform,
+                    };
+                    node.reset_interpolation();
+                }
+            }
+
+            let Some(rigid_body_handle) = rigid_body_handle else {
+                continue;
+            };
+            let isometry = transform_to_isometry(&transform);
+            let physics_state = &mut engine_state.physics_state;
+            if let Some(rigid_body) = physics_state.rigid_body_set.get_mut(rigid_body_handle) {
+                rigid_body.set_position(isometry, true);
+                rigid_body.set_linvel(array_to_vector(object.linear_velocity), true);
+                rigid_body.set_angvel(array_to_vector(object.angular_velocity), true);
+            }
+            if let Some(node_id) = node_id {
+                physics_state.teleport_rigid_body_component(node_id, isometry);
+            }
+        }
+
+        self.objects
+            .keys()
+            .filter(|id| !restored_ids.contains(*id))
+            .cloned()
+            .collect()
+    }
+
+    /// Stores game specific state under the key, as RON
+    pub fn set_custom<T: Serialize>(&mut self, key: &str, value: &T) -> Result<()> {
+        self.custom.insert(key.to_string(), ron::to_string(value)?);
+        Ok(())
+    }
+
+    /// None if nothing was stored under the key
+    pub fn custom<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        self.custom
+            .get(key)
+            .map(|text| Ok(ron::from_str(text)?))
+            .transpose()
+    }
+
+    /// Migrates the saves made by older versions of the game
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut save_game: Self = ron::from_str(text)?;
+        migrate(&mut save_game)?;
+        save_game.version = SAVE_GAME_VERSION;
+        Ok(save_game)
+    }
+
+    pub fn to_ron_string(&self) -> Result<String> {
+        Ok(ron::ser::to_string_pretty(
+            self,
+            ron::ser::PrettyConfig::default(),
+        )?)
+    }
+}
+
+/// What the list of the slots shows, see SaveSlots::list
+#[derive(Debug, Clone, PartialEq)]
+pub struct SaveSlotInfo {
+    pub slot: String,
+    pub description: String,
+    pub saved_at: i64,
+}
+
+/// The save files, one per slot, e.g. "quicksave" or "slot_1"
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone)]
+pub struct SaveSlots {
+    pub directory: PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl SaveSlots {
+    pub fn new(directory: PathBuf) -> Self {
+        Self { directory }
+    }
+
+    /// e.g. ~/.local/share/<app_name>/saves on linux
+    pub fn default_directory(app_name: &str) -> Option<PathBuf> {
+        dirs::data_dir().map(|data_dir| data_dir.join(app_name).join("saves"))
+    }
+
+    /// Slot names are limited to letters, digits, '-' and '_' since they're file names
+    pub fn path(&self, slot: &str) -> Result<PathBuf> {
+        if slot.is_empty()
+            || !slot
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            bail!("Invalid save slot name: {slot:?}");
+        }
+        Ok(self.directory.join(format!("{slot}.ron")))
+    }
+
+    pub fn exists(&self, slot: &str) -> bool {
+        self.path(slot).is_ok_and(|path| path.exists())
+    }
+
+    /// The file is replaced in one go, a crash while saving leaves the previous save intact
+    pub fn save(&self, slot: &str, save_game: &SaveGame) -> Result<()> {
+        let path = self.path(slot)?;
+        std::fs::create_dir_all(&self.directory)?;
+        let temp_path = path.with_extension("ron.tmp");
+        std::fs::write(&temp_path, save_game.to_ron_string()?)?;
+        std::fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+
+    pub fn load(&self, slot: &str) -> Result<SaveGame> {
+        SaveGame::parse(&std::fs::read_to_string(self.path(slot)?)?)
+    }
+
+    pub fn delete(&self, slot: &str) -> Result<()> {
+        std::fs::remove_file(self.path(slot)?)?;
+        Ok(())
+    }
+
+    /// The most recent first, the files that can't be read are skipped
+    pub fn list(&self) -> Vec<SaveSlotInfo> {
+        let Ok(entries) = std::fs::read_dir(&self.directory) else {
+            return vec![];
+        };
+        let mut slots: Vec<_> = entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension().and_then(|extension| extension.to_str()) != Some("ron") {
+                    return None;
+                }
+                let slot = path.file_stem()?.to_str()?.to_string();
+                let save_game = load_slot_file(&path)?;
+                Some(SaveSlotInfo {
+                    slot,
+                    description: save_game.description,
+                    saved_at: save_game.saved_at,
+                })
+            })
+            .collect();
+        slots.sort_by(|a, b| b.saved_at.cmp(&a.saved_at));
+        slots
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn load_slot_file(path: &Path) -> Option<SaveGame> {
+    std::fs::read_to_string(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|text| SaveGame::parse(&text))
+        .map_err(|err| log::warn!("Skipping the save file {}: {err:?}", path.display()))
+        .ok()
+}
+
+/// Upgrades a save made with an older SAVE_GAME_VERSION, one version at a time
+fn migrate(save_game: &mut SaveGame) -> Result<()> {
+    if save_game.version > SAVE_GAME_VERSION {
+        bail!(
+            "The game was saved by a newer version ({}) of the game",
+            save_game.version
+        );
+    }
+    // no fields have changed since the first version
+    Ok(())
+}
+
+fn vector_to_array(vector: &Vector<Real>) -> [f32; 3] {
+    [vector.x as f32, vector.y as f32, vector.z as f32]
+}
+
+fn array_to_vector(array: [f32; 3]) -> Vector<Real> {
+    let vector = Vec3::from(array);
+    vector![vector.x as f64, vector.y as f64, vector.z as f64]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::GameNodeDesc;
+
+    #[test]
+    fn objects_are_restored_and_the_missing_ones_despawned() {
+        let mut engine_state = EngineState::new_headless();
+        let mut spawn_object = |id: &str, position: Vec3| {
+            let node_id = engine_state
+                .scene
+                .add_node(GameNodeDesc {
+                    transform: TransformBuilder::new().position(position).build(),
+                    ..Default::default()
+                })
+                .id();
+            let entity = engine_state.world.spawn();
+            engine_state.world.insert(entity, SceneNode(node_id));
+            engine_state.world.insert(entity, SaveId(id.to_string()));
+            engine_state.world.insert(entity, Health::new(3.0));
+            entity
+        };
+        let barrel = spawn_object("barrel", Vec3::ZERO);
+        let crate_entity = spawn_object("crate", Vec3::X);
+
+        let mut save_game = SaveGame::new("test");
+        save_game.objects.insert(
+            "barrel".to_string(),
+            SavedObject {
+                transform: TransformBuilder::new()
+                    .position(Vec3::new(4.0, 5.0, 6.0))
+                    .build()
+                    .into(),
+                linear_velocity: [0.0; 3],
+                angular_velocity: [0.0; 3],
+                health: Some(Health {
+                    current: 1.0,
+                    max: 3.0,
+                }),
+            },
+        );
+        save_game
+            .objects
+            .insert("key".to_string(), save_game.objects["barrel"].clone());
+        save_game.triggered_events.insert("intro".to_string());
+        save_game.set_custom("score", &42u32).unwrap();
+
+        let parsed = SaveGame::parse(&save_game.to_ron_string().unwrap()).unwrap();
+        assert_eq!(parsed, save_game);
+        assert_eq!(parsed.custom::<u32>("score").unwrap(), Some(42));
+        assert_eq!(parsed.custom::<u32>("missing").unwrap(), None);
+
+        let missing_ids = parsed.restore_objects(&mut engine_state);
+
+        assert_eq!(missing_ids, vec!["key".to_string()]);
+        assert!(!engine_state.world.is_alive(crate_entity));
+        let SceneNode(node_id) = *engine_state.world.get::<SceneNode>(barrel).unwrap();
+        assert_eq!(
+            engine_state
+                .scene
+                .get_node(node_id)
+                .unwrap()
+                .transform
+                .position(),
+            Vec3::new(4.0, 5.0, 6.0)
+        );
+        assert_eq!(
+            engine_state.world.get::<Health>(barrel).unwrap().current,
+            1.0
+        );
+
+        let newer_save = SaveGame {
+            version: SAVE_GAME_VERSION + 1,
+            ..Default::default()
+        };
+        assert!(SaveGame::parse(&newer_save.to_ron_string().unwrap()).is_err());
+    }
+}
@@ -1,10 +1,15 @@
 use crate::animation::*;
+use crate::asset_registry::{MaterialHandle, MeshHandle};
+use crate::bvh::Bvh;
 use crate::collisions::*;
+use crate::custom_material::{CustomMaterialDataId, CustomMaterialId};
 use crate::mesh::*;
+use crate::picking::Ray;
 use crate::renderer::*;
 
 use std::{collections::HashMap, hash::BuildHasherDefault};
 
+use anyhow::{bail, Result};
 use glam::f32::{Mat4, Vec3, Vec4};
 use twox_hash::XxHash64;
 
@@ -17,13 +22,19 @@ pub struct Scene {
     // node_transforms: Vec<Mat4>,
     global_node_transforms: Vec<crate::transform::Transform>,
     global_node_bounding_spheres: Vec<Sphere>,
+    /// over the bounding spheres of the nodes with a visual, by node index
+    bvh: Bvh,
     pub skins: Vec<Skin>,
     pub animations: Vec<Animation>,
     // skeleton skin node index -> parent_index_map
     skeleton_parent_index_maps:
         HashMap<u32, HashMap<u32, u32, BuildHasherDefault<XxHash64>>, BuildHasherDefault<XxHash64>>,
+    /// can be changed at any time, the renderer grows its light buffer and shadow maps to fit
     pub point_lights: Vec<PointLight>,
+    /// up to MAX_LIGHT_COUNT, see add_directional_light
     pub directional_lights: Vec<DirectionalLight>,
+    /// None until the first fixed update, see set_interpolation_alpha
+    interpolation_alpha: Option<f32>,
 }
 
 #[derive(Debug, Clone)]
@@ -33,6 +44,10 @@ pub struct GameNodeDesc {
     pub visual: Option<GameNodeVisual>,
     pub name: Option<String>,
     pub parent_id: Option<GameNodeId>,
+    pub visible: bool,
+    pub casts_shadows: bool,
+    pub render_layers: RenderLayers,
+    pub interpolate_transform: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -42,16 +57,67 @@ pub struct GameNode {
     pub visual: Option<GameNodeVisual>,
     pub name: Option<String>,
     pub parent_id: Option<GameNodeId>,
+    /// hidden nodes aren't drawn in any pass, the ones of their children are unaffected
+    pub visible: bool,
+    /// only has an effect on pbr meshes, which are the only ones in the shadow maps
+    pub casts_shadows: bool,
+    /// the node's visual is only drawn by the cameras whose mask shares a layer with it,
+    /// see RendererData::camera_render_layers
+    pub render_layers: RenderLayers,
+    /// for the nodes that are moved in the fixed update. They're drawn in between their
+    /// transforms of the last two fixed updates so they don't stutter when the display
+    /// refreshes faster than the simulation. Not needed for the ones synced with physics
+    pub interpolate_transform: bool,
+    /// the transform before the last fixed update, see Scene::store_previous_transforms
+    previous_transform: Option<crate::transform::Transform>,
     id: GameNodeId,
 }
 
+/// A bitmask of up to 32 layers, e.g. one for the first person viewmodel, one for debug geometry
+/// and one for what only shows up on the minimap
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct RenderLayers(pub u32);
+
+impl RenderLayers {
+    /// Layer 0, where the nodes are by default and which the cameras see by default
+    pub const DEFAULT: Self = Self(1);
+    pub const ALL: Self = Self(u32::MAX);
+    pub const NONE: Self = Self(0);
+
+    pub const LAYER_COUNT: u32 = u32::BITS;
+
+    /// Panics if the layer is 32 or above since it wouldn't fit in the mask
+    pub const fn layer(layer: u32) -> Self {
+        assert!(layer < Self::LAYER_COUNT, "There are only 32 render layers");
+        Self(1 << layer)
+    }
+
+    pub const fn with(self, layer: u32) -> Self {
+        Self(self.0 | Self::layer(layer).0)
+    }
+
+    pub const fn without(self, layer: u32) -> Self {
+        Self(self.0 & !Self::layer(layer).0)
+    }
+
+    pub fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl Default for RenderLayers {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct GameNodeId(u32, usize); // (index into GameScene::nodes array, generation num)
 
 #[derive(Debug, Clone)]
 pub struct GameNodeVisual {
     pub material: Material,
-    pub mesh_index: usize,
+    pub mesh: MeshHandle,
     pub wireframe: bool,
     pub cullable: bool,
 }
@@ -59,7 +125,7 @@ pub struct GameNodeVisual {
 #[derive(Debug, Copy, Clone)]
 pub enum Material {
     Pbr {
-        binded_material_index: usize,
+        binded_material: MaterialHandle,
         /// if set, takes precedence over the material's own params
         dynamic_pbr_params: Option<DynamicPbrParams>,
     },
@@ -70,6 +136,14 @@ pub enum Material {
         color: Vec4,
         premultiplied_alpha: bool,
     },
+    /// See CustomMaterial
+    Custom {
+        material: CustomMaterialId,
+        /// required if the material takes user data
+        user_data: Option<CustomMaterialDataId>,
+        /// passed to the shader with each instance
+        params: Vec4,
+    },
 }
 
 impl Default for Material {
@@ -106,14 +180,15 @@ pub struct IndexedSkin {
     pub bone_bounding_box_transforms: Vec<crate::transform::Transform>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct IndexedAnimation {
     pub name: Option<String>,
     pub length_seconds: f32,
     pub channels: Vec<IndexedChannel>,
+    pub events: Vec<AnimationEventMarker>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct IndexedChannel {
     pub node_index: usize,
     pub property: gltf::animation::Property,
@@ -147,6 +222,7 @@ impl Scene {
                         keyframe_values_u8: indexed_channel.keyframe_values_u8.clone(),
                     })
                     .collect(),
+                events: indexed_animation.events.clone(),
                 state: AnimationState::default(),
             })
             .collect();
@@ -155,11 +231,13 @@ impl Scene {
             empty_node_indices: Vec::new(),
             global_node_transforms: Vec::new(),
             global_node_bounding_spheres: Vec::new(),
+            bvh: Bvh::default(),
             skins: Vec::new(),
             animations,
             skeleton_parent_index_maps: Default::default(),
             point_lights: vec![],
             directional_lights: vec![],
+            interpolation_alpha: None,
         };
 
         nodes_desc.iter().for_each(|node_desc| {
@@ -177,6 +255,7 @@ impl Scene {
                 name,
                 parent_id: parent_index
                     .map(|parent_index| GameNodeId((parent_index).try_into().unwrap(), 0)),
+                ..Default::default()
             });
         });
 
@@ -226,68 +305,186 @@ impl Scene {
 
     // TODO: compute this for the required nodes in the ancestry tree whenever a node's position is updated?
     //       but expose API for updating node transform cheaply and then calling this function at the end.
+    // each node walks up its own ancestry so they're all independent and computed on the job
+    // workers
     #[profiling::function]
     pub fn recompute_global_node_transforms(&mut self, renderer_data: &mut RendererData) {
-        if self.nodes.len() <= self.global_node_transforms.len() {
-            self.global_node_transforms.truncate(self.nodes.len());
-            self.global_node_bounding_spheres.truncate(self.nodes.len());
-        } else {
-            // eliminate potential allocations in the subsequent push() calls
-            self.global_node_transforms
-                .reserve_exact(self.nodes.len() - self.global_node_transforms.len());
-            self.global_node_bounding_spheres
-                .reserve_exact(self.nodes.len() - self.global_node_bounding_spheres.len());
+        let binded_meshes = &renderer_data.binded_meshes;
+        let transforms_and_bounding_spheres = crate::jobs::par_map(
+            &self.nodes,
+            crate::jobs::MIN_PARALLEL_LEN,
+            |_, (node, _)| {
+                let transform = node
+                    .as_ref()
+                    .map(|node| self.get_interpolated_global_transform_for_node(node.id()))
+                    .unwrap_or_default();
+                let bounding_sphere = node
+                    .as_ref()
+                    .and_then(|node| node.visual.as_ref())
+                    .map(|visual| {
+                        build_mesh_bounding_sphere(visual.mesh, &transform, binded_meshes)
+                    })
+                    .unwrap_or_default();
+                (transform, bounding_sphere)
+            },
+        );
+
+        self.global_node_transforms.clear();
+        self.global_node_bounding_spheres.clear();
+        for (transform, bounding_sphere) in transforms_and_bounding_spheres {
+            self.global_node_transforms.push(transform);
+            self.global_node_bounding_spheres.push(bounding_sphere);
         }
-        for (node_index, (node, _)) in self.nodes.iter().enumerate() {
-            let transform = node
-                .as_ref()
-                .map(|node| self.get_global_transform_for_node_internal(node.id()))
-                .unwrap_or_default();
-            let bounding_sphere = node
-                .as_ref()
-                .and_then(|node| node.visual.as_ref())
-                .map(|visual| {
-                    build_mesh_bounding_sphere(visual.mesh_index, &transform, renderer_data)
-                })
-                .unwrap_or_default();
 
-            if node_index < self.global_node_transforms.len() {
-                self.global_node_transforms[node_index] = transform;
-                self.global_node_bounding_spheres[node_index] = bounding_sphere;
+        profiling::scope!("Update BVH");
+        for (node_index, (node, _)) in self.nodes.iter().enumerate() {
+            if node.as_ref().map_or(false, |node| node.visual.is_some()) {
+                self.bvh.update(
+                    node_index,
+                    self.global_node_bounding_spheres[node_index].aabb(),
+                );
             } else {
-                self.global_node_transforms.push(transform);
-                self.global_node_bounding_spheres.push(bounding_sphere);
+                self.bvh.remove(node_index);
             }
         }
     }
 
+    /// The returned id can be given to unload_asset once the scene's nodes are removed
     #[profiling::function]
     pub fn merge_scene(
         &mut self,
         renderer_data: &mut RendererData,
         mut other_scene: Scene,
-        mut other_render_buffers: BindedSceneData,
-    ) {
+        other_render_buffers: BindedSceneData,
+    ) -> BindedAssetId {
+        let asset_id = other_scene.bind_render_buffers(renderer_data, other_render_buffers);
+        self.merge_scene_nodes(other_scene);
+        asset_id
+    }
+
+    /// Moves the render buffers into the renderer data and points the scene's visuals at them.
+    /// The scene can then be merged with merge_scene_nodes
+    pub fn bind_render_buffers(
+        &mut self,
+        renderer_data: &mut RendererData,
+        mut render_buffers: BindedSceneData,
+    ) -> BindedAssetId {
         let mesh_index_offset = renderer_data.binded_meshes.len();
         let material_index_offset = renderer_data.binded_pbr_materials.len();
+        let texture_index_offset = renderer_data.textures.len();
+
+        let asset_id = renderer_data.binded_assets.add(BindedAssetResources {
+            meshes: mesh_index_offset..mesh_index_offset + render_buffers.binded_meshes.len(),
+            wireframe_meshes: renderer_data.binded_wireframe_meshes.len()
+                ..renderer_data.binded_wireframe_meshes.len()
+                    + render_buffers.binded_wireframe_meshes.len(),
+            pbr_materials: material_index_offset
+                ..material_index_offset + render_buffers.binded_pbr_materials.len(),
+            textures: texture_index_offset..texture_index_offset + render_buffers.textures.len(),
+        });
+
+        if let Some(cpu_data) = render_buffers.cpu_data.take() {
+            renderer_data.retained_cpu_data.add_scene(
+                mesh_index_offset,
+                renderer_data.binded_wireframe_meshes.len(),
+                texture_index_offset,
+                cpu_data,
+            );
+        }
 
-        for binded_wireframe_mesh in &mut other_render_buffers.binded_wireframe_meshes {
+        for binded_wireframe_mesh in &mut render_buffers.binded_wireframe_meshes {
             binded_wireframe_mesh.source_mesh_index += mesh_index_offset;
         }
+        for binded_pbr_material in &mut render_buffers.binded_pbr_materials {
+            if let Some(texture_indices) = &mut binded_pbr_material.texture_indices {
+                texture_indices.offset(texture_index_offset);
+            }
+        }
 
         renderer_data
             .binded_meshes
-            .append(&mut other_render_buffers.binded_meshes);
+            .append(&mut render_buffers.binded_meshes);
         renderer_data
             .binded_wireframe_meshes
-            .append(&mut other_render_buffers.binded_wireframe_meshes);
+            .append(&mut render_buffers.binded_wireframe_meshes);
         renderer_data
             .binded_pbr_materials
-            .append(&mut other_render_buffers.binded_pbr_materials);
-        renderer_data
-            .textures
-            .append(&mut other_render_buffers.textures);
+            .append(&mut render_buffers.binded_pbr_materials);
+        renderer_data.textures.append(&mut render_buffers.textures);
+
+        for node in self.nodes_mut() {
+            if let Some(ref mut visual) = node.visual {
+                visual.mesh = renderer_data
+                    .asset_registry
+                    .mesh(visual.mesh.index() + mesh_index_offset);
+
+                match visual.material {
+                    Material::Pbr {
+                        ref mut binded_material,
+                        ..
+                    } => {
+                        *binded_material = renderer_data
+                            .asset_registry
+                            .material(binded_material.index() + material_index_offset);
+                    }
+                    Material::Unlit { .. } => {}
+                    Material::Transparent { .. } => {}
+                    Material::Custom { .. } => {}
+                }
+            }
+        }
+
+        asset_id
+    }
 
+    /// The nodes whose visual uses the meshes or materials of the asset
+    pub fn binded_asset_users(
+        &self,
+        renderer_data: &RendererData,
+        asset_id: BindedAssetId,
+    ) -> Vec<GameNodeId> {
+        let Some(resources) = renderer_data.binded_assets.get(asset_id) else {
+            return vec![];
+        };
+        self.nodes()
+            .filter(|node| {
+                node.visual
+                    .as_ref()
+                    .map_or(false, |visual| resources.is_used_by(visual))
+            })
+            .map(|node| node.id)
+            .collect()
+    }
+
+    /// Releases a reference to the asset, see merge_scene and BindedAssets::retain. Once the
+    /// last one is released, the meshes, materials and textures that were binded along with it
+    /// are freed. That fails if some nodes still use them, e.g. remove the asset's root node
+    /// with remove_subtree first
+    pub fn unload_asset(
+        &self,
+        renderer_data: &mut RendererData,
+        asset_id: BindedAssetId,
+    ) -> Result<()> {
+        let Some(reference_count) = renderer_data.binded_assets.reference_count(asset_id) else {
+            bail!("The asset {asset_id:?} was already unloaded");
+        };
+        if reference_count <= 1 {
+            let user_count = self.binded_asset_users(renderer_data, asset_id).len();
+            if user_count > 0 {
+                bail!("Can't unload the asset {asset_id:?}, {user_count} nodes still use it");
+            }
+        }
+        if renderer_data.binded_assets.release(asset_id)? {
+            renderer_data.free_binded_asset(asset_id)?;
+        }
+        Ok(())
+    }
+
+    /// Adds the nodes, skins and animations of a scene whose visuals already point into the renderer
+    /// data, e.g. after bind_render_buffers. Returns the new id of each of the other scene's nodes,
+    /// indexed by their index in the other scene
+    #[profiling::function]
+    pub fn merge_scene_nodes(&mut self, mut other_scene: Scene) -> Vec<GameNodeId> {
         let skin_index_offset = self.skins.len();
         let node_index_offset = self.nodes.len();
         let convert_node_id = |old_node_id| {
@@ -297,20 +494,6 @@ impl Scene {
         };
         for (node, _) in &mut other_scene.nodes {
             if let Some(ref mut node) = node {
-                if let Some(ref mut visual) = node.visual {
-                    visual.mesh_index += mesh_index_offset;
-
-                    match visual.material {
-                        Material::Pbr {
-                            ref mut binded_material_index,
-                            ..
-                        } => {
-                            *binded_material_index += material_index_offset;
-                        }
-                        Material::Unlit { .. } => {}
-                        Material::Transparent { .. } => {}
-                    }
-                }
                 if let Some(ref mut skin_index) = node.skin_index {
                     *skin_index += skin_index_offset;
                 }
@@ -335,10 +518,16 @@ impl Scene {
             }
         }
 
+        let new_node_ids = (0..other_scene.nodes.len())
+            .map(|node_index| convert_node_id(GameNodeId(node_index as u32, 0)))
+            .collect();
+
         self.nodes.append(&mut other_scene.nodes);
         self.skins.append(&mut other_scene.skins);
         self.animations.append(&mut other_scene.animations);
         self.rebuild_skeleton_parent_index_maps();
+
+        new_node_ids
     }
 
     pub fn get_node_bounding_sphere(
@@ -350,9 +539,9 @@ impl Scene {
             .and_then(|node| node.visual.as_ref())
             .map(|visual| {
                 build_mesh_bounding_sphere(
-                    visual.mesh_index,
+                    visual.mesh,
                     &self.get_global_transform_for_node(node_id),
-                    renderer_data,
+                    &renderer_data.binded_meshes,
                 )
             })
     }
@@ -362,6 +551,34 @@ impl Scene {
         self.global_node_bounding_spheres[node_index as usize]
     }
 
+    /// Keyed by node index, up to date as of the last recompute_global_node_transforms so the
+    /// nodes added since then aren't in it yet
+    pub fn bvh(&self) -> &Bvh {
+        &self.bvh
+    }
+
+    /// The nodes with a visual whose bounding sphere is within radius of center
+    pub fn nodes_within_radius(&self, center: Vec3, radius: f32) -> Vec<GameNodeId> {
+        let mut node_ids = vec![];
+        self.bvh
+            .query_sphere(Sphere { center, radius }, |node_index| {
+                let bounding_sphere = self.global_node_bounding_spheres[node_index];
+                if bounding_sphere.center.distance(center) <= radius + bounding_sphere.radius {
+                    node_ids.extend(self.nodes[node_index].0.as_ref().map(|node| node.id));
+                }
+            });
+        node_ids
+    }
+
+    /// The nodes with a visual whose box in the BVH is hit by the ray, in no particular order
+    pub fn nodes_along_ray(&self, ray: Ray) -> Vec<GameNodeId> {
+        let mut node_ids = vec![];
+        self.bvh.query_ray(ray, |node_index| {
+            node_ids.extend(self.nodes[node_index].0.as_ref().map(|node| node.id));
+        });
+        node_ids
+    }
+
     pub fn _get_skeleton_skin_node_id(&self, node_id: GameNodeId) -> Option<GameNodeId> {
         self.nodes
             .iter()
@@ -377,7 +594,10 @@ impl Scene {
             })
     }
 
-    fn get_node_ancestry_list(&self, node_id: GameNodeId) -> impl Iterator<Item = GameNodeId> + '_ {
+    pub(crate) fn get_node_ancestry_list(
+        &self,
+        node_id: GameNodeId,
+    ) -> impl Iterator<Item = GameNodeId> + '_ {
         std::iter::successors(Some(node_id), |node_id| {
             self.get_node(*node_id).and_then(|node| node.parent_id)
         })
@@ -422,8 +642,33 @@ impl Scene {
         )
     }
 
+    /// Called by gameloop before each fixed update, for the nodes with interpolate_transform
+    pub fn store_previous_transforms(&mut self) {
+        for node in self.nodes_mut().filter(|node| node.interpolate_transform) {
+            node.previous_transform = Some(node.transform);
+        }
+    }
+
+    /// Moves all the root nodes by the offset along with their previous transforms, so the
+    /// move isn't interpolated. See LargeWorld
+    pub fn shift_origin(&mut self, offset: Vec3) {
+        for node in self.nodes_mut().filter(|node| node.parent_id.is_none()) {
+            node.transform
+                .set_position(node.transform.position() + offset);
+            if let Some(previous_transform) = &mut node.previous_transform {
+                previous_transform.set_position(previous_transform.position() + offset);
+            }
+        }
+    }
+
+    /// Where the rendered transforms are in between the last two fixed updates,
+    /// set by gameloop from EngineState::simulation_timestep
+    pub fn set_interpolation_alpha(&mut self, interpolation_alpha: f64) {
+        self.interpolation_alpha = Some(interpolation_alpha as f32);
+    }
+
     // #[profiling::function]
-    fn get_global_transform_for_node_internal(
+    fn get_interpolated_global_transform_for_node(
         &self,
         node_id: GameNodeId,
     ) -> crate::transform::Transform {
@@ -440,7 +685,11 @@ impl Scene {
         let mut ancestry_transforms = (0..ancestry_length).rev().map(|ancestry_list_index| {
             let node_index = node_ancestry_list[ancestry_list_index];
             let (node, _) = &self.nodes[node_index as usize];
-            node.as_ref().unwrap().transform
+            let node = node.as_ref().unwrap();
+            match self.interpolation_alpha {
+                Some(interpolation_alpha) => node.interpolated_transform(interpolation_alpha),
+                None => node.transform,
+            }
         });
         let mut acc: crate::transform::Transform = ancestry_transforms.next().unwrap();
         for ancestry_transform in ancestry_transforms {
@@ -464,6 +713,10 @@ impl Scene {
             visual,
             name,
             parent_id,
+            visible,
+            casts_shadows,
+            render_layers,
+            interpolate_transform,
         } = node;
 
         let make_new_node = |id| GameNode {
@@ -473,6 +726,11 @@ impl Scene {
             name,
             id,
             parent_id,
+            visible,
+            casts_shadows,
+            render_layers,
+            interpolate_transform,
+            previous_transform: None,
         };
 
         let empty_node = self
@@ -536,6 +794,7 @@ impl Scene {
             let GameNodeId(node_index, _) = node.id;
             self.nodes[node_index as usize].0.take();
             self.empty_node_indices.push(node_index as usize);
+            self.bvh.remove(node_index as usize);
 
             // TODO: this is slow, is it needed?
             if REBUILD_SKELETON_PARENT_MAP_ON_REMOVE {
@@ -544,6 +803,24 @@ impl Scene {
         }
     }
 
+    /// Removes the node along with all of its descendants
+    pub fn remove_subtree(&mut self, node_id: GameNodeId) {
+        if self.get_node(node_id).is_none() {
+            return;
+        }
+        let subtree_node_ids: Vec<_> = self
+            .nodes()
+            .map(|node| node.id)
+            .filter(|descendant_id| {
+                self.get_node_ancestry_list(*descendant_id)
+                    .any(|ancestor_id| ancestor_id == node_id)
+            })
+            .collect();
+        for subtree_node_id in subtree_node_ids {
+            self.remove_node(subtree_node_id);
+        }
+    }
+
     pub fn node_count(&self) -> usize {
         self.nodes.iter().filter(|(node, _)| node.is_some()).count()
     }
@@ -555,12 +832,53 @@ impl Scene {
     pub fn nodes_mut(&mut self) -> impl Iterator<Item = &mut GameNode> {
         self.nodes.iter_mut().flat_map(|(node, _)| node)
     }
+
+    /// The light follows its node, which should only have one light
+    pub fn add_point_light(&mut self, point_light: PointLight) {
+        self.point_lights.push(point_light);
+    }
+
+    pub fn get_point_light(&self, node_id: GameNodeId) -> Option<&PointLight> {
+        self.point_lights
+            .iter()
+            .find(|point_light| point_light.node_id == node_id)
+    }
+
+    pub fn get_point_light_mut(&mut self, node_id: GameNodeId) -> Option<&mut PointLight> {
+        self.point_lights
+            .iter_mut()
+            .find(|point_light| point_light.node_id == node_id)
+    }
+
+    /// The node itself is left in the scene
+    pub fn remove_point_light(&mut self, node_id: GameNodeId) -> Option<PointLight> {
+        let light_index = self
+            .point_lights
+            .iter()
+            .position(|point_light| point_light.node_id == node_id)?;
+        Some(self.point_lights.remove(light_index))
+    }
+
+    /// Returns the index of the light. Fails when there are already MAX_LIGHT_COUNT of them
+    pub fn add_directional_light(&mut self, directional_light: DirectionalLight) -> Result<usize> {
+        if self.directional_lights.len() >= MAX_LIGHT_COUNT {
+            bail!("There can't be more than {MAX_LIGHT_COUNT} directional lights");
+        }
+        self.directional_lights.push(directional_light);
+        Ok(self.directional_lights.len() - 1)
+    }
+
+    /// The lights after it move down one index
+    pub fn remove_directional_light(&mut self, light_index: usize) -> Option<DirectionalLight> {
+        (light_index < self.directional_lights.len())
+            .then(|| self.directional_lights.remove(light_index))
+    }
 }
 
 fn build_mesh_bounding_sphere(
-    mesh_index: usize,
+    mesh: MeshHandle,
     global_transform: &crate::transform::Transform,
-    renderer_data: &RendererData,
+    binded_meshes: &[BindedGeometryBuffers],
 ) -> Sphere {
     let global_node_scale = global_transform.scale();
     let largest_axis_scale = global_node_scale
@@ -568,7 +886,7 @@ fn build_mesh_bounding_sphere(
         .max(global_node_scale.y)
         .max(global_node_scale.z);
 
-    let bounding_box = renderer_data.binded_meshes[mesh_index].bounding_box;
+    let bounding_box = binded_meshes[mesh.index()].bounding_box;
 
     let center = global_transform.transform_point3((bounding_box.max + bounding_box.min) / 2.0);
 
@@ -582,12 +900,32 @@ impl GameNode {
     pub fn id(&self) -> GameNodeId {
         self.id
     }
+
+    /// alpha = 0 is the transform before the last fixed update and 1 the current one
+    pub fn interpolated_transform(&self, alpha: f32) -> crate::transform::Transform {
+        match self.previous_transform {
+            Some(previous_transform) if self.interpolate_transform => {
+                previous_transform.lerp(&self.transform, alpha)
+            }
+            _ => self.transform,
+        }
+    }
+
+    /// e.g. after teleporting the node, so it isn't drawn sliding across the map for a frame
+    pub fn reset_interpolation(&mut self) {
+        self.previous_transform = None;
+    }
 }
 
 impl GameNodeId {
     pub fn _raw(&self) -> (u32, usize) {
         (self.0, self.1)
     }
+
+    /// The node's slot in the scene, e.g. its key in Scene::bvh. Reused once the node is removed
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
 }
 
 impl Default for GameNodeDesc {
@@ -598,6 +936,10 @@ impl Default for GameNodeDesc {
             visual: None,
             name: None,
             parent_id: None,
+            visible: true,
+            casts_shadows: true,
+            render_layers: RenderLayers::DEFAULT,
+            interpolate_transform: false,
         }
     }
 }
@@ -609,6 +951,10 @@ pub struct GameNodeDescBuilder {
     visual: Option<GameNodeVisual>,
     name: Option<String>,
     parent_id: Option<GameNodeId>,
+    visible: bool,
+    casts_shadows: bool,
+    render_layers: RenderLayers,
+    interpolate_transform: bool,
 }
 
 impl GameNodeDescBuilder {
@@ -619,6 +965,10 @@ impl GameNodeDescBuilder {
             visual,
             name,
             parent_id,
+            visible,
+            casts_shadows,
+            render_layers,
+            interpolate_transform,
         } = GameNodeDesc::default();
         Self {
             transform,
@@ -626,6 +976,10 @@ impl GameNodeDescBuilder {
             visual,
             name,
             parent_id,
+            visible,
+            casts_shadows,
+            render_layers,
+            interpolate_transform,
         }
     }
 
@@ -655,6 +1009,26 @@ impl GameNodeDescBuilder {
         self
     }
 
+    pub fn visible(mut self, visible: bool) -> Self {
+        self.visible = visible;
+        self
+    }
+
+    pub fn casts_shadows(mut self, casts_shadows: bool) -> Self {
+        self.casts_shadows = casts_shadows;
+        self
+    }
+
+    pub fn render_layers(mut self, render_layers: RenderLayers) -> Self {
+        self.render_layers = render_layers;
+        self
+    }
+
+    pub fn interpolate_transform(mut self, interpolate_transform: bool) -> Self {
+        self.interpolate_transform = interpolate_transform;
+        self
+    }
+
     pub fn build(self) -> GameNodeDesc {
         GameNodeDesc {
             transform: self.transform,
@@ -662,6 +1036,10 @@ impl GameNodeDescBuilder {
             visual: self.visual,
             name: self.name,
             parent_id: self.parent_id,
+            visible: self.visible,
+            casts_shadows: self.casts_shadows,
+            render_layers: self.render_layers,
+            interpolate_transform: self.interpolate_transform,
         }
     }
 }
@@ -673,11 +1051,11 @@ impl Default for GameNodeDescBuilder {
 }
 
 impl GameNodeVisual {
-    pub fn make_pbr(mesh_index: usize, binded_pbr_material_index: usize) -> Self {
+    pub fn make_pbr(mesh: MeshHandle, binded_material: MaterialHandle) -> Self {
         Self {
-            mesh_index,
+            mesh,
             material: Material::Pbr {
-                binded_material_index: binded_pbr_material_index,
+                binded_material,
                 dynamic_pbr_params: None,
             },
             wireframe: false,
@@ -685,9 +1063,9 @@ impl GameNodeVisual {
         }
     }
 
-    pub fn from_mesh_mat(mesh_index: usize, material: Material) -> Self {
+    pub fn from_mesh_mat(mesh: MeshHandle, material: Material) -> Self {
         Self {
-            mesh_index,
+            mesh,
             material,
             wireframe: false,
             cullable: true,
@@ -733,10 +1111,114 @@ mod tests {
         assert_node_exists(&scene, node_3_id);
     }
 
+    #[test]
+    fn lights_are_added_and_removed_at_runtime() {
+        let mut scene = Scene::new(vec![], vec![], vec![]);
+        let [node_1_id, node_2_id] = [(); 2].map(|_| scene.add_node(GameNodeDesc::default()).id());
+        for node_id in [node_1_id, node_2_id] {
+            scene.add_point_light(PointLight {
+                node_id,
+                color: Vec3::ONE,
+                intensity: 1.0,
+                casts_shadows: true,
+            });
+        }
+
+        scene.get_point_light_mut(node_2_id).unwrap().intensity = 2.0;
+        assert_eq!(scene.remove_point_light(node_1_id).unwrap().intensity, 1.0);
+        assert!(scene.remove_point_light(node_1_id).is_none());
+        assert_eq!(scene.get_point_light(node_2_id).unwrap().intensity, 2.0);
+        assert_node_exists(&scene, node_1_id);
+
+        let directional_light = DirectionalLight {
+            direction: Vec3::NEG_Y,
+            color: Vec3::ONE,
+            intensity: 1.0,
+            shadow_mapping_config: Default::default(),
+        };
+        for light_index in 0..MAX_LIGHT_COUNT {
+            assert_eq!(
+                scene.add_directional_light(directional_light).unwrap(),
+                light_index
+            );
+        }
+        assert!(scene.add_directional_light(directional_light).is_err());
+        assert!(scene.remove_directional_light(0).is_some());
+        assert!(scene.remove_directional_light(MAX_LIGHT_COUNT).is_none());
+    }
+
+    #[test]
+    fn removing_a_subtree_removes_the_descendants() {
+        let mut scene = Scene::new(vec![], vec![], vec![]);
+
+        let root_id = scene.add_node(GameNodeDesc::default()).id();
+        let child_id = scene
+            .add_node(GameNodeDescBuilder::new().parent_id(Some(root_id)).build())
+            .id();
+        let grandchild_id = scene
+            .add_node(GameNodeDescBuilder::new().parent_id(Some(child_id)).build())
+            .id();
+        let other_id = scene.add_node(GameNodeDesc::default()).id();
+
+        scene.remove_subtree(child_id);
+
+        assert_node_exists(&scene, root_id);
+        assert_node_doesnt_exist(&scene, child_id);
+        assert_node_doesnt_exist(&scene, grandchild_id);
+        assert_node_exists(&scene, other_id);
+    }
+
+    #[test]
+    fn interpolated_nodes_are_rendered_between_fixed_updates() {
+        let mut scene = Scene::new(vec![], vec![], vec![]);
+        let node_id = scene
+            .add_node(
+                GameNodeDescBuilder::new()
+                    .interpolate_transform(true)
+                    .build(),
+            )
+            .id();
+
+        scene.store_previous_transforms();
+        scene
+            .get_node_mut(node_id)
+            .unwrap()
+            .transform
+            .set_position(Vec3::new(2.0, 0.0, 0.0));
+        scene.set_interpolation_alpha(0.25);
+
+        let node = scene.get_node(node_id).unwrap();
+        assert_eq!(
+            scene
+                .get_interpolated_global_transform_for_node(node_id)
+                .position(),
+            Vec3::new(0.5, 0.0, 0.0)
+        );
+        assert_eq!(
+            scene.get_global_transform_for_node(node_id).position(),
+            Vec3::new(2.0, 0.0, 0.0)
+        );
+
+        let mut node = node.clone();
+        node.reset_interpolation();
+        assert_eq!(
+            node.interpolated_transform(0.25).position(),
+            Vec3::new(2.0, 0.0, 0.0)
+        );
+    }
+
     fn assert_node_exists(scene: &Scene, node_id: GameNodeId) {
         assert_eq!(scene.get_node(node_id).map(|node| node.id), Some(node_id));
     }
 
+    #[test]
+    fn render_layers_stay_within_the_mask() {
+        assert_eq!(RenderLayers::layer(31).0, 1 << 31);
+        assert_eq!(RenderLayers::ALL.without(0).with(0), RenderLayers::ALL);
+        assert!(std::panic::catch_unwind(|| RenderLayers::layer(32)).is_err());
+        assert!(std::panic::catch_unwind(|| RenderLayers::DEFAULT.with(40)).is_err());
+    }
+
     fn assert_node_doesnt_exist(scene: &Scene, node_id: GameNodeId) {
         assert_eq!(scene.get_node(node_id).map(|node| node.id), None);
     }
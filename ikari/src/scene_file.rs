@@ -0,0 +1,809 @@
+use crate::asset_loader::*;
+use crate::asset_registry::MeshHandle;
+use crate::engine_state::EngineState;
+use crate::file_manager::{FileManager, GameFilePath, GamePathMaker};
+use crate::math::{deg_to_rad, rad_to_deg};
+use crate::mesh::{DynamicPbrParams, PbrTextures};
+use crate::physics::rapier3d_f64::prelude::*;
+use crate::physics::PhysicsState;
+use crate::ragdoll::transform_to_isometry;
+use crate::renderer::*;
+use crate::scene::*;
+use crate::transform::{Transform, TransformBuilder};
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use glam::f32::{Quat, Vec3};
+use glam::EulerRot;
+use serde::{Deserialize, Serialize};
+
+/// A level that can be written by hand or saved from a running game, e.g.:
+/// ```ron
+/// (
+///     nodes: [
+///         (name: Some("level"), visual: Some(Asset((path: "src/models/gltf/TestLevel/test_level.glb")))),
+///         (
+///             name: Some("crate"),
+///             transform: (position: (2.0, 5.0, 0.0), rotation: (0.0, 45.0, 0.0)),
+///             visual: Some(Primitive(shape: Cube, material: Pbr(base_color: (0.6, 0.4, 0.2, 1.0)))),
+///             rigid_body: Some((colliders: [(shape: Cuboid(half_extents: (1.0, 1.0, 1.0)))])),
+///         ),
+///         (name: Some("lamp"), transform: (position: (0.0, 3.0, 0.0))),
+///     ],
+///     point_lights: [(node: 2, color: (1.0, 0.9, 0.8), intensity: 5.0)],
+/// )
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneFile {
+    pub nodes: Vec<SceneFileNode>,
+    pub point_lights: Vec<SceneFilePointLight>,
+    pub directional_lights: Vec<SceneFileDirectionalLight>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneFileNode {
+    pub name: Option<String>,
+    /// index into SceneFile::nodes, parents must come before their children
+    pub parent: Option<usize>,
+    pub transform: SceneFileTransform,
+    pub visual: Option<SceneFileVisual>,
+    pub rigid_body: Option<SceneFileRigidBody>,
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneFileTransform {
+    pub position: [f32; 3],
+    /// euler angles in degrees, applied in yaw (y), pitch (x), roll (z) order
+    pub rotation: [f32; 3],
+    pub scale: [f32; 3],
+}
+
+impl Default for SceneFileTransform {
+    fn default() -> Self {
+        Self {
+            position: [0.0, 0.0, 0.0],
+            rotation: [0.0, 0.0, 0.0],
+            scale: [1.0, 1.0, 1.0],
+        }
+    }
+}
+
+impl From<Transform> for SceneFileTransform {
+    fn from(transform: Transform) -> Self {
+        let (yaw, pitch, roll) = transform.rotation().to_euler(EulerRot::YXZ);
+        Self {
+            position: transform.position().into(),
+            rotation: [rad_to_deg(pitch), rad_to_deg(yaw), rad_to_deg(roll)],
+            scale: transform.scale().into(),
+        }
+    }
+}
+
+impl From<SceneFileTransform> for Transform {
+    fn from(transform: SceneFileTransform) -> Self {
+        let [pitch, yaw, roll] = transform.rotation;
+        TransformBuilder::new()
+            .position(transform.position.into())
+            .rotation(Quat::from_euler(
+                EulerRot::YXZ,
+                deg_to_rad(yaw),
+                deg_to_rad(pitch),
+                deg_to_rad(roll),
+            ))
+            .scale(transform.scale.into())
+            .build()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum SceneFileVisual {
+    /// its nodes are added as children of this node once it's loaded
+    Asset(SceneFileAsset),
+    /// one of the renderer's built-in meshes
+    Primitive {
+        shape: PrimitiveShape,
+        material: SceneFileMaterial,
+        #[serde(default)]
+        wireframe: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneFileAsset {
+    /// gltf/glb file, relative to the game's root
+    pub path: String,
+    #[serde(default)]
+    pub generate_wireframe_meshes: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PrimitiveShape {
+    Cube,
+    Sphere,
+    Plane,
+}
+
+impl PrimitiveShape {
+    fn mesh(&self, constant_data: &RendererConstantData) -> MeshHandle {
+        match self {
+            PrimitiveShape::Cube => constant_data.cube_mesh,
+            PrimitiveShape::Sphere => constant_data.sphere_mesh,
+            PrimitiveShape::Plane => constant_data.plane_mesh,
+        }
+    }
+
+    fn from_mesh(mesh: MeshHandle, constant_data: &RendererConstantData) -> Option<Self> {
+        [
+            PrimitiveShape::Cube,
+            PrimitiveShape::Sphere,
+            PrimitiveShape::Plane,
+        ]
+        .into_iter()
+        .find(|shape| shape.mesh(constant_data) == mesh)
+    }
+}
+
+/// PBR materials don't have textures, use an asset for textured meshes
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum SceneFileMaterial {
+    Pbr {
+        #[serde(default = "default_color")]
+        base_color: [f32; 4],
+        #[serde(default)]
+        emissive: [f32; 3],
+        #[serde(default = "default_factor")]
+        metallic: f32,
+        #[serde(default = "default_factor")]
+        roughness: f32,
+    },
+    Unlit {
+        color: [f32; 3],
+    },
+    Transparent {
+        color: [f32; 4],
+        #[serde(default)]
+        premultiplied_alpha: bool,
+    },
+}
+
+fn default_color() -> [f32; 4] {
+    [1.0, 1.0, 1.0, 1.0]
+}
+
+fn default_factor() -> f32 {
+    1.0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneFileRigidBody {
+    pub body_type: SceneFileRigidBodyType,
+    /// positioned at the center of the rigid body. the node's scale isn't applied to them
+    pub colliders: Vec<SceneFileCollider>,
+}
+
+impl Default for SceneFileRigidBody {
+    fn default() -> Self {
+        Self {
+            body_type: SceneFileRigidBodyType::Dynamic,
+            colliders: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SceneFileRigidBodyType {
+    Dynamic,
+    Fixed,
+    KinematicPositionBased,
+    KinematicVelocityBased,
+}
+
+impl From<SceneFileRigidBodyType> for RigidBodyType {
+    fn from(body_type: SceneFileRigidBodyType) -> Self {
+        match body_type {
+            SceneFileRigidBodyType::Dynamic => RigidBodyType::Dynamic,
+            SceneFileRigidBodyType::Fixed => RigidBodyType::Fixed,
+            SceneFileRigidBodyType::KinematicPositionBased => RigidBodyType::KinematicPositionBased,
+            SceneFileRigidBodyType::KinematicVelocityBased => RigidBodyType::KinematicVelocityBased,
+        }
+    }
+}
+
+impl From<RigidBodyType> for SceneFileRigidBodyType {
+    fn from(body_type: RigidBodyType) -> Self {
+        match body_type {
+            RigidBodyType::Dynamic => SceneFileRigidBodyType::Dynamic,
+            RigidBodyType::Fixed => SceneFileRigidBodyType::Fixed,
+            RigidBodyType::KinematicPositionBased => SceneFileRigidBodyType::KinematicPositionBased,
+            RigidBodyType::KinematicVelocityBased => SceneFileRigidBodyType::KinematicVelocityBased,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneFileCollider {
+    pub shape: SceneFileColliderShape,
+    pub friction: f32,
+    pub restitution: f32,
+    pub density: f32,
+    pub is_sensor: bool,
+    /// bits of the rapier InteractionGroups
+    pub collision_memberships: u32,
+    pub collision_filter: u32,
+}
+
+impl Default for SceneFileCollider {
+    fn default() -> Self {
+        Self {
+            shape: SceneFileColliderShape::Ball { radius: 0.5 },
+            friction: 0.5,
+            restitution: 0.0,
+            density: 1.0,
+            is_sensor: false,
+            collision_memberships: u32::MAX,
+            collision_filter: u32::MAX,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub enum SceneFileColliderShape {
+    Ball {
+        radius: f32,
+    },
+    Cuboid {
+        half_extents: [f32; 3],
+    },
+    /// along the y axis
+    Capsule {
+        half_height: f32,
+        radius: f32,
+    },
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneFilePointLight {
+    /// index into SceneFile::nodes
+    pub node: usize,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    #[serde(default = "default_casts_shadows")]
+    pub casts_shadows: bool,
+}
+
+fn default_casts_shadows() -> bool {
+    true
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneFileDirectionalLight {
+    pub direction: [f32; 3],
+    pub color: [f32; 3],
+    pub intensity: f32,
+    #[serde(default)]
+    pub shadow_mapping: Option<SceneFileShadowMappingConfig>,
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneFileShadowMappingConfig {
+    pub num_cascades: u32,
+    pub maximum_distance: f32,
+    pub first_cascade_far_bound: f32,
+}
+
+impl SceneFile {
+    pub async fn load(path: &GameFilePath) -> Result<Self> {
+        let text = FileManager::read_to_string(path).await?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let scene_file: Self = ron::from_str(text)?;
+        scene_file.validate()?;
+        Ok(scene_file)
+    }
+
+    pub fn to_ron_string(&self) -> Result<String> {
+        Ok(ron::ser::to_string_pretty(
+            self,
+            ron::ser::PrettyConfig::default(),
+        )?)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: &GameFilePath) -> Result<()> {
+        std::fs::write(path.resolve(), self.to_ron_string()?)?;
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        let mut errors = vec![];
+        for (node_index, node) in self.nodes.iter().enumerate() {
+            if node.parent.is_some_and(|parent| parent >= node_index) {
+                errors.push(format!(
+                    "nodes[{node_index}]: parent must come before the node"
+                ));
+            }
+        }
+        for (light_index, light) in self.point_lights.iter().enumerate() {
+            if light.node >= self.nodes.len() {
+                errors.push(format!(
+                    "point_lights[{light_index}]: node {} doesn't exist",
+                    light.node
+                ));
+            }
+        }
+        if !errors.is_empty() {
+            anyhow::bail!("Invalid scene file:\n  - {}", errors.join("\n  - "));
+        }
+        Ok(())
+    }
+
+    /// Adds the scene's nodes, lights and rigid bodies to the engine state and starts loading
+    /// its assets. SceneFileInstance::update must then be called every frame until the assets
+    /// are spawned. Locks renderer.data
+    pub fn spawn(
+        &self,
+        engine_state: &mut EngineState,
+        renderer: &Renderer,
+        asset_loader: &AssetLoader,
+        path_maker: &GamePathMaker,
+    ) -> Result<SceneFileInstance> {
+        let mut instance = SceneFileInstance::default();
+        let mut renderer_data_guard = renderer.data.lock().unwrap();
+
+        // all the pbr primitives share a textureless material and override its params
+        let mut pbr_material_index = None;
+
+        for node in &self.nodes {
+            let visual = match &node.visual {
+                Some(SceneFileVisual::Primitive {
+                    shape,
+                    material,
+                    wireframe,
+                }) => {
+                    let material = match *material {
+                        SceneFileMaterial::Pbr {
+                            base_color,
+                            emissive,
+                            metallic,
+                            roughness,
+                        } => {
+                            let binded_material = match pbr_material_index {
+                                Some(pbr_material_index) => pbr_material_index,
+                                None => *pbr_material_index.insert(Renderer::bind_pbr_material(
+                                    &renderer.base,
+                                    &renderer.constant_data,
+                                    &mut renderer_data_guard,
+                                    &PbrTextures::default(),
+                                    DynamicPbrParams::default(),
+                                )?),
+                            };
+                            Material::Pbr {
+                                binded_material,
+                                dynamic_pbr_params: Some(DynamicPbrParams {
+                                    base_color_factor: base_color.into(),
+                                    emissive_factor: emissive.into(),
+                                    metallic_factor: metallic,
+                                    roughness_factor: roughness,
+                                    ..Default::default()
+                                }),
+                            }
+                        }
+                        SceneFileMaterial::Unlit { color } => Material::Unlit {
+                            color: color.into(),
+                        },
+                        SceneFileMaterial::Transparent {
+                            color,
+                            premultiplied_alpha,
+                        } => Material::Transparent {
+                            color: color.into(),
+                            premultiplied_alpha,
+                        },
+                    };
+                    Some(GameNodeVisual {
+                        wireframe: *wireframe,
+                        ..GameNodeVisual::from_mesh_mat(
+                            shape.mesh(&renderer.constant_data),
+                            material,
+                        )
+                    })
+                }
+                _ => None,
+            };
+
+            let node_id = engine_state
+                .scene
+                .add_node(
+                    GameNodeDescBuilder::new()
+                        .name(node.name.clone())
+                        .transform(node.transform.into())
+                        .visual(visual)
+                        .parent_id(node.parent.map(|parent| instance.node_ids[parent]))
+                        .build(),
+                )
+                .id();
+            instance.node_ids.push(node_id);
+
+            if let Some(SceneFileVisual::Asset(asset)) = &node.visual {
+                let asset_id = asset_loader.load_gltf_scene(SceneAssetLoadParams {
+                    path: path_maker.make(&asset.path),
+                    generate_wireframe_meshes: asset.generate_wireframe_meshes,
+                    generate_colliders: None,
+                });
+                instance.pending_assets.push((asset_id, node_id));
+                instance.assets.insert(node_id, asset.clone());
+            }
+
+            if let Some(rigid_body) = &node.rigid_body {
+                spawn_rigid_body(
+                    &mut engine_state.physics_state,
+                    &engine_state.scene,
+                    node_id,
+                    rigid_body,
+                );
+            }
+        }
+
+        for light in &self.point_lights {
+            engine_state.scene.point_lights.push(PointLight {
+                node_id: instance.node_ids[light.node],
+                color: light.color.into(),
+                intensity: light.intensity,
+                casts_shadows: light.casts_shadows,
+            });
+        }
+
+        for light in &self.directional_lights {
+            engine_state
+                .scene
+                .directional_lights
+                .push(DirectionalLight {
+                    direction: Vec3::from(light.direction).normalize_or_zero(),
+                    color: light.color.into(),
+                    intensity: light.intensity,
+                    shadow_mapping_config: light
+                        .shadow_mapping
+                        .map(|config| DirectionalLightShadowMappingConfig {
+                            num_cascades: config.num_cascades,
+                            maximum_distance: config.maximum_distance,
+                            first_cascade_far_bound: config.first_cascade_far_bound,
+                        })
+                        .unwrap_or_default(),
+                });
+        }
+
+        Ok(instance)
+    }
+
+    /// Describes the nodes, lights and rigid bodies of the scene. The nodes in assets are saved
+    /// as the asset's reference without their children (see SceneFileInstance::assets),
+    /// the other nodes that use meshes other than the built-in primitives are skipped
+    /// along with their children. Locks renderer.data
+    pub fn capture(
+        scene: &Scene,
+        physics_state: &PhysicsState,
+        renderer: &Renderer,
+        assets: &HashMap<GameNodeId, SceneFileAsset>,
+    ) -> Self {
+        let renderer_data_guard = renderer.data.lock().unwrap();
+        let constant_data = &renderer.constant_data;
+
+        // parents must be written before their children
+        let mut nodes: Vec<_> = scene
+            .nodes()
+            .map(|node| (scene.get_node_ancestry_list(node.id()).count(), node))
+            .collect();
+        nodes.sort_by_key(|(depth, _)| *depth);
+
+        let mut file = SceneFile::default();
+        let mut file_node_indices: HashMap<GameNodeId, usize> = HashMap::new();
+        let mut skipped_node_count = 0;
+
+        for (_, node) in nodes {
+            let node_id = node.id();
+
+            let parent = match node.parent_id {
+                // the asset's nodes come back when it's loaded again
+                Some(parent_id) if assets.contains_key(&parent_id) => continue,
+                Some(parent_id) => match file_node_indices.get(&parent_id) {
+                    Some(parent_index) => Some(*parent_index),
+                    None => continue,
+                },
+                None => None,
+            };
+
+            let visual = if let Some(asset) = assets.get(&node_id) {
+                Some(SceneFileVisual::Asset(asset.clone()))
+            } else if let Some(visual) = &node.visual {
+                let Some(shape) = PrimitiveShape::from_mesh(visual.mesh, constant_data) else {
+                    skipped_node_count += 1;
+                    continue;
+                };
+                let material = match visual.material {
+                    Material::Pbr {
+                        binded_material,
+                        dynamic_pbr_params,
+                    } => {
+                        let params = dynamic_pbr_params
+                            .or_else(|| {
+                                renderer_data_guard
+                                    .binded_pbr_materials
+                                    .get(binded_material.index())
+                                    .map(|material| material.dynamic_pbr_params)
+                            })
+                            .unwrap_or_default();
+                        SceneFileMaterial::Pbr {
+                            base_color: params.base_color_factor.into(),
+                            emissive: params.emissive_factor.into(),
+                            metallic: params.metallic_factor,
+                            roughness: params.roughness_factor,
+                        }
+                    }
+                    Material::Unlit { color } => SceneFileMaterial::Unlit {
+                        color: color.into(),
+                    },
+                    Material::Transparent {
+                        color,
+                        premultiplied_alpha,
+                    } => SceneFileMaterial::Transparent {
+                        color: color.into(),
+                        premultiplied_alpha,
+                    },
+                    // made in code by the game
+                    Material::Custom { .. } => {
+                        skipped_node_count += 1;
+                        continue;
+                    }
+                };
+                Some(SceneFileVisual::Primitive {
+                    shape,
+                    material,
+                    wireframe: visual.wireframe,
+                })
+            } else {
+                None
+            };
+
+            file_node_indices.insert(node_id, file.nodes.len());
+            file.nodes.push(SceneFileNode {
+                name: node.name.clone(),
+                parent,
+                transform: node.transform.into(),
+                visual,
+                rigid_body: capture_rigid_body(physics_state, node_id),
+            });
+        }
+
+        if skipped_node_count > 0 {
+            log::warn!(
+                "Skipped {skipped_node_count} nodes that weren't spawned from a scene file asset \
+                 or a primitive, they won't be in the saved scene"
+            );
+        }
+
+        file.point_lights = scene
+            .point_lights
+            .iter()
+            .filter_map(|light| {
+                Some(SceneFilePointLight {
+                    node: *file_node_indices.get(&light.node_id)?,
+                    color: light.color.into(),
+                    intensity: light.intensity,
+                    casts_shadows: light.casts_shadows,
+                })
+            })
+            .collect();
+
+        file.directional_lights = scene
+            .directional_lights
+            .iter()
+            .map(|light| SceneFileDirectionalLight {
+                direction: light.direction.into(),
+                color: light.color.into(),
+                intensity: light.intensity,
+                shadow_mapping: Some(SceneFileShadowMappingConfig {
+                    num_cascades: light.shadow_mapping_config.num_cascades,
+                    maximum_distance: light.shadow_mapping_config.maximum_distance,
+                    first_cascade_far_bound: light.shadow_mapping_config.first_cascade_far_bound,
+                }),
+            })
+            .collect();
+
+        file
+    }
+}
+
+fn spawn_rigid_body(
+    physics_state: &mut PhysicsState,
+    scene: &Scene,
+    node_id: GameNodeId,
+    rigid_body: &SceneFileRigidBody,
+) {
+    let isometry = transform_to_isometry(&scene.get_global_transform_for_node(node_id));
+    let rigid_body_handle = physics_state.rigid_body_set.insert(
+        RigidBodyBuilder::new(rigid_body.body_type.into())
+            .position(isometry)
+            .build(),
+    );
+    for collider in &rigid_body.colliders {
+        let collider_builder = match collider.shape {
+            SceneFileColliderShape::Ball { radius } => ColliderBuilder::ball(radius as f64),
+            SceneFileColliderShape::Cuboid { half_extents } => ColliderBuilder::cuboid(
+                half_extents[0] as f64,
+                half_extents[1] as f64,
+                half_extents[2] as f64,
+            ),
+            SceneFileColliderShape::Capsule {
+                half_height,
+                radius,
+            } => ColliderBuilder::capsule_y(half_height as f64, radius as f64),
+        };
+        physics_state.collider_set.insert_with_parent(
+            collider_builder
+                .friction(collider.friction as f64)
+                .restitution(collider.restitution as f64)
+                .density(collider.density as f64)
+                .sensor(collider.is_sensor)
+                .collision_groups(InteractionGroups::new(
+                    Group::from_bits_truncate(collider.collision_memberships),
+                    Group::from_bits_truncate(collider.collision_filter),
+                ))
+                .build(),
+            rigid_body_handle,
+            &mut physics_state.rigid_body_set,
+        );
+    }
+    physics_state.add_rigid_body_component(node_id, rigid_body_handle);
+}
+
+fn capture_rigid_body(
+    physics_state: &PhysicsState,
+    node_id: GameNodeId,
+) -> Option<SceneFileRigidBody> {
+    let rigid_body_handle = physics_state
+        .get_rigid_body_component(node_id)?
+        .rigid_body_handle;
+    let rigid_body = physics_state.rigid_body_set.get(rigid_body_handle)?;
+
+    let colliders = rigid_body
+        .colliders()
+        .iter()
+        .filter_map(|collider_handle| physics_state.collider_set.get(*collider_handle))
+        .filter_map(|collider| {
+            let shape = collider.shape();
+            let shape = if let Some(ball) = shape.as_ball() {
+                SceneFileColliderShape::Ball {
+                    radius: ball.radius as f32,
+                }
+            } else if let Some(cuboid) = shape.as_cuboid() {
+                SceneFileColliderShape::Cuboid {
+                    half_extents: [
+                        cuboid.half_extents.x as f32,
+                        cuboid.half_extents.y as f32,
+                        cuboid.half_extents.z as f32,
+                    ],
+                }
+            } else if let Some(capsule) = shape.as_capsule() {
+                SceneFileColliderShape::Capsule {
+                    half_height: capsule.half_height() as f32,
+                    radius: capsule.radius as f32,
+                }
+            } else {
+                log::warn!("Skipped a collider of node {node_id:?} whose shape can't be saved");
+                return None;
+            };
+            let collision_groups = collider.collision_groups();
+            Some(SceneFileCollider {
+                shape,
+                friction: collider.friction() as f32,
+                restitution: collider.restitution() as f32,
+                density: collider.density() as f32,
+                is_sensor: collider.is_sensor(),
+                collision_memberships: collision_groups.memberships.bits(),
+                collision_filter: collision_groups.filter.bits(),
+            })
+        })
+        .collect();
+
+    Some(SceneFileRigidBody {
+        body_type: rigid_body.body_type().into(),
+        colliders,
+    })
+}
+
+/// Keeps track of the nodes that were spawned from a SceneFile
+#[derive(Debug, Default)]
+pub struct SceneFileInstance {
+    /// the scene node spawned for each of SceneFile::nodes, in the same order
+    pub node_ids: Vec<GameNodeId>,
+    assets: HashMap<GameNodeId, SceneFileAsset>,
+    pending_assets: Vec<(AssetId, GameNodeId)>,
+}
+
+impl SceneFileInstance {
+    /// true until all the assets were spawned
+    pub fn is_loading(&self) -> bool {
+        !self.pending_assets.is_empty()
+    }
+
+    /// the node under which each asset is spawned -> the asset
+    pub fn assets(&self) -> &HashMap<GameNodeId, SceneFileAsset> {
+        &self.assets
+    }
+
+    /// shorthand for SceneFile::capture with the assets of this instance
+    pub fn save(&self, engine_state: &EngineState, renderer: &Renderer) -> SceneFile {
+        SceneFile::capture(
+            &engine_state.scene,
+            &engine_state.physics_state,
+            renderer,
+            &self.assets,
+        )
+    }
+
+    /// Merges the assets that finished loading into the scene under their node.
+    /// loaded_scenes comes from AssetBinder::loaded_scenes
+    pub fn update(
+        &mut self,
+        scene: &mut Scene,
+        renderer_data: &mut RendererData,
+        loaded_scenes: &mut HashMap<AssetId, (Scene, BindedSceneData)>,
+    ) {
+        self.pending_assets.retain(|(asset_id, parent_node_id)| {
+            let Some((other_scene, other_render_buffers)) = loaded_scenes.remove(asset_id) else {
+                return true;
+            };
+
+            // the other scene's nodes are appended after the existing ones
+            let skip_nodes = scene.node_count();
+            scene.merge_scene(renderer_data, other_scene, other_render_buffers);
+            for node in scene.nodes_mut().skip(skip_nodes) {
+                if node.parent_id.is_none() {
+                    node.parent_id = Some(*parent_node_id);
+                }
+            }
+            false
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transforms_survive_a_round_trip() {
+        let transform: Transform = TransformBuilder::new()
+            .position(Vec3::new(1.0, 2.0, 3.0))
+            .rotation(Quat::from_euler(EulerRot::YXZ, 0.5, -0.25, 0.125))
+            .scale(Vec3::new(2.0, 2.0, 2.0))
+            .build();
+        let file_transform: SceneFileTransform = transform.into();
+        let text = ron::to_string(&file_transform).unwrap();
+        let parsed: Transform = ron::from_str::<SceneFileTransform>(&text).unwrap().into();
+
+        assert!(parsed.position().abs_diff_eq(transform.position(), 1e-5));
+        assert!(parsed.scale().abs_diff_eq(transform.scale(), 1e-5));
+        assert!(parsed.rotation().angle_between(transform.rotation()) < 1e-4);
+    }
+
+    #[test]
+    fn parents_must_come_first() {
+        let text = "(nodes: [(parent: Some(1)), ()])";
+        assert!(SceneFile::parse(text).is_err());
+        assert!(SceneFile::parse("(nodes: [(), (parent: Some(0))])").is_ok());
+    }
+}
@@ -0,0 +1,373 @@
+use crate::asset_loader::{AssetBinder, AssetId, AssetLoader, SceneAssetLoadParams};
+use crate::hud::ProgressBarStyle;
+use crate::renderer::BindedSceneData;
+use crate::scene::Scene;
+use crate::time::Duration;
+
+use anyhow::{bail, Result};
+use iced::widget::{progress_bar, text, Column, Container};
+use iced::{Alignment, Background, Color, Element, Length, Theme};
+use std::collections::HashMap;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SceneTransitionPhase {
+    Idle,
+    /// the loading screen fades in over the old scene
+    FadingOut,
+    /// the loading screen covers the whole screen until the new scenes are on the gpu
+    Loading,
+    /// the loading screen fades out over the new scene
+    FadingIn,
+}
+
+/// A scene that was loaded and uploaded to the gpu, ready for Scene::merge_scene
+#[derive(Debug)]
+pub struct LoadedScene {
+    pub asset_id: AssetId,
+    pub params: SceneAssetLoadParams,
+    pub scene: Scene,
+    pub binded_data: BindedSceneData,
+}
+
+#[derive(Debug)]
+struct TransitionScene {
+    asset_id: AssetId,
+    params: SceneAssetLoadParams,
+    loaded: Option<(Scene, BindedSceneData)>,
+    /// the last progress reported by the loader
+    progress: f32,
+}
+
+/// What the ui needs to draw the loading screen, see LoadingScreen::view
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct LoadingScreen {
+    /// 0 when the screen is clear, 1 when it's fully covered
+    pub opacity: f32,
+    /// None unless the scenes are loading
+    pub progress: Option<f32>,
+}
+
+/// Moves the game from one set of scenes to another behind a loading screen. The new scenes
+/// start loading with the AssetLoader as soon as the transition starts and the loading screen
+/// fades in. Once it covers the screen and all the scenes are uploaded to the gpu, update hands
+/// them over so the game can swap out the old ones, then the loading screen fades out.
+///
+/// Everything is polled from update, so the render loop keeps going the whole time
+#[derive(Debug)]
+pub struct SceneTransition {
+    pub fade_out_duration: Duration,
+    pub fade_in_duration: Duration,
+    /// the scenes that still aren't loaded by then are given up on, e.g. because the file is
+    /// missing. The loader logs their errors
+    pub load_timeout: Duration,
+    phase: SceneTransitionPhase,
+    opacity: f32,
+    loading_time: Duration,
+    scenes: Vec<TransitionScene>,
+    /// given up on but still loading, they're dropped when they show up
+    abandoned_asset_ids: Vec<AssetId>,
+}
+
+impl Default for SceneTransition {
+    fn default() -> Self {
+        Self {
+            fade_out_duration: Duration::from_secs_f32(0.5),
+            fade_in_duration: Duration::from_secs_f32(0.5),
+            load_timeout: Duration::from_secs(60),
+            phase: SceneTransitionPhase::Idle,
+            opacity: 0.0,
+            loading_time: Duration::ZERO,
+            scenes: vec![],
+            abandoned_asset_ids: vec![],
+        }
+    }
+}
+
+impl SceneTransition {
+    /// Starts loading the scenes. Fails if a transition is already in progress
+    pub fn start(
+        &mut self,
+        asset_loader: &AssetLoader,
+        scenes: Vec<SceneAssetLoadParams>,
+    ) -> Result<()> {
+        if self.is_active() {
+            bail!("A scene transition is already in progress");
+        }
+        self.scenes = scenes
+            .into_iter()
+            .map(|params| TransitionScene {
+                asset_id: asset_loader.load_gltf_scene(params.clone()),
+                params,
+                loaded: None,
+                progress: 0.0,
+            })
+            .collect();
+        self.phase = SceneTransitionPhase::FadingOut;
+        self.loading_time = Duration::ZERO;
+        Ok(())
+    }
+
+    /// Call it once per frame, after AssetBinder::update. Returns the new scenes, in the order
+    /// they were given to start, on the frame they're handed over. The old scenes are fully
+    /// hidden by the loading screen at that point and can be removed
+    pub fn update(
+        &mut self,
+        delta_time: Duration,
+        asset_loader: &AssetLoader,
+        asset_binder: &AssetBinder,
+    ) -> Option<Vec<LoadedScene>> {
+        {
+            let loaded_scenes = asset_binder.loaded_scenes();
+            self.collect_loaded_scenes(&mut loaded_scenes.lock().unwrap(), |asset_id| {
+                asset_loader.scene_load_progress(asset_id)
+            });
+        }
+
+        if !self.advance(delta_time) {
+            return None;
+        }
+
+        Some(
+            self.scenes
+                .drain(..)
+                .filter_map(|scene| {
+                    let Some((loaded_scene, binded_data)) = scene.loaded else {
+                        log::warn!(
+                            "Scene {:?} didn't load in time for the scene transition",
+                            scene.params.path
+                        );
+                        self.abandoned_asset_ids.push(scene.asset_id);
+                        return None;
+                    };
+                    Some(LoadedScene {
+                        asset_id: scene.asset_id,
+                        params: scene.params,
+                        scene: loaded_scene,
+                        binded_data,
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    /// Takes the scenes of the transition out of the loaded scenes, and drops the abandoned ones
+    /// so they don't sit there forever. scene_load_progress is AssetLoader::scene_load_progress
+    fn collect_loaded_scenes(
+        &mut self,
+        loaded_scenes: &mut HashMap<AssetId, (Scene, BindedSceneData)>,
+        scene_load_progress: impl Fn(AssetId) -> Option<f32>,
+    ) {
+        if matches!(
+            self.phase,
+            SceneTransitionPhase::FadingOut | SceneTransitionPhase::Loading
+        ) {
+            for scene in self
+                .scenes
+                .iter_mut()
+                .filter(|scene| scene.loaded.is_none())
+            {
+                scene.loaded = loaded_scenes.remove(&scene.asset_id);
+                if let Some(progress) = scene_load_progress(scene.asset_id) {
+                    scene.progress = progress;
+                }
+            }
+        }
+
+        // the progress goes away after the scene is loaded, so a scene without one that isn't
+        // loaded either failed
+        self.abandoned_asset_ids.retain(|asset_id| {
+            let is_loading = scene_load_progress(*asset_id).is_some();
+            if loaded_scenes.remove(asset_id).is_some() {
+                log::debug!("Dropped scene {asset_id:?} that loaded after its scene transition");
+                return false;
+            }
+            is_loading
+        });
+    }
+
+    /// Returns true when the scenes should be handed over
+    fn advance(&mut self, delta_time: Duration) -> bool {
+        match self.phase {
+            SceneTransitionPhase::Idle => {}
+            SceneTransitionPhase::FadingOut => {
+                self.opacity += fade_step(delta_time, self.fade_out_duration);
+                self.loading_time += delta_time;
+                if self.opacity >= 1.0 {
+                    self.opacity = 1.0;
+                    self.phase = SceneTransitionPhase::Loading;
+                }
+            }
+            SceneTransitionPhase::Loading => {
+                self.loading_time += delta_time;
+                let is_done = self.scenes.iter().all(|scene| scene.loaded.is_some());
+                if is_done || self.loading_time >= self.load_timeout {
+                    self.phase = SceneTransitionPhase::FadingIn;
+                    return true;
+                }
+            }
+            SceneTransitionPhase::FadingIn => {
+                self.opacity -= fade_step(delta_time, self.fade_in_duration);
+                if self.opacity <= 0.0 {
+                    self.opacity = 0.0;
+                    self.phase = SceneTransitionPhase::Idle;
+                }
+            }
+        }
+        false
+    }
+
+    pub fn phase(&self) -> SceneTransitionPhase {
+        self.phase
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.phase != SceneTransitionPhase::Idle
+    }
+
+    /// 0..1, the average of the scenes' progress as reported by the loader
+    pub fn progress(&self) -> f32 {
+        if self.scenes.is_empty() {
+            return 1.0;
+        }
+        let done: f32 = self
+            .scenes
+            .iter()
+            .map(|scene| match scene.loaded {
+                Some(_) => 1.0,
+                None => scene.progress,
+            })
+            .sum();
+        done / self.scenes.len() as f32
+    }
+
+    pub fn loading_screen(&self) -> LoadingScreen {
+        LoadingScreen {
+            opacity: self.opacity,
+            progress: match self.phase {
+                SceneTransitionPhase::FadingOut | SceneTransitionPhase::Loading => {
+                    Some(self.progress())
+                }
+                _ => None,
+            },
+        }
+    }
+}
+
+fn fade_step(delta_time: Duration, duration: Duration) -> f32 {
+    if duration.is_zero() {
+        return 1.0;
+    }
+    delta_time.as_secs_f32() / duration.as_secs_f32()
+}
+
+impl LoadingScreen {
+    pub fn is_visible(&self) -> bool {
+        self.opacity > 0.0
+    }
+
+    /// Fills the screen with black and a progress bar in the middle, meant to be drawn over
+    /// the rest of the ui
+    pub fn view<'a, Message: 'a>(&self) -> Element<'a, Message, Theme, iced::Renderer> {
+        let opacity = self.opacity.clamp(0.0, 1.0);
+        let mut column = Column::new().spacing(12).align_items(Alignment::Center);
+        if let Some(progress) = self.progress {
+            column = column
+                .push(
+                    text("Loading...")
+                        .size(24.0)
+                        .style(Color::from_rgba(1.0, 1.0, 1.0, opacity)),
+                )
+                .push(
+                    progress_bar(0.0..=1.0, progress.clamp(0.0, 1.0))
+                        .width(Length::Fixed(320.0))
+                        .height(Length::Fixed(12.0))
+                        .style(iced::theme::ProgressBar::Custom(Box::new(
+                            ProgressBarStyle {
+                                bar_color: Color::from_rgba(0.9, 0.9, 0.9, opacity),
+                                background_color: Color::from_rgba(0.2, 0.2, 0.2, opacity),
+                            },
+                        ))),
+                );
+        }
+        Container::new(column)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .center_x()
+            .center_y()
+            .style(iced::theme::Container::Custom(Box::new(BackgroundStyle(
+                Color::from_rgba(0.0, 0.0, 0.0, opacity),
+            ))))
+            .into()
+    }
+}
+
+struct BackgroundStyle(Color);
+
+impl iced::widget::container::StyleSheet for BackgroundStyle {
+    type Style = Theme;
+
+    fn appearance(&self, _: &Self::Style) -> iced::widget::container::Appearance {
+        iced::widget::container::Appearance {
+            background: Some(Background::Color(self.0)),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manager::GamePathMaker;
+
+    #[test]
+    fn scenes_are_handed_over_behind_the_loading_screen() {
+        let mut transition = SceneTransition {
+            fade_out_duration: Duration::from_secs(1),
+            phase: SceneTransitionPhase::FadingOut,
+            ..Default::default()
+        };
+        for id in [1, 2] {
+            transition.scenes.push(TransitionScene {
+                asset_id: AssetId(id),
+                params: SceneAssetLoadParams {
+                    path: GamePathMaker::new(None).make("level.glb"),
+                    generate_wireframe_meshes: false,
+                    generate_colliders: None,
+                },
+                loaded: None,
+                progress: 0.0,
+            });
+        }
+        let mut loaded_scenes = HashMap::new();
+        transition.collect_loaded_scenes(&mut loaded_scenes, |asset_id| {
+            (asset_id == AssetId(2)).then_some(0.5)
+        });
+        assert_eq!(transition.progress(), 0.25);
+
+        // not before the screen is covered
+        transition.scenes[0].loaded = Some(Default::default());
+        transition.scenes[1].loaded = Some(Default::default());
+        assert!(!transition.advance(Duration::from_secs_f32(0.6)));
+        assert_eq!(transition.loading_screen().progress, Some(1.0));
+        assert!(!transition.advance(Duration::from_secs_f32(0.6)));
+        assert_eq!(transition.phase(), SceneTransitionPhase::Loading);
+        assert_eq!(transition.loading_screen().opacity, 1.0);
+        assert!(transition.advance(Duration::ZERO));
+
+        assert!(!transition.advance(Duration::from_secs_f32(0.25)));
+        assert_eq!(transition.loading_screen().opacity, 0.5);
+        assert_eq!(transition.loading_screen().progress, None);
+        transition.advance(Duration::from_secs_f32(0.25));
+        assert!(!transition.is_active());
+        assert!(!transition.loading_screen().is_visible());
+
+        // a scene that was given up on is dropped once it shows up
+        transition.abandoned_asset_ids.push(AssetId(3));
+        transition.collect_loaded_scenes(&mut loaded_scenes, |_| Some(0.5));
+        assert_eq!(transition.abandoned_asset_ids, vec![AssetId(3)]);
+        loaded_scenes.insert(AssetId(3), Default::default());
+        transition.collect_loaded_scenes(&mut loaded_scenes, |_| None);
+        assert!(loaded_scenes.is_empty());
+        assert!(transition.abandoned_asset_ids.is_empty());
+    }
+}
@@ -0,0 +1,581 @@
+use crate::animation::LoopType;
+use crate::engine_state::EngineState;
+use crate::file_manager::GameFilePath;
+use crate::file_watcher::FileWatcher;
+use crate::physics::PhysicsEventKind;
+use crate::prefab::{PrefabLibrary, PrefabOverrides};
+use crate::ragdoll::transform_to_isometry;
+use crate::scene::*;
+use crate::time::Duration;
+use crate::transform::{Transform, TransformBuilder};
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use glam::f32::{Quat, Vec3};
+use mlua::{
+    FromLua, Function, Lua, LuaOptions, MetaMethod, RegistryKey, StdLib, Table, UserData,
+    UserDataMethods,
+};
+use winit::event::{ElementState, WindowEvent};
+use winit::keyboard::Key;
+
+/// A node as seen by scripts. Nodes can be compared with == and passed back to the api
+#[derive(Debug, Copy, Clone, PartialEq, Eq, FromLua)]
+pub struct ScriptNode(pub GameNodeId);
+
+impl UserData for ScriptNode {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_meta_method(MetaMethod::Eq, |_, this, other: ScriptNode| {
+            Ok(*this == other)
+        });
+    }
+}
+
+struct ScriptSource {
+    path: GameFilePath,
+    /// the table returned by the script, which holds its callbacks
+    callbacks: RegistryKey,
+}
+
+struct NodeScript {
+    node_id: GameNodeId,
+    source_index: usize,
+    /// passed to the callbacks as self, the script keeps its state in it
+    instance: RegistryKey,
+    is_initialized: bool,
+    /// the script isn't run anymore after an error, until its file changes
+    has_failed: bool,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum ScriptCallKind {
+    Init,
+    Update(f64),
+    Collision(Option<GameNodeId>),
+}
+
+impl ScriptCallKind {
+    fn callback_name(self) -> &'static str {
+        match self {
+            ScriptCallKind::Init => "on_init",
+            ScriptCallKind::Update(_) => "on_update",
+            ScriptCallKind::Collision(_) => "on_collision",
+        }
+    }
+}
+
+/// Runs Lua scripts attached to nodes. A script file must return a table with any of these
+/// callbacks, which get the node's instance table as self (with self.node set) and the api:
+///
+/// - on_init(self, api)
+/// - on_update(self, api, delta_time_seconds)
+/// - on_collision(self, api, other_node), other_node is nil for colliders without a node
+///
+/// The api can move nodes, spawn prefabs from ScriptHost::prefabs, play animations,
+/// the sounds registered with register_sound and sound cues and query the keyboard,
+/// see create_api.
+/// It's only valid during the callback. Scripts don't have access to the file system
+pub struct ScriptHost {
+    lua: Lua,
+    sources: Vec<ScriptSource>,
+    /// by source index
+    source_watcher: FileWatcher<usize>,
+    scripts: Vec<NodeScript>,
+    sounds: HashMap<String, usize>,
+    pub prefabs: PrefabLibrary,
+    /// logical key names in lowercase, e.g. "w" or "space"
+    pressed_keys: HashSet<String>,
+}
+
+impl ScriptHost {
+    pub fn new() -> Result<Self> {
+        let lua = Lua::new_with(
+            StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::UTF8 | StdLib::COROUTINE,
+            LuaOptions::default(),
+        )?;
+        Ok(Self {
+            lua,
+            sources: vec![],
+            source_watcher: FileWatcher::new(Duration::ZERO),
+            scripts: vec![],
+            sounds: HashMap::new(),
+            prefabs: PrefabLibrary::default(),
+            pressed_keys: HashSet::new(),
+        })
+    }
+
+    /// lets scripts play the sound with api.play_sound(name)
+    pub fn register_sound(&mut self, name: &str, sound_index: usize) {
+        self.sounds.insert(name.to_string(), sound_index);
+    }
+
+    /// The file is loaded once and shared by all the nodes it's attached to.
+    /// on_init is called during the next update
+    pub fn attach(&mut self, node_id: GameNodeId, path: &GameFilePath) -> Result<()> {
+        let source_index = match self.sources.iter().position(|source| source.path == *path) {
+            Some(source_index) => source_index,
+            None => {
+                let callbacks = load_source(&self.lua, path)?;
+                self.sources.push(ScriptSource {
+                    path: path.clone(),
+                    callbacks,
+                });
+                let source_index = self.sources.len() - 1;
+                self.source_watcher
+                    .watch(source_index, [path.resolve().into()]);
+                source_index
+            }
+        };
+
+        let instance = self.lua.create_table()?;
+        instance.set("node", ScriptNode(node_id))?;
+        self.scripts.push(NodeScript {
+            node_id,
+            source_index,
+            instance: self.lua.create_registry_value(instance)?,
+            is_initialized: false,
+            has_failed: false,
+        });
+        Ok(())
+    }
+
+    /// the scripts of removed nodes are detached automatically
+    pub fn detach(&mut self, node_id: GameNodeId) {
+        self.scripts.retain(|script| script.node_id != node_id);
+    }
+
+    /// Reloads the scripts whose file changed on disk, e.g. to tweak gameplay while the game
+    /// is running. The nodes keep their instance table so the scripts don't lose their state
+    pub fn reload_changed_scripts(&mut self) {
+        for source_index in self.source_watcher.poll() {
+            let source = &mut self.sources[source_index];
+            match load_source(&self.lua, &source.path) {
+                Ok(callbacks) => {
+                    source.callbacks = callbacks;
+                    for script in &mut self.scripts {
+                        if script.source_index == source_index {
+                            script.has_failed = false;
+                        }
+                    }
+                    log::info!("Reloaded script {}", source.path.relative_path.display());
+                }
+                Err(err) => {
+                    log::error!(
+                        "Error reloading script {}: {err:?}",
+                        source.path.relative_path.display()
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn process_window_event(&mut self, event: &WindowEvent) {
+        let WindowEvent::KeyboardInput { event, .. } = event else {
+            return;
+        };
+        let key_name = match event.logical_key.as_ref() {
+            Key::Character(character) => character.to_lowercase(),
+            Key::Named(named_key) => format!("{named_key:?}").to_lowercase(),
+            _ => return,
+        };
+        match event.state {
+            ElementState::Pressed => {
+                self.pressed_keys.insert(key_name);
+            }
+            ElementState::Released => {
+                self.pressed_keys.remove(&key_name);
+            }
+        }
+    }
+
+    /// Calls on_init for the newly attached scripts and then on_update for all of them.
+    /// Should be called once per frame
+    #[profiling::function]
+    pub fn update(&mut self, engine_state: &mut EngineState, delta_time_seconds: f64) {
+        self.scripts
+            .retain(|script| engine_state.scene.get_node(script.node_id).is_some());
+        // the registry values of the detached scripts are freed here
+        self.lua.expire_registry_values();
+
+        let mut calls = vec![];
+        for (script_index, script) in self.scripts.iter_mut().enumerate() {
+            if !script.is_initialized {
+                script.is_initialized = true;
+                calls.push((script_index, ScriptCallKind::Init));
+            }
+            calls.push((script_index, ScriptCallKind::Update(delta_time_seconds)));
+        }
+        self.run_callbacks(engine_state, calls);
+    }
+
+    /// Calls on_collision for the contacts that began during the last physics step.
+    /// Should be called after each step, e.g. at the end of on_fixed_update
+    #[profiling::function]
+    pub fn fixed_update(&mut self, engine_state: &mut EngineState) {
+        let mut calls = vec![];
+        for event in engine_state.physics_state.events() {
+            if !matches!(event.kind, PhysicsEventKind::Began) {
+                continue;
+            }
+            for (script_index, script) in self.scripts.iter().enumerate() {
+                if script.node_id == event.node_id && script.is_initialized {
+                    calls.push((script_index, ScriptCallKind::Collision(event.other_node_id)));
+                }
+            }
+        }
+        self.run_callbacks(engine_state, calls);
+    }
+
+    fn run_callbacks(
+        &mut self,
+        engine_state: &mut EngineState,
+        calls: Vec<(usize, ScriptCallKind)>,
+    ) {
+        if calls.is_empty() {
+            return;
+        }
+
+        let lua = &self.lua;
+        let sources = &self.sources;
+        let scripts = &self.scripts;
+        let engine_state = RefCell::new(engine_state);
+        let mut failed_script_indices = vec![];
+
+        let result = lua.scope(|scope| {
+            let api = create_api(
+                lua,
+                scope,
+                &engine_state,
+                &self.sounds,
+                &self.prefabs,
+                &self.pressed_keys,
+            )?;
+
+            for (script_index, call_kind) in calls {
+                let script = &scripts[script_index];
+                if script.has_failed || failed_script_indices.contains(&script_index) {
+                    continue;
+                }
+                let source = &sources[script.source_index];
+                let callbacks: Table = lua.registry_value(&source.callbacks)?;
+                let Some(callback) =
+                    callbacks.get::<_, Option<Function>>(call_kind.callback_name())?
+                else {
+                    continue;
+                };
+                let instance: Table = lua.registry_value(&script.instance)?;
+
+                let result = match call_kind {
+                    ScriptCallKind::Init => callback.call::<_, ()>((instance, api.clone())),
+                    ScriptCallKind::Update(delta_time_seconds) => {
+                        callback.call::<_, ()>((instance, api.clone(), delta_time_seconds))
+                    }
+                    ScriptCallKind::Collision(other_node_id) => callback.call::<_, ()>((
+                        instance,
+                        api.clone(),
+                        other_node_id.map(ScriptNode),
+                    )),
+                };
+                if let Err(err) = result {
+                    log::error!(
+                        "Error in {} of script {}, it won't run again until it's reloaded:\n{err}",
+                        call_kind.callback_name(),
+                        source.path.relative_path.display()
+                    );
+                    failed_script_indices.push(script_index);
+                }
+            }
+            Ok(())
+        });
+        if let Err(err) = result {
+            log::error!("Error running scripts: {err}");
+        }
+
+        for script_index in failed_script_indices {
+            self.scripts[script_index].has_failed = true;
+        }
+    }
+}
+
+fn load_source(lua: &Lua, path: &GameFilePath) -> Result<RegistryKey> {
+    let source = std::fs::read_to_string(path.resolve())?;
+    let callbacks: Table = lua
+        .load(&source)
+        .set_name(path.relative_path.to_string_lossy())
+        .eval()?;
+    Ok(lua.create_registry_value(callbacks)?)
+}
+
+fn removed_node_error() -> mlua::Error {
+    mlua::Error::RuntimeError(String::from("the node was removed"))
+}
+
+/// also moves the node's rigid body, if it has a RigidBodyComponent
+fn update_node_transform(
+    engine_state: &mut EngineState,
+    node: ScriptNode,
+    update: impl FnOnce(&mut Transform),
+) -> mlua::Result<()> {
+    let scene_node = engine_state
+        .scene
+        .get_node_mut(node.0)
+        .ok_or_else(removed_node_error)?;
+    update(&mut scene_node.transform);
+    if engine_state
+        .physics_state
+        .get_rigid_body_component(node.0)
+        .is_some()
+    {
+        let global_transform = engine_state.scene.get_global_transform_for_node(node.0);
+        engine_state
+            .physics_state
+            .teleport_rigid_body_component(node.0, transform_to_isometry(&global_transform));
+    }
+    Ok(())
+}
+
+/// Transforms are local to the node's parent, rotations are quaternions given as x, y, z, w:
+///
+/// - get_position(node) -> x, y, z / set_position(node, x, y, z)
+/// - get_rotation(node) -> x, y, z, w / set_rotation(node, x, y, z, w)
+/// - rotate(node, axis_x, axis_y, axis_z, angle_radians)
+/// - get_scale(node) -> x, y, z / set_scale(node, x, y, z)
+/// - get_name(node) -> name or nil / find_node(name) -> node or nil
+/// - spawn(prefab_name, x, y, z) -> node / remove_node(node), which also removes its children
+/// - play_animation(name, is_looping) / stop_animation(name)
+/// - play_sound(name)
+/// - trigger_cue(name) / trigger_cue_at(name, x, y, z), see sound_cue::SoundCue
+/// - is_key_down(key_name)
+/// - log(message)
+fn create_api<'lua, 'scope>(
+    lua: &'lua Lua,
+    scope: &mlua::Scope<'lua, 'scope>,
+    engine_state: &'scope RefCell<&mut EngineState>,
+    sounds: &'scope HashMap<String, usize>,
+    prefabs: &'scope PrefabLibrary,
+    pressed_keys: &'scope HashSet<String>,
+) -> mlua::Result<Table<'lua>> {
+    let api = lua.create_table()?;
+
+    api.set(
+        "get_position",
+        scope.create_function(|_, node: ScriptNode| {
+            let engine_state = engine_state.borrow();
+            let scene_node = engine_state
+                .scene
+                .get_node(node.0)
+                .ok_or_else(removed_node_error)?;
+            let position = scene_node.transform.position();
+            Ok((position.x, position.y, position.z))
+        })?,
+    )?;
+    api.set(
+        "set_position",
+        scope.create_function(|_, (node, x, y, z): (ScriptNode, f32, f32, f32)| {
+            update_node_transform(&mut engine_state.borrow_mut(), node, |transform| {
+                transform.set_position(Vec3::new(x, y, z))
+            })
+        })?,
+    )?;
+    api.set(
+        "get_rotation",
+        scope.create_function(|_, node: ScriptNode| {
+            let engine_state = engine_state.borrow();
+            let scene_node = engine_state
+                .scene
+                .get_node(node.0)
+                .ok_or_else(removed_node_error)?;
+            let rotation = scene_node.transform.rotation();
+            Ok((rotation.x, rotation.y, rotation.z, rotation.w))
+        })?,
+    )?;
+    api.set(
+        "set_rotation",
+        scope.create_function(|_, (node, x, y, z, w): (ScriptNode, f32, f32, f32, f32)| {
+            update_node_transform(&mut engine_state.borrow_mut(), node, |transform| {
+                transform.set_rotation(Quat::from_xyzw(x, y, z, w).normalize())
+            })
+        })?,
+    )?;
+    api.set(
+        "rotate",
+        scope.create_function(
+            |_, (node, x, y, z, angle): (ScriptNode, f32, f32, f32, f32)| {
+                let axis = Vec3::new(x, y, z).normalize_or_zero();
+                update_node_transform(&mut engine_state.borrow_mut(), node, |transform| {
+                    if axis != Vec3::ZERO {
+                        transform.set_rotation(
+                            Quat::from_axis_angle(axis, angle) * transform.rotation(),
+                        );
+                    }
+                })
+            },
+        )?,
+    )?;
+    api.set(
+        "get_scale",
+        scope.create_function(|_, node: ScriptNode| {
+            let engine_state = engine_state.borrow();
+            let scene_node = engine_state
+                .scene
+                .get_node(node.0)
+                .ok_or_else(removed_node_error)?;
+            let scale = scene_node.transform.scale();
+            Ok((scale.x, scale.y, scale.z))
+        })?,
+    )?;
+    api.set(
+        "set_scale",
+        scope.create_function(|_, (node, x, y, z): (ScriptNode, f32, f32, f32)| {
+            update_node_transform(&mut engine_state.borrow_mut(), node, |transform| {
+                transform.set_scale(Vec3::new(x, y, z))
+            })
+        })?,
+    )?;
+    api.set(
+        "get_name",
+        scope.create_function(|_, node: ScriptNode| {
+            let engine_state = engine_state.borrow();
+            let scene_node = engine_state
+                .scene
+                .get_node(node.0)
+                .ok_or_else(removed_node_error)?;
+            Ok(scene_node.name.clone())
+        })?,
+    )?;
+    api.set(
+        "find_node",
+        scope.create_function(|_, name: String| {
+            let engine_state = engine_state.borrow();
+            Ok(engine_state
+                .scene
+                .nodes()
+                .find(|node| node.name.as_deref() == Some(&name))
+                .map(|node| ScriptNode(node.id())))
+        })?,
+    )?;
+    api.set(
+        "spawn",
+        scope.create_function(|_, (prefab_name, x, y, z): (String, f32, f32, f32)| {
+            let mut engine_state = engine_state.borrow_mut();
+            let instance = prefabs
+                .instantiate(
+                    &prefab_name,
+                    &mut engine_state.scene,
+                    None,
+                    TransformBuilder::new().position(Vec3::new(x, y, z)).build(),
+                    &PrefabOverrides::default(),
+                )
+                .ok_or_else(|| {
+                    mlua::Error::RuntimeError(format!("there is no prefab named {prefab_name}"))
+                })?;
+            Ok(ScriptNode(instance.root_node_id))
+        })?,
+    )?;
+    api.set(
+        "remove_node",
+        scope.create_function(|_, node: ScriptNode| {
+            let mut engine_state = engine_state.borrow_mut();
+            let scene = &mut engine_state.scene;
+            let removed_node_ids: Vec<_> = scene
+                .nodes()
+                .map(|scene_node| scene_node.id())
+                .filter(|node_id| {
+                    scene
+                        .get_node_ancestry_list(*node_id)
+                        .any(|id| id == node.0)
+                })
+                .collect();
+            for node_id in removed_node_ids {
+                scene.remove_node(node_id);
+            }
+            Ok(())
+        })?,
+    )?;
+    api.set(
+        "play_animation",
+        scope.create_function(|_, (name, is_looping): (String, bool)| {
+            let mut engine_state = engine_state.borrow_mut();
+            let scene = &mut engine_state.scene;
+            for animation in &mut scene.animations {
+                if animation.name.as_deref() == Some(&name) {
+                    animation.state.current_time_seconds = 0.0;
+                    animation.state.is_playing = true;
+                    animation.state.loop_type = if is_looping {
+                        LoopType::Wrap
+                    } else {
+                        LoopType::Once
+                    };
+                }
+            }
+            Ok(())
+        })?,
+    )?;
+    api.set(
+        "stop_animation",
+        scope.create_function(|_, name: String| {
+            let mut engine_state = engine_state.borrow_mut();
+            let scene = &mut engine_state.scene;
+            for animation in &mut scene.animations {
+                if animation.name.as_deref() == Some(&name) {
+                    animation.state.is_playing = false;
+                }
+            }
+            Ok(())
+        })?,
+    )?;
+    api.set(
+        "play_sound",
+        scope.create_function(|_, name: String| {
+            let sound_index = *sounds.get(&name).ok_or_else(|| {
+                mlua::Error::RuntimeError(format!("there is no sound named {name}"))
+            })?;
+            engine_state
+                .borrow()
+                .audio_manager
+                .lock()
+                .unwrap()
+                .play_sound(sound_index);
+            Ok(())
+        })?,
+    )?;
+    api.set(
+        "trigger_cue",
+        scope.create_function(|_, name: String| {
+            Ok(engine_state
+                .borrow()
+                .audio_manager
+                .lock()
+                .unwrap()
+                .trigger_cue(&name, None))
+        })?,
+    )?;
+    api.set(
+        "trigger_cue_at",
+        scope.create_function(|_, (name, x, y, z): (String, f32, f32, f32)| {
+            Ok(engine_state
+                .borrow()
+                .audio_manager
+                .lock()
+                .unwrap()
+                .trigger_cue(&name, Some(Vec3::new(x, y, z))))
+        })?,
+    )?;
+    api.set(
+        "is_key_down",
+        scope.create_function(|_, key_name: String| {
+            Ok(pressed_keys.contains(&key_name.to_lowercase()))
+        })?,
+    )?;
+    api.set(
+        "log",
+        scope.create_function(|_, message: String| {
+            log::info!("[script] {message}");
+            Ok(())
+        })?,
+    )?;
+
+    Ok(api)
+}
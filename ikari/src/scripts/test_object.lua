@@ -0,0 +1,16 @@
+-- makes the test object bob up and down, with the scripting feature enabled
+-- this file can be edited while the game is running
+local script = {}
+
+function script.on_init(self, api)
+    self.time = 0
+    self.start_x, self.start_y, self.start_z = api.get_position(self.node)
+end
+
+function script.on_update(self, api, delta_time_seconds)
+    self.time = self.time + delta_time_seconds
+    local offset = math.sin(self.time * 2.0) * 0.5
+    api.set_position(self.node, self.start_x, self.start_y + offset, self.start_z)
+end
+
+return script
@@ -0,0 +1,378 @@
+use crate::collisions::*;
+use crate::mesh::*;
+use crate::renderer::*;
+use crate::scene::*;
+
+use glam::f32::{Mat4, Vec3};
+
+/// extra empty voxels around the baked geometry so rays can leave the volume cleanly
+const BOUNDS_PADDING_VOXELS: f32 = 2.0;
+const MAX_SHADOW_RAY_STEPS: usize = 64;
+const AO_SAMPLE_COUNT: usize = 5;
+
+/// Coarse unsigned distance field of static geometry, stored as a 3d grid of
+/// distances to the nearest triangle. Meant for large-scale sun shadows and AO
+/// that don't need to resolve small details, see `Renderer::set_sdf_volume`.
+#[derive(Debug, Clone)]
+pub struct SdfVolume {
+    /// world-space position of the center of the first voxel
+    pub origin: Vec3,
+    pub voxel_size: f32,
+    pub dims: (u32, u32, u32),
+    /// x-major, then y, then z
+    pub distances: Vec<f32>,
+}
+
+#[derive(Debug, Default)]
+pub struct SdfBaker {
+    triangles: Vec<[Vec3; 3]>,
+}
+
+impl SdfBaker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    pub fn add_triangles(&mut self, vertices: &[Vertex], indices: &[u32], transform: Mat4) {
+        let positions: Vec<_> = vertices
+            .iter()
+            .map(|vertex| transform.transform_point3(Vec3::from(vertex.position)))
+            .collect();
+        self.triangles
+            .extend(indices.chunks_exact(3).map(|triangle| {
+                [
+                    positions[triangle[0] as usize],
+                    positions[triangle[1] as usize],
+                    positions[triangle[2] as usize],
+                ]
+            }));
+    }
+
+    /// Adds the meshes of all static nodes of a freshly loaded scene, before it's merged
+    /// into the main scene. Skinned nodes are skipped since they move around.
+    pub fn add_scene(&mut self, scene: &Scene, bindable_scene_data: &BindableSceneData) {
+        for node in scene.nodes() {
+            let Some(visual) = node.visual.as_ref() else {
+                continue;
+            };
+            if node.skin_index.is_some() {
+                continue;
+            }
+            let Some(geometry) = bindable_scene_data.bindable_meshes.get(visual.mesh.index())
+            else {
+                continue;
+            };
+            let transform: Mat4 = scene.get_global_transform_for_node(node.id()).into();
+            self.add_triangles(
+                &geometry.vertices,
+                &geometry.indices.to_u32_vec(),
+                transform,
+            );
+        }
+    }
+
+    /// max_resolution is the voxel count along the longest side of the baked bounds
+    #[profiling::function]
+    pub fn bake(&self, max_resolution: u32) -> Option<SdfVolume> {
+        let bounds = Aabb::make_from_points(self.triangles.iter().flatten().copied())?;
+        let max_resolution = max_resolution.max(2);
+
+        let longest_side = bounds.size().max_element().max(0.001);
+        let voxel_size = longest_side / (max_resolution as f32 - 1.0 - 2.0 * BOUNDS_PADDING_VOXELS);
+        let origin = bounds.min - Vec3::splat(BOUNDS_PADDING_VOXELS * voxel_size);
+        let dims = {
+            let counts = ((bounds.size() / voxel_size).ceil()
+                + Vec3::splat(2.0 * BOUNDS_PADDING_VOXELS + 1.0))
+            .min(Vec3::splat(max_resolution as f32));
+            (counts.x as u32, counts.y as u32, counts.z as u32)
+        };
+
+        let voxel_count = (dims.0 * dims.1 * dims.2) as usize;
+        let voxel_index = |x: u32, y: u32, z: u32| (x + dims.0 * (y + dims.1 * z)) as usize;
+        let voxel_center =
+            |x: u32, y: u32, z: u32| origin + Vec3::new(x as f32, y as f32, z as f32) * voxel_size;
+        let to_voxel_coord = |position: Vec3, round_up: bool| {
+            let coord = (position - origin) / voxel_size;
+            let coord = if round_up {
+                coord.ceil()
+            } else {
+                coord.floor()
+            };
+            coord.clamp(
+                Vec3::ZERO,
+                Vec3::new(
+                    dims.0 as f32 - 1.0,
+                    dims.1 as f32 - 1.0,
+                    dims.2 as f32 - 1.0,
+                ),
+            )
+        };
+
+        // seed the voxels around each triangle with their exact closest point,
+        // then propagate the closest points to the rest of the grid
+        let mut closest_points = vec![Vec3::splat(f32::MAX); voxel_count];
+        let mut distances = vec![f32::MAX; voxel_count];
+
+        for triangle in &self.triangles {
+            let triangle_min = triangle[0].min(triangle[1]).min(triangle[2]) - voxel_size;
+            let triangle_max = triangle[0].max(triangle[1]).max(triangle[2]) + voxel_size;
+            let min_coord = to_voxel_coord(triangle_min, false);
+            let max_coord = to_voxel_coord(triangle_max, true);
+
+            for z in min_coord.z as u32..=max_coord.z as u32 {
+                for y in min_coord.y as u32..=max_coord.y as u32 {
+                    for x in min_coord.x as u32..=max_coord.x as u32 {
+                        let center = voxel_center(x, y, z);
+                        let closest_point = closest_point_on_triangle(center, triangle);
+                        let distance = center.distance(closest_point);
+                        let index = voxel_index(x, y, z);
+                        if distance < distances[index] {
+                            distances[index] = distance;
+                            closest_points[index] = closest_point;
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut propagate = |x: u32, y: u32, z: u32, neighbor_offsets: &[(i32, i32, i32)]| {
+            let index = voxel_index(x, y, z);
+            let center = voxel_center(x, y, z);
+            for (offset_x, offset_y, offset_z) in neighbor_offsets {
+                let (neighbor_x, neighbor_y, neighbor_z) = (
+                    x as i32 + offset_x,
+                    y as i32 + offset_y,
+                    z as i32 + offset_z,
+                );
+                if neighbor_x < 0
+                    || neighbor_y < 0
+                    || neighbor_z < 0
+                    || neighbor_x >= dims.0 as i32
+                    || neighbor_y >= dims.1 as i32
+                    || neighbor_z >= dims.2 as i32
+                {
+                    continue;
+                }
+                let neighbor_index =
+                    voxel_index(neighbor_x as u32, neighbor_y as u32, neighbor_z as u32);
+                if distances[neighbor_index] == f32::MAX {
+                    continue;
+                }
+                let candidate = closest_points[neighbor_index];
+                let distance = center.distance(candidate);
+                if distance < distances[index] {
+                    distances[index] = distance;
+                    closest_points[index] = candidate;
+                }
+            }
+        };
+
+        // the 13 neighbors that come before a voxel in scan order, and their mirror images
+        let backward_neighbors: Vec<(i32, i32, i32)> = (-1..=1)
+            .flat_map(|z| (-1..=1).flat_map(move |y| (-1..=1).map(move |x| (x, y, z))))
+            .filter(|&(x, y, z)| (z, y, x) < (0, 0, 0))
+            .collect();
+        let forward_neighbors: Vec<(i32, i32, i32)> = backward_neighbors
+            .iter()
+            .map(|(x, y, z)| (-x, -y, -z))
+            .collect();
+
+        for _ in 0..2 {
+            for z in 0..dims.2 {
+                for y in 0..dims.1 {
+                    for x in 0..dims.0 {
+                        propagate(x, y, z, &backward_neighbors);
+                    }
+                }
+            }
+            for z in (0..dims.2).rev() {
+                for y in (0..dims.1).rev() {
+                    for x in (0..dims.0).rev() {
+                        propagate(x, y, z, &forward_neighbors);
+                    }
+                }
+            }
+        }
+
+        Some(SdfVolume {
+            origin,
+            voxel_size,
+            dims,
+            distances,
+        })
+    }
+}
+
+impl SdfVolume {
+    pub fn bounds(&self) -> Aabb {
+        Aabb {
+            min: self.origin,
+            max: self.origin
+                + Vec3::new(
+                    (self.dims.0 - 1) as f32,
+                    (self.dims.1 - 1) as f32,
+                    (self.dims.2 - 1) as f32,
+                ) * self.voxel_size,
+        }
+    }
+
+    /// trilinearly filtered distance, None outside of the volume
+    pub fn sample(&self, position: Vec3) -> Option<f32> {
+        let coord = (position - self.origin) / self.voxel_size;
+        let max_coord = Vec3::new(
+            (self.dims.0 - 1) as f32,
+            (self.dims.1 - 1) as f32,
+            (self.dims.2 - 1) as f32,
+        );
+        if coord.cmplt(Vec3::ZERO).any() || coord.cmpgt(max_coord).any() {
+            return None;
+        }
+
+        let base = coord.floor().min(max_coord - Vec3::ONE).max(Vec3::ZERO);
+        let t = coord - base;
+        let (base_x, base_y, base_z) = (base.x as u32, base.y as u32, base.z as u32);
+        let get = |x: u32, y: u32, z: u32| {
+            let x = (base_x + x).min(self.dims.0 - 1);
+            let y = (base_y + y).min(self.dims.1 - 1);
+            let z = (base_z + z).min(self.dims.2 - 1);
+            self.distances[(x + self.dims.0 * (y + self.dims.1 * z)) as usize]
+        };
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+
+        let x00 = lerp(get(0, 0, 0), get(1, 0, 0), t.x);
+        let x10 = lerp(get(0, 1, 0), get(1, 1, 0), t.x);
+        let x01 = lerp(get(0, 0, 1), get(1, 0, 1), t.x);
+        let x11 = lerp(get(0, 1, 1), get(1, 1, 1), t.x);
+        Some(lerp(lerp(x00, x10, t.y), lerp(x01, x11, t.y), t.z))
+    }
+
+    /// Sphere-traces from origin towards the light. Returns 1.0 if fully lit and
+    /// 0.0 if fully occluded, higher softness values give harder penumbras.
+    /// This is a cpu reference for the shader implementation in textured_mesh.wgsl
+    pub fn soft_shadow(&self, origin: Vec3, to_light_direction: Vec3, softness: f32) -> f32 {
+        // the field is unsigned, so treat a shell around the surfaces as solid
+        let surface_thickness = 0.5 * self.voxel_size;
+        let max_distance = self.bounds().size().length();
+
+        let mut visibility: f32 = 1.0;
+        let mut t = self.voxel_size;
+        for _ in 0..MAX_SHADOW_RAY_STEPS {
+            let Some(distance) = self.sample(origin + to_light_direction * t) else {
+                break;
+            };
+            let distance = distance - surface_thickness;
+            if distance <= 0.0 {
+                return 0.0;
+            }
+            visibility = visibility.min(softness * distance / t);
+            t += distance.max(0.5 * self.voxel_size);
+            if t > max_distance {
+                break;
+            }
+        }
+        visibility.clamp(0.0, 1.0)
+    }
+
+    /// 1.0 means unoccluded
+    pub fn ambient_occlusion(&self, position: Vec3, normal: Vec3) -> f32 {
+        let mut occlusion = 0.0;
+        let mut weight = 1.0;
+        for i in 1..=AO_SAMPLE_COUNT {
+            let step = i as f32 * self.voxel_size;
+            let Some(distance) = self.sample(position + normal * step) else {
+                break;
+            };
+            occlusion += (step - distance).max(0.0) * weight;
+            weight *= 0.5;
+        }
+        (1.0 - occlusion / self.voxel_size).clamp(0.0, 1.0)
+    }
+}
+
+/// From Real-Time Collision Detection by Christer Ericson, section 5.1.5
+fn closest_point_on_triangle(p: Vec3, [a, b, c]: &[Vec3; 3]) -> Vec3 {
+    let (a, b, c) = (*a, *b, *c);
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+
+    let bp = p - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = p - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let denom = 1.0 / (va + vb + vc);
+    let v = vb * denom;
+    let w = vc * denom;
+    a + ab * v + ac * w
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quad_vertices(center: Vec3, half_size: f32) -> Vec<Vertex> {
+        [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+            .iter()
+            .map(|(x, z)| Vertex {
+                position: (center + Vec3::new(x * half_size, 0.0, z * half_size)).to_array(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn floating_quad_shadows_the_floor_below_it() {
+        let mut baker = SdfBaker::new();
+        let indices = [0, 1, 2, 0, 2, 3];
+        baker.add_triangles(&quad_vertices(Vec3::ZERO, 10.0), &indices, Mat4::IDENTITY);
+        baker.add_triangles(
+            &quad_vertices(Vec3::new(0.0, 3.0, 0.0), 2.0),
+            &indices,
+            Mat4::IDENTITY,
+        );
+
+        let volume = baker.bake(48).unwrap();
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let floor_offset = up * volume.voxel_size;
+
+        let under_quad = volume.soft_shadow(Vec3::ZERO + floor_offset, up, 8.0);
+        let away_from_quad = volume.soft_shadow(Vec3::new(8.0, 0.0, 8.0) + floor_offset, up, 8.0);
+
+        assert_eq!(under_quad, 0.0);
+        assert!(away_from_quad > 0.9, "{away_from_quad}");
+    }
+}
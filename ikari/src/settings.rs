@@ -0,0 +1,600 @@
+use crate::audio::AudioManager;
+use crate::audio_mixer::{MASTER_BUS, MUSIC_BUS, SFX_BUS, VOICE_BUS};
+use crate::input::InputBindings;
+use crate::particles::particle_limit;
+use crate::renderer::{BloomType, RendererData};
+use crate::sampler_cache::{TextureFiltering, TextureQuality};
+
+use std::collections::BTreeMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+/// Bumped whenever a field is renamed or changes meaning, along with a step in migrate
+pub const SETTINGS_VERSION: i64 = 1;
+
+/// Sets all the quality options of GraphicsSettings at once, see GraphicsSettings::set_preset
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GraphicsQualityPreset {
+    Low,
+    Medium,
+    High,
+    Ultra,
+    /// the options were changed one by one
+    Custom,
+}
+
+impl GraphicsQualityPreset {
+    pub const ALL: [GraphicsQualityPreset; 5] = [
+        GraphicsQualityPreset::Low,
+        GraphicsQualityPreset::Medium,
+        GraphicsQualityPreset::High,
+        GraphicsQualityPreset::Ultra,
+        GraphicsQualityPreset::Custom,
+    ];
+}
+
+impl std::fmt::Display for GraphicsQualityPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                GraphicsQualityPreset::Low => "Low",
+                GraphicsQualityPreset::Medium => "Medium",
+                GraphicsQualityPreset::High => "High",
+                GraphicsQualityPreset::Ultra => "Ultra",
+                GraphicsQualityPreset::Custom => "Custom",
+            }
+        )
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShadowQuality {
+    Off,
+    /// hard shadows
+    Low,
+    Medium,
+    High,
+}
+
+impl ShadowQuality {
+    /// the soft shadow sampling grid, None for hard shadows
+    pub fn soft_shadow_grid_dims(self) -> Option<u32> {
+        match self {
+            ShadowQuality::Off | ShadowQuality::Low => None,
+            ShadowQuality::Medium => Some(4),
+            ShadowQuality::High => Some(8),
+        }
+    }
+
+    /// taps per lookup when the shadows are filtered with PCF or PCSS
+    fn pcf_sample_count(self) -> u32 {
+        match self {
+            ShadowQuality::Off | ShadowQuality::Low | ShadowQuality::Medium => 8,
+            ShadowQuality::High => 16,
+        }
+    }
+}
+
+/// The renderer has no MSAA, its anti-aliasing is temporal
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AntiAliasing {
+    Off,
+    /// see TemporalAntiAliasing
+    Taa,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BloomQuality {
+    Off,
+    Low,
+    High,
+}
+
+impl BloomQuality {
+    pub fn bloom_type(self) -> BloomType {
+        match self {
+            BloomQuality::Off => BloomType::Disabled,
+            BloomQuality::Low => BloomType::Old,
+            BloomQuality::High => BloomType::New,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GraphicsSettings {
+    /// set by set_preset and from_renderer_data. the options below are saved as well so that a
+    /// Custom preset keeps its overrides
+    pub preset: GraphicsQualityPreset,
+    pub render_scale: f32,
+    pub vsync: bool,
+    /// None for no limit, see FrameLimiter
+    pub max_fps: Option<f32>,
+    pub shadow_quality: ShadowQuality,
+    /// of the directional light shadow maps, the point light ones are half of it
+    pub shadow_map_resolution: u32,
+    pub anti_aliasing: AntiAliasing,
+    pub exposure: f32,
+    pub bloom_quality: BloomQuality,
+    pub texture_filtering: TextureFiltering,
+    /// see TextureQuality::resolution_bias
+    pub texture_resolution_bias: u32,
+    /// see RendererData::parallax_max_step_count
+    pub parallax_max_step_count: u32,
+    /// the SDF ambient occlusion, only visible once a volume was set with
+    /// Renderer::set_sdf_volume
+    pub ambient_occlusion: bool,
+    /// see ParticleSystem::set_particle_limit_scale
+    pub particle_limit_scale: f32,
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> Self {
+        Self {
+            preset: GraphicsQualityPreset::High,
+            render_scale: 1.0,
+            vsync: true,
+            max_fps: None,
+            shadow_quality: ShadowQuality::Medium,
+            shadow_map_resolution: 2048,
+            anti_aliasing: AntiAliasing::Taa,
+            exposure: 1.0,
+            bloom_quality: BloomQuality::High,
+            texture_filtering: TextureFiltering::Anisotropic8x,
+            texture_resolution_bias: 0,
+            parallax_max_step_count: 32,
+            ambient_occlusion: true,
+            particle_limit_scale: 1.0,
+        }
+    }
+}
+
+impl GraphicsSettings {
+    /// The settings as they currently are in the renderer, e.g. after the player changed them.
+    /// The filtering requested by the assets is saved as the default filtering
+    pub fn from_renderer_data(
+        renderer_data: &RendererData,
+        texture_quality: TextureQuality,
+        vsync: bool,
+        max_fps: Option<f32>,
+        particle_limit_scale: f32,
+    ) -> Self {
+        let shadow_quality = if !renderer_data.enable_shadows {
+            ShadowQuality::Off
+        } else if !renderer_data.enable_soft_shadows {
+            ShadowQuality::Low
+        } else if renderer_data.soft_shadow_grid_dims <= 4 {
+            ShadowQuality::Medium
+        } else {
+            ShadowQuality::High
+        };
+        let bloom_quality = match renderer_data.bloom_type {
+            BloomType::Disabled => BloomQuality::Off,
+            BloomType::Old => BloomQuality::Low,
+            BloomType::New => BloomQuality::High,
+        };
+        let mut settings = Self {
+            preset: GraphicsQualityPreset::Custom,
+            render_scale: renderer_data.render_scale,
+            vsync,
+            max_fps,
+            shadow_quality,
+            shadow_map_resolution: renderer_data.directional_shadow_map_resolution,
+            anti_aliasing: if renderer_data.taa.is_enabled() {
+                AntiAliasing::Taa
+            } else {
+                AntiAliasing::Off
+            },
+            exposure: renderer_data.tone_mapping_exposure,
+            bloom_quality,
+            texture_filtering: texture_quality
+                .filtering
+                .unwrap_or(Self::default().texture_filtering),
+            texture_resolution_bias: texture_quality.resolution_bias,
+            parallax_max_step_count: renderer_data.parallax_max_step_count,
+            ambient_occlusion: renderer_data.sdf_ao_strength > 0.0,
+            particle_limit_scale,
+        };
+        settings.preset = settings.matching_preset();
+        settings
+    }
+
+    /// Sets the quality options to the ones of the preset, Custom keeps them as they are
+    pub fn set_preset(&mut self, preset: GraphicsQualityPreset) {
+        *self = match preset {
+            GraphicsQualityPreset::Low => Self {
+                render_scale: 0.5,
+                shadow_quality: ShadowQuality::Low,
+                shadow_map_resolution: 1024,
+                anti_aliasing: AntiAliasing::Off,
+                bloom_quality: BloomQuality::Off,
+                texture_filtering: TextureFiltering::Trilinear,
+                texture_resolution_bias: 1,
+                parallax_max_step_count: 0,
+                ambient_occlusion: false,
+                particle_limit_scale: 0.25,
+                ..*self
+            },
+            GraphicsQualityPreset::Medium => Self {
+                render_scale: 0.75,
+                shadow_quality: ShadowQuality::Medium,
+                shadow_map_resolution: 2048,
+                anti_aliasing: AntiAliasing::Taa,
+                bloom_quality: BloomQuality::Low,
+                texture_filtering: TextureFiltering::Anisotropic4x,
+                texture_resolution_bias: 0,
+                parallax_max_step_count: 8,
+                ambient_occlusion: false,
+                particle_limit_scale: 0.5,
+                ..*self
+            },
+            GraphicsQualityPreset::High => Self {
+                render_scale: 1.0,
+                shadow_quality: ShadowQuality::Medium,
+                shadow_map_resolution: 2048,
+                anti_aliasing: AntiAliasing::Taa,
+                bloom_quality: BloomQuality::High,
+                texture_filtering: TextureFiltering::Anisotropic8x,
+                texture_resolution_bias: 0,
+                parallax_max_step_count: 32,
+                ambient_occlusion: true,
+                particle_limit_scale: 1.0,
+                ..*self
+            },
+            GraphicsQualityPreset::Ultra => Self {
+                render_scale: 1.0,
+                shadow_quality: ShadowQuality::High,
+                shadow_map_resolution: 4096,
+                anti_aliasing: AntiAliasing::Taa,
+                bloom_quality: BloomQuality::High,
+                texture_filtering: TextureFiltering::Anisotropic16x,
+                texture_resolution_bias: 0,
+                parallax_max_step_count: 64,
+                ambient_occlusion: true,
+                particle_limit_scale: 1.0,
+                ..*self
+            },
+            GraphicsQualityPreset::Custom => *self,
+        };
+        self.preset = preset;
+    }
+
+    /// The first preset with the same quality options, or Custom
+    pub fn matching_preset(&self) -> GraphicsQualityPreset {
+        GraphicsQualityPreset::ALL
+            .into_iter()
+            .find(|preset| {
+                let mut with_preset = *self;
+                with_preset.set_preset(*preset);
+                with_preset.preset = self.preset;
+                with_preset == *self
+            })
+            .unwrap_or(GraphicsQualityPreset::Custom)
+    }
+
+    /// For Renderer::set_texture_quality
+    pub fn texture_quality(&self) -> TextureQuality {
+        TextureQuality {
+            filtering: Some(self.texture_filtering),
+            resolution_bias: self.texture_resolution_bias,
+        }
+    }
+
+    /// How many particles an emitter with this max_particles may have alive, at least one
+    pub fn particle_limit(&self, max_particles: u32) -> u32 {
+        particle_limit(max_particles, self.particle_limit_scale)
+    }
+
+    /// Renderer::resize_surface must be called afterwards for the render scale to take effect,
+    /// the vsync is set with Renderer::set_vsync since it needs the surface and the texture
+    /// quality with Renderer::set_texture_quality. max_fps goes to EngineState::frame_limiter
+    /// and the particle limit to ParticleSystem::set_particle_limit_scale
+    pub fn apply(&self, renderer_data: &mut RendererData) {
+        renderer_data.render_scale = self.render_scale;
+        renderer_data.tone_mapping_exposure = self.exposure;
+        renderer_data.enable_shadows = self.shadow_quality != ShadowQuality::Off;
+        let soft_shadow_grid_dims = self.shadow_quality.soft_shadow_grid_dims();
+        renderer_data.enable_soft_shadows = soft_shadow_grid_dims.is_some();
+        if let Some(soft_shadow_grid_dims) = soft_shadow_grid_dims {
+            renderer_data.soft_shadow_grid_dims = soft_shadow_grid_dims;
+        }
+        renderer_data.shadow_filter.pcf_sample_count = self.shadow_quality.pcf_sample_count();
+        renderer_data.directional_shadow_map_resolution = self.shadow_map_resolution;
+        renderer_data.point_shadow_map_resolution = self.shadow_map_resolution / 2;
+        renderer_data
+            .taa
+            .set_enabled(self.anti_aliasing == AntiAliasing::Taa);
+        renderer_data.bloom_type = self.bloom_quality.bloom_type();
+        renderer_data.parallax_max_step_count = self.parallax_max_step_count;
+        if !self.ambient_occlusion {
+            renderer_data.sdf_ao_strength = 0.0;
+        } else if renderer_data.sdf_ao_strength <= 0.0 {
+            renderer_data.sdf_ao_strength = 1.0;
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioSettings {
+    /// by bus name, in 0..1
+    pub bus_volumes: BTreeMap<String, f32>,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            bus_volumes: [MASTER_BUS, MUSIC_BUS, SFX_BUS, VOICE_BUS]
+                .into_iter()
+                .map(|bus| (bus.to_string(), 1.0))
+                .collect(),
+        }
+    }
+}
+
+impl AudioSettings {
+    pub fn from_audio_manager(audio_manager: &AudioManager) -> Self {
+        Self {
+            bus_volumes: audio_manager
+                .bus_names()
+                .filter_map(|bus| {
+                    let volume = audio_manager.bus_settings(bus)?.volume;
+                    Some((bus.to_string(), volume))
+                })
+                .collect(),
+        }
+    }
+
+    pub fn apply(&self, audio_manager: &mut AudioManager) {
+        for (bus, volume) in &self.bus_volumes {
+            if audio_manager.bus_settings(bus).is_none() {
+                log::warn!("There's no audio bus named {bus}, its volume setting is ignored");
+                continue;
+            }
+            audio_manager.set_bus_volume(bus, *volume);
+        }
+    }
+}
+
+/// The player's settings, saved as a TOML file in the platform's config directory or in the
+/// browser's local storage on the web
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub version: i64,
+    pub graphics: GraphicsSettings,
+    pub audio: AudioSettings,
+    /// None to use the game's default bindings
+    pub input: Option<InputBindings>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            graphics: Default::default(),
+            audio: Default::default(),
+            input: None,
+        }
+    }
+}
+
+impl Settings {
+    /// e.g. ~/.config/<app_name>/settings.toml on linux
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn default_path(app_name: &str) -> Option<PathBuf> {
+        dirs::config_dir().map(|config_dir| config_dir.join(app_name).join("settings.toml"))
+    }
+
+    /// Migrates the files written by older versions and fixes the invalid values,
+    /// logging a warning for each of them
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut table: toml::Table = text.parse()?;
+        let version = table
+            .get("version")
+            .and_then(toml::Value::as_integer)
+            .unwrap_or(SETTINGS_VERSION);
+        migrate(&mut table, version)?;
+        let mut settings: Self = table.try_into()?;
+        settings.version = SETTINGS_VERSION;
+        settings.validate();
+        Ok(settings)
+    }
+
+    pub fn to_toml_string(&self) -> Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// The defaults if the file doesn't exist or can't be read
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_or_default(path: &Path) -> Self {
+        if !path.exists() {
+            return Self::default();
+        }
+        Self::load(path).unwrap_or_else(|err| {
+            log::error!(
+                "Error loading the settings from {}, using the defaults: {err:?}",
+                path.display()
+            );
+            Self::default()
+        })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, self.to_toml_string()?)?;
+        Ok(())
+    }
+
+    /// The browser's local storage takes the place of the config dir on the web.
+    /// The defaults if there's nothing under this key or it can't be read
+    #[cfg(target_arch = "wasm32")]
+    pub fn load_from_local_storage_or_default(key: &str) -> Self {
+        let text = match local_storage().and_then(|storage| {
+            storage
+                .get_item(key)
+                .map_err(|err| anyhow::anyhow!("{err:?}"))
+        }) {
+            Ok(Some(text)) => text,
+            Ok(None) => return Self::default(),
+            Err(err) => {
+                log::error!("Error reading the settings from local storage: {err:?}");
+                return Self::default();
+            }
+        };
+        Self::parse(&text).unwrap_or_else(|err| {
+            log::error!(
+                "Error loading the settings from local storage, using the defaults: {err:?}"
+            );
+            Self::default()
+        })
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn save_to_local_storage(&self, key: &str) -> Result<()> {
+        local_storage()?
+            .set_item(key, &self.to_toml_string()?)
+            .map_err(|err| anyhow::anyhow!("{err:?}"))
+    }
+
+    fn validate(&mut self) {
+        clamp_setting(
+            "graphics.render_scale",
+            &mut self.graphics.render_scale,
+            0.1,
+            4.0,
+        );
+        clamp_setting("graphics.exposure", &mut self.graphics.exposure, 0.0, 20.0);
+        clamp_setting(
+            "graphics.particle_limit_scale",
+            &mut self.graphics.particle_limit_scale,
+            0.0,
+            1.0,
+        );
+        let shadow_map_resolution = self.graphics.shadow_map_resolution;
+        if !(256..=8192).contains(&shadow_map_resolution)
+            || !shadow_map_resolution.is_power_of_two()
+        {
+            let fixed = GraphicsSettings::default().shadow_map_resolution;
+            log::warn!(
+                "Setting graphics.shadow_map_resolution = {} isn't a power of two in 256..=8192, using {}",
+                shadow_map_resolution,
+                fixed
+            );
+            self.graphics.shadow_map_resolution = fixed;
+        }
+        if let Some(max_fps) = &mut self.graphics.max_fps {
+            clamp_setting("graphics.max_fps", max_fps, 10.0, 1000.0);
+        }
+        for (bus, volume) in &mut self.audio.bus_volumes {
+            clamp_setting(&format!("audio.bus_volumes.{bus}"), volume, 0.0, 1.0);
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Result<web_sys::Storage> {
+    web_sys::window()
+        .and_then(|window| window.local_storage().ok().flatten())
+        .ok_or_else(|| anyhow::anyhow!("Local storage isn't available"))
+}
+
+fn clamp_setting(name: &str, value: &mut f32, min: f32, max: f32) {
+    if value.is_nan() || *value < min || *value > max {
+        let fixed = if value.is_nan() {
+            min
+        } else {
+            value.clamp(min, max)
+        };
+        log::warn!("Setting {name} = {value} is out of range, using {fixed}");
+        *value = fixed;
+    }
+}
+
+/// Upgrades the raw table of a file written with an older SETTINGS_VERSION, one version at a time
+fn migrate(_table: &mut toml::Table, version: i64) -> Result<()> {
+    if version > SETTINGS_VERSION {
+        bail!("The settings were saved by a newer version ({version}) of the game");
+    }
+    // no fields have changed since the first version
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_state_stack::InputContext;
+    use crate::input::{BindingSet, InputBinding};
+
+    #[test]
+    fn settings_round_trip_and_get_fixed() {
+        let settings = Settings {
+            graphics: GraphicsSettings {
+                render_scale: 0.75,
+                max_fps: Some(60.0),
+                shadow_quality: ShadowQuality::High,
+                ..Default::default()
+            },
+            input: Some(InputBindings::default().context(
+                InputContext::Gameplay,
+                BindingSet::default().action("jump", vec![InputBinding::key("Space")]),
+            )),
+            ..Default::default()
+        };
+        let parsed = Settings::parse(&settings.to_toml_string().unwrap()).unwrap();
+        assert_eq!(parsed, settings);
+
+        let parsed = Settings::parse(
+            "[graphics]\nrender_scale = 50.0\n\n[audio.bus_volumes]\nmusic = -1.0\n",
+        )
+        .unwrap();
+        assert_eq!(parsed.graphics.render_scale, 4.0);
+        assert_eq!(parsed.graphics.vsync, GraphicsSettings::default().vsync);
+        assert_eq!(
+            Settings::parse("[graphics]\nshadow_map_resolution = 1000\n")
+                .unwrap()
+                .graphics
+                .shadow_map_resolution,
+            GraphicsSettings::default().shadow_map_resolution
+        );
+        assert_eq!(parsed.audio.bus_volumes[MUSIC_BUS], 0.0);
+
+        assert!(Settings::parse("version = 1000").is_err());
+    }
+
+    #[test]
+    fn presets_set_and_match_the_quality_options() {
+        let mut graphics = GraphicsSettings::default();
+        assert_eq!(graphics.matching_preset(), GraphicsQualityPreset::High);
+
+        graphics.set_preset(GraphicsQualityPreset::Low);
+        assert_eq!(graphics.render_scale, 0.5);
+        assert_eq!(graphics.shadow_map_resolution, 1024);
+        assert_eq!(graphics.anti_aliasing, AntiAliasing::Off);
+        assert_eq!(graphics.particle_limit(256), 64);
+        assert_eq!(graphics.matching_preset(), GraphicsQualityPreset::Low);
+
+        graphics.parallax_max_step_count = 16;
+        assert_eq!(graphics.matching_preset(), GraphicsQualityPreset::Custom);
+        graphics.preset = GraphicsQualityPreset::Custom;
+        let settings = Settings {
+            graphics,
+            ..Default::default()
+        };
+        let parsed = Settings::parse(&settings.to_toml_string().unwrap()).unwrap();
+        assert_eq!(parsed.graphics, graphics);
+    }
+}
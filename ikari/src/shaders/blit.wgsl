@@ -29,6 +29,20 @@ var<uniform> NEW_BLOOM_UPSCALE_CONFIG: NewBloomUpscaleConfig;
 @group(1) @binding(0)
 var<uniform> TONE_MAPPING_CONFIG: ToneMappingConfigUniform;
 
+// globals that change every frame, shared by all the pipelines
+struct FrameConstants {
+    time_seconds: f32,
+    delta_time_seconds: f32,
+    frame_index: u32,
+    // width, height, 1 / width, 1 / height of the render targets, render_scale included
+    render_resolution: vec4<f32>,
+    // xy: subpixel offset of the main camera's projection in pixels, zw: the same in clip space
+    jitter: vec4<f32>,
+}
+
+@group(2) @binding(0)
+var<uniform> FRAME: FrameConstants;
+
 struct VertexOutput {
     @builtin(position) position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
@@ -106,6 +120,16 @@ fn tone_mapping_fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     return vec4<f32>(1.0 - exp(-final_color_hdr * exposure), 1.0);
 }
 
+// undoes tone_mapping_fs_main, the bloom factor is 0 when this runs so the result is the
+// shaded color like it was before the tone mapping
+@fragment
+fn inverse_tone_mapping_fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let exposure = max(TONE_MAPPING_CONFIG.exposure_bloom_factor.x, 0.0001);
+    let tone_mapped_color = textureSample(texture_1, sampler_1, in.tex_coords).rgb;
+    let exposed_color = -log(max(vec3<f32>(1.0) - tone_mapped_color, vec3<f32>(0.0001)));
+    return vec4<f32>(exposed_color / exposure, 1.0);
+}
+
 @fragment
 fn bloom_threshold_fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let threshold = BLOOM_CONFIG.threshold;
@@ -0,0 +1,82 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+struct ColorGradingConfig {
+    // xyz is the input range of each lut
+    domain_min: array<vec4<f32>, 2>,
+    domain_max: array<vec4<f32>, 2>,
+    // x: how much of the second lut is used
+    blend_factor: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var input_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var input_sampler: sampler;
+
+@group(1) @binding(0)
+var lut_0: texture_3d<f32>;
+@group(1) @binding(1)
+var lut_1: texture_3d<f32>;
+@group(1) @binding(2)
+var lut_sampler: sampler;
+@group(1) @binding(3)
+var<uniform> CONFIG: ColorGradingConfig;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let tc = vec2<f32>(f32(i32(vertex_index) / 2) * 2.0, f32(i32(vertex_index) & 1) * 2.0);
+    out.position = vec4<f32>(tc.x * 2.0 - 1.0, 1.0 - tc.y * 2.0, 0.0, 1.0);
+    out.tex_coords = tc;
+    return out;
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let is_low = color <= vec3<f32>(0.0031308);
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, is_low);
+}
+
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    let is_low = color <= vec3<f32>(0.04045);
+    let low = color / 12.92;
+    let high = pow((color + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, is_low);
+}
+
+// samples at the texel centers so that the ends of the domain land exactly on the first and
+// last entries of the table
+fn lut_coords(color: vec3<f32>, size: f32, domain_min: vec3<f32>, domain_max: vec3<f32>) -> vec3<f32> {
+    let normalized = clamp((color - domain_min) / (domain_max - domain_min), vec3<f32>(0.0), vec3<f32>(1.0));
+    return normalized * ((size - 1.0) / size) + 0.5 / size;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(input_texture, input_sampler, in.tex_coords);
+
+    // luts are authored on display-referred colors
+    let encoded = linear_to_srgb(clamp(color.rgb, vec3<f32>(0.0), vec3<f32>(1.0)));
+
+    let lut_0_coords = lut_coords(
+        encoded,
+        f32(textureDimensions(lut_0).x),
+        CONFIG.domain_min[0].xyz,
+        CONFIG.domain_max[0].xyz
+    );
+    let lut_1_coords = lut_coords(
+        encoded,
+        f32(textureDimensions(lut_1).x),
+        CONFIG.domain_min[1].xyz,
+        CONFIG.domain_max[1].xyz
+    );
+    let graded_0 = textureSampleLevel(lut_0, lut_sampler, lut_0_coords, 0.0).rgb;
+    let graded_1 = textureSampleLevel(lut_1, lut_sampler, lut_1_coords, 0.0).rgb;
+    let graded = mix(graded_0, graded_1, CONFIG.blend_factor.x);
+
+    return vec4<f32>(srgb_to_linear(clamp(graded, vec3<f32>(0.0), vec3<f32>(1.0))), color.a);
+}
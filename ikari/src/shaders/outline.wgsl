@@ -0,0 +1,61 @@
+const MAX_OUTLINE_WIDTH = 16;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+struct OutlineConfig {
+    // x: width in pixels
+    params: vec4<f32>,
+}
+
+// the silhouettes of the outlined meshes in their outline color, 0 alpha where there's none
+@group(0) @binding(0)
+var mask_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> CONFIG: OutlineConfig;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let tc = vec2<f32>(f32(i32(vertex_index) / 2) * 2.0, f32(i32(vertex_index) & 1) * 2.0);
+    out.position = vec4<f32>(tc.x * 2.0 - 1.0, 1.0 - tc.y * 2.0, 0.0, 1.0);
+    out.tex_coords = tc;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.position.xy);
+    let max_pixel = vec2<i32>(textureDimensions(mask_texture)) - vec2<i32>(1);
+    if textureLoad(mask_texture, pixel, 0).a > 0.0 {
+        discard;
+    }
+
+    let width = clamp(CONFIG.params.x, 0.0, f32(MAX_OUTLINE_WIDTH));
+    let radius = i32(ceil(width));
+    var closest_distance = width + 1.0;
+    var color = vec4<f32>(0.0);
+    for (var y = -radius; y <= radius; y++) {
+        for (var x = -radius; x <= radius; x++) {
+            let distance = length(vec2<f32>(f32(x), f32(y)));
+            if distance >= closest_distance {
+                continue;
+            }
+            let neighbor = clamp(pixel + vec2<i32>(x, y), vec2<i32>(0), max_pixel);
+            let mask = textureLoad(mask_texture, neighbor, 0);
+            if mask.a > 0.0 {
+                closest_distance = distance;
+                color = mask;
+            }
+        }
+    }
+    if color.a == 0.0 {
+        discard;
+    }
+
+    // antialiases the outer edge
+    let coverage = clamp(width + 0.5 - closest_distance, 0.0, 1.0);
+    return vec4<f32>(color.rgb, color.a * coverage);
+}
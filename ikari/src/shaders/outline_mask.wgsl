@@ -0,0 +1,48 @@
+struct Instance {
+    model_view_proj_0: vec4<f32>,
+    model_view_proj_1: vec4<f32>,
+    model_view_proj_2: vec4<f32>,
+    model_view_proj_3: vec4<f32>,
+    color: vec4<f32>,
+}
+
+struct InstancesUniform {
+    value: array<Instance>,
+}
+
+@group(0) @binding(0)
+var<storage, read> instances_uniform: InstancesUniform;
+
+struct VertexInput {
+    @location(0) object_position: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(
+    vshader_input: VertexInput,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    let instance = instances_uniform.value[instance_index];
+    let model_view_proj = mat4x4<f32>(
+        instance.model_view_proj_0,
+        instance.model_view_proj_1,
+        instance.model_view_proj_2,
+        instance.model_view_proj_3,
+    );
+
+    var out: VertexOutput;
+    out.clip_position = model_view_proj * vec4<f32>(vshader_input.object_position, 1.0);
+    out.color = instance.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // the alpha marks the covered pixels, the outline's own opacity goes in the composite
+    return vec4<f32>(in.color.rgb, max(in.color.a, 0.001));
+}
@@ -0,0 +1,369 @@
+const pi: f32 = 3.141592653589793;
+const epsilon: f32 = 0.00001;
+// offset along the normal of the rays leaving a surface so they don't hit it again
+const RAY_BIAS: f32 = 0.001;
+const NO_HIT: u32 = 0xffffffffu;
+const MAX_DISTANCE: f32 = 1.0e30;
+const BVH_STACK_SIZE: u32 = 32u;
+const MAX_DIRECTIONAL_LIGHTS: u32 = 4u;
+const MAX_POINT_LIGHTS: u32 = 16u;
+
+struct Light {
+    // towards the light for directional lights
+    direction_or_position: vec4<f32>,
+    radiance: vec4<f32>,
+}
+
+struct PathTracerUniform {
+    inv_view_proj: mat4x4<f32>,
+    resolution: vec2<u32>,
+    sample_index: u32,
+    max_bounces: u32,
+    // directional, point, padding
+    light_counts: vec4<u32>,
+    // skybox weights, environment intensity, padding
+    environment: vec4<f32>,
+    directional_lights: array<Light, MAX_DIRECTIONAL_LIGHTS>,
+    point_lights: array<Light, MAX_POINT_LIGHTS>,
+}
+
+struct Triangle {
+    // the material index is in p0.w
+    p0: vec4<f32>,
+    p1: vec4<f32>,
+    p2: vec4<f32>,
+    n0: vec4<f32>,
+    n1: vec4<f32>,
+    n2: vec4<f32>,
+}
+
+struct BvhNode {
+    aabb_min: vec3<f32>,
+    // first triangle for leaves, left child for branches
+    left_or_first: u32,
+    aabb_max: vec3<f32>,
+    // 0 for branches
+    triangle_count: u32,
+}
+
+struct Material {
+    base_color: vec4<f32>,
+    emissive: vec4<f32>,
+    metallic_roughness: vec4<f32>,
+}
+
+struct Hit {
+    distance: f32,
+    triangle_index: u32,
+    barycentric: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> params: PathTracerUniform;
+@group(0) @binding(1)
+var<storage, read> triangles: array<Triangle>;
+@group(0) @binding(2)
+var<storage, read> bvh_nodes: array<BvhNode>;
+@group(0) @binding(3)
+var<storage, read> materials: array<Material>;
+
+@group(1) @binding(0)
+var<storage, read_write> accumulation: array<vec4<f32>>;
+@group(1) @binding(1)
+var output_texture: texture_storage_2d<rgba16float, write>;
+@group(1) @binding(2)
+var skybox_texture: texture_cube<f32>;
+@group(1) @binding(3)
+var skybox_texture_2: texture_cube<f32>;
+@group(1) @binding(4)
+var skybox_sampler: sampler;
+
+var<private> rng_state: u32;
+
+fn pcg_hash(input: u32) -> u32 {
+    let state = input * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn random() -> f32 {
+    rng_state = pcg_hash(rng_state);
+    return f32(rng_state) / 4294967295.0;
+}
+
+fn intersect_aabb(
+    origin: vec3<f32>,
+    inv_direction: vec3<f32>,
+    aabb_min: vec3<f32>,
+    aabb_max: vec3<f32>,
+    max_distance: f32,
+) -> bool {
+    let t0 = (aabb_min - origin) * inv_direction;
+    let t1 = (aabb_max - origin) * inv_direction;
+    let t_min = min(t0, t1);
+    let t_max = max(t0, t1);
+    let enter = max(max(t_min.x, t_min.y), t_min.z);
+    let exit = min(min(t_max.x, t_max.y), t_max.z);
+    return exit >= max(enter, 0.0) && enter < max_distance;
+}
+
+// Möller–Trumbore, returns the distance and the barycentric coordinates of p1 and p2.
+// The distance is negative if the triangle is missed
+fn intersect_triangle(origin: vec3<f32>, direction: vec3<f32>, triangle: Triangle) -> vec3<f32> {
+    let miss = vec3<f32>(-1.0, 0.0, 0.0);
+    let ab = triangle.p1.xyz - triangle.p0.xyz;
+    let ac = triangle.p2.xyz - triangle.p0.xyz;
+    let p = cross(direction, ac);
+    let determinant = dot(ab, p);
+    if abs(determinant) < 1.0e-12 {
+        return miss;
+    }
+    let inverse_determinant = 1.0 / determinant;
+    let to_origin = origin - triangle.p0.xyz;
+    let u = dot(to_origin, p) * inverse_determinant;
+    if u < 0.0 || u > 1.0 {
+        return miss;
+    }
+    let q = cross(to_origin, ab);
+    let v = dot(direction, q) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return miss;
+    }
+    return vec3<f32>(dot(ac, q) * inverse_determinant, u, v);
+}
+
+fn trace(origin: vec3<f32>, direction: vec3<f32>, max_distance: f32) -> Hit {
+    var hit: Hit;
+    hit.distance = max_distance;
+    hit.triangle_index = NO_HIT;
+
+    // keeps the sign of the direction's components while avoiding divisions by 0
+    let safe_direction = select(
+        direction,
+        sign(direction) * 1.0e-8 + vec3<f32>(1.0e-12),
+        abs(direction) < vec3<f32>(1.0e-8)
+    );
+    let inv_direction = 1.0 / safe_direction;
+
+    var stack: array<u32, BVH_STACK_SIZE>;
+    var stack_size = 1u;
+    stack[0] = 0u;
+    loop {
+        if stack_size == 0u {
+            break;
+        }
+        stack_size -= 1u;
+        let node = bvh_nodes[stack[stack_size]];
+        if !intersect_aabb(origin, inv_direction, node.aabb_min, node.aabb_max, hit.distance) {
+            continue;
+        }
+        if node.triangle_count > 0u {
+            for (var i = 0u; i < node.triangle_count; i++) {
+                let triangle_index = node.left_or_first + i;
+                let result = intersect_triangle(origin, direction, triangles[triangle_index]);
+                if result.x > 0.0 && result.x < hit.distance {
+                    hit.distance = result.x;
+                    hit.triangle_index = triangle_index;
+                    hit.barycentric = result.yz;
+                }
+            }
+        } else if stack_size + 2u <= BVH_STACK_SIZE {
+            stack[stack_size] = node.left_or_first;
+            stack[stack_size + 1u] = node.left_or_first + 1u;
+            stack_size += 2u;
+        }
+    }
+    return hit;
+}
+
+fn is_occluded(origin: vec3<f32>, direction: vec3<f32>, max_distance: f32) -> bool {
+    return trace(origin, direction, max_distance).triangle_index != NO_HIT;
+}
+
+fn sample_environment(direction: vec3<f32>) -> vec3<f32> {
+    let cubemap_direction = vec3<f32>(-direction.x, direction.y, direction.z);
+    let sky_1 = textureSampleLevel(skybox_texture, skybox_sampler, cubemap_direction, 0.0).rgb;
+    let sky_2 = textureSampleLevel(skybox_texture_2, skybox_sampler, cubemap_direction, 0.0).rgb;
+    return params.environment.z * (params.environment.x * sky_1 + params.environment.y * sky_2);
+}
+
+fn tangent_to_world(n: vec3<f32>, direction: vec3<f32>) -> vec3<f32> {
+    let up = select(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 1.0, 0.0), abs(n.y) < 0.999);
+    let tangent = normalize(cross(up, n));
+    let bitangent = cross(n, tangent);
+    return tangent * direction.x + bitangent * direction.y + n * direction.z;
+}
+
+fn sample_cosine_weighted(n: vec3<f32>) -> vec3<f32> {
+    let radius = sqrt(random());
+    let angle = 2.0 * pi * random();
+    let z = sqrt(max(1.0 - radius * radius, 0.0));
+    return tangent_to_world(n, vec3<f32>(radius * cos(angle), radius * sin(angle), z));
+}
+
+// half vector following the ggx distribution
+fn sample_ggx(n: vec3<f32>, a: f32) -> vec3<f32> {
+    let a2 = a * a;
+    let r = random();
+    let cos_theta = sqrt((1.0 - r) / (1.0 + (a2 - 1.0) * r));
+    let sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+    let phi = 2.0 * pi * random();
+    return tangent_to_world(n, vec3<f32>(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta));
+}
+
+// same as in textured_mesh.wgsl, so the two can be compared
+fn normal_distribution_func_tr_ggx(a: f32, n: vec3<f32>, h: vec3<f32>) -> f32 {
+    let a2 = a * a;
+    let n_dot_h = dot(n, h);
+    let n_dot_h_2 = n_dot_h * n_dot_h;
+    let denom_temp = n_dot_h_2 * (a2 - 1.0) + 1.0;
+    return a2 / (pi * denom_temp * denom_temp + epsilon);
+}
+
+fn geometry_func_schlick_ggx(n_dot_v: f32, k: f32) -> f32 {
+    return n_dot_v / (n_dot_v * (1.0 - k) + k + epsilon);
+}
+
+fn fresnel_func_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+fn evaluate_brdf(
+    n: vec3<f32>,
+    v: vec3<f32>,
+    l: vec3<f32>,
+    base_color: vec3<f32>,
+    metallicness: f32,
+    roughness: f32,
+) -> vec3<f32> {
+    let h = normalize(v + l);
+    let n_dot_v = max(dot(n, v), 0.0);
+    let n_dot_l = max(dot(n, l), 0.0);
+    let f0 = mix(vec3<f32>(0.04), base_color, metallicness);
+
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let geometry = geometry_func_schlick_ggx(n_dot_v, k) * geometry_func_schlick_ggx(n_dot_l, k);
+    let fresnel = fresnel_func_schlick(max(dot(h, v), 0.0), f0);
+    let specular = normal_distribution_func_tr_ggx(roughness, n, h) * geometry * fresnel
+        / (4.0 * n_dot_v * n_dot_l + epsilon);
+    let kd = (vec3<f32>(1.0) - fresnel) * (1.0 - metallicness);
+    return kd * base_color / pi + specular;
+}
+
+fn direct_lighting(
+    origin: vec3<f32>,
+    n: vec3<f32>,
+    v: vec3<f32>,
+    base_color: vec3<f32>,
+    metallicness: f32,
+    roughness: f32,
+) -> vec3<f32> {
+    var total = vec3<f32>(0.0);
+    for (var i = 0u; i < min(params.light_counts.x, MAX_DIRECTIONAL_LIGHTS); i++) {
+        let light = params.directional_lights[i];
+        let l = light.direction_or_position.xyz;
+        let n_dot_l = dot(n, l);
+        if n_dot_l <= 0.0 || is_occluded(origin, l, MAX_DISTANCE) {
+            continue;
+        }
+        total += evaluate_brdf(n, v, l, base_color, metallicness, roughness) * light.radiance.rgb * n_dot_l;
+    }
+    for (var i = 0u; i < min(params.light_counts.y, MAX_POINT_LIGHTS); i++) {
+        let light = params.point_lights[i];
+        let to_light = light.direction_or_position.xyz - origin;
+        let distance = length(to_light);
+        let l = to_light / max(distance, epsilon);
+        let n_dot_l = dot(n, l);
+        if n_dot_l <= 0.0 || is_occluded(origin, l, distance) {
+            continue;
+        }
+        // the falloff of textured_mesh.wgsl
+        let attenuation = 1.0 / (1.0 + 0.007 * distance + 0.0002 * distance * distance);
+        total += evaluate_brdf(n, v, l, base_color, metallicness, roughness) * light.radiance.rgb * n_dot_l * attenuation;
+    }
+    return total;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn cs_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if global_id.x >= params.resolution.x || global_id.y >= params.resolution.y {
+        return;
+    }
+    let pixel_index = global_id.x + global_id.y * params.resolution.x;
+    rng_state = pcg_hash(pixel_index ^ pcg_hash(params.sample_index));
+
+    // jittered within the pixel, which also antialiases the image
+    let pixel = vec2<f32>(global_id.xy) + vec2<f32>(random(), random());
+    let resolution = vec2<f32>(params.resolution);
+    let ndc = vec2<f32>(pixel.x / resolution.x * 2.0 - 1.0, 1.0 - pixel.y / resolution.y * 2.0);
+    // reverse-z, 1 is the near plane
+    let near_point = params.inv_view_proj * vec4<f32>(ndc, 1.0, 1.0);
+    let far_point = params.inv_view_proj * vec4<f32>(ndc, 0.5, 1.0);
+    var origin = near_point.xyz / near_point.w;
+    var direction = normalize(far_point.xyz / far_point.w - origin);
+
+    var radiance = vec3<f32>(0.0);
+    var throughput = vec3<f32>(1.0);
+    for (var bounce = 0u; bounce <= params.max_bounces; bounce++) {
+        let hit = trace(origin, direction, MAX_DISTANCE);
+        if hit.triangle_index == NO_HIT {
+            radiance += throughput * sample_environment(direction);
+            break;
+        }
+
+        let triangle = triangles[hit.triangle_index];
+        let material = materials[bitcast<u32>(triangle.p0.w)];
+        let barycentric = vec3<f32>(1.0 - hit.barycentric.x - hit.barycentric.y, hit.barycentric);
+        var geometric_normal = normalize(cross(triangle.p1.xyz - triangle.p0.xyz, triangle.p2.xyz - triangle.p0.xyz));
+        if dot(geometric_normal, direction) > 0.0 {
+            geometric_normal = -geometric_normal;
+        }
+        var n = normalize(
+            triangle.n0.xyz * barycentric.x + triangle.n1.xyz * barycentric.y + triangle.n2.xyz * barycentric.z
+        );
+        if dot(n, geometric_normal) < 0.0 {
+            n = -n;
+        }
+        let v = -direction;
+        let base_color = material.base_color.rgb;
+        let metallicness = material.metallic_roughness.x;
+        let roughness = max(material.metallic_roughness.y, 0.02);
+        let surface_origin = origin + direction * hit.distance + geometric_normal * RAY_BIAS;
+
+        radiance += throughput * material.emissive.rgb;
+        radiance += throughput * direct_lighting(surface_origin, n, v, base_color, metallicness, roughness);
+
+        if bounce == params.max_bounces {
+            break;
+        }
+
+        // picks between the specular and the diffuse lobes, weighted by the pdf of both
+        let specular_probability = mix(0.5, 1.0, metallicness);
+        var l: vec3<f32>;
+        if random() < specular_probability {
+            l = reflect(-v, sample_ggx(n, roughness));
+        } else {
+            l = sample_cosine_weighted(n);
+        }
+        let n_dot_l = dot(n, l);
+        if n_dot_l <= 0.0 || dot(geometric_normal, l) <= 0.0 {
+            break;
+        }
+        let h = normalize(v + l);
+        let specular_pdf = normal_distribution_func_tr_ggx(roughness, n, h) * max(dot(n, h), 0.0)
+            / (4.0 * max(dot(h, v), epsilon));
+        let diffuse_pdf = n_dot_l / pi;
+        let pdf = specular_probability * specular_pdf + (1.0 - specular_probability) * diffuse_pdf;
+        throughput *= evaluate_brdf(n, v, l, base_color, metallicness, roughness) * n_dot_l / max(pdf, epsilon);
+
+        origin = surface_origin;
+        direction = l;
+    }
+
+    var accumulated = vec4<f32>(radiance, 1.0);
+    if params.sample_index > 0u {
+        accumulated += accumulation[pixel_index];
+    }
+    accumulation[pixel_index] = accumulated;
+    textureStore(output_texture, vec2<i32>(global_id.xy), vec4<f32>(accumulated.rgb / accumulated.w, 1.0));
+}
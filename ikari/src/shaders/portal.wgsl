@@ -0,0 +1,103 @@
+const MAX_CLIP_PLANES = 4u;
+
+struct MeshShaderCameraRaw {
+    view_proj: mat4x4<f32>,
+    position: vec3<f32>,
+    far_plane_distance: f32,
+    clip_plane_count: u32,
+    // normal.xyz, distance
+    clip_planes: array<vec4<f32>, MAX_CLIP_PLANES>,
+}
+
+@group(0) @binding(0)
+var<uniform> CAMERA: MeshShaderCameraRaw;
+
+struct FrameConstants {
+    time_seconds: f32,
+    delta_time_seconds: f32,
+    frame_index: u32,
+    // width, height, 1 / width, 1 / height of the render targets, render_scale included
+    render_resolution: vec4<f32>,
+    // xy: subpixel offset of the main camera's projection in pixels, zw: the same in clip space
+    jitter: vec4<f32>,
+}
+
+@group(0) @binding(4)
+var<uniform> FRAME: FrameConstants;
+
+fn is_clipped(world_position: vec3<f32>) -> bool {
+    for (var i = 0u; i < CAMERA.clip_plane_count; i++) {
+        let plane = CAMERA.clip_planes[i];
+        if dot(plane.xyz, world_position) + plane.w < 0.0 {
+            return true;
+        }
+    }
+    return false;
+}
+
+struct Instance {
+    model_transform_0: vec4<f32>,
+    model_transform_1: vec4<f32>,
+    model_transform_2: vec4<f32>,
+    model_transform_3: vec4<f32>,
+    // x: 1 for mirrors
+    params: vec4<f32>,
+}
+
+struct InstancesUniform {
+    value: array<Instance>,
+}
+
+@group(1) @binding(1)
+var<storage, read> instances_uniform: InstancesUniform;
+
+// the view through the portal, rendered from the same screen position as the main camera's
+@group(2) @binding(0)
+var portal_texture: texture_2d<f32>;
+@group(2) @binding(1)
+var portal_sampler: sampler;
+
+struct VertexInput {
+    @location(0) object_position: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) params: vec4<f32>,
+}
+
+@vertex
+fn vs_main(
+    vshader_input: VertexInput,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    let instance = instances_uniform.value[instance_index];
+    let model_transform = mat4x4<f32>(
+        instance.model_transform_0,
+        instance.model_transform_1,
+        instance.model_transform_2,
+        instance.model_transform_3,
+    );
+    let world_position = model_transform * vec4<f32>(vshader_input.object_position, 1.0);
+
+    var out: VertexOutput;
+    out.clip_position = CAMERA.view_proj * world_position;
+    out.world_position = world_position.xyz;
+    out.params = instance.params;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    if is_clipped(in.world_position) {
+        discard;
+    }
+    var uv = in.clip_position.xy * FRAME.render_resolution.zw;
+    // the mirror's camera is flipped horizontally to keep the triangles' winding
+    if in.params.x > 0.5 {
+        uv.x = 1.0 - uv.x;
+    }
+    let color = textureSampleLevel(portal_texture, portal_sampler, uv, 0.0).rgb;
+    return vec4<f32>(color, 1.0);
+}
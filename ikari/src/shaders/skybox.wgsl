@@ -7,6 +7,20 @@ struct SkyboxShaderCameraRaw {
 @group(1) @binding(0)
 var<uniform> CAMERA: SkyboxShaderCameraRaw;
 
+// globals that change every frame, shared by all the pipelines
+struct FrameConstants {
+    time_seconds: f32,
+    delta_time_seconds: f32,
+    frame_index: u32,
+    // width, height, 1 / width, 1 / height of the render targets, render_scale included
+    render_resolution: vec4<f32>,
+    // xy: subpixel offset of the main camera's projection in pixels, zw: the same in clip space
+    jitter: vec4<f32>,
+}
+
+@group(1) @binding(4)
+var<uniform> FRAME: FrameConstants;
+
 struct RougnessInput {
     value: f32,
 }
@@ -52,7 +66,8 @@ var<uniform> skybox_weights: vec4<f32>;
 fn background_fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let background_col_1 = textureSample(skybox_texture, skybox_sampler, world_normal_to_cubemap_vec(in.world_position));
     let background_col_2 = textureSample(skybox_texture_2, skybox_sampler, world_normal_to_cubemap_vec(in.world_position));
-    let col_combined = (skybox_weights.x * background_col_1) + (skybox_weights.y * background_col_2);
+    // z is RendererData::environment_intensity
+    let col_combined = skybox_weights.z * ((skybox_weights.x * background_col_1) + (skybox_weights.y * background_col_2));
     return vec4<f32>(col_combined.xyz, 1.0);
 }
 
@@ -273,4 +288,123 @@ fn specular_env_map_gen_fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let pre_filtered_color = total_pre_filtered_color / total_weight;
 
     return vec4<f32>(pre_filtered_color.rgb, 1.0);
-}
\ No newline at end of file
+}
+struct ProceduralSkyUniform {
+    // xyz, turbidity in w
+    sun_direction: vec4<f32>,
+    // model in w: 0 for the gradient and 1 for the physical model
+    zenith_color: vec4<f32>,
+    // sun intensity in w
+    horizon_color: vec4<f32>,
+    // exposure in w
+    ground_color: vec4<f32>,
+    // x: cos of the sun angular radius, y: 1 to draw the sun disk
+    sun_disk: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> procedural_sky: ProceduralSkyUniform;
+
+// A, B, C, D and E in the first five elements
+fn perez(cos_theta: f32, gamma: f32, cos_gamma: f32, coefficients: array<f32, 5>) -> f32 {
+    return (1.0 + coefficients[0] * exp(coefficients[1] / max(cos_theta, 0.01)))
+        * (1.0 + coefficients[2] * exp(coefficients[3] * gamma) + coefficients[4] * cos_gamma * cos_gamma);
+}
+
+// Preetham et al. 1999, "A Practical Analytic Model for Daylight", in kcd/m²
+fn preetham_sky(direction: vec3<f32>, sun_direction: vec3<f32>, turbidity: f32) -> vec3<f32> {
+    let t = turbidity;
+    // the model breaks down once the sun is under the horizon
+    let theta_s = min(acos(clamp(sun_direction.y, -1.0, 1.0)), half_pi - 0.01);
+    let cos_theta = max(direction.y, 0.0);
+    let cos_gamma = clamp(dot(direction, sun_direction), -1.0, 1.0);
+    let gamma = acos(cos_gamma);
+
+    let coefficients_luminance = array<f32, 5>(
+        0.1787 * t - 1.4630,
+        -0.3554 * t + 0.4275,
+        -0.0227 * t + 5.3251,
+        0.1206 * t - 2.5771,
+        -0.0670 * t + 0.3703,
+    );
+    let coefficients_x = array<f32, 5>(
+        -0.0193 * t - 0.2592,
+        -0.0665 * t + 0.0008,
+        -0.0004 * t + 0.2125,
+        -0.0641 * t - 0.8989,
+        -0.0033 * t + 0.0452,
+    );
+    let coefficients_y = array<f32, 5>(
+        -0.0167 * t - 0.2608,
+        -0.0950 * t + 0.0092,
+        -0.0079 * t + 0.2102,
+        -0.0441 * t - 1.6537,
+        -0.0109 * t + 0.0529,
+    );
+
+    let chi = (4.0 / 9.0 - t / 120.0) * (pi - 2.0 * theta_s);
+    let zenith_luminance = max((4.0453 * t - 4.9710) * tan(chi) - 0.2155 * t + 2.4192, 0.0);
+    let theta_s_2 = theta_s * theta_s;
+    let theta_s_3 = theta_s_2 * theta_s;
+    let t_2 = t * t;
+    let zenith_x = t_2 * (0.00166 * theta_s_3 - 0.00375 * theta_s_2 + 0.00209 * theta_s)
+        + t * (-0.02903 * theta_s_3 + 0.06377 * theta_s_2 - 0.03202 * theta_s + 0.00394)
+        + (0.11693 * theta_s_3 - 0.21196 * theta_s_2 + 0.06052 * theta_s + 0.25886);
+    let zenith_y = t_2 * (0.00275 * theta_s_3 - 0.00610 * theta_s_2 + 0.00317 * theta_s)
+        + t * (-0.04214 * theta_s_3 + 0.08970 * theta_s_2 - 0.04153 * theta_s + 0.00516)
+        + (0.15346 * theta_s_3 - 0.26756 * theta_s_2 + 0.06670 * theta_s + 0.26688);
+
+    let cos_theta_s = cos(theta_s);
+    let luminance = zenith_luminance * perez(cos_theta, gamma, cos_gamma, coefficients_luminance)
+        / perez(1.0, theta_s, cos_theta_s, coefficients_luminance);
+    let x = zenith_x * perez(cos_theta, gamma, cos_gamma, coefficients_x)
+        / perez(1.0, theta_s, cos_theta_s, coefficients_x);
+    let y = zenith_y * perez(cos_theta, gamma, cos_gamma, coefficients_y)
+        / perez(1.0, theta_s, cos_theta_s, coefficients_y);
+
+    // xyY to XYZ to linear sRGB
+    let xyz = vec3<f32>(x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+    let rgb = vec3<f32>(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    );
+    // fades out over the twilight instead of holding the colors of the sun on the horizon
+    let sun_height_fade = smoothstep(-0.1, 0.05, sun_direction.y);
+    return max(rgb, vec3<f32>(0.0)) * sun_height_fade;
+}
+
+@fragment
+fn procedural_sky_fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // the cubemaps are sampled with world_normal_to_cubemap_vec so the sky is computed for the
+    // world direction that ends up at this texel
+    let direction = normalize(world_normal_to_cubemap_vec(in.world_position));
+    let sun_direction = procedural_sky.sun_direction.xyz;
+    let turbidity = procedural_sky.sun_direction.w;
+    let is_physical = procedural_sky.zenith_color.w > 0.5;
+    let sun_intensity = procedural_sky.horizon_color.w;
+    let exposure = procedural_sky.ground_color.w;
+    let cos_gamma = dot(direction, sun_direction);
+
+    var sky: vec3<f32>;
+    if is_physical {
+        sky = preetham_sky(direction, sun_direction, turbidity);
+    } else {
+        let height = sqrt(max(direction.y, 0.0));
+        // hazier skies have a wider glow
+        let glow = pow(max(cos_gamma, 0.0), 64.0 / turbidity) * 0.01 * sun_intensity;
+        sky = mix(procedural_sky.horizon_color.rgb, procedural_sky.zenith_color.rgb, height)
+            + glow * procedural_sky.horizon_color.rgb;
+    }
+
+    // under the horizon the sky keeps its value at the horizon, it fades into the ground which
+    // is lit as brightly as the horizon
+    let ground = procedural_sky.ground_color.rgb * dot(sky, vec3<f32>(0.2126, 0.7152, 0.0722));
+    sky = mix(sky, ground, 1.0 - smoothstep(-0.05, 0.0, direction.y));
+
+    if procedural_sky.sun_disk.y > 0.5 && cos_gamma > procedural_sky.sun_disk.x && direction.y > 0.0 {
+        sky += vec3<f32>(sun_intensity);
+    }
+
+    return vec4<f32>(sky * exposure, 1.0);
+}
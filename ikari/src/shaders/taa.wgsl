@@ -0,0 +1,73 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+struct TaaConfig {
+    // from the unjittered clip space of this frame to the clip space of the previous one
+    current_to_previous_clip: mat4x4<f32>,
+    // xy: jitter of this frame in clip space, z: weight of this frame, w: 1 if there's no history
+    params: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var current_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var history_texture: texture_2d<f32>;
+@group(0) @binding(2)
+var depth_texture: texture_depth_2d;
+@group(0) @binding(3)
+var history_sampler: sampler;
+@group(0) @binding(4)
+var<uniform> CONFIG: TaaConfig;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let tc = vec2<f32>(f32(i32(vertex_index) / 2) * 2.0, f32(i32(vertex_index) & 1) * 2.0);
+    out.position = vec4<f32>(tc.x * 2.0 - 1.0, 1.0 - tc.y * 2.0, 0.0, 1.0);
+    out.tex_coords = tc;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.position.xy);
+    let max_pixel = vec2<i32>(textureDimensions(current_texture)) - vec2<i32>(1);
+    let current = textureLoad(current_texture, pixel, 0);
+    if CONFIG.params.w > 0.0 {
+        return current;
+    }
+
+    // the history is clamped to the colors around the pixel, and reprojected with the closest
+    // depth around it so the edges of moving objects are kept
+    var neighborhood_min = current.rgb;
+    var neighborhood_max = current.rgb;
+    var closest_depth = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let neighbor = clamp(pixel + vec2<i32>(x, y), vec2<i32>(0), max_pixel);
+            let color = textureLoad(current_texture, neighbor, 0).rgb;
+            neighborhood_min = min(neighborhood_min, color);
+            neighborhood_max = max(neighborhood_max, color);
+            // reverse z
+            closest_depth = max(closest_depth, textureLoad(depth_texture, neighbor, 0));
+        }
+    }
+
+    let uv = in.position.xy / vec2<f32>(max_pixel + vec2<i32>(1));
+    let ndc = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0) - CONFIG.params.xy;
+    let previous_clip = CONFIG.current_to_previous_clip * vec4<f32>(ndc, closest_depth, 1.0);
+    if previous_clip.w <= 0.0 {
+        return current;
+    }
+    let previous_ndc = previous_clip.xy / previous_clip.w;
+    let previous_uv = vec2<f32>(previous_ndc.x * 0.5 + 0.5, 0.5 - previous_ndc.y * 0.5);
+    if any(previous_uv < vec2<f32>(0.0)) || any(previous_uv > vec2<f32>(1.0)) {
+        return current;
+    }
+
+    let history = textureSampleLevel(history_texture, history_sampler, previous_uv, 0.0).rgb;
+    let clamped_history = clamp(history, neighborhood_min, neighborhood_max);
+    return vec4<f32>(mix(clamped_history, current.rgb, CONFIG.params.z), current.a);
+}
@@ -1,19 +1,48 @@
+const MAX_CLIP_PLANES = 4u;
+
 struct MeshShaderCameraRaw {
     view_proj: mat4x4<f32>,
     position: vec3<f32>,
     far_plane_distance: f32,
+    clip_plane_count: u32,
+    // normal.xyz, distance
+    clip_planes: array<vec4<f32>, MAX_CLIP_PLANES>,
 }
 
 @group(0) @binding(0)
 var<uniform> CAMERA: MeshShaderCameraRaw;
 
+// globals that change every frame, shared by all the pipelines
+struct FrameConstants {
+    time_seconds: f32,
+    delta_time_seconds: f32,
+    frame_index: u32,
+    // width, height, 1 / width, 1 / height of the render targets, render_scale included
+    render_resolution: vec4<f32>,
+    // xy: subpixel offset of the main camera's projection in pixels, zw: the same in clip space
+    jitter: vec4<f32>,
+}
+
+@group(0) @binding(4)
+var<uniform> FRAME: FrameConstants;
+
+fn is_clipped(world_position: vec3<f32>) -> bool {
+    for (var i = 0u; i < CAMERA.clip_plane_count; i++) {
+        let plane = CAMERA.clip_planes[i];
+        if dot(plane.xyz, world_position) + plane.w < 0.0 {
+            return true;
+        }
+    }
+    return false;
+}
+
 const MAX_LIGHTS = 32u;
 const MAX_BONES = 512u;
-const POINT_LIGHT_SHOW_MAP_COUNT = 2u;
 const DIRECTIONAL_LIGHT_SHOW_MAP_COUNT = 2u;
 const POINT_LIGHT_SHADOW_MAP_FRUSTUM_FAR_PLANE: f32 = 1000.0;
 const MAX_SHADOW_CASCADES = 4u;
 const MAX_TOTAL_SHADOW_CASCADES = 128u; // MAX_LIGHTS * MAX_SHADOW_CASCADES
+const MAX_BLOB_SHADOWS = 32u;
 // TODO: pass this from cpu
 const SOFT_SHADOW_MAX_DISTANCE: f32 = 10000.0;
 
@@ -23,6 +52,10 @@ const two_pi: f32 = 6.283185307179586;
 const half_pi: f32 = 1.570796326794897;
 const epsilon: f32 = 0.00001;
 const DEBUG_POINT_LIGHT_SAMPLED_FACES: f32 = 0.0;
+const SHADOW_FILTER_MODE_JITTERED = 0u;
+const SHADOW_FILTER_MODE_PCF = 1u;
+const SHADOW_FILTER_MODE_PCSS = 2u;
+const MAX_PCF_SAMPLES = 16u;
 
 struct PointLight {
     position: vec4<f32>,
@@ -34,6 +67,7 @@ struct DirectionalLight {
 }
 struct DirectionalLightCascade {
     world_space_to_light_space: mat4x4<f32>,
+    // slice far distance, pixel size, depth range, padding
     distance_and_pixel_size: vec4<f32>,
 }
 struct Instance {
@@ -44,11 +78,16 @@ struct Instance {
     base_color_factor: vec4<f32>,
     emissive_factor: vec4<f32>,
     mrno: vec4<f32>, // metallicness_factor, roughness_factor, normal scale, occlusion strength
-    alpha_cutoff: vec4<f32>, // alpha_cutoff, padding
+    alpha_cutoff: vec4<f32>, // alpha_cutoff, lightmap_intensity, parallax_height_scale, padding
+    // rows of the uv transform's affine matrix, scroll velocity in w
+    uv_transform_0: vec4<f32>,
+    uv_transform_1: vec4<f32>,
 }
 
 struct PointLightsUniform {
-    lights: array<PointLight, MAX_LIGHTS>,
+    // x: the number of lights
+    count: vec4<u32>,
+    lights: array<PointLight>,
 }
 struct DirectionalLightsUniform {
     lights: array<DirectionalLight, MAX_LIGHTS>,
@@ -66,10 +105,15 @@ struct PbrShaderOptionsUniform {
     options_2: vec4<f32>,
     options_3: vec4<f32>,
     options_4: vec4<f32>,
+    options_5: vec4<f32>,
+    options_6: vec4<f32>,
+    options_7: vec4<f32>,
+    // bottom center xyz, radius
+    blob_shadows: array<vec4<f32>, MAX_BLOB_SHADOWS>,
 }
 
 @group(0) @binding(1)
-var<uniform> point_lights: PointLightsUniform;
+var<storage, read> point_lights: PointLightsUniform;
 @group(0) @binding(2)
 var<uniform> directional_lights: DirectionalLightsUniform;
 @group(0) @binding(3)
@@ -96,6 +140,7 @@ struct VertexInput {
     @location(5) object_color: vec4<f32>,
     @location(6) bone_indices: vec4<u32>,
     @location(7) bone_weights: vec4<f32>,
+    @location(8) object_lightmap_tex_coords: vec2<f32>,
 }
 
 struct VertexOutput {
@@ -114,6 +159,9 @@ struct VertexOutput {
     @location(11) occlusion_strength: f32,
     @location(12) alpha_cutoff: f32,
     @location(13) object_tangent: vec3<f32>,
+    // lightmap uv in xy, lightmap_intensity in z
+    @location(14) lightmap: vec3<f32>,
+    @location(15) parallax_height_scale: f32,
 }
 
 struct FragmentOutput {
@@ -156,6 +204,10 @@ var emissive_map_sampler: sampler;
 var ambient_occlusion_map_texture: texture_2d<f32>;
 @group(3) @binding(9)
 var ambient_occlusion_map_sampler: sampler;
+@group(3) @binding(10)
+var lightmap_texture: texture_2d<f32>;
+@group(3) @binding(11)
+var lightmap_sampler: sampler;
 
 @group(1) @binding(0)
 var skybox_texture: texture_cube<f32>;
@@ -183,6 +235,10 @@ var point_shadow_map_textures: texture_2d_array<f32>;
 var directional_shadow_map_textures: texture_2d_array<f32>;
 @group(1) @binding(12)
 var shadow_map_sampler: sampler;
+@group(1) @binding(13)
+var sdf_volume_texture: texture_3d<f32>;
+@group(1) @binding(14)
+var sdf_volume_sampler: sampler;
 
 fn get_soft_shadows_are_enabled() -> bool {
     return shader_options.options_1[0] > 0.0;
@@ -208,6 +264,179 @@ fn get_cascade_debug_enabled() -> bool {
     return shader_options.options_2[1] > 0.0;
 }
 
+fn get_sdf_shadows_are_enabled() -> bool {
+    return shader_options.options_2[2] > 0.0;
+}
+
+fn get_sdf_shadow_softness() -> f32 {
+    return shader_options.options_2[2];
+}
+
+fn get_sdf_ao_strength() -> f32 {
+    return shader_options.options_2[3];
+}
+
+fn get_sdf_volume_origin() -> vec3<f32> {
+    return shader_options.options_3.xyz;
+}
+
+fn get_sdf_voxel_size() -> f32 {
+    return shader_options.options_3[3];
+}
+
+fn get_sdf_volume_dims() -> vec3<f32> {
+    return shader_options.options_4.xyz;
+}
+
+// in shadow map texels
+fn get_shadow_normal_offset() -> f32 {
+    return shader_options.options_4[3];
+}
+
+fn get_point_shadows_are_enabled() -> bool {
+    return shader_options.options_5[0] > 0.0;
+}
+
+fn get_blob_shadow_count() -> u32 {
+    return u32(shader_options.options_5[1]);
+}
+
+fn get_blob_shadow_fade_height() -> f32 {
+    return shader_options.options_5[2];
+}
+
+fn get_blob_shadow_opacity() -> f32 {
+    return shader_options.options_5[3];
+}
+
+fn get_shadow_filter_mode() -> u32 {
+    return u32(shader_options.options_6[0]);
+}
+
+// in shadow map texels
+fn get_pcf_kernel_radius() -> f32 {
+    return shader_options.options_6[1];
+}
+
+fn get_pcf_sample_count() -> u32 {
+    return clamp(u32(shader_options.options_6[2]), 1u, MAX_PCF_SAMPLES);
+}
+
+fn get_pcf_poisson_rotation_enabled() -> bool {
+    return shader_options.options_6[3] > 0.0;
+}
+
+// in radians
+fn get_pcss_directional_light_angular_size() -> f32 {
+    return shader_options.options_7[0];
+}
+
+// in world units
+fn get_pcss_point_light_radius() -> f32 {
+    return shader_options.options_7[1];
+}
+
+// in shadow map texels, also the radius of the blocker search
+fn get_pcss_max_kernel_radius() -> f32 {
+    return shader_options.options_7[2];
+}
+
+// 0 disables the parallax occlusion mapping
+fn get_parallax_max_step_count() -> u32 {
+    return u32(shader_options.options_7[3]);
+}
+
+// returns 1.0 when the position isn't under any blob shadow
+fn compute_blob_shadow_visibility(world_position: vec3<f32>, world_normal: vec3<f32>) -> f32 {
+    let blob_count = min(get_blob_shadow_count(), MAX_BLOB_SHADOWS);
+    let fade_height = get_blob_shadow_fade_height();
+    let opacity = get_blob_shadow_opacity();
+    // only the ground receives blob shadows, not walls
+    let upward_facing = saturate(world_normal.y);
+
+    var visibility = 1.0;
+    for (var blob_index = 0u; blob_index < blob_count; blob_index = blob_index + 1u) {
+        let blob = shader_options.blob_shadows[blob_index];
+        let height_above_receiver = blob.y - world_position.y;
+        // small tolerance so objects resting on the ground still get their blob,
+        // but the caster doesn't shadow itself
+        if height_above_receiver < -0.05 {
+            continue;
+        }
+        let height_fraction = saturate(height_above_receiver / fade_height);
+        // spreads out as it fades, like a real penumbra would
+        let radius = blob.w * (1.0 + height_fraction);
+        let distance_from_center = distance(world_position.xz, blob.xz);
+        let falloff = 1.0 - smoothstep(radius * 0.4, radius, distance_from_center);
+        let strength = opacity * falloff * (1.0 - height_fraction) * upward_facing;
+        visibility = visibility * (1.0 - strength);
+    }
+    return visibility;
+}
+
+// returns a negative distance outside of the volume
+fn sample_sdf_volume(world_position: vec3<f32>) -> f32 {
+    let voxel_coord = (world_position - get_sdf_volume_origin()) / get_sdf_voxel_size();
+    let dims = get_sdf_volume_dims();
+    if any(voxel_coord < vec3<f32>(0.0)) || any(voxel_coord > dims - vec3<f32>(1.0)) {
+        return -1.0;
+    }
+    return textureSampleLevel(
+        sdf_volume_texture,
+        sdf_volume_sampler,
+        (voxel_coord + vec3<f32>(0.5)) / dims,
+        0.0
+    ).r;
+}
+
+// sphere-traces the distance field towards the light, see SdfVolume::soft_shadow
+fn compute_sdf_soft_shadow(world_position: vec3<f32>, world_normal: vec3<f32>, to_light_dir: vec3<f32>) -> f32 {
+    let voxel_size = get_sdf_voxel_size();
+    let softness = get_sdf_shadow_softness();
+    // the field is unsigned, so a shell around the surfaces is treated as solid
+    let surface_thickness = 0.5 * voxel_size;
+    let origin = world_position + world_normal * voxel_size;
+
+    var visibility = 1.0;
+    var t = voxel_size;
+    for (var i = 0; i < 64; i++) {
+        let distance = sample_sdf_volume(origin + to_light_dir * t);
+        if distance < 0.0 {
+            break;
+        }
+        let surface_distance = distance - surface_thickness;
+        if surface_distance <= 0.0 {
+            return 0.0;
+        }
+        visibility = min(visibility, softness * surface_distance / t);
+        t = t + max(surface_distance, 0.5 * voxel_size);
+    }
+    return clamp(visibility, 0.0, 1.0);
+}
+
+fn compute_sdf_ambient_occlusion(world_position: vec3<f32>, world_normal: vec3<f32>) -> f32 {
+    let voxel_size = get_sdf_voxel_size();
+    var occlusion = 0.0;
+    var weight = 1.0;
+    for (var i = 1; i <= 5; i++) {
+        let step = f32(i) * voxel_size;
+        let distance = sample_sdf_volume(world_position + world_normal * step);
+        if distance < 0.0 {
+            break;
+        }
+        occlusion = occlusion + max(step - distance, 0.0) * weight;
+        weight = weight * 0.5;
+    }
+    return clamp(1.0 - occlusion / voxel_size, 0.0, 1.0);
+}
+
+fn transform_uv(tex_coords: vec2<f32>, uv_transform_0: vec4<f32>, uv_transform_1: vec4<f32>) -> vec2<f32> {
+    let uv = vec3<f32>(tex_coords, 1.0);
+    // fract keeps the precision as time goes on, the textures repeat anyway
+    let scroll = fract(vec2<f32>(uv_transform_0.w, uv_transform_1.w) * FRAME.time_seconds);
+    return vec2<f32>(dot(uv_transform_0.xyz, uv), dot(uv_transform_1.xyz, uv)) + scroll;
+}
+
 fn do_vertex_shade(
     vshader_input: VertexInput,
     camera_view_proj: mat4x4<f32>,
@@ -219,7 +448,11 @@ fn do_vertex_shade(
     roughness_factor: f32,
     normal_scale: f32,
     occlusion_strength: f32,
-    alpha_cutoff: f32
+    alpha_cutoff: f32,
+    lightmap_intensity: f32,
+    parallax_height_scale: f32,
+    uv_transform_0: vec4<f32>,
+    uv_transform_1: vec4<f32>
 ) -> VertexOutput {
     var out: VertexOutput;
     out.world_normal = vshader_input.object_normal;
@@ -238,7 +471,7 @@ fn do_vertex_shade(
     out.world_tangent = world_tangent;
     out.object_tangent = vshader_input.object_tangent;
     out.world_bitangent = world_bitangent;
-    out.tex_coords = vshader_input.object_tex_coords;
+    out.tex_coords = transform_uv(vshader_input.object_tex_coords, uv_transform_0, uv_transform_1);
     out.vertex_color = vshader_input.object_color;
     out.base_color_factor = base_color_factor;
     out.emissive_factor = emissive_factor;
@@ -247,6 +480,8 @@ fn do_vertex_shade(
     out.normal_scale = normal_scale;
     out.occlusion_strength = occlusion_strength;
     out.alpha_cutoff = alpha_cutoff;
+    out.lightmap = vec3<f32>(vshader_input.object_lightmap_tex_coords, lightmap_intensity);
+    out.parallax_height_scale = parallax_height_scale;
 
     return out;
 }
@@ -285,6 +520,10 @@ fn vs_main(
         instance.mrno[2],
         instance.mrno[3],
         instance.alpha_cutoff[0],
+        instance.alpha_cutoff[1],
+        instance.alpha_cutoff[2],
+        instance.uv_transform_0,
+        instance.uv_transform_1,
     );
 }
 
@@ -319,16 +558,31 @@ fn shadow_map_vs_main(
     var out: ShadowMappingVertexOutput;
     out.clip_position = clip_position;
     out.world_position = world_position.xyz;
-    out.tex_coords = vshader_input.object_tex_coords;
+    out.tex_coords = transform_uv(
+        vshader_input.object_tex_coords,
+        instance.uv_transform_0,
+        instance.uv_transform_1,
+    );
     out.alpha_cutoff = instance.alpha_cutoff[0];
     return out;
 }
 
+fn point_shadow_map_depth(in: ShadowMappingVertexOutput) -> ShadowMappingFragmentOutput {
+    var out: ShadowMappingFragmentOutput;
+    let light_distance = length(in.world_position - CAMERA.position.xyz);
+    out.depth = light_distance / CAMERA.far_plane_distance;
+    return out;
+}
+
 @fragment
 fn point_shadow_map_fs_main(
     in: ShadowMappingVertexOutput
 ) -> ShadowMappingFragmentOutput {
+    return point_shadow_map_depth(in);
+}
 
+// for the materials with an alpha cutoff, e.g. fences and foliage
+fn shadow_map_alpha_test(in: ShadowMappingVertexOutput) {
     let base_color_alpha = textureSample(
         shadow_diffuse_texture,
         shadow_diffuse_sampler,
@@ -338,11 +592,19 @@ fn point_shadow_map_fs_main(
     if base_color_alpha <= in.alpha_cutoff {
         discard;
     }
+}
 
-    var out: ShadowMappingFragmentOutput;
-    let light_distance = length(in.world_position - CAMERA.position.xyz);
-    out.depth = light_distance / CAMERA.far_plane_distance;
-    return out;
+@fragment
+fn point_shadow_map_alpha_masked_fs_main(
+    in: ShadowMappingVertexOutput
+) -> ShadowMappingFragmentOutput {
+    shadow_map_alpha_test(in);
+    return point_shadow_map_depth(in);
+}
+
+@fragment
+fn directional_shadow_map_alpha_masked_fs_main(in: ShadowMappingVertexOutput) {
+    shadow_map_alpha_test(in);
 }
 
 // https://learnopengl.com/PBR/Theory
@@ -491,6 +753,186 @@ fn get_soft_shadow_sample_jitter(grid_coord: vec2<u32>, jitter_amount: vec2<f32>
     return vec2(2.0, 2.0) * disk_warp(jittered_cell_center) - vec2(1.0, 1.0);
 }
 
+var<private> poisson_disk: array<vec2<f32>, MAX_PCF_SAMPLES> = array<vec2<f32>, MAX_PCF_SAMPLES>(
+    vec2<f32>(-0.94201624, -0.39906216),
+    vec2<f32>(0.94558609, -0.76890725),
+    vec2<f32>(-0.094184101, -0.92938870),
+    vec2<f32>(0.34495938, 0.29387760),
+    vec2<f32>(-0.91588581, 0.45771432),
+    vec2<f32>(-0.81544232, -0.87912464),
+    vec2<f32>(-0.38277543, 0.27676845),
+    vec2<f32>(0.97484398, 0.75648379),
+    vec2<f32>(0.44323325, -0.97511554),
+    vec2<f32>(0.53742981, -0.47373420),
+    vec2<f32>(-0.26496911, -0.41893023),
+    vec2<f32>(0.79197514, 0.19090188),
+    vec2<f32>(-0.24188840, 0.99706507),
+    vec2<f32>(-0.81409955, 0.91437590),
+    vec2<f32>(0.19984126, 0.78641367),
+    vec2<f32>(0.14383161, -0.14100790)
+);
+
+// percentage-closer filtering over a poisson disk, radius is in uv units.
+// returns the fraction of the samples that are lit
+fn filter_directional_shadow(
+    uv: vec2<f32>,
+    cascade_index: u32,
+    current_depth: f32,
+    bias: f32,
+    radius: vec2<f32>,
+    poisson_rotation: mat2x2<f32>
+) -> f32 {
+    let sample_count = get_pcf_sample_count();
+    var lit_sample_count = 0.0;
+    for (var i = 0u; i < sample_count; i++) {
+        let closest_depth = textureSampleLevel(
+            directional_shadow_map_textures,
+            shadow_map_sampler,
+            uv + (poisson_rotation * poisson_disk[i]) * radius,
+            i32(cascade_index),
+            0.0
+        ).r;
+        if current_depth - bias < closest_depth {
+            lit_sample_count += 1.0;
+        }
+    }
+    return lit_sample_count / f32(sample_count);
+}
+
+// average depth of the shadow casters around uv, or -1.0 if there aren't any
+fn find_directional_shadow_blocker_depth(
+    uv: vec2<f32>,
+    cascade_index: u32,
+    current_depth: f32,
+    bias: f32,
+    radius: vec2<f32>,
+    poisson_rotation: mat2x2<f32>
+) -> f32 {
+    let sample_count = get_pcf_sample_count();
+    var blocker_depth_sum = 0.0;
+    var blocker_count = 0.0;
+    for (var i = 0u; i < sample_count; i++) {
+        let closest_depth = textureSampleLevel(
+            directional_shadow_map_textures,
+            shadow_map_sampler,
+            uv + (poisson_rotation * poisson_disk[i]) * radius,
+            i32(cascade_index),
+            0.0
+        ).r;
+        if current_depth - bias >= closest_depth {
+            blocker_depth_sum += closest_depth;
+            blocker_count += 1.0;
+        }
+    }
+    return select(-1.0, blocker_depth_sum / blocker_count, blocker_count > 0.0);
+}
+
+// PCF or PCSS, depending on the shadow filter mode
+fn compute_directional_shadow_pcf(
+    uv: vec2<f32>,
+    cascade_index: u32,
+    current_depth: f32,
+    bias: f32,
+    poisson_rotation: mat2x2<f32>
+) -> f32 {
+    let texel_size = 1.0 / vec2<f32>(textureDimensions(directional_shadow_map_textures));
+    var kernel_radius = get_pcf_kernel_radius();
+    if get_shadow_filter_mode() == SHADOW_FILTER_MODE_PCSS {
+        let max_kernel_radius = get_pcss_max_kernel_radius();
+        let blocker_depth = find_directional_shadow_blocker_depth(
+            uv,
+            cascade_index,
+            current_depth,
+            bias,
+            max_kernel_radius * texel_size,
+            poisson_rotation
+        );
+        if blocker_depth < 0.0 {
+            return 1.0;
+        }
+        // the light is directional so the penumbra only grows with the distance to the caster
+        let cascade = directional_lights.cascades[cascade_index];
+        let pixel_size = cascade.distance_and_pixel_size.y;
+        let depth_range = cascade.distance_and_pixel_size.z;
+        let blocker_distance = (current_depth - blocker_depth) * depth_range;
+        let penumbra_size = blocker_distance * tan(get_pcss_directional_light_angular_size());
+        kernel_radius = clamp(0.5 * penumbra_size / pixel_size, 1.0, max_kernel_radius);
+    }
+    return filter_directional_shadow(
+        uv,
+        cascade_index,
+        current_depth,
+        bias,
+        kernel_radius * texel_size,
+        poisson_rotation
+    );
+}
+
+// the faces of the cube map are side by side in the shadow map, so a texel of a face
+// has the same size in uv units as a texel of the whole map
+fn compute_point_shadow_pcf(
+    uv: vec2<f32>,
+    face_slice: f32,
+    shadow_layer: i32,
+    current_depth: f32,
+    bias: f32,
+    poisson_rotation: mat2x2<f32>
+) -> f32 {
+    let shadow_map_dims = vec2<f32>(textureDimensions(point_shadow_map_textures));
+    let texel_size = 1.0 / shadow_map_dims;
+    let sample_count = get_pcf_sample_count();
+
+    var kernel_radius = get_pcf_kernel_radius();
+    if get_shadow_filter_mode() == SHADOW_FILTER_MODE_PCSS {
+        let max_kernel_radius = get_pcss_max_kernel_radius();
+        var blocker_depth_sum = 0.0;
+        var blocker_count = 0.0;
+        for (var i = 0u; i < sample_count; i++) {
+            let offset = (poisson_rotation * poisson_disk[i]) * max_kernel_radius * texel_size;
+            let closest_depth = textureSampleLevel(
+                point_shadow_map_textures,
+                shadow_map_sampler,
+                clamp_jittered_cubemap_uv(uv + offset, face_slice),
+                shadow_layer,
+                0.0
+            ).r;
+            if current_depth - bias >= closest_depth {
+                blocker_depth_sum += closest_depth;
+                blocker_count += 1.0;
+            }
+        }
+        if blocker_count == 0.0 {
+            return 1.0;
+        }
+        let receiver_distance = current_depth * POINT_LIGHT_SHADOW_MAP_FRUSTUM_FAR_PLANE;
+        let blocker_distance = max(
+            blocker_depth_sum / blocker_count * POINT_LIGHT_SHADOW_MAP_FRUSTUM_FAR_PLANE,
+            epsilon
+        );
+        // similar triangles between the light, the caster and the receiver
+        let penumbra_size = get_pcss_point_light_radius() * (receiver_distance - blocker_distance) / blocker_distance;
+        // a face of the cube map is twice the receiver distance wide at the receiver
+        let penumbra_texels = penumbra_size / (2.0 * receiver_distance) * shadow_map_dims.y;
+        kernel_radius = clamp(0.5 * penumbra_texels, 1.0, max_kernel_radius);
+    }
+
+    var lit_sample_count = 0.0;
+    for (var i = 0u; i < sample_count; i++) {
+        let offset = (poisson_rotation * poisson_disk[i]) * kernel_radius * texel_size;
+        let closest_depth = textureSampleLevel(
+            point_shadow_map_textures,
+            shadow_map_sampler,
+            clamp_jittered_cubemap_uv(uv + offset, face_slice),
+            shadow_layer,
+            0.0
+        ).r;
+        if current_depth - bias < closest_depth {
+            lit_sample_count += 1.0;
+        }
+    }
+    return lit_sample_count / f32(sample_count);
+}
+
 fn compute_direct_lighting(
     world_normal: vec3<f32>,
     to_viewer_vec: vec3<f32>,
@@ -611,7 +1053,8 @@ fn do_fragment_shade(
     metallicness_factor: f32,
     roughness_factor: f32,
     occlusion_strength: f32,
-    alpha_cutoff: f32
+    alpha_cutoff: f32,
+    lightmap: vec3<f32>
 ) -> FragmentOutput {
 
     // let roughness = 0.12;
@@ -665,7 +1108,8 @@ fn do_fragment_shade(
         roughness * MAX_REFLECTION_LOD
     ).rgb;
 
-    let pre_filtered_color = (skybox_weights.x * pre_filtered_color_1) + (skybox_weights.y * pre_filtered_color_2);
+    // z is RendererData::environment_intensity
+    let pre_filtered_color = skybox_weights.z * ((skybox_weights.x * pre_filtered_color_1) + (skybox_weights.y * pre_filtered_color_2));
 
     // copy variable names from the math formulas
     let n = world_normal;
@@ -687,7 +1131,10 @@ fn do_fragment_shade(
         world_normal_to_cubemap_vec(world_normal)
     ).rgb;
 
-    let env_map_diffuse_irradiance = (skybox_weights.x * env_map_diffuse_irradiance_1) + (skybox_weights.y * env_map_diffuse_irradiance_2);
+    let env_map_diffuse_irradiance_ibl = skybox_weights.z * ((skybox_weights.x * env_map_diffuse_irradiance_1) + (skybox_weights.y * env_map_diffuse_irradiance_2));
+    // baked materials take their indirect diffuse light from the lightmap instead of the IBL
+    let baked_irradiance = lightmap.z * textureSample(lightmap_texture, lightmap_sampler, lightmap.xy).rgb;
+    let env_map_diffuse_irradiance = select(env_map_diffuse_irradiance_ibl, baked_irradiance, lightmap.z > 0.0);
 
     if base_color_t.a <= alpha_cutoff {
         discard;
@@ -703,17 +1150,28 @@ fn do_fragment_shade(
 
     let shadow_bias = get_shadow_bias();
     let soft_shadow_grid_dims = get_soft_shadow_grid_dims();
+    let use_pcf = get_soft_shadows_are_enabled() && get_shadow_filter_mode() != SHADOW_FILTER_MODE_JITTERED;
+    // rotating the poisson disk per pixel trades the banding of the kernel for noise
+    let poisson_angle = select(0.0, pi * (random_jitter.x + 1.0), get_pcf_poisson_rotation_enabled());
+    let poisson_rotation = mat2x2<f32>(
+        cos(poisson_angle), sin(poisson_angle),
+        -sin(poisson_angle), cos(poisson_angle)
+    );
 
     var total_shadow_occlusion_acc = 0.0;
     var total_light_count = 0u;
 
+    var blob_shadow_visibility = 1.0;
+    if !get_point_shadows_are_enabled() {
+        blob_shadow_visibility = compute_blob_shadow_visibility(world_position, world_normal);
+    }
+
     var total_light_irradiance = vec3<f32>(0.0);
-    for (var light_index = 0u; light_index < MAX_LIGHTS; light_index = light_index + 1u) {
+    for (var light_index = 0u; light_index < point_lights.count.x; light_index = light_index + 1u) {
         let light = point_lights.lights[light_index];
 
         if light.color.w < epsilon {
-            // break seems to be decently faster than continue here
-            break;
+            continue;
         }
 
         let light_color_scaled = light.color.xyz * light.color.w;
@@ -728,10 +1186,27 @@ fn do_fragment_shade(
         let current_depth = length(from_shadow_vec) / POINT_LIGHT_SHADOW_MAP_FRUSTUM_FAR_PLANE; // domain is (0, 1), lower means closer to the light
         let bias = mix(shadow_bias, MIN_SHADOW_MAP_BIAS, n_dot_l);
 
+        // -1 for the lights without a shadow map
+        let shadow_layer = i32(light.position.w);
+
         var shadow_occlusion_acc = 0.0;
 
-        if n_dot_l > 0.0 && light_index < POINT_LIGHT_SHOW_MAP_COUNT {
-            if get_soft_shadows_are_enabled() {
+        if !get_point_shadows_are_enabled() {
+            // the shadow maps aren't being rendered, fall back to the blob shadows
+            shadow_occlusion_acc = blob_shadow_visibility;
+        } else if shadow_layer < 0 {
+            shadow_occlusion_acc = 1.0;
+        } else if n_dot_l > 0.0 {
+            if use_pcf {
+                shadow_occlusion_acc = compute_point_shadow_pcf(
+                    light_space_position_uv,
+                    light_space_position_face_slice,
+                    shadow_layer,
+                    current_depth,
+                    bias,
+                    poisson_rotation
+                );
+            } else if get_soft_shadows_are_enabled() {
                 // soft shadows code path
                 // TODO: dedupe with directional lights
 
@@ -756,7 +1231,7 @@ fn do_fragment_shade(
                             light_space_position_uv + sample_jitter, 
                             light_space_position_face_slice
                         ),
-                        i32(light_index),
+                        shadow_layer,
                         0.0
                     ).r;
 
@@ -792,7 +1267,7 @@ fn do_fragment_shade(
                                     light_space_position_uv + sample_jitter, 
                                     light_space_position_face_slice
                                 ),
-                                i32(light_index),
+                                shadow_layer,
                                 0.0
                             ).r;
                             
@@ -808,7 +1283,7 @@ fn do_fragment_shade(
                     point_shadow_map_textures,
                     shadow_map_sampler,
                     light_space_position_uv,
-                    i32(light_index),
+                    shadow_layer,
                     0.0
                 ).r;
                 if (current_depth - bias < closest_depth) {
@@ -897,7 +1372,13 @@ fn do_fragment_shade(
                 }
 
                 let shadow_cascade_pixel_size = directional_lights.cascades[shadow_cascade_index].distance_and_pixel_size.y;
-                let light_space_position_nopersp = directional_lights.cascades[shadow_cascade_index].world_space_to_light_space * vec4<f32>(world_position, 1.0);
+                // normal offset: surfaces at grazing angles to the light are pushed out the most,
+                // which avoids acne without the peter-panning of a large depth bias
+                let geometric_normal = normalize(world_normal);
+                let geometric_n_dot_l = saturate(dot(geometric_normal, to_light_vec_norm));
+                let normal_offset = get_shadow_normal_offset() * shadow_cascade_pixel_size * (1.0 - geometric_n_dot_l);
+                let shadow_receiver_position = world_position + geometric_normal * normal_offset;
+                let light_space_position_nopersp = directional_lights.cascades[shadow_cascade_index].world_space_to_light_space * vec4<f32>(shadow_receiver_position, 1.0);
                 let light_space_position = light_space_position_nopersp / light_space_position_nopersp.w;
                 let light_space_position_uv = vec2<f32>(
                     light_space_position.x * 0.5 + 0.5,
@@ -907,7 +1388,15 @@ fn do_fragment_shade(
 
                 // assume we're not in shadow if we're outside the shadow's viewproj area
                 if shadow_cascade_dist != 0.0 && to_viewer_vec_length < shadow_cascade_dist && light_space_position.x >= -1.0 && light_space_position.x <= 1.0 && light_space_position.y >= -1.0 && light_space_position.y <= 1.0 && light_space_position.z >= 0.0 && light_space_position.z <= 1.0 {
-                    if get_soft_shadows_are_enabled() && to_viewer_vec_length < SOFT_SHADOW_MAX_DISTANCE {
+                    if use_pcf && to_viewer_vec_length < SOFT_SHADOW_MAX_DISTANCE {
+                        shadow_occlusion_acc = compute_directional_shadow_pcf(
+                            light_space_position_uv,
+                            shadow_cascade_index,
+                            current_depth,
+                            bias,
+                            poisson_rotation
+                        );
+                    } else if get_soft_shadows_are_enabled() && to_viewer_vec_length < SOFT_SHADOW_MAX_DISTANCE {
                         // soft shadows code path. costs about 0.15ms extra (per shadow map?) per frame
                         // on an RTX 3060 when compared to hard shadows
 
@@ -986,6 +1475,9 @@ fn do_fragment_shade(
                             shadow_occlusion_acc = 1.0;
                         }
                     }
+                } else if get_sdf_shadows_are_enabled() {
+                    // beyond the range of the cascades, fall back to the coarse distance field
+                    shadow_occlusion_acc = compute_sdf_soft_shadow(world_position, n, to_light_vec_norm);
                 } else {
                     shadow_occlusion_acc = 1.0;
                 }
@@ -1063,11 +1555,20 @@ fn do_fragment_shade(
     let ambient_diffuse_irradiance = env_map_diffuse_irradiance * base_color;
 
     let ambient_irradiance_pre_ao = (kd_ambient * ambient_diffuse_irradiance + ambient_specular_irradiance);
-    let ambient_irradiance = mix(
+    var ambient_irradiance = mix(
         ambient_irradiance_pre_ao,
         ambient_irradiance_pre_ao * ambient_occlusion,
         occlusion_strength
     );
+    if get_sdf_shadows_are_enabled() && get_sdf_ao_strength() > 0.0 {
+        let sdf_ambient_occlusion = compute_sdf_ambient_occlusion(world_position, n);
+        ambient_irradiance = mix(
+            ambient_irradiance,
+            ambient_irradiance * sdf_ambient_occlusion,
+            get_sdf_ao_strength()
+        );
+    }
+    ambient_irradiance = ambient_irradiance * blob_shadow_visibility;
     // let ambient_irradiance = ambient_irradiance_pre_ao;
 
     let combined_irradiance_hdr = ambient_irradiance + total_light_irradiance + emissive;
@@ -1084,17 +1585,81 @@ fn do_fragment_shade(
     return out;
 }
 
+// Steps along the view ray through the height map in the alpha channel of the normal map until
+// it goes under the surface. A height of 1 is at the surface and 0 is height_scale uv units below
+fn get_parallax_tex_coords(
+    tex_coords: vec2<f32>,
+    tangent_space_to_viewer: vec3<f32>,
+    height_scale: f32,
+) -> vec2<f32> {
+    let max_step_count = get_parallax_max_step_count();
+    var step_count = 0u;
+    if height_scale > 0.0 && max_step_count > 0u && tangent_space_to_viewer.z > 0.001 {
+        // grazing angles need more steps than looking straight at the surface
+        step_count = u32(mix(f32(max_step_count), max(f32(max_step_count) / 4.0, 1.0), tangent_space_to_viewer.z));
+    }
+
+    // the loop has no implicit derivatives so they're taken up front
+    let tex_coords_ddx = dpdx(tex_coords);
+    let tex_coords_ddy = dpdy(tex_coords);
+
+    let layer_depth = 1.0 / f32(max(step_count, 1u));
+    // y is flipped like for the normal map
+    let uv_step = vec2<f32>(tangent_space_to_viewer.x, -tangent_space_to_viewer.y)
+        / max(tangent_space_to_viewer.z, 0.001) * height_scale * layer_depth;
+
+    var current_tex_coords = tex_coords;
+    var current_layer_depth = 0.0;
+    var current_depth = 1.0 - textureSampleGrad(
+        normal_map_texture,
+        normal_map_sampler,
+        current_tex_coords,
+        tex_coords_ddx,
+        tex_coords_ddy
+    ).a;
+    var previous_tex_coords = current_tex_coords;
+    var previous_depth_difference = 0.0;
+    for (var step_index = 0u; step_index < step_count && current_layer_depth < current_depth; step_index++) {
+        previous_tex_coords = current_tex_coords;
+        previous_depth_difference = current_depth - current_layer_depth;
+        current_tex_coords -= uv_step;
+        current_layer_depth += layer_depth;
+        current_depth = 1.0 - textureSampleGrad(
+            normal_map_texture,
+            normal_map_sampler,
+            current_tex_coords,
+            tex_coords_ddx,
+            tex_coords_ddy
+        ).a;
+    }
+
+    // interpolate between the last step above the surface and the first one under it
+    let depth_difference = current_depth - current_layer_depth;
+    let previous_weight = clamp(depth_difference / min(depth_difference - previous_depth_difference, -0.0001), 0.0, 1.0);
+    return mix(current_tex_coords, previous_tex_coords, previous_weight);
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> FragmentOutput {
+    if is_clipped(in.world_position) {
+        discard;
+    }
+
     let tbn = (mat3x3<f32>(
         in.world_tangent,
         in.world_bitangent,
         in.world_normal,
     ));
+    let to_viewer = normalize(CAMERA.position.xyz - in.world_position);
+    let tex_coords = get_parallax_tex_coords(
+        in.tex_coords,
+        normalize(to_viewer * tbn),
+        in.parallax_height_scale
+    );
     let normal_map_normal = textureSample(
         normal_map_texture,
         normal_map_sampler,
-        in.tex_coords
+        tex_coords
     ) * 2.0 - 1.0;
     let tangent_space_normal = vec3<f32>(
         normal_map_normal.x,
@@ -1116,7 +1681,7 @@ fn fs_main(in: VertexOutput) -> FragmentOutput {
     return do_fragment_shade(
         in.world_position,
         transformed_normal,
-        in.tex_coords,
+        tex_coords,
         in.vertex_color,
         CAMERA.position.xyz,
         in.base_color_factor,
@@ -1124,12 +1689,17 @@ fn fs_main(in: VertexOutput) -> FragmentOutput {
         in.metallicness_factor,
         in.roughness_factor,
         in.occlusion_strength,
-        in.alpha_cutoff
+        in.alpha_cutoff,
+        in.lightmap
     );
 }
 
 @fragment
 fn depth_prepass_fs_main(in: VertexOutput) {
+    if is_clipped(in.world_position) {
+        discard;
+    }
+
     let base_color_t = textureSample(
         diffuse_texture,
         diffuse_sampler,
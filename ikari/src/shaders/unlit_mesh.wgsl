@@ -1,12 +1,41 @@
+const MAX_CLIP_PLANES = 4u;
+
 struct MeshShaderCameraRaw {
     view_proj: mat4x4<f32>,
     position: vec3<f32>,
     far_plane_distance: f32,
+    clip_plane_count: u32,
+    // normal.xyz, distance
+    clip_planes: array<vec4<f32>, MAX_CLIP_PLANES>,
 }
 
 @group(0) @binding(0)
 var<uniform> CAMERA: MeshShaderCameraRaw;
 
+// globals that change every frame, shared by all the pipelines
+struct FrameConstants {
+    time_seconds: f32,
+    delta_time_seconds: f32,
+    frame_index: u32,
+    // width, height, 1 / width, 1 / height of the render targets, render_scale included
+    render_resolution: vec4<f32>,
+    // xy: subpixel offset of the main camera's projection in pixels, zw: the same in clip space
+    jitter: vec4<f32>,
+}
+
+@group(0) @binding(4)
+var<uniform> FRAME: FrameConstants;
+
+fn is_clipped(world_position: vec3<f32>) -> bool {
+    for (var i = 0u; i < CAMERA.clip_plane_count; i++) {
+        let plane = CAMERA.clip_planes[i];
+        if dot(plane.xyz, world_position) + plane.w < 0.0 {
+            return true;
+        }
+    }
+    return false;
+}
+
 struct Instance {
     model_transform_0: vec4<f32>,
     model_transform_1: vec4<f32>,
@@ -42,6 +71,7 @@ struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) color: vec4<f32>,
     @location(1) vertex_color: vec4<f32>,
+    @location(2) world_position: vec3<f32>,
 }
 
 struct FragmentOutput {
@@ -78,6 +108,7 @@ fn vs_main(
     let clip_position = model_view_matrix * object_position;
 
     out.clip_position = clip_position;
+    out.world_position = (skinned_model_transform * object_position).xyz;
     out.color = instance.color;
     out.vertex_color = vshader_input.object_color;
     return out;
@@ -85,6 +116,10 @@ fn vs_main(
 
 @fragment
 fn fs_main(in: VertexOutput) -> FragmentOutput {
+    if is_clipped(in.world_position) {
+        discard;
+    }
+
     var out: FragmentOutput;
     // out.color = vec4<f32>(0.996078431372549, 0.9725490196078431, 0.6627450980392157, 1.0);
     out.color = in.color * in.vertex_color;
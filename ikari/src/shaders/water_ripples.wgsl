@@ -0,0 +1,77 @@
+const MAX_RIPPLE_DROPS = 16u;
+
+struct RippleStepUniform {
+    // damping, drop count, unused, unused
+    params: vec4<f32>,
+    // uv.x, uv.y, radius (in uv units), strength
+    drops: array<vec4<f32>, MAX_RIPPLE_DROPS>,
+}
+
+// r: current height, g: previous height, b: dh/dx, a: dh/dz
+@group(0) @binding(0)
+var ripple_state_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> RIPPLE_STEP: RippleStepUniform;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+// same fullscreen triangle as in blit.wgsl
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let x = i32(vertex_index) / 2;
+    let y = i32(vertex_index) & 1;
+    let tc = vec2<f32>(
+        f32(x) * 2.0,
+        f32(y) * 2.0
+    );
+    out.position = vec4<f32>(
+        tc.x * 2.0 - 1.0,
+        1.0 - tc.y * 2.0,
+        0.0,
+        1.0
+    );
+    out.tex_coords = tc;
+    return out;
+}
+
+fn load_height(coord: vec2<i32>) -> f32 {
+    let dims = vec2<i32>(textureDimensions(ripple_state_texture));
+    // the edges of the heightfield act as walls
+    let clamped_coord = clamp(coord, vec2<i32>(0), dims - vec2<i32>(1));
+    return textureLoad(ripple_state_texture, clamped_coord, 0).r;
+}
+
+@fragment
+fn ripple_step_fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coord = vec2<i32>(in.position.xy);
+    let state = textureLoad(ripple_state_texture, coord, 0);
+    let current_height = state.r;
+    let previous_height = state.g;
+
+    let left = load_height(coord - vec2<i32>(1, 0));
+    let right = load_height(coord + vec2<i32>(1, 0));
+    let up = load_height(coord - vec2<i32>(0, 1));
+    let down = load_height(coord + vec2<i32>(0, 1));
+
+    let damping = RIPPLE_STEP.params.x;
+    var new_height = ((left + right + up + down) * 0.5 - previous_height) * damping;
+
+    let drop_count = u32(RIPPLE_STEP.params.y);
+    for (var i = 0u; i < drop_count; i++) {
+        let drop = RIPPLE_STEP.drops[i];
+        let distance_to_drop = distance(in.tex_coords, drop.xy);
+        if distance_to_drop < drop.z {
+            // smooth bump so the drop doesn't introduce high frequency noise
+            let falloff = 0.5 + 0.5 * cos(3.141592653589793 * distance_to_drop / drop.z);
+            new_height = new_height - drop.w * falloff;
+        }
+    }
+
+    let gradient = vec2<f32>(right - left, down - up) * 0.5;
+
+    return vec4<f32>(new_height, current_height, gradient);
+}
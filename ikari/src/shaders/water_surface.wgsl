@@ -0,0 +1,106 @@
+const MAX_CLIP_PLANES = 4u;
+// how much the ripple heights move the surface up and down
+const HEIGHT_SCALE = 0.1;
+// how much the ripple slopes tilt the normals, the gradient is per texel
+const NORMAL_STRENGTH = 8.0;
+const DEEP_COLOR = vec3<f32>(0.01, 0.05, 0.08);
+const SKY_COLOR = vec3<f32>(0.45, 0.6, 0.8);
+const SUN_COLOR = vec3<f32>(4.0, 3.8, 3.4);
+const SUN_DIRECTION = vec3<f32>(0.267, 0.891, 0.267);
+
+struct MeshShaderCameraRaw {
+    view_proj: mat4x4<f32>,
+    position: vec3<f32>,
+    far_plane_distance: f32,
+    clip_plane_count: u32,
+    // normal.xyz, distance
+    clip_planes: array<vec4<f32>, MAX_CLIP_PLANES>,
+}
+
+@group(0) @binding(0)
+var<uniform> CAMERA: MeshShaderCameraRaw;
+
+fn is_clipped(world_position: vec3<f32>) -> bool {
+    for (var i = 0u; i < CAMERA.clip_plane_count; i++) {
+        let plane = CAMERA.clip_planes[i];
+        if dot(plane.xyz, world_position) + plane.w < 0.0 {
+            return true;
+        }
+    }
+    return false;
+}
+
+struct Instance {
+    model_transform_0: vec4<f32>,
+    model_transform_1: vec4<f32>,
+    model_transform_2: vec4<f32>,
+    model_transform_3: vec4<f32>,
+    // min x, min z, size x, size z of the water surface
+    params: vec4<f32>,
+}
+
+struct InstancesUniform {
+    value: array<Instance>,
+}
+
+@group(1) @binding(1)
+var<storage, read> instances_uniform: InstancesUniform;
+
+// r: current height, g: previous height, b: dh/dx, a: dh/dz, see water_ripples.wgsl
+@group(2) @binding(0)
+var ripple_state_texture: texture_2d<f32>;
+@group(2) @binding(1)
+var ripple_state_sampler: sampler;
+
+struct VertexInput {
+    @location(0) object_position: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) ripple_uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(
+    vshader_input: VertexInput,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    let instance = instances_uniform.value[instance_index];
+    let model_transform = mat4x4<f32>(
+        instance.model_transform_0,
+        instance.model_transform_1,
+        instance.model_transform_2,
+        instance.model_transform_3,
+    );
+    var world_position = model_transform * vec4<f32>(vshader_input.object_position, 1.0);
+    let ripple_uv = (world_position.xz - instance.params.xy) / instance.params.zw;
+    let height = textureSampleLevel(ripple_state_texture, ripple_state_sampler, ripple_uv, 0.0).r;
+    world_position.y = world_position.y + height * HEIGHT_SCALE;
+
+    var out: VertexOutput;
+    out.clip_position = CAMERA.view_proj * world_position;
+    out.world_position = world_position.xyz;
+    out.ripple_uv = ripple_uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    if is_clipped(in.world_position) {
+        discard;
+    }
+    let gradient = textureSampleLevel(ripple_state_texture, ripple_state_sampler, in.ripple_uv, 0.0).ba;
+    let normal = normalize(vec3<f32>(-gradient.x * NORMAL_STRENGTH, 1.0, -gradient.y * NORMAL_STRENGTH));
+    let to_camera = normalize(CAMERA.position - in.world_position);
+
+    // schlick's approximation with the reflectance of water
+    let n_dot_v = max(dot(normal, to_camera), 0.0);
+    let fresnel = 0.02 + 0.98 * pow(1.0 - n_dot_v, 5.0);
+    let half_vector = normalize(SUN_DIRECTION + to_camera);
+    let specular = pow(max(dot(normal, half_vector), 0.0), 256.0);
+
+    let color = mix(DEEP_COLOR, SKY_COLOR, fresnel) + SUN_COLOR * specular * fresnel;
+    return vec4<f32>(color, 1.0);
+}
@@ -0,0 +1,175 @@
+use crate::scene::GameNodeId;
+
+use glam::f32::Vec3;
+
+/// Decides which point light shadow maps are rendered in a frame. A shadow map is kept from
+/// the last time it was rendered when neither the light nor the casters in its range changed,
+/// and the lights far from the camera are refreshed less often
+#[derive(Debug, Clone)]
+pub struct ShadowUpdates {
+    /// casters further than this from a point light are culled from its shadow map
+    pub point_light_shadow_distance: f32,
+    /// the lights closer than this to the camera are refreshed every frame
+    pub full_rate_distance: f32,
+    /// past full_rate_distance, one more frame is skipped between updates every this many units
+    pub distance_per_skipped_frame: f32,
+    /// in frames, for the lights that are the furthest away
+    pub max_update_interval: u32,
+    /// keeps the shadow maps of the lights whose casters didn't move, see invalidate
+    pub enable_static_caching: bool,
+    lights: Vec<Option<PointLightShadowState>>,
+    is_invalidated: bool,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct PointLightShadowState {
+    node_id: GameNodeId,
+    position: Vec3,
+    casters_fingerprint: u64,
+    shadow_layer: u32,
+    frames_since_update: u32,
+}
+
+/// What the renderer knows about a point light in the current frame
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct PointLightShadowInfo {
+    pub node_id: GameNodeId,
+    pub position: Vec3,
+    /// hash of the transforms and meshes of the casters in the light's frusta. skinned
+    /// casters change it every frame since their bones aren't part of it
+    pub casters_fingerprint: u64,
+    /// see PointShadowAtlas, a new layer has to be rendered before it's used
+    pub shadow_layer: u32,
+}
+
+impl Default for ShadowUpdates {
+    fn default() -> Self {
+        Self {
+            point_light_shadow_distance: 50.0,
+            full_rate_distance: 20.0,
+            distance_per_skipped_frame: 20.0,
+            max_update_interval: 4,
+            enable_static_caching: true,
+            lights: vec![],
+            is_invalidated: false,
+        }
+    }
+}
+
+impl ShadowUpdates {
+    /// Renders all the shadow maps again in the next frame. Needed when the geometry of a
+    /// caster changes without its node moving, e.g. with a DynamicMesh
+    pub fn invalidate(&mut self) {
+        self.is_invalidated = true;
+    }
+
+    /// Whether the shadow map of each light should be rendered this frame, None for the lights
+    /// whose node is missing or that have no shadow map
+    pub(crate) fn schedule(
+        &mut self,
+        lights: &[Option<PointLightShadowInfo>],
+        camera_position: Vec3,
+    ) -> Vec<bool> {
+        self.lights.resize(lights.len(), None);
+        let is_invalidated = std::mem::take(&mut self.is_invalidated);
+
+        lights
+            .iter()
+            .zip(self.lights.iter_mut())
+            .map(|(light, state)| {
+                let Some(light) = light else {
+                    *state = None;
+                    return false;
+                };
+                let needs_update = match state {
+                    Some(state)
+                        if state.node_id == light.node_id
+                            && state.shadow_layer == light.shadow_layer
+                            && !is_invalidated =>
+                    {
+                        let is_unchanged = self.enable_static_caching
+                            && state.position == light.position
+                            && state.casters_fingerprint == light.casters_fingerprint;
+                        let distance = light.position.distance(camera_position);
+                        let update_interval = 1
+                            + ((distance - self.full_rate_distance).max(0.0)
+                                / self.distance_per_skipped_frame.max(0.001))
+                                as u32;
+                        !is_unchanged
+                            && state.frames_since_update + 1
+                                >= update_interval.min(self.max_update_interval.max(1))
+                    }
+                    // new lights and the lights that took the slot of a removed one
+                    _ => true,
+                };
+
+                if needs_update {
+                    *state = Some(PointLightShadowState {
+                        node_id: light.node_id,
+                        position: light.position,
+                        casters_fingerprint: light.casters_fingerprint,
+                        shadow_layer: light.shadow_layer,
+                        frames_since_update: 0,
+                    });
+                } else if let Some(state) = state {
+                    state.frames_since_update += 1;
+                }
+                needs_update
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::{GameNodeDesc, Scene};
+
+    #[test]
+    fn unchanged_and_distant_lights_are_skipped() {
+        let mut scene = Scene::default();
+        let node_id = scene.add_node(GameNodeDesc::default()).id();
+        let mut shadow_updates = ShadowUpdates::default();
+        let near_light = PointLightShadowInfo {
+            node_id,
+            position: Vec3::ZERO,
+            casters_fingerprint: 1,
+            shadow_layer: 0,
+        };
+        let far_light = PointLightShadowInfo {
+            position: Vec3::new(100.0, 0.0, 0.0),
+            shadow_layer: 1,
+            ..near_light
+        };
+        let lights = [Some(near_light), Some(far_light)];
+
+        assert_eq!(shadow_updates.schedule(&lights, Vec3::ZERO), [true, true]);
+        assert_eq!(shadow_updates.schedule(&lights, Vec3::ZERO), [false, false]);
+
+        let moved_lights = lights.map(|light| {
+            light.map(|light| PointLightShadowInfo {
+                casters_fingerprint: 2,
+                ..light
+            })
+        });
+        assert_eq!(
+            shadow_updates.schedule(&moved_lights, Vec3::ZERO),
+            [true, false]
+        );
+        // the far light waits for max_update_interval frames
+        assert_eq!(
+            shadow_updates.schedule(&moved_lights, Vec3::ZERO),
+            [false, false]
+        );
+        assert_eq!(
+            shadow_updates.schedule(&moved_lights, Vec3::ZERO),
+            [false, true]
+        );
+
+        shadow_updates.invalidate();
+        assert_eq!(
+            shadow_updates.schedule(&moved_lights, Vec3::ZERO),
+            [true, true]
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use crate::animation::*;
+use crate::physics::*;
+use crate::scene::*;
+use crate::transform::*;
+
+use std::collections::VecDeque;
+
+/// Accumulates frame time and tells how many fixed steps should be run to catch up with it
+#[derive(Debug, Copy, Clone)]
+pub struct FixedTimestep {
+    pub timestep_seconds: f64,
+    /// steps beyond this in a single frame are dropped so a slow frame
+    /// doesn't cause even slower frames afterwards
+    pub max_steps_per_frame: u32,
+    accumulator_seconds: f64,
+    interpolation_alpha: f64,
+    tick: u64,
+}
+
+impl FixedTimestep {
+    pub fn new(timestep_seconds: f64) -> Self {
+        Self {
+            timestep_seconds,
+            max_steps_per_frame: 8,
+            accumulator_seconds: 0.0,
+            interpolation_alpha: 1.0,
+            tick: 0,
+        }
+    }
+
+    /// returns the number of steps that should be run. increment_tick must be called after each one
+    pub fn advance(&mut self, delta_time_seconds: f64) -> u32 {
+        self.accumulator_seconds += delta_time_seconds;
+
+        let mut step_count = 0;
+        while self.accumulator_seconds >= self.timestep_seconds {
+            if step_count == self.max_steps_per_frame {
+                self.accumulator_seconds %= self.timestep_seconds;
+                break;
+            }
+            self.accumulator_seconds -= self.timestep_seconds;
+            step_count += 1;
+        }
+
+        self.interpolation_alpha = self.accumulator_seconds / self.timestep_seconds;
+
+        step_count
+    }
+
+    /// fraction of a step that has elapsed since the last one, used to
+    /// interpolate between the last two simulation states when rendering
+    pub fn interpolation_alpha(&self) -> f64 {
+        self.interpolation_alpha
+    }
+
+    /// number of steps taken since the start
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn increment_tick(&mut self) {
+        self.tick += 1;
+    }
+
+    /// used when rolling back to a snapshot
+    pub fn set_tick(&mut self, tick: u64) {
+        self.tick = tick;
+    }
+}
+
+impl Default for FixedTimestep {
+    fn default() -> Self {
+        Self::new(1.0 / 60.0)
+    }
+}
+
+/// The parts of the scene that change while the simulation runs. Nodes that were
+/// added or removed since the snapshot was taken are left as they are when restoring
+#[derive(Debug, Clone)]
+pub struct SceneSnapshot {
+    node_transforms: Vec<(GameNodeId, Transform)>,
+    animation_states: Vec<AnimationState>,
+}
+
+impl SceneSnapshot {
+    pub fn new(scene: &Scene) -> Self {
+        Self {
+            node_transforms: scene
+                .nodes()
+                .map(|node| (node.id(), node.transform))
+                .collect(),
+            animation_states: scene
+                .animations
+                .iter()
+                .map(|animation| animation.state)
+                .collect(),
+        }
+    }
+
+    pub fn restore(&self, scene: &mut Scene) {
+        for (node_id, transform) in &self.node_transforms {
+            if let Some(node) = scene.get_node_mut(*node_id) {
+                node.transform = *transform;
+            }
+        }
+        for (animation, state) in scene.animations.iter_mut().zip(&self.animation_states) {
+            animation.state = *state;
+        }
+    }
+}
+
+/// State of the engine's simulation at the end of a fixed step. Games that need
+/// replays or rollback keep one of these alongside a snapshot of their own state.
+///
+/// Rapier is only deterministic between runs of the same build on the same platform,
+/// so rollback netcode between different machines shouldn't rely on resimulating physics
+#[derive(Clone)]
+pub struct SimulationSnapshot {
+    pub tick: u64,
+    pub scene: SceneSnapshot,
+    pub physics: PhysicsSnapshot,
+}
+
+/// Keeps the snapshots of the last few ticks, oldest first. T is usually a
+/// SimulationSnapshot paired with the game's own state
+#[derive(Debug, Clone)]
+pub struct SnapshotHistory<T> {
+    capacity: usize,
+    snapshots: VecDeque<(u64, T)>,
+}
+
+impl<T> SnapshotHistory<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            snapshots: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// snapshots of ticks at or after this one are discarded since they belong to another timeline
+    pub fn push(&mut self, tick: u64, snapshot: T) {
+        self.truncate_from(tick);
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back((tick, snapshot));
+    }
+
+    pub fn get(&self, tick: u64) -> Option<&T> {
+        self.snapshots
+            .iter()
+            .find(|(snapshot_tick, _)| *snapshot_tick == tick)
+            .map(|(_, snapshot)| snapshot)
+    }
+
+    pub fn latest(&self) -> Option<(u64, &T)> {
+        self.snapshots
+            .back()
+            .map(|(tick, snapshot)| (*tick, snapshot))
+    }
+
+    pub fn oldest_tick(&self) -> Option<u64> {
+        self.snapshots.front().map(|(tick, _)| *tick)
+    }
+
+    /// Drops the snapshots that come after tick and returns the one at tick, which is
+    /// the state to restore before resimulating, e.g. when a late network input arrives
+    pub fn rollback_to(&mut self, tick: u64) -> Option<&T> {
+        self.truncate_from(tick.saturating_add(1));
+        self.get(tick)
+    }
+
+    /// removes the snapshots of this tick and later ones
+    fn truncate_from(&mut self, tick: u64) {
+        while self
+            .snapshots
+            .back()
+            .is_some_and(|(snapshot_tick, _)| *snapshot_tick >= tick)
+        {
+            self.snapshots.pop_back();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+}
@@ -1,6 +1,6 @@
 use crate::scene::*;
 
-use std::collections::{hash_map::Entry, HashMap};
+use std::collections::HashMap;
 
 use glam::f32::Mat4;
 
@@ -32,62 +32,73 @@ pub fn get_all_bone_data(
     )
     .to_vec();
 
-    let mut animated_bone_transforms: Vec<AllBoneTransformsSlice> = Vec::new();
+    // (mesh_index, skin_index) of the visible skinned meshes
+    let skinned_meshes: Vec<(usize, usize)> = scene
+        .skins
+        .iter()
+        .filter_map(|skin| {
+            let skin_node = scene.get_node(skin.node_id)?;
+            let visual = skin_node.visual.as_ref()?;
+            Some((visual.mesh.index(), skin_node.skin_index.unwrap()))
+        })
+        .collect();
+
+    let mut unique_skin_indices: Vec<usize> = Vec::new();
+    for (_, skin_index) in &skinned_meshes {
+        if !unique_skin_indices.contains(skin_index) {
+            unique_skin_indices.push(*skin_index);
+        }
+    }
+
+    // the skins are independent from each other so their bones are computed on the job workers
+    let all_skin_bone_transforms: Vec<Vec<Mat4>> = {
+        profiling::scope!("Compute bone transforms");
+        crate::jobs::par_map(&unique_skin_indices, 1, |_, skin_index| {
+            let skin = &scene.skins[*skin_index];
+            skin.bone_node_ids
+                .iter()
+                .enumerate()
+                .map(|(bone_index, bone_node_id)| {
+                    get_bone_skeleton_space_transform(
+                        scene,
+                        skin,
+                        skin.node_id,
+                        bone_index,
+                        *bone_node_id,
+                    )
+                })
+                .collect()
+        })
+    };
+
     let mut skin_index_to_slice_map: HashMap<usize, (usize, usize)> = HashMap::new();
+    for (skin_index, bone_transforms) in unique_skin_indices
+        .iter()
+        .zip(all_skin_bone_transforms.iter())
+    {
+        let start_index = buffer.len();
+        let end_index = start_index + bone_transforms.len() * matrix_size_bytes;
+        buffer.extend_from_slice(bytemuck::cast_slice(bone_transforms));
+
+        // add padding
+        let needed_padding = min_storage_buffer_offset_alignment as usize
+            - (buffer.len() % min_storage_buffer_offset_alignment as usize);
+        buffer.resize(buffer.len() + needed_padding, 0);
+
+        skin_index_to_slice_map.insert(*skin_index, (start_index, end_index));
+    }
 
-    for skin in &scene.skins {
-        if let Some(visual) = &scene
-            .get_node(skin.node_id)
-            .and_then(|skin_node| skin_node.visual.as_ref())
-        {
-            let skin_index = scene.get_node(skin.node_id).unwrap().skin_index.unwrap();
-
-            match skin_index_to_slice_map.entry(skin_index) {
-                Entry::Occupied(entry) => {
-                    let (start_index, end_index) = *entry.get();
-                    animated_bone_transforms.push(AllBoneTransformsSlice {
-                        mesh_index: visual.mesh_index,
-                        start_index,
-                        end_index,
-                    });
-                }
-                Entry::Vacant(entry) => {
-                    let skin = &scene.skins[skin_index];
-                    let bone_transforms: Vec<_> = skin
-                        .bone_node_ids
-                        .iter()
-                        .enumerate()
-                        .map(|(bone_index, bone_node_id)| {
-                            get_bone_skeleton_space_transform(
-                                scene,
-                                skin,
-                                skin.node_id,
-                                bone_index,
-                                *bone_node_id,
-                            )
-                        })
-                        .collect();
-
-                    let start_index = buffer.len();
-                    let end_index = start_index + bone_transforms.len() * matrix_size_bytes;
-                    buffer.append(&mut bytemuck::cast_slice(&bone_transforms).to_vec());
-
-                    // add padding
-                    let needed_padding = min_storage_buffer_offset_alignment as usize
-                        - (buffer.len() % min_storage_buffer_offset_alignment as usize);
-                    let mut padding: Vec<_> = (0..needed_padding).map(|_| 0u8).collect();
-                    buffer.append(&mut padding);
-
-                    animated_bone_transforms.push(AllBoneTransformsSlice {
-                        mesh_index: visual.mesh_index,
-                        start_index,
-                        end_index,
-                    });
-                    entry.insert((start_index, end_index));
-                }
+    let animated_bone_transforms = skinned_meshes
+        .into_iter()
+        .map(|(mesh_index, skin_index)| {
+            let (start_index, end_index) = skin_index_to_slice_map[&skin_index];
+            AllBoneTransformsSlice {
+                mesh_index,
+                start_index,
+                end_index,
             }
-        }
-    }
+        })
+        .collect();
 
     AllBoneTransforms {
         buffer,
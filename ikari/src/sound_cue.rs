@@ -0,0 +1,201 @@
+use crate::time::Instant;
+
+use std::collections::HashMap;
+
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SoundCueClip {
+    pub sound_index: usize,
+    /// relative to the other clips of the cue
+    pub weight: f32,
+}
+
+/// A named sound event, e.g. "footstep", that plays one of its clips at random with a
+/// slightly different volume and pitch each time so that repeated sounds don't get tiring
+#[derive(Debug, Clone, PartialEq)]
+pub struct SoundCue {
+    pub clips: Vec<SoundCueClip>,
+    /// (min, max), multiplied with the clip's volume
+    pub volume_range: (f32, f32),
+    /// (min, max), 1 plays the clip at its normal speed
+    pub pitch_range: (f32, f32),
+    /// triggers that come sooner than this after the last one are ignored
+    pub cooldown_seconds: f32,
+    /// never pick the same clip twice in a row if there's another one
+    pub avoid_repeats: bool,
+}
+
+impl SoundCue {
+    pub fn new(clips: Vec<SoundCueClip>) -> Self {
+        Self {
+            clips,
+            volume_range: (1.0, 1.0),
+            pitch_range: (1.0, 1.0),
+            cooldown_seconds: 0.0,
+            avoid_repeats: true,
+        }
+    }
+}
+
+/// What a cue picked when it was triggered
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SoundCuePick {
+    pub sound_index: usize,
+    pub volume: f32,
+    pub pitch: f32,
+}
+
+#[derive(Debug)]
+struct SoundCueState {
+    cue: SoundCue,
+    last_trigger_time: Option<Instant>,
+    last_clip_index: Option<usize>,
+}
+
+/// The cues of the game, usually triggered through AudioManager::trigger_cue
+#[derive(Debug)]
+pub struct SoundCues {
+    cues: HashMap<String, SoundCueState>,
+    rng: SmallRng,
+}
+
+impl Default for SoundCues {
+    fn default() -> Self {
+        Self {
+            cues: HashMap::new(),
+            rng: SmallRng::from_entropy(),
+        }
+    }
+}
+
+impl SoundCues {
+    /// Replaces the cue if there's already one with that name
+    pub fn add(&mut self, name: &str, cue: SoundCue) {
+        self.cues.insert(
+            name.to_string(),
+            SoundCueState {
+                cue,
+                last_trigger_time: None,
+                last_clip_index: None,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.cues.remove(name);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SoundCue> {
+        self.cues.get(name).map(|state| &state.cue)
+    }
+
+    /// None if there's no such cue, it's cooling down or none of its clips can be picked
+    pub fn pick(&mut self, name: &str) -> Option<SoundCuePick> {
+        let Some(state) = self.cues.get_mut(name) else {
+            log::warn!("There's no sound cue named {name}");
+            return None;
+        };
+        let cue = &state.cue;
+
+        if let Some(last_trigger_time) = state.last_trigger_time {
+            if last_trigger_time.elapsed().as_secs_f32() < cue.cooldown_seconds {
+                return None;
+            }
+        }
+
+        let can_pick = |clip_index: usize| {
+            cue.clips[clip_index].weight > 0.0
+                && !(cue.avoid_repeats
+                    && cue.clips.len() > 1
+                    && state.last_clip_index == Some(clip_index))
+        };
+        let total_weight: f32 = (0..cue.clips.len())
+            .filter(|clip_index| can_pick(*clip_index))
+            .map(|clip_index| cue.clips[clip_index].weight)
+            .sum();
+        if total_weight <= 0.0 {
+            return None;
+        }
+
+        let mut remaining_weight = self.rng.gen_range(0.0..total_weight);
+        let mut picked_clip_index = None;
+        for clip_index in (0..cue.clips.len()).filter(|clip_index| can_pick(*clip_index)) {
+            picked_clip_index = Some(clip_index);
+            remaining_weight -= cue.clips[clip_index].weight;
+            if remaining_weight < 0.0 {
+                break;
+            }
+        }
+        let clip_index = picked_clip_index?;
+
+        let random_in = |rng: &mut SmallRng, (min, max): (f32, f32)| {
+            if max > min {
+                rng.gen_range(min..max)
+            } else {
+                min
+            }
+        };
+        let volume = random_in(&mut self.rng, cue.volume_range);
+        let pitch = random_in(&mut self.rng, cue.pitch_range);
+
+        state.last_trigger_time = Some(Instant::now());
+        state.last_clip_index = Some(clip_index);
+        Some(SoundCuePick {
+            sound_index: cue.clips[clip_index].sound_index,
+            volume,
+            pitch,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_follow_the_weights_and_the_cooldown() {
+        let mut sound_cues = SoundCues {
+            cues: HashMap::new(),
+            rng: SmallRng::seed_from_u64(0),
+        };
+        sound_cues.add(
+            "footstep",
+            SoundCue::new(vec![
+                SoundCueClip {
+                    sound_index: 0,
+                    weight: 1.0,
+                },
+                SoundCueClip {
+                    sound_index: 1,
+                    weight: 0.0,
+                },
+                SoundCueClip {
+                    sound_index: 2,
+                    weight: 3.0,
+                },
+            ]),
+        );
+
+        // the zero weight clip is never picked and repeats are avoided, so they alternate
+        let picks: Vec<_> = (0..4)
+            .map(|_| sound_cues.pick("footstep").unwrap().sound_index)
+            .collect();
+        assert!(picks == [0, 2, 0, 2] || picks == [2, 0, 2, 0]);
+
+        sound_cues.add(
+            "gunshot",
+            SoundCue {
+                cooldown_seconds: 60.0,
+                ..SoundCue::new(vec![SoundCueClip {
+                    sound_index: 3,
+                    weight: 1.0,
+                }])
+            },
+        );
+        assert!(sound_cues.pick("gunshot").is_some());
+        assert!(sound_cues.pick("gunshot").is_none());
+        assert!(sound_cues.pick("missing").is_none());
+    }
+}
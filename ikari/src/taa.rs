@@ -0,0 +1,312 @@
+use crate::renderer::{BaseRenderer, USE_LABELS};
+use crate::texture::Texture;
+
+use glam::f32::{Mat4, Vec2};
+use wgpu::util::DeviceExt;
+
+/// How much of the current frame goes into the history. Lower is smoother but leaves more
+/// ghosting behind moving objects
+const CURRENT_FRAME_WEIGHT: f32 = 0.1;
+/// The jitter cycles through this many points of the Halton (2, 3) sequence
+const JITTER_SAMPLE_COUNT: u32 = 8;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TaaConfigUniform {
+    current_to_previous_clip: [[f32; 4]; 4],
+    params: [f32; 4],
+}
+
+/// Temporal anti-aliasing. The main camera is jittered by a different subpixel offset every
+/// frame and the shaded image is blended with the previous frames, which are reprojected with
+/// the depth buffer. The history is clamped to the colors around each pixel so moving objects
+/// don't leave trails. Runs on the hdr image before bloom, lives in RendererData::taa
+pub struct TemporalAntiAliasing {
+    enabled: bool,
+    sample_index: u32,
+    /// the unjittered view projection of the last frame that went into the history
+    previous_view_proj: Option<Mat4>,
+    history_texture: Texture,
+    history_sampler: wgpu::Sampler,
+    config_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl TemporalAntiAliasing {
+    pub(crate) fn new(base: &BaseRenderer) -> Self {
+        let device = &base.device;
+
+        let texture_entry =
+            |binding: u32, sample_type: wgpu::TextureSampleType| wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type,
+                },
+                count: None,
+            };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0, wgpu::TextureSampleType::Float { filterable: true }),
+                texture_entry(1, wgpu::TextureSampleType::Float { filterable: true }),
+                texture_entry(2, wgpu::TextureSampleType::Depth),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: USE_LABELS.then_some("taa_bind_group_layout"),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: USE_LABELS.then_some("TAA shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/taa.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: USE_LABELS.then_some("TAA pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: USE_LABELS.then_some("TAA pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: wgpu::TextureFormat::Rgba16Float,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let history_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: USE_LABELS.then_some("TAA history sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let config_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: USE_LABELS.then_some("TAA config buffer"),
+            contents: bytemuck::cast_slice(&[TaaConfigUniform {
+                current_to_previous_clip: Mat4::IDENTITY.to_cols_array_2d(),
+                params: [0.0; 4],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        Self {
+            enabled: false,
+            sample_index: 0,
+            previous_view_proj: None,
+            history_texture: Texture::create_scaled_surface_texture(
+                base,
+                (1, 1),
+                1.0,
+                "taa_history_texture",
+            ),
+            history_sampler,
+            config_buffer,
+            bind_group_layout,
+            pipeline,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// The history starts over when it's turned back on
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.previous_view_proj = None;
+        }
+        self.enabled = enabled;
+    }
+
+    /// The subpixel offset of this frame, in pixels, added to RendererData::camera_jitter.
+    /// Zero when disabled
+    pub(crate) fn next_jitter(&mut self) -> Vec2 {
+        if !self.enabled {
+            return Vec2::ZERO;
+        }
+        self.sample_index = (self.sample_index + 1) % JITTER_SAMPLE_COUNT;
+        halton_jitter(self.sample_index)
+    }
+
+    /// view_proj is the main camera's without the jitter, jitter_clip_space the jitter of this
+    /// frame. The history starts over when the render resolution changed
+    pub(crate) fn update(
+        &mut self,
+        base: &BaseRenderer,
+        view_proj: Mat4,
+        jitter_clip_space: Vec2,
+        render_size: wgpu::Extent3d,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        if self.history_texture.size != render_size {
+            self.history_texture = Texture::create_scaled_surface_texture(
+                base,
+                (render_size.width, render_size.height),
+                1.0,
+                "taa_history_texture",
+            );
+            self.previous_view_proj = None;
+        }
+        let (current_to_previous_clip, is_history_valid) = match self.previous_view_proj {
+            Some(previous_view_proj) => (previous_view_proj * view_proj.inverse(), true),
+            None => (Mat4::IDENTITY, false),
+        };
+        base.queue.write_buffer(
+            &self.config_buffer,
+            0,
+            bytemuck::cast_slice(&[TaaConfigUniform {
+                current_to_previous_clip: current_to_previous_clip.to_cols_array_2d(),
+                params: [
+                    jitter_clip_space.x,
+                    jitter_clip_space.y,
+                    CURRENT_FRAME_WEIGHT,
+                    if is_history_valid { 0.0 } else { 1.0 },
+                ],
+            }]),
+        );
+        self.previous_view_proj = Some(view_proj);
+    }
+
+    /// Blends the shaded image with the history, scratch_texture must be the size of the shaded
+    /// image. The result ends up in both the shaded image and the history
+    pub(crate) fn render(
+        &self,
+        base: &BaseRenderer,
+        encoder: &mut wgpu::CommandEncoder,
+        shading_texture: &Texture,
+        depth_texture: &Texture,
+        scratch_texture: &Texture,
+    ) {
+        let bind_group = base.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&shading_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&self.history_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&depth_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&self.history_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: self.config_buffer.as_entire_binding(),
+                },
+            ],
+            label: USE_LABELS.then_some("taa_bind_group"),
+        });
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: USE_LABELS.then_some("TAA"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &scratch_texture.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+
+        for destination in [shading_texture, &self.history_texture] {
+            encoder.copy_texture_to_texture(
+                scratch_texture.texture.as_image_copy(),
+                destination.texture.as_image_copy(),
+                destination.size,
+            );
+        }
+    }
+
+    /// After the gpu device was recreated
+    pub(crate) fn recreate(&mut self, base: &BaseRenderer) {
+        let enabled = self.enabled;
+        *self = Self::new(base);
+        self.enabled = enabled;
+    }
+}
+
+/// In pixels, within half a pixel of the center
+fn halton_jitter(sample_index: u32) -> Vec2 {
+    // the first point of the sequence is skipped since it's 0
+    Vec2::new(halton(sample_index + 1, 2), halton(sample_index + 1, 3)) - Vec2::splat(0.5)
+}
+
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jitter_stays_within_the_pixel_and_cycles() {
+        assert_eq!(halton(1, 2), 0.5);
+        assert_eq!(halton(3, 2), 0.75);
+        assert!((halton(2, 3) - 2.0 / 3.0).abs() < 1e-6);
+
+        let samples: Vec<_> = (0..JITTER_SAMPLE_COUNT).map(halton_jitter).collect();
+        for (index, sample) in samples.iter().enumerate() {
+            assert!(sample.abs().max_element() < 0.5);
+            assert!(!samples[..index].contains(sample));
+        }
+    }
+}
@@ -0,0 +1,380 @@
+use anyhow::{anyhow, bail, Result};
+use glam::f32::{Vec2, Vec3};
+use lyon_tessellation::{
+    path::{iterator::PathIterator, Path, PathEvent},
+    BuffersBuilder, FillOptions, FillTessellator, FillVertex, VertexBuffers,
+};
+
+use crate::mesh::{BasicMesh, Vertex};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextAlignment {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct TextMeshParams {
+    /// height of an em in world units
+    pub size: f32,
+    /// how far the text is extruded along z, 0 for flat text
+    pub depth: f32,
+    pub alignment: TextAlignment,
+    /// multiplied by the line height of the font
+    pub line_spacing: f32,
+    /// how far the curves of the glyphs may be from the flattened outlines, in world units
+    pub tolerance: f32,
+    /// neighboring side faces with normals closer than this get smooth normals
+    pub smoothing_angle_degrees: f32,
+}
+
+impl Default for TextMeshParams {
+    fn default() -> Self {
+        Self {
+            size: 1.0,
+            depth: 0.2,
+            alignment: TextAlignment::Left,
+            line_spacing: 1.0,
+            tolerance: 0.005,
+            smoothing_angle_degrees: 30.0,
+        }
+    }
+}
+
+/// Triangulates the glyph outlines of a TTF/OTF font into an extruded mesh, for titles and
+/// signage that are part of the scene. The text faces +z and is centered on z = 0, with the
+/// baseline of the first line at y = 0. Bind it with Renderer::bind_basic_mesh
+pub fn make_text_mesh(font_bytes: &[u8], text: &str, params: &TextMeshParams) -> Result<BasicMesh> {
+    let face = ttf_parser::Face::parse(font_bytes, 0)?;
+    let scale = params.size / face.units_per_em() as f32;
+    let line_height =
+        (face.ascender() - face.descender() + face.line_gap()) as f32 * scale * params.line_spacing;
+    let tolerance = params.tolerance / scale;
+
+    let mut mesh_builder = TextMeshBuilder {
+        vertices: vec![],
+        indices: vec![],
+        half_depth: params.depth / 2.0,
+        size: params.size,
+        smoothing_cos: params.smoothing_angle_degrees.to_radians().cos(),
+    };
+    let mut tessellator = FillTessellator::new();
+
+    for (line_index, line) in text.lines().enumerate() {
+        let glyphs: Vec<_> = line
+            .chars()
+            .map(|character| {
+                face.glyph_index(character)
+                    .unwrap_or(ttf_parser::GlyphId(0))
+            })
+            .collect();
+        let line_width: f32 = glyphs
+            .iter()
+            .map(|glyph| face.glyph_hor_advance(*glyph).unwrap_or(0) as f32 * scale)
+            .sum();
+        let mut pen = Vec2::new(
+            match params.alignment {
+                TextAlignment::Left => 0.0,
+                TextAlignment::Center => -line_width / 2.0,
+                TextAlignment::Right => -line_width,
+            },
+            -(line_index as f32) * line_height,
+        );
+
+        for glyph in glyphs {
+            let mut outline_builder = GlyphOutlineBuilder {
+                path_builder: Path::builder(),
+            };
+            if face.outline_glyph(glyph, &mut outline_builder).is_some() {
+                let path = outline_builder.path_builder.build();
+                mesh_builder.add_glyph(&mut tessellator, &path, pen, scale, tolerance)?;
+            }
+            pen.x += face.glyph_hor_advance(glyph).unwrap_or(0) as f32 * scale;
+        }
+    }
+
+    if mesh_builder.vertices.len() > u16::MAX as usize {
+        bail!(
+            "Text mesh has {} vertices which is more than fits in 16 bit indices, split the text into several meshes",
+            mesh_builder.vertices.len()
+        );
+    }
+
+    Ok(BasicMesh {
+        vertices: mesh_builder.vertices,
+        indices: mesh_builder
+            .indices
+            .into_iter()
+            .map(|index| index as u16)
+            .collect(),
+    })
+}
+
+struct GlyphOutlineBuilder {
+    path_builder: lyon_tessellation::path::path::Builder,
+}
+
+impl ttf_parser::OutlineBuilder for GlyphOutlineBuilder {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.path_builder
+            .begin(lyon_tessellation::math::point(x, y));
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.path_builder
+            .line_to(lyon_tessellation::math::point(x, y));
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        self.path_builder.quadratic_bezier_to(
+            lyon_tessellation::math::point(x1, y1),
+            lyon_tessellation::math::point(x, y),
+        );
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        self.path_builder.cubic_bezier_to(
+            lyon_tessellation::math::point(x1, y1),
+            lyon_tessellation::math::point(x2, y2),
+            lyon_tessellation::math::point(x, y),
+        );
+    }
+
+    fn close(&mut self) {
+        self.path_builder.end(true);
+    }
+}
+
+struct TextMeshBuilder {
+    vertices: Vec<Vertex>,
+    indices: Vec<usize>,
+    half_depth: f32,
+    size: f32,
+    smoothing_cos: f32,
+}
+
+impl TextMeshBuilder {
+    fn add_glyph(
+        &mut self,
+        tessellator: &mut FillTessellator,
+        path: &Path,
+        pen: Vec2,
+        scale: f32,
+        tolerance: f32,
+    ) -> Result<()> {
+        let to_world = |x: f32, y: f32| pen + Vec2::new(x, y) * scale;
+
+        let mut fill: VertexBuffers<Vec2, usize> = VertexBuffers::new();
+        tessellator
+            .tessellate_path(
+                path,
+                &FillOptions::non_zero().with_tolerance(tolerance),
+                &mut BuffersBuilder::new(&mut fill, |vertex: FillVertex| {
+                    let position = vertex.position();
+                    to_world(position.x, position.y)
+                }),
+            )
+            .map_err(|err| anyhow!("Failed to tessellate glyph: {err:?}"))?;
+
+        // the front at +z, and the back facing the other way if the text has depth
+        let faces: &[(f32, bool)] = if self.half_depth > 0.0 {
+            &[(1.0, false), (-1.0, true)]
+        } else {
+            &[(1.0, false)]
+        };
+        for &(side, is_back) in faces {
+            let first_vertex = self.vertices.len();
+            for position in &fill.vertices {
+                self.vertices.push(Vertex {
+                    position: [position.x, position.y, side * self.half_depth],
+                    normal: [0.0, 0.0, side],
+                    // mirrored on the back so the texture reads the right way around from there
+                    tex_coords: [side * position.x / self.size, -position.y / self.size],
+                    tangent: [side, 0.0, 0.0],
+                    bitangent: [0.0, -1.0, 0.0],
+                    ..Default::default()
+                });
+            }
+            for triangle in fill.indices.chunks_exact(3) {
+                let [a, b, c] = [
+                    fill.vertices[triangle[0]],
+                    fill.vertices[triangle[1]],
+                    fill.vertices[triangle[2]],
+                ];
+                let is_ccw = (b - a).perp_dot(c - a) > 0.0;
+                let triangle = if is_ccw != is_back {
+                    [triangle[0], triangle[1], triangle[2]]
+                } else {
+                    [triangle[0], triangle[2], triangle[1]]
+                };
+                self.indices
+                    .extend(triangle.iter().map(|index| first_vertex + index));
+            }
+        }
+
+        if self.half_depth > 0.0 {
+            let contours = flattened_contours(path, tolerance, to_world);
+            // truetype outlines go clockwise around the solid parts and cff ones go
+            // counter-clockwise, the holes go the other way in both
+            let total_area: f32 = contours.iter().map(|contour| signed_area(contour)).sum();
+            for contour in &contours {
+                self.add_sides(contour, total_area < 0.0);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Extrudes the outline into quads. The normals are smoothed between neighboring edges
+    /// that are flatter than the smoothing angle so the curves look round
+    fn add_sides(&mut self, contour: &[Vec2], is_solid_on_right: bool) {
+        let edge_count = contour.len();
+        let edge_normals: Vec<Vec2> = (0..edge_count)
+            .map(|edge_index| {
+                let direction = (contour[(edge_index + 1) % edge_count] - contour[edge_index])
+                    .normalize_or_zero();
+                if is_solid_on_right {
+                    direction.perp()
+                } else {
+                    -direction.perp()
+                }
+            })
+            .collect();
+        let corner_normal = |edge_normal: Vec2, neighbor_normal: Vec2| {
+            if edge_normal.dot(neighbor_normal) >= self.smoothing_cos {
+                (edge_normal + neighbor_normal).normalize_or_zero()
+            } else {
+                edge_normal
+            }
+        };
+
+        let mut distance_along_contour = 0.0;
+        for edge_index in 0..edge_count {
+            let start = contour[edge_index];
+            let end = contour[(edge_index + 1) % edge_count];
+            let edge_length = start.distance(end);
+            let edge_normal = edge_normals[edge_index];
+            let start_normal = corner_normal(
+                edge_normal,
+                edge_normals[(edge_index + edge_count - 1) % edge_count],
+            );
+            let end_normal =
+                corner_normal(edge_normal, edge_normals[(edge_index + 1) % edge_count]);
+            let tangent = (end - start).normalize_or_zero().extend(0.0);
+
+            let first_vertex = self.vertices.len();
+            for (position, normal, u) in [
+                (start, start_normal, distance_along_contour),
+                (end, end_normal, distance_along_contour + edge_length),
+            ] {
+                for z in [self.half_depth, -self.half_depth] {
+                    self.vertices.push(Vertex {
+                        position: [position.x, position.y, z],
+                        normal: normal.extend(0.0).to_array(),
+                        tex_coords: [u / self.size, -z / self.size],
+                        tangent: tangent.to_array(),
+                        bitangent: Vec3::NEG_Z.to_array(),
+                        ..Default::default()
+                    });
+                }
+            }
+            // 0: start front, 1: start back, 2: end front, 3: end back
+            let quad = if is_solid_on_right {
+                [0, 2, 1, 1, 2, 3]
+            } else {
+                [0, 1, 2, 2, 1, 3]
+            };
+            self.indices
+                .extend(quad.iter().map(|index| first_vertex + index));
+            distance_along_contour += edge_length;
+        }
+    }
+}
+
+fn flattened_contours(
+    path: &Path,
+    tolerance: f32,
+    to_world: impl Fn(f32, f32) -> Vec2,
+) -> Vec<Vec<Vec2>> {
+    let mut contours = vec![];
+    let mut contour: Vec<Vec2> = vec![];
+    for event in path.iter().flattened(tolerance) {
+        match event {
+            PathEvent::Begin { at } => {
+                contour = vec![to_world(at.x, at.y)];
+            }
+            PathEvent::Line { to, .. } => {
+                let to = to_world(to.x, to.y);
+                if contour.last().map_or(true, |last| *last != to) {
+                    contour.push(to);
+                }
+            }
+            PathEvent::End { .. } => {
+                if contour.len() > 1 && contour.first() == contour.last() {
+                    contour.pop();
+                }
+                if contour.len() >= 3 {
+                    contours.push(std::mem::take(&mut contour));
+                }
+            }
+            _ => {}
+        }
+    }
+    contours
+}
+
+fn signed_area(contour: &[Vec2]) -> f32 {
+    (0..contour.len())
+        .map(|index| contour[index].perp_dot(contour[(index + 1) % contour.len()]))
+        .sum::<f32>()
+        / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn side_normals_point_out_of_the_glyph() {
+        let font_bytes = include_bytes!("../../example_game/src/fonts/Lato-Regular.ttf");
+        let params = TextMeshParams {
+            depth: 0.5,
+            ..Default::default()
+        };
+
+        let mesh = make_text_mesh(font_bytes, "o", &params).unwrap();
+
+        let side_vertices: Vec<_> = mesh
+            .vertices
+            .iter()
+            .filter(|vertex| vertex.normal[2] == 0.0)
+            .collect();
+        assert!(!side_vertices.is_empty());
+        let center = side_vertices
+            .iter()
+            .map(|vertex| Vec2::new(vertex.position[0], vertex.position[1]))
+            .sum::<Vec2>()
+            / side_vertices.len() as f32;
+        let distances: Vec<f32> = side_vertices
+            .iter()
+            .map(|vertex| Vec2::new(vertex.position[0], vertex.position[1]).distance(center))
+            .collect();
+        let middle_distance = (distances.iter().copied().fold(f32::MAX, f32::min)
+            + distances.iter().copied().fold(0.0, f32::max))
+            / 2.0;
+        for vertex in side_vertices {
+            let offset = Vec2::new(vertex.position[0], vertex.position[1]) - center;
+            let normal = Vec2::new(vertex.normal[0], vertex.normal[1]);
+            // the outside of the o faces away from the center and the hole faces into it
+            let is_outer = offset.length() > middle_distance;
+            assert_eq!(normal.dot(offset) > 0.0, is_outer);
+        }
+        for triangle in mesh.indices.chunks_exact(3) {
+            let [a, b, c] =
+                [0, 1, 2].map(|i| Vec3::from(mesh.vertices[triangle[i] as usize].position));
+            let normal = Vec3::from(mesh.vertices[triangle[0] as usize].normal);
+            assert!((b - a).cross(c - a).dot(normal) >= -1e-6);
+        }
+    }
+}
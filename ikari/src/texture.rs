@@ -1,12 +1,14 @@
 use std::collections::hash_map::Entry;
 
 use crate::camera::*;
+use crate::color_grading::Lut3d;
+use crate::procedural_sky::{ProceduralSky, ProceduralSkyUniform};
 use crate::renderer::BaseRenderer;
+use crate::renderer::Float16;
 use crate::renderer::RendererConstantData;
-use crate::renderer::FAR_PLANE_DISTANCE;
-use crate::renderer::NEAR_PLANE_DISTANCE;
 use crate::renderer::USE_LABELS;
 use crate::sampler_cache::*;
+use crate::sdf::SdfVolume;
 use crate::wasm_not_sync::WasmNotArc;
 
 use anyhow::*;
@@ -45,6 +47,72 @@ impl RawImage {
     }
 }
 
+impl RawImage {
+    /// Drops the top drop_count mip levels, halving the resolution each time. Images without baked
+    /// mips are downscaled on the cpu instead, which is only supported for rgba8 images.
+    /// Returns None if nothing could be dropped
+    pub fn drop_top_mips(&self, format: wgpu::TextureFormat, drop_count: u32) -> Option<RawImage> {
+        if drop_count == 0 || self.depth != 1 {
+            return None;
+        }
+
+        if self.mip_count <= 1 {
+            if !matches!(
+                format,
+                wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb
+            ) {
+                return None;
+            }
+            let divisor = 1u32 << drop_count.min(31);
+            let width = (self.width / divisor).max(1);
+            let height = (self.height / divisor).max(1);
+            if (width, height) == (self.width, self.height) {
+                return None;
+            }
+            let image = image::RgbaImage::from_raw(self.width, self.height, self.raw.clone())?;
+            return Some(
+                image::imageops::resize(
+                    &image,
+                    width,
+                    height,
+                    image::imageops::FilterType::Triangle,
+                )
+                .into(),
+            );
+        }
+
+        let (block_width, block_height) = format.block_dimensions();
+        let block_size = format.block_copy_size(None)?;
+
+        let mut width = self.width;
+        let mut height = self.height;
+        let mut dropped_count = 0;
+        let mut dropped_bytes = 0;
+        // keep at least one level, and the new top level must be made of whole blocks
+        while dropped_count < drop_count && dropped_count + 1 < self.mip_count {
+            let next_width = (width / 2).max(1);
+            let next_height = (height / 2).max(1);
+            if next_width % block_width != 0 || next_height % block_height != 0 {
+                break;
+            }
+            let blocks_per_row = (width + block_width - 1) / block_width;
+            let block_rows = (height + block_height - 1) / block_height;
+            dropped_bytes += (blocks_per_row * block_rows * block_size) as usize;
+            width = next_width;
+            height = next_height;
+            dropped_count += 1;
+        }
+
+        (dropped_count > 0 && dropped_bytes <= self.raw.len()).then(|| RawImage {
+            width,
+            height,
+            depth: 1,
+            mip_count: self.mip_count - dropped_count,
+            raw: self.raw[dropped_bytes..].to_vec(),
+        })
+    }
+}
+
 impl<P: image::Pixel<Subpixel = u8>> From<image::ImageBuffer<P, Vec<u8>>> for RawImage {
     fn from(value: image::ImageBuffer<P, Vec<u8>>) -> Self {
         Self {
@@ -349,6 +417,77 @@ impl Texture {
         Ok(result)
     }
 
+    /// A texture for Renderer::render_offscreen, format should be the renderer's framebuffer
+    /// format. It's rendered to through an srgb view like the window surface
+    pub fn create_render_target(
+        base_renderer: &BaseRenderer,
+        (width, height): (u32, u32),
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        };
+        let view_format = format.add_srgb_suffix();
+        let texture = base_renderer
+            .device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: USE_LABELS.then_some(label),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::COPY_SRC
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[view_format],
+            });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            format: Some(view_format),
+            ..Default::default()
+        });
+        let sampler_index = base_renderer
+            .sampler_cache
+            .lock()
+            .unwrap()
+            .get_sampler_index(&base_renderer.device, &SamplerDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            sampler_index,
+            size,
+        }
+    }
+
+    /// Reads back a render target, only the 8 bit rgba and bgra formats are supported
+    pub async fn to_rgba_image(&self, base_renderer: &BaseRenderer) -> Result<image::RgbaImage> {
+        let is_bgra = match self.texture.format().remove_srgb_suffix() {
+            wgpu::TextureFormat::Rgba8Unorm => false,
+            wgpu::TextureFormat::Bgra8Unorm => true,
+            format => bail!("Can't convert a texture of format {format:?} to an rgba image"),
+        };
+        let mut bytes = self
+            .to_bytes(base_renderer)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("Texture has no layers"))?;
+        // to_bytes reads all the mips one after the other, keep the first one
+        bytes.truncate((self.size.width * self.size.height * 4) as usize);
+        if is_bgra {
+            for pixel in bytes.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        image::RgbaImage::from_raw(self.size.width, self.size.height, bytes)
+            .ok_or_else(|| anyhow!("Texture bytes don't match its size"))
+    }
+
     pub fn create_scaled_surface_texture(
         base_renderer: &BaseRenderer,
         (width, height): (u32, u32),
@@ -370,6 +509,7 @@ impl Texture {
                 dimension: wgpu::TextureDimension::D2,
                 format: wgpu::TextureFormat::Rgba16Float,
                 usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC
                     | wgpu::TextureUsages::COPY_DST
                     | wgpu::TextureUsages::RENDER_ATTACHMENT,
                 view_formats: &[],
@@ -641,9 +781,107 @@ impl Texture {
         // make sure it's a multiple of 4 (cheap trick to support Bc7 compression)
         let size = ((((er_texture.size.width as f32 / 3.0) / 4.0).ceil() * 4.0) as u32).max(4);
 
+        let er_texture_bind_group;
+        {
+            let mut sampler_cache_guard = base_renderer.sampler_cache.lock().unwrap();
+            let sampler = sampler_cache_guard.get_sampler(
+                &base_renderer.device,
+                &SamplerDescriptor {
+                    address_mode_u: wgpu::AddressMode::ClampToEdge,
+                    address_mode_v: wgpu::AddressMode::ClampToEdge,
+                    address_mode_w: wgpu::AddressMode::ClampToEdge,
+                    mag_filter: wgpu::FilterMode::Linear,
+                    min_filter: wgpu::FilterMode::Linear,
+                    mipmap_filter: wgpu::FilterMode::Nearest,
+                    ..Default::default()
+                },
+            );
+            er_texture_bind_group =
+                base_renderer
+                    .device
+                    .create_bind_group(&wgpu::BindGroupDescriptor {
+                        layout: &renderer_constant_data.single_texture_bind_group_layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: wgpu::BindingResource::TextureView(&er_texture.view),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: wgpu::BindingResource::Sampler(sampler),
+                            },
+                        ],
+                        label: None,
+                    });
+        }
+
+        Ok(Self::render_cubemap(
+            base_renderer,
+            renderer_constant_data,
+            equirectangular_to_cubemap_pipeline,
+            &er_texture_bind_group,
+            size,
+            format,
+            label,
+        ))
+    }
+
+    /// Renders the sky into an hdr cubemap. The sun disk is left out of the ones that the
+    /// environment maps are made from since the directional light already lights the scene
+    pub fn create_cubemap_from_procedural_sky(
+        base_renderer: &BaseRenderer,
+        renderer_constant_data: &RendererConstantData,
+        label: Option<&str>,
+        sky: &ProceduralSky,
+        draw_sun_disk: bool,
+    ) -> Self {
+        let sky_buffer =
+            base_renderer
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: USE_LABELS.then_some("Procedural Sky Buffer"),
+                    contents: bytemuck::cast_slice(&[ProceduralSkyUniform::new(
+                        sky,
+                        draw_sun_disk,
+                    )]),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+        let sky_bind_group = base_renderer
+            .device
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &renderer_constant_data.single_uniform_bind_group_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: sky_buffer.as_entire_binding(),
+                }],
+                label: USE_LABELS.then_some("procedural_sky_bind_group"),
+            });
+
+        Self::render_cubemap(
+            base_renderer,
+            renderer_constant_data,
+            &renderer_constant_data.procedural_sky_pipeline,
+            &sky_bind_group,
+            sky.face_size.max(4),
+            wgpu::TextureFormat::Rgba16Float,
+            label,
+        )
+    }
+
+    /// Draws the skybox mesh once per face with a pipeline that uses the skybox shader's vs_main.
+    /// The input bind group goes in group 0 and the camera in group 1
+    fn render_cubemap(
+        base_renderer: &BaseRenderer,
+        renderer_constant_data: &RendererConstantData,
+        pipeline: &wgpu::RenderPipeline,
+        input_bind_group: &wgpu::BindGroup,
+        face_size: u32,
+        format: wgpu::TextureFormat,
+        label: Option<&str>,
+    ) -> Self {
         let size = wgpu::Extent3d {
-            width: size,
-            height: size,
+            width: face_size,
+            height: face_size,
             depth_or_array_layers: 6,
         };
 
@@ -684,8 +922,8 @@ impl Texture {
 
         let faces: Vec<_> = build_cubemap_face_camera_views(
             Vec3::new(0.0, 0.0, 0.0),
-            NEAR_PLANE_DISTANCE,
-            FAR_PLANE_DISTANCE,
+            DEFAULT_NEAR_PLANE_DISTANCE,
+            DEFAULT_FAR_PLANE_DISTANCE,
             true,
         )
         .iter()
@@ -709,43 +947,8 @@ impl Texture {
                 base_renderer
                     .device
                     .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                        label: USE_LABELS
-                            .then_some("create_cubemap_texture_from_equirectangular encoder"),
+                        label: USE_LABELS.then_some("render_cubemap encoder"),
                     });
-            let er_texture_bind_group;
-            {
-                let mut sampler_cache_guard = base_renderer.sampler_cache.lock().unwrap();
-                let sampler = sampler_cache_guard.get_sampler(
-                    &base_renderer.device,
-                    &SamplerDescriptor {
-                        address_mode_u: wgpu::AddressMode::ClampToEdge,
-                        address_mode_v: wgpu::AddressMode::ClampToEdge,
-                        address_mode_w: wgpu::AddressMode::ClampToEdge,
-                        mag_filter: wgpu::FilterMode::Linear,
-                        min_filter: wgpu::FilterMode::Linear,
-                        mipmap_filter: wgpu::FilterMode::Nearest,
-                        ..Default::default()
-                    },
-                );
-                er_texture_bind_group =
-                    base_renderer
-                        .device
-                        .create_bind_group(&wgpu::BindGroupDescriptor {
-                            layout: &renderer_constant_data.single_texture_bind_group_layout,
-                            entries: &[
-                                wgpu::BindGroupEntry {
-                                    binding: 0,
-                                    resource: wgpu::BindingResource::TextureView(&er_texture.view),
-                                },
-                                wgpu::BindGroupEntry {
-                                    binding: 1,
-                                    resource: wgpu::BindingResource::Sampler(sampler),
-                                },
-                            ],
-                            label: None,
-                        });
-            }
-
             base_renderer.queue.write_buffer(
                 &camera_buffer,
                 0,
@@ -766,8 +969,8 @@ impl Texture {
                     occlusion_query_set: None,
                     timestamp_writes: None,
                 });
-                rpass.set_pipeline(equirectangular_to_cubemap_pipeline);
-                rpass.set_bind_group(0, &er_texture_bind_group, &[]);
+                rpass.set_pipeline(pipeline);
+                rpass.set_bind_group(0, input_bind_group, &[]);
                 rpass.set_bind_group(1, &camera_bind_group, &[]);
                 rpass.set_vertex_buffer(
                     0,
@@ -821,12 +1024,12 @@ impl Texture {
                 },
             );
 
-        Ok(Self {
+        Self {
             texture: cubemap_texture,
             view,
             sampler_index,
             size,
-        })
+        }
     }
 
     /// Each image should have the same dimensions!
@@ -939,8 +1142,8 @@ impl Texture {
 
         let faces: Vec<_> = build_cubemap_face_camera_views(
             Vec3::new(0.0, 0.0, 0.0),
-            NEAR_PLANE_DISTANCE,
-            FAR_PLANE_DISTANCE,
+            DEFAULT_NEAR_PLANE_DISTANCE,
+            DEFAULT_FAR_PLANE_DISTANCE,
             true,
         )
         .iter()
@@ -1141,8 +1344,8 @@ impl Texture {
 
         let camera_projection_matrices = build_cubemap_face_camera_views(
             Vec3::new(0.0, 0.0, 0.0),
-            NEAR_PLANE_DISTANCE,
-            FAR_PLANE_DISTANCE,
+            DEFAULT_NEAR_PLANE_DISTANCE,
+            DEFAULT_FAR_PLANE_DISTANCE,
             true,
         );
 
@@ -1315,8 +1518,10 @@ impl Texture {
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
                 format: wgpu::TextureFormat::Rg16Float,
+                // COPY_SRC for the IblCache
                 usage: wgpu::TextureUsages::TEXTURE_BINDING
-                    | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::COPY_SRC,
                 view_formats: &[],
             });
 
@@ -1325,21 +1530,7 @@ impl Texture {
             ..Default::default()
         });
 
-        let sampler_index = base_renderer
-            .sampler_cache
-            .lock()
-            .unwrap()
-            .get_sampler_index(
-                &base_renderer.device,
-                &SamplerDescriptor {
-                    address_mode_u: wgpu::AddressMode::ClampToEdge,
-                    address_mode_v: wgpu::AddressMode::ClampToEdge,
-                    address_mode_w: wgpu::AddressMode::ClampToEdge,
-                    mag_filter: wgpu::FilterMode::Linear,
-                    min_filter: wgpu::FilterMode::Linear,
-                    ..Default::default()
-                },
-            );
+        let sampler_index = Self::brdf_lut_sampler_index(base_renderer);
 
         let mut encoder =
             base_renderer
@@ -1374,6 +1565,212 @@ impl Texture {
             size,
         }
     }
+
+    /// From a lut previously generated by create_brdf_lut
+    pub fn create_brdf_lut_from_image(base_renderer: &BaseRenderer, image: RawImageSlice) -> Self {
+        let size = wgpu::Extent3d {
+            width: image.width,
+            height: image.height,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = base_renderer.device.create_texture_with_data(
+            &base_renderer.queue,
+            &wgpu::TextureDescriptor {
+                label: USE_LABELS.then_some("Brdf Lut"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rg16Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            image.raw,
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler_index: Self::brdf_lut_sampler_index(base_renderer),
+            size,
+        }
+    }
+
+    fn brdf_lut_sampler_index(base_renderer: &BaseRenderer) -> usize {
+        base_renderer
+            .sampler_cache
+            .lock()
+            .unwrap()
+            .get_sampler_index(
+                &base_renderer.device,
+                &SamplerDescriptor {
+                    address_mode_u: wgpu::AddressMode::ClampToEdge,
+                    address_mode_v: wgpu::AddressMode::ClampToEdge,
+                    address_mode_w: wgpu::AddressMode::ClampToEdge,
+                    mag_filter: wgpu::FilterMode::Linear,
+                    min_filter: wgpu::FilterMode::Linear,
+                    ..Default::default()
+                },
+            )
+    }
+
+    /// None creates a 1x1x1 placeholder so the environment bind group always has something to bind
+    pub fn create_sdf_volume_texture(
+        base_renderer: &BaseRenderer,
+        volume: Option<&SdfVolume>,
+    ) -> Self {
+        let (width, height, depth) = volume.map(|volume| volume.dims).unwrap_or((1, 1, 1));
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: depth,
+        };
+
+        let texture = base_renderer
+            .device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: USE_LABELS.then_some("Sdf Volume"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D3,
+                format: wgpu::TextureFormat::R16Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            });
+
+        let distances: Vec<_> = match volume {
+            Some(volume) => volume
+                .distances
+                .iter()
+                .map(|distance| Float16(half::f16::from_f32(*distance)))
+                .collect(),
+            None => vec![Float16(half::f16::MAX)],
+        };
+
+        base_renderer.queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(&distances),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(width * 2),
+                rows_per_image: Some(height),
+            },
+            size,
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D3),
+            ..Default::default()
+        });
+
+        let sampler_index = base_renderer
+            .sampler_cache
+            .lock()
+            .unwrap()
+            .get_sampler_index(
+                &base_renderer.device,
+                &SamplerDescriptor {
+                    address_mode_u: wgpu::AddressMode::ClampToEdge,
+                    address_mode_v: wgpu::AddressMode::ClampToEdge,
+                    address_mode_w: wgpu::AddressMode::ClampToEdge,
+                    mag_filter: wgpu::FilterMode::Linear,
+                    min_filter: wgpu::FilterMode::Linear,
+                    ..Default::default()
+                },
+            );
+
+        Self {
+            texture,
+            view,
+            sampler_index,
+            size,
+        }
+    }
+
+    pub fn create_lut_texture(base_renderer: &BaseRenderer, lut: &Lut3d) -> Self {
+        let size = wgpu::Extent3d {
+            width: lut.size,
+            height: lut.size,
+            depth_or_array_layers: lut.size,
+        };
+
+        let texture = base_renderer
+            .device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: USE_LABELS.then_some("Color grading lut"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D3,
+                format: wgpu::TextureFormat::Rgba16Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            });
+
+        let texels: Vec<_> = lut
+            .data
+            .iter()
+            .flat_map(|[r, g, b]| [*r, *g, *b, 1.0])
+            .map(|channel| Float16(half::f16::from_f32(channel)))
+            .collect();
+
+        base_renderer.queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(&texels),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(lut.size * 8),
+                rows_per_image: Some(lut.size),
+            },
+            size,
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D3),
+            ..Default::default()
+        });
+
+        let sampler_index = base_renderer
+            .sampler_cache
+            .lock()
+            .unwrap()
+            .get_sampler_index(
+                &base_renderer.device,
+                &SamplerDescriptor {
+                    address_mode_u: wgpu::AddressMode::ClampToEdge,
+                    address_mode_v: wgpu::AddressMode::ClampToEdge,
+                    address_mode_w: wgpu::AddressMode::ClampToEdge,
+                    mag_filter: wgpu::FilterMode::Linear,
+                    min_filter: wgpu::FilterMode::Linear,
+                    ..Default::default()
+                },
+            );
+
+        Self {
+            texture,
+            view,
+            sampler_index,
+            size,
+        }
+    }
 }
 
 #[profiling::function]
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+
+use crate::time::*;
+
+/// The parts of the game that can be slowed down separately. The fixed update
+/// (physics, ai, triggers) always runs at the global time scale
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TimeChannel {
+    Animation,
+    /// particles and the animated materials in the shaders
+    Effects,
+    Player,
+    Scripts,
+}
+
+impl TimeChannel {
+    pub const ALL: [TimeChannel; 4] = [
+        TimeChannel::Animation,
+        TimeChannel::Effects,
+        TimeChannel::Player,
+        TimeChannel::Scripts,
+    ];
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ChannelTimeScale {
+    pub scale: f64,
+    /// e.g. so the player keeps moving in real time while the rest of the world is in
+    /// slow motion. The channel still stops while the game is paused
+    pub ignore_global_scale: bool,
+}
+
+impl Default for ChannelTimeScale {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            ignore_global_scale: false,
+        }
+    }
+}
+
+/// Slow motion and frame stepping for the game world. The game is paused through the
+/// GameStateStack, whenever its current state doesn't run the simulation. The UI and the camera
+/// keep using the real frame time from EngineState::time
+#[derive(Debug, Clone)]
+pub struct TimeControl {
+    time_scale: f64,
+    /// whether the GameStateStack was paused at the start of the frame
+    is_paused: bool,
+    pending_step_count: u32,
+    channel_scales: HashMap<TimeChannel, ChannelTimeScale>,
+    real_delta_seconds: f64,
+    /// set for the frames that were stepped while paused, to the length of a fixed step
+    step_delta_seconds: Option<f64>,
+    elapsed_seconds: HashMap<TimeChannel, f64>,
+}
+
+impl Default for TimeControl {
+    fn default() -> Self {
+        Self {
+            time_scale: 1.0,
+            is_paused: false,
+            pending_step_count: 0,
+            channel_scales: HashMap::new(),
+            real_delta_seconds: 0.0,
+            step_delta_seconds: None,
+            elapsed_seconds: HashMap::new(),
+        }
+    }
+}
+
+impl TimeControl {
+    pub fn time_scale(&self) -> f64 {
+        self.time_scale
+    }
+
+    /// 1 is real time, 0.25 is slow motion. Negative values are treated as 0
+    pub fn set_time_scale(&mut self, time_scale: f64) {
+        self.time_scale = time_scale.max(0.0);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.is_paused
+    }
+
+    /// Advances the paused game by a single fixed step on the next frame. Calling it several
+    /// times in a frame steps over several frames. The steps are dropped if the game isn't
+    /// paused by then
+    pub fn step_frame(&mut self) {
+        self.pending_step_count += 1;
+    }
+
+    /// Whether the game is paused but is being advanced by step_frame during this frame
+    pub fn is_stepping(&self) -> bool {
+        self.step_delta_seconds.is_some()
+    }
+
+    pub fn channel_scale(&self, channel: TimeChannel) -> ChannelTimeScale {
+        self.channel_scales
+            .get(&channel)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn set_channel_scale(&mut self, channel: TimeChannel, channel_scale: ChannelTimeScale) {
+        self.channel_scales.insert(channel, channel_scale);
+    }
+
+    /// Game time that passed during the last frame, for the fixed update
+    pub fn world_delta_seconds(&self) -> f64 {
+        match self.step_delta_seconds {
+            Some(step_delta_seconds) => step_delta_seconds,
+            None if self.is_paused => 0.0,
+            None => self.real_delta_seconds * self.time_scale,
+        }
+    }
+
+    pub fn delta_seconds(&self, channel: TimeChannel) -> f64 {
+        let channel_scale = self.channel_scale(channel);
+        let delta_seconds = if channel_scale.ignore_global_scale {
+            match self.step_delta_seconds {
+                Some(step_delta_seconds) => step_delta_seconds,
+                None if self.is_paused => 0.0,
+                None => self.real_delta_seconds,
+            }
+        } else {
+            self.world_delta_seconds()
+        };
+        delta_seconds * channel_scale.scale
+    }
+
+    /// Sum of the delta_seconds of the channel since the start
+    pub fn elapsed_seconds(&self, channel: TimeChannel) -> f64 {
+        self.elapsed_seconds.get(&channel).copied().unwrap_or(0.0)
+    }
+
+    pub(crate) fn on_frame_started(
+        &mut self,
+        real_frame_time: Duration,
+        fixed_step_seconds: f64,
+        is_paused: bool,
+    ) {
+        self.real_delta_seconds = real_frame_time.as_secs_f64();
+        self.is_paused = is_paused;
+        self.step_delta_seconds = None;
+        if !is_paused {
+            self.pending_step_count = 0;
+        } else if self.pending_step_count > 0 {
+            self.pending_step_count -= 1;
+            self.step_delta_seconds = Some(fixed_step_seconds);
+        }
+
+        for channel in TimeChannel::ALL {
+            let delta_seconds = self.delta_seconds(channel);
+            *self.elapsed_seconds.entry(channel).or_default() += delta_seconds;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn player_can_ignore_the_slow_motion() {
+        let frame_time = Duration::from_millis(100);
+        let mut time_control = TimeControl::default();
+        time_control.set_time_scale(0.25);
+        time_control.set_channel_scale(
+            TimeChannel::Player,
+            ChannelTimeScale {
+                scale: 1.0,
+                ignore_global_scale: true,
+            },
+        );
+
+        time_control.on_frame_started(frame_time, 1.0 / 60.0, false);
+        assert!((time_control.world_delta_seconds() - 0.025).abs() < 1e-9);
+        assert!((time_control.delta_seconds(TimeChannel::Animation) - 0.025).abs() < 1e-9);
+        assert!((time_control.delta_seconds(TimeChannel::Player) - 0.1).abs() < 1e-9);
+
+        time_control.step_frame();
+        time_control.on_frame_started(frame_time, 1.0 / 60.0, true);
+        assert!(time_control.is_stepping());
+        assert_eq!(time_control.delta_seconds(TimeChannel::Player), 1.0 / 60.0);
+        time_control.on_frame_started(frame_time, 1.0 / 60.0, true);
+        assert!(!time_control.is_stepping());
+        assert_eq!(time_control.world_delta_seconds(), 0.0);
+        assert_eq!(time_control.delta_seconds(TimeChannel::Player), 0.0);
+
+        // the steps requested while running are dropped
+        time_control.step_frame();
+        time_control.on_frame_started(frame_time, 1.0 / 60.0, false);
+        time_control.on_frame_started(frame_time, 1.0 / 60.0, true);
+        assert!(!time_control.is_stepping());
+    }
+}
@@ -0,0 +1,212 @@
+use crate::math::lerp_vec;
+use crate::renderer::{DirectionalLight, Renderer};
+
+use glam::f32::{Quat, Vec3};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TimeOfDayEvent {
+    Sunrise,
+    Sunset,
+    /// the clock went past midnight
+    NewDay,
+}
+
+/// How the sun, moon and environment look over the course of a day
+#[derive(Debug, Copy, Clone)]
+pub struct TimeOfDayParams {
+    /// real time that a whole day lasts
+    pub day_length_seconds: f32,
+    /// tilts the sun path towards the south (positive) or north, in degrees.
+    /// 0 makes the sun pass right overhead at noon
+    pub sun_path_tilt_degrees: f32,
+    /// the sun rises in this direction, on the horizontal plane
+    pub east: Vec3,
+    pub noon_sun_color: Vec3,
+    pub horizon_sun_color: Vec3,
+    pub sun_intensity: f32,
+    /// the moon is opposite to the sun and takes over the directional light at night
+    pub moon_color: Vec3,
+    pub moon_intensity: f32,
+    /// environment_intensity of the renderer at midnight, it's 1 during the day
+    pub night_environment_intensity: f32,
+    /// sun elevation in degrees over which the light, skybox and IBL transition between
+    /// day and night, centered on the horizon
+    pub twilight_degrees: f32,
+}
+
+impl Default for TimeOfDayParams {
+    fn default() -> Self {
+        Self {
+            day_length_seconds: 20.0 * 60.0,
+            sun_path_tilt_degrees: 30.0,
+            east: Vec3::X,
+            noon_sun_color: Vec3::new(1.0, 0.96, 0.9),
+            horizon_sun_color: Vec3::new(1.0, 0.5, 0.2),
+            sun_intensity: 1.0,
+            moon_color: Vec3::new(0.6, 0.7, 1.0),
+            moon_intensity: 0.08,
+            night_environment_intensity: 0.05,
+            twilight_degrees: 12.0,
+        }
+    }
+}
+
+/// A clock that animates a directional light along the sun path and fades the
+/// environment between a day skybox in SkyboxSlot::One and a night one in SkyboxSlot::Two.
+/// Gameplay can query the time with hours, is_night or daylight
+#[derive(Debug, Copy, Clone)]
+pub struct TimeOfDay {
+    pub params: TimeOfDayParams,
+    /// 0 to 24, 12 is noon
+    hours: f32,
+    pub is_paused: bool,
+}
+
+impl TimeOfDay {
+    pub fn new(params: TimeOfDayParams, hours: f32) -> Self {
+        Self {
+            params,
+            hours: hours.rem_euclid(24.0),
+            is_paused: false,
+        }
+    }
+
+    pub fn hours(&self) -> f32 {
+        self.hours
+    }
+
+    pub fn set_hours(&mut self, hours: f32) {
+        self.hours = hours.rem_euclid(24.0);
+    }
+
+    /// Advances the clock, returns the events that happened in that time in order
+    pub fn update(&mut self, delta_time_seconds: f32) -> Vec<TimeOfDayEvent> {
+        if self.is_paused || self.params.day_length_seconds <= 0.0 {
+            return vec![];
+        }
+        let mut events = vec![];
+        let mut remaining_hours = 24.0 * delta_time_seconds / self.params.day_length_seconds;
+        // a step that's longer than a day still reports each sunrise and sunset
+        while remaining_hours > 0.0 {
+            let step_hours = remaining_hours.min(1.0);
+            remaining_hours -= step_hours;
+
+            let was_night = self.is_night();
+            let new_hours = self.hours + step_hours;
+            self.hours = new_hours.rem_euclid(24.0);
+            if new_hours >= 24.0 {
+                events.push(TimeOfDayEvent::NewDay);
+            }
+            match (was_night, self.is_night()) {
+                (true, false) => events.push(TimeOfDayEvent::Sunrise),
+                (false, true) => events.push(TimeOfDayEvent::Sunset),
+                _ => {}
+            }
+        }
+        events
+    }
+
+    /// Unit vector pointing from the ground towards the sun
+    pub fn sun_direction(&self) -> Vec3 {
+        let east = Vec3::new(self.params.east.x, 0.0, self.params.east.z).normalize_or_zero();
+        let east = if east == Vec3::ZERO { Vec3::X } else { east };
+
+        // 6 am is sunrise, in the east. rotating around the east axis keeps
+        // sunrise and sunset on the horizon
+        let angle = (self.hours - 6.0) / 24.0 * std::f32::consts::TAU;
+        let sun_direction = east * angle.cos() + Vec3::Y * angle.sin();
+        Quat::from_axis_angle(east, self.params.sun_path_tilt_degrees.to_radians()) * sun_direction
+    }
+
+    /// In degrees, negative when the sun is under the horizon
+    pub fn sun_elevation_degrees(&self) -> f32 {
+        self.sun_direction().y.clamp(-1.0, 1.0).asin().to_degrees()
+    }
+
+    /// 1 in full daylight, 0 at night and in between during twilight
+    pub fn daylight(&self) -> f32 {
+        let half_twilight = (self.params.twilight_degrees * 0.5).max(f32::EPSILON);
+        ((self.sun_elevation_degrees() + half_twilight) / (2.0 * half_twilight)).clamp(0.0, 1.0)
+    }
+
+    pub fn is_night(&self) -> bool {
+        self.sun_elevation_degrees() < 0.0
+    }
+
+    /// The sun during the day, the moon at night. The shadow mapping config is kept
+    pub fn directional_light(&self, light: &DirectionalLight) -> DirectionalLight {
+        let sun_direction = self.sun_direction();
+        let daylight = self.daylight();
+        let params = &self.params;
+        if daylight > 0.0 {
+            // redder the closer the sun gets to the horizon
+            let height = sun_direction.y.max(0.0).sqrt();
+            DirectionalLight {
+                direction: -sun_direction,
+                color: lerp_vec(params.horizon_sun_color, params.noon_sun_color, height),
+                intensity: params.sun_intensity * daylight,
+                ..*light
+            }
+        } else {
+            let night = (-self.sun_elevation_degrees() / params.twilight_degrees).clamp(0.0, 1.0);
+            DirectionalLight {
+                direction: sun_direction,
+                color: params.moon_color,
+                intensity: params.moon_intensity * night,
+                ..*light
+            }
+        }
+    }
+
+    pub fn environment_intensity(&self) -> f32 {
+        let night_intensity = self.params.night_environment_intensity;
+        night_intensity + (1.0 - night_intensity) * self.daylight()
+    }
+
+    /// Updates the directional light at light_index and the renderer's skybox weights
+    /// and environment intensity
+    pub fn apply(
+        &self,
+        directional_lights: &mut [DirectionalLight],
+        light_index: usize,
+        renderer: &Renderer,
+    ) {
+        if let Some(light) = directional_lights.get_mut(light_index) {
+            *light = self.directional_light(light);
+        }
+        let daylight = self.daylight();
+        renderer.set_skybox_weights([daylight.max(0.001), (1.0 - daylight).max(0.001)]);
+        renderer.data.lock().unwrap().environment_intensity = self.environment_intensity();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sun_rises_and_sets_once_a_day() {
+        let mut time_of_day = TimeOfDay::new(
+            TimeOfDayParams {
+                day_length_seconds: 24.0,
+                ..Default::default()
+            },
+            0.0,
+        );
+        assert!(time_of_day.is_night());
+
+        let events = time_of_day.update(24.0);
+
+        assert_eq!(
+            events,
+            vec![
+                TimeOfDayEvent::Sunrise,
+                TimeOfDayEvent::Sunset,
+                TimeOfDayEvent::NewDay
+            ]
+        );
+        assert!(time_of_day.sun_direction().y < 0.0);
+        time_of_day.set_hours(12.0);
+        assert!(time_of_day.daylight() == 1.0);
+    }
+}
@@ -97,6 +97,17 @@ impl Transform {
         ));
     }
 
+    /// Lerps the position and scale and slerps the rotation. alpha = 0 gives self
+    pub fn lerp(&self, other: &Transform, alpha: f32) -> Transform {
+        let from = self.decompose();
+        let to = other.decompose();
+        Self(Affine3A::from_scale_rotation_translation(
+            from.scale.lerp(to.scale, alpha),
+            from.rotation.slerp(to.rotation, alpha),
+            from.position.lerp(to.position, alpha),
+        ))
+    }
+
     pub fn decompose(&self) -> SimpleTransform {
         let (scale, rotation, position) = self.to_scale_rotation_translation();
 
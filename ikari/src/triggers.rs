@@ -0,0 +1,293 @@
+use std::collections::HashSet;
+
+use crate::collisions::Plane;
+use crate::ecs::{Entity, SceneNode, Tags};
+use crate::engine_state::EngineState;
+use crate::health::DamageEvent;
+use crate::physics::rapier3d_f64::parry::transformation::convex_hull;
+use crate::physics::rapier3d_f64::prelude::*;
+
+use glam::f32::{Mat4, Vec3};
+
+/// In the local space of the trigger's node, so they follow its transform, scale included
+#[derive(Debug, Clone)]
+pub enum TriggerShape {
+    Box {
+        half_extents: Vec3,
+    },
+    Sphere {
+        radius: f32,
+    },
+    /// faces of the hull with their normals pointing inwards, see TriggerShape::convex
+    Convex {
+        planes: Vec<Plane>,
+    },
+}
+
+impl TriggerShape {
+    /// Returns None if the points don't enclose any volume
+    pub fn convex(points: &[Vec3]) -> Option<Self> {
+        let (vertices, triangles) = convex_hull(
+            &points
+                .iter()
+                .map(|point| point![point.x as f64, point.y as f64, point.z as f64])
+                .collect::<Vec<_>>(),
+        );
+        let vertices: Vec<_> = vertices
+            .iter()
+            .map(|vertex| Vec3::new(vertex.x as f32, vertex.y as f32, vertex.z as f32))
+            .collect();
+        if vertices.len() < 4 {
+            return None;
+        }
+        let center = vertices.iter().sum::<Vec3>() / vertices.len() as f32;
+
+        let planes = triangles
+            .iter()
+            .filter_map(|[a, b, c]| {
+                let a = vertices[*a as usize];
+                let normal = (vertices[*b as usize] - a).cross(vertices[*c as usize] - a);
+                if normal.length_squared() < f32::EPSILON {
+                    return None;
+                }
+                // the winding of the hull's triangles isn't relied on
+                let normal = if normal.dot(center - a) < 0.0 {
+                    -normal
+                } else {
+                    normal
+                };
+                Some(Plane::from_normal_and_point(normal, a))
+            })
+            .collect();
+        Some(Self::Convex { planes })
+    }
+
+    pub fn contains_local_point(&self, point: Vec3) -> bool {
+        match self {
+            Self::Box { half_extents } => point.abs().cmple(*half_extents).all(),
+            Self::Sphere { radius } => point.length_squared() <= radius * radius,
+            Self::Convex { planes } => planes
+                .iter()
+                .all(|plane| plane.normal.dot(point) + plane.d >= -f32::EPSILON),
+        }
+    }
+}
+
+/// Component of the entities that detect the ones whose SceneNode goes in and out of a volume,
+/// e.g. for doors, checkpoints or music transitions. The volume's entity needs a SceneNode too
+#[derive(Debug, Clone)]
+pub struct TriggerVolume {
+    pub shape: TriggerShape,
+    /// only the entities with this tag in their Tags component are detected
+    pub required_tag: Option<String>,
+    inside: HashSet<Entity>,
+}
+
+impl TriggerVolume {
+    pub fn new(shape: TriggerShape, required_tag: Option<&str>) -> Self {
+        Self {
+            shape,
+            required_tag: required_tag.map(|tag| tag.to_string()),
+            inside: HashSet::new(),
+        }
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.inside.contains(&entity)
+    }
+
+    pub fn entities_inside(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.inside.iter().copied()
+    }
+}
+
+/// Sends a DamageEvent every update to the entities inside the TriggerVolume of the same entity
+#[derive(Debug, Copy, Clone)]
+pub struct DamageZone {
+    pub damage_per_second: f32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TriggerEventKind {
+    Entered,
+    /// also sent when the entity inside is despawned or loses its tag
+    Exited,
+}
+
+/// Sent to EngineState::events by update_trigger_volumes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TriggerEvent {
+    pub kind: TriggerEventKind,
+    pub trigger: Entity,
+    pub entity: Entity,
+}
+
+/// System that checks which entities are inside each TriggerVolume, meant for
+/// SystemStage::FixedUpdate. Only the origin of the entities' nodes is tested
+#[profiling::function]
+pub fn update_trigger_volumes(engine_state: &mut EngineState) {
+    let delta_time_seconds = engine_state.simulation_timestep.timestep_seconds as f32;
+    let candidates: Vec<(Entity, Vec3)> = engine_state
+        .world
+        .query::<SceneNode>()
+        .filter(|(entity, _)| !engine_state.world.has::<TriggerVolume>(*entity))
+        .filter_map(|(entity, SceneNode(node_id))| {
+            engine_state.scene.get_node(*node_id)?;
+            Some((
+                entity,
+                engine_state
+                    .scene
+                    .get_global_transform_for_node(*node_id)
+                    .position(),
+            ))
+        })
+        .collect();
+
+    for trigger in engine_state.world.entities_with::<TriggerVolume>() {
+        let Some(SceneNode(trigger_node_id)) =
+            engine_state.world.get::<SceneNode>(trigger).copied()
+        else {
+            continue;
+        };
+        if engine_state.scene.get_node(trigger_node_id).is_none() {
+            continue;
+        }
+        let world_to_local = Mat4::from(
+            engine_state
+                .scene
+                .get_global_transform_for_node(trigger_node_id),
+        )
+        .inverse();
+        let damage_zone = engine_state.world.get::<DamageZone>(trigger).copied();
+
+        let Some(volume) = engine_state.world.get::<TriggerVolume>(trigger) else {
+            continue;
+        };
+        let now_inside: HashSet<Entity> = candidates
+            .iter()
+            .filter(|(entity, _)| match &volume.required_tag {
+                Some(tag) => engine_state
+                    .world
+                    .get::<Tags>(*entity)
+                    .map_or(false, |tags| tags.has(tag)),
+                None => true,
+            })
+            .filter(|(_, position)| {
+                volume
+                    .shape
+                    .contains_local_point(world_to_local.transform_point3(*position))
+            })
+            .map(|(entity, _)| *entity)
+            .collect();
+
+        let mut events = vec![];
+        events.extend(
+            volume
+                .inside
+                .difference(&now_inside)
+                .map(|entity| (TriggerEventKind::Exited, *entity)),
+        );
+        events.extend(
+            now_inside
+                .difference(&volume.inside)
+                .map(|entity| (TriggerEventKind::Entered, *entity)),
+        );
+        // sorted so the order doesn't depend on the hash set
+        events.sort_by_key(|(kind, entity)| (*kind == TriggerEventKind::Entered, *entity));
+        for (kind, entity) in events {
+            engine_state.events.send(TriggerEvent {
+                kind,
+                trigger,
+                entity,
+            });
+        }
+
+        if let Some(DamageZone { damage_per_second }) = damage_zone {
+            let mut targets: Vec<_> = now_inside.iter().copied().collect();
+            targets.sort();
+            for target in targets {
+                engine_state.events.send(DamageEvent {
+                    target,
+                    amount: damage_per_second * delta_time_seconds,
+                    position: None,
+                });
+            }
+        }
+
+        if let Some(volume) = engine_state.world.get_mut::<TriggerVolume>(trigger) {
+            volume.inside = now_inside;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::scene::GameNodeDescBuilder;
+    use crate::transform::TransformBuilder;
+
+    #[test]
+    fn entities_enter_and_exit_tagged_volumes() {
+        let mut engine_state = EngineState::new_headless();
+        let spawn_at = |engine_state: &mut EngineState, position: Vec3| {
+            let node = engine_state.scene.add_node(
+                GameNodeDescBuilder::new()
+                    .transform(TransformBuilder::new().position(position).build())
+                    .build(),
+            );
+            let node_id = node.id();
+            let entity = engine_state.world.spawn();
+            engine_state.world.insert(entity, SceneNode(node_id));
+            (entity, node_id)
+        };
+
+        let (trigger, _) = spawn_at(&mut engine_state, Vec3::ZERO);
+        engine_state.world.insert(
+            trigger,
+            TriggerVolume::new(TriggerShape::Sphere { radius: 1.0 }, Some("player")),
+        );
+        let (player, player_node_id) = spawn_at(&mut engine_state, Vec3::new(0.5, 0.0, 0.0));
+        engine_state.world.insert(player, Tags::new(&["player"]));
+        spawn_at(&mut engine_state, Vec3::ZERO);
+
+        update_trigger_volumes(&mut engine_state);
+        engine_state
+            .scene
+            .get_node_mut(player_node_id)
+            .unwrap()
+            .transform
+            .set_position(Vec3::new(2.0, 0.0, 0.0));
+        update_trigger_volumes(&mut engine_state);
+
+        assert_eq!(
+            engine_state.events.drain::<TriggerEvent>(),
+            vec![
+                TriggerEvent {
+                    kind: TriggerEventKind::Entered,
+                    trigger,
+                    entity: player,
+                },
+                TriggerEvent {
+                    kind: TriggerEventKind::Exited,
+                    trigger,
+                    entity: player,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn convex_shape_contains_its_inside() {
+        let shape = TriggerShape::convex(&[
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ])
+        .unwrap();
+
+        assert!(shape.contains_local_point(Vec3::new(0.2, 0.2, 0.2)));
+        assert!(!shape.contains_local_point(Vec3::new(0.6, 0.6, 0.6)));
+    }
+}
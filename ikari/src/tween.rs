@@ -0,0 +1,480 @@
+use crate::engine_state::EngineState;
+use crate::mesh::DynamicPbrParams;
+use crate::scene::{GameNodeId, Material, Scene};
+use crate::time_control::TimeChannel;
+
+use glam::f32::{Quat, Vec3, Vec4};
+
+/// See https://easings.net for what they look like
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Easing {
+    #[default]
+    Linear,
+    QuadIn,
+    QuadOut,
+    QuadInOut,
+    CubicIn,
+    CubicOut,
+    CubicInOut,
+    SineIn,
+    SineOut,
+    SineInOut,
+    ExpoIn,
+    ExpoOut,
+    /// overshoots a little at the start
+    BackIn,
+    /// overshoots a little at the end
+    BackOut,
+    ElasticOut,
+    BounceOut,
+}
+
+impl Easing {
+    /// t goes from 0 to 1, the result is 0 at t = 0 and 1 at t = 1
+    pub fn apply(self, t: f32) -> f32 {
+        use std::f32::consts::PI;
+
+        const BACK_OVERSHOOT: f32 = 1.70158;
+
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::QuadIn => t * t,
+            Easing::QuadOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::QuadInOut => {
+                if t < 0.5 {
+                    2.0 * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(2) / 2.0
+                }
+            }
+            Easing::CubicIn => t * t * t,
+            Easing::CubicOut => 1.0 - (1.0 - t).powi(3),
+            Easing::CubicInOut => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+                }
+            }
+            Easing::SineIn => 1.0 - (t * PI / 2.0).cos(),
+            Easing::SineOut => (t * PI / 2.0).sin(),
+            Easing::SineInOut => -((PI * t).cos() - 1.0) / 2.0,
+            Easing::ExpoIn => {
+                if t == 0.0 {
+                    0.0
+                } else {
+                    2.0f32.powf(10.0 * t - 10.0)
+                }
+            }
+            Easing::ExpoOut => {
+                if t == 1.0 {
+                    1.0
+                } else {
+                    1.0 - 2.0f32.powf(-10.0 * t)
+                }
+            }
+            Easing::BackIn => (BACK_OVERSHOOT + 1.0) * t * t * t - BACK_OVERSHOOT * t * t,
+            Easing::BackOut => {
+                let t = t - 1.0;
+                1.0 + (BACK_OVERSHOOT + 1.0) * t * t * t + BACK_OVERSHOOT * t * t
+            }
+            Easing::ElasticOut => {
+                if t == 0.0 || t == 1.0 {
+                    t
+                } else {
+                    2.0f32.powf(-10.0 * t) * ((t * 10.0 - 0.75) * (2.0 * PI / 3.0)).sin() + 1.0
+                }
+            }
+            Easing::BounceOut => {
+                const N: f32 = 7.5625;
+                const D: f32 = 2.75;
+                if t < 1.0 / D {
+                    N * t * t
+                } else if t < 2.0 / D {
+                    let t = t - 1.5 / D;
+                    N * t * t + 0.75
+                } else if t < 2.5 / D {
+                    let t = t - 2.25 / D;
+                    N * t * t + 0.9375
+                } else {
+                    let t = t - 2.625 / D;
+                    N * t * t + 0.984375
+                }
+            }
+        }
+    }
+}
+
+/// A property of a node along with the value it's animated to.
+/// The value it starts from is read from the node when the tween starts
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TweenProperty {
+    Position(Vec3),
+    Rotation(Quat),
+    Scale(Vec3),
+    /// of the node's material. Pbr materials without dynamic params start from white
+    Color(Vec4),
+    /// of the point light attached to the node
+    LightColor(Vec3),
+    LightIntensity(f32),
+}
+
+impl TweenProperty {
+    /// the current value of the same property, None if the node doesn't have it
+    fn read(&self, scene: &Scene, node_id: GameNodeId) -> Option<Self> {
+        let node = scene.get_node(node_id)?;
+        let point_light = || {
+            scene
+                .point_lights
+                .iter()
+                .find(|point_light| point_light.node_id == node_id)
+        };
+        Some(match self {
+            TweenProperty::Position(_) => TweenProperty::Position(node.transform.position()),
+            TweenProperty::Rotation(_) => TweenProperty::Rotation(node.transform.rotation()),
+            TweenProperty::Scale(_) => TweenProperty::Scale(node.transform.scale()),
+            TweenProperty::Color(_) => TweenProperty::Color(match node.visual.as_ref()?.material {
+                Material::Pbr {
+                    dynamic_pbr_params, ..
+                } => dynamic_pbr_params.unwrap_or_default().base_color_factor,
+                Material::Unlit { color } => color.extend(1.0),
+                Material::Transparent { color, .. } => color,
+                Material::Custom { .. } => return None,
+            }),
+            TweenProperty::LightColor(_) => TweenProperty::LightColor(point_light()?.color),
+            TweenProperty::LightIntensity(_) => {
+                TweenProperty::LightIntensity(point_light()?.intensity)
+            }
+        })
+    }
+
+    fn write(&self, scene: &mut Scene, node_id: GameNodeId) {
+        match *self {
+            TweenProperty::LightColor(color) => {
+                for point_light in &mut scene.point_lights {
+                    if point_light.node_id == node_id {
+                        point_light.color = color;
+                    }
+                }
+            }
+            TweenProperty::LightIntensity(intensity) => {
+                for point_light in &mut scene.point_lights {
+                    if point_light.node_id == node_id {
+                        point_light.intensity = intensity;
+                    }
+                }
+            }
+            _ => {
+                let Some(node) = scene.get_node_mut(node_id) else {
+                    return;
+                };
+                match *self {
+                    TweenProperty::Position(position) => node.transform.set_position(position),
+                    TweenProperty::Rotation(rotation) => node.transform.set_rotation(rotation),
+                    TweenProperty::Scale(scale) => node.transform.set_scale(scale),
+                    TweenProperty::Color(new_color) => {
+                        let Some(visual) = node.visual.as_mut() else {
+                            return;
+                        };
+                        match &mut visual.material {
+                            Material::Pbr {
+                                dynamic_pbr_params, ..
+                            } => {
+                                dynamic_pbr_params
+                                    .get_or_insert_with(DynamicPbrParams::default)
+                                    .base_color_factor = new_color;
+                            }
+                            Material::Unlit { color } => *color = new_color.truncate(),
+                            Material::Transparent { color, .. } => *color = new_color,
+                            Material::Custom { .. } => {}
+                        }
+                    }
+                    TweenProperty::LightColor(_) | TweenProperty::LightIntensity(_) => {}
+                }
+            }
+        }
+    }
+
+    /// self at alpha = 0, to at alpha = 1. Easings can make alpha go a bit past those
+    fn interpolate(&self, to: &Self, alpha: f32) -> Self {
+        match (*self, *to) {
+            (TweenProperty::Position(from), TweenProperty::Position(to)) => {
+                TweenProperty::Position(from.lerp(to, alpha))
+            }
+            (TweenProperty::Rotation(from), TweenProperty::Rotation(to)) => {
+                TweenProperty::Rotation(from.slerp(to, alpha))
+            }
+            (TweenProperty::Scale(from), TweenProperty::Scale(to)) => {
+                TweenProperty::Scale(from.lerp(to, alpha))
+            }
+            (TweenProperty::Color(from), TweenProperty::Color(to)) => {
+                TweenProperty::Color(from.lerp(to, alpha))
+            }
+            (TweenProperty::LightColor(from), TweenProperty::LightColor(to)) => {
+                TweenProperty::LightColor(from.lerp(to, alpha))
+            }
+            (TweenProperty::LightIntensity(from), TweenProperty::LightIntensity(to)) => {
+                TweenProperty::LightIntensity(from + (to - from) * alpha)
+            }
+            _ => *to,
+        }
+    }
+}
+
+pub type TweenCallback = Box<dyn FnOnce(&mut EngineState)>;
+
+/// Animates a property of a node, see Tweens::add
+pub struct Tween {
+    pub node_id: GameNodeId,
+    pub property: TweenProperty,
+    pub duration_seconds: f32,
+    pub delay_seconds: f32,
+    pub easing: Easing,
+    on_complete: Option<TweenCallback>,
+    next: Option<Box<Tween>>,
+}
+
+impl Tween {
+    pub fn new(node_id: GameNodeId, property: TweenProperty, duration_seconds: f32) -> Self {
+        Self {
+            node_id,
+            property,
+            duration_seconds,
+            delay_seconds: 0.0,
+            easing: Easing::default(),
+            on_complete: None,
+            next: None,
+        }
+    }
+
+    pub fn easing(mut self, easing: Easing) -> Self {
+        self.easing = easing;
+        self
+    }
+
+    pub fn delay(mut self, delay_seconds: f32) -> Self {
+        self.delay_seconds = delay_seconds;
+        self
+    }
+
+    /// called once the property reaches its value, before the next tween of the chain starts
+    pub fn on_complete(mut self, on_complete: impl FnOnce(&mut EngineState) + 'static) -> Self {
+        self.on_complete = Some(Box::new(on_complete));
+        self
+    }
+
+    /// Starts the other tween once this one and the ones chained to it are done
+    pub fn then(mut self, next: Tween) -> Self {
+        let mut last = &mut self;
+        while last.next.is_some() {
+            last = last.next.as_mut().unwrap();
+        }
+        last.next = Some(Box::new(next));
+        self
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TweenId(u64);
+
+struct RunningTween {
+    id: TweenId,
+    tween: Tween,
+    elapsed_seconds: f32,
+    /// read from the node when the delay is over
+    from: Option<TweenProperty>,
+    /// the previous tween of the chain completed and its callback has to run before this one
+    /// reads its start value
+    is_waiting_for_callback: bool,
+}
+
+/// The running tweens, updated by gameloop every frame with the animation time of
+/// EngineState::time_control so they follow the pause and the slow motion
+#[derive(Default)]
+pub struct Tweens {
+    running: Vec<RunningTween>,
+    next_id: u64,
+}
+
+impl Tweens {
+    pub fn add(&mut self, tween: Tween) -> TweenId {
+        let id = TweenId(self.next_id);
+        self.next_id += 1;
+        self.running.push(RunningTween {
+            id,
+            tween,
+            elapsed_seconds: 0.0,
+            from: None,
+            is_waiting_for_callback: false,
+        });
+        id
+    }
+
+    /// the property stays where it is and the callbacks of the chain aren't called
+    pub fn cancel(&mut self, id: TweenId) {
+        self.running.retain(|running| running.id != id);
+    }
+
+    /// e.g. before removing the node
+    pub fn cancel_node_tweens(&mut self, node_id: GameNodeId) {
+        self.running
+            .retain(|running| running.tween.node_id != node_id);
+    }
+
+    /// true until the last tween of the chain is done
+    pub fn is_running(&self, id: TweenId) -> bool {
+        self.running.iter().any(|running| running.id == id)
+    }
+
+    /// Returns the callbacks of the tweens that completed. A chain stops after a tween with a
+    /// callback until the callback ran, then advancing with None picks up only those chains
+    fn advance(
+        &mut self,
+        scene: &mut Scene,
+        delta_time_seconds: Option<f32>,
+    ) -> Vec<TweenCallback> {
+        let mut callbacks = vec![];
+        let mut index = 0;
+        while index < self.running.len() {
+            let running = &mut self.running[index];
+            match delta_time_seconds {
+                Some(delta_time_seconds) => running.elapsed_seconds += delta_time_seconds,
+                None if !running.is_waiting_for_callback => {
+                    index += 1;
+                    continue;
+                }
+                None => {}
+            }
+            running.is_waiting_for_callback = false;
+
+            let mut is_done = false;
+            loop {
+                let tween = &mut running.tween;
+                let active_seconds = running.elapsed_seconds - tween.delay_seconds;
+                if active_seconds < 0.0 {
+                    break;
+                }
+                if scene.get_node(tween.node_id).is_none() {
+                    is_done = true;
+                    break;
+                }
+                let from = *running.from.get_or_insert_with(|| {
+                    tween
+                        .property
+                        .read(scene, tween.node_id)
+                        .unwrap_or(tween.property)
+                });
+                let t = if tween.duration_seconds > 0.0 {
+                    (active_seconds / tween.duration_seconds).min(1.0)
+                } else {
+                    1.0
+                };
+                from.interpolate(&tween.property, tween.easing.apply(t))
+                    .write(scene, tween.node_id);
+                if t < 1.0 {
+                    break;
+                }
+
+                let on_complete = tween.on_complete.take();
+                let has_callback = on_complete.is_some();
+                callbacks.extend(on_complete);
+                match tween.next.take() {
+                    Some(next) => {
+                        // the time past the end carries over to the next one
+                        running.elapsed_seconds = active_seconds - tween.duration_seconds.max(0.0);
+                        running.tween = *next;
+                        running.from = None;
+                        if has_callback {
+                            running.is_waiting_for_callback = true;
+                            break;
+                        }
+                    }
+                    None => {
+                        is_done = true;
+                        break;
+                    }
+                }
+            }
+
+            if is_done {
+                self.running.remove(index);
+            } else {
+                index += 1;
+            }
+        }
+        callbacks
+    }
+}
+
+/// Called by gameloop before the update systems
+#[profiling::function]
+pub(crate) fn update_tweens(engine_state: &mut EngineState) {
+    let delta_time_seconds = engine_state
+        .time_control
+        .delta_seconds(TimeChannel::Animation) as f32;
+    let mut callbacks = engine_state
+        .tweens
+        .advance(&mut engine_state.scene, Some(delta_time_seconds));
+    while !callbacks.is_empty() {
+        for callback in callbacks {
+            callback(engine_state);
+        }
+        callbacks = engine_state.tweens.advance(&mut engine_state.scene, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::GameNodeDesc;
+
+    #[test]
+    fn chained_tweens_run_one_after_the_other() {
+        let mut scene = Scene::new(vec![], vec![], vec![]);
+        let node_id = scene.add_node(GameNodeDesc::default()).id();
+        let mut tweens = Tweens::default();
+        let id = tweens.add(
+            Tween::new(
+                node_id,
+                TweenProperty::Position(Vec3::new(4.0, 0.0, 0.0)),
+                1.0,
+            )
+            .delay(0.5)
+            .on_complete(|_| {})
+            .then(Tween::new(
+                node_id,
+                TweenProperty::Scale(Vec3::splat(3.0)),
+                1.0,
+            )),
+        );
+        let position = |scene: &Scene| scene.get_node(node_id).unwrap().transform.position();
+
+        assert!(tweens.advance(&mut scene, Some(0.25)).is_empty());
+        assert_eq!(position(&scene), Vec3::ZERO);
+        tweens.advance(&mut scene, Some(0.5));
+        assert_eq!(position(&scene), Vec3::new(1.0, 0.0, 0.0));
+
+        let callbacks = tweens.advance(&mut scene, Some(1.25));
+        assert_eq!(callbacks.len(), 1);
+        assert_eq!(position(&scene), Vec3::new(4.0, 0.0, 0.0));
+        assert_eq!(
+            scene.get_node(node_id).unwrap().transform.scale(),
+            Vec3::ONE
+        );
+
+        // the next tween starts after the callback, from what it left the node at
+        scene
+            .get_node_mut(node_id)
+            .unwrap()
+            .transform
+            .set_scale(Vec3::splat(5.0));
+        assert!(tweens.advance(&mut scene, None).is_empty());
+        let scale = scene.get_node(node_id).unwrap().transform.scale();
+        assert!(scale.distance(Vec3::splat(4.0)) < 1e-5, "{scale}");
+
+        tweens.advance(&mut scene, Some(1.0));
+        assert!(!tweens.is_running(id));
+        assert_eq!(Easing::BounceOut.apply(1.0), 1.0);
+    }
+}
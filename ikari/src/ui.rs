@@ -28,6 +28,8 @@ where
     last_cursor_icon: Option<winit::window::CursorIcon>,
     theme: UiOverlay::Theme,
     surface_format: wgpu::TextureFormat,
+    settings: iced_wgpu::Settings,
+    fonts: Vec<&'static [u8]>,
 }
 
 impl<UiOverlay> IkariUiContainer<UiOverlay>
@@ -67,21 +69,8 @@ where
         let surface_format = surface_format.add_srgb_suffix();
 
         let mut debug = runtime::Debug::new();
-        let wgpu_renderer = iced_wgpu::Renderer::new(
-            iced_wgpu::Backend::new(device, queue, settings, surface_format),
-            settings.default_font,
-            settings.default_text_size,
-        );
-
-        let mut renderer = iced::Renderer::Wgpu(wgpu_renderer);
-
-        {
-            use iced_wgpu::core::text::Renderer;
-
-            for font_bytes in load_fonts {
-                renderer.load_font(Cow::from(font_bytes));
-            }
-        }
+        let mut renderer =
+            Self::make_renderer(device, queue, settings, surface_format, &load_fonts);
 
         let program_container = iced_winit::runtime::program::State::new(
             state,
@@ -104,9 +93,48 @@ where
             last_cursor_icon: None,
             theme,
             surface_format,
+            settings,
+            fonts: load_fonts,
         }
     }
 
+    fn make_renderer(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        settings: iced_wgpu::Settings,
+        surface_format: wgpu::TextureFormat,
+        fonts: &[&'static [u8]],
+    ) -> iced::Renderer {
+        let wgpu_renderer = iced_wgpu::Renderer::new(
+            iced_wgpu::Backend::new(device, queue, settings, surface_format),
+            settings.default_font,
+            settings.default_text_size,
+        );
+
+        let mut renderer = iced::Renderer::Wgpu(wgpu_renderer);
+
+        {
+            use iced_wgpu::core::text::Renderer;
+
+            for font_bytes in fonts {
+                renderer.load_font(Cow::from(*font_bytes));
+            }
+        }
+
+        renderer
+    }
+
+    /// Needed after the gpu device was recreated, the state of the ui is kept
+    pub fn recreate_renderer(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.renderer = Self::make_renderer(
+            device,
+            queue,
+            self.settings,
+            self.surface_format,
+            &self.fonts,
+        );
+    }
+
     pub fn resize(&mut self, framebuffer_size: winit::dpi::PhysicalSize<u32>, scale_factor: f64) {
         self.viewport = Viewport::with_physical_size(
             Size::new(framebuffer_size.width, framebuffer_size.height),
@@ -195,6 +223,12 @@ where
     pub fn get_state(&self) -> &UiOverlay {
         self.program_container.program()
     }
+
+    /// whether the cursor is over an interactive widget, e.g. to know if a click was meant
+    /// for the game
+    pub fn is_cursor_over_widget(&self) -> bool {
+        self.program_container.mouse_interaction() != iced::mouse::Interaction::Idle
+    }
 }
 
 impl runtime::Program for EmptyUiOverlay {
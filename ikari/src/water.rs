@@ -0,0 +1,640 @@
+use crate::custom_material::{CustomMaterial, CustomMaterialDataId, CustomMaterialId};
+use crate::physics::rapier3d_f64::prelude::*;
+use crate::physics::*;
+use crate::renderer::*;
+use crate::scene::Material;
+use crate::texture::*;
+use crate::time::*;
+
+use anyhow::Result;
+use glam::f32::{Vec2, Vec3, Vec4};
+use wgpu::util::DeviceExt;
+
+const MAX_RIPPLE_DROPS: usize = 16;
+const RIPPLE_STATE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+/// the wave equation is only stable at a fixed step size, so the simulation runs at a fixed rate
+const RIPPLE_STEP_RATE_HZ: f32 = 60.0;
+/// avoids spiraling if the game stalls for a while
+const MAX_RIPPLE_STEPS_PER_UPDATE: usize = 4;
+
+/// Flat, axis-aligned area of water at a fixed height
+#[derive(Debug, Copy, Clone)]
+pub struct WaterSurface {
+    pub height: f32,
+    pub min_xz: Vec2,
+    pub max_xz: Vec2,
+}
+
+impl WaterSurface {
+    pub fn contains_xz(&self, position: Vec3) -> bool {
+        position.x >= self.min_xz.x
+            && position.x <= self.max_xz.x
+            && position.z >= self.min_xz.y
+            && position.z <= self.max_xz.y
+    }
+
+    /// maps a world position to the [0, 1] range of the ripple heightfield
+    pub fn position_to_uv(&self, position: Vec3) -> Vec2 {
+        (Vec2::new(position.x, position.z) - self.min_xz) / (self.max_xz - self.min_xz)
+    }
+
+    pub fn size(&self) -> Vec2 {
+        self.max_xz - self.min_xz
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct RippleDrop {
+    pub uv: Vec2,
+    pub radius_uv: f32,
+    pub strength: f32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WaterSplashKind {
+    /// the object fell into the water
+    Entry,
+    /// the object jumped or was thrown out of the water
+    Exit,
+}
+
+/// An object crossed the water surface fast enough to splash, games can react to this by
+/// spawning splash particles and playing sounds
+#[derive(Debug, Copy, Clone)]
+pub struct WaterSplashEvent {
+    pub kind: WaterSplashKind,
+    pub rigid_body_handle: RigidBodyHandle,
+    /// where the object crossed the water surface
+    pub position: Vec3,
+    /// vertical speed through the surface, in m/s
+    pub impact_speed: f32,
+    pub radius: f32,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct WaterInteractionParams {
+    /// entries and exits slower than this don't produce splash events, but still make ripples
+    pub min_splash_speed: f32,
+    /// ripple strength per m/s of impact speed
+    pub impact_ripple_strength: f32,
+    /// ripple strength per m/s of horizontal speed, for objects moving along the surface
+    pub wake_ripple_strength: f32,
+    pub max_ripple_strength: f32,
+}
+
+impl Default for WaterInteractionParams {
+    fn default() -> Self {
+        Self {
+            min_splash_speed: 1.0,
+            impact_ripple_strength: 0.05,
+            wake_ripple_strength: 0.01,
+            max_ripple_strength: 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct TrackedBody {
+    rigid_body_handle: RigidBodyHandle,
+    radius: f32,
+    was_touching_water: bool,
+    distance_since_last_wake_drop: f32,
+}
+
+/// Watches rigid bodies and turns their movement through a water surface into
+/// splash events and ripple drops for a WaterRippleSimulation
+#[derive(Debug)]
+pub struct WaterInteractionSystem {
+    pub surface: WaterSurface,
+    pub params: WaterInteractionParams,
+    tracked_bodies: Vec<TrackedBody>,
+    pending_drops: Vec<RippleDrop>,
+}
+
+impl WaterInteractionSystem {
+    pub fn new(surface: WaterSurface) -> Self {
+        Self {
+            surface,
+            params: Default::default(),
+            tracked_bodies: vec![],
+            pending_drops: vec![],
+        }
+    }
+
+    /// the radius is taken from the bounding sphere of the body's first collider
+    pub fn track_rigid_body(&mut self, physics_state: &PhysicsState, handle: RigidBodyHandle) {
+        let radius = physics_state
+            .rigid_body_set
+            .get(handle)
+            .and_then(|rigid_body| rigid_body.colliders().first())
+            .and_then(|collider_handle| physics_state.collider_set.get(*collider_handle))
+            .map(|collider| collider.shape().compute_local_bounding_sphere().radius as f32)
+            .unwrap_or(0.5);
+
+        self.tracked_bodies.push(TrackedBody {
+            rigid_body_handle: handle,
+            radius,
+            was_touching_water: false,
+            distance_since_last_wake_drop: 0.0,
+        });
+    }
+
+    pub fn is_tracking_rigid_body(&self, handle: RigidBodyHandle) -> bool {
+        self.tracked_bodies
+            .iter()
+            .any(|tracked_body| tracked_body.rigid_body_handle == handle)
+    }
+
+    pub fn untrack_rigid_body(&mut self, handle: RigidBodyHandle) {
+        self.tracked_bodies
+            .retain(|tracked_body| tracked_body.rigid_body_handle != handle);
+    }
+
+    /// call after stepping the physics. bodies that were removed from the physics world are untracked
+    #[profiling::function]
+    pub fn update(
+        &mut self,
+        physics_state: &PhysicsState,
+        delta_time_seconds: f32,
+    ) -> Vec<WaterSplashEvent> {
+        let mut splash_events = vec![];
+        let surface = self.surface;
+        let params = self.params;
+        let surface_size = surface.size().max_element();
+
+        self.tracked_bodies.retain_mut(|tracked_body| {
+            let Some(rigid_body) = physics_state
+                .rigid_body_set
+                .get(tracked_body.rigid_body_handle)
+            else {
+                return false;
+            };
+
+            let translation = rigid_body.translation();
+            let position = Vec3::new(
+                translation.x as f32,
+                translation.y as f32,
+                translation.z as f32,
+            );
+            let linvel = rigid_body.linvel();
+            let velocity = Vec3::new(linvel.x as f32, linvel.y as f32, linvel.z as f32);
+
+            let is_touching_water = surface.contains_xz(position)
+                && position.y - tracked_body.radius < surface.height
+                && position.y + tracked_body.radius > surface.height;
+            let surface_position = Vec3::new(position.x, surface.height, position.z);
+            let radius_uv = tracked_body.radius / surface_size;
+
+            if is_touching_water && !tracked_body.was_touching_water {
+                let impact_speed = (-velocity.y).max(0.0);
+                self.pending_drops.push(RippleDrop {
+                    uv: surface.position_to_uv(surface_position),
+                    radius_uv,
+                    strength: (impact_speed * params.impact_ripple_strength)
+                        .min(params.max_ripple_strength),
+                });
+                if impact_speed >= params.min_splash_speed {
+                    splash_events.push(WaterSplashEvent {
+                        kind: WaterSplashKind::Entry,
+                        rigid_body_handle: tracked_body.rigid_body_handle,
+                        position: surface_position,
+                        impact_speed,
+                        radius: tracked_body.radius,
+                    });
+                }
+                tracked_body.distance_since_last_wake_drop = 0.0;
+            } else if !is_touching_water
+                && tracked_body.was_touching_water
+                && position.y > surface.height
+            {
+                let exit_speed = velocity.y.max(0.0);
+                self.pending_drops.push(RippleDrop {
+                    uv: surface.position_to_uv(surface_position),
+                    radius_uv,
+                    strength: (exit_speed * params.impact_ripple_strength)
+                        .min(params.max_ripple_strength),
+                });
+                if exit_speed >= params.min_splash_speed {
+                    splash_events.push(WaterSplashEvent {
+                        kind: WaterSplashKind::Exit,
+                        rigid_body_handle: tracked_body.rigid_body_handle,
+                        position: surface_position,
+                        impact_speed: exit_speed,
+                        radius: tracked_body.radius,
+                    });
+                }
+            } else if is_touching_water {
+                // leave a wake, one drop per radius travelled
+                let horizontal_speed = Vec2::new(velocity.x, velocity.z).length();
+                tracked_body.distance_since_last_wake_drop += horizontal_speed * delta_time_seconds;
+                if tracked_body.distance_since_last_wake_drop >= tracked_body.radius {
+                    tracked_body.distance_since_last_wake_drop = 0.0;
+                    self.pending_drops.push(RippleDrop {
+                        uv: surface.position_to_uv(surface_position),
+                        radius_uv,
+                        strength: (horizontal_speed * params.wake_ripple_strength)
+                            .min(params.max_ripple_strength),
+                    });
+                }
+            }
+
+            tracked_body.was_touching_water = is_touching_water;
+            true
+        });
+
+        splash_events
+    }
+
+    /// ripple drops produced since the last call, to be passed to WaterRippleSimulation::add_drops
+    pub fn take_ripple_drops(&mut self) -> Vec<RippleDrop> {
+        std::mem::take(&mut self.pending_drops)
+    }
+}
+
+/// Draws a water surface displaced and shaded by the ripple heightfield, see
+/// WaterRippleSimulation::bind_surface_material. Any mesh works as long as it covers the
+/// surface's area at its height, e.g. a flat plane
+pub struct WaterSurfaceMaterial;
+
+impl CustomMaterial for WaterSurfaceMaterial {
+    fn label(&self) -> &str {
+        "Water Surface"
+    }
+
+    fn shader_source(&self) -> String {
+        include_str!("shaders/water_surface.wgsl").to_string()
+    }
+
+    fn user_data_layout_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+        vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ]
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct RippleStepUniform {
+    params: [f32; 4],
+    drops: [[f32; 4]; MAX_RIPPLE_DROPS],
+}
+
+/// Wave-equation heightfield on the gpu, ping-ponging between two textures.
+/// The current state texture holds the height in r and its gradient in ba,
+/// which a water material can use to perturb its normals
+pub struct WaterRippleSimulation {
+    pub damping: f32,
+    state_textures: [Texture; 2],
+    state_bind_groups: [wgpu::BindGroup; 2],
+    uniform_buffer: wgpu::Buffer,
+    pipeline: wgpu::RenderPipeline,
+    current_state_index: usize,
+    pending_drops: Vec<RippleDrop>,
+    unsimulated_time: Duration,
+    /// the user data of each state texture
+    surface_material: Option<(CustomMaterialId, [CustomMaterialDataId; 2])>,
+}
+
+impl WaterRippleSimulation {
+    pub fn new(base: &BaseRenderer, resolution: u32) -> Self {
+        let shader = base
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: USE_LABELS.then_some("Water Ripples Shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("shaders/water_ripples.wgsl").into()),
+            });
+
+        let bind_group_layout =
+            base.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Texture {
+                                multisampled: false,
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                    ],
+                    label: USE_LABELS.then_some("water_ripples_bind_group_layout"),
+                });
+
+        let pipeline_layout = base
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: USE_LABELS.then_some("Water Ripples Pipeline Layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        let pipeline = base
+            .device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: USE_LABELS.then_some("Water Ripples Pipeline"),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "ripple_step_fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: RIPPLE_STATE_FORMAT,
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            });
+
+        let uniform_buffer = base
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: USE_LABELS.then_some("Water Ripples Uniform Buffer"),
+                contents: bytemuck::cast_slice(&[RippleStepUniform {
+                    params: [0.0; 4],
+                    drops: [[0.0; 4]; MAX_RIPPLE_DROPS],
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+
+        let state_textures = [0, 1].map(|_| Self::create_state_texture(base, resolution));
+
+        let state_bind_groups = [0, 1].map(|i| {
+            base.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&state_textures[i].view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                ],
+                label: USE_LABELS.then_some("water_ripples_bind_group"),
+            })
+        });
+
+        Self {
+            damping: 0.985,
+            state_textures,
+            state_bind_groups,
+            uniform_buffer,
+            pipeline,
+            current_state_index: 0,
+            pending_drops: vec![],
+            unsimulated_time: Duration::ZERO,
+            surface_material: None,
+        }
+    }
+
+    fn create_state_texture(base: &BaseRenderer, resolution: u32) -> Texture {
+        let size = wgpu::Extent3d {
+            width: resolution,
+            height: resolution,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = base.device.create_texture(&wgpu::TextureDescriptor {
+            label: USE_LABELS.then_some("Water Ripples State"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: RIPPLE_STATE_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let sampler_index = base.sampler_cache.lock().unwrap().get_sampler_index(
+            &base.device,
+            &crate::sampler_cache::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                ..Default::default()
+            },
+        );
+
+        Texture {
+            texture,
+            view,
+            sampler_index,
+            size,
+        }
+    }
+
+    pub fn add_drops(&mut self, drops: impl IntoIterator<Item = RippleDrop>) {
+        self.pending_drops.extend(drops);
+    }
+
+    /// the texture that holds the latest simulation state
+    pub fn state_texture(&self) -> &Texture {
+        &self.state_textures[self.current_state_index]
+    }
+
+    /// Registers a WaterSurfaceMaterial that reads the state textures, see surface_material
+    pub fn bind_surface_material(&mut self, renderer: &Renderer) -> Result<()> {
+        let mut data_guard = renderer.data.lock().unwrap();
+        let material_id = match self.surface_material {
+            Some((material_id, data_ids)) => {
+                for data_id in data_ids {
+                    data_guard.custom_materials.unbind_user_data(data_id);
+                }
+                material_id
+            }
+            None => data_guard
+                .custom_materials
+                .register(&renderer.base.device, WaterSurfaceMaterial),
+        };
+
+        let sampler_cache_guard = renderer.base.sampler_cache.lock().unwrap();
+        let mut data_ids = vec![];
+        for texture in &self.state_textures {
+            data_ids.push(data_guard.custom_materials.bind_user_data(
+                &renderer.base.device,
+                material_id,
+                &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(
+                            sampler_cache_guard.get_sampler_by_index(texture.sampler_index),
+                        ),
+                    },
+                ],
+            )?);
+        }
+        self.surface_material = Some((material_id, [data_ids[0], data_ids[1]]));
+        Ok(())
+    }
+
+    /// The material of the surface's node. It reads the latest state texture so it has to be set
+    /// again after every update. None until bind_surface_material is called
+    pub fn surface_material(&self, surface: &WaterSurface) -> Option<Material> {
+        let (material_id, data_ids) = self.surface_material?;
+        let size = surface.size();
+        Some(Material::Custom {
+            material: material_id,
+            user_data: Some(data_ids[self.current_state_index]),
+            params: Vec4::new(surface.min_xz.x, surface.min_xz.y, size.x, size.y),
+        })
+    }
+
+    #[profiling::function]
+    pub fn update(&mut self, base: &BaseRenderer, delta_time: Duration) {
+        let step_duration = Duration::from_secs_f32(1.0 / RIPPLE_STEP_RATE_HZ);
+        self.unsimulated_time += delta_time;
+
+        let mut step_count = 0;
+        while self.unsimulated_time >= step_duration && step_count < MAX_RIPPLE_STEPS_PER_UPDATE {
+            self.unsimulated_time -= step_duration;
+            step_count += 1;
+        }
+        if step_count == MAX_RIPPLE_STEPS_PER_UPDATE {
+            self.unsimulated_time = Duration::ZERO;
+        }
+
+        for _ in 0..step_count {
+            // drops that don't fit in this step's uniform are applied in the next ones
+            let drop_count = self.pending_drops.len().min(MAX_RIPPLE_DROPS);
+            let mut uniform = RippleStepUniform {
+                params: [self.damping, drop_count as f32, 0.0, 0.0],
+                drops: [[0.0; 4]; MAX_RIPPLE_DROPS],
+            };
+            for (i, drop) in self.pending_drops.drain(..drop_count).enumerate() {
+                uniform.drops[i] = [drop.uv.x, drop.uv.y, drop.radius_uv, drop.strength];
+            }
+            // each step is submitted on its own since the uniform is rewritten every step
+            base.queue
+                .write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+            let next_state_index = 1 - self.current_state_index;
+            let mut encoder = base
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: USE_LABELS.then_some("Water Ripples Encoder"),
+                });
+            {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: USE_LABELS.then_some("Water Ripples Step"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &self.state_textures[next_state_index].view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    occlusion_query_set: None,
+                    timestamp_writes: None,
+                });
+                render_pass.set_pipeline(&self.pipeline);
+                render_pass.set_bind_group(
+                    0,
+                    &self.state_bind_groups[self.current_state_index],
+                    &[],
+                );
+                render_pass.draw(0..3, 0..1);
+            }
+            base.queue.submit(Some(encoder.finish()));
+
+            self.current_state_index = next_state_index;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splash_events_are_sent_on_entry_and_exit() {
+        let mut physics_state = PhysicsState::new();
+        let rigid_body_handle = physics_state.rigid_body_set.insert(
+            RigidBodyBuilder::dynamic()
+                .translation(vector![0.0, 2.0, 0.0])
+                .build(),
+        );
+        physics_state.collider_set.insert_with_parent(
+            ColliderBuilder::ball(0.5).build(),
+            rigid_body_handle,
+            &mut physics_state.rigid_body_set,
+        );
+        let mut water = WaterInteractionSystem::new(WaterSurface {
+            height: 0.0,
+            min_xz: Vec2::splat(-10.0),
+            max_xz: Vec2::splat(10.0),
+        });
+        water.track_rigid_body(&physics_state, rigid_body_handle);
+        let move_body = |physics_state: &mut PhysicsState, y: f64, velocity_y: f64| {
+            let rigid_body = &mut physics_state.rigid_body_set[rigid_body_handle];
+            rigid_body.set_translation(vector![0.0, y, 0.0], true);
+            rigid_body.set_linvel(vector![0.0, velocity_y, 0.0], true);
+        };
+
+        assert!(water.update(&physics_state, 0.1).is_empty());
+
+        move_body(&mut physics_state, 0.2, -5.0);
+        let events = water.update(&physics_state, 0.1);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, WaterSplashKind::Entry);
+        assert_eq!(events[0].impact_speed, 5.0);
+
+        move_body(&mut physics_state, 1.0, 3.0);
+        let events = water.update(&physics_state, 0.1);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, WaterSplashKind::Exit);
+        assert_eq!(water.take_ripple_drops().len(), 2);
+
+        // too slow to splash
+        move_body(&mut physics_state, 0.2, -0.5);
+        assert!(water.update(&physics_state, 0.1).is_empty());
+        assert!(water.is_tracking_rigid_body(rigid_body_handle));
+    }
+}
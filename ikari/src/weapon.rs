@@ -0,0 +1,466 @@
+use crate::ecs::{Entity, SceneNode};
+use crate::engine_state::EngineState;
+use crate::physics::rapier3d_f64::prelude::*;
+use crate::physics::PhysicsState;
+use crate::scene::{GameNodeDesc, GameNodeId, GameNodeVisual, Scene};
+use crate::transform::TransformBuilder;
+
+use glam::f32::Vec3;
+
+/// How the shots of a weapon travel
+#[derive(Debug, Clone)]
+pub enum ShotKind {
+    /// hits the first collider along the aim ray right away
+    Hitscan { max_distance: f32 },
+    /// fires a Projectile entity taken from EngineState::projectile_pool, see update_projectiles
+    Projectile {
+        speed: f32,
+        /// 0 for projectiles that fly straight, 1 for the physics world's gravity
+        gravity_scale: f32,
+        max_lifetime_seconds: f32,
+        /// given to the projectile's node, which is moved along with it
+        visual: Option<GameNodeVisual>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct WeaponDefinition {
+    pub shot_kind: ShotKind,
+    /// minimum time between two shots
+    pub fire_interval_seconds: f32,
+    /// None for weapons that never need to reload
+    pub magazine_size: Option<u32>,
+    pub reload_seconds: f32,
+    pub damage: f32,
+    /// indices into Scene::animations, restarted when the weapon fires or starts reloading
+    pub fire_animation: Option<usize>,
+    pub reload_animation: Option<usize>,
+    /// names of effects in the EffectLibrary, passed along with the Fired events and the hits so
+    /// the game can spawn their particles, light flashes and decals
+    pub muzzle_effect: Option<String>,
+    pub impact_effect: Option<String>,
+    /// the colliders the shots can hit, e.g. everything but the shooter
+    pub collision_groups: InteractionGroups,
+}
+
+impl Default for WeaponDefinition {
+    fn default() -> Self {
+        Self {
+            shot_kind: ShotKind::Hitscan {
+                max_distance: 1000.0,
+            },
+            fire_interval_seconds: 0.5,
+            magazine_size: None,
+            reload_seconds: 1.0,
+            damage: 1.0,
+            fire_animation: None,
+            reload_animation: None,
+            muzzle_effect: None,
+            impact_effect: None,
+            collision_groups: InteractionGroups::all(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WeaponState {
+    Ready,
+    /// waiting for the fire interval to pass after a shot
+    Cooldown {
+        remaining_seconds: f32,
+    },
+    Reloading {
+        remaining_seconds: f32,
+    },
+}
+
+/// Sent to EngineState::events where a shot hit something, by Weapon::fire for the hitscan
+/// shots and by update_projectiles for the projectiles
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeaponHit {
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub collider_handle: ColliderHandle,
+    /// see PhysicsState::find_collider_node
+    pub node_id: Option<GameNodeId>,
+    pub damage: f32,
+    pub impact_effect: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WeaponEvent {
+    Fired {
+        origin: Vec3,
+        direction: Vec3,
+        muzzle_effect: Option<String>,
+    },
+    /// the trigger was pulled with an empty magazine and no ammo left to reload
+    DryFire,
+    ReloadStarted,
+    ReloadFinished,
+}
+
+/// A hitscan or projectile weapon with its ammo and its fire/reload state machine
+#[derive(Debug, Clone)]
+pub struct Weapon {
+    pub definition: WeaponDefinition,
+    state: WeaponState,
+    ammo_in_magazine: u32,
+    /// the rounds left to refill the magazine with, None for unlimited
+    pub reserve_ammo: Option<u32>,
+}
+
+impl Weapon {
+    /// Starts with a full magazine
+    pub fn new(definition: WeaponDefinition, reserve_ammo: Option<u32>) -> Self {
+        Self {
+            ammo_in_magazine: definition.magazine_size.unwrap_or(0),
+            definition,
+            state: WeaponState::Ready,
+            reserve_ammo,
+        }
+    }
+
+    pub fn state(&self) -> WeaponState {
+        self.state
+    }
+
+    /// None if the weapon has no magazine
+    pub fn ammo_in_magazine(&self) -> Option<u32> {
+        self.definition.magazine_size.map(|_| self.ammo_in_magazine)
+    }
+
+    /// Capped to the size of the magazine, e.g. when loading a save
+    pub fn set_ammo_in_magazine(&mut self, ammo: u32) {
+        if let Some(magazine_size) = self.definition.magazine_size {
+            self.ammo_in_magazine = ammo.min(magazine_size);
+        }
+    }
+
+    /// 0 right after firing or starting to reload, 1 once it can fire again
+    pub fn cooldown_progress(&self) -> f32 {
+        let (remaining_seconds, total_seconds) = match self.state {
+            WeaponState::Ready => return 1.0,
+            WeaponState::Cooldown { remaining_seconds } => {
+                (remaining_seconds, self.definition.fire_interval_seconds)
+            }
+            WeaponState::Reloading { remaining_seconds } => {
+                (remaining_seconds, self.definition.reload_seconds)
+            }
+        };
+        if total_seconds <= 0.0 {
+            return 1.0;
+        }
+        (1.0 - remaining_seconds / total_seconds).clamp(0.0, 1.0)
+    }
+
+    /// Advances the cooldown and the reload. An empty magazine is reloaded automatically
+    pub fn update(&mut self, scene: &mut Scene, delta_time_seconds: f32) -> Vec<WeaponEvent> {
+        let mut events = vec![];
+        match &mut self.state {
+            WeaponState::Ready => {}
+            WeaponState::Cooldown { remaining_seconds } => {
+                *remaining_seconds -= delta_time_seconds;
+                if *remaining_seconds <= 0.0 {
+                    self.state = WeaponState::Ready;
+                }
+            }
+            WeaponState::Reloading { remaining_seconds } => {
+                *remaining_seconds -= delta_time_seconds;
+                if *remaining_seconds <= 0.0 {
+                    self.state = WeaponState::Ready;
+                    self.refill_magazine();
+                    events.push(WeaponEvent::ReloadFinished);
+                }
+            }
+        }
+        if self.state == WeaponState::Ready
+            && self.ammo_in_magazine() == Some(0)
+            && self.reload(scene)
+        {
+            events.push(WeaponEvent::ReloadStarted);
+        }
+        events
+    }
+
+    /// Returns false if the weapon is busy, the magazine is full or there's no ammo left
+    pub fn reload(&mut self, scene: &mut Scene) -> bool {
+        let Some(magazine_size) = self.definition.magazine_size else {
+            return false;
+        };
+        if self.state != WeaponState::Ready
+            || self.ammo_in_magazine == magazine_size
+            || self.reserve_ammo == Some(0)
+        {
+            return false;
+        }
+        self.state = WeaponState::Reloading {
+            remaining_seconds: self.definition.reload_seconds,
+        };
+        if let Some(animation_index) = self.definition.reload_animation {
+            restart_animation(scene, animation_index);
+        }
+        true
+    }
+
+    /// Shoots from origin towards direction if the weapon is ready. Hitscan shots are resolved
+    /// right away and their hit is sent as a WeaponHit event, projectiles are spawned as entities
+    pub fn fire(
+        &mut self,
+        engine_state: &mut EngineState,
+        origin: Vec3,
+        direction: Vec3,
+    ) -> Vec<WeaponEvent> {
+        if self.state != WeaponState::Ready {
+            return vec![];
+        }
+        if self.ammo_in_magazine() == Some(0) {
+            return vec![WeaponEvent::DryFire];
+        }
+        if self.definition.magazine_size.is_some() {
+            self.ammo_in_magazine -= 1;
+        }
+        self.state = WeaponState::Cooldown {
+            remaining_seconds: self.definition.fire_interval_seconds,
+        };
+        if let Some(animation_index) = self.definition.fire_animation {
+            restart_animation(&mut engine_state.scene, animation_index);
+        }
+
+        let direction = direction.normalize_or_zero();
+        match &self.definition.shot_kind {
+            ShotKind::Hitscan { max_distance } => {
+                if let Some((position, normal, collider_handle)) = cast_shot(
+                    &engine_state.physics_state,
+                    origin,
+                    direction,
+                    *max_distance,
+                    self.definition.collision_groups,
+                ) {
+                    engine_state.events.send(WeaponHit {
+                        position,
+                        normal,
+                        collider_handle,
+                        node_id: engine_state
+                            .physics_state
+                            .find_collider_node(collider_handle),
+                        damage: self.definition.damage,
+                        impact_effect: self.definition.impact_effect.clone(),
+                    });
+                }
+            }
+            ShotKind::Projectile {
+                speed,
+                gravity_scale,
+                max_lifetime_seconds,
+                visual,
+            } => {
+                let mut projectile_pool = std::mem::take(&mut engine_state.projectile_pool);
+                let entity = projectile_pool.acquire(
+                    engine_state,
+                    TransformBuilder::new().position(origin).build(),
+                    spawn_projectile,
+                );
+                engine_state.projectile_pool = projectile_pool;
+                engine_state.world.insert(
+                    entity,
+                    Projectile {
+                        position: origin,
+                        velocity: direction * *speed,
+                        gravity_scale: *gravity_scale,
+                        remaining_lifetime_seconds: *max_lifetime_seconds,
+                        damage: self.definition.damage,
+                        impact_effect: self.definition.impact_effect.clone(),
+                        collision_groups: self.definition.collision_groups,
+                    },
+                );
+                if let Some(node) = engine_state
+                    .world
+                    .get::<SceneNode>(entity)
+                    .and_then(|SceneNode(node_id)| engine_state.scene.get_node_mut(*node_id))
+                {
+                    node.visual = visual.clone();
+                }
+            }
+        }
+        vec![WeaponEvent::Fired {
+            origin,
+            direction,
+            muzzle_effect: self.definition.muzzle_effect.clone(),
+        }]
+    }
+
+    fn refill_magazine(&mut self) {
+        let Some(magazine_size) = self.definition.magazine_size else {
+            return;
+        };
+        let missing = magazine_size - self.ammo_in_magazine;
+        let refilled = match &mut self.reserve_ammo {
+            Some(reserve_ammo) => {
+                let refilled = missing.min(*reserve_ammo);
+                *reserve_ammo -= refilled;
+                refilled
+            }
+            None => missing,
+        };
+        self.ammo_in_magazine += refilled;
+    }
+}
+
+/// The component of the entities spawned by projectile weapons
+#[derive(Debug, Clone)]
+pub struct Projectile {
+    pub position: Vec3,
+    pub velocity: Vec3,
+    pub gravity_scale: f32,
+    pub remaining_lifetime_seconds: f32,
+    pub damage: f32,
+    pub impact_effect: Option<String>,
+    pub collision_groups: InteractionGroups,
+}
+
+/// Moves the projectiles and puts the ones that expired or hit something back in
+/// EngineState::projectile_pool, sending a WeaponHit event for the latter. The path travelled during the frame is ray cast so that fast
+/// projectiles don't go through thin walls
+#[profiling::function]
+pub fn update_projectiles(engine_state: &mut EngineState, delta_time_seconds: f32) {
+    let gravity = engine_state.physics_state.gravity;
+    let gravity = Vec3::new(gravity.x as f32, gravity.y as f32, gravity.z as f32);
+    let mut hits = vec![];
+    let mut released_entities: Vec<Entity> = vec![];
+
+    for entity in engine_state.world.entities_with::<Projectile>() {
+        let Some(projectile) = engine_state.world.get_mut::<Projectile>(entity) else {
+            continue;
+        };
+        projectile.velocity += gravity * projectile.gravity_scale * delta_time_seconds;
+        let displacement = projectile.velocity * delta_time_seconds;
+        let hit = cast_shot(
+            &engine_state.physics_state,
+            projectile.position,
+            displacement.normalize_or_zero(),
+            displacement.length(),
+            projectile.collision_groups,
+        );
+        projectile.remaining_lifetime_seconds -= delta_time_seconds;
+
+        if let Some((position, normal, collider_handle)) = hit {
+            hits.push(WeaponHit {
+                position,
+                normal,
+                collider_handle,
+                node_id: engine_state
+                    .physics_state
+                    .find_collider_node(collider_handle),
+                damage: projectile.damage,
+                impact_effect: projectile.impact_effect.clone(),
+            });
+            released_entities.push(entity);
+            continue;
+        }
+        if projectile.remaining_lifetime_seconds <= 0.0 {
+            released_entities.push(entity);
+            continue;
+        }
+
+        projectile.position += displacement;
+        let position = projectile.position;
+        if let Some(SceneNode(node_id)) = engine_state.world.get::<SceneNode>(entity).copied() {
+            if let Some(node) = engine_state.scene.get_node_mut(node_id) {
+                node.transform.set_position(position);
+            }
+        }
+    }
+
+    let mut projectile_pool = std::mem::take(&mut engine_state.projectile_pool);
+    for entity in released_entities {
+        engine_state.world.remove::<Projectile>(entity);
+        if !projectile_pool.release(engine_state, entity) {
+            engine_state.despawn_entity(entity);
+        }
+    }
+    engine_state.projectile_pool = projectile_pool;
+    for hit in hits {
+        engine_state.events.send(hit);
+    }
+}
+
+/// The entity of a projectile with an empty node, for EngineState::projectile_pool
+fn spawn_projectile(engine_state: &mut EngineState) -> Entity {
+    let node_id = engine_state.scene.add_node(GameNodeDesc::default()).id();
+    let entity = engine_state.world.spawn();
+    engine_state.world.insert(entity, SceneNode(node_id));
+    entity
+}
+
+/// (position, normal, collider) of the first hit within max_distance
+fn cast_shot(
+    physics_state: &PhysicsState,
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+    collision_groups: InteractionGroups,
+) -> Option<(Vec3, Vec3, ColliderHandle)> {
+    if direction == Vec3::ZERO || max_distance <= 0.0 {
+        return None;
+    }
+    let ray = Ray::new(
+        point![origin.x as f64, origin.y as f64, origin.z as f64],
+        vector![direction.x as f64, direction.y as f64, direction.z as f64],
+    );
+    let solid = true;
+    let (collider_handle, intersection) = physics_state.query_pipeline.cast_ray_and_get_normal(
+        &physics_state.rigid_body_set,
+        &physics_state.collider_set,
+        &ray,
+        max_distance as f64,
+        solid,
+        QueryFilter::from(collision_groups),
+    )?;
+    let position = ray.point_at(intersection.toi);
+    let normal = intersection.normal;
+    Some((
+        Vec3::new(position.x as f32, position.y as f32, position.z as f32),
+        Vec3::new(normal.x as f32, normal.y as f32, normal.z as f32),
+        collider_handle,
+    ))
+}
+
+fn restart_animation(scene: &mut Scene, animation_index: usize) {
+    if let Some(animation) = scene.animations.get_mut(animation_index) {
+        animation.state.is_playing = true;
+        animation.state.current_time_seconds = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reloading_takes_from_the_reserve() {
+        let mut scene = Scene::new(vec![], vec![], vec![]);
+        let mut weapon = Weapon::new(
+            WeaponDefinition {
+                magazine_size: Some(6),
+                reload_seconds: 1.0,
+                ..Default::default()
+            },
+            Some(4),
+        );
+        weapon.ammo_in_magazine = 0;
+
+        assert_eq!(
+            weapon.update(&mut scene, 0.0),
+            vec![WeaponEvent::ReloadStarted]
+        );
+        assert!(weapon.update(&mut scene, 0.5).is_empty());
+        assert_eq!(
+            weapon.update(&mut scene, 0.5),
+            vec![WeaponEvent::ReloadFinished]
+        );
+        assert_eq!(weapon.ammo_in_magazine(), Some(4));
+        assert_eq!(weapon.reserve_ammo, Some(0));
+        assert!(!weapon.reload(&mut scene));
+    }
+}
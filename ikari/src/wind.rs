@@ -0,0 +1,44 @@
+use crate::noise::Noise;
+
+use glam::f32::Vec3;
+
+/// The wind of the world, shared by everything that sways in it like the cloth and the foliage
+#[derive(Debug, Clone)]
+pub struct Wind {
+    /// normalized
+    pub direction: Vec3,
+    /// base speed in units per second
+    pub strength: f32,
+    /// how much faster the gusts get on top of the base speed
+    pub gust_strength: f32,
+    /// how many gusts pass by per second
+    pub gust_frequency: f32,
+    /// size of the gusts in world units, smaller makes nearby objects move less in sync
+    pub gust_size: f32,
+    noise: Noise,
+}
+
+impl Default for Wind {
+    fn default() -> Self {
+        Self {
+            direction: Vec3::X,
+            strength: 1.0,
+            gust_strength: 1.0,
+            gust_frequency: 0.3,
+            gust_size: 20.0,
+            noise: Noise::new(0),
+        }
+    }
+}
+
+impl Wind {
+    /// Velocity of the air at a position, the gusts roll through the world along the wind
+    /// direction
+    pub fn velocity_at(&self, position: Vec3, time_seconds: f32) -> Vec3 {
+        let gust_position = position / self.gust_size.max(0.001)
+            - self.direction * time_seconds * self.gust_frequency;
+        // in [0, 1] so the gusts never blow the other way
+        let gust = self.noise.simplex_3d(gust_position) * 0.5 + 0.5;
+        self.direction * (self.strength + self.gust_strength * gust)
+    }
+}
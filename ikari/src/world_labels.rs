@@ -0,0 +1,357 @@
+use crate::physics::rapier3d_f64::prelude::*;
+use crate::physics::PhysicsState;
+use crate::renderer::RendererData;
+use crate::scene::{GameNodeId, Scene};
+
+use glam::f32::{Mat4, Vec3, Vec4};
+use iced::widget::canvas;
+use iced::{mouse, Color, Element, Length, Point, Rectangle, Theme};
+
+/// Text that follows a scene node, drawn by the ui so that it uses the same fonts
+#[derive(Debug, Clone)]
+pub struct WorldLabel {
+    pub node_id: GameNodeId,
+    /// added to the node's global position, in world space
+    pub offset: Vec3,
+    pub text: String,
+    /// in logical pixels, when the label is reference_distance away from the camera
+    pub size: f32,
+    pub reference_distance: f32,
+    /// (min, max) size multiplier applied for the distance to the camera
+    pub scale_range: (f32, f32),
+    pub color: Color,
+    /// the label fades out over the last 10% of this distance
+    pub max_distance: f32,
+    /// fades the label out while a collider is between it and the camera
+    pub fade_when_occluded: bool,
+    /// added to offset every second, e.g. to make damage numbers rise
+    pub velocity: Vec3,
+    /// the label is removed after this long, fading out over its last quarter
+    pub lifetime_seconds: Option<f32>,
+}
+
+impl WorldLabel {
+    pub fn new(node_id: GameNodeId, text: &str) -> Self {
+        Self {
+            node_id,
+            offset: Vec3::ZERO,
+            text: text.to_string(),
+            size: 20.0,
+            reference_distance: 5.0,
+            scale_range: (0.5, 1.5),
+            color: Color::WHITE,
+            max_distance: 50.0,
+            fade_when_occluded: true,
+            velocity: Vec3::ZERO,
+            lifetime_seconds: None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct WorldLabelId(usize);
+
+#[derive(Debug, Clone)]
+struct WorldLabelEntry {
+    label: WorldLabel,
+    age_seconds: f32,
+    /// 0 when fully occluded, moves towards its target over WorldLabels::occlusion_fade_seconds
+    visibility: f32,
+}
+
+/// A label as it should be drawn this frame
+#[derive(Debug, Clone)]
+pub struct ScreenLabel {
+    pub text: String,
+    /// center of the text in logical pixels from the top left of the window
+    pub position: (f32, f32),
+    pub size: f32,
+    pub color: Color,
+}
+
+/// The labels of a frame, sorted back to front. Drawn with ScreenLabels::view, which puts all of
+/// them in a single canvas
+#[derive(Debug, Clone, Default)]
+pub struct ScreenLabels(pub Vec<ScreenLabel>);
+
+#[derive(Debug, Clone)]
+pub struct WorldLabels {
+    labels: Vec<Option<WorldLabelEntry>>,
+    pub occlusion_fade_seconds: f32,
+    /// the colliders that can hide the labels
+    pub occluder_groups: InteractionGroups,
+}
+
+impl Default for WorldLabels {
+    fn default() -> Self {
+        Self {
+            labels: vec![],
+            occlusion_fade_seconds: 0.15,
+            occluder_groups: InteractionGroups::all(),
+        }
+    }
+}
+
+impl WorldLabels {
+    pub fn add(&mut self, label: WorldLabel) -> WorldLabelId {
+        let entry = Some(WorldLabelEntry {
+            label,
+            age_seconds: 0.0,
+            visibility: 1.0,
+        });
+        match self.labels.iter().position(Option::is_none) {
+            Some(index) => {
+                self.labels[index] = entry;
+                WorldLabelId(index)
+            }
+            None => {
+                self.labels.push(entry);
+                WorldLabelId(self.labels.len() - 1)
+            }
+        }
+    }
+
+    pub fn remove(&mut self, label_id: WorldLabelId) {
+        if let Some(entry) = self.labels.get_mut(label_id.0) {
+            *entry = None;
+        }
+    }
+
+    pub fn get(&self, label_id: WorldLabelId) -> Option<&WorldLabel> {
+        self.labels
+            .get(label_id.0)
+            .and_then(Option::as_ref)
+            .map(|entry| &entry.label)
+    }
+
+    pub fn get_mut(&mut self, label_id: WorldLabelId) -> Option<&mut WorldLabel> {
+        self.labels
+            .get_mut(label_id.0)
+            .and_then(Option::as_mut)
+            .map(|entry| &mut entry.label)
+    }
+
+    /// Moves the labels, removes the expired ones and the ones whose node is gone, and projects
+    /// the rest with the renderer's camera. viewport_size is in logical pixels
+    pub fn update(
+        &mut self,
+        scene: &Scene,
+        renderer_data: &RendererData,
+        physics_state: &PhysicsState,
+        viewport_size: (f32, f32),
+        frame_time_seconds: f32,
+    ) -> ScreenLabels {
+        let camera = renderer_data
+            .camera_node_id
+            .filter(|camera_node_id| scene.get_node(*camera_node_id).is_some())
+            .map(|camera_node_id| {
+                let camera_transform = scene.get_global_transform_for_node(camera_node_id);
+                let camera_data = renderer_data.camera_lens.shader_camera_data(
+                    Mat4::from(camera_transform),
+                    viewport_size.0 / viewport_size.1,
+                );
+                (
+                    camera_transform.position(),
+                    camera_data.proj * camera_data.view,
+                )
+            });
+
+        let fade_step = if self.occlusion_fade_seconds > 0.0 {
+            frame_time_seconds / self.occlusion_fade_seconds
+        } else {
+            1.0
+        };
+
+        let mut screen_labels = vec![];
+        for slot in &mut self.labels {
+            let Some(entry) = slot else {
+                continue;
+            };
+            entry.age_seconds += frame_time_seconds;
+            entry.label.offset += entry.label.velocity * frame_time_seconds;
+            let is_expired = entry
+                .label
+                .lifetime_seconds
+                .map_or(false, |lifetime_seconds| {
+                    entry.age_seconds >= lifetime_seconds
+                });
+            if is_expired || scene.get_node(entry.label.node_id).is_none() {
+                *slot = None;
+                continue;
+            }
+
+            let Some((camera_position, view_proj)) = camera else {
+                continue;
+            };
+            let label = &entry.label;
+            let position = scene
+                .get_global_transform_for_node(label.node_id)
+                .position()
+                + label.offset;
+            let distance = camera_position.distance(position);
+            if distance > label.max_distance {
+                continue;
+            }
+
+            let is_occluded = label.fade_when_occluded
+                && is_occluded(
+                    physics_state,
+                    self.occluder_groups,
+                    label.node_id,
+                    camera_position,
+                    position,
+                );
+            let target_visibility = if is_occluded { 0.0 } else { 1.0 };
+            entry.visibility = if entry.visibility < target_visibility {
+                (entry.visibility + fade_step).min(target_visibility)
+            } else {
+                (entry.visibility - fade_step).max(target_visibility)
+            };
+
+            let Some(screen_position) = project_to_screen(view_proj, position, viewport_size)
+            else {
+                continue;
+            };
+
+            let distance_fade =
+                ((label.max_distance - distance) / (label.max_distance * 0.1)).clamp(0.0, 1.0);
+            let lifetime_fade = label.lifetime_seconds.map_or(1.0, |lifetime_seconds| {
+                ((lifetime_seconds - entry.age_seconds) / (lifetime_seconds * 0.25)).clamp(0.0, 1.0)
+            });
+            let alpha = label.color.a * entry.visibility * distance_fade * lifetime_fade;
+            if alpha <= 0.0 {
+                continue;
+            }
+
+            let scale = (label.reference_distance / distance.max(0.001))
+                .clamp(label.scale_range.0, label.scale_range.1);
+            screen_labels.push((
+                distance,
+                ScreenLabel {
+                    text: label.text.clone(),
+                    position: screen_position,
+                    size: label.size * scale,
+                    color: Color {
+                        a: alpha,
+                        ..label.color
+                    },
+                },
+            ));
+        }
+
+        screen_labels.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        ScreenLabels(screen_labels.into_iter().map(|(_, label)| label).collect())
+    }
+}
+
+fn is_occluded(
+    physics_state: &PhysicsState,
+    occluder_groups: InteractionGroups,
+    node_id: GameNodeId,
+    from: Vec3,
+    to: Vec3,
+) -> bool {
+    let distance = from.distance(to);
+    if distance <= 0.0 {
+        return false;
+    }
+    let direction = (to - from) / distance;
+    let ray = Ray::new(
+        point![from.x as Real, from.y as Real, from.z as Real],
+        vector![
+            direction.x as Real,
+            direction.y as Real,
+            direction.z as Real
+        ],
+    );
+    // the node's own colliders would always hide labels that are inside of them
+    let predicate = |collider_handle: ColliderHandle, _: &Collider| {
+        physics_state.find_collider_node(collider_handle) != Some(node_id)
+    };
+    physics_state
+        .query_pipeline
+        .cast_ray(
+            &physics_state.rigid_body_set,
+            &physics_state.collider_set,
+            &ray,
+            distance as Real,
+            true,
+            QueryFilter::from(occluder_groups).predicate(&predicate),
+        )
+        .is_some()
+}
+
+/// None if the point is behind the camera
+fn project_to_screen(
+    view_proj: Mat4,
+    position: Vec3,
+    viewport_size: (f32, f32),
+) -> Option<(f32, f32)> {
+    let clip = view_proj * Vec4::new(position.x, position.y, position.z, 1.0);
+    if clip.w <= 0.0 {
+        return None;
+    }
+    let ndc = clip.truncate() / clip.w;
+    Some((
+        (ndc.x * 0.5 + 0.5) * viewport_size.0,
+        (0.5 - ndc.y * 0.5) * viewport_size.1,
+    ))
+}
+
+impl ScreenLabels {
+    /// Fills the screen
+    pub fn view<'a, Message: 'a>(&'a self) -> Element<'a, Message, Theme, iced::Renderer> {
+        canvas(self).width(Length::Fill).height(Length::Fill).into()
+    }
+}
+
+impl<Message> canvas::Program<Message, Theme, iced::Renderer> for ScreenLabels {
+    type State = ();
+
+    fn draw(
+        &self,
+        _state: &Self::State,
+        renderer: &iced::Renderer,
+        _theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<canvas::Geometry> {
+        let mut frame = canvas::Frame::new(renderer, bounds.size());
+        for label in &self.0 {
+            frame.fill_text(canvas::Text {
+                content: label.text.clone(),
+                position: Point::new(label.position.0, label.position.1),
+                color: label.color,
+                size: label.size.into(),
+                horizontal_alignment: iced::alignment::Horizontal::Center,
+                vertical_alignment: iced::alignment::Vertical::Center,
+                ..Default::default()
+            });
+        }
+        vec![frame.into_geometry()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::make_perspective_proj_matrix;
+
+    #[test]
+    fn points_in_front_of_the_camera_project_into_the_viewport() {
+        let proj = make_perspective_proj_matrix(0.1, 100.0, 90f32.to_radians(), 2.0, true);
+        let viewport_size = (200.0, 100.0);
+
+        // the camera is at the origin looking down -z
+        assert_eq!(
+            project_to_screen(proj, Vec3::new(0.0, 0.0, -5.0), viewport_size),
+            Some((100.0, 50.0))
+        );
+        let (x, y) = project_to_screen(proj, Vec3::new(5.0, 2.5, -5.0), viewport_size).unwrap();
+        assert!((x - 150.0).abs() < 1e-3 && (y - 25.0).abs() < 1e-3);
+        assert_eq!(
+            project_to_screen(proj, Vec3::new(0.0, 0.0, 5.0), viewport_size),
+            None
+        );
+    }
+}
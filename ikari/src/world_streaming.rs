@@ -0,0 +1,239 @@
+use crate::asset_loader::{AssetBinder, AssetId, AssetLoader, SceneAssetLoadParams};
+use crate::large_world::LargeWorld;
+use crate::renderer::{BindedAssetId, RendererData};
+use crate::scene::{GameNodeDescBuilder, GameNodeId, Scene};
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use glam::f32::Vec3;
+use glam::f64::{DVec2, DVec3};
+use glam::i32::IVec2;
+
+#[derive(Debug)]
+enum CellState {
+    Unloaded,
+    Loading(AssetId),
+    Loaded {
+        asset_id: BindedAssetId,
+        root_node_id: GameNodeId,
+    },
+}
+
+#[derive(Debug)]
+struct WorldCell {
+    scene: SceneAssetLoadParams,
+    state: CellState,
+}
+
+/// A world made of one scene per cell of a grid on the xz plane. The cells near the camera are
+/// loaded with the AssetLoader and the far ones are removed from the scene and unloaded.
+///
+/// Positions in the scene are relative to the floating origin of LargeWorld, which must be
+/// enabled for the origin to follow the camera. The cells' root nodes are placed with
+/// LargeWorld::set_position so they stay exact as the origin moves
+#[derive(Debug)]
+pub struct WorldStreamer {
+    pub cell_size: f32,
+    /// the cells closer than this to the camera start loading
+    pub load_distance: f32,
+    /// the loaded cells further than this are unloaded. Larger than load_distance so the cells
+    /// on the border don't load and unload over and over as the camera moves back and forth
+    pub unload_distance: f32,
+    cells: HashMap<IVec2, WorldCell>,
+}
+
+impl WorldStreamer {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size,
+            load_distance: cell_size * 0.5,
+            unload_distance: cell_size,
+            cells: HashMap::new(),
+        }
+    }
+
+    /// The scene is placed with its origin at the cell's corner with the lowest x and z
+    pub fn add_cell(&mut self, coords: IVec2, scene: SceneAssetLoadParams) {
+        self.cells.insert(
+            coords,
+            WorldCell {
+                scene,
+                state: CellState::Unloaded,
+            },
+        );
+    }
+
+    /// The cell under a world position
+    pub fn cell_coords(&self, world_position: DVec3) -> IVec2 {
+        let cell_size = self.cell_size as f64;
+        IVec2::new(
+            (world_position.x / cell_size).floor() as i32,
+            (world_position.z / cell_size).floor() as i32,
+        )
+    }
+
+    /// The root node that the nodes of a loaded cell are parented to
+    pub fn cell_root_node(&self, coords: IVec2) -> Option<GameNodeId> {
+        match self.cells.get(&coords)?.state {
+            CellState::Loaded { root_node_id, .. } => Some(root_node_id),
+            _ => None,
+        }
+    }
+
+    /// Call it once per frame with the position of the camera in the scene, e.g. from
+    /// EngineState::large_world
+    pub fn update(
+        &mut self,
+        camera_position: Vec3,
+        scene: &mut Scene,
+        renderer_data: &mut RendererData,
+        large_world: &mut LargeWorld,
+        asset_loader: &AssetLoader,
+        asset_binder: &AssetBinder,
+    ) -> Result<()> {
+        let camera_world_position = large_world.to_world_position(camera_position);
+
+        for (coords, load) in self.cell_changes(camera_world_position) {
+            let cell = self.cells.get_mut(&coords).unwrap();
+            if load {
+                cell.state = CellState::Loading(asset_loader.load_gltf_scene(cell.scene.clone()));
+            } else if let CellState::Loaded {
+                asset_id,
+                root_node_id,
+            } = cell.state
+            {
+                large_world.remove(root_node_id);
+                scene.remove_subtree(root_node_id);
+                scene.unload_asset(renderer_data, asset_id)?;
+                cell.state = CellState::Unloaded;
+            }
+        }
+
+        let loaded_scenes = asset_binder.loaded_scenes();
+        let mut loaded_scenes_guard = loaded_scenes.lock().unwrap();
+        for (coords, cell) in &mut self.cells {
+            let CellState::Loading(asset_id) = cell.state else {
+                continue;
+            };
+            let Some((mut cell_scene, render_buffers)) = loaded_scenes_guard.remove(&asset_id)
+            else {
+                continue;
+            };
+            // the camera left before the cell was done loading
+            if cell_distance(*coords, self.cell_size, camera_world_position)
+                > self.unload_distance as f64
+            {
+                cell.state = CellState::Unloaded;
+                continue;
+            }
+
+            let corner = coords.as_dvec2() * self.cell_size as f64;
+            let root_node_id = scene
+                .add_node(
+                    GameNodeDescBuilder::new()
+                        .name(Some(format!("world cell {} {}", coords.x, coords.y)))
+                        .build(),
+                )
+                .id();
+            large_world.set_position(scene, root_node_id, DVec3::new(corner.x, 0.0, corner.y));
+            let asset_id = cell_scene.bind_render_buffers(renderer_data, render_buffers);
+            for node_id in scene.merge_scene_nodes(cell_scene) {
+                if let Some(node) = scene.get_node_mut(node_id) {
+                    if node.parent_id.is_none() {
+                        node.parent_id = Some(root_node_id);
+                    }
+                }
+            }
+            cell.state = CellState::Loaded {
+                asset_id,
+                root_node_id,
+            };
+        }
+
+        Ok(())
+    }
+
+    /// The cells to start loading (true) and the loaded ones to unload (false)
+    fn cell_changes(&self, camera_world_position: DVec3) -> Vec<(IVec2, bool)> {
+        self.cells
+            .iter()
+            .filter_map(|(coords, cell)| {
+                let distance = cell_distance(*coords, self.cell_size, camera_world_position);
+                match cell.state {
+                    CellState::Unloaded if distance < self.load_distance as f64 => {
+                        Some((*coords, true))
+                    }
+                    CellState::Loaded { .. } if distance > self.unload_distance as f64 => {
+                        Some((*coords, false))
+                    }
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+}
+
+/// On the xz plane, 0 when the position is inside the cell
+fn cell_distance(coords: IVec2, cell_size: f32, world_position: DVec3) -> f64 {
+    let cell_size = cell_size as f64;
+    let min = coords.as_dvec2() * cell_size;
+    let position = DVec2::new(world_position.x, world_position.z);
+    let closest = position.clamp(min, min + cell_size);
+    position.distance(closest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manager::GamePathMaker;
+    use crate::renderer::BindedAssets;
+
+    #[test]
+    fn cells_stream_with_hysteresis() {
+        let mut world_streamer = WorldStreamer::new(100.0);
+        for x in 0..3 {
+            world_streamer.add_cell(
+                IVec2::new(x, 0),
+                SceneAssetLoadParams {
+                    path: GamePathMaker::new(None).make(format!("cell_{x}.glb")),
+                    generate_wireframe_meshes: false,
+                    generate_colliders: None,
+                },
+            );
+        }
+
+        let mut changes = world_streamer.cell_changes(DVec3::new(130.0, 0.0, 50.0));
+        changes.sort_by_key(|(coords, _)| coords.x);
+        assert_eq!(
+            changes,
+            [(IVec2::new(0, 0), true), (IVec2::new(1, 0), true)]
+        );
+
+        let mut scene = Scene::default();
+        let root_node_id = scene.add_node(Default::default()).id();
+        world_streamer
+            .cells
+            .get_mut(&IVec2::new(0, 0))
+            .unwrap()
+            .state = CellState::Loaded {
+            asset_id: BindedAssets::default().add(Default::default()),
+            root_node_id,
+        };
+        // past load_distance but not unload_distance
+        let mut changes = world_streamer.cell_changes(DVec3::new(160.0, 0.0, 50.0));
+        changes.sort_by_key(|(coords, _)| coords.x);
+        assert_eq!(
+            changes,
+            [(IVec2::new(1, 0), true), (IVec2::new(2, 0), true)]
+        );
+        assert!(world_streamer
+            .cell_changes(DVec3::new(210.0, 0.0, 50.0))
+            .contains(&(IVec2::new(0, 0), false)));
+
+        assert_eq!(
+            world_streamer.cell_coords(DVec3::new(-0.5, 0.0, 250.0)),
+            IVec2::new(-1, 2)
+        );
+    }
+}